@@ -0,0 +1,135 @@
+//! Export of the chain history as CSV tables, for analysis outside of the node.
+//!
+//! Rows are written as the canonical chain is walked, so exporting a long history does not hold
+//! it in memory. The heights table has a row for every height, skipped ones without a block, and
+//! the validators table summarizes every block proposer like the validator monitor does.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use near_primitives::types::BlockIndex;
+
+use crate::chain::Chain;
+use crate::error::{Error, ErrorKind};
+use crate::types::RuntimeAdapter;
+use crate::validator_monitor::ValidatorMonitor;
+
+const HEIGHT_COLUMNS: &[&str] =
+    &["height", "block_hash", "timestamp", "proposer", "approvals", "transactions", "total_weight"];
+
+const VALIDATOR_COLUMNS: &[&str] = &[
+    "account_id",
+    "blocks_produced",
+    "blocks_missed",
+    "approvals_included",
+    "approvals_missed",
+    "stake",
+    "is_active",
+];
+
+/// Writes a row for every height of the canonical chain from `from` up to the head.
+pub fn export_heights<W: Write>(
+    chain: &mut Chain,
+    runtime_adapter: &dyn RuntimeAdapter,
+    from: BlockIndex,
+    writer: &mut W,
+) -> Result<(), Error> {
+    write_row(writer, HEIGHT_COLUMNS.iter().map(|column| column.to_string()))?;
+    let head = chain.head()?.height;
+    for height in from..=head {
+        let proposer = runtime_adapter.get_block_proposer(height).unwrap_or_default();
+        let row = match chain.get_block_by_height(height) {
+            Ok(block) => vec![
+                height.to_string(),
+                block.hash().to_string(),
+                block.header.timestamp.to_rfc3339(),
+                proposer,
+                block.header.approval_mask.iter().filter(|approved| **approved).count().to_string(),
+                block.transactions.len().to_string(),
+                block.header.total_weight.to_num().to_string(),
+            ],
+            Err(ref err) if is_not_found(err) => {
+                vec![height.to_string(), String::new(), String::new(), proposer]
+            }
+            Err(err) => return Err(err),
+        };
+        write_row(writer, row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a row for every block proposer between `from` and the head, summarizing the blocks
+/// it produced and missed and its approvals in that range.
+pub fn export_validators<W: Write>(
+    chain: &mut Chain,
+    runtime_adapter: &dyn RuntimeAdapter,
+    from: BlockIndex,
+    writer: &mut W,
+) -> Result<(), Error> {
+    let head = chain.head()?.height;
+    let from = from.max(1);
+
+    let mut accounts = BTreeSet::new();
+    for height in from - 1..=head {
+        if let Ok(proposers) = runtime_adapter.get_epoch_block_proposers(height) {
+            accounts.extend(proposers.into_iter().map(|(account_id, _)| account_id));
+        }
+    }
+
+    let mut monitor = ValidatorMonitor::quiet(accounts.into_iter().collect());
+    for height in from..=head {
+        let header = match chain.get_header_by_height(height) {
+            Ok(header) => header.clone(),
+            Err(ref err) if is_not_found(err) => continue,
+            Err(err) => return Err(err),
+        };
+        let prev_height = chain.get_block_header(&header.prev_hash)?.height;
+        monitor.process_block(&header, prev_height, runtime_adapter);
+    }
+
+    write_row(writer, VALIDATOR_COLUMNS.iter().map(|column| column.to_string()))?;
+    let mut summaries: Vec<_> = monitor.summaries().collect();
+    summaries.sort_by(|(left, _), (right, _)| left.cmp(right));
+    for (account_id, summary) in summaries {
+        write_row(
+            writer,
+            vec![
+                account_id.clone(),
+                summary.blocks_produced.to_string(),
+                summary.blocks_missed.to_string(),
+                summary.approvals_included.to_string(),
+                summary.approvals_missed.to_string(),
+                summary.stake.map(|stake| stake.to_string()).unwrap_or_default(),
+                summary.is_active.to_string(),
+            ],
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn is_not_found(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::DBNotFoundErr(_) => true,
+        _ => false,
+    }
+}
+
+/// Writes `fields` as a CSV record, quoting the ones that need it.
+fn write_row<W: Write, I: IntoIterator<Item = String>>(
+    writer: &mut W,
+    fields: I,
+) -> io::Result<()> {
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|field| {
+            if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+    writeln!(writer, "{}", fields.join(","))
+}
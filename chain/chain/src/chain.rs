@@ -1,19 +1,27 @@
 use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use std::time::{Duration as TimeDuration, Instant};
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
-use log::{debug, info};
+use slog::Logger;
 
 use near_primitives::hash::CryptoHash;
 use near_primitives::transaction::{ReceiptTransaction, TransactionResult};
-use near_primitives::types::{BlockIndex, MerkleHash};
-use near_store::Store;
+use near_primitives::types::{AccountId, BlockIndex, MerkleHash};
+use near_store::{Store, Trie, WrappedTrieChanges};
 
 use crate::error::{Error, ErrorKind};
+use crate::fork_choice::ForkChoice;
+use crate::logging;
+use crate::metrics;
+use crate::replay::{self, ReplayOptions, ReplayReport};
 use crate::store::{ChainStore, ChainStoreAccess, ChainStoreUpdate};
-use crate::types::{Block, BlockHeader, BlockStatus, Provenance, RuntimeAdapter, Tip};
+use crate::types::{Block, BlockHeader, BlockStatus, ChainEvent, Provenance, RuntimeAdapter, Tip};
+use crate::validator_monitor::ValidatorMonitor;
 
 /// Maximum number of orphans chain can store.
 pub const MAX_ORPHAN_SIZE: usize = 1024;
@@ -33,12 +41,18 @@ pub struct Orphan {
 pub struct OrphanBlockPool {
     orphans: HashMap<CryptoHash, Orphan>,
     height_idx: HashMap<u64, Vec<CryptoHash>>,
+    prev_hash_idx: HashMap<CryptoHash, Vec<CryptoHash>>,
     evicted: usize,
 }
 
 impl OrphanBlockPool {
     fn new() -> OrphanBlockPool {
-        OrphanBlockPool { orphans: HashMap::default(), height_idx: HashMap::default(), evicted: 0 }
+        OrphanBlockPool {
+            orphans: HashMap::default(),
+            height_idx: HashMap::default(),
+            prev_hash_idx: HashMap::default(),
+            evicted: 0,
+        }
     }
 
     fn len(&self) -> usize {
@@ -52,6 +66,9 @@ impl OrphanBlockPool {
     fn add(&mut self, orphan: Orphan) {
         let height_hashes = self.height_idx.entry(orphan.block.header.height).or_insert(vec![]);
         height_hashes.push(orphan.block.hash());
+        let prev_hash_hashes =
+            self.prev_hash_idx.entry(orphan.block.header.prev_hash).or_insert(vec![]);
+        prev_hash_hashes.push(orphan.block.hash());
         self.orphans.insert(orphan.block.hash(), orphan);
 
         if self.orphans.len() > MAX_ORPHAN_SIZE {
@@ -75,6 +92,11 @@ impl OrphanBlockPool {
                 }
             }
             self.height_idx.retain(|_, ref mut xs| xs.iter().any(|x| !removed_hashes.contains(&x)));
+            let orphans = &self.orphans;
+            self.prev_hash_idx.retain(|_, xs| {
+                xs.retain(|x| orphans.contains_key(x));
+                !xs.is_empty()
+            });
 
             self.evicted += old_len - self.orphans.len();
         }
@@ -84,10 +106,34 @@ impl OrphanBlockPool {
         self.orphans.contains_key(hash)
     }
 
-    pub fn remove_by_height(&mut self, height: BlockIndex) -> Option<Vec<Orphan>> {
-        self.height_idx
-            .remove(&height)
-            .map(|hs| hs.iter().filter_map(|h| self.orphans.remove(h)).collect())
+    /// Removes the orphans whose previous block is `prev_hash`.
+    pub fn remove_by_prev_hash(&mut self, prev_hash: &CryptoHash) -> Option<Vec<Orphan>> {
+        let orphans = self
+            .prev_hash_idx
+            .remove(prev_hash)?
+            .iter()
+            .filter_map(|h| self.orphans.remove(h))
+            .collect::<Vec<_>>();
+        for orphan in orphans.iter() {
+            let height = orphan.block.header.height;
+            if let Some(hashes) = self.height_idx.get_mut(&height) {
+                hashes.retain(|h| *h != orphan.block.hash());
+                if hashes.is_empty() {
+                    self.height_idx.remove(&height);
+                }
+            }
+        }
+        Some(orphans)
+    }
+
+    /// The first ancestor of `hash` that is not an orphan, the block missing for it to be
+    /// processed.
+    pub fn missing_ancestor(&self, hash: &CryptoHash) -> CryptoHash {
+        let mut hash = *hash;
+        while let Some(orphan) = self.orphans.get(&hash) {
+            hash = orphan.block.header.prev_hash;
+        }
+        hash
     }
 }
 
@@ -98,6 +144,17 @@ pub struct Chain {
     runtime_adapter: Arc<dyn RuntimeAdapter>,
     orphans: OrphanBlockPool,
     genesis: BlockHeader,
+    fork_choice: ForkChoice,
+    validator_monitor: ValidatorMonitor,
+    /// How many blocks below the finalized one keep their state, `None` to never prune.
+    state_retention: Option<BlockIndex>,
+    /// Pruning keeps the states at multiples of this height, `None` to keep none.
+    state_snapshot_interval: Option<BlockIndex>,
+    /// Where to write the replay report of a block whose state root does not match.
+    replay_report_dir: Option<PathBuf>,
+    /// Senders of the receivers returned by `subscribe`, dropped once their receiver is.
+    subscribers: Vec<Sender<ChainEvent>>,
+    log: Logger,
 }
 
 impl Chain {
@@ -106,8 +163,15 @@ impl Chain {
         runtime_adapter: Arc<dyn RuntimeAdapter>,
         genesis_time: DateTime<Utc>,
     ) -> Result<Chain, Error> {
+        let log = logging::logger(logging::CHAIN);
         let mut store = ChainStore::new(store);
 
+        // Undo the state insertions of a block batch the node stopped in the middle of.
+        let reverted = store.revert_batch_insertions(runtime_adapter.get_trie())?;
+        if reverted > 0 {
+            info!(log, "Init: reverted unfinished block batch"; "blocks" => reverted);
+        }
+
         // Get runtime initial state and create genesis block out of it.
         let (state_store_update, state_root) = runtime_adapter.genesis_state(0);
         let genesis = Block::genesis(state_root, genesis_time);
@@ -150,6 +214,7 @@ impl Chain {
                     store_update.save_block_header(genesis.header.clone());
                     store_update.save_block(genesis.clone());
                     store_update.save_receipt(&genesis.header.hash(), vec![]);
+                    store_update.save_fork_choice_block(&genesis.header, &ForkChoice::default());
 
                     head = Tip::from_header(&genesis.header);
                     store_update.save_head(&head)?;
@@ -157,23 +222,85 @@ impl Chain {
 
                     store_update.merge(state_store_update);
 
-                    info!(target: "chain", "Init: saved genesis: {:?} / {:?}", genesis.hash(), state_root);
+                    info!(log, "Init: saved genesis"; "block_hash" => %genesis.hash(), "state_root" => %state_root);
                 }
                 e => return Err(e.into()),
             },
         }
         store_update.commit()?;
 
-        info!(target: "chain", "Init: head: {} @ {} [{}]", head.total_weight.to_num(), head.height, head.last_block_hash);
+        let fork_choice = ForkChoice::restore(store.store())?;
+
+        info!(
+            log, "Init: head";
+            "block_hash" => %head.last_block_hash,
+            "height" => head.height,
+            "total_weight" => head.total_weight.to_num(),
+        );
 
         Ok(Chain {
             store,
             runtime_adapter,
             orphans: OrphanBlockPool::new(),
             genesis: genesis.header,
+            fork_choice,
+            validator_monitor: ValidatorMonitor::default(),
+            state_retention: None,
+            state_snapshot_interval: None,
+            replay_report_dir: None,
+            subscribers: vec![],
+            log,
         })
     }
 
+    /// Starts the chain from a trusted finalized `block` instead of genesis. `state` holds the
+    /// key-values of the state after the block and `receipts` the receipts it produced. Blocks
+    /// are processed on top of it right away, and the headers below it are missing until
+    /// backfilled with `backfill_headers`.
+    ///
+    /// A store that holds a chain already is opened as it is, like with `Chain::new`.
+    pub fn from_checkpoint(
+        store: Arc<Store>,
+        runtime_adapter: Arc<dyn RuntimeAdapter>,
+        genesis_time: DateTime<Utc>,
+        block: Block,
+        state: Vec<(Vec<u8>, Vec<u8>)>,
+        receipts: Vec<ReceiptTransaction>,
+    ) -> Result<Chain, Error> {
+        let log = logging::logger(logging::CHAIN);
+        let mut chain_store = ChainStore::new(store.clone());
+        let (_, genesis_state_root) = runtime_adapter.genesis_state(0);
+        let genesis = Block::genesis(genesis_state_root, genesis_time);
+        if chain_store.head().is_ok() || block.hash() == genesis.hash() {
+            return Chain::new(store, runtime_adapter, genesis_time);
+        }
+
+        let trie = runtime_adapter.get_trie();
+        let (state_store_update, state_root) = trie
+            .update(&Trie::empty_root(), state.into_iter().map(|(key, value)| (key, Some(value))))
+            .and_then(|trie_changes| trie_changes.into(trie.clone()))
+            .map_err(|err| ErrorKind::Other(err.to_string()))?;
+
+        let mut store_update = chain_store.store_update();
+        store_update.save_checkpoint(genesis.header, block.header.clone());
+        store_update.save_post_state_root(&block.hash(), &state_root);
+        store_update.save_receipt(&block.hash(), receipts);
+        store_update.save_fork_choice_block(&block.header, &ForkChoice::default());
+        store_update.save_justified(block.hash());
+        store_update.save_finalized(block.hash());
+        store_update.save_block(block.clone());
+        store_update.merge(state_store_update);
+        store_update.commit()?;
+
+        info!(
+            log, "Init: saved checkpoint";
+            "block_hash" => %block.hash(),
+            "height" => block.header.height,
+            "state_root" => %state_root,
+        );
+        Chain::new(store, runtime_adapter, genesis_time)
+    }
+
     /// Reset "sync" head to current header head.
     /// Do this when first transition to header syncing.
     pub fn reset_sync_head(&mut self) -> Result<Tip, Error> {
@@ -187,8 +314,13 @@ impl Chain {
     /// Process a block header received during "header first" propagation.
     pub fn process_block_header(&mut self, header: &BlockHeader) -> Result<(), Error> {
         // We create new chain update, but it's not going to be committed so it's read only.
-        let mut chain_update =
-            ChainUpdate::new(&mut self.store, self.runtime_adapter.clone(), &self.orphans);
+        let mut chain_update = ChainUpdate::new(
+            &mut self.store,
+            self.runtime_adapter.clone(),
+            &self.orphans,
+            &self.fork_choice,
+            &self.log,
+        );
         chain_update.process_block_header(header)?;
         Ok(())
     }
@@ -204,20 +336,348 @@ impl Chain {
     where
         F: Copy + FnMut(&Block, BlockStatus, Provenance) -> (),
     {
-        let height = block.header.height;
+        let hash = block.hash();
         let res = self.process_block_single(block, provenance, block_accepted);
         if res.is_ok() {
-            if let Some(new_res) = self.check_orphans(height + 1, block_accepted) {
+            if let Some(new_res) = self.check_orphans(hash, block_accepted) {
                 return Ok(Some(new_res));
             }
         }
         res
     }
 
+    /// Processes a batch of `blocks`, e.g. a range received while syncing, by height. Each block
+    /// is applied on the state and receipts its parent left in the same update, and the chain is
+    /// written once at the end. Known blocks are skipped and blocks whose parent is unknown are
+    /// kept as orphans, and if any other block is rejected, nothing of the batch is kept.
+    pub fn process_block_batch<F>(
+        &mut self,
+        blocks: Vec<Block>,
+        provenance: Provenance,
+        mut block_accepted: F,
+    ) -> Result<Option<Tip>, Error>
+    where
+        F: Copy + FnMut(&Block, BlockStatus, Provenance) -> (),
+    {
+        let mut batch = vec![];
+        for block in blocks {
+            if !self.store.block_exists(&block.hash())? {
+                batch.push(block);
+            }
+        }
+        batch.sort_by_key(|block| (block.header.height, block.hash()));
+        batch.dedup_by_key(|block| block.hash());
+
+        let mut prev_head = self.store.head()?;
+        let mut chain_update = ChainUpdate::new(
+            &mut self.store,
+            self.runtime_adapter.clone(),
+            &self.orphans,
+            &self.fork_choice,
+            &self.log,
+        );
+        let mut accepted = vec![];
+        let mut orphans = vec![];
+        let mut rejected = None;
+        for block in batch {
+            let res = chain_update.process_block(&block, &provenance).and_then(|head| {
+                // The next block is applied on top of this state.
+                chain_update.chain_store_update.commit_insertions()?;
+                Ok(head)
+            });
+            match res {
+                Ok(head) => accepted.push((block, head)),
+                Err(ref err) if err.kind() == ErrorKind::Orphan => orphans.push(block),
+                Err(err) => {
+                    rejected = Some((block.hash(), err));
+                    break;
+                }
+            }
+        }
+        if let Some((hash, err)) = rejected {
+            chain_update.chain_store_update.revert_insertions()?;
+            metrics::BLOCKS_PROCESSED.with_label_values(&["rejected"]).inc();
+            self.emit_invalid_block(hash, &err);
+            debug!(self.log, "Block batch rejected"; "blocks" => accepted.len(), "error" => %err);
+            return Err(err);
+        }
+        chain_update.commit()?;
+
+        let orphan_hashes: Vec<_> = orphans.iter().map(|block| block.hash()).collect();
+        for block in orphans {
+            metrics::BLOCKS_PROCESSED.with_label_values(&["orphan"]).inc();
+            debug!(self.log, "Block batch: orphan"; "block_hash" => %block.hash());
+            self.orphans.add(Orphan { block, provenance, added: Instant::now() });
+        }
+        let mut missing = vec![];
+        for hash in orphan_hashes.iter() {
+            let ancestor = self.orphans.missing_ancestor(hash);
+            if !missing.contains(&ancestor) {
+                missing.push(ancestor);
+                self.emit(ChainEvent::NeedBlock(ancestor));
+            }
+        }
+
+        let mut new_head = None;
+        for (block, head) in accepted.iter() {
+            let status = self.block_processed(block, head.clone(), prev_head.clone())?;
+            if let Some(head) = head {
+                prev_head = head.clone();
+                new_head = Some(head.clone());
+            }
+            // Notify other parts of the system of the update.
+            block_accepted(block, status, provenance);
+        }
+        debug!(self.log, "Processed block batch"; "blocks" => accepted.len());
+
+        for (block, _) in accepted.iter() {
+            if let Some(head) = self.check_orphans(block.hash(), block_accepted) {
+                new_head = Some(head);
+            }
+        }
+        Ok(new_head)
+    }
+
+    /// Returns a receiver of the chain events from now on: head updates, reorgs, finalized,
+    /// invalid and missing blocks.
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Marks block `hash` as justified.
+    pub fn set_justified(&mut self, hash: CryptoHash) -> Result<(), Error> {
+        let mut chain_store_update = self.store.store_update();
+        chain_store_update.save_justified(hash);
+        chain_store_update.commit()?;
+        self.fork_choice.set_justified(hash);
+        Ok(())
+    }
+
+    /// Marks block `hash` as finalized. The states and forks it lets go are pruned by
+    /// `prune_states`.
+    pub fn set_finalized(&mut self, hash: CryptoHash) -> Result<(), Error> {
+        let mut chain_store_update = self.store.store_update();
+        chain_store_update.save_finalized(hash);
+        chain_store_update.commit()?;
+        self.fork_choice.set_finalized(hash);
+        self.emit(ChainEvent::Finalized(hash));
+        Ok(())
+    }
+
+    /// Prunes the states and forks below the finalized block that are older than the state
+    /// retention allows, returning how many fork blocks were deleted. Does nothing without a
+    /// retention or a finalized block.
+    ///
+    /// Pruning may apply the trie changes of many blocks, so it is not done while importing
+    /// blocks but called apart from it.
+    pub fn prune_states(&mut self) -> Result<usize, Error> {
+        let (retention, finalized) = match (self.state_retention, self.fork_choice.finalized()) {
+            (Some(retention), Some(finalized)) => (retention, *finalized),
+            _ => return Ok(0),
+        };
+        let height = self.store.get_block_header(&finalized)?.height.saturating_sub(retention);
+        let deleted_blocks = self.store.prune(
+            height,
+            self.runtime_adapter.get_trie(),
+            self.state_snapshot_interval,
+        )?;
+        for hash in deleted_blocks.iter() {
+            self.fork_choice.forget(hash);
+        }
+        debug!(
+            self.log, "Pruned states";
+            "height" => height,
+            "deleted_blocks" => deleted_blocks.len(),
+        );
+        Ok(deleted_blocks.len())
+    }
+
+    /// Keeps the states of `retention` blocks below the finalized one, or every state if `None`.
+    pub fn set_state_retention(&mut self, retention: Option<BlockIndex>) {
+        self.state_retention = retention;
+    }
+
+    /// Keeps the states at multiples of `interval` when pruning, so that `state_at_height` can
+    /// replay the pruned states from them. An `interval` of zero keeps none, like `None`.
+    pub fn set_state_snapshot_interval(&mut self, interval: Option<BlockIndex>) {
+        self.state_snapshot_interval = interval.filter(|interval| *interval > 0);
+    }
+
+    /// Writes a replay report into `dir` when a block is rejected because its state root does
+    /// not match the state after its previous block, or none if `None`.
+    pub fn set_replay_report_dir(&mut self, dir: Option<PathBuf>) {
+        self.replay_report_dir = dir;
+    }
+
+    /// Calls `f` with the state root of the canonical block at `height`. A pruned state is
+    /// replayed from the nearest snapshot below it, and discarded again once `f` returns.
+    pub fn state_at_height<T, F>(&mut self, height: BlockIndex, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&MerkleHash) -> T,
+    {
+        let hash = self.store.get_block_hash_by_height(height)?;
+        self.state_at_block(hash, f)
+    }
+
+    /// Like `state_at_height`, for the state after block `hash`.
+    fn state_at_block<T, F>(&mut self, mut hash: CryptoHash, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&MerkleHash) -> T,
+    {
+        // Blocks without a state, from the highest down.
+        let mut pruned = vec![];
+        let mut state_root = loop {
+            match self.store.get_post_state_root(&hash) {
+                Ok(state_root) => break *state_root,
+                Err(err) => match err.kind() {
+                    ErrorKind::DBNotFoundErr(_) => {
+                        pruned.push(hash);
+                        hash = self.store.get_block_header(&hash)?.prev_hash;
+                    }
+                    _ => return Err(err),
+                },
+            }
+        };
+
+        let mut replayed = vec![];
+        let result = self.replay_blocks(&pruned, &mut state_root, &mut replayed);
+        let value = result.map(|()| f(&state_root));
+        for trie_changes in replayed.iter().rev() {
+            let mut store_update = self.store.store().store_update();
+            trie_changes
+                .revert_insertions_into(&mut store_update)
+                .map_err(|err| ErrorKind::Other(err.to_string()))?;
+            store_update.commit()?;
+        }
+        value
+    }
+
+    /// Replays `hashes`, from the last one up, on top of `state_root`. The states are written to
+    /// the store, and their changes pushed to `replayed` to discard them again.
+    fn replay_blocks(
+        &mut self,
+        hashes: &[CryptoHash],
+        state_root: &mut MerkleHash,
+        replayed: &mut Vec<WrappedTrieChanges>,
+    ) -> Result<(), Error> {
+        for hash in hashes.iter().rev() {
+            let block = self.store.get_block(hash)?.clone();
+            if block.header.prev_state_root != *state_root {
+                return Err(ErrorKind::InvalidStateRoot.into());
+            }
+            let receipts = self.store.get_receipts(&block.header.prev_hash)?.clone();
+            let (trie_changes, next_state_root) = self
+                .runtime_adapter
+                .replay_transactions(
+                    0,
+                    state_root,
+                    block.header.height,
+                    &block.header.prev_hash,
+                    &vec![receipts], // TODO: currently only taking into account one shard.
+                    &block.transactions,
+                )
+                .map_err(|err| ErrorKind::Other(err.to_string()))?;
+            let mut store_update = self.store.store().store_update();
+            trie_changes
+                .insertions_into(&mut store_update)
+                .map_err(|err| ErrorKind::Other(err.to_string()))?;
+            store_update.commit()?;
+            replayed.push(trie_changes);
+            *state_root = next_state_root;
+        }
+        Ok(())
+    }
+
+    /// Re-executes the already imported block `hash` on the state after its previous block, with
+    /// every stage traced by the `replay` logger. The report is also written as CBOR if the state
+    /// root does not match and `options` has a report directory. Nothing is kept in the store.
+    pub fn replay(
+        &mut self,
+        hash: &CryptoHash,
+        options: ReplayOptions,
+    ) -> Result<ReplayReport, Error> {
+        let block = self.store.get_block(hash)?.clone();
+        let receipts = self.store.get_receipts(&block.header.prev_hash)?.clone();
+        let expected_state_root = match options.expected_state_root {
+            Some(state_root) => Some(state_root),
+            None => match self.store.get_post_state_root(hash) {
+                Ok(state_root) => Some(*state_root),
+                Err(err) => match err.kind() {
+                    // Pruned, nothing to compare with.
+                    ErrorKind::DBNotFoundErr(_) => None,
+                    _ => return Err(err),
+                },
+            },
+        };
+
+        let runtime_adapter = self.runtime_adapter.clone();
+        let log = logging::logger(logging::REPLAY);
+        let report = self.state_at_block(block.header.prev_hash, |state_root| {
+            replay::replay_block(
+                &*runtime_adapter,
+                &block,
+                state_root,
+                &receipts,
+                expected_state_root,
+                &log,
+            )
+        })??;
+        if let (true, Some(dir)) = (report.is_mismatch(), options.report_dir) {
+            let path = report.write_to_dir(&dir)?;
+            warn!(log, "Wrote replay report"; "path" => %path.display());
+        }
+        Ok(report)
+    }
+
+    /// Saves `headers` below the tail of a chain started from a checkpoint, newest first, each
+    /// the parent of the one before. Returns whether every header down to genesis is known.
+    pub fn backfill_headers(&mut self, headers: Vec<BlockHeader>) -> Result<bool, Error> {
+        let mut tail = match self.store.tail() {
+            Ok(tail) => tail,
+            Err(err) => match err.kind() {
+                // Started from genesis.
+                ErrorKind::DBNotFoundErr(_) => return Ok(true),
+                _ => return Err(err),
+            },
+        };
+        let genesis_hash = self.genesis.hash();
+
+        let mut chain_store_update = self.store.store_update();
+        for header in headers {
+            if tail.prev_block_hash == genesis_hash {
+                break;
+            }
+            if header.hash() != tail.prev_block_hash {
+                return Err(ErrorKind::Other(format!(
+                    "Header {} is not the parent of the tail {}",
+                    header.hash(),
+                    tail.last_block_hash
+                ))
+                .into());
+            }
+            tail = Tip::from_header(&header);
+            chain_store_update.save_tail_header(header);
+        }
+        chain_store_update.commit()?;
+        Ok(tail.prev_block_hash == genesis_hash)
+    }
+
+    /// Starts monitoring `accounts`, replacing the previously monitored validators.
+    pub fn monitor_validators(&mut self, accounts: Vec<AccountId>) {
+        self.validator_monitor = ValidatorMonitor::new(accounts);
+    }
+
     /// Processes headers and adds them to store for syncing.
     pub fn sync_block_headers(&mut self, headers: Vec<BlockHeader>) -> Result<(), Error> {
-        let mut chain_update =
-            ChainUpdate::new(&mut self.store, self.runtime_adapter.clone(), &self.orphans);
+        let mut chain_update = ChainUpdate::new(
+            &mut self.store,
+            self.runtime_adapter.clone(),
+            &self.orphans,
+            &self.fork_choice,
+            &self.log,
+        );
         chain_update.sync_block_headers(headers)?;
         chain_update.commit()
     }
@@ -277,20 +737,90 @@ impl Chain {
         None
     }
 
-    fn determine_status(&self, head: Option<Tip>, prev_head: Tip) -> BlockStatus {
-        let has_head = head.is_some();
-        let mut is_next_block = false;
+    /// Status of `block`, which led to `head` from `prev_head`, with the headers it added to the
+    /// canonical chain, oldest first.
+    fn determine_status(
+        &mut self,
+        block: &Block,
+        head: &Option<Tip>,
+        prev_head: &Tip,
+    ) -> Result<(BlockStatus, Vec<BlockHeader>), Error> {
+        match head {
+            Some(head) if head.prev_block_hash == prev_head.last_block_hash => {
+                Ok((BlockStatus::Next, vec![block.header.clone()]))
+            }
+            Some(head) => {
+                let (depth, added) = self.reorg(prev_head, head)?;
+                Ok((BlockStatus::Reorg(depth), added))
+            }
+            None => Ok((BlockStatus::Fork, vec![])),
+        }
+    }
 
+    /// Accounts for `block` once committed, with the `head` it led to from `prev_head`.
+    fn block_processed(
+        &mut self,
+        block: &Block,
+        head: Option<Tip>,
+        prev_head: Tip,
+    ) -> Result<BlockStatus, Error> {
+        metrics::BLOCKS_PROCESSED.with_label_values(&["accepted"]).inc();
+        self.fork_choice.process_block(&block.header);
+        metrics::FORK_CHOICE_RUNS.inc();
+        let (status, added) = self.determine_status(block, &head, &prev_head)?;
+        if !self.validator_monitor.is_empty() {
+            for header in added.iter() {
+                let prev_height = self.store.get_block_header(&header.prev_hash)?.height;
+                self.validator_monitor.process_block(header, prev_height, &*self.runtime_adapter);
+            }
+        }
         if let Some(head) = head {
-            if head.prev_block_hash == prev_head.last_block_hash {
-                is_next_block = true;
+            if let BlockStatus::Reorg(depth) = status {
+                self.emit(ChainEvent::Reorg {
+                    old_head: prev_head.last_block_hash,
+                    new_head: head.last_block_hash,
+                    depth,
+                });
+            }
+            self.emit(ChainEvent::HeadUpdated(head));
+        }
+        Ok(status)
+    }
+
+    /// Number of blocks of the chain ending at `old_head` that are not on the one ending at
+    /// `new_head`, and the headers of the chain ending at `new_head` that are not on the other
+    /// one, oldest first.
+    fn reorg(
+        &mut self,
+        old_head: &Tip,
+        new_head: &Tip,
+    ) -> Result<(BlockIndex, Vec<BlockHeader>), Error> {
+        let mut depth = 0;
+        let mut added = vec![];
+        let mut old = self.store.get_block_header(&old_head.last_block_hash)?.clone();
+        let mut new = self.store.get_block_header(&new_head.last_block_hash)?.clone();
+        while old.hash() != new.hash() {
+            if old.height >= new.height {
+                old = self.store.get_previous_header(&old)?.clone();
+                depth += 1;
+            } else {
+                let prev = self.store.get_previous_header(&new)?.clone();
+                added.push(mem::replace(&mut new, prev));
             }
         }
+        added.reverse();
+        Ok((depth, added))
+    }
 
-        match (has_head, is_next_block) {
-            (true, true) => BlockStatus::Next,
-            (true, false) => BlockStatus::Reorg,
-            (false, _) => BlockStatus::Fork,
+    /// Sends `event` to every subscriber still listening.
+    fn emit(&mut self, event: ChainEvent) {
+        self.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Reports an invalid block to the subscribers, unless `err` is not about its data.
+    fn emit_invalid_block(&mut self, hash: CryptoHash, err: &Error) {
+        if err.is_bad_data() {
+            self.emit(ChainEvent::InvalidBlock { hash, error: err.kind().to_string() });
         }
     }
 
@@ -304,8 +834,13 @@ impl Chain {
         F: FnMut(&Block, BlockStatus, Provenance) -> (),
     {
         let prev_head = self.store.head()?;
-        let mut chain_update =
-            ChainUpdate::new(&mut self.store, self.runtime_adapter.clone(), &self.orphans);
+        let mut chain_update = ChainUpdate::new(
+            &mut self.store,
+            self.runtime_adapter.clone(),
+            &self.orphans,
+            &self.fork_choice,
+            &self.log,
+        );
         let maybe_new_head = chain_update.process_block(&block, &provenance);
 
         if let Ok(_) = maybe_new_head {
@@ -314,7 +849,7 @@ impl Chain {
 
         match maybe_new_head {
             Ok(head) => {
-                let status = self.determine_status(head.clone(), prev_head);
+                let status = self.block_processed(&block, head.clone(), prev_head)?;
 
                 // Notify other parts of the system of the update.
                 block_accepted(&block, status, provenance);
@@ -323,84 +858,102 @@ impl Chain {
             }
             Err(e) => match e.kind() {
                 ErrorKind::Orphan => {
+                    metrics::BLOCKS_PROCESSED.with_label_values(&["orphan"]).inc();
                     let block_hash = block.hash();
                     let orphan = Orphan { block, provenance, added: Instant::now() };
 
                     self.orphans.add(orphan);
+                    let missing = self.orphans.missing_ancestor(&block_hash);
+                    self.emit(ChainEvent::NeedBlock(missing));
 
                     debug!(
-                        target: "chain",
-                        "Process block: orphan: {:?}, # orphans {}{}",
-                        block_hash,
-                        self.orphans.len(),
-                        if self.orphans.len_evicted() > 0 {
-                            format!(", # evicted {}", self.orphans.len_evicted())
-                        } else {
-                            String::new()
-                        },
+                        self.log, "Process block: orphan";
+                        "block_hash" => %block_hash,
+                        "orphans" => self.orphans.len(),
+                        "evicted" => self.orphans.len_evicted(),
                     );
                     Err(ErrorKind::Orphan.into())
                 }
                 ErrorKind::Unfit(ref msg) => {
+                    metrics::BLOCKS_PROCESSED.with_label_values(&["unfit"]).inc();
                     debug!(
-                        target: "chain",
-                        "Block {} at {} is unfit at this time: {}",
-                        block.hash(),
-                        block.header.height,
-                        msg
+                        self.log, "Block is unfit at this time";
+                        "block_hash" => %block.hash(),
+                        "height" => block.header.height,
+                        "reason" => msg,
                     );
                     Err(ErrorKind::Unfit(msg.clone()).into())
                 }
-                _ => Err(ErrorKind::Other(format!("{:?}", e)).into()),
+                ErrorKind::InvalidStateRoot => {
+                    metrics::BLOCKS_PROCESSED.with_label_values(&["rejected"]).inc();
+                    self.emit_invalid_block(block.hash(), &e);
+                    if let Some(dir) = self.replay_report_dir.clone() {
+                        // Replays the previous block, whose state the block disagrees with.
+                        let options = ReplayOptions {
+                            expected_state_root: Some(block.header.prev_state_root),
+                            report_dir: Some(dir),
+                        };
+                        if let Err(err) = self.replay(&block.header.prev_hash, options) {
+                            warn!(
+                                self.log, "Failed to replay block";
+                                "block_hash" => %block.header.prev_hash,
+                                "error" => %err,
+                            );
+                        }
+                    }
+                    Err(ErrorKind::Other(format!("{:?}", e)).into())
+                }
+                _ => {
+                    metrics::BLOCKS_PROCESSED.with_label_values(&["rejected"]).inc();
+                    self.emit_invalid_block(block.hash(), &e);
+                    Err(ErrorKind::Other(format!("{:?}", e)).into())
+                }
             },
         }
     }
 
-    /// Check for orphans, once a block is successfully added.
-    fn check_orphans<F>(&mut self, mut height: BlockIndex, block_accepted: F) -> Option<Tip>
+    /// Check for orphans, once block `hash` is successfully added. The orphans it unlocks are
+    /// processed in turn, down to every descendant that is known.
+    fn check_orphans<F>(&mut self, hash: CryptoHash, block_accepted: F) -> Option<Tip>
     where
         F: Copy + FnMut(&Block, BlockStatus, Provenance) -> (),
     {
-        let initial_height = height;
-
-        let mut orphan_accepted = false;
+        let mut accepted = 0;
         let mut maybe_new_head = None;
 
         // Check if there are orphans we can process.
-        debug!(target: "chain", "Check orphans: at {}, # orphans {}", height, self.orphans.len());
-        loop {
-            if let Some(orphans) = self.orphans.remove_by_height(height) {
-                debug!(target: "chain", "Check orphans: found {} orphans", orphans.len());
+        debug!(self.log, "Check orphans"; "block_hash" => %hash, "orphans" => self.orphans.len());
+        let mut parents = vec![hash];
+        while let Some(parent) = parents.pop() {
+            if let Some(orphans) = self.orphans.remove_by_prev_hash(&parent) {
+                debug!(self.log, "Check orphans: found orphans"; "block_hash" => %parent, "found" => orphans.len());
                 for orphan in orphans.into_iter() {
+                    let orphan_hash = orphan.block.hash();
                     let res =
                         self.process_block_single(orphan.block, orphan.provenance, block_accepted);
                     match res {
                         Ok(maybe_tip) => {
-                            maybe_new_head = maybe_tip;
-                            orphan_accepted = true;
+                            if maybe_tip.is_some() {
+                                maybe_new_head = maybe_tip;
+                            }
+                            accepted += 1;
+                            // Accepted a block, so check the orphans it unlocks.
+                            parents.push(orphan_hash);
                         }
                         Err(err) => {
-                            debug!(target: "chain", "Orphan declined: {}", err);
+                            debug!(self.log, "Orphan declined"; "error" => %err);
                         }
                     }
                 }
-
-                if orphan_accepted {
-                    // Accepted a block, so should check if there are now new orphans unlocked.
-                    height += 1;
-                    continue;
-                }
             }
-            break;
         }
 
-        if initial_height != height {
+        if accepted > 0 {
             debug!(
-                target: "chain",
-                "Check orphans: {} blocks accepted since height {}, remaining # orphans {}",
-                height - initial_height,
-                initial_height,
-                self.orphans.len(),
+                self.log, "Check orphans: accepted";
+                "accepted" => accepted,
+                "block_hash" => %hash,
+                "orphans" => self.orphans.len(),
             );
         }
 
@@ -422,6 +975,13 @@ impl Chain {
         self.store.header_head()
     }
 
+    /// Gets chain tail, the lowest block with every header above it known. Only set on chains
+    /// started from a checkpoint.
+    #[inline]
+    pub fn tail(&self) -> Result<Tip, Error> {
+        self.store.tail()
+    }
+
     /// Gets "sync" head. This may be significantly different to current header chain.
     #[inline]
     pub fn sync_head(&self) -> Result<Tip, Error> {
@@ -499,6 +1059,18 @@ impl Chain {
         &self.store
     }
 
+    /// Returns fork choice state.
+    #[inline]
+    pub fn fork_choice(&self) -> &ForkChoice {
+        &self.fork_choice
+    }
+
+    /// Returns the life events of the monitored validators.
+    #[inline]
+    pub fn validator_monitor(&self) -> &ValidatorMonitor {
+        &self.validator_monitor
+    }
+
     /// Returns genesis block header.
     #[inline]
     pub fn genesis(&self) -> &BlockHeader {
@@ -522,6 +1094,13 @@ impl Chain {
     pub fn is_orphan(&self, hash: &CryptoHash) -> bool {
         self.orphans.contains(hash)
     }
+
+    /// Hash of the first ancestor of orphan `hash` that is not an orphan itself, the block to
+    /// request for `hash` to be processed.
+    #[inline]
+    pub fn missing_ancestor(&self, hash: &CryptoHash) -> CryptoHash {
+        self.orphans.missing_ancestor(hash)
+    }
 }
 
 /// Chain update helper, contains information that is needed to process block
@@ -532,6 +1111,8 @@ struct ChainUpdate<'a> {
     runtime_adapter: Arc<dyn RuntimeAdapter>,
     chain_store_update: ChainStoreUpdate<'a, ChainStore>,
     orphans: &'a OrphanBlockPool,
+    fork_choice: &'a ForkChoice,
+    log: &'a Logger,
 }
 
 impl<'a> ChainUpdate<'a> {
@@ -539,9 +1120,11 @@ impl<'a> ChainUpdate<'a> {
         store: &'a mut ChainStore,
         runtime_adapter: Arc<dyn RuntimeAdapter>,
         orphans: &'a OrphanBlockPool,
+        fork_choice: &'a ForkChoice,
+        log: &'a Logger,
     ) -> Self {
         let chain_store_update = store.store_update();
-        ChainUpdate { runtime_adapter, chain_store_update, orphans }
+        ChainUpdate { runtime_adapter, chain_store_update, orphans, fork_choice, log }
     }
 
     /// Commit changes to the chain into the database.
@@ -554,7 +1137,7 @@ impl<'a> ChainUpdate<'a> {
     /// based on this. We will update these once we get the block back after
     /// requesting it.
     pub fn process_block_header(&mut self, header: &BlockHeader) -> Result<(), Error> {
-        debug!(target: "chain", "Process block header: {} at {}", header.hash(), header.height);
+        debug!(self.log, "Process block header"; "block_hash" => %header.hash(), "height" => header.height);
 
         self.check_header_known(header)?;
         self.validate_header(header, &Provenance::NONE)?;
@@ -576,7 +1159,13 @@ impl<'a> ChainUpdate<'a> {
         block: &Block,
         provenance: &Provenance,
     ) -> Result<Option<Tip>, Error> {
-        debug!(target: "chain", "Process block {} at {}, approvals: {}, tx: {}", block.hash(), block.header.height, block.header.approval_sigs.len(), block.transactions.len());
+        debug!(
+            self.log, "Process block";
+            "block_hash" => %block.hash(),
+            "height" => block.header.height,
+            "approvals" => block.header.approval_sigs.len(),
+            "tx" => block.transactions.len(),
+        );
 
         // Check if we have already processed this block previously.
         self.check_known(&block)?;
@@ -623,7 +1212,7 @@ impl<'a> ChainUpdate<'a> {
                 &block.transactions,
             )
             .map_err(|e| ErrorKind::Other(e.to_string()))?;
-        self.chain_store_update.save_trie_changes(trie_changes);
+        self.chain_store_update.save_trie_changes(block.header.height, &block.hash(), trie_changes);
         // Save state root after applying transactions.
         self.chain_store_update.save_post_state_root(&block.hash(), &state_root);
         // Save resulting receipts.
@@ -644,6 +1233,7 @@ impl<'a> ChainUpdate<'a> {
 
         // Add validated block to the db, even if it's not the selected fork.
         self.chain_store_update.save_block(block.clone());
+        self.chain_store_update.save_fork_choice_block(&block.header, self.fork_choice);
 
         // Update the chain head if total weight has increased.
         let res = self.update_head(block)?;
@@ -666,7 +1256,12 @@ impl<'a> ChainUpdate<'a> {
     /// Process incoming block headers for syncing.
     fn sync_block_headers(&mut self, headers: Vec<BlockHeader>) -> Result<Option<Tip>, Error> {
         let _first_header = if let Some(header) = headers.first() {
-            debug!(target: "chain", "Sync block headers: {} headers from {} at {}", headers.len(), header.hash(), header.height);
+            debug!(
+                self.log, "Sync block headers";
+                "headers" => headers.len(),
+                "block_hash" => %header.hash(),
+                "height" => header.height,
+            );
             header
         } else {
             return Ok(None);
@@ -737,10 +1332,13 @@ impl<'a> ChainUpdate<'a> {
     /// Update the header head if this header has most work.
     fn update_header_head(&mut self, header: &BlockHeader) -> Result<Option<Tip>, Error> {
         let header_head = self.chain_store_update.header_head()?;
-        if header.total_weight > header_head.total_weight {
+        if header.total_weight > header_head.total_weight
+            && (header.prev_hash == header_head.last_block_hash
+                || self.is_after_finalized(header)?)
+        {
             let tip = Tip::from_header(header);
             self.chain_store_update.save_header_head(&tip)?;
-            debug!(target: "chain", "Header head updated to {} at {}", tip.last_block_hash, tip.height);
+            debug!(self.log, "Header head updated"; "block_hash" => %tip.last_block_hash, "height" => tip.height);
 
             Ok(Some(tip))
         } else {
@@ -755,22 +1353,44 @@ impl<'a> ChainUpdate<'a> {
         // if we made a fork with more work than the head (which should also be true
         // when extending the head), update it
         let head = self.chain_store_update.head()?;
-        if block.header.total_weight > head.total_weight {
+        if block.header.total_weight > head.total_weight
+            && (block.header.prev_hash == head.last_block_hash
+                || self.is_after_finalized(&block.header)?)
+        {
             let tip = Tip::from_header(&block.header);
 
             self.chain_store_update.save_body_head(&tip);
-            debug!(target: "chain", "Head updated to {} at {}", tip.last_block_hash, tip.height);
+            debug!(self.log, "Head updated"; "block_hash" => %tip.last_block_hash, "height" => tip.height);
             Ok(Some(tip))
         } else {
             Ok(None)
         }
     }
 
+    /// Whether `header` descends from the finalized block, which a new head or header head has
+    /// to: the finalized chain is never reorganized. Walks back to the indexed chain of the
+    /// header head, which does.
+    fn is_after_finalized(&mut self, header: &BlockHeader) -> Result<bool, Error> {
+        let finalized_height = match self.fork_choice.finalized() {
+            Some(finalized) => self.chain_store_update.get_block_header(finalized)?.height,
+            None => return Ok(true),
+        };
+        let mut header = header.clone();
+        while header.height > finalized_height {
+            match self.chain_store_update.get_block_hash_by_height(header.height) {
+                Ok(hash) if hash == header.hash() => return Ok(true),
+                _ => header = self.chain_store_update.get_previous_header(&header)?.clone(),
+            }
+        }
+        Ok(header.height == finalized_height
+            && Some(&header.hash()) == self.fork_choice.finalized())
+    }
+
     /// Updates "sync" head with given block header.
     fn update_sync_head(&mut self, header: &BlockHeader) -> Result<(), Error> {
         let tip = Tip::from_header(header);
         self.chain_store_update.save_sync_head(&tip);
-        debug!(target: "chain", "Sync head {} @ {}", tip.last_block_hash, tip.height);
+        debug!(self.log, "Sync head updated"; "block_hash" => %tip.last_block_hash, "height" => tip.height);
         Ok(())
     }
 
@@ -3,6 +3,7 @@ use std::io;
 
 use chrono::{DateTime, Utc};
 use failure::{Backtrace, Context, Fail};
+use filesys_errors::{CoreError, ErrorCode};
 
 #[derive(Debug)]
 pub struct Error {
@@ -106,6 +107,25 @@ impl Error {
     }
 }
 
+impl CoreError for Error {
+    fn code(&self) -> ErrorCode {
+        match self.kind() {
+            ErrorKind::Orphan | ErrorKind::DBNotFoundErr(_) => ErrorCode::NotFound,
+            ErrorKind::OldBlock => ErrorCode::Conflict,
+            ErrorKind::IOErr(_) => ErrorCode::Io,
+            ErrorKind::Other(_) => ErrorCode::Internal,
+            ErrorKind::Unfit(_)
+            | ErrorKind::InvalidBlockPastTime(_, _)
+            | ErrorKind::InvalidBlockFutureTime(_)
+            | ErrorKind::InvalidBlockHeight
+            | ErrorKind::InvalidBlockProposer
+            | ErrorKind::InvalidBlockConfirmation
+            | ErrorKind::InvalidBlockWeight
+            | ErrorKind::InvalidStateRoot => ErrorCode::InvalidInput,
+        }
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Error {
         Error { inner: Context::new(kind) }
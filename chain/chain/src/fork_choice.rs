@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use near_primitives::hash::CryptoHash;
+use near_primitives::serialize::Decode;
+use near_store::{Store, StoreUpdate, COL_FORK_CHOICE};
+
+use crate::error::{Error, ErrorKind};
+use crate::types::{BlockHeader, Weight};
+
+const JUSTIFIED_KEY: &[u8; 9] = b"JUSTIFIED";
+const FINALIZED_KEY: &[u8; 9] = b"FINALIZED";
+const VOTE_PREFIX: u8 = b'v';
+const WEIGHT_PREFIX: u8 = b'w';
+
+/// Key under which the latest vote of the block producer at `index` is stored.
+fn vote_key(index: u64) -> Vec<u8> {
+    let mut key = vec![VOTE_PREFIX];
+    key.extend_from_slice(&index.to_le_bytes());
+    key
+}
+
+/// Key under which the total weight of block `hash` is stored.
+pub(crate) fn weight_key(hash: &CryptoHash) -> Vec<u8> {
+    let mut key = vec![WEIGHT_PREFIX];
+    key.extend_from_slice(hash.as_ref());
+    key
+}
+
+/// Fork choice accounting: latest approval of each block producer, total weight of every imported
+/// block and the justified / finalized markers.
+///
+/// The persisted copy lives in `COL_FORK_CHOICE` and is written by `ChainStoreUpdate` in the same
+/// batch as the block it accounts for, so after a crash `restore` always matches the stored blocks.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ForkChoice {
+    votes: HashMap<u64, CryptoHash>,
+    weights: HashMap<CryptoHash, Weight>,
+    justified: Option<CryptoHash>,
+    finalized: Option<CryptoHash>,
+}
+
+impl ForkChoice {
+    /// Loads fork choice state from the store.
+    pub fn restore(store: &Store) -> Result<ForkChoice, Error> {
+        let mut fork_choice = ForkChoice::default();
+        for (key, value) in store.iter(COL_FORK_CHOICE) {
+            match key.first() {
+                Some(&VOTE_PREFIX) if key.len() == 9 => {
+                    let mut index = [0u8; 8];
+                    index.copy_from_slice(&key[1..]);
+                    fork_choice.votes.insert(u64::from_le_bytes(index), Decode::decode(&value)?);
+                }
+                Some(&WEIGHT_PREFIX) => {
+                    let hash = CryptoHash::try_from(&key[1..])
+                        .map_err(|err| ErrorKind::Other(err.to_string()))?;
+                    fork_choice.weights.insert(hash, Decode::decode(&value)?);
+                }
+                _ => {}
+            }
+        }
+        fork_choice.justified = store.get_ser(COL_FORK_CHOICE, JUSTIFIED_KEY)?;
+        fork_choice.finalized = store.get_ser(COL_FORK_CHOICE, FINALIZED_KEY)?;
+        Ok(fork_choice)
+    }
+
+    /// Writes every entry of `self` into `store_update`.
+    ///
+    /// Used by `ChainStoreUpdate` with a fork choice that only holds the changes made during the
+    /// update, so entries already in the store are left untouched.
+    pub(crate) fn write_to(&self, store_update: &mut StoreUpdate) -> Result<(), Error> {
+        for (index, hash) in self.votes.iter() {
+            store_update.set_ser(COL_FORK_CHOICE, &vote_key(*index), hash)?;
+        }
+        for (hash, weight) in self.weights.iter() {
+            store_update.set_ser(COL_FORK_CHOICE, &weight_key(hash), weight)?;
+        }
+        if let Some(hash) = self.justified {
+            store_update.set_ser(COL_FORK_CHOICE, JUSTIFIED_KEY, &hash)?;
+        }
+        if let Some(hash) = self.finalized {
+            store_update.set_ser(COL_FORK_CHOICE, FINALIZED_KEY, &hash)?;
+        }
+        Ok(())
+    }
+
+    /// Accounts for a newly imported block: records its weight and the approvals it carries.
+    /// Approvals in a header are votes for its parent.
+    ///
+    /// A vote only moves to a target heavier than the one it replaces, so a block imported after
+    /// its descendants never moves votes back to an older block.
+    pub fn process_block(&mut self, header: &BlockHeader) {
+        self.process_block_over(header, &ForkChoice::default());
+    }
+
+    /// Like `process_block`, for a fork choice holding the changes made on top of `base`.
+    pub(crate) fn process_block_over(&mut self, header: &BlockHeader, base: &ForkChoice) {
+        self.weights.insert(header.hash(), header.total_weight);
+        let weight = |hash: &CryptoHash| self.weight(hash).or_else(|| base.weight(hash));
+        let target_weight = weight(&header.prev_hash);
+        let mut votes = vec![];
+        for (index, _) in header.approval_mask.iter().enumerate().filter(|(_, approved)| **approved)
+        {
+            let index = index as u64;
+            let replaces = match self.latest_vote(index).or_else(|| base.latest_vote(index)) {
+                Some(current) => target_weight > weight(current),
+                None => true,
+            };
+            if replaces {
+                votes.push(index);
+            }
+        }
+        for index in votes {
+            self.votes.insert(index, header.prev_hash);
+        }
+    }
+
+    pub fn set_justified(&mut self, hash: CryptoHash) {
+        self.justified = Some(hash);
+    }
+
+    pub fn set_finalized(&mut self, hash: CryptoHash) {
+        self.finalized = Some(hash);
+    }
+
+    /// Forgets the weight of block `hash`, once it is pruned.
+    pub(crate) fn forget(&mut self, hash: &CryptoHash) {
+        self.weights.remove(hash);
+    }
+
+    /// Total weight of block `hash`, if it was imported.
+    pub fn weight(&self, hash: &CryptoHash) -> Option<Weight> {
+        self.weights.get(hash).cloned()
+    }
+
+    /// Latest block approved by the block producer at `index` of the approval mask.
+    pub fn latest_vote(&self, index: u64) -> Option<&CryptoHash> {
+        self.votes.get(&index)
+    }
+
+    pub fn justified(&self) -> Option<&CryptoHash> {
+        self.justified.as_ref()
+    }
+
+    pub fn finalized(&self) -> Option<&CryptoHash> {
+        self.finalized.as_ref()
+    }
+}
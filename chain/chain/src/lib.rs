@@ -1,16 +1,31 @@
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate prometheus;
+#[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate slog;
 
 pub use chain::{Chain, MAX_ORPHAN_SIZE};
 pub use error::{Error, ErrorKind};
+pub use fork_choice::ForkChoice;
+pub use replay::{ReplayOptions, ReplayReport, ReplayStage};
 pub use store::{ChainStore, ChainStoreAccess};
 pub use types::{
-    Block, BlockApproval, BlockHeader, BlockStatus, Provenance, ReceiptResult, RuntimeAdapter, Tip,
-    ValidTransaction, Weight,
+    Block, BlockApproval, BlockHeader, BlockStatus, ChainEvent, Provenance, ReceiptResult,
+    RuntimeAdapter, Tip, ValidTransaction, Weight,
 };
+pub use validator_monitor::{ValidatorMonitor, ValidatorSummary};
 
+pub mod analytics;
 mod chain;
 mod error;
+mod fork_choice;
+pub mod logging;
+mod metrics;
+mod replay;
 mod store;
 pub mod test_utils;
 mod types;
+mod validator_monitor;
@@ -0,0 +1,125 @@
+//! Structured logging of the chain.
+//!
+//! Records are written to stderr as JSON lines, with their fields next to the message so they can
+//! be filtered on, like `height` and `block_hash`. Every subsystem has its own logger and level,
+//! which can be changed while the node runs, e.g. to debug block processing without drowning in
+//! validator events.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock};
+
+use slog::{Drain, Fuse, Logger, Never, OwnedKVList, Record};
+
+pub use slog::Level;
+
+/// Block processing, orphans and heads.
+pub const CHAIN: &str = "chain";
+/// Life events of the monitored validators.
+pub const VALIDATOR_MONITOR: &str = "validator_monitor";
+
+/// Stages of replayed blocks, at debug level.
+pub const REPLAY: &str = "replay";
+
+/// Every subsystem with a logger.
+pub const SUBSYSTEMS: &[&str] = &[CHAIN, VALIDATOR_MONITOR, REPLAY];
+
+/// Level of subsystems not configured otherwise.
+const DEFAULT_LEVEL: Level = Level::Info;
+
+type SharedDrain = Fuse<Mutex<slog_json::Json<io::Stderr>>>;
+
+lazy_static! {
+    static ref LEVELS: RwLock<HashMap<&'static str, Level>> = RwLock::new(HashMap::new());
+    static ref DRAIN: Arc<SharedDrain> =
+        Arc::new(Mutex::new(slog_json::Json::new(io::stderr()).add_default_keys().build()).fuse());
+}
+
+/// Logger of `subsystem`, one of `SUBSYSTEMS`.
+pub fn logger(subsystem: &'static str) -> Logger {
+    let drain = SubsystemFilter { subsystem, drain: DRAIN.clone() };
+    Logger::root(drain, o!("subsystem" => subsystem))
+}
+
+/// Current level of `subsystem`.
+pub fn level(subsystem: &str) -> Level {
+    LEVELS
+        .read()
+        .expect("lock is never poisoned; qed")
+        .get(subsystem)
+        .cloned()
+        .unwrap_or(DEFAULT_LEVEL)
+}
+
+/// Log the records of `subsystem` at `level` and above, returning `false` if there is no such
+/// subsystem.
+pub fn set_level(subsystem: &str, level: Level) -> bool {
+    match SUBSYSTEMS.iter().find(|known| **known == subsystem) {
+        Some(known) => {
+            LEVELS.write().expect("lock is never poisoned; qed").insert(known, level);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Level of every subsystem.
+pub fn levels() -> Vec<(&'static str, Level)> {
+    SUBSYSTEMS.iter().map(|subsystem| (*subsystem, level(subsystem))).collect()
+}
+
+/// Passes on the records of `subsystem` at or above its current level.
+struct SubsystemFilter<D> {
+    subsystem: &'static str,
+    drain: D,
+}
+
+impl<D: Drain<Ok = (), Err = Never>> Drain for SubsystemFilter<D> {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        if record.level().is_at_least(level(self.subsystem)) {
+            self.drain.log(record, values)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collects the messages of the records logged.
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for Collect {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_subsystem_levels() {
+        let collect = Collect::default();
+        let log = Logger::root(
+            SubsystemFilter { subsystem: VALIDATOR_MONITOR, drain: collect.clone() },
+            o!(),
+        );
+
+        debug!(log, "hidden"; "height" => 1);
+        info!(log, "shown"; "height" => 1);
+        assert!(set_level(VALIDATOR_MONITOR, Level::Debug));
+        debug!(log, "debugged"; "height" => 2);
+        assert!(set_level(VALIDATOR_MONITOR, Level::Info));
+
+        assert_eq!(*collect.0.lock().unwrap(), vec!["shown".to_string(), "debugged".to_string()]);
+        assert!(!set_level("unknown", Level::Debug));
+        assert_eq!(level(CHAIN), DEFAULT_LEVEL);
+    }
+}
@@ -0,0 +1,41 @@
+//! Prometheus metrics of the chain, registered with the default registry and served by the API.
+
+use prometheus::{GaugeVec, IntCounter, IntCounterVec, IntGaugeVec};
+
+lazy_static! {
+    /// Blocks processed, by outcome: `accepted`, `orphan`, `unfit` or `rejected`.
+    pub static ref BLOCKS_PROCESSED: IntCounterVec = register_int_counter_vec!(
+        "chain_blocks_processed_total",
+        "Blocks processed by the chain, by outcome",
+        &["outcome"]
+    )
+    .expect("metric is registered once; qed");
+    /// Blocks accounted for in fork choice.
+    pub static ref FORK_CHOICE_RUNS: IntCounter = register_int_counter!(
+        "chain_fork_choice_runs_total",
+        "Blocks accounted for in fork choice"
+    )
+    .expect("metric is registered once; qed");
+    /// Life events of the monitored validators, by account and event: `produced`, `missed`,
+    /// `approval_included` or `approval_missed`.
+    pub static ref VALIDATOR_EVENTS: IntCounterVec = register_int_counter_vec!(
+        "chain_validator_events_total",
+        "Life events of the monitored validators, by account and event",
+        &["account_id", "event"]
+    )
+    .expect("metric is registered once; qed");
+    /// Whether a monitored validator is a block proposer, by account.
+    pub static ref VALIDATOR_ACTIVE: IntGaugeVec = register_int_gauge_vec!(
+        "chain_validator_active",
+        "Whether the monitored validator is a block proposer",
+        &["account_id"]
+    )
+    .expect("metric is registered once; qed");
+    /// Latest stake proposal of a monitored validator, by account.
+    pub static ref VALIDATOR_STAKE: GaugeVec = register_gauge_vec!(
+        "chain_validator_stake",
+        "Amount of the latest stake proposal of the monitored validator",
+        &["account_id"]
+    )
+    .expect("metric is registered once; qed");
+}
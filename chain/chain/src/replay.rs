@@ -0,0 +1,135 @@
+//! Replay of an imported block's state transition, for debugging state root mismatches.
+//!
+//! The block is re-executed on the state it was applied to, one stage at a time: every
+//! transaction is validated, then the receipts of the previous block are applied, then the
+//! transactions one more at a time. Each stage is logged by the `replay` subsystem and recorded
+//! in a report, which can be written as CBOR and attached to a bug report.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use slog::Logger;
+
+use near_primitives::hash::CryptoHash;
+use near_primitives::transaction::ReceiptTransaction;
+use near_primitives::types::{BlockIndex, MerkleHash};
+
+use crate::error::{Error, ErrorKind};
+use crate::types::{Block, RuntimeAdapter};
+
+/// How to replay a block.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayOptions {
+    /// State root the replay should end with, the one stored for the block if `None`.
+    pub expected_state_root: Option<MerkleHash>,
+    /// Directory to write the report to if the state root does not match, none written if `None`.
+    pub report_dir: Option<PathBuf>,
+}
+
+/// One stage of a replayed block.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ReplayStage {
+    /// Transaction `hash` validated against the state before the block, `error` if rejected.
+    ValidateTransaction { hash: CryptoHash, error: Option<String> },
+    /// The receipts of the previous block applied, without any transaction.
+    ApplyReceipts { receipts: usize, state_root: MerkleHash },
+    /// The receipts and the transactions up to and including `hash` applied.
+    ApplyTransaction { hash: CryptoHash, state_root: MerkleHash },
+}
+
+/// Outcome of a replayed block.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplayReport {
+    pub block_hash: CryptoHash,
+    pub height: BlockIndex,
+    /// State the block was applied to.
+    pub prev_state_root: MerkleHash,
+    pub stages: Vec<ReplayStage>,
+    /// State root the replay ended with.
+    pub state_root: MerkleHash,
+    /// State root the replay should have ended with, if known.
+    pub expected_state_root: Option<MerkleHash>,
+}
+
+impl ReplayReport {
+    /// Whether the replay ended with another state root than expected.
+    pub fn is_mismatch(&self) -> bool {
+        self.expected_state_root.map_or(false, |expected| expected != self.state_root)
+    }
+
+    /// Writes the report as CBOR into `dir`, returning the path of the file.
+    pub fn write_to_dir(&self, dir: &Path) -> Result<PathBuf, Error> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("replay-{}-{}.cbor", self.height, self.block_hash));
+        let bytes = serde_cbor::to_vec(self).map_err(|err| ErrorKind::Other(err.to_string()))?;
+        fs::write(&path, bytes)?;
+        Ok(path)
+    }
+}
+
+/// Replays `block` on `state_root`, the state after its previous block with `receipts`. Nothing
+/// is written to the store.
+pub(crate) fn replay_block(
+    runtime_adapter: &dyn RuntimeAdapter,
+    block: &Block,
+    state_root: &MerkleHash,
+    receipts: &Vec<ReceiptTransaction>,
+    expected_state_root: Option<MerkleHash>,
+    log: &Logger,
+) -> Result<ReplayReport, Error> {
+    let block_hash = block.hash();
+    let height = block.header.height;
+    info!(log, "Replay block"; "block_hash" => %block_hash, "height" => height, "prev_state_root" => %state_root);
+
+    let mut stages = vec![];
+    for transaction in block.transactions.iter() {
+        let hash = transaction.get_hash();
+        let error = runtime_adapter.validate_tx(0, *state_root, transaction.clone()).err();
+        debug!(log, "Validated transaction"; "hash" => %hash, "error" => ?error);
+        stages.push(ReplayStage::ValidateTransaction { hash, error });
+    }
+
+    let apply = |count: usize| {
+        runtime_adapter
+            .replay_transactions(
+                0,
+                state_root,
+                height,
+                &block.header.prev_hash,
+                &vec![receipts.clone()], // TODO: currently only taking into account one shard.
+                &block.transactions[..count].to_vec(),
+            )
+            .map(|(_, state_root)| state_root)
+            .map_err(|err| Error::from(ErrorKind::Other(err.to_string())))
+    };
+    let mut next_state_root = apply(0)?;
+    debug!(log, "Applied receipts"; "receipts" => receipts.len(), "state_root" => %next_state_root);
+    stages
+        .push(ReplayStage::ApplyReceipts { receipts: receipts.len(), state_root: next_state_root });
+    for (i, transaction) in block.transactions.iter().enumerate() {
+        let hash = transaction.get_hash();
+        next_state_root = apply(i + 1)?;
+        debug!(log, "Applied transaction"; "hash" => %hash, "state_root" => %next_state_root);
+        stages.push(ReplayStage::ApplyTransaction { hash, state_root: next_state_root });
+    }
+
+    let report = ReplayReport {
+        block_hash,
+        height,
+        prev_state_root: *state_root,
+        stages,
+        state_root: next_state_root,
+        expected_state_root,
+    };
+    if report.is_mismatch() {
+        warn!(
+            log, "Replayed state root mismatch";
+            "block_hash" => %block_hash,
+            "state_root" => %report.state_root,
+            "expected_state_root" => ?report.expected_state_root,
+        );
+    } else {
+        info!(log, "Replayed block"; "block_hash" => %block_hash, "state_root" => %report.state_root);
+    }
+    Ok(report)
+}
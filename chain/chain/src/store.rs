@@ -1,30 +1,58 @@
 use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::io;
 use std::sync::Arc;
 
-use cached::SizedCache;
-use log::debug;
+use cached::{Cached, SizedCache};
 
 use near_primitives::hash::CryptoHash;
+use near_primitives::serialize::Decode;
 use near_primitives::transaction::{ReceiptTransaction, TransactionResult};
 use near_primitives::types::{BlockIndex, MerkleHash};
 use near_primitives::utils::index_to_bytes;
 use near_store::{
-    read_with_cache, Store, StoreUpdate, WrappedTrieChanges, COL_BLOCK, COL_BLOCK_HEADER,
-    COL_BLOCK_INDEX, COL_BLOCK_MISC, COL_RECEIPTS, COL_STATE_REF, COL_TRANSACTION_RESULT,
+    read_with_cache, Store, StoreUpdate, Trie, TrieChanges, WrappedTrieChanges, COL_BLOCK,
+    COL_BLOCK_HEADER, COL_BLOCK_INDEX, COL_BLOCK_MISC, COL_FORK_CHOICE, COL_RECEIPTS,
+    COL_STATE_REF, COL_TRANSACTION_RESULT, COL_TRIE_CHANGES,
 };
 
 use crate::error::{Error, ErrorKind};
+use crate::fork_choice::{weight_key, ForkChoice};
+use crate::logging;
 use crate::types::{Block, BlockHeader, Tip};
 
 const HEAD_KEY: &[u8; 4] = b"HEAD";
 const TAIL_KEY: &[u8; 4] = b"TAIL";
 const SYNC_HEAD_KEY: &[u8; 9] = b"SYNC_HEAD";
 const HEADER_HEAD_KEY: &[u8; 11] = b"HEADER_HEAD";
+const PRUNED_KEY: &[u8; 6] = b"PRUNED";
+const PINNED_KEY: &[u8; 6] = b"PINNED";
+const BATCH_INSERTIONS_PREFIX: &[u8; 16] = b"BATCH_INSERTIONS";
+
+/// Key of the trie changes committed `i`-th by `commit_insertions`, kept until their update is
+/// committed or reverted.
+fn batch_insertions_key(i: usize) -> Vec<u8> {
+    let mut key = BATCH_INSERTIONS_PREFIX.to_vec();
+    key.extend_from_slice(&(i as u64).to_be_bytes());
+    key
+}
 
 /// lru cache size
 const CACHE_SIZE: usize = 20;
 
+/// Key of the trie changes of block `hash`, ordered by `height`.
+fn trie_changes_key(height: BlockIndex, hash: &CryptoHash) -> Vec<u8> {
+    let mut key = height.to_be_bytes().to_vec();
+    key.extend_from_slice(hash.as_ref());
+    key
+}
+
+/// Whether the state of a block at `prev_height` followed by one at `height` is the state at a
+/// multiple of `interval`, and so kept as a snapshot.
+fn is_snapshot(prev_height: BlockIndex, height: BlockIndex, interval: BlockIndex) -> bool {
+    (height - 1) / interval * interval >= prev_height
+}
+
 /// Accesses the chain store. Used to create atomic editable views that can be reverted.
 pub trait ChainStoreAccess {
     /// Returns underlaying store.
@@ -98,6 +126,151 @@ impl ChainStore {
     pub fn store_update(&mut self) -> ChainStoreUpdate<Self> {
         ChainStoreUpdate::new(self)
     }
+
+    /// Discards the states of blocks below `height` and deletes the blocks of forks below it,
+    /// returning the deleted blocks. `height` must not be above the finalized block.
+    /// With a `snapshot_interval`, the states at its multiples are kept for good, so the others
+    /// can be replayed from them.
+    ///
+    /// Trie changes of every block are applied in a batch of their own, since reference counts
+    /// are read from the store. The last applied one is recorded so none is applied twice after
+    /// a crash, and all of them are deleted at the end. Snapshots are pinned the same way.
+    ///
+    /// The results of the transactions and receipts of deleted forks go with them, unless the
+    /// chain includes the same ones.
+    pub fn prune(
+        &mut self,
+        height: BlockIndex,
+        trie: Arc<Trie>,
+        snapshot_interval: Option<BlockIndex>,
+    ) -> Result<Vec<CryptoHash>, Error> {
+        let store = self.store.clone();
+        let end = height.to_be_bytes();
+        let pruned: Option<Vec<u8>> = store.get_ser(COL_BLOCK_MISC, PRUNED_KEY)?;
+        let pinned: Option<Vec<u8>> = store.get_ser(COL_BLOCK_MISC, PINNED_KEY)?;
+        let mut deleted_blocks = vec![];
+        let mut canonical_results = None;
+
+        for (key, value) in store
+            .iter(COL_TRIE_CHANGES)
+            .skip_while(|(key, _)| pruned.as_ref().map_or(false, |pruned| key[..] <= pruned[..]))
+            .take_while(|(key, _)| key[..] < end[..])
+        {
+            let mut block_height = [0u8; 8];
+            block_height.copy_from_slice(&key[..8]);
+            let block_height = BlockIndex::from_be_bytes(block_height);
+            let hash =
+                CryptoHash::try_from(&key[8..]).map_err(|err| ErrorKind::Other(err.to_string()))?;
+            let trie_changes: TrieChanges = Decode::decode(&value)?;
+            let trie_changes = WrappedTrieChanges::new(trie.clone(), trie_changes);
+
+            let mut store_update = store.store_update();
+            if self.get_block_hash_by_height(block_height).ok() == Some(hash) {
+                let prev_hash = self.get_block_header(&hash)?.prev_hash;
+                let prev_height = self.get_block_header(&prev_hash)?.height;
+                let snapshot = snapshot_interval
+                    .map_or(false, |interval| is_snapshot(prev_height, block_height, interval));
+                if snapshot && pinned.as_ref().map_or(true, |pinned| pinned[..] != key[..]) {
+                    let state_root = *self.get_post_state_root(&prev_hash)?;
+                    let pin = trie
+                        .pin_changes(&state_root)
+                        .map_err(|err| ErrorKind::Other(err.to_string()))?;
+                    let mut pin_update = store.store_update();
+                    pin.insertions_into(trie.clone(), &mut pin_update)
+                        .map_err(|err| ErrorKind::Other(err.to_string()))?;
+                    pin_update.set_ser(COL_BLOCK_MISC, PINNED_KEY, &key.to_vec())?;
+                    pin_update.commit()?;
+                }
+
+                // The block replaced the state of its parent.
+                trie_changes
+                    .deletions_into(&mut store_update)
+                    .map_err(|err| ErrorKind::Other(err.to_string()))?;
+                if !snapshot {
+                    store_update.delete(COL_STATE_REF, prev_hash.as_ref());
+                    self.post_state_roots.cache_remove(&prev_hash.as_ref().to_vec());
+                }
+            } else {
+                // Forks below the finalized block never become the chain.
+                trie_changes
+                    .revert_insertions_into(&mut store_update)
+                    .map_err(|err| ErrorKind::Other(err.to_string()))?;
+                if canonical_results.is_none() {
+                    canonical_results = Some(self.canonical_results(block_height)?);
+                }
+                let canonical_results = canonical_results.as_ref().expect("set above; qed");
+                for result in self.results(&hash)? {
+                    if !canonical_results.contains(&result) {
+                        store_update.delete(COL_TRANSACTION_RESULT, result.as_ref());
+                        self.transaction_results.cache_remove(&result.as_ref().to_vec());
+                    }
+                }
+                for column in [COL_BLOCK, COL_BLOCK_HEADER, COL_STATE_REF, COL_RECEIPTS].iter() {
+                    store_update.delete(*column, hash.as_ref());
+                }
+                store_update.delete(COL_FORK_CHOICE, &weight_key(&hash));
+                let cache_key = hash.as_ref().to_vec();
+                self.blocks.cache_remove(&cache_key);
+                self.headers.cache_remove(&cache_key);
+                self.post_state_roots.cache_remove(&cache_key);
+                self.receipts.cache_remove(&cache_key);
+                deleted_blocks.push(hash);
+            }
+            store_update.set_ser(COL_BLOCK_MISC, PRUNED_KEY, &key.to_vec())?;
+            store_update.commit()?;
+        }
+
+        store.delete_column_range(COL_TRIE_CHANGES, &[], &end)?;
+        Ok(deleted_blocks)
+    }
+
+    /// Reverts the state insertions `ChainStoreUpdate::commit_insertions` committed for an update
+    /// that was never committed itself, as the node stopped in between. Returns how many blocks'
+    /// insertions were reverted.
+    pub fn revert_batch_insertions(&mut self, trie: Arc<Trie>) -> Result<usize, Error> {
+        let store = self.store.clone();
+        let mut batch: Vec<(Vec<u8>, TrieChanges)> = vec![];
+        for (key, value) in store.iter(COL_BLOCK_MISC) {
+            if key.starts_with(BATCH_INSERTIONS_PREFIX) {
+                batch.push((key.to_vec(), Decode::decode(&value)?));
+            }
+        }
+        // The last committed first, each in a batch of its own like they were committed.
+        batch.sort_by(|(a, _), (b, _)| b.cmp(a));
+        let mut reverted = 0;
+        for (key, trie_changes) in batch.into_iter() {
+            let trie_changes = WrappedTrieChanges::new(trie.clone(), trie_changes);
+            let mut store_update = store.store_update();
+            trie_changes
+                .revert_insertions_into(&mut store_update)
+                .map_err(|err| ErrorKind::Other(err.to_string()))?;
+            store_update.delete(COL_BLOCK_MISC, &key);
+            store_update.commit()?;
+            reverted += 1;
+        }
+        Ok(reverted)
+    }
+
+    /// Hashes of the transaction results saved for block `hash`'s transactions, and by its
+    /// children for its receipts.
+    fn results(&mut self, hash: &CryptoHash) -> Result<Vec<CryptoHash>, Error> {
+        let mut results: Vec<_> =
+            self.get_block(hash)?.transactions.iter().map(|tx| tx.get_hash()).collect();
+        results.extend(self.get_receipts(hash)?.iter().map(|receipt| receipt.get_hash()));
+        Ok(results)
+    }
+
+    /// Hashes of the transaction results of the chain from `height` to the head, see `results`.
+    fn canonical_results(&mut self, height: BlockIndex) -> Result<HashSet<CryptoHash>, Error> {
+        let mut results = HashSet::new();
+        for height in height..=self.head()?.height {
+            // Heights without a block were skipped.
+            if let Ok(hash) = self.get_block_hash_by_height(height) {
+                results.extend(self.results(&hash)?);
+            }
+        }
+        Ok(results)
+    }
 }
 
 impl ChainStoreAccess for ChainStore {
@@ -218,7 +391,11 @@ pub struct ChainStoreUpdate<'a, T> {
     tail: Option<Tip>,
     header_head: Option<Tip>,
     sync_head: Option<Tip>,
-    trie_changes: Option<WrappedTrieChanges>,
+    trie_changes: Vec<(BlockIndex, CryptoHash, WrappedTrieChanges)>,
+    /// How many of `trie_changes` had their insertions committed by `commit_insertions`.
+    committed_insertions: usize,
+    /// Fork choice changes, written in the same batch as the blocks they account for.
+    fork_choice: ForkChoice,
 }
 
 impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
@@ -237,7 +414,9 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
             tail: None,
             header_head: None,
             sync_head: None,
-            trie_changes: None,
+            trie_changes: vec![],
+            committed_insertions: 0,
+            fork_choice: ForkChoice::default(),
         }
     }
 }
@@ -400,6 +579,26 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
         self.sync_head = Some(t.clone());
     }
 
+    /// Start the chain at checkpoint `header` instead of genesis. It becomes the head and the
+    /// tail, and the headers between it and `genesis` are missing until backfilled.
+    pub fn save_checkpoint(&mut self, genesis: BlockHeader, header: BlockHeader) {
+        let tip = Tip::from_header(&header);
+        self.head = Some(tip.clone());
+        self.header_head = Some(tip.clone());
+        self.sync_head = Some(tip);
+        self.block_index.insert(0, Some(genesis.hash()));
+        self.headers.insert(genesis.hash(), genesis);
+        self.save_tail_header(header);
+    }
+
+    /// Save `header`, the parent of the tail, as the new tail.
+    pub fn save_tail_header(&mut self, header: BlockHeader) {
+        let tip = Tip::from_header(&header);
+        self.block_index.insert(tip.height, Some(tip.last_block_hash));
+        self.tail = Some(tip);
+        self.headers.insert(header.hash(), header);
+    }
+
     /// Save block.
     pub fn save_block(&mut self, block: Block) {
         self.blocks.insert(block.hash(), block);
@@ -426,6 +625,22 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
         self.transaction_results.insert(*hash, result);
     }
 
+    /// Account for the block with `header` in fork choice, on top of the fork choice `base` of
+    /// the chain, see `ForkChoice::process_block`.
+    pub fn save_fork_choice_block(&mut self, header: &BlockHeader, base: &ForkChoice) {
+        self.fork_choice.process_block_over(header, base);
+    }
+
+    /// Save justified block marker.
+    pub fn save_justified(&mut self, hash: CryptoHash) {
+        self.fork_choice.set_justified(hash);
+    }
+
+    /// Save finalized block marker.
+    pub fn save_finalized(&mut self, hash: CryptoHash) {
+        self.fork_choice.set_finalized(hash);
+    }
+
     /// Starts a sub-ChainUpdate with atomic commit/rollback of all operations done
     /// within this scope.
     /// If the closure returns and error, all changes are canceled.
@@ -446,14 +661,61 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
             // Rolling back changes.
             Ok(false) => Ok(false),
             Err(err) => {
-                debug!(target: "chain", "Error returned, discarding extension");
+                debug!(logging::logger(logging::CHAIN), "Error returned, discarding extension"; "error" => %err);
                 Err(err)
             }
         }
     }
 
-    pub fn save_trie_changes(&mut self, trie_changes: WrappedTrieChanges) {
-        self.trie_changes = Some(trie_changes);
+    /// Save the trie changes of block `hash` at `height`. Insertions are applied right away,
+    /// the changes are kept to discard the states of the block or its parent once pruned.
+    pub fn save_trie_changes(
+        &mut self,
+        height: BlockIndex,
+        hash: &CryptoHash,
+        trie_changes: WrappedTrieChanges,
+    ) {
+        self.trie_changes.push((height, *hash, trie_changes));
+    }
+
+    /// Commits the state insertions saved so far right away, so that the blocks processed next
+    /// in this update can be applied on top of their states. If the update is not committed in
+    /// the end, `revert_insertions` discards them again, or `ChainStore::revert_batch_insertions`
+    /// once restarted if the node stopped before.
+    pub fn commit_insertions(&mut self) -> Result<(), Error> {
+        for (i, (_, _, trie_changes)) in
+            self.trie_changes.iter().enumerate().skip(self.committed_insertions)
+        {
+            // One batch each, as the reference counts are read from the store.
+            let mut store_update = self.chain_store.store().store_update();
+            trie_changes
+                .insertions_into(&mut store_update)
+                .map_err(|err| ErrorKind::Other(err.to_string()))?;
+            store_update.set_ser(
+                COL_BLOCK_MISC,
+                &batch_insertions_key(i),
+                trie_changes.trie_changes(),
+            )?;
+            store_update.commit()?;
+        }
+        self.committed_insertions = self.trie_changes.len();
+        Ok(())
+    }
+
+    /// Discards the state insertions committed by `commit_insertions`.
+    pub fn revert_insertions(&mut self) -> Result<(), Error> {
+        for (i, (_, _, trie_changes)) in
+            self.trie_changes[..self.committed_insertions].iter().enumerate().rev()
+        {
+            let mut store_update = self.chain_store.store().store_update();
+            trie_changes
+                .revert_insertions_into(&mut store_update)
+                .map_err(|err| ErrorKind::Other(err.to_string()))?;
+            store_update.delete(COL_BLOCK_MISC, &batch_insertions_key(i));
+            store_update.commit()?;
+        }
+        self.committed_insertions = 0;
+        Ok(())
     }
 
     /// Merge another StoreUpdate into this one
@@ -512,11 +774,20 @@ impl<'a, T: ChainStoreAccess> ChainStoreUpdate<'a, T> {
         for (hash, tx_result) in self.transaction_results.drain() {
             store_update.set_ser(COL_TRANSACTION_RESULT, hash.as_ref(), &tx_result)?;
         }
-        if let Some(trie_changes) = self.trie_changes {
-            trie_changes
-                .insertions_into(&mut store_update)
-                .map_err(|err| ErrorKind::Other(err.to_string()))?;
-            // TODO: save deletions separately for garbage collection.
+        self.fork_choice.write_to(&mut store_update)?;
+        for (i, (height, hash, trie_changes)) in self.trie_changes.into_iter().enumerate() {
+            if i >= self.committed_insertions {
+                trie_changes
+                    .insertions_into(&mut store_update)
+                    .map_err(|err| ErrorKind::Other(err.to_string()))?;
+            } else {
+                store_update.delete(COL_BLOCK_MISC, &batch_insertions_key(i));
+            }
+            store_update.set_ser(
+                COL_TRIE_CHANGES,
+                &trie_changes_key(height, &hash),
+                trie_changes.trie_changes(),
+            )?;
         }
         for other in self.store_updates {
             store_update.merge(other);
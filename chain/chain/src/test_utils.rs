@@ -70,6 +70,10 @@ impl RuntimeAdapter for KeyValueRuntime {
         (self.store.store_update(), MerkleHash::default())
     }
 
+    fn get_trie(&self) -> Arc<Trie> {
+        self.trie.clone()
+    }
+
     fn compute_block_weight(
         &self,
         prev_header: &BlockHeader,
@@ -155,6 +159,21 @@ impl RuntimeAdapter for KeyValueRuntime {
         ))
     }
 
+    fn replay_transactions(
+        &self,
+        _shard_id: ShardId,
+        state_root: &MerkleHash,
+        _block_index: BlockIndex,
+        _prev_block_hash: &CryptoHash,
+        _receipts: &Vec<Vec<ReceiptTransaction>>,
+        _transactions: &Vec<SignedTransaction>,
+    ) -> Result<(WrappedTrieChanges, MerkleHash), Box<dyn std::error::Error>> {
+        Ok((
+            WrappedTrieChanges::new(self.trie.clone(), TrieChanges::empty(state_root.clone())),
+            *state_root,
+        ))
+    }
+
     fn query(
         &self,
         _state_root: MerkleHash,
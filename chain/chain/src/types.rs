@@ -16,7 +16,7 @@ use near_primitives::transaction::{ReceiptTransaction, SignedTransaction, Transa
 use near_primitives::types::{AccountId, BlockIndex, MerkleHash, ShardId, ValidatorStake};
 use near_primitives::utils::proto_to_type;
 use near_protos::chain as chain_proto;
-use near_store::{StoreUpdate, WrappedTrieChanges};
+use near_store::{StoreUpdate, Trie, WrappedTrieChanges};
 
 use crate::error::Error;
 
@@ -299,12 +299,29 @@ pub enum BlockStatus {
     /// Block does not update the chain head and is a fork.
     Fork,
     /// Block updates the chain head via a (potentially disruptive) "reorg".
-    /// Previous block was not our previous chain head.
-    Reorg,
+    /// Previous block was not our previous chain head, and this many blocks of the previous
+    /// chain are no longer on the current one.
+    Reorg(BlockIndex),
+}
+
+/// Chain activity, sent to the receivers of `Chain::subscribe`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    /// The chain head moved to a new block.
+    HeadUpdated(Tip),
+    /// The chain head moved to another fork, leaving the last `depth` blocks of the old one.
+    /// Followed by the `HeadUpdated` of the new head.
+    Reorg { old_head: CryptoHash, new_head: CryptoHash, depth: BlockIndex },
+    /// Block `hash` was marked as finalized.
+    Finalized(CryptoHash),
+    /// Block `hash` was rejected as invalid.
+    InvalidBlock { hash: CryptoHash, error: String },
+    /// Block `hash` is missing for orphans to be processed, the first unknown ancestor.
+    NeedBlock(CryptoHash),
 }
 
 /// Options for block origin.
-#[derive(Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub enum Provenance {
     /// No provenance.
     NONE,
@@ -330,6 +347,9 @@ pub trait RuntimeAdapter: Send + Sync {
     /// StoreUpdate can be discarded if the chain past the genesis.
     fn genesis_state(&self, shard_id: ShardId) -> (StoreUpdate, MerkleHash);
 
+    /// Trie holding the state, used to discard the states of old blocks.
+    fn get_trie(&self) -> Arc<Trie>;
+
     /// Verify block producer validity and return weight of given block for fork choice rule.
     fn compute_block_weight(
         &self,
@@ -389,6 +409,18 @@ pub trait RuntimeAdapter: Send + Sync {
         Box<dyn std::error::Error>,
     >;
 
+    /// Apply transactions of a block that was applied before, to reconstruct its pruned state.
+    /// Unlike `apply_transactions` it leaves the validators as they are.
+    fn replay_transactions(
+        &self,
+        shard_id: ShardId,
+        merkle_hash: &MerkleHash,
+        block_index: BlockIndex,
+        prev_block_hash: &CryptoHash,
+        receipts: &Vec<Vec<ReceiptTransaction>>,
+        transactions: &Vec<SignedTransaction>,
+    ) -> Result<(WrappedTrieChanges, MerkleHash), Box<dyn std::error::Error>>;
+
     /// Query runtime with given `path` and `data`.
     fn query(
         &self,
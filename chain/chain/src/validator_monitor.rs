@@ -0,0 +1,213 @@
+use std::collections::{HashMap, HashSet};
+
+use slog::{Discard, Logger};
+
+use near_primitives::types::{AccountId, Balance, BlockIndex};
+
+use crate::logging;
+use crate::metrics;
+use crate::types::{BlockHeader, RuntimeAdapter};
+
+/// Skipped heights checked for missed blocks before a block. Heights skipped before those are
+/// not accounted for, so that a long gap does not stall the import.
+pub const MAX_SKIPPED_HEIGHTS: BlockIndex = 1024;
+
+/// Life events of a monitored validator, accumulated since the node started.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidatorSummary {
+    /// Blocks produced by the validator.
+    pub blocks_produced: u64,
+    /// Heights the validator was the block proposer for, but that were skipped.
+    pub blocks_missed: u64,
+    /// Blocks that include the validator's approval of their parent.
+    pub approvals_included: u64,
+    /// Blocks that do not include the validator's approval of their parent.
+    pub approvals_missed: u64,
+    /// Amount of the latest stake proposal of the validator.
+    pub stake: Option<Balance>,
+    /// Whether the validator is currently a block proposer.
+    pub is_active: bool,
+}
+
+/// Watches a set of validators and logs their life events as blocks join the canonical chain.
+/// The events are also counted in the `chain_validator_*` metrics.
+///
+/// Approvals of a block are carried by its child, in the order of the epoch block proposers at
+/// the parent height. The proposer of a block does not approve its parent, so it is counted
+/// neither as a hit nor as a miss. Blocks of a chain abandoned by a re-org stay counted.
+#[derive(Debug)]
+pub struct ValidatorMonitor {
+    validators: HashMap<AccountId, ValidatorSummary>,
+    log: Logger,
+    /// Whether the events are counted in the metrics.
+    metrics: bool,
+}
+
+impl Default for ValidatorMonitor {
+    fn default() -> Self {
+        ValidatorMonitor::new(vec![])
+    }
+}
+
+impl ValidatorMonitor {
+    pub fn new(accounts: Vec<AccountId>) -> Self {
+        Self::with_logger(accounts, logging::logger(logging::VALIDATOR_MONITOR), true)
+    }
+
+    /// Monitor that neither logs nor counts events in the metrics, to summarize past blocks.
+    pub(crate) fn quiet(accounts: Vec<AccountId>) -> Self {
+        Self::with_logger(accounts, Logger::root(Discard, o!()), false)
+    }
+
+    fn with_logger(accounts: Vec<AccountId>, log: Logger, metrics: bool) -> Self {
+        ValidatorMonitor {
+            validators: accounts
+                .into_iter()
+                .map(|account_id| (account_id, ValidatorSummary::default()))
+                .collect(),
+            log,
+            metrics,
+        }
+    }
+
+    /// Whether no validator is monitored.
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    /// Summary of `account_id`, if monitored.
+    pub fn summary(&self, account_id: &AccountId) -> Option<&ValidatorSummary> {
+        self.validators.get(account_id)
+    }
+
+    /// Summaries of all monitored validators.
+    pub fn summaries(&self) -> impl Iterator<Item = (&AccountId, &ValidatorSummary)> {
+        self.validators.iter()
+    }
+
+    /// Accounts for a block that joined the canonical chain, whose parent is at `prev_height`.
+    ///
+    /// Only the last `MAX_SKIPPED_HEIGHTS` heights skipped before the block are checked for
+    /// missed blocks.
+    pub fn process_block(
+        &mut self,
+        header: &BlockHeader,
+        prev_height: BlockIndex,
+        runtime_adapter: &dyn RuntimeAdapter,
+    ) {
+        if self.is_empty() {
+            return;
+        }
+
+        let first_skipped = header.height.saturating_sub(MAX_SKIPPED_HEIGHTS).max(prev_height + 1);
+        if first_skipped > prev_height + 1 {
+            warn!(
+                self.log, "Too many skipped heights to check for missed blocks";
+                "from" => prev_height + 1,
+                "to" => first_skipped - 1,
+            );
+        }
+        for height in first_skipped..header.height {
+            if let Some((account_id, summary)) = self.proposer_summary(height, runtime_adapter) {
+                summary.blocks_missed += 1;
+                warn!(self.log, "Missed block"; "account_id" => %account_id, "height" => height);
+                self.record(&account_id, "missed");
+            }
+        }
+
+        let proposer = match self.proposer_summary(header.height, runtime_adapter) {
+            Some((account_id, summary)) => {
+                summary.blocks_produced += 1;
+                info!(
+                    self.log, "Produced block";
+                    "account_id" => %account_id,
+                    "block_hash" => %header.hash(),
+                    "height" => header.height,
+                );
+                self.record(&account_id, "produced");
+                Some(account_id)
+            }
+            None => runtime_adapter.get_block_proposer(header.height).ok(),
+        };
+
+        if let Ok(proposers) = runtime_adapter.get_epoch_block_proposers(prev_height) {
+            let active: HashSet<_> = proposers.iter().map(|(account_id, _)| account_id).collect();
+            for (account_id, summary) in self.validators.iter_mut() {
+                let is_active = active.contains(account_id);
+                if self.metrics {
+                    metrics::VALIDATOR_ACTIVE
+                        .with_label_values(&[account_id.as_str()])
+                        .set(is_active as i64);
+                }
+                if is_active != summary.is_active {
+                    summary.is_active = is_active;
+                    info!(
+                        self.log, "Block proposers changed";
+                        "account_id" => %account_id,
+                        "joined" => is_active,
+                        "height" => prev_height,
+                    );
+                }
+            }
+
+            for (index, (account_id, _)) in proposers.iter().enumerate() {
+                if Some(account_id) == proposer.as_ref() {
+                    continue;
+                }
+                let summary = match self.validators.get_mut(account_id) {
+                    Some(summary) => summary,
+                    None => continue,
+                };
+                if header.approval_mask.get(index).cloned().unwrap_or(false) {
+                    summary.approvals_included += 1;
+                    self.record(account_id, "approval_included");
+                } else {
+                    summary.approvals_missed += 1;
+                    warn!(
+                        self.log, "Approval is missing from block";
+                        "account_id" => %account_id,
+                        "approved_hash" => %header.prev_hash,
+                        "height" => header.height,
+                    );
+                    self.record(account_id, "approval_missed");
+                }
+            }
+        }
+
+        for stake in header.validator_proposal.iter() {
+            if let Some(summary) = self.validators.get_mut(&stake.account_id) {
+                info!(
+                    self.log, "Stake proposal changed";
+                    "account_id" => %stake.account_id,
+                    "from" => ?summary.stake,
+                    "to" => stake.amount,
+                    "height" => header.height,
+                );
+                summary.stake = Some(stake.amount);
+                if self.metrics {
+                    metrics::VALIDATOR_STAKE
+                        .with_label_values(&[stake.account_id.as_str()])
+                        .set(stake.amount as f64);
+                }
+            }
+        }
+    }
+
+    /// Counts `event` of `account_id` in the metrics, unless the monitor is quiet.
+    fn record(&self, account_id: &AccountId, event: &str) {
+        if self.metrics {
+            metrics::VALIDATOR_EVENTS.with_label_values(&[account_id.as_str(), event]).inc();
+        }
+    }
+
+    /// Block proposer at `height` together with its summary, if it is monitored.
+    fn proposer_summary(
+        &mut self,
+        height: BlockIndex,
+        runtime_adapter: &dyn RuntimeAdapter,
+    ) -> Option<(AccountId, &mut ValidatorSummary)> {
+        let account_id = runtime_adapter.get_block_proposer(height).ok()?;
+        let summary = self.validators.get_mut(&account_id)?;
+        Some((account_id, summary))
+    }
+}
@@ -1,9 +1,23 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::Arc;
 
-use near_chain::test_utils::setup;
-use near_chain::{Block, ErrorKind, Provenance};
+use chrono::Utc;
+
+use near_chain::test_utils::{setup, KeyValueRuntime};
+use near_chain::{
+    analytics, Block, BlockStatus, Chain, ChainEvent, ChainStore, ChainStoreAccess, ErrorKind,
+    ForkChoice, Provenance, ReplayOptions, ReplayStage, RuntimeAdapter, Tip,
+};
+use near_primitives::crypto::signer::EDSigner;
+use near_primitives::hash::hash;
 use near_primitives::test_utils::init_test_logger;
+use near_primitives::transaction::TransactionBody;
 use near_primitives::types::MerkleHash;
+use near_store::test_utils::create_test_store;
+use near_store::{Trie, WrappedTrieChanges};
 
 #[test]
 fn empty_chain() {
@@ -29,11 +43,13 @@ fn build_chain() {
 fn build_chain_with_orhpans() {
     init_test_logger();
     let (mut chain, _, signer) = setup();
+    let events = chain.subscribe();
     let mut blocks = vec![chain.get_block(&chain.genesis().hash()).unwrap().clone()];
     for i in 1..4 {
         let block = Block::empty(&blocks[i - 1].header, signer.clone());
         blocks.push(block);
     }
+    let (b1_hash, b2_hash) = (blocks[1].hash(), blocks[2].hash());
     assert_eq!(
         chain
             .process_block(blocks.pop().unwrap(), Provenance::PRODUCED, |_, _, _| {})
@@ -48,6 +64,10 @@ fn build_chain_with_orhpans() {
             .kind(),
         ErrorKind::Orphan
     );
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![ChainEvent::NeedBlock(b2_hash), ChainEvent::NeedBlock(b1_hash)]
+    );
     let res = chain.process_block(blocks.pop().unwrap(), Provenance::PRODUCED, |_, _, _| {});
     assert_eq!(res.unwrap().unwrap().height, 3);
     assert_eq!(
@@ -59,6 +79,131 @@ fn build_chain_with_orhpans() {
     );
 }
 
+#[test]
+fn process_orphans_after_skipped_heights() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let b1 = Block::empty(chain.genesis(), signer.clone());
+    let b3 = Block::produce(
+        &b1.header,
+        3,
+        b1.header.prev_state_root,
+        vec![],
+        HashMap::default(),
+        vec![],
+        signer.clone(),
+    );
+    let b4 = Block::empty(&b3.header, signer);
+    let (b1_hash, b4_hash) = (b1.hash(), b4.hash());
+    for block in vec![b4, b3] {
+        let err = chain.process_block(block, Provenance::NONE, |_, _, _| {}).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Orphan);
+    }
+    assert_eq!(chain.missing_ancestor(&b4_hash), b1_hash);
+
+    let tip = chain.process_block(b1, Provenance::NONE, |_, _, _| {}).unwrap();
+    assert_eq!(tip.unwrap().last_block_hash, b4_hash);
+    assert_eq!(chain.orphans_len(), 0);
+}
+
+#[test]
+fn process_block_batch() {
+    init_test_logger();
+    let (mut source, _, signer) = setup();
+    let mut blocks = vec![];
+    for _ in 0..4 {
+        let prev = source.head_header().unwrap();
+        let block = Block::empty(&prev, signer.clone());
+        blocks.push(block.clone());
+        source.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+    let head_hash = blocks[3].hash();
+
+    let store = create_test_store();
+    let runtime = Arc::new(KeyValueRuntime::new(store.clone()));
+    let mut chain = Chain::new(store, runtime, source.genesis().timestamp).unwrap();
+    chain.process_block(blocks[0].clone(), Provenance::NONE, |_, _, _| {}).unwrap();
+    let invalid = Block::produce(
+        &blocks[1].header,
+        3,
+        hash(b"invalid"),
+        vec![],
+        HashMap::default(),
+        vec![],
+        signer,
+    );
+    let err = chain
+        .process_block_batch(vec![invalid, blocks[1].clone()], Provenance::SYNC, |_, _, _| {})
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidStateRoot);
+    assert_eq!(chain.head().unwrap().height, 1);
+    assert!(!chain.block_exists(&blocks[1].hash()).unwrap());
+
+    let batch = vec![blocks[3].clone(), blocks[1].clone(), blocks[2].clone(), blocks[0].clone()];
+    let tip = chain.process_block_batch(batch, Provenance::SYNC, |_, status, _| {
+        assert_eq!(status, BlockStatus::Next);
+    });
+    assert_eq!(tip.unwrap().unwrap().last_block_hash, head_hash);
+    assert_eq!(chain.head().unwrap().last_block_hash, head_hash);
+    assert_eq!(chain.get_header_by_height(2).unwrap().hash(), blocks[1].hash());
+}
+
+#[test]
+fn process_block_batch_with_orphans() {
+    init_test_logger();
+    let (mut source, _, signer) = setup();
+    let mut blocks = vec![];
+    for _ in 0..3 {
+        let prev = source.head_header().unwrap();
+        let block = Block::empty(&prev, signer.clone());
+        blocks.push(block.clone());
+        source.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+
+    let store = create_test_store();
+    let runtime = Arc::new(KeyValueRuntime::new(store.clone()));
+    let mut chain = Chain::new(store, runtime, source.genesis().timestamp).unwrap();
+    let events = chain.subscribe();
+    let batch = vec![blocks[0].clone(), blocks[2].clone()];
+    let tip = chain.process_block_batch(batch, Provenance::SYNC, |_, _, _| {}).unwrap();
+    assert_eq!(tip.unwrap().last_block_hash, blocks[0].hash());
+    assert!(chain.is_orphan(&blocks[2].hash()));
+    assert!(events.try_iter().any(|event| event == ChainEvent::NeedBlock(blocks[1].hash())));
+
+    let tip = chain.process_block(blocks[1].clone(), Provenance::NONE, |_, _, _| {}).unwrap();
+    assert_eq!(tip.unwrap().last_block_hash, blocks[2].hash());
+    assert!(!chain.is_orphan(&blocks[2].hash()));
+}
+
+#[test]
+fn revert_batch_insertions_on_restart() {
+    init_test_logger();
+    let store = create_test_store();
+    let runtime = Arc::new(KeyValueRuntime::new(store.clone()));
+    let genesis_time = Utc::now();
+    Chain::new(store.clone(), runtime.clone(), genesis_time).unwrap();
+    let trie = runtime.get_trie();
+    let changes = trie
+        .update(&Trie::empty_root(), vec![(b"key".to_vec(), Some(b"value".to_vec()))].into_iter())
+        .unwrap();
+    let root = changes.new_root;
+
+    // The node stops after committing the insertions but before the update.
+    let mut chain_store = ChainStore::new(store.clone());
+    let mut store_update = chain_store.store_update();
+    store_update.save_trie_changes(
+        1,
+        &hash(b"block"),
+        WrappedTrieChanges::new(trie.clone(), changes),
+    );
+    store_update.commit_insertions().unwrap();
+    drop(store_update);
+    assert_eq!(trie.get(&root, b"key"), Some(b"value".to_vec()));
+
+    Chain::new(store, runtime, genesis_time).unwrap();
+    assert_eq!(trie.get(&root, b"key"), None);
+}
+
 #[test]
 fn build_chain_with_skips_and_forks() {
     init_test_logger();
@@ -92,3 +237,416 @@ fn build_chain_with_skips_and_forks() {
     assert!(chain.get_header_by_height(1).is_err());
     assert_eq!(chain.get_header_by_height(5).unwrap().height, 5);
 }
+
+#[test]
+fn reorg_above_finalized_only() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let b1 = Block::empty(chain.genesis(), signer.clone());
+    let b2 = Block::empty(&b1.header, signer.clone());
+    let b3 = Block::empty(&b2.header, signer.clone());
+    let b1_hash = b1.hash();
+    for block in vec![b1.clone(), b2, b3] {
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+    chain.set_finalized(b1_hash).unwrap();
+    let head = chain.head().unwrap();
+
+    // Heavier than the head, but not on top of the finalized block.
+    let mut prev = chain.genesis().clone();
+    for height in 4..8 {
+        let block = Block::produce(
+            &prev,
+            height,
+            MerkleHash::default(),
+            vec![],
+            HashMap::default(),
+            vec![],
+            signer.clone(),
+        );
+        prev = block.header.clone();
+        assert_eq!(chain.process_block(block, Provenance::NONE, |_, _, _| {}).unwrap(), None);
+    }
+    assert_eq!(chain.head().unwrap(), head);
+    assert_eq!(chain.header_head().unwrap(), head);
+
+    let mut prev = b1.header;
+    let statuses = RefCell::new(vec![]);
+    for height in 4..7 {
+        let block = Block::produce(
+            &prev,
+            height,
+            MerkleHash::default(),
+            vec![],
+            HashMap::default(),
+            vec![],
+            signer.clone(),
+        );
+        prev = block.header.clone();
+        chain
+            .process_block(block, Provenance::NONE, |_, status, _| {
+                statuses.borrow_mut().push(status)
+            })
+            .unwrap();
+    }
+    assert_eq!(
+        statuses.into_inner(),
+        vec![BlockStatus::Fork, BlockStatus::Fork, BlockStatus::Reorg(2)]
+    );
+    assert_eq!(chain.head().unwrap().last_block_hash, prev.hash());
+}
+
+#[test]
+fn subscribe_to_events() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let events = chain.subscribe();
+    let b1 = Block::empty(chain.genesis(), signer.clone());
+    let b2 = Block::produce(
+        chain.genesis(),
+        2,
+        MerkleHash::default(),
+        vec![],
+        HashMap::default(),
+        vec![],
+        signer.clone(),
+    );
+    let b3 = Block::empty(&b1.header, signer.clone());
+    let b4 = Block::empty(&b2.header, signer.clone());
+    let b5 = Block::empty(&b4.header, signer.clone());
+    let invalid =
+        Block::produce(&b5.header, 6, hash(b"invalid"), vec![], HashMap::default(), vec![], signer);
+    let (b1_tip, b3_tip, b5_tip) =
+        (Tip::from_header(&b1.header), Tip::from_header(&b3.header), Tip::from_header(&b5.header));
+    let invalid_hash = invalid.hash();
+    for block in vec![b1, b2, b3, b4, b5] {
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+    assert!(chain.process_block(invalid, Provenance::NONE, |_, _, _| {}).is_err());
+    chain.set_finalized(b5_tip.last_block_hash).unwrap();
+
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![
+            ChainEvent::HeadUpdated(b1_tip),
+            ChainEvent::HeadUpdated(b3_tip.clone()),
+            ChainEvent::Reorg {
+                old_head: b3_tip.last_block_hash,
+                new_head: b5_tip.last_block_hash,
+                depth: 2
+            },
+            ChainEvent::HeadUpdated(b5_tip.clone()),
+            ChainEvent::InvalidBlock {
+                hash: invalid_hash,
+                error: ErrorKind::InvalidStateRoot.to_string()
+            },
+            ChainEvent::Finalized(b5_tip.last_block_hash),
+        ]
+    );
+}
+
+#[test]
+fn restore_fork_choice() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    for _ in 0..3 {
+        let prev = chain.head_header().unwrap();
+        let block = Block::empty(&prev, signer.clone());
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+    let head = chain.head().unwrap();
+    chain.set_justified(head.prev_block_hash).unwrap();
+    assert_eq!(chain.fork_choice().weight(&head.last_block_hash), Some(head.total_weight));
+
+    let restored = ForkChoice::restore(chain.store().store()).unwrap();
+    assert_eq!(&restored, chain.fork_choice());
+    assert_eq!(restored.justified(), Some(&head.prev_block_hash));
+}
+
+#[test]
+fn fork_choice_keeps_heavier_votes() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let approve = |block: &Block| {
+        vec![(0, signer.sign(block.hash().as_ref()))].into_iter().collect::<HashMap<_, _>>()
+    };
+    let b1 = Block::empty(chain.genesis(), signer.clone());
+    let b2 = Block::produce(
+        &b1.header,
+        2,
+        MerkleHash::default(),
+        vec![],
+        approve(&b1),
+        vec![],
+        signer.clone(),
+    );
+    let b3 = Block::produce(
+        &b2.header,
+        3,
+        MerkleHash::default(),
+        vec![],
+        approve(&b2),
+        vec![],
+        signer.clone(),
+    );
+    // A fork whose approval for b1 only arrives after the one for b2.
+    let fork = Block::produce(
+        &b1.header,
+        2,
+        MerkleHash::default(),
+        vec![],
+        approve(&b1),
+        vec![],
+        signer.clone(),
+    );
+    let b2_hash = b2.hash();
+    for block in vec![b1, b2, b3, fork] {
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+
+    assert_eq!(chain.fork_choice().latest_vote(0), Some(&b2_hash));
+    let restored = ForkChoice::restore(chain.store().store()).unwrap();
+    assert_eq!(&restored, chain.fork_choice());
+}
+
+#[test]
+fn monitor_validators() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    chain.monitor_validators(vec!["test".to_string()]);
+    for _ in 0..2 {
+        let prev = chain.head_header().unwrap();
+        let block = Block::empty(&prev, signer.clone());
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+    let prev = chain.head_header().unwrap().clone();
+    let block = Block::produce(
+        &prev,
+        prev.height + 2,
+        prev.prev_state_root,
+        vec![],
+        HashMap::default(),
+        vec![],
+        signer.clone(),
+    );
+    chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+
+    let summary = chain.validator_monitor().summary(&"test".to_string()).unwrap();
+    assert_eq!(summary.blocks_produced, 3);
+    assert_eq!(summary.blocks_missed, 1);
+    assert!(summary.is_active);
+    assert!(chain.validator_monitor().summary(&"other".to_string()).is_none());
+}
+
+#[test]
+fn monitor_canonical_blocks_only() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    chain.monitor_validators(vec!["test".to_string()]);
+    let b1 = Block::empty(chain.genesis(), signer.clone());
+    let b2 = Block::empty(&b1.header, signer.clone());
+    let b3 = Block::empty(&b2.header, signer.clone());
+    for block in vec![b1.clone(), b2, b3] {
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+
+    // Lighter than the head: not counted until a re-org brings it on the canonical chain.
+    let mut prev = b1.header;
+    for height in 4..7 {
+        let block = Block::produce(
+            &prev,
+            height,
+            MerkleHash::default(),
+            vec![],
+            HashMap::default(),
+            vec![],
+            signer.clone(),
+        );
+        prev = block.header.clone();
+        chain.process_block(block, Provenance::NONE, |_, _, _| {}).unwrap();
+        let summary = chain.validator_monitor().summary(&"test".to_string()).unwrap();
+        if height < 6 {
+            assert_eq!(summary.blocks_produced, 3);
+            assert_eq!(summary.blocks_missed, 0);
+        }
+    }
+
+    assert_eq!(chain.head().unwrap().last_block_hash, prev.hash());
+    let summary = chain.validator_monitor().summary(&"test".to_string()).unwrap();
+    assert_eq!(summary.blocks_produced, 6);
+    assert_eq!(summary.blocks_missed, 2);
+}
+
+#[test]
+fn prune_on_finalization() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let genesis_hash = chain.genesis().hash();
+    let shared_tx = TransactionBody::send_money(1, "test", "test2", 100).sign(&*signer);
+    let fork_tx = TransactionBody::send_money(2, "test", "test2", 100).sign(&*signer);
+    let fork = Block::produce(
+        chain.genesis(),
+        1,
+        MerkleHash::default(),
+        vec![shared_tx.clone(), fork_tx.clone()],
+        HashMap::default(),
+        vec![],
+        signer.clone(),
+    );
+    let b2 = Block::produce(
+        chain.genesis(),
+        2,
+        MerkleHash::default(),
+        vec![shared_tx.clone()],
+        HashMap::default(),
+        vec![],
+        signer.clone(),
+    );
+    let b3 = Block::empty(&b2.header, signer.clone());
+    let b4 = Block::empty(&b3.header, signer);
+    let (fork_hash, b2_hash, b4_hash) = (fork.hash(), b2.hash(), b4.hash());
+    for block in vec![fork, b2, b3, b4] {
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+    assert_eq!(chain.head().unwrap().last_block_hash, b4_hash);
+    assert!(chain.get_transaction_result(&fork_tx.get_hash()).is_ok());
+
+    // Prunes below height 3: the fork, and the state of genesis that b2 replaced.
+    chain.set_state_retention(Some(1));
+    chain.set_finalized(b4_hash).unwrap();
+    assert!(chain.block_exists(&fork_hash).unwrap());
+    assert_eq!(chain.prune_states().unwrap(), 1);
+    assert!(!chain.block_exists(&fork_hash).unwrap());
+    assert!(chain.get_block(&b2_hash).is_ok());
+    assert!(chain.get_post_state_root(&genesis_hash).is_err());
+    assert!(chain.get_post_state_root(&b2_hash).is_ok());
+    assert_eq!(chain.fork_choice().weight(&fork_hash), None);
+    assert!(chain.get_transaction_result(&fork_tx.get_hash()).is_err());
+    assert!(chain.get_transaction_result(&shared_tx.get_hash()).is_ok());
+    let restored = ForkChoice::restore(chain.store().store()).unwrap();
+    assert_eq!(restored.weight(&fork_hash), None);
+    assert_eq!(&restored, chain.fork_choice());
+
+    assert_eq!(chain.prune_states().unwrap(), 0);
+    assert!(chain.get_block(&b2_hash).is_ok());
+}
+
+#[test]
+fn replay_state_from_snapshot() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut hashes = vec![chain.genesis().hash()];
+    for _ in 0..6 {
+        let prev = chain.head_header().unwrap();
+        let block = Block::empty(&prev, signer.clone());
+        hashes.push(block.hash());
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+
+    // Prunes below height 5, keeping the states at heights 0 and 3.
+    chain.set_state_retention(Some(1));
+    chain.set_state_snapshot_interval(Some(3));
+    chain.set_finalized(hashes[6]).unwrap();
+    chain.prune_states().unwrap();
+    assert!(chain.get_post_state_root(&hashes[0]).is_ok());
+    assert!(chain.get_post_state_root(&hashes[2]).is_err());
+    assert!(chain.get_post_state_root(&hashes[3]).is_ok());
+
+    let expected = chain.get_block_header(&hashes[3]).unwrap().prev_state_root;
+    assert_eq!(chain.state_at_height(2, |state_root| *state_root).unwrap(), expected);
+    let expected = *chain.get_post_state_root(&hashes[3]).unwrap();
+    assert_eq!(chain.state_at_height(3, |state_root| *state_root).unwrap(), expected);
+    assert!(chain.state_at_height(7, |_| ()).is_err());
+}
+
+#[test]
+fn export_analytics() {
+    init_test_logger();
+    let (mut chain, runtime, signer) = setup();
+    let b1 = Block::empty(chain.genesis(), signer.clone());
+    let b3 = Block::produce(
+        &b1.header,
+        3,
+        b1.header.prev_state_root,
+        vec![],
+        HashMap::default(),
+        vec![],
+        signer,
+    );
+    let b3_hash = b3.hash();
+    chain.process_block(b1, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    chain.process_block(b3, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+
+    let mut heights = vec![];
+    analytics::export_heights(&mut chain, &*runtime, 1, &mut heights).unwrap();
+    let heights = String::from_utf8(heights).unwrap();
+    let rows: Vec<_> = heights.lines().collect();
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[0], "height,block_hash,timestamp,proposer,approvals,transactions,total_weight");
+    assert_eq!(rows[2], "2,,,test");
+    assert!(rows[3].starts_with(&format!("3,{},", b3_hash)));
+
+    let mut validators = vec![];
+    analytics::export_validators(&mut chain, &*runtime, 0, &mut validators).unwrap();
+    assert_eq!(
+        String::from_utf8(validators).unwrap(),
+        "account_id,blocks_produced,blocks_missed,approvals_included,approvals_missed,stake,\
+         is_active\ntest,2,1,0,0,,true\n"
+    );
+}
+
+#[test]
+fn start_from_checkpoint() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let mut blocks = vec![];
+    for _ in 0..4 {
+        let prev = chain.head_header().unwrap();
+        let block = Block::empty(&prev, signer.clone());
+        blocks.push(block.clone());
+        chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+    }
+
+    let store = create_test_store();
+    let runtime = Arc::new(KeyValueRuntime::new(store.clone()));
+    let genesis_time = chain.genesis().timestamp;
+    let mut checkpoint =
+        Chain::from_checkpoint(store, runtime, genesis_time, blocks[2].clone(), vec![], vec![])
+            .unwrap();
+    assert_eq!(checkpoint.head().unwrap().height, 3);
+    assert_eq!(checkpoint.tail().unwrap().height, 3);
+    assert!(checkpoint.get_header_by_height(1).is_err());
+
+    let tip = checkpoint.process_block(blocks[3].clone(), Provenance::NONE, |_, _, _| {}).unwrap();
+    assert_eq!(tip.unwrap().height, 4);
+
+    let headers = vec![blocks[1].header.clone(), blocks[0].header.clone()];
+    assert!(checkpoint.backfill_headers(vec![blocks[0].header.clone()]).is_err());
+    assert!(checkpoint.backfill_headers(headers).unwrap());
+    assert_eq!(checkpoint.get_header_by_height(1).unwrap().hash(), blocks[0].hash());
+    assert_eq!(checkpoint.tail().unwrap().height, 1);
+}
+
+#[test]
+fn replay_block() {
+    init_test_logger();
+    let (mut chain, _, signer) = setup();
+    let block = Block::empty(chain.genesis(), signer);
+    let block_hash = block.hash();
+    chain.process_block(block, Provenance::PRODUCED, |_, _, _| {}).unwrap();
+
+    let report = chain.replay(&block_hash, ReplayOptions::default()).unwrap();
+    let state_root = *chain.get_post_state_root(&block_hash).unwrap();
+    assert_eq!(report.height, 1);
+    assert_eq!(report.state_root, state_root);
+    assert_eq!(report.stages, vec![ReplayStage::ApplyReceipts { receipts: 0, state_root }]);
+    assert!(!report.is_mismatch());
+
+    let dir = env::temp_dir().join(format!("replay_block_{}", block_hash));
+    let options =
+        ReplayOptions { expected_state_root: Some(hash(b"other")), report_dir: Some(dir.clone()) };
+    let report = chain.replay(&block_hash, options).unwrap();
+    assert!(report.is_mismatch());
+    assert!(dir.join(format!("replay-1-{}.cbor", block_hash)).exists());
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -78,7 +78,11 @@ impl ClientActor {
         block_producer: Option<BlockProducer>,
     ) -> Result<Self, Error> {
         // TODO: Wait until genesis.
-        let chain = Chain::new(store, runtime_adapter.clone(), genesis_time)?;
+        let mut chain = Chain::new(store, runtime_adapter.clone(), genesis_time)?;
+        chain.monitor_validators(config.monitored_validators.clone());
+        chain.set_state_retention(config.state_retention);
+        chain.set_state_snapshot_interval(config.state_snapshot_interval);
+        chain.set_replay_report_dir(config.replay_report_dir.clone());
         let tx_pool = TransactionPool::new();
         let sync_status = SyncStatus::AwaitingPeers;
         let header_sync = HeaderSync::new(network_actor.clone());
@@ -122,6 +126,9 @@ impl Actor for ClientActor {
 
         // Start periodic logging of current state of the client.
         self.log_summary(ctx);
+
+        // Start periodic pruning of the states and forks below the finalized block.
+        self.prune_states(ctx);
     }
 }
 
@@ -263,8 +270,42 @@ impl ClientActor {
         // Reconcile the txpool against the new block *after* we have broadcast it too our peers.
         // This may be slow and we do not want to delay block propagation.
         // We only want to reconcile the txpool against the new block *if* total weight has increased.
-        if status == BlockStatus::Next || status == BlockStatus::Reorg {
-            self.tx_pool.reconcile_block(&block);
+        match status {
+            BlockStatus::Next | BlockStatus::Reorg(_) => {
+                self.tx_pool.reconcile_block(&block);
+                self.update_finality(&block);
+            }
+            BlockStatus::Fork => {}
+        }
+    }
+
+    /// Justifies the parent of the new head `block` if more than two thirds of the block producers
+    /// approved it, and finalizes the grandparent if that was the block justified before.
+    /// The finalized block lets `prune_states` go below it.
+    fn update_finality(&mut self, block: &Block) {
+        let validators =
+            unwrap_or_return!(self.runtime_adapter.get_epoch_block_proposers(block.header.height));
+        let prev = unwrap_or_return!(self.chain.get_block_header(&block.header.prev_hash)).clone();
+        let producer =
+            unwrap_or_return!(self.runtime_adapter.get_block_proposer(block.header.height));
+        let prev_producer = unwrap_or_return!(self.runtime_adapter.get_block_proposer(prev.height));
+        // Producers don't send approvals for their own blocks, building on the parent approves it.
+        let approvals = block.header.approval_mask.iter().filter(|approved| **approved).count()
+            + if producer == prev_producer { 1 } else { 2 };
+        if approvals * 3 <= validators.len() * 2 {
+            return;
+        }
+
+        let prev_justified = self.chain.fork_choice().justified().cloned();
+        if prev_justified == Some(prev.hash()) {
+            return;
+        }
+        unwrap_or_return!(self.chain.set_justified(prev.hash()));
+        if prev_justified == Some(prev.prev_hash)
+            && self.chain.fork_choice().finalized() != Some(&prev.prev_hash)
+        {
+            unwrap_or_return!(self.chain.set_finalized(prev.prev_hash));
+            debug!(target: "client", "Finalized block {}", prev.prev_hash);
         }
     }
 
@@ -415,7 +456,10 @@ impl ClientActor {
         }
 
         // Take transactions from the pool.
-        let transactions = self.tx_pool.prepare_transactions(self.config.block_expected_weight)?;
+        let transactions = self.tx_pool.prepare_transactions(
+            self.config.block_expected_weight,
+            self.config.max_transactions_per_sender,
+        )?;
         let block = Block::produce(
             &prev,
             next_height,
@@ -461,7 +505,6 @@ impl ClientActor {
     ) -> NetworkClientResponses {
         let hash = block.hash();
         debug!(target: "client", "Received block {} at {} from {}", hash, block.header.height, peer_id);
-        let previous = self.chain.get_previous_header(&block.header).map(Clone::clone);
         let provenance =
             if was_requested { near_chain::Provenance::SYNC } else { near_chain::Provenance::NONE };
         match self.process_block(ctx, block, provenance) {
@@ -475,15 +518,13 @@ impl ClientActor {
             }
             Err(e) => match e.kind() {
                 near_chain::ErrorKind::Orphan => {
-                    if let Ok(previous) = previous {
-                        if !self.chain.is_orphan(&previous.hash()) {
-                            debug!(
-                                "Process block: received an orphan block, checking the parent: {}",
-                                previous.hash()
-                            );
-                            self.request_block_by_hash(previous.hash(), peer_id)
-                        }
-                    }
+                    // Parked until its ancestors arrive, request the first one missing.
+                    let missing = self.chain.missing_ancestor(&hash);
+                    debug!(
+                        "Process block: received an orphan block, requesting the ancestor: {}",
+                        missing
+                    );
+                    self.request_block_by_hash(missing, peer_id);
                     NetworkClientResponses::NoResponse
                 }
                 _ => {
@@ -725,6 +766,16 @@ impl ClientActor {
         });
     }
 
+    /// Periodically prune the states and forks below the finalized block.
+    fn prune_states(&self, ctx: &mut Context<Self>) {
+        ctx.run_later(self.config.prune_period, move |act, ctx| {
+            if let Err(err) = act.chain.prune_states() {
+                error!(target: "client", "Failed to prune states: {}", err);
+            }
+            act.prune_states(ctx);
+        });
+    }
+
     /// Periodically log summary.
     fn log_summary(&self, ctx: &mut Context<Self>) {
         ctx.run_later(self.config.log_summary_period, move |act, ctx| {
@@ -755,6 +806,16 @@ impl ClientActor {
                   Cyan.bold().paint(format!("{:2}/{:2} peers", act.network_info.num_active_peers, act.network_info.peer_max_count)),
                   Green.bold().paint(format!("{:.2} bls {:.2} tps", avg_bls, avg_tps))
             );
+            for (account_id, summary) in act.chain.validator_monitor().summaries() {
+                info!(target: "info", "{} {}: {} produced, {} missed, {}/{} approvals included",
+                    account_id,
+                    if summary.is_active { "V" } else { "-" },
+                    summary.blocks_produced,
+                    summary.blocks_missed,
+                    summary.approvals_included,
+                    summary.approvals_included + summary.approvals_missed,
+                );
+            }
             act.started = Instant::now();
             act.num_blocks_processed = 0;
             act.num_tx_processed = 0;
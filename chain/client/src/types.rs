@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -73,6 +74,8 @@ pub struct ClientConfig {
     pub max_block_production_delay: Duration,
     /// Expected block weight (num of tx, gas, etc).
     pub block_expected_weight: u32,
+    /// Most transactions of one account in a block, `None` for no limit.
+    pub max_transactions_per_sender: Option<u32>,
     /// Skip waiting for sync (for testing or single node testnet).
     pub skip_sync_wait: bool,
     /// How often to check that we are not out of sync.
@@ -91,6 +94,16 @@ pub struct ClientConfig {
     pub log_summary_period: Duration,
     /// Produce empty blocks, use `false` for testing.
     pub produce_empty_blocks: bool,
+    /// Validators whose life events are logged and summarized.
+    pub monitored_validators: Vec<AccountId>,
+    /// Blocks below the finalized one that keep their state, `None` to keep every state.
+    pub state_retention: Option<BlockIndex>,
+    /// Pruning keeps the states at multiples of this height, to replay the others from.
+    pub state_snapshot_interval: Option<BlockIndex>,
+    /// Period between pruning the states and forks below the finalized block.
+    pub prune_period: Duration,
+    /// Where to write the replay report of a block rejected for its state root, `None` for none.
+    pub replay_report_dir: Option<PathBuf>,
 }
 
 impl ClientConfig {
@@ -101,6 +114,7 @@ impl ClientConfig {
             min_block_production_delay: Duration::from_millis(100),
             max_block_production_delay: Duration::from_millis(300),
             block_expected_weight: 1000,
+            max_transactions_per_sender: None,
             skip_sync_wait,
             sync_check_period: Duration::from_millis(100),
             sync_step_period: Duration::from_millis(10),
@@ -110,6 +124,11 @@ impl ClientConfig {
             fetch_info_period: Duration::from_millis(100),
             log_summary_period: Duration::from_secs(10),
             produce_empty_blocks: true,
+            monitored_validators: vec![],
+            state_retention: None,
+            state_snapshot_interval: None,
+            prune_period: Duration::from_secs(10),
+            replay_report_dir: None,
         }
     }
 }
@@ -122,6 +141,7 @@ impl ClientConfig {
             min_block_production_delay: Duration::from_millis(100),
             max_block_production_delay: Duration::from_millis(2000),
             block_expected_weight: 1000,
+            max_transactions_per_sender: None,
             skip_sync_wait: false,
             sync_check_period: Duration::from_secs(10),
             sync_step_period: Duration::from_millis(10),
@@ -131,6 +151,11 @@ impl ClientConfig {
             fetch_info_period: Duration::from_millis(100),
             log_summary_period: Duration::from_secs(10),
             produce_empty_blocks: true,
+            monitored_validators: vec![],
+            state_retention: None,
+            state_snapshot_interval: None,
+            prune_period: Duration::from_secs(10),
+            replay_report_dir: None,
         }
     }
 }
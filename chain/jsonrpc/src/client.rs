@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use actix_web::client::Client;
@@ -170,6 +171,8 @@ jsonrpc_client!(pub struct JsonRpcClient {
     pub fn tx(&mut self, hash: String) -> RpcRequest<FinalTransactionResult>;
     pub fn tx_details(&mut self, hash: String) -> RpcRequest<TransactionResult>;
     pub fn block(&mut self, height: BlockIndex) -> RpcRequest<Block>;
+    pub fn log_levels(&mut self) -> RpcRequest<HashMap<String, String>>;
+    pub fn set_log_level(&mut self, subsystem: String, level: String) -> RpcRequest<HashMap<String, String>>;
 });
 
 /// Create new JSON RPC client that connects to the given address.
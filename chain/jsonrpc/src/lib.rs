@@ -15,6 +15,7 @@ use serde_json::Value;
 
 use async_utils::{delay, timeout};
 use message::Message;
+use near_chain::logging::{self, Level};
 use near_client::{ClientActor, GetBlock, Query, Status, TxDetails, TxStatus, ViewClientActor};
 use near_network::{NetworkClientMessages, NetworkClientResponses};
 use near_primitives::hash::CryptoHash;
@@ -139,6 +140,8 @@ impl JsonRpcHandler {
             "tx" => self.tx_status(request.params).await,
             "tx_details" => self.tx_details(request.params).await,
             "block" => self.block(request.params).await,
+            "log_levels" => self.log_levels().await,
+            "set_log_level" => self.set_log_level(request.params).await,
             _ => Err(RpcError::method_not_found(request.method)),
         }
     }
@@ -217,6 +220,30 @@ impl JsonRpcHandler {
         let (height,) = parse_params::<(BlockIndex,)>(params)?;
         jsonify(self.view_client_addr.send(GetBlock::Height(height)).compat().await)
     }
+
+    async fn log_levels(&self) -> Result<Value, RpcError> {
+        Ok(Value::Object(
+            logging::levels()
+                .into_iter()
+                .map(|(subsystem, level)| (subsystem.to_owned(), Value::from(level.as_str())))
+                .collect(),
+        ))
+    }
+
+    /// Changes the level of a logging subsystem, returning the levels of all of them.
+    async fn set_log_level(&self, params: Option<Value>) -> Result<Value, RpcError> {
+        let (subsystem, level) = parse_params::<(String, String)>(params)?;
+        let level = level.parse::<Level>().map_err(|_| {
+            RpcError::invalid_params(Some(format!("Unknown log level: {}", level)))
+        })?;
+        if !logging::set_level(&subsystem, level) {
+            return Err(RpcError::invalid_params(Some(format!(
+                "Unknown log subsystem: {}",
+                subsystem
+            ))));
+        }
+        self.log_levels().await
+    }
 }
 
 fn rpc_handler(
@@ -96,3 +96,31 @@ fn test_health_ok() {
     })
     .unwrap();
 }
+
+/// Change the level of a logging subsystem.
+#[test]
+fn test_set_log_level() {
+    init_test_logger();
+
+    System::run(|| {
+        let (_view_client_addr, addr) = start_all(false);
+
+        let mut client = new_client(&format!("http://{}", addr));
+        actix::spawn(
+            client
+                .set_log_level("validator_monitor".to_string(), "debug".to_string())
+                .then(move |res| {
+                    let levels = res.unwrap();
+                    assert_eq!(levels["validator_monitor"], "DEBUG");
+                    assert_eq!(levels["chain"], "INFO");
+                    client.set_log_level("unknown".to_string(), "debug".to_string())
+                })
+                .then(|res| {
+                    assert!(res.is_err());
+                    System::current().stop();
+                    future::result(Ok(()))
+                }),
+        );
+    })
+    .unwrap();
+}
@@ -34,18 +34,35 @@ impl TransactionPool {
 
     /// Take transactions from the pool, in the appropriate order to be put in a new block.
     /// Ensure that on average they will fit into expected weight.
+    ///
+    /// Accounts take turns giving their transaction of the lowest nonce, so one account can't
+    /// fill the block while the others wait, and give at most `max_per_sender` transactions.
     pub fn prepare_transactions(
         &mut self,
         expected_weight: u32,
+        max_per_sender: Option<u32>,
     ) -> Result<Vec<SignedTransaction>, Error> {
         // TODO: pack transactions better.
-        let result = self
-            .transactions
-            .values()
-            .flat_map(BTreeMap::values)
-            .take(expected_weight as usize)
-            .cloned()
-            .collect();
+        let expected_weight = expected_weight as usize;
+        let max_per_sender = max_per_sender.map_or(usize::max_value(), |max| max as usize);
+        let mut accounts: Vec<_> = self.transactions.iter().collect();
+        // Same turns for the same pool.
+        accounts.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut accounts: Vec<_> =
+            accounts.into_iter().map(|(_, txs)| txs.values().take(max_per_sender)).collect();
+
+        let mut result = vec![];
+        while result.len() < expected_weight {
+            let turn: Vec<_> = accounts
+                .iter_mut()
+                .filter_map(Iterator::next)
+                .take(expected_weight - result.len())
+                .collect();
+            if turn.is_empty() {
+                break;
+            }
+            result.extend(turn.into_iter().cloned());
+        }
         Ok(result)
     }
 
@@ -79,7 +96,7 @@ mod tests {
 
     use near_chain::ValidTransaction;
     use near_primitives::crypto::signer::InMemorySigner;
-    use near_primitives::transaction::TransactionBody;
+    use near_primitives::transaction::{SignedTransaction, TransactionBody};
 
     use crate::TransactionPool;
     use near_primitives::types::Balance;
@@ -98,9 +115,52 @@ mod tests {
         for tx in transactions {
             pool.insert_transaction(ValidTransaction { transaction: tx });
         }
-        let transactions = pool.prepare_transactions(10).unwrap();
+        let transactions = pool.prepare_transactions(10, None).unwrap();
         let nonces: Vec<u64> = transactions.iter().map(|tx| tx.body.get_nonce()).collect();
         assert_eq!(nonces, (1..10).collect::<Vec<u64>>())
     }
 
+    /// Alice's transactions don't keep Bob's out of a block.
+    #[test]
+    fn test_sender_turns() {
+        let alice = InMemorySigner::from_seed("alice.near", "alice.near");
+        let bob = InMemorySigner::from_seed("bob.near", "bob.near");
+        let mut pool = TransactionPool::new();
+        for i in 1..10 {
+            let tx = TransactionBody::send_money(i, "alice.near", "bob.near", i as Balance);
+            pool.insert_transaction(ValidTransaction { transaction: tx.sign(&alice) });
+        }
+        for i in 1..3 {
+            let tx = TransactionBody::send_money(i, "bob.near", "alice.near", i as Balance);
+            pool.insert_transaction(ValidTransaction { transaction: tx.sign(&bob) });
+        }
+        let senders = |transactions: Vec<SignedTransaction>| {
+            transactions
+                .iter()
+                .map(|tx| (tx.body.get_originator(), tx.body.get_nonce()))
+                .collect::<Vec<_>>()
+        };
+
+        let transactions = pool.prepare_transactions(3, None).unwrap();
+        assert_eq!(
+            senders(transactions),
+            vec![
+                ("alice.near".to_string(), 1),
+                ("bob.near".to_string(), 1),
+                ("alice.near".to_string(), 2)
+            ]
+        );
+        let transactions = pool.prepare_transactions(10, Some(3)).unwrap();
+        assert_eq!(
+            senders(transactions),
+            vec![
+                ("alice.near".to_string(), 1),
+                ("bob.near".to_string(), 1),
+                ("alice.near".to_string(), 2),
+                ("bob.near".to_string(), 2),
+                ("alice.near".to_string(), 3),
+            ]
+        );
+    }
+
 }
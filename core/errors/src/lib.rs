@@ -0,0 +1,203 @@
+//! Error codes shared by every filesys subsystem.
+//!
+//! Each module keeps its own error type, but implements `CoreError` so callers (most notably the
+//! HTTP handler) can answer with a machine-readable code and decide whether an operation is worth
+//! retrying without knowing the concrete error.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+/// A machine-readable classification of an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The requested item does not exist.
+    NotFound,
+    /// The input was malformed or failed validation.
+    InvalidInput,
+    /// The request is well-formed but not supported (unknown codec, hash, route...).
+    Unsupported,
+    /// Stored or received bytes could not be decoded.
+    Decode,
+    /// A value could not be encoded.
+    Encode,
+    /// The item conflicts with existing data.
+    Conflict,
+    /// The operation did not complete in time.
+    Timeout,
+    /// A resource is temporarily unavailable (lock held, peer unreachable...).
+    Unavailable,
+    /// The underlying storage or IO layer failed.
+    Io,
+    /// Anything else.
+    Internal,
+}
+
+impl ErrorCode {
+    /// Returns a stable string representation, suitable for API responses and logs.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::InvalidInput => "invalid_input",
+            ErrorCode::Unsupported => "unsupported",
+            ErrorCode::Decode => "decode",
+            ErrorCode::Encode => "encode",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::Io => "io",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
+    /// Returns `true` if retrying the same operation later may succeed.
+    pub fn is_retriable(&self) -> bool {
+        matches!(self, ErrorCode::Timeout | ErrorCode::Unavailable | ErrorCode::Io)
+    }
+
+    /// Returns the HTTP status code an API should answer with.
+    pub fn http_status(&self) -> u16 {
+        match self {
+            ErrorCode::NotFound => 404,
+            ErrorCode::InvalidInput | ErrorCode::Unsupported | ErrorCode::Decode => 400,
+            ErrorCode::Conflict => 409,
+            ErrorCode::Timeout => 504,
+            ErrorCode::Unavailable => 503,
+            ErrorCode::Encode | ErrorCode::Io | ErrorCode::Internal => 500,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Implemented by the error type of every subsystem.
+pub trait CoreError: StdError + Send + Sync + 'static {
+    /// The machine-readable code of this error.
+    fn code(&self) -> ErrorCode;
+
+    /// Returns `true` if retrying the same operation later may succeed.
+    fn is_retriable(&self) -> bool {
+        self.code().is_retriable()
+    }
+
+    /// Iterates over this error and all of its sources, outermost first.
+    fn chain(&self) -> Chain<'_>
+    where
+        Self: Sized,
+    {
+        Chain { next: Some(self) }
+    }
+}
+
+/// Iterator over an error and its sources. See `CoreError::chain`.
+pub struct Chain<'a> {
+    next: Option<&'a (dyn StdError + 'static)>,
+}
+
+impl<'a> Chain<'a> {
+    /// Walks the sources of any `std::error::Error`.
+    pub fn new(error: &'a (dyn StdError + 'static)) -> Self {
+        Chain { next: Some(error) }
+    }
+}
+
+impl<'a> Iterator for Chain<'a> {
+    type Item = &'a (dyn StdError + 'static);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        self.next = current.source();
+        Some(current)
+    }
+}
+
+/// A generic error carrying a code, a message and an optional source.
+///
+/// Useful for modules that do not need their own error enum.
+#[derive(Debug)]
+pub struct Error {
+    code: ErrorCode,
+    message: String,
+    source: Option<Box<dyn StdError + Send + Sync + 'static>>,
+}
+
+impl Error {
+    pub fn new<M: Into<String>>(code: ErrorCode, message: M) -> Self {
+        Error { code, message: message.into(), source: None }
+    }
+
+    /// Attaches the error that caused `self`.
+    pub fn with_source<E: StdError + Send + Sync + 'static>(mut self, source: E) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl CoreError for Error {
+    fn code(&self) -> ErrorCode {
+        self.code
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| &**e as &(dyn StdError + 'static))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        let code = match e.kind() {
+            io::ErrorKind::NotFound => ErrorCode::NotFound,
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorCode::Timeout,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => ErrorCode::InvalidInput,
+            _ => ErrorCode::Io,
+        };
+        Error::new(code, e.to_string()).with_source(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retriable() {
+        assert!(ErrorCode::Timeout.is_retriable());
+        assert!(ErrorCode::Io.is_retriable());
+        assert!(!ErrorCode::NotFound.is_retriable());
+        assert!(!ErrorCode::Decode.is_retriable());
+    }
+
+    #[test]
+    fn test_source_chain() {
+        let io = io::Error::new(io::ErrorKind::Other, "disk on fire");
+        let inner = Error::from(io);
+        let outer = Error::new(ErrorCode::Internal, "put failed").with_source(inner);
+
+        let messages: Vec<String> = outer.chain().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec!["internal: put failed", "io: disk on fire", "disk on fire"]);
+        assert!(!outer.is_retriable());
+    }
+
+    #[test]
+    fn test_io_error_code() {
+        let err = Error::from(io::Error::new(io::ErrorKind::NotFound, "missing"));
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert_eq!(err.code().http_status(), 404);
+    }
+}
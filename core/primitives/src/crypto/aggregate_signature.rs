@@ -181,6 +181,11 @@ impl<E: Engine> Signature<E> {
     pub fn empty() -> Self {
         Signature { point: E::G2Affine::zero() }
     }
+
+    /// Whether the signature is the point at infinity, see `AggregateSignature::is_infinity`.
+    pub fn is_infinity(&self) -> bool {
+        self.point.is_zero()
+    }
 }
 
 // Note: deriving PartialEq and Eq doesn't work
@@ -304,8 +309,14 @@ impl<E: Engine> TryFrom<Vec<u8>> for Signature<E> {
 }
 
 impl<E: Engine> CompressedPublicKey<E> {
+    /// Decompress a pubkey, verifying that the point is on the curve and in the G1 subgroup.
+    /// Points outside the subgroup would let a forged key cancel out others in an aggregate.
     pub fn decompress(&self) -> Result<PublicKey<E>, GroupDecodingError> {
-        Ok(PublicKey { point: self.0.into_affine()? })
+        let point = self.0.into_affine_semi_checked()?;
+        if !point.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(GroupDecodingError::NotInSubgroup);
+        }
+        Ok(PublicKey { point })
     }
 
     /// Decompress a pubkey, without verifying that the resulting point is actually on the curve.
@@ -483,12 +494,55 @@ impl<E: Engine> AggregateSignature<E> {
     }
 
     pub fn aggregate(&mut self, sig: &Signature<E>) {
+        self.add_assign(sig);
+    }
+
+    /// Add `sig` to the aggregate.
+    pub fn add_assign(&mut self, sig: &Signature<E>) {
         self.point.add_assign_mixed(&sig.point);
     }
 
+    /// Remove `sig`, added before, from the aggregate.
+    pub fn sub_assign(&mut self, sig: &Signature<E>) {
+        let mut point = sig.point;
+        point.negate();
+        self.point.add_assign_mixed(&point);
+    }
+
+    /// Whether the aggregate is the point at infinity, like an empty aggregate. It verifies
+    /// against an aggregate of keys that cancel out, so it must not be accepted from peers.
+    pub fn is_infinity(&self) -> bool {
+        self.point.is_zero()
+    }
+
     pub fn get_signature(&self) -> Signature<E> {
         Signature { point: self.point.into_affine() }
     }
+
+    pub fn compress(&self) -> CompressedSignature<E> {
+        self.get_signature().compress()
+    }
+}
+
+impl<E: Engine> From<&AggregateSignature<E>> for Vec<u8> {
+    fn from(signature: &AggregateSignature<E>) -> Vec<u8> {
+        signature.compress().into()
+    }
+}
+
+/// Decodes a compressed aggregate, verifying that it is in the G2 subgroup right away instead of
+/// when it is verified.
+impl<E: Engine> TryFrom<&[u8]> for AggregateSignature<E> {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(v: &[u8]) -> Result<Self, Self::Error> {
+        let signature =
+            CompressedSignature::<E>::try_from(v)?.decode().map_err(|err| err.to_string())?;
+        if !signature.point.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(GroupDecodingError::NotInSubgroup.to_string().into());
+        }
+        Ok(AggregateSignature { point: signature.point.into_projective() })
+    }
 }
 
 impl<E: Engine> Default for AggregateSignature<E> {
@@ -609,6 +663,60 @@ mod tests {
         assert!(blank_pk.verify(message.as_bytes(), &blank_signature));
     }
 
+    #[test]
+    fn aggregate_add_sub() {
+        let mut rng = XorShiftRng::seed_from_u64(5);
+
+        let secret = (0..3).map(|_| BlsSecretKey::generate_from_rng(&mut rng)).collect::<Vec<_>>();
+        let message = "Hello, world!";
+        let signature = secret.iter().map(|s| s.sign(message.as_bytes())).collect::<Vec<_>>();
+
+        let mut aggregate = BlsAggregateSignature::new();
+        assert!(aggregate.is_infinity());
+        for s in &signature {
+            aggregate.add_assign(s);
+        }
+        aggregate.sub_assign(&signature[2]);
+
+        let mut expected = BlsAggregateSignature::new();
+        expected.add_assign(&signature[0]);
+        expected.add_assign(&signature[1]);
+        assert_eq!(aggregate.get_signature(), expected.get_signature());
+
+        aggregate.sub_assign(&signature[0]);
+        aggregate.sub_assign(&signature[1]);
+        assert!(aggregate.is_infinity());
+        assert!(aggregate.get_signature().is_infinity());
+    }
+
+    #[test]
+    fn aggregate_encoding() {
+        let mut rng = XorShiftRng::seed_from_u64(6);
+
+        let secret = BlsSecretKey::generate_from_rng(&mut rng);
+        let mut aggregate = BlsAggregateSignature::new();
+        aggregate.add_assign(&secret.sign(b"message"));
+
+        let bytes: Vec<u8> = (&aggregate).into();
+        let decoded = BlsAggregateSignature::try_from(&bytes[..]).unwrap();
+        assert_eq!(decoded.get_signature(), aggregate.get_signature());
+        assert!(BlsAggregateSignature::try_from(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn pubkey_subgroup() {
+        // (0, 2) is on the curve but of order 3, outside the G1 subgroup.
+        let mut bytes = [0u8; 48];
+        bytes[0] = 0x80;
+        let compressed = CompressedPublicKey::<Bls12>::try_from(&bytes[..]).unwrap();
+
+        match compressed.decompress() {
+            Err(GroupDecodingError::NotInSubgroup) => {}
+            other => panic!("unexpected {:?}", other.map(|key| key.to_string())),
+        }
+        assert!(BlsPublicKey::try_from(&bytes[..]).is_err());
+    }
+
     #[test]
     fn encoding() {
         let mut rng = XorShiftRng::seed_from_u64(4);
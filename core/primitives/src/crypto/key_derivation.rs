@@ -0,0 +1,191 @@
+//! Hierarchical derivation of BLS secret keys (EIP-2333) along EIP-2334 paths.
+//!
+//! A single seed, usually taken from a BIP-39 mnemonic, deterministically produces a tree of
+//! keys: `derive_master_key` gives the root and `derive_child_key` each child. Validator keys live
+//! at `m/12381/3600/<index>/0/0`, their withdrawal keys at `m/12381/3600/<index>/0`.
+use std::convert::TryFrom;
+use std::error::Error;
+
+use bip39::{Language, Mnemonic, MnemonicType, Seed};
+use hmac::{Hmac, Mac};
+use pairing::bls12_381::{Fr, FrRepr};
+use pairing::{Field, PrimeField, PrimeFieldRepr};
+use sha2::{Digest, Sha256};
+
+use crate::crypto::aggregate_signature::BlsSecretKey;
+
+/// EIP-2334 purpose of BLS12-381 keys.
+const PURPOSE: u32 = 12381;
+/// EIP-2334 coin type of eth2 validators.
+const COIN_TYPE: u32 = 3600;
+
+const KEYGEN_SALT: &[u8] = b"BLS-SIG-KEYGEN-SALT-";
+/// Bytes of HKDF output reduced modulo r, 48 leaves a negligible bias.
+const HKDF_MOD_R_LEN: u16 = 48;
+/// Number of 32 bytes chunks in each half of a lamport secret key.
+const LAMPORT_CHUNKS: usize = 255;
+
+/// Returns a new random 24 words BIP-39 mnemonic, to be written down by the user.
+pub fn generate_mnemonic() -> String {
+    Mnemonic::new(MnemonicType::Words24, Language::English).into_phrase()
+}
+
+/// Derives the master key of the BIP-39 `mnemonic`, protected by the optional `passphrase`.
+pub fn master_key_from_mnemonic(
+    mnemonic: &str,
+    passphrase: &str,
+) -> Result<BlsSecretKey, Box<dyn Error>> {
+    let mnemonic =
+        Mnemonic::from_phrase(mnemonic, Language::English).map_err(|err| err.to_string())?;
+    derive_master_key(Seed::new(&mnemonic, passphrase).as_bytes())
+}
+
+/// Derives the master key of `seed`, which must be at least 32 bytes long.
+pub fn derive_master_key(seed: &[u8]) -> Result<BlsSecretKey, Box<dyn Error>> {
+    if seed.len() < 32 {
+        return Err("the seed must be at least 32 bytes long".into());
+    }
+    Ok(hkdf_mod_r(seed))
+}
+
+/// Derives the child `index` of `parent`.
+pub fn derive_child_key(parent: &BlsSecretKey, index: u32) -> BlsSecretKey {
+    let ikm = Vec::from(parent.clone());
+    let salt = index.to_be_bytes();
+    let not_ikm: Vec<u8> = ikm.iter().map(|byte| !byte).collect();
+
+    let mut lamport_public_key = Sha256::new();
+    for ikm in &[ikm, not_ikm] {
+        let prk = hkdf_extract(&salt, &[&ikm[..]]);
+        let lamport_secret_key = hkdf_expand(&prk, &[], 32 * LAMPORT_CHUNKS);
+        for chunk in lamport_secret_key.chunks(32) {
+            lamport_public_key.input(Sha256::digest(chunk));
+        }
+    }
+    hkdf_mod_r(&lamport_public_key.result())
+}
+
+/// Derives the key at `path` below `master`, a path such as `m/12381/3600/0/0/0`.
+pub fn derive_path(master: &BlsSecretKey, path: &str) -> Result<BlsSecretKey, Box<dyn Error>> {
+    let mut nodes = path.split('/');
+    if nodes.next() != Some("m") {
+        return Err(format!("path {} does not start with m", path).into());
+    }
+    let mut key = master.clone();
+    for node in nodes {
+        let index = node.parse().map_err(|_| format!("invalid path index {}", node))?;
+        key = derive_child_key(&key, index);
+    }
+    Ok(key)
+}
+
+/// Returns the signing key of validator `index`, at `m/12381/3600/<index>/0/0`.
+pub fn validator_signing_key(master: &BlsSecretKey, index: u32) -> BlsSecretKey {
+    derive_child_key(&validator_withdrawal_key(master, index), 0)
+}
+
+/// Returns the withdrawal key of validator `index`, at `m/12381/3600/<index>/0`.
+pub fn validator_withdrawal_key(master: &BlsSecretKey, index: u32) -> BlsSecretKey {
+    [PURPOSE, COIN_TYPE, index, 0].iter().fold(master.clone(), |key, &i| derive_child_key(&key, i))
+}
+
+/// Maps `ikm` to a non-zero secret key, `HKDF_mod_r` of EIP-2333.
+fn hkdf_mod_r(ikm: &[u8]) -> BlsSecretKey {
+    let mut salt = Sha256::digest(KEYGEN_SALT);
+    loop {
+        let prk = hkdf_extract(&salt, &[ikm, &[0u8]]);
+        let okm = hkdf_expand(&prk, &[&HKDF_MOD_R_LEN.to_be_bytes()], HKDF_MOD_R_LEN as usize);
+        let scalar = reduce_mod_r(&okm);
+        if !scalar.is_zero() {
+            let mut bytes = vec![];
+            scalar.into_repr().write_be(&mut bytes).expect("writing to a Vec never fails");
+            return BlsSecretKey::try_from(bytes.as_ref())
+                .expect("a reduced scalar is a secret key");
+        }
+        salt = Sha256::digest(&salt);
+    }
+}
+
+/// Interprets `bytes` as a big-endian integer modulo r.
+fn reduce_mod_r(bytes: &[u8]) -> Fr {
+    let base = Fr::from_repr(FrRepr::from(256)).expect("256 is smaller than r");
+    let mut scalar = Fr::zero();
+    for &byte in bytes {
+        scalar.mul_assign(&base);
+        scalar.add_assign(
+            &Fr::from_repr(FrRepr::from(u64::from(byte))).expect("a byte is smaller than r"),
+        );
+    }
+    scalar
+}
+
+fn hmac_sha256(key: &[u8], message: &[&[u8]]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC takes keys of any length");
+    for part in message {
+        mac.input(part);
+    }
+    mac.result().code().to_vec()
+}
+
+fn hkdf_extract(salt: &[u8], ikm: &[&[u8]]) -> Vec<u8> {
+    hmac_sha256(salt, ikm)
+}
+
+fn hkdf_expand(prk: &[u8], info: &[&[u8]], len: usize) -> Vec<u8> {
+    let mut okm = Vec::with_capacity(len);
+    let mut block = vec![];
+    let mut counter = 0u8;
+    while okm.len() < len {
+        counter += 1;
+        block = {
+            let counter = [counter];
+            let mut message = vec![&block[..]];
+            message.extend_from_slice(info);
+            message.push(&counter);
+            hmac_sha256(prk, &message)
+        };
+        okm.extend_from_slice(&block);
+    }
+    okm.truncate(len);
+    okm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first test case of EIP-2333, its seed is the BIP-39 test vector of `MNEMONIC`.
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    const SEED: &str = "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04";
+    /// 6083874454709270928345386274498605044986640685124978867557563392430687146096
+    const MASTER_KEY: &str = "0d7359d57963ab8fbbde1852dcf553fedbc31f464d80ee7d40ae683122b45070";
+    /// 20397789859736650942317412262472558107875392172444076792671091975210932703118
+    const CHILD_KEY: &str = "2d18bd6c14e6d15bf8b5085c9b74f3daae3b03cc2014770a599d8c1539e50f8e";
+
+    fn key(hex: &str) -> BlsSecretKey {
+        BlsSecretKey::try_from(hex::decode(hex).unwrap().as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_eip2333_vector() {
+        let master = derive_master_key(&hex::decode(SEED).unwrap()).unwrap();
+        assert!(master == key(MASTER_KEY));
+        assert!(derive_child_key(&master, 0) == key(CHILD_KEY));
+        assert!(master_key_from_mnemonic(MNEMONIC, "TREZOR").unwrap() == master);
+        assert!(derive_master_key(&[0; 31]).is_err());
+    }
+
+    #[test]
+    fn test_derive_path() {
+        let master = master_key_from_mnemonic(&generate_mnemonic(), "").unwrap();
+        assert!(derive_path(&master, "m/0").unwrap() == derive_child_key(&master, 0));
+        assert!(
+            derive_path(&master, "m/12381/3600/7/0/0").unwrap()
+                == validator_signing_key(&master, 7)
+        );
+        assert!(validator_signing_key(&master, 7) != validator_signing_key(&master, 8));
+        assert!(derive_path(&master, "0/1").is_err());
+        assert!(derive_path(&master, "m/x").is_err());
+        assert!(master_key_from_mnemonic("abandon about", "").is_err());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod aggregate_signature;
 pub mod group_signature;
+pub mod key_derivation;
+pub mod keystore;
 pub mod signature;
 pub mod signer;
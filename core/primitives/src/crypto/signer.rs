@@ -9,7 +9,10 @@ use rand::distributions::Alphanumeric;
 use rand::rngs::OsRng;
 use rand::Rng;
 
+use exonum_sodiumoxide::crypto::sign::ed25519;
+
 use crate::crypto::aggregate_signature::BlsPublicKey;
+use crate::crypto::keystore::{Keystore, KeystoreError, KeystoreKey};
 use crate::crypto::signature::{get_key_pair, sign, verify, PublicKey, SecretKey, Signature};
 use crate::serialize::base_format;
 use crate::types::{AccountId, PartialSignature};
@@ -200,6 +203,23 @@ impl InMemorySigner {
         InMemorySigner::from(content.as_str())
     }
 
+    /// Reads the ed25519 key stored under `name` in `keystore` into signer.
+    pub fn from_keystore(
+        account_id: String,
+        keystore: &Keystore,
+        name: &str,
+    ) -> Result<Self, KeystoreError> {
+        match keystore.get(name)? {
+            KeystoreKey::Ed25519(secret_key) => {
+                // An ed25519 secret key is its seed followed by its public key.
+                let public_key = ed25519::PublicKey::from_slice(&(secret_key.0).0[32..])
+                    .expect("ed25519 secret keys embed a valid public key");
+                Ok(Self { account_id, public_key: PublicKey(public_key), secret_key })
+            }
+            KeystoreKey::Bls(_) => Err(KeystoreError::Unsupported("signing needs an ed25519 key")),
+        }
+    }
+
     /// Initialize `InMemorySigner` with a random ED25519 and BLS keys, and random account id. Used
     /// for testing only.
     pub fn from_random() -> Self {
@@ -28,7 +28,9 @@ pub const COL_TRANSACTION_RESULT: Option<u32> = Some(6);
 pub const COL_RECEIPTS: Option<u32> = Some(7);
 pub const COL_PEERS: Option<u32> = Some(8);
 pub const COL_VALIDATORS: Option<u32> = Some(9);
-const NUM_COLS: u32 = 10;
+pub const COL_FORK_CHOICE: Option<u32> = Some(10);
+pub const COL_TRIE_CHANGES: Option<u32> = Some(11);
+const NUM_COLS: u32 = 12;
 
 pub struct Store {
     storage: Arc<dyn KeyValueDB>,
@@ -72,6 +74,24 @@ impl Store {
     ) -> Box<dyn Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
         self.storage.iter(column)
     }
+
+    /// Deletes every key of `column` from `start` up to, but not including, `end`.
+    pub fn delete_column_range(
+        &self,
+        column: Option<u32>,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<(), io::Error> {
+        let mut store_update = self.store_update();
+        for (key, _) in self
+            .iter(column)
+            .skip_while(|(key, _)| &key[..] < start)
+            .take_while(|(key, _)| &key[..] < end)
+        {
+            store_update.delete(column, &key);
+        }
+        store_update.commit()
+    }
 }
 
 /// Keeps track of current changes to the database and can commit all of them to the database.
@@ -187,3 +207,26 @@ pub fn get<T: DeserializeOwned>(state_update: &TrieUpdate, key: &[u8]) -> Option
 pub fn set<T: Serialize>(state_update: &mut TrieUpdate, key: Vec<u8>, value: &T) {
     value.encode().ok().map(|data| state_update.set(key, DBValue::from_vec(data))).or_else(|| None);
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::create_test_store;
+
+    use super::*;
+
+    #[test]
+    fn test_delete_column_range() {
+        let store = create_test_store();
+        let mut store_update = store.store_update();
+        for key in vec![vec![1u8], vec![2, 0], vec![2, 1], vec![3]] {
+            store_update.set(COL_TRIE_CHANGES, &key, b"value");
+        }
+        store_update.set(COL_BLOCK, &[2, 0], b"value");
+        store_update.commit().unwrap();
+
+        store.delete_column_range(COL_TRIE_CHANGES, &[2], &[3]).unwrap();
+        let keys: Vec<_> = store.iter(COL_TRIE_CHANGES).map(|(key, _)| key.to_vec()).collect();
+        assert_eq!(keys, vec![vec![1], vec![3]]);
+        assert!(store.exists(COL_BLOCK, &[2, 0]).unwrap());
+    }
+}
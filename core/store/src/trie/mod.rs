@@ -9,6 +9,7 @@ use cached::{Cached, SizedCache};
 pub use kvdb::DBValue;
 use kvdb::{DBOp, DBTransaction};
 use log::error;
+use serde_derive::{Deserialize, Serialize};
 
 use near_primitives::hash::{hash, CryptoHash};
 
@@ -391,6 +392,7 @@ pub struct Trie {
 /// Having old_root and values in deletions allows to apply TrieChanges in reverse
 ///
 /// StoreUpdate are the changes from current state refcount to refcount + delta.
+#[derive(Serialize, Deserialize)]
 pub struct TrieChanges {
     #[allow(dead_code)]
     old_root: CryptoHash,
@@ -421,9 +423,26 @@ impl TrieChanges {
         &self,
         trie: Arc<Trie>,
         store_update: &mut StoreUpdate,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::decrement_rc_into(&self.deletions, trie, store_update)
+    }
+
+    /// Applies insertions as deletions, discarding the new state of a fork.
+    pub fn revert_insertions_into(
+        &self,
+        trie: Arc<Trie>,
+        store_update: &mut StoreUpdate,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Self::decrement_rc_into(&self.insertions, trie, store_update)
+    }
+
+    fn decrement_rc_into(
+        changes: &[(CryptoHash, Vec<u8>, u32)],
+        trie: Arc<Trie>,
+        store_update: &mut StoreUpdate,
     ) -> Result<(), Box<dyn std::error::Error>> {
         store_update.trie = Some(trie.clone());
-        for (key, value, rc) in self.deletions.iter() {
+        for (key, value, rc) in changes.iter() {
             let storage_rc = trie.storage.retrieve_rc(&key).unwrap_or_default();
             assert!(*rc <= storage_rc);
             if *rc < storage_rc {
@@ -471,6 +490,17 @@ impl WrappedTrieChanges {
     ) -> Result<(), Box<dyn std::error::Error>> {
         self.trie_changes.deletions_into(self.trie.clone(), store_update)
     }
+
+    pub fn revert_insertions_into(
+        &self,
+        store_update: &mut StoreUpdate,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.trie_changes.revert_insertions_into(self.trie.clone(), store_update)
+    }
+
+    pub fn trie_changes(&self) -> &TrieChanges {
+        &self.trie_changes
+    }
 }
 
 enum FlattenNodesCrumb {
@@ -1054,6 +1084,43 @@ impl Trie {
         Trie::flatten_nodes(root, memory, root_node)
     }
 
+    /// Changes inserting a reference to every node of the state at `root`, which keeps the state
+    /// when the blocks after it are pruned. Reverting their insertions releases it again.
+    pub fn pin_changes(
+        &self,
+        root: &CryptoHash,
+    ) -> Result<TrieChanges, Box<dyn std::error::Error>> {
+        let mut refcounts: HashMap<CryptoHash, (Vec<u8>, u32)> = HashMap::new();
+        let mut stack = vec![*root];
+        while let Some(hash) = stack.pop() {
+            if hash == Trie::empty_root() {
+                continue;
+            }
+            let bytes = self
+                .storage
+                .retrieve_raw_bytes(&hash)
+                .ok_or_else(|| format!("Node {} not found in storage", hash))?;
+            let (node, _) = RcTrieNode::decode(&bytes)?;
+            refcounts
+                .entry(hash)
+                .or_insert_with(|| {
+                    (RcTrieNode::decode_raw(&bytes).expect("decoded above").0.to_vec(), 0)
+                })
+                .1 += 1;
+            match node {
+                RawTrieNode::Leaf(_, _) => {}
+                RawTrieNode::Branch(children, _) => {
+                    stack.extend(children.iter().filter_map(|child| *child))
+                }
+                RawTrieNode::Extension(_, child) => stack.push(child),
+            }
+        }
+        let mut insertions: Vec<_> =
+            refcounts.into_iter().map(|(hash, (value, rc))| (hash, value, rc)).collect();
+        insertions.sort();
+        Ok(TrieChanges { old_root: *root, new_root: *root, insertions, deletions: vec![] })
+    }
+
     pub fn iter<'a>(
         &'a self,
         root: &CryptoHash,
@@ -1427,6 +1494,58 @@ mod tests {
         test_populate_trie(trie, &Trie::empty_root(), changes);
     }
 
+    #[test]
+    fn test_trie_revert_insertions() {
+        let trie = create_trie();
+        let changes = vec![
+            (b"dog".to_vec(), Some(b"puppy".to_vec())),
+            (b"horse".to_vec(), Some(b"stallion".to_vec())),
+        ];
+        let root = test_populate_trie(trie.clone(), &Trie::empty_root(), changes);
+        let nodes = trie.storage.store.iter(COL_STATE).count();
+
+        let fork = trie
+            .update(&root, vec![(b"doge".to_vec(), Some(b"coin".to_vec()))].into_iter())
+            .unwrap();
+        let mut store_update = trie.storage.store.store_update();
+        fork.insertions_into(trie.clone(), &mut store_update).unwrap();
+        store_update.commit().unwrap();
+        assert_eq!(trie.get(&fork.new_root, b"doge"), Some(b"coin".to_vec()));
+
+        let mut store_update = trie.storage.store.store_update();
+        fork.revert_insertions_into(trie.clone(), &mut store_update).unwrap();
+        store_update.commit().unwrap();
+        assert_eq!(trie.storage.store.iter(COL_STATE).count(), nodes);
+        assert_eq!(trie.get(&root, b"dog"), Some(b"puppy".to_vec()));
+    }
+
+    #[test]
+    fn test_trie_pin() {
+        let trie = create_trie();
+        let changes = vec![
+            (b"dog".to_vec(), Some(b"puppy".to_vec())),
+            (b"doge".to_vec(), Some(b"coin".to_vec())),
+            (b"horse".to_vec(), Some(b"stallion".to_vec())),
+        ];
+        let root = test_populate_trie(trie.clone(), &Trie::empty_root(), changes);
+        let pin = trie.pin_changes(&root).unwrap();
+        let mut store_update = trie.storage.store.store_update();
+        pin.insertions_into(trie.clone(), &mut store_update).unwrap();
+        store_update.commit().unwrap();
+
+        // The next state discards the pinned one, which is still readable.
+        let next = test_clear_trie(trie.clone(), &root, vec![(b"doge".to_vec(), None)]);
+        assert_eq!(trie.get(&root, b"doge"), Some(b"coin".to_vec()));
+        assert_eq!(trie.get(&next, b"dog"), Some(b"puppy".to_vec()));
+
+        let mut store_update = trie.storage.store.store_update();
+        pin.revert_insertions_into(trie.clone(), &mut store_update).unwrap();
+        store_update.commit().unwrap();
+        let nodes = trie.pin_changes(&next).unwrap().insertions.len();
+        assert_eq!(trie.storage.store.iter(COL_STATE).count(), nodes);
+        assert_eq!(trie.get(&next, b"horse"), Some(b"stallion".to_vec()));
+    }
+
     #[test]
     fn test_trie_iter_seek_stop_at_extension() {
         let trie = create_trie();
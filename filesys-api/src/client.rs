@@ -24,12 +24,18 @@ use hyper::client::{Client, HttpConnector};
 use hyper_multipart::client::multipart;
 #[cfg(feature = "hyper")]
 use hyper_tls::HttpsConnector;
+#[cfg(feature = "in-process")]
+use ipfstools::{repo::Repo, RepoTypes};
+#[cfg(feature = "in-process")]
+use local::{InProcess, LocalBackend};
 use multiaddr::{AddrComponent, ToMultiaddr};
 use read::{JsonLineDecoder, LineDecoder, StreamReader};
 use request::{self, ApiRequest};
 use response::{self, Error};
 use serde::{Deserialize, Serialize};
 use serde_json;
+#[cfg(feature = "in-process")]
+use std::sync::Arc;
 use std::{
     fs,
     io::Read,
@@ -69,6 +75,8 @@ pub struct FileSysClient {
     base: Uri,
     #[cfg(feature = "hyper")]
     client: Client<HttpsConnector<HttpConnector>, hyper::Body>,
+    #[cfg(feature = "in-process")]
+    local: Option<Arc<LocalBackend>>,
 }
 
 impl Default for FileSysClient {
@@ -130,9 +138,33 @@ impl FileSysClient {
                 let connector = HttpsConnector::new(4).unwrap();
                 Client::builder().keep_alive(false).build(connector)
             },
+            #[cfg(feature = "in-process")]
+            local: None,
         })
     }
 
+    /// Creates a `FileSysClient` that serves requests directly from `repo`
+    /// instead of going through the HTTP API.
+    ///
+    /// Only add, block, cat, dag get, gateway path and repo block stats calls
+    /// are answered in-process, every other call fails with
+    /// `Error::LocalUnsupported`. Must be called within the tokio 1 runtime
+    /// that runs the repo, see `InProcess::new`.
+    ///
+    #[cfg(feature = "in-process")]
+    pub fn in_process<T: RepoTypes>(repo: Repo<T>) -> FileSysClient {
+        FileSysClient::with_backend(Arc::new(InProcess::new(repo)))
+    }
+
+    /// Creates a `FileSysClient` that serves requests from any `LocalBackend`.
+    ///
+    #[cfg(feature = "in-process")]
+    pub fn with_backend(backend: Arc<LocalBackend>) -> FileSysClient {
+        let mut client = FileSysClient::new("localhost", 5001).unwrap();
+        client.local = Some(backend);
+        client
+    }
+
     /// Builds the base url path for the Ipfs api.
     ///
     fn build_base_path(uri: &str) -> Result<Uri, InvalidUri> {
@@ -242,6 +274,13 @@ impl FileSysClient {
     where
         Req: ApiRequest + Serialize,
     {
+        #[cfg(feature = "in-process")]
+        {
+            if self.local.is_some() {
+                return Box::new(future::err(Error::LocalUnsupported(Req::PATH)));
+            }
+        }
+
         match self.build_base_request(req, form) {
             Ok(req) => {
                 #[cfg(feature = "hyper")]
@@ -285,6 +324,13 @@ impl FileSysClient {
         Res: 'static + Send,
         F: 'static + Fn(Response) -> AsyncStreamResponse<Res> + Send,
     {
+        #[cfg(feature = "in-process")]
+        {
+            if self.local.is_some() {
+                return Box::new(stream::once(Err(Error::LocalUnsupported(Req::PATH))));
+            }
+        }
+
         #[cfg(feature = "hyper")]
         match self.build_base_request(req, form) {
             Ok(req) => {
@@ -477,11 +523,48 @@ impl FileSysClient {
     where
         R: 'static + Read + Send,
     {
+        self.add_with_pin(data, true)
+    }
+
+    /// Add file to Ipfs, pinning it recursively only if `pin` is set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # extern crate filesys_api;
+    /// #
+    /// use filesys_api::FileSysClient;
+    /// use std::io::Cursor;
+    ///
+    /// # fn main() {
+    /// let client = FileSysClient::default();
+    /// let data = Cursor::new("Hello World!");
+    /// let req = client.add_with_pin(data, false);
+    /// # }
+    /// ```
+    ///
+    #[inline]
+    pub fn add_with_pin<R>(&self, data: R, pin: bool) -> AsyncResponse<response::AddResponse>
+    where
+        R: 'static + Read + Send,
+    {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                let mut data = data;
+                let mut buf = Vec::new();
+                if let Err(err) = data.read_to_end(&mut buf) {
+                    return Box::new(future::err(err.into()));
+                }
+                return local.add(buf, pin);
+            }
+        }
+
         let mut form = multipart::Form::default();
 
         form.add_reader("path", data);
 
-        self.request(&request::Add, Some(form))
+        self.request(&request::Add { pin: Some(pin) }, Some(form))
     }
 
     /// Add a path to Ipfs. Can be a file or directory.
@@ -557,7 +640,7 @@ impl FileSysClient {
         }
 
         Box::new(
-            self.request_stream_json(&request::Add, Some(form))
+            self.request_stream_json(&request::Add { pin: None }, Some(form))
                 .collect()
                 .map(|mut responses: Vec<response::AddResponse>| responses.pop().unwrap()),
         )
@@ -686,6 +769,13 @@ impl FileSysClient {
     ///
     #[inline]
     pub fn block_get(&self, hash: &str) -> AsyncStreamResponse<Bytes> {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                return Box::new(local.block_get(hash).into_stream());
+            }
+        }
+
         self.request_stream_bytes(&request::BlockGet { hash }, None)
     }
 
@@ -711,6 +801,18 @@ impl FileSysClient {
     where
         R: 'static + Read + Send,
     {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                let mut data = data;
+                let mut buf = Vec::new();
+                if let Err(err) = data.read_to_end(&mut buf) {
+                    return Box::new(future::err(err.into()));
+                }
+                return local.block_put(buf);
+            }
+        }
+
         let mut form = multipart::Form::default();
 
         form.add_reader("data", data);
@@ -735,6 +837,13 @@ impl FileSysClient {
     ///
     #[inline]
     pub fn block_rm(&self, hash: &str) -> AsyncResponse<response::BlockRmResponse> {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                return local.block_rm(hash);
+            }
+        }
+
         self.request(&request::BlockRm { hash }, None)
     }
 
@@ -755,6 +864,13 @@ impl FileSysClient {
     ///
     #[inline]
     pub fn block_stat(&self, hash: &str) -> AsyncResponse<response::BlockStatResponse> {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                return local.block_stat(hash);
+            }
+        }
+
         self.request(&request::BlockStat { hash }, None)
     }
 
@@ -838,6 +954,13 @@ impl FileSysClient {
     ///
     #[inline]
     pub fn cat(&self, path: &str) -> AsyncStreamResponse<Bytes> {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                return Box::new(local.cat(path).into_stream());
+            }
+        }
+
         self.request_stream_bytes(&request::Cat { path }, None)
     }
 
@@ -939,7 +1062,48 @@ impl FileSysClient {
     ///
     #[inline]
     pub fn dag_get(&self, path: &str) -> AsyncResponse<response::DagGetResponse> {
-        self.request(&request::DagGet { path }, None)
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                return local.dag_get(path);
+            }
+        }
+
+        self.request(
+            &request::DagGet {
+                path,
+                output_codec: None,
+            },
+            None,
+        )
+    }
+
+    /// Returns a dag node re-encoded with `output_codec` (`dag-json` or
+    /// `dag-cbor`), regardless of how it is stored.
+    ///
+    /// ```no_run
+    /// # extern crate filesys_api;
+    /// #
+    /// use filesys_api::FileSysClient;
+    ///
+    /// # fn main() {
+    /// let client = FileSysClient::default();
+    /// let req = client.dag_get_encoded(
+    ///     "QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA",
+    ///     "dag-json",
+    /// );
+    /// # }
+    /// ```
+    ///
+    #[inline]
+    pub fn dag_get_encoded(&self, path: &str, output_codec: &str) -> AsyncStreamResponse<Bytes> {
+        self.request_stream_bytes(
+            &request::DagGet {
+                path,
+                output_codec: Some(output_codec),
+            },
+            None,
+        )
     }
 
     // TODO /dag routes are experimental, and there isn't a whole lot of
@@ -1457,6 +1621,66 @@ impl FileSysClient {
         self.request_stream_bytes(&request::Get { path }, None)
     }
 
+    /// Returns the contents of the file an `/ipfs/` or `/ipns/` path resolves
+    /// to, as a gateway serves it.
+    ///
+    /// Only answered by an in-process client, the HTTP API has no such call.
+    ///
+    /// ```no_run
+    /// # extern crate filesys_api;
+    /// #
+    /// use filesys_api::FileSysClient;
+    ///
+    /// # fn main() {
+    /// let client = FileSysClient::default();
+    /// let req = client.get_path("/ipfs/QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA/index.html");
+    /// # }
+    /// ```
+    ///
+    #[inline]
+    pub fn get_path(&self, path: &str) -> AsyncResponse<Bytes> {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                return local.get_path(path);
+            }
+        }
+
+        Box::new(future::err(Error::Uncategorized(
+            "gateway paths are only served in-process".into(),
+        )))
+    }
+
+    /// Returns the cids of every block an `/ipfs/` or `/ipns/` path passes
+    /// through, the root first and the block it ends in last.
+    ///
+    /// Only answered by an in-process client, the HTTP API has no such call.
+    ///
+    /// ```no_run
+    /// # extern crate filesys_api;
+    /// #
+    /// use filesys_api::FileSysClient;
+    ///
+    /// # fn main() {
+    /// let client = FileSysClient::default();
+    /// let req = client.resolve_path("/ipfs/QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA/index.html");
+    /// # }
+    /// ```
+    ///
+    #[inline]
+    pub fn resolve_path(&self, path: &str) -> AsyncResponse<Vec<String>> {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                return local.resolve_path(path);
+            }
+        }
+
+        Box::new(future::err(Error::Uncategorized(
+            "gateway paths are only served in-process".into(),
+        )))
+    }
+
     /// Returns information about a peer.
     ///
     /// If `peer` is `None`, returns information about you.
@@ -1699,6 +1923,25 @@ impl FileSysClient {
         )
     }
 
+    /// Publish an IPFS path under the node's own IPNS name, which is derived
+    /// from its peer ID.
+    ///
+    /// ```no_run
+    /// # extern crate filesys_api;
+    /// #
+    /// use filesys_api::FileSysClient;
+    ///
+    /// # fn main() {
+    /// let client = FileSysClient::default();
+    /// let req = client.name_publish_self("/ipfs/QmVrLsEDn27sScp3k23sgZNefVTjSAL3wpgW1iWPi4MgoY");
+    /// # }
+    /// ```
+    ///
+    #[inline]
+    pub fn name_publish_self(&self, path: &str) -> AsyncResponse<response::NamePublishResponse> {
+        self.name_publish(path, false, None, None, Some("self"))
+    }
+
     /// Resolve an IPNS name.
     ///
     /// ```no_run
@@ -2166,6 +2409,35 @@ impl FileSysClient {
         self.request(&request::StatsRepo, None)
     }
 
+    /// Counts the stored blocks by codec and by multihash type.
+    ///
+    /// Only answered by an in-process client, go-ipfs has no such call.
+    ///
+    /// ```no_run
+    /// # extern crate filesys_api;
+    /// #
+    /// use filesys_api::FileSysClient;
+    ///
+    /// # fn main() {
+    /// let client = FileSysClient::default();
+    /// let req = client.repo_block_stats();
+    /// # }
+    /// ```
+    ///
+    #[inline]
+    pub fn repo_block_stats(&self) -> AsyncResponse<response::RepoBlockStatsResponse> {
+        #[cfg(feature = "in-process")]
+        {
+            if let Some(ref local) = self.local {
+                return local.repo_block_stats();
+            }
+        }
+
+        Box::new(future::err(Error::Uncategorized(
+            "repo block stats are only served in-process".into(),
+        )))
+    }
+
     // TODO /swarm/addrs/listen
 
     /// Return a list of local addresses.
@@ -149,6 +149,17 @@
 //! # }
 //! ```
 //!
+//! ### Talking to an embedded node
+//!
+//! Binaries that embed a node can skip the HTTP round-trip entirely by
+//! enabling the `in-process` feature and handing the node's repo to the
+//! client. Block and cat calls are then served directly from the repo.
+//!
+//! ```toml
+//! [dependencies]
+//! filesys-api = { version = "0.5.1", features = ["in-process"] }
+//! ```
+//!
 //! ### Additional Examples
 //!
 //! There are also a bunch of examples included in the project, which
@@ -185,6 +196,15 @@ extern crate hyper_multipart_rfc7578 as hyper_multipart;
 #[cfg(feature = "hyper")]
 extern crate hyper_tls;
 
+#[cfg(feature = "in-process")]
+extern crate base64;
+#[cfg(feature = "in-process")]
+extern crate futures03;
+#[cfg(feature = "in-process")]
+extern crate ipfstools;
+#[cfg(feature = "in-process")]
+extern crate tokio1;
+
 extern crate bytes;
 #[macro_use]
 extern crate failure;
@@ -203,10 +223,14 @@ extern crate tokio_io;
 extern crate walkdir;
 
 pub use client::FileSysClient;
+#[cfg(feature = "in-process")]
+pub use local::{InProcess, LocalBackend, LocalResponse};
 pub use request::{KeyType, Logger, LoggingLevel, ObjectTemplate};
 
 mod client;
 mod header;
+#[cfg(feature = "in-process")]
+mod local;
 mod read;
 mod request;
 pub mod response;
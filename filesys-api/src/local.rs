@@ -0,0 +1,508 @@
+// Copyright 2017 rust-filesys-api Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//
+
+//! In-process backend for `FileSysClient`.
+//!
+//! Binaries that embed a node already own its `Repo`, so there is no reason to
+//! go through the HTTP API to talk to it. A client created with
+//! `FileSysClient::in_process` answers the supported calls directly from the
+//! repo and reuses the same response types as the HTTP client.
+//!
+//! The repo's futures need a tokio 1 runtime, while the client's futures can
+//! be polled by any executor. They are spawned on the runtime the backend was
+//! created in and awaited through the futures 0.3 compat layer.
+//!
+
+use bytes::Bytes;
+use futures::{future, Future};
+use futures03::future::{Either, FutureExt, TryFutureExt};
+use ipfstools::cid_profile;
+use ipfstools::ipld::IpldDag;
+use ipfstools::ipns::{Ipns, IpnsKey};
+use ipfstools::repo::{BlockCount, Repo};
+use ipfstools::unixfs::{self, build_file, File};
+use ipfstools::{AddOptions, Block, Cid, Context, IpfsPath, Ipld, RepoTypes};
+use response::{self, Error};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio1::runtime::Handle;
+
+/// Time after which an in-process request gives up, same as for the HTTP API.
+///
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Returns early from a `LocalBackend` method with a failed future.
+///
+macro_rules! try_local {
+    ($e: expr) => {
+        match $e {
+            Ok(value) => value,
+            Err(err) => return Box::new(future::err(err)),
+        }
+    };
+}
+
+/// A future returned by a `LocalBackend`.
+///
+pub type LocalResponse<T> = Box<Future<Item = T, Error = Error> + Send + 'static>;
+
+/// Operations that can be served without going through the HTTP API.
+///
+pub trait LocalBackend: Send + Sync {
+    /// Adds `data` as a UnixFS file, pinning it recursively if `pin` is set.
+    ///
+    fn add(&self, data: Vec<u8>, pin: bool) -> LocalResponse<response::AddResponse>;
+
+    /// Returns the raw bytes of a block.
+    ///
+    fn block_get(&self, hash: &str) -> LocalResponse<Bytes>;
+
+    /// Stores `data` as a block.
+    ///
+    fn block_put(&self, data: Vec<u8>) -> LocalResponse<response::BlockPutResponse>;
+
+    /// Removes a block.
+    ///
+    fn block_rm(&self, hash: &str) -> LocalResponse<response::BlockRmResponse>;
+
+    /// Returns the size of a block.
+    ///
+    fn block_stat(&self, hash: &str) -> LocalResponse<response::BlockStatResponse>;
+
+    /// Returns the contents of a UnixFS file.
+    ///
+    fn cat(&self, path: &str) -> LocalResponse<Bytes>;
+
+    /// Returns the dag-pb node a path resolves to, with its data base64
+    /// encoded like go-ipfs.
+    ///
+    fn dag_get(&self, path: &str) -> LocalResponse<response::DagGetResponse>;
+
+    /// Resolves an `/ipfs/` or `/ipns/` path and returns the contents of the
+    /// file it ends in, or the raw bytes of the block if it is no file.
+    ///
+    fn get_path(&self, path: &str) -> LocalResponse<Bytes>;
+
+    /// Resolves an `/ipfs/` or `/ipns/` path and returns the cids of every
+    /// block it passes through, the root first and the block it ends in last.
+    ///
+    fn resolve_path(&self, path: &str) -> LocalResponse<Vec<String>>;
+
+    /// Counts the stored blocks by codec and by multihash type.
+    ///
+    fn repo_block_stats(&self) -> LocalResponse<response::RepoBlockStatsResponse>;
+}
+
+/// A `LocalBackend` serving requests from an ipfstools `Repo`.
+///
+pub struct InProcess<Types: RepoTypes> {
+    repo: Repo<Types>,
+    dag: IpldDag<Types>,
+    /// Only resolves names, so the key it would publish with is throwaway.
+    ///
+    ipns: Ipns<Types>,
+    timeout: Duration,
+    /// Runs the repo's futures.
+    ///
+    runtime: Handle,
+}
+
+impl<Types: RepoTypes> InProcess<Types> {
+    /// Serves requests from `repo`, running them on the current tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// When called outside of a tokio 1 runtime.
+    ///
+    pub fn new(repo: Repo<Types>) -> Self {
+        let dag = IpldDag::new(repo.clone());
+        let ipns = Ipns::from_repo(&repo, IpnsKey::generate());
+
+        InProcess {
+            repo,
+            dag,
+            ipns,
+            timeout: DEFAULT_TIMEOUT,
+            runtime: Handle::current(),
+        }
+    }
+
+    /// Sets the time after which a request stops waiting for missing blocks.
+    ///
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn context(&self) -> Context {
+        Context::with_timeout(self.timeout)
+    }
+
+    /// Spawns a repo future on the runtime and converts it into a futures 0.1
+    /// future usable by the client.
+    ///
+    fn compat<T, F>(&self, fut: F) -> LocalResponse<T>
+    where
+        T: 'static + Send,
+        F: 'static + Send + ::std::future::Future<Output = Result<T, ipfstools::Error>>,
+    {
+        let spawned = self.runtime.spawn(fut).map(|res| match res {
+            Ok(res) => res.map_err(|e| Error::Local(e.to_string())),
+            Err(e) => Err(Error::Local(e.to_string())),
+        });
+
+        Box::new(spawned.boxed().compat())
+    }
+}
+
+impl<Types: RepoTypes> LocalBackend for InProcess<Types> {
+    fn add(&self, data: Vec<u8>, pin: bool) -> LocalResponse<response::AddResponse> {
+        let (root, blocks) = build_file(&data, &*AddOptions::default().chunker);
+        // the cumulative size of the dag, like go-ipfs reports it.
+        let size: usize = blocks.iter().map(Block::size).sum();
+        let put = if pin {
+            Either::Left(self.repo.put_blocks_pinned(blocks, &root))
+        } else {
+            Either::Right(self.repo.put_blocks(blocks))
+        };
+        let put = put.map_ok(move |_| response::AddResponse {
+            name: cid_profile::display(&root),
+            hash: cid_profile::display(&root),
+            size: size.to_string(),
+        });
+
+        self.compat(put)
+    }
+
+    fn block_get(&self, hash: &str) -> LocalResponse<Bytes> {
+        let cid = try_local!(parse_cid(hash));
+        let get = self
+            .repo
+            .get_block(&cid, self.context())
+            .map_ok(|block| Bytes::from(block.data().to_owned()));
+
+        self.compat(get)
+    }
+
+    fn block_put(&self, data: Vec<u8>) -> LocalResponse<response::BlockPutResponse> {
+        let block = Block::from(data);
+        let size = block.size() as u64;
+        let put = self
+            .repo
+            .put_block(block)
+            .map_ok(move |cid| response::BlockPutResponse {
+                key: cid_profile::display(&cid),
+                size,
+            });
+
+        self.compat(put)
+    }
+
+    fn block_rm(&self, hash: &str) -> LocalResponse<response::BlockRmResponse> {
+        let cid = try_local!(parse_cid(hash));
+        let rm = self
+            .repo
+            .remove_block(&cid)
+            .map_ok(move |()| response::BlockRmResponse {
+                hash: cid_profile::display(&cid),
+                error: None,
+            });
+
+        self.compat(rm)
+    }
+
+    fn block_stat(&self, hash: &str) -> LocalResponse<response::BlockStatResponse> {
+        let cid = try_local!(parse_cid(hash));
+        let stat = self
+            .repo
+            .get_block(&cid, self.context())
+            .map_ok(|block| response::BlockStatResponse {
+                key: cid_profile::display(block.cid()),
+                size: block.size() as u64,
+            });
+
+        self.compat(stat)
+    }
+
+    fn cat(&self, path: &str) -> LocalResponse<Bytes> {
+        let path = try_local!(parse_path(path));
+        let cat = File::get_unixfs_v1(&self.dag, path, self.context())
+            .map_ok(|file| Bytes::from(file.data().to_owned()));
+
+        self.compat(cat)
+    }
+
+    fn dag_get(&self, path: &str) -> LocalResponse<response::DagGetResponse> {
+        let path = try_local!(parse_path(path));
+        let dag = self.dag.clone();
+        let ctx = self.context();
+        let get = self
+            .ipns
+            .resolve(&path, ctx)
+            .and_then(move |path| dag.get(path, ctx))
+            .map(|res| res.and_then(dag_get_response));
+
+        self.compat(get)
+    }
+
+    fn get_path(&self, path: &str) -> LocalResponse<Bytes> {
+        let path = try_local!(parse_path(path));
+        let repo = self.repo.clone();
+        let ctx = self.context();
+        let get = self
+            .ipns
+            .resolve(&path, ctx)
+            .and_then(move |path| unixfs::read(repo, path, ctx))
+            .map_ok(Bytes::from);
+
+        self.compat(get)
+    }
+
+    fn resolve_path(&self, path: &str) -> LocalResponse<Vec<String>> {
+        let path = try_local!(parse_path(path));
+        let dag = self.dag.clone();
+        let ctx = self.context();
+        let resolve = self
+            .ipns
+            .resolve(&path, ctx)
+            .and_then(move |path| dag.resolve_cids(path, ctx))
+            .map_ok(|cids| cids.iter().map(cid_profile::display).collect());
+
+        self.compat(resolve)
+    }
+
+    fn repo_block_stats(&self) -> LocalResponse<response::RepoBlockStatsResponse> {
+        let stats = self
+            .repo
+            .stats()
+            .map_ok(|stats| response::RepoBlockStatsResponse {
+                blocks: stats.total.blocks,
+                bytes: stats.total.bytes,
+                codecs: block_counts(stats.codecs),
+                hashes: block_counts(stats.hashes),
+            });
+
+        self.compat(stats)
+    }
+}
+
+fn block_counts(counts: BTreeMap<String, BlockCount>) -> Vec<response::RepoBlockCount> {
+    counts
+        .into_iter()
+        .map(|(name, count)| response::RepoBlockCount {
+            name,
+            blocks: count.blocks,
+            bytes: count.bytes,
+        })
+        .collect()
+}
+
+/// Converts a resolved dag-pb node into the response of `dag get`.
+///
+fn dag_get_response(node: Ipld) -> Result<response::DagGetResponse, ipfstools::Error> {
+    let mut node = match node {
+        Ipld::Object(node) => node,
+        _ => bail!("not a dag-pb node"),
+    };
+    let data = match node.remove("Data") {
+        Some(Ipld::Bytes(data)) => Some(base64::encode(&data)),
+        _ => None,
+    };
+    let links = match node.remove("Links") {
+        Some(Ipld::Array(links)) => links,
+        _ => bail!("not a dag-pb node"),
+    };
+    let links = links
+        .into_iter()
+        .map(dag_get_link)
+        .collect::<Result<_, _>>()?;
+
+    Ok(response::DagGetResponse { data, links })
+}
+
+fn dag_get_link(link: Ipld) -> Result<response::DagIpfsHeader, ipfstools::Error> {
+    let mut link = match link {
+        Ipld::Object(link) => link,
+        _ => bail!("invalid dag-pb link"),
+    };
+    let cid = match link.remove("Hash") {
+        Some(Ipld::Link(root)) => root.cid().map(cid_profile::display),
+        _ => None,
+    };
+    let cid = match cid {
+        Some(cid) => cid,
+        None => bail!("invalid dag-pb link"),
+    };
+    let name = match link.remove("Name") {
+        Some(Ipld::String(name)) => name,
+        _ => String::new(),
+    };
+    let size = match link.remove("Tsize") {
+        Some(Ipld::U64(size)) => size,
+        _ => 0,
+    };
+    let mut cids = HashMap::new();
+    cids.insert("/".to_string(), cid);
+
+    Ok(response::DagIpfsHeader {
+        name,
+        size,
+        cid: cids,
+    })
+}
+
+fn parse_cid(hash: &str) -> Result<Cid, Error> {
+    Cid::from(hash).map_err(|e| Error::Local(e.to_string()))
+}
+
+/// Accepts both `/ipfs/<cid>/...` paths and bare CIDs, like the HTTP API.
+///
+fn parse_path(path: &str) -> Result<IpfsPath, Error> {
+    if path.starts_with('/') {
+        IpfsPath::from_str(path).map_err(|e| Error::Local(e.to_string()))
+    } else {
+        parse_cid(path).map(IpfsPath::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ipfstools::repo::{create_repo, RepoOptions};
+    use ipfstools::{IpfsOptions, TestTypes};
+    use tokio1::runtime::Runtime;
+
+    /// The returned backend runs the repo's futures on `runtime`, so its own
+    /// futures can be waited on from the test thread.
+    ///
+    fn in_process(runtime: &Runtime) -> InProcess<TestTypes> {
+        let _guard = runtime.enter();
+        let options = IpfsOptions::<TestTypes>::default();
+        let (repo, _) = create_repo(RepoOptions::from(&options));
+
+        InProcess::new(repo)
+    }
+
+    #[test]
+    fn test_block_put_get() {
+        let runtime = Runtime::new().unwrap();
+        let local = in_process(&runtime);
+
+        let put = local.block_put(b"hello block\n".to_vec()).wait().unwrap();
+        assert_eq!(put.key, "QmVNrZhKw9JwYa4YPEZVccQxfgQJq993yP78QEN28927vq");
+        assert_eq!(put.size, 12);
+
+        let data = local.block_get(&put.key).wait().unwrap();
+        assert_eq!(&data[..], b"hello block\n");
+
+        let stat = local.block_stat(&put.key).wait().unwrap();
+        assert_eq!(stat.size, 12);
+    }
+
+    #[test]
+    fn test_add() {
+        let runtime = Runtime::new().unwrap();
+        let local = in_process(&runtime);
+
+        let add = local.add(b"hello file\n".to_vec(), true).wait().unwrap();
+        assert_eq!(add.name, add.hash);
+
+        // a single chunk is stored as the root node alone.
+        let stat = local.block_stat(&add.hash).wait().unwrap();
+        assert_eq!(add.size, stat.size.to_string());
+    }
+
+    #[test]
+    fn test_add_pin() {
+        let runtime = Runtime::new().unwrap();
+        let local = in_process(&runtime);
+
+        let pinned = local.add(b"pinned file\n".to_vec(), true).wait().unwrap();
+        let unpinned = local
+            .add(b"unpinned file\n".to_vec(), false)
+            .wait()
+            .unwrap();
+
+        let report = runtime.block_on(local.repo.gc()).unwrap();
+        let freed: Vec<_> = report.freed.iter().map(cid_profile::display).collect();
+        assert_eq!(freed, vec![unpinned.hash]);
+
+        let data = local.cat(&pinned.hash).wait().unwrap();
+        assert_eq!(&data[..], b"pinned file\n");
+    }
+
+    #[test]
+    fn test_dag_get() {
+        let runtime = Runtime::new().unwrap();
+        let local = in_process(&runtime);
+
+        let add = local.add(b"hello file\n".to_vec(), true).wait().unwrap();
+        let node = local.dag_get(&add.hash).wait().unwrap();
+        let data = base64::decode(&node.data.unwrap()).unwrap();
+        // the unixfs header around the file's contents.
+        assert!(data.windows(11).any(|window| window == b"hello file\n"));
+        assert!(node.links.is_empty());
+
+        assert!(local
+            .dag_get(&format!("/ipfs/{}/missing", add.hash))
+            .wait()
+            .is_err());
+    }
+
+    #[test]
+    fn test_get_path() {
+        let runtime = Runtime::new().unwrap();
+        let local = in_process(&runtime);
+
+        let add = local.add(b"hello file\n".to_vec(), true).wait().unwrap();
+        let data = local.get_path(&format!("/ipfs/{}", add.hash)).wait().unwrap();
+        assert_eq!(&data[..], b"hello file\n");
+
+        assert!(local.get_path(&format!("/ipfs/{}/missing", add.hash)).wait().is_err());
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let runtime = Runtime::new().unwrap();
+        let local = in_process(&runtime);
+
+        let add = local.add(b"hello file\n".to_vec(), true).wait().unwrap();
+        let cids = local
+            .resolve_path(&format!("/ipfs/{}", add.hash))
+            .wait()
+            .unwrap();
+        assert_eq!(cids, vec![add.hash.clone()]);
+
+        assert!(local
+            .resolve_path(&format!("/ipfs/{}/missing", add.hash))
+            .wait()
+            .is_err());
+    }
+
+    #[test]
+    fn test_repo_block_stats() {
+        let runtime = Runtime::new().unwrap();
+        let local = in_process(&runtime);
+
+        local.block_put(b"hello block\n".to_vec()).wait().unwrap();
+
+        let stats = local.repo_block_stats().wait().unwrap();
+        assert_eq!((stats.blocks, stats.bytes), (1, 12));
+        assert_eq!(stats.codecs.len(), 1);
+        assert_eq!(stats.codecs[0].name, "dag-pb");
+        assert_eq!(stats.hashes[0].name, "sha2-256");
+    }
+
+    #[test]
+    fn test_invalid_cid() {
+        let runtime = Runtime::new().unwrap();
+        let local = in_process(&runtime);
+
+        assert!(local.block_get("foobar").wait().is_err());
+    }
+}
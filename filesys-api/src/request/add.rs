@@ -9,9 +9,10 @@
 use http::Method;
 use request::ApiRequest;
 
-pub struct Add;
-
-impl_skip_serialize!(Add);
+#[derive(Serialize)]
+pub struct Add {
+    pub pin: Option<bool>,
+}
 
 impl ApiRequest for Add {
     const PATH: &'static str = "/add";
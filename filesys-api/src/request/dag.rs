@@ -13,6 +13,9 @@ use request::ApiRequest;
 pub struct DagGet<'a> {
     #[serde(rename = "arg")]
     pub path: &'a str,
+
+    #[serde(rename = "output-codec")]
+    pub output_codec: Option<&'a str>,
 }
 
 impl<'a> ApiRequest for DagGet<'a> {
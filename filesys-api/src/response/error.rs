@@ -74,6 +74,16 @@ pub enum Error {
 
     #[fail(display = "api returned unknwon error '{}'", _0)]
     Uncategorized(String),
+
+    /// An error returned by the in-process backend.
+    #[cfg(feature = "in-process")]
+    #[fail(display = "in-process backend error '{}'", _0)]
+    Local(String),
+
+    /// The in-process backend does not serve this API path.
+    #[cfg(feature = "in-process")]
+    #[fail(display = "'{}' is not supported by the in-process backend", _0)]
+    LocalUnsupported(&'static str),
 }
 
 #[cfg(feature = "hyper")]
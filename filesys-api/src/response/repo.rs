@@ -9,6 +9,29 @@
 use response::serde;
 use std::collections::HashMap;
 
+/// Number and total size of the blocks of one codec or multihash type.
+///
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RepoBlockCount {
+    pub name: String,
+    pub blocks: u64,
+    pub bytes: u64,
+}
+
+/// The stored blocks counted by codec and by multihash type.
+///
+/// Only answered by an in-process client, go-ipfs has no such call.
+///
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RepoBlockStatsResponse {
+    pub blocks: u64,
+    pub bytes: u64,
+    pub codecs: Vec<RepoBlockCount>,
+    pub hashes: Vec<RepoBlockCount>,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct RepoFsckResponse {
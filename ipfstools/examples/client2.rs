@@ -1,23 +1,24 @@
-#![feature(async_await, await_macro, futures_api)]
-use ipfs::{Ipfs, IpfsOptions, IpfsPath, TestTypes};
+use ipfs::{Context, Ipfs, IpfsOptions, IpfsPath, TestTypes};
 use futures::join;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let options = IpfsOptions::<TestTypes>::default();
     env_logger::Builder::new().parse_filters(&options.ipfs_log).init();
     let mut ipfs = Ipfs::new(options);
     let path = IpfsPath::from_str("/ipfs/zdpuB1caPcm4QNXeegatVfLQ839Lmprd5zosXGwRUBJHwj66X").unwrap();
 
-    tokio::run_async(async move {
-        let fut = ipfs.start_daemon().unwrap();
-        tokio::spawn_async(fut);
+    let fut = ipfs.start_daemon().unwrap();
+    let daemon = tokio::spawn(fut);
 
-        let f1 = ipfs.get_dag(path.sub_path("0").unwrap());
-        let f2 = ipfs.get_dag(path.sub_path("1").unwrap());
-        let (res1, res2) = join!(f1, f2);
-        println!("Received block with contents: {:?}", res1.unwrap());
-        println!("Received block with contents: {:?}", res2.unwrap());
+    let f1 = ipfs.get_dag(path.sub_path("0").unwrap(), Context::default());
+    let f2 = ipfs.get_dag(path.sub_path("1").unwrap(), Context::default());
+    let (res1, res2) = join!(f1, f2);
+    println!("Received block with contents: {:?}", res1.unwrap());
+    println!("Received block with contents: {:?}", res2.unwrap());
 
-        ipfs.exit_daemon();
-    });
+    ipfs.exit_daemon();
+
+    // Keep running until the daemon exits.
+    daemon.await.unwrap();
 }
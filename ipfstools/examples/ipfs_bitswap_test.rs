@@ -1,34 +1,35 @@
-#![feature(async_await, await_macro, futures_api)]
-use ipfs::{Block, Ipfs, IpfsOptions, TestTypes};
+use ipfs::{Block, Context, Ipfs, IpfsOptions, TestTypes};
 use std::convert::TryInto;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let options = IpfsOptions::<TestTypes>::default();
     env_logger::Builder::new().parse_filters(&options.ipfs_log).init();
     let mut ipfs = Ipfs::new(options);
 
-    tokio::run_async(async move {
-        // Start daemon and initialize repo
-        let fut = ipfs.start_daemon().unwrap();
-        tokio::spawn_async(fut);
-        await!(ipfs.init_repo()).unwrap();
-        await!(ipfs.open_repo()).unwrap();
+    // Start daemon and initialize repo
+    let fut = ipfs.start_daemon().unwrap();
+    let daemon = tokio::spawn(fut);
+    ipfs.init_repo().await.unwrap();
+    ipfs.open_repo().await.unwrap();
 
-        // Create a Block
-        await!(ipfs.put_block(Block::from("block-provide"))).unwrap();
+    // Create a Block
+    ipfs.put_block(Block::from("block-provide")).await.unwrap();
 
-        // Retrive a Block
-        let block = await!(ipfs.get_block(Block::from("block-want\n").cid())).unwrap();
-        let contents: String = block.into();
-        println!("block contents: {:?}", contents);
+    // Retrive a Block
+    let block = ipfs.get_block(Block::from("block-want\n").cid(), Context::default()).await.unwrap();
+    let contents: String = block.into();
+    println!("block contents: {:?}", contents);
 
-        // Add a file
-        await!(ipfs.add("./examples/block.data".into())).unwrap();
+    // Add a file
+    ipfs.add("./examples/block.data".into()).await.unwrap();
 
-        // Get a file
-        let path = "/QmSy5pnHk1EnvE5dmJSyFKG5unXLGjPpBuJJCBQkBTvBaW".try_into().unwrap();
-        let file = await!(ipfs.get(path)).unwrap();
-        let contents: String = file.into();
-        println!("file contents: {:?}", contents);
-    });
+    // Get a file
+    let path = "/QmSy5pnHk1EnvE5dmJSyFKG5unXLGjPpBuJJCBQkBTvBaW".try_into().unwrap();
+    let file = ipfs.get(path, Context::default()).await.unwrap();
+    let contents: String = file.into();
+    println!("file contents: {:?}", contents);
+
+    // Keep running until the daemon exits.
+    daemon.await.unwrap();
 }
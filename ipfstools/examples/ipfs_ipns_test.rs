@@ -1,35 +1,36 @@
-#![feature(async_await, await_macro, futures_api)]
-use ipfs::{Ipfs, IpfsOptions, IpfsPath, PeerId, TestTypes};
+use ipfs::{Context, Ipfs, IpfsOptions, IpfsPath, PeerId, TestTypes};
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let options = IpfsOptions::<TestTypes>::default();
     env_logger::Builder::new().parse_filters(&options.ipfs_log).init();
     let mut ipfs = Ipfs::new(options);
 
-    tokio::run_async(async move {
-        // Start daemon and initialize repo
-        let fut = ipfs.start_daemon().unwrap();
-        tokio::spawn_async(fut);
-        await!(ipfs.init_repo()).unwrap();
-        await!(ipfs.open_repo()).unwrap();
+    // Start daemon and initialize repo
+    let fut = ipfs.start_daemon().unwrap();
+    let daemon = tokio::spawn(fut);
+    ipfs.init_repo().await.unwrap();
+    ipfs.open_repo().await.unwrap();
 
-        // Create a Block
-        let ipfs_path = await!(ipfs.put_dag("block v0".into())).unwrap();
-        // Publish a Block
-        let ipns_path = await!(ipfs.publish_ipns(&PeerId::random(), &ipfs_path)).unwrap();
+    // Create a Block
+    let ipfs_path = ipfs.put_dag("block v0".into()).await.unwrap();
+    // Publish a Block
+    let ipns_path = ipfs.publish_ipns(&PeerId::random(), &ipfs_path).await.unwrap();
 
-        // Resolve a Block
-        let new_ipfs_path = await!(ipfs.resolve_ipns(&ipns_path)).unwrap();
-        assert_eq!(ipfs_path, new_ipfs_path);
+    // Resolve a Block
+    let new_ipfs_path = ipfs.resolve_ipns(&ipns_path, Context::default()).await.unwrap();
+    assert_eq!(ipfs_path, new_ipfs_path);
 
-        // Resolve dnslink
-        let ipfs_path = IpfsPath::from_str("/ipns/ipfs.io").unwrap();
-        println!("Resolving {:?}", ipfs_path.to_string());
-        let ipfs_path = await!(ipfs.resolve_ipns(&ipfs_path)).unwrap();
-        println!("Resolved stage 1: {:?}", ipfs_path.to_string());
-        let ipfs_path = await!(ipfs.resolve_ipns(&ipfs_path)).unwrap();
-        println!("Resolved stage 2: {:?}", ipfs_path.to_string());
+    // Resolve dnslink
+    let ipfs_path = IpfsPath::from_str("/ipns/ipfs.io").unwrap();
+    println!("Resolving {:?}", ipfs_path.to_string());
+    let ipfs_path = ipfs.resolve_ipns(&ipfs_path, Context::default()).await.unwrap();
+    println!("Resolved stage 1: {:?}", ipfs_path.to_string());
+    let ipfs_path = ipfs.resolve_ipns(&ipfs_path, Context::default()).await.unwrap();
+    println!("Resolved stage 2: {:?}", ipfs_path.to_string());
 
-        ipfs.exit_daemon();
-    });
+    ipfs.exit_daemon();
+
+    // Keep running until the daemon exits.
+    daemon.await.unwrap();
 }
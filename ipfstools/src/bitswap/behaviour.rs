@@ -11,6 +11,7 @@ use crate::bitswap::strategy::{Strategy, StrategyEvent};
 use crate::block::{Block, Cid};
 use crate::p2p::SwarmTypes;
 use fnv::FnvHashSet;
+use futures01::Async;
 use libp2p::core::swarm::{
     ConnectedPoint, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
 };
@@ -18,7 +19,7 @@ use libp2p::core::protocols_handler::{OneShotHandler, ProtocolsHandler};
 use libp2p::{Multiaddr, PeerId};
 use std::collections::{HashMap, VecDeque};
 use std::marker::PhantomData;
-use tokio::prelude::*;
+use tokio_io::{AsyncRead, AsyncWrite};
 
 /// Network behaviour that handles sending and receiving IPFS blocks.
 pub struct Bitswap<TSubstream, TSwarmTypes: SwarmTypes> {
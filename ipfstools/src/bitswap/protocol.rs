@@ -9,7 +9,7 @@ use crate::error::Error;
 use libp2p::core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, upgrade};
 use protobuf::ProtobufError;
 use std::{io, iter};
-use tokio::prelude::*;
+use tokio_io::{AsyncRead, AsyncWrite};
 
 // Undocumented, but according to JS we our messages have a max size of 512*1024
 // https://github.com/ipfs/js-ipfs-bitswap/blob/d8f80408aadab94c962f6b88f343eb9f39fa0fcc/src/decision-engine/index.js#L16
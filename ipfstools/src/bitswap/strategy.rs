@@ -1,5 +1,7 @@
 use crate::block::{Block, Cid};
 use crate::bitswap::Priority;
+use crate::cid_profile;
+use crate::context::Context;
 use crate::repo::{Repo, RepoTypes};
 use libp2p::PeerId;
 use std::sync::mpsc::{channel, Sender, Receiver};
@@ -38,25 +40,27 @@ impl<TRepoTypes: RepoTypes> Strategy<TRepoTypes> for AltruisticStrategy<TRepoTyp
         priority: Priority,
     ) {
         info!("Peer {} wants block {} with priority {}",
-              source.to_base58(), cid.to_string(), priority);
+              source.to_base58(), cid_profile::display(&cid), priority);
         let events = self.events.0.clone();
-        let future = self.repo.get_block(&cid);
-        tokio::spawn_async(async move {
-            let block = await!(future).unwrap();
-            events.send(StrategyEvent::Send {
-                peer_id: source,
-                block: block,
-            }).unwrap();
+        // only serve blocks we already have, don't go looking for them on behalf of the peer.
+        let future = self.repo.get_block(&cid, Context::default().want_network(false));
+        tokio::spawn(async move {
+            if let Ok(block) = future.await {
+                events.send(StrategyEvent::Send {
+                    peer_id: source,
+                    block: block,
+                }).unwrap();
+            }
         });
     }
 
     fn process_block(&mut self, source: PeerId, block: Block) {
         info!("Received block {} from peer {}",
-              block.cid().to_string(),
+              cid_profile::display(block.cid()),
               source.to_base58());
         let future = self.repo.put_block(block);
-        tokio::spawn_async(async move {
-            await!(future).unwrap();
+        tokio::spawn(async move {
+            future.await.unwrap();
         });
     }
 
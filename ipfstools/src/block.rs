@@ -40,20 +40,25 @@ impl Block {
     }
 }
 
-impl From<&str> for Block {
-    fn from(content: &str) -> Block {
+impl From<Vec<u8>> for Block {
+    fn from(data: Vec<u8>) -> Block {
         let prefix = cid::Prefix {
             version: cid::Version::V0,
             codec: cid::Codec::DagProtobuf,
             mh_type: multihash::Hash::SHA2256,
             mh_len: 32,
         };
-        let data = content.as_bytes().to_vec();
         let cid = cid::Cid::new_from_prefix(&prefix, &data);
         Block::new(data, cid)
     }
 }
 
+impl From<&str> for Block {
+    fn from(content: &str) -> Block {
+        Block::from(content.as_bytes().to_vec())
+    }
+}
+
 impl Into<String> for Block {
     fn into(self) -> String {
         String::from_utf8_lossy(self.data()).to_string()
@@ -0,0 +1,393 @@
+//! Content addressable archives (CAR v1).
+//!
+//! An archive starts with a dag-cbor header listing its roots, followed by the blocks of the dags
+//! below them, each prefixed with its cid. `export` writes every block once, after a block
+//! linking to it, while `CarReader` accepts the blocks in any order, as go-ipfs writes them: each
+//! block only has to hash to its cid. Archives are streamed, neither `export` nor `import` holds a
+//! whole archive in memory.
+use crate::block::{Block, Cid};
+use crate::cid_profile;
+use crate::context::Context;
+use crate::error::Error;
+use crate::ipld::links::block_links;
+use crate::ipld::formats::cbor as dag_cbor;
+use crate::ipld::Ipld;
+use crate::repo::{Repo, RepoTypes};
+use cid::Codec;
+use core::future::Future;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::mem;
+
+/// Number of blocks `import` stores at once.
+const IMPORT_BATCH: usize = 256;
+
+/// Writes the dags below `roots` as an archive to `writer`, down to `max_depth` links away from the
+/// roots (unlimited if `None`), and returns the writer. Blocks are written as they are fetched and
+/// blocks shared by several dags are only written once. Links of blocks of codecs that cannot be
+/// scanned for links are not followed.
+pub fn export<Types: RepoTypes, W: Write>(
+    repo: Repo<Types>,
+    roots: Vec<Cid>,
+    max_depth: Option<usize>,
+    mut writer: W,
+    ctx: Context,
+) -> impl Future<Output=Result<W, Error>>
+{
+    async move {
+        write_header(&roots, &mut writer)?;
+
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<_> = roots.into_iter().map(|cid| (cid, 0)).collect();
+        while let Some((cid, depth)) = queue.pop_front() {
+            if !seen.insert(cid.clone()) {
+                continue;
+            }
+            ctx.check()?;
+            let block = repo.get_block(&cid, ctx).await?;
+            if max_depth.map_or(true, |max_depth| depth < max_depth) {
+                queue.extend(car_links(&block)?.into_iter().map(|cid| (cid, depth + 1)));
+            }
+            write_section(&block.cid().to_bytes(), block.data(), &mut writer)?;
+        }
+        Ok(writer)
+    }
+}
+
+/// Stores the blocks of the archive read from `reader` in `repo` and returns its roots.
+///
+/// Blocks are verified as they are read and stored `IMPORT_BATCH` at a time, so an archive does
+/// not have to fit in memory. Fails on the first invalid block; the batches stored before it are
+/// kept.
+pub fn import<Types: RepoTypes, R: Read>(repo: Repo<Types>, reader: R) ->
+impl Future<Output=Result<Vec<Cid>, Error>>
+{
+    async move {
+        let mut car = CarReader::new(reader)?;
+        let mut batch = Vec::new();
+        while let Some(block) = car.next_block()? {
+            batch.push(block);
+            if batch.len() == IMPORT_BATCH {
+                repo.put_blocks(mem::replace(&mut batch, Vec::new())).await?;
+            }
+        }
+        repo.put_blocks(batch).await?;
+        Ok(car.roots)
+    }
+}
+
+/// Reads and verifies the archive `car`, returning its roots and blocks in archive order.
+pub fn read(car: &[u8]) -> Result<(Vec<Cid>, Vec<Block>), Error> {
+    let mut car = CarReader::new(car)?;
+    let mut blocks = Vec::new();
+    while let Some(block) = car.next_block()? {
+        blocks.push(block);
+    }
+    Ok((car.roots, blocks))
+}
+
+/// Reads an archive one block at a time, checking each block before returning it.
+pub struct CarReader<R: Read> {
+    reader: R,
+    roots: Vec<Cid>,
+}
+
+impl<R: Read> CarReader<R> {
+    /// Reads the header of the archive.
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let header = match read_section(&mut reader)? {
+            Some(header) => header,
+            None => bail!("empty car"),
+        };
+        let roots = read_header(&header)?;
+        Ok(CarReader { reader, roots })
+    }
+
+    /// The roots listed in the header.
+    pub fn roots(&self) -> &[Cid] {
+        &self.roots
+    }
+
+    /// Returns the next block, or `None` at the end of the archive. Fails if the block does not
+    /// hash to its cid.
+    pub fn next_block(&mut self) -> Result<Option<Block>, Error> {
+        let section = match read_section(&mut self.reader)? {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+
+        let (cid, data) = section.split_at(cid_len(&section)?);
+        let cid = Cid::from(cid)?;
+        if Cid::new_from_prefix(&cid.prefix(), data) != cid {
+            bail!("block {} does not match its hash", cid_profile::display(&cid));
+        }
+        Ok(Some(Block::new(data.to_vec(), cid)))
+    }
+}
+
+/// Links of `block`, none for codecs whose links cannot be found.
+fn car_links(block: &Block) -> Result<Vec<Cid>, Error> {
+    match block.cid().prefix().codec {
+        Codec::Raw | Codec::DagProtobuf | Codec::DagCBOR | Codec::DagJSON => block_links(block),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn write_header<W: Write>(roots: &[Cid], writer: &mut W) -> Result<(), Error> {
+    let mut header = HashMap::new();
+    header.insert("roots", Ipld::Array(roots.iter().map(|cid| Ipld::Link(cid.to_owned().into())).collect()));
+    header.insert("version", Ipld::U64(1));
+    let header = dag_cbor::encode(&header.into())?;
+    write_section(&header, &[], writer)
+}
+
+fn read_header(header: &[u8]) -> Result<Vec<Cid>, Error> {
+    let mut header = match dag_cbor::decode(header)? {
+        Ipld::Object(header) => header,
+        _ => bail!("car header is not a map"),
+    };
+    match header.remove("version") {
+        Some(Ipld::U64(1)) => {}
+        version => bail!("unsupported car version {:?}", version),
+    }
+    match header.remove("roots") {
+        Some(Ipld::Array(roots)) => roots
+            .into_iter()
+            .map(|root| match root {
+                Ipld::Link(root) => match root.cid() {
+                    Some(cid) => Ok(cid.to_owned()),
+                    None => bail!("car root is not a cid"),
+                },
+                _ => bail!("car root is not a link"),
+            })
+            .collect(),
+        _ => bail!("car header has no roots"),
+    }
+}
+
+/// Writes `a` and `b` prefixed with their total length.
+fn write_section<W: Write>(a: &[u8], b: &[u8], writer: &mut W) -> Result<(), Error> {
+    let mut len = Vec::new();
+    write_varint((a.len() + b.len()) as u64, &mut len);
+    writer.write_all(&len)?;
+    writer.write_all(a)?;
+    writer.write_all(b)?;
+    Ok(())
+}
+
+/// Reads the next length-prefixed section, `None` at the end of the archive.
+fn read_section<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut len = Vec::new();
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            if len.is_empty() {
+                return Ok(None);
+            }
+            bail!("truncated car section");
+        }
+        len.push(byte[0]);
+        if byte[0] & 0x80 == 0 || len.len() == 10 {
+            break;
+        }
+    }
+    let len = read_varint(&len)?.0;
+
+    // Not allocating `len` upfront, a corrupted length could be huge.
+    let mut section = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut section)?;
+    if (section.len() as u64) < len {
+        bail!("truncated car section");
+    }
+    Ok(Some(section))
+}
+
+/// Length of the binary cid at the start of `bytes`, failing if `bytes` ends within the cid.
+fn cid_len(bytes: &[u8]) -> Result<usize, Error> {
+    // A v0 cid is a bare sha2-256 multihash.
+    let len = if bytes.starts_with(&[0x12, 0x20]) {
+        34
+    } else {
+        let mut offset = 0;
+        let mut digest_len = 0;
+        // version, codec, hash function and digest length.
+        for _ in 0..4 {
+            if offset > bytes.len() {
+                bail!("truncated cid");
+            }
+            let (n, len) = read_varint(&bytes[offset..])?;
+            digest_len = n;
+            offset += len;
+        }
+        match (offset as u64).checked_add(digest_len) {
+            Some(len) => len,
+            None => bail!("truncated cid"),
+        }
+    };
+    if len > bytes.len() as u64 {
+        bail!("truncated cid");
+    }
+    Ok(len as usize)
+}
+
+fn write_varint(mut n: u64, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Returns the unsigned varint at the start of `bytes` and its length.
+fn read_varint(bytes: &[u8]) -> Result<(u64, usize), Error> {
+    let mut n = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        n |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((n, i + 1));
+        }
+    }
+    bail!("invalid varint")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipld::IpldDag;
+    use crate::repo::tests::create_mock_repo;
+    use rustc_serialize::hex::FromHex;
+
+    /// The header and first block of the CARv1 fixture of go-car: two dag-cbor roots, the first
+    /// block `{"link": <dag-pb cid>, "name": "blip"}`.
+    const GO_CAR_FIXTURE: &str = concat!(
+        "63a265726f6f747382d82a58250001711220f88bc853804cf294fe417e4fa83028689fcdb1b1592c5102e147",
+        "4dbc200fab8bd82a5825000171122069ea0740f9807a28f4d932c62e7c1c83be055e55072c90266ab3e79df6",
+        "3a365b6776657273696f6e01",
+        "5b01711220f88bc853804cf294fe417e4fa83028689fcdb1b1592c5102e1474dbc200fab8ba2646c696e6bd8",
+        "2a582300122002acecc5de2438ea4126a3010ecb1f8a599c8eff22fff1a1dcffe999b27fd3de646e616d6564",
+        "626c6970",
+    );
+
+    #[test]
+    fn test_varint() {
+        for n in &[0, 1, 127, 128, 300, u64::max_value()] {
+            let mut bytes = Vec::new();
+            write_varint(*n, &mut bytes);
+            assert_eq!(read_varint(&bytes).unwrap(), (*n, bytes.len()));
+        }
+    }
+
+    #[test]
+    fn test_cid_len() {
+        let cid = Block::from("block").cid().to_bytes();
+        let mut section = cid.clone();
+        section.extend_from_slice(b"block");
+        assert_eq!(cid_len(&section).unwrap(), cid.len());
+
+        for len in 0..cid.len() {
+            assert!(cid_len(&cid[..len]).is_err());
+        }
+        // version 1, dag-pb, sha2-256 and a digest longer than any section.
+        assert!(cid_len(&[0x01, 0x70, 0x12, 0xff, 0xff, 0xff, 0xff, 0x0f]).is_err());
+        assert!(cid_len(&[0x01, 0x70, 0x12, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_read() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo.clone());
+        let shared = dag.put(vec![1].into(), Codec::DagCBOR).await.unwrap();
+        let data = vec![shared.root().to_owned()].into();
+        let root1 = dag.put(data, Codec::DagCBOR).await.unwrap();
+        let data = vec![shared.root().to_owned(), Ipld::U64(2)].into();
+        let root2 = dag.put(data, Codec::DagCBOR).await.unwrap();
+        let shared = shared.root().cid().unwrap().to_owned();
+        let roots = vec![
+            root1.root().cid().unwrap().to_owned(),
+            root2.root().cid().unwrap().to_owned(),
+        ];
+
+        let car = export(repo.clone(), roots.clone(), None, Vec::new(), Context::default()).await.unwrap();
+        let (read_roots, blocks) = read(&car).unwrap();
+        let cids: Vec<_> = blocks.iter().map(|block| block.cid().to_owned()).collect();
+        assert_eq!(read_roots, roots);
+        assert_eq!(cids, vec![roots[0].clone(), roots[1].clone(), shared]);
+
+        let car = export(repo, roots.clone(), Some(0), Vec::new(), Context::default()).await.unwrap();
+        assert_eq!(read(&car).unwrap().1.len(), 2);
+    }
+
+    #[test]
+    fn test_read_go_car() {
+        let car = GO_CAR_FIXTURE.from_hex().unwrap();
+        let (roots, blocks) = read(&car).unwrap();
+        let roots: Vec<_> = roots.iter().map(ToString::to_string).collect();
+        assert_eq!(roots, vec![
+            "bafyreihyrpefhacm6kkp4ql6j6udakdit7g3dmkzfriqfykhjw6cad5lrm",
+            "bafyreidj5idub6mapiupjwjsyyxhyhedxycv4vihfsicm2vt46o7morwlm",
+        ]);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].cid().to_string(), roots[0]);
+        let links: Vec<_> = block_links(&blocks[0]).unwrap().iter().map(ToString::to_string).collect();
+        assert_eq!(links, vec!["QmNX6Tffavsya4xgBi2VJQnSuqy9GsxongxZZ9uZBqp16d"]);
+
+        // written back, the roots are tagged like go-car tags them.
+        let mut header = Vec::new();
+        write_header(&read(&car).unwrap().0, &mut header).unwrap();
+        let first_root = &car[9..50];
+        assert!(header.windows(first_root.len()).any(|window| window == first_root));
+    }
+
+    #[test]
+    fn test_read_unlinked_blocks() {
+        let block = Block::from("orphan");
+        let mut car = Vec::new();
+        write_header(&[], &mut car).unwrap();
+        write_section(&block.cid().to_bytes(), block.data(), &mut car).unwrap();
+        assert_eq!(read(&car).unwrap().1, vec![block.clone()]);
+
+        let mut car = Vec::new();
+        write_header(&[], &mut car).unwrap();
+        write_section(&block.cid().to_bytes(), b"tampered", &mut car).unwrap();
+        assert!(read(&car).is_err());
+    }
+
+    #[test]
+    fn test_read_rejects_truncated_cids() {
+        let block = Block::from("block");
+        let cid = block.cid().to_bytes();
+        for len in 0..cid.len() {
+            let mut car = Vec::new();
+            write_header(&[block.cid().to_owned()], &mut car).unwrap();
+            write_section(&cid[..len], &[], &mut car).unwrap();
+            assert!(read(&car).is_err());
+        }
+
+        // A v1 cid claiming a digest longer than the section.
+        let mut car = Vec::new();
+        write_header(&[], &mut car).unwrap();
+        write_section(&[0x01, 0x71, 0x12, 0xff, 0xff, 0x03], b"data", &mut car).unwrap();
+        assert!(read(&car).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo.clone());
+        let leaf = dag.put(vec![1].into(), Codec::DagCBOR).await.unwrap();
+        let root = dag.put(vec![leaf.root().to_owned()].into(), Codec::DagCBOR).await.unwrap();
+        let leaf = leaf.root().cid().unwrap().to_owned();
+        let root = root.root().cid().unwrap().to_owned();
+        let car = export(repo, vec![root.clone()], None, Vec::new(), Context::default()).await.unwrap();
+
+        let truncated = create_mock_repo();
+        assert!(import(truncated, &car[..car.len() - 1]).await.is_err());
+
+        let imported = create_mock_repo();
+        assert_eq!(import(imported.clone(), &car[..]).await.unwrap(), vec![root.clone()]);
+        let ctx = Context::default().want_network(false);
+        for cid in &[root, leaf] {
+            assert_eq!(imported.get_block(cid, ctx).await.unwrap().cid(), cid);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! How CIDs are shown to users.
+//!
+//! API responses, error messages and logs format CIDs with the repo's `CidProfile` rather than
+//! `Cid::to_string`, so a node can answer in CIDv1 and a base of its choice everywhere at once.
+//! Internal keys, like block file names and ipns records, keep their own fixed encoding.
+use crate::block::Cid;
+use crate::error::Error;
+use cid::Version;
+use multibase::Base;
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref CURRENT: RwLock<CidProfile> = RwLock::new(CidProfile::default());
+}
+
+/// Version and multibase CIDs are formatted with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CidProfile {
+    /// CIDv0s are upgraded to CIDv1 if this is `V1`. CIDv1s are never downgraded.
+    pub version: Version,
+    /// Base of CIDv1s, CIDv0s are always in base58btc.
+    pub base: Base,
+}
+
+impl Default for CidProfile {
+    /// CIDs as they are, CIDv1s in base58btc.
+    fn default() -> Self {
+        CidProfile {
+            version: Version::V0,
+            base: Base::Base58Btc,
+        }
+    }
+}
+
+impl CidProfile {
+    /// Parses a version, `0` or `1`, and a multibase name like `base32`.
+    pub fn from_names(version: &str, base: &str) -> Result<Self, Error> {
+        let version = match version {
+            "0" | "v0" => Version::V0,
+            "1" | "v1" => Version::V1,
+            _ => bail!("unknown cid version {}", version),
+        };
+        let base = match base {
+            "base16" => Base::Base16Lower,
+            "base16upper" => Base::Base16Upper,
+            "base32" => Base::Base32Lower,
+            "base32upper" => Base::Base32Upper,
+            "base32hex" => Base::Base32HexLower,
+            "base36" => Base::Base36Lower,
+            "base58btc" => Base::Base58Btc,
+            "base58flickr" => Base::Base58Flickr,
+            "base64" => Base::Base64,
+            "base64url" => Base::Base64Url,
+            _ => bail!("unknown multibase {}", base),
+        };
+        Ok(CidProfile { version, base })
+    }
+
+    /// Formats `cid` for an API response, error message or log.
+    pub fn format(&self, cid: &Cid) -> String {
+        if cid.version == Version::V0 && self.version == Version::V0 {
+            cid.to_string()
+        } else {
+            multibase::encode(self.base, to_v1(cid).to_bytes())
+        }
+    }
+
+    /// Formats `cid` for a subdomain, like `<cid>.ipfs.example.com`. Labels are case-insensitive,
+    /// so CIDv0s are upgraded to CIDv1 and case-sensitive bases are replaced by base32.
+    pub fn format_subdomain(&self, cid: &Cid) -> String {
+        let base = if is_case_insensitive(self.base) { self.base } else { Base::Base32Lower };
+        multibase::encode(base, to_v1(cid).to_bytes())
+    }
+}
+
+/// Profile of the repo, see `set`.
+pub fn current() -> CidProfile {
+    *CURRENT.read().expect("lock is never poisoned; qed")
+}
+
+/// Format every CID shown to users with `profile` from now on.
+pub fn set(profile: CidProfile) {
+    *CURRENT.write().expect("lock is never poisoned; qed") = profile;
+}
+
+/// Formats `cid` with the profile of the repo.
+pub fn display(cid: &Cid) -> String {
+    current().format(cid)
+}
+
+fn to_v1(cid: &Cid) -> Cid {
+    Cid::new(cid.codec, Version::V1, &cid.hash)
+}
+
+fn is_case_insensitive(base: Base) -> bool {
+    match base {
+        Base::Base16Lower | Base::Base32Lower | Base::Base32HexLower | Base::Base36Lower => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+
+    const V0: &str = "QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8";
+    const V1_BASE32: &str = "bafybeicysg23kiwv34eg2d7qweipxwosdo2py4ldv42nbauguluen5v6am";
+
+    #[test]
+    fn test_format() {
+        let cid = Block::from("hello\n").cid().to_owned();
+        assert_eq!(CidProfile::default().format(&cid), V0);
+
+        let v1 = CidProfile::from_names("1", "base32").unwrap();
+        assert_eq!(v1.format(&cid), V1_BASE32);
+        let upper = CidProfile::from_names("1", "base32upper").unwrap();
+        assert_eq!(upper.format(&cid), V1_BASE32.to_uppercase());
+        let btc = CidProfile::from_names("1", "base58btc").unwrap();
+        assert_eq!(btc.format(&cid), "zdj7WbPdpgddtVoFyUvWQbAG4YFUU5yxiWxXKbN2jELXpnUw4");
+
+        // CIDv1s are not downgraded.
+        let v0 = CidProfile::from_names("0", "base32").unwrap();
+        assert_eq!(v0.format(&Cid::from(V1_BASE32).unwrap()), V1_BASE32);
+    }
+
+    #[test]
+    fn test_format_subdomain() {
+        let cid = Block::from("hello\n").cid().to_owned();
+        assert_eq!(CidProfile::default().format_subdomain(&cid), V1_BASE32);
+        let base36 = CidProfile::from_names("1", "base36").unwrap();
+        assert!(base36.format_subdomain(&cid).starts_with('k'));
+    }
+
+    #[test]
+    fn test_from_names() {
+        assert!(CidProfile::from_names("2", "base32").is_err());
+        assert!(CidProfile::from_names("1", "base99").is_err());
+    }
+}
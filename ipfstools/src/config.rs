@@ -1,11 +1,17 @@
+use crate::error::Error;
+use crate::ipns::IpnsKey;
 use libp2p::{Multiaddr, PeerId};
 use libp2p::multiaddr::Protocol;
 use libp2p::secio::SecioKeyPair;
+use near_primitives::crypto::keystore::{Keystore, KeystoreError, KeystoreKey};
 use rand::{Rng, rngs::EntropyRng};
 use serde_derive::{Serialize, Deserialize};
 use std::fs;
 use std::path::Path;
 
+/// Name of the node's identity key in the keystore.
+pub const SELF_KEY: &str = "self";
+
 const BOOTSTRAP_NODES: &[&'static str] = &[
     "/ip4/104.131.131.82/tcp/4001/p2p/QmaCpDMGvV2BGHeYERUEnRQAwe3N8SzbUtfsmvsqQLuvuJ",
     "/ip4/104.236.179.241/tcp/4001/p2p/QmSoLPppuBtQSGwKDZT2M73ULpjvfd3aZ6ha4oFGL1KrGM",
@@ -36,10 +42,35 @@ impl ConfigFile {
         })
     }
 
+    /// Replaces the identity with the key stored under `SELF_KEY` in `keystore`.
+    ///
+    /// A keystore without that key gets the identity of the config, so a node keeps its peer id
+    /// once it has a keystore.
+    pub fn load_identity(&mut self, keystore: &Keystore) -> Result<(), Error> {
+        let key = match keystore.get(SELF_KEY) {
+            Ok(key) => key,
+            Err(KeystoreError::NotFound(_)) => {
+                let key = KeystoreKey::ed25519_from_seed(&self.raw_key)?;
+                keystore.put(SELF_KEY, &key)?;
+                key
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match key.ed25519_seed() {
+            Some(seed) => self.raw_key.copy_from_slice(seed),
+            None => bail!("the {} key is no ed25519 key", SELF_KEY),
+        }
+        Ok(())
+    }
+
     pub fn secio_key_pair(&self) -> SecioKeyPair {
         SecioKeyPair::ed25519_raw_key(&self.raw_key).unwrap()
     }
 
+    pub fn ipns_key(&self) -> IpnsKey {
+        IpnsKey::from_raw_key(&self.raw_key).unwrap()
+    }
+
     pub fn peer_id(&self) -> PeerId {
         self.secio_key_pair().to_peer_id()
     }
@@ -70,3 +101,25 @@ impl Default for ConfigFile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_identity() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("ipfstools-keystore-{}", std::process::id()));
+        let keystore = Keystore::new(&path);
+
+        let mut first = ConfigFile::default();
+        let peer_id = first.peer_id();
+        first.load_identity(&keystore).unwrap();
+        assert_eq!(first.peer_id(), peer_id);
+
+        let mut second = ConfigFile::default();
+        second.load_identity(&keystore).unwrap();
+        assert_eq!(second.peer_id(), peer_id);
+        assert_eq!(second.ipns_key().peer_id(), peer_id);
+    }
+}
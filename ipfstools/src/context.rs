@@ -0,0 +1,121 @@
+//! Per request limits
+use crate::block::Cid;
+use crate::cid_profile;
+use crate::error::Error;
+use filesys_errors::{CoreError, ErrorCode};
+use std::time::{Duration, Instant};
+
+/// Limits a request puts on the work done to answer it.
+///
+/// Passed down to every operation that may wait on the network, which checks it at each await
+/// point. Once the deadline is exceeded the operation fails with `ContextError::DeadlineExceeded`
+/// instead of waiting forever on a block no peer sends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Context {
+    deadline: Option<Instant>,
+    want_network: bool,
+}
+
+impl Default for Context {
+    /// No deadline, blocks may be fetched from the network.
+    fn default() -> Self {
+        Context {
+            deadline: None,
+            want_network: true,
+        }
+    }
+}
+
+impl Context {
+    /// Creates a context expiring `timeout` from now.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Context::default().deadline(Instant::now() + timeout)
+    }
+
+    /// Sets the instant after which the request is abandoned.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets whether missing blocks may be requested from the network.
+    pub fn want_network(mut self, want_network: bool) -> Self {
+        self.want_network = want_network;
+        self
+    }
+
+    /// Returns the deadline, if any.
+    pub fn get_deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Returns `true` if missing blocks may be requested from the network.
+    pub fn wants_network(&self) -> bool {
+        self.want_network
+    }
+
+    /// Returns `true` once the deadline is exceeded.
+    pub fn is_expired(&self) -> bool {
+        match self.deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    /// Fails if the deadline is exceeded.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_expired() {
+            return Err(ContextError::DeadlineExceeded.into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ContextError {
+    DeadlineExceeded,
+    NotAvailableOffline(Cid),
+}
+
+impl std::error::Error for ContextError {
+    fn description(&self) -> &str {
+        match *self {
+            ContextError::DeadlineExceeded => "deadline exceeded",
+            ContextError::NotAvailableOffline(_) => "not available offline",
+        }
+    }
+}
+
+impl std::fmt::Display for ContextError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ContextError::DeadlineExceeded => {
+                write!(f, "Deadline exceeded")
+            }
+            ContextError::NotAvailableOffline(ref cid) => {
+                write!(f, "Block {} is not available offline", cid_profile::display(cid))
+            }
+        }
+    }
+}
+
+impl CoreError for ContextError {
+    fn code(&self) -> ErrorCode {
+        match *self {
+            ContextError::DeadlineExceeded => ErrorCode::Timeout,
+            ContextError::NotAvailableOffline(_) => ErrorCode::Unavailable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired() {
+        assert!(Context::default().check().is_ok());
+        assert!(Context::with_timeout(Duration::from_secs(60)).check().is_ok());
+        assert!(Context::default().deadline(Instant::now()).check().is_err());
+    }
+}
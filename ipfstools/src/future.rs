@@ -1,40 +1,73 @@
 use crate::block::{Block, Cid};
+use crate::context::{Context, ContextError};
 use crate::error::Error;
 use crate::repo::BlockStore;
-use futures::future::FutureObj;
+use futures::channel::oneshot;
+use futures::future::BoxFuture;
 use futures::prelude::*;
 use std::future::Future;
 use std::pin::Pin;
-use std::task::{Poll, Waker};
+use std::task::{Context as TaskContext, Poll};
+use tokio::time::{self, Instant, Sleep};
 
 pub struct BlockFuture<TBlockStore: BlockStore> {
     block_store: TBlockStore,
     cid: Cid,
-    future: FutureObj<'static, Result<Option<Block>, Error>>,
+    ctx: Context,
+    future: BoxFuture<'static, Result<Option<Block>, Error>>,
+    wanted: Option<oneshot::Receiver<Block>>,
+    /// Wakes the future at the deadline of `ctx`, set on the first poll.
+    timer: Option<Pin<Box<Sleep>>>,
 }
 
 impl<TBlockStore: BlockStore> BlockFuture<TBlockStore> {
-    pub fn new(block_store: TBlockStore, cid: Cid) -> Self {
+    pub fn new(block_store: TBlockStore, cid: Cid, ctx: Context) -> Self {
         let future = block_store.get(&cid);
         BlockFuture {
             block_store,
             cid,
+            ctx,
             future,
+            wanted: None,
+            timer: None,
         }
     }
+
+    /// Also completes when the block arrives through `wanted`, see `BlockExchange::want`.
+    pub fn or_wanted(mut self, wanted: oneshot::Receiver<Block>) -> Self {
+        self.wanted = Some(wanted);
+        self
+    }
 }
 
 impl<TBlockStore: BlockStore> Future for BlockFuture<TBlockStore> {
     type Output = Result<Block, Error>;
 
-    fn poll(mut self: Pin<&mut Self>, waker: &Waker) -> Poll<Self::Output> {
-        return match self.future.poll_unpin(waker) {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Self::Output> {
+        // neither the block store nor the exchange may ever answer, so the deadline is a timer.
+        if let Some(deadline) = self.ctx.get_deadline() {
+            let timer = self.timer.get_or_insert_with(|| Box::pin(time::sleep_until(Instant::from_std(deadline))));
+            if timer.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(ContextError::DeadlineExceeded.into()));
+            }
+        }
+        if let Some(wanted) = &mut self.wanted {
+            match wanted.poll_unpin(cx) {
+                Poll::Ready(Ok(block)) => return Poll::Ready(Ok(block)),
+                // the exchange is gone, keep looking in the block store.
+                Poll::Ready(Err(_)) => self.wanted = None,
+                Poll::Pending => {}
+            }
+        }
+        return match self.future.poll_unpin(cx) {
             Poll::Ready(Ok(Some(block))) => Poll::Ready(Ok(block)),
             Poll::Ready(Ok(None)) => {
+                if let Err(err) = self.ctx.check() {
+                    return Poll::Ready(Err(err));
+                }
                 let future = self.block_store.get(&self.cid);
                 self.get_mut().future = future;
-                tokio::prelude::task::current().notify();
-                //waker.wake();
+                cx.waker().wake_by_ref();
                 Poll::Pending
             },
             Poll::Ready(Err(err)) => {
@@ -44,3 +77,57 @@ impl<TBlockStore: BlockStore> Future for BlockFuture<TBlockStore> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::BlockCount;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    /// Block store that never answers a `get`.
+    #[derive(Clone)]
+    struct StalledBlockStore;
+
+    impl BlockStore for StalledBlockStore {
+        fn new(_path: PathBuf) -> Self {
+            StalledBlockStore
+        }
+        fn init(&self) -> BoxFuture<'static, Result<(), Error>> {
+            future::ok(()).boxed()
+        }
+        fn open(&self) -> BoxFuture<'static, Result<(), Error>> {
+            future::ok(()).boxed()
+        }
+        fn contains(&self, _cid: &Cid) -> BoxFuture<'static, Result<bool, Error>> {
+            future::pending().boxed()
+        }
+        fn get(&self, _cid: &Cid) -> BoxFuture<'static, Result<Option<Block>, Error>> {
+            future::pending().boxed()
+        }
+        fn put(&self, _block: Block) -> BoxFuture<'static, Result<Cid, Error>> {
+            future::pending().boxed()
+        }
+        fn remove(&self, _cid: &Cid) -> BoxFuture<'static, Result<(), Error>> {
+            future::pending().boxed()
+        }
+        fn list(&self) -> BoxFuture<'static, Result<Vec<Cid>, Error>> {
+            future::pending().boxed()
+        }
+        fn stat(&self) -> BoxFuture<'static, Result<BlockCount, Error>> {
+            future::pending().boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deadline_wakes() {
+        let cid = Block::from("stalled block").cid().to_owned();
+        let ctx = Context::with_timeout(Duration::from_millis(10));
+
+        let err = BlockFuture::new(StalledBlockStore, cid, ctx).await.unwrap_err();
+        match err.downcast_ref::<ContextError>() {
+            Some(ContextError::DeadlineExceeded) => {}
+            _ => panic!("expected deadline exceeded, got {}", err),
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use crate::context::Context;
 use crate::error::Error;
-use crate::ipld::Ipld;
+use crate::ipld::{formats, Ipld, OutputCodec};
 use crate::path::{IpfsPath, IpfsPathError, PathRoot, SubPath};
 use crate::repo::{Repo, RepoTypes};
-use cid::Codec;
+use cid::{Cid, Codec};
 use core::future::Future;
+use std::collections::{HashSet, VecDeque};
 
 #[derive(Clone)]
 pub struct IpldDag<Types: RepoTypes> {
@@ -23,38 +25,155 @@ impl<Types: RepoTypes> IpldDag<Types> {
         let repo = self.repo.clone();
         async move {
             let block = data.to_block(codec)?;
-            let cid = await!(repo.put_block(block))?;
+            let cid = repo.put_block(block).await?;
             Ok(IpfsPath::new(PathRoot::Ipld(cid)))
         }
     }
 
-    pub fn get(&self, path: IpfsPath) -> impl Future<Output=Result<Ipld, Error>> {
+    /// Resolves `path` to an ipld node, fetching the blocks on the way within the limits of `ctx`.
+    pub fn get(&self, path: IpfsPath, ctx: Context) -> impl Future<Output=Result<Ipld, Error>> {
+        let resolve = self.resolve(path, ctx);
+        async move {
+            let (_, ipld) = resolve.await?;
+            Ok(ipld)
+        }
+    }
+
+    /// Resolves `path` like `get`, also returning the cid of the block the node is in.
+    pub fn resolve(&self, path: IpfsPath, ctx: Context) ->
+    impl Future<Output=Result<(Cid, Ipld), Error>>
+    {
+        let walk = self.walk(path, ctx);
+        async move {
+            let (mut cids, ipld) = walk.await?;
+            let cid = cids.pop().expect("a walk starts at the root");
+            Ok((cid, ipld))
+        }
+    }
+
+    /// Resolves `path` like `get`, returning the cids of every block on the way instead of the
+    /// node: the root first and the block the node is in last.
+    pub fn resolve_cids(&self, path: IpfsPath, ctx: Context) ->
+    impl Future<Output=Result<Vec<Cid>, Error>>
+    {
+        let walk = self.walk(path, ctx);
+        async move {
+            let (cids, _) = walk.await?;
+            Ok(cids)
+        }
+    }
+
+    fn walk(&self, path: IpfsPath, ctx: Context) ->
+    impl Future<Output=Result<(Vec<Cid>, Ipld), Error>>
+    {
         let repo = self.repo.clone();
         async move {
-            let cid = match path.root().cid() {
-                Some(cid) => cid,
+            let mut cid = match path.root().cid() {
+                Some(cid) => cid.to_owned(),
                 None => bail!("expected cid"),
             };
-            let mut ipld = Ipld::from(&await!(repo.get_block(&cid))?)?;
+            let mut cids = vec![cid.clone()];
+            let mut codec = cid.prefix().codec;
+            let mut ipld = Ipld::from(&repo.get_block(&cid, ctx).await?)?;
             for sub_path in path.iter() {
-                if !can_resolve(&ipld, sub_path) {
-                    let path = sub_path.to_owned();
-                    return Err(IpfsPathError::ResolveError { ipld, path }.into());
-                }
-                ipld = resolve(ipld, sub_path);
+                // unixfs directories are dag-pb nodes naming their entries in the links.
+                let named = match sub_path {
+                    SubPath::Key(name) if codec == Codec::DagProtobuf => {
+                        formats::pb::named_link(&ipld, name)
+                    }
+                    _ => None,
+                };
+                ipld = match named {
+                    Some(link) => link,
+                    None => {
+                        if !can_resolve(&ipld, sub_path) {
+                            let path = sub_path.to_owned();
+                            return Err(IpfsPathError::ResolveError { ipld, path }.into());
+                        }
+                        resolve(ipld, sub_path)
+                    }
+                };
+                ctx.check()?;
                 ipld = match ipld {
                     Ipld::Link(root) => {
                         match root.cid() {
-                            Some(cid) => Ipld::from(&await!(repo.get_block(cid))?)?,
+                            Some(link) => {
+                                cid = link.to_owned();
+                                cids.push(cid.clone());
+                                codec = cid.prefix().codec;
+                                Ipld::from(&repo.get_block(&cid, ctx).await?)?
+                            }
                             None => bail!("expected cid"),
                         }
                     }
                     ipld => ipld,
                 };
             }
-            Ok(ipld)
+            Ok((cids, ipld))
+        }
+    }
+
+    /// Resolves `path` like `get`, then encodes the node with `output_codec` regardless of the
+    /// codec of the block it was stored in.
+    pub fn get_encoded(&self, path: IpfsPath, output_codec: OutputCodec, ctx: Context) ->
+    impl Future<Output=Result<Vec<u8>, Error>>
+    {
+        let get = self.get(path, ctx);
+        async move {
+            let ipld = get.await?;
+            output_codec.encode(&ipld)
         }
     }
+
+    /// Walks the dag below `path` breadth-first, calling `visitor` with the cid, node and depth
+    /// of every block reached through a link, down to `max_depth` links away (unlimited if
+    /// `None`).
+    ///
+    /// The node at `path` itself is not visited. A block linked from several nodes is visited
+    /// once, at the smallest depth it was found at.
+    pub fn traverse<F>(&self, path: IpfsPath, max_depth: Option<usize>, ctx: Context, mut visitor: F) ->
+    impl Future<Output=Result<(), Error>>
+    where
+        F: FnMut(&Cid, &Ipld, usize),
+    {
+        let repo = self.repo.clone();
+        let get = self.get(path, ctx);
+        async move {
+            let root = get.await?;
+            let mut seen = HashSet::new();
+            let mut queue: VecDeque<_> = links(&root).into_iter().map(|cid| (cid, 1)).collect();
+            while let Some((cid, depth)) = queue.pop_front() {
+                // Links are queued by increasing depth, nothing after this one is shallower.
+                if max_depth.map_or(false, |max_depth| depth > max_depth) {
+                    break;
+                }
+                if !seen.insert(cid.clone()) {
+                    continue;
+                }
+                ctx.check()?;
+                let ipld = Ipld::from(&repo.get_block(&cid, ctx).await?)?;
+                visitor(&cid, &ipld, depth);
+                queue.extend(links(&ipld).into_iter().map(|cid| (cid, depth + 1)));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Cids of the blocks linked from `ipld`, links to ipns or dns paths are ignored.
+pub(crate) fn links(ipld: &Ipld) -> Vec<Cid> {
+    fn collect(ipld: &Ipld, cids: &mut Vec<Cid>) {
+        match ipld {
+            Ipld::Link(root) => cids.extend(root.cid().cloned()),
+            Ipld::Array(vec) => vec.iter().for_each(|ipld| collect(ipld, cids)),
+            Ipld::Object(map) => map.values().for_each(|ipld| collect(ipld, cids)),
+            _ => {}
+        }
+    }
+
+    let mut cids = Vec::new();
+    collect(ipld, &mut cids);
+    cids
 }
 
 fn can_resolve(ipld: &Ipld, sub_path: &SubPath) -> bool {
@@ -96,71 +215,138 @@ fn resolve(ipld: Ipld, sub_path: &SubPath) -> Ipld {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::block::Block;
     use crate::repo::tests::create_mock_repo;
     use std::collections::HashMap;
 
-    #[test]
-    fn test_resolve_root_cid() {
-        tokio::run_async(async {
-            let repo = create_mock_repo();
-            let dag = IpldDag::new(repo);
-            let data = Ipld::Array(vec![Ipld::U64(1), Ipld::U64(2), Ipld::U64(3)]);
-            let path = await!(dag.put(data.clone(), Codec::DagCBOR)).unwrap();
-
-            let res = await!(dag.get(path)).unwrap();
-            assert_eq!(res, data);
-
-        });
-    }
-
-    #[test]
-    fn test_resolve_array_elem() {
-        tokio::run_async(async {
-            let repo = create_mock_repo();
-            let dag = IpldDag::new(repo);
-            let data: Ipld = vec![1, 2, 3].into();
-            let path = await!(dag.put(data.clone(), Codec::DagCBOR)).unwrap();
-            let res = await!(dag.get(path.sub_path("1").unwrap())).unwrap();
-            assert_eq!(res, Ipld::U64(2));
-        });
-    }
-
-    #[test]
-    fn test_resolve_nested_array_elem() {
-        tokio::run_async(async {
-            let repo = create_mock_repo();
-            let dag = IpldDag::new(repo);
-            let data = Ipld::Array(vec![Ipld::U64(1), Ipld::Array(vec![Ipld::U64(2)]), Ipld::U64(3)]);
-            let path = await!(dag.put(data.clone(), Codec::DagCBOR)).unwrap();
-            let res = await!(dag.get(path.sub_path("1/0").unwrap())).unwrap();
-            assert_eq!(res, Ipld::U64(2));
-        });
-    }
-
-    #[test]
-    fn test_resolve_object_elem() {
-        tokio::run_async(async {
-            let repo = create_mock_repo();
-            let dag = IpldDag::new(repo);
-            let mut data = HashMap::new();
-            data.insert("key", false);
-            let path = await!(dag.put(data.into(), Codec::DagCBOR)).unwrap();
-            let res = await!(dag.get(path.sub_path("key").unwrap())).unwrap();
-            assert_eq!(res, Ipld::Bool(false));
-        });
-    }
-
-    #[test]
-    fn test_resolve_cid_elem() {
-        tokio::run_async(async {
-            let repo = create_mock_repo();
-            let dag = IpldDag::new(repo);
-            let data1 = vec![1].into();
-            let path1 = await!(dag.put(data1, Codec::DagCBOR)).unwrap();
-            let data2 = vec![path1.root().to_owned()].into();
-            let path = await!(dag.put(data2, Codec::DagCBOR)).unwrap();
-            let res = await!(dag.get(path.sub_path("0/0").unwrap())).unwrap();
-            assert_eq!(res, Ipld::U64(1));
-        });
+    #[tokio::test]
+    async fn test_resolve_root_cid() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo);
+        let data = Ipld::Array(vec![Ipld::U64(1), Ipld::U64(2), Ipld::U64(3)]);
+        let path = dag.put(data.clone(), Codec::DagCBOR).await.unwrap();
+
+        let res = dag.get(path, Context::default()).await.unwrap();
+        assert_eq!(res, data);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_array_elem() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo);
+        let data: Ipld = vec![1, 2, 3].into();
+        let path = dag.put(data.clone(), Codec::DagCBOR).await.unwrap();
+        let res = dag.get(path.sub_path("1").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(res, Ipld::U64(2));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_nested_array_elem() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo);
+        let data = Ipld::Array(vec![Ipld::U64(1), Ipld::Array(vec![Ipld::U64(2)]), Ipld::U64(3)]);
+        let path = dag.put(data.clone(), Codec::DagCBOR).await.unwrap();
+        let res = dag.get(path.sub_path("1/0").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(res, Ipld::U64(2));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_object_elem() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo);
+        let mut data = HashMap::new();
+        data.insert("key", false);
+        let path = dag.put(data.into(), Codec::DagCBOR).await.unwrap();
+        let res = dag.get(path.sub_path("key").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(res, Ipld::Bool(false));
+    }
+
+    #[tokio::test]
+    async fn test_get_encoded_json() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo);
+        let data = Ipld::Array(vec![Ipld::U64(1), Ipld::Bool(true)]);
+        let path = dag.put(data, Codec::DagCBOR).await.unwrap();
+        let res = dag.get_encoded(path, OutputCodec::DagJson, Context::default()).await.unwrap();
+        assert_eq!(res, b"[1,true]".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_cid_elem() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo);
+        let data1 = vec![1].into();
+        let path1 = dag.put(data1, Codec::DagCBOR).await.unwrap();
+        let data2 = vec![path1.root().to_owned()].into();
+        let path = dag.put(data2, Codec::DagCBOR).await.unwrap();
+        let res = dag.get(path.sub_path("0/0").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(res, Ipld::U64(1));
+
+        let root = path.root().cid().unwrap().to_owned();
+        let leaf = path1.root().cid().unwrap().to_owned();
+        let cids = dag.resolve_cids(path.sub_path("0/0").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(cids, vec![root.clone(), leaf]);
+        let cids = dag.resolve_cids(path, Context::default()).await.unwrap();
+        assert_eq!(cids, vec![root]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_dag_json() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo);
+        let leaf = dag.put(vec![1].into(), Codec::DagCBOR).await.unwrap();
+        let mut data = HashMap::new();
+        data.insert("leaf", Ipld::from(leaf.root().to_owned()));
+        let path = dag.put(data.into(), Codec::DagJSON).await.unwrap();
+        assert_eq!(path.root().cid().unwrap().prefix().codec, Codec::DagJSON);
+        let res = dag.get(path.sub_path("leaf/0").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(res, Ipld::U64(1));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_dag_pb_names() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo.clone());
+        let file = repo.put_block(Block::from("hello\n")).await.unwrap();
+        let mut link = HashMap::new();
+        link.insert("Hash", Ipld::from(file));
+        link.insert("Name", "hello.txt".into());
+        link.insert("Tsize", 6u64.into());
+        let mut dir = HashMap::new();
+        // unixfs data of a directory.
+        dir.insert("Data", Ipld::Bytes(vec![8, 1]));
+        dir.insert("Links", Ipld::Array(vec![link.into()]));
+        let path = dag.put(dir.into(), Codec::DagProtobuf).await.unwrap();
+
+        let res = dag.get(path.sub_path("hello.txt").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(res, Ipld::Bytes(b"hello\n".to_vec()));
+        let res = dag.get(path.sub_path("Links/0/Hash").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(res, Ipld::Bytes(b"hello\n".to_vec()));
+        assert!(dag.get(path.sub_path("missing").unwrap(), Context::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_traverse() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo);
+        let leaf = dag.put(vec![1].into(), Codec::DagCBOR).await.unwrap();
+        let data = vec![leaf.root().to_owned(), leaf.root().to_owned()].into();
+        let node = dag.put(data, Codec::DagCBOR).await.unwrap();
+        let data = vec![node.root().to_owned()].into();
+        let root = dag.put(data, Codec::DagCBOR).await.unwrap();
+        let leaf = leaf.root().cid().unwrap().to_owned();
+        let node = node.root().cid().unwrap().to_owned();
+
+        let mut visits = Vec::new();
+        dag.traverse(root.clone(), None, Context::default(), |cid, _, depth| {
+            visits.push((cid.to_owned(), depth))
+        }).await.unwrap();
+        assert_eq!(visits, vec![(node.clone(), 1), (leaf, 2)]);
+
+        let mut visits = Vec::new();
+        dag.traverse(root, Some(1), Context::default(), |cid, _, depth| {
+            visits.push((cid.to_owned(), depth))
+        }).await.unwrap();
+        assert_eq!(visits, vec![(node, 1)]);
     }
 }
@@ -1,4 +1,5 @@
 use cid::Codec;
+use filesys_errors::{CoreError, ErrorCode};
 
 #[derive(Debug)]
 pub enum IpldError {
@@ -22,3 +23,11 @@ impl std::fmt::Display for IpldError {
         }
     }
 }
+
+impl CoreError for IpldError {
+    fn code(&self) -> ErrorCode {
+        match *self {
+            IpldError::UnsupportedCodec(_) => ErrorCode::Unsupported,
+        }
+    }
+}
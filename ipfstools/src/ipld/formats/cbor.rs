@@ -1,10 +1,17 @@
-use cbor::{Cbor, Decoder, Encoder};
+use cbor::Encoder;
 pub use cbor::{CborBytes, CborTagEncode, CborError, ReadError};
 use cid::Prefix;
 use crate::block::Cid;
 use crate::error::Error;
 use crate::ipld::Ipld;
 use rustc_serialize::{Encodable, Encoder as RustcEncoder};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_cbor::tags::{Tagged, CID_TAG};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Multibase prefix of the cids in tag 42 byte strings, the identity multibase.
+pub(crate) const CID_MULTIBASE: u8 = 0x00;
 
 pub(crate) const PREFIX: Prefix = Prefix {
     version: cid::Version::V1,
@@ -13,10 +20,9 @@ pub(crate) const PREFIX: Prefix = Prefix {
     mh_len: 32,
 };
 
-pub(crate) fn decode(bytes: Vec<u8>) -> Result<Ipld, Error> {
-    let mut d = Decoder::from_bytes(bytes);
-    let cbor: Cbor = d.read_data_item(None)?;
-    cbor_to_ipld(cbor)
+/// Decodes `bytes` straight into `Ipld`, mapping tag 42 byte strings to links.
+pub(crate) fn decode(bytes: &[u8]) -> Result<Ipld, Error> {
+    Ok(serde_cbor::from_slice(bytes)?)
 }
 
 pub(crate) fn encode(data: &Ipld) -> Result<Vec<u8>, Error> {
@@ -25,50 +31,101 @@ pub(crate) fn encode(data: &Ipld) -> Result<Vec<u8>, Error> {
     Ok(e.as_bytes().to_owned())
 }
 
-fn cbor_to_ipld(cbor: Cbor) -> Result<Ipld, Error> {
-    let ipld = match cbor {
-        Cbor::Break => {
-            let err = ReadError::Other("Break.".into());
-            return Err(CborError::Decode(err).into())
-        }
-        Cbor::Undefined => Ipld::Null,
-        Cbor::Null => Ipld::Null,
-        Cbor::Bool(b) => Ipld::Bool(b),
-        Cbor::Unsigned(u) => Ipld::U64(u.into_u64()),
-        Cbor::Signed(i) => Ipld::I64(i.into_i64()),
-        Cbor::Float(f) => Ipld::F64(f.into_f64()),
-        Cbor::Bytes(bytes) => Ipld::Bytes(bytes.0),
-        Cbor::Unicode(string) => Ipld::String(string),
-        Cbor::Array(vec) => {
-            let ipld_vec = vec.into_iter()
-                .map(|item| cbor_to_ipld(item))
-                .collect::<Result<_, _>>()?;
-            Ipld::Array(ipld_vec)
+impl<'de> Deserialize<'de> for Ipld {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let tagged = Tagged::<Untagged>::deserialize(deserializer)?;
+        match (tagged.tag, tagged.value.0) {
+            (None, ipld) => Ok(ipld),
+            (Some(CID_TAG), Ipld::Bytes(bytes)) => {
+                let cid = match bytes.split_first() {
+                    Some((&CID_MULTIBASE, cid)) => Cid::from(cid).map_err(de::Error::custom)?,
+                    _ => return Err(de::Error::custom("cid without multibase prefix")),
+                };
+                Ok(Ipld::Link(cid.into()))
+            }
+            (Some(CID_TAG), _) => Err(de::Error::custom("invalid cid")),
+            (Some(tag), _) => Err(de::Error::custom(format!("unknown tag {}", tag))),
         }
-        Cbor::Map(map) => {
-            let ipld_map = map.into_iter()
-                .map(|(k, v)| {
-                    Ok((k, cbor_to_ipld(v)?))
-                })
-                .collect::<Result<_, Error>>()?;
-            Ipld::Object(ipld_map)
+    }
+}
+
+/// An `Ipld` deserialized without looking at its tag, nested values are read as `Ipld`.
+struct Untagged(Ipld);
+
+impl<'de> Deserialize<'de> for Untagged {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IpldVisitor).map(Untagged)
+    }
+}
+
+struct IpldVisitor;
+
+impl<'de> Visitor<'de> for IpldVisitor {
+    type Value = Ipld;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("an ipld value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Ipld, E> {
+        Ok(Ipld::Bool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Ipld, E> {
+        Ok(Ipld::I64(i))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> Result<Ipld, E> {
+        Ok(Ipld::U64(u))
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Ipld, E> {
+        Ok(Ipld::F64(f))
+    }
+
+    fn visit_str<E>(self, string: &str) -> Result<Ipld, E> {
+        Ok(Ipld::String(string.to_owned()))
+    }
+
+    fn visit_string<E>(self, string: String) -> Result<Ipld, E> {
+        Ok(Ipld::String(string))
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Ipld, E> {
+        Ok(Ipld::Bytes(bytes.to_owned()))
+    }
+
+    fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<Ipld, E> {
+        Ok(Ipld::Bytes(bytes))
+    }
+
+    fn visit_unit<E>(self) -> Result<Ipld, E> {
+        Ok(Ipld::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Ipld, E> {
+        Ok(Ipld::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Ipld, D::Error> {
+        Ipld::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Ipld, A::Error> {
+        let mut vec = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(ipld) = seq.next_element()? {
+            vec.push(ipld);
         }
-        Cbor::Tag(tag) => {
-            if tag.tag == 42 {
-                if let Cbor::Bytes(bytes) = *tag.data {
-                    Ipld::Link(Cid::from(bytes.0)?.into())
-                } else {
-                    println!("{:?}", *tag.data);
-                    let err = ReadError::Other("Invalid CID.".into());
-                    return Err(CborError::Decode(err).into())
-                }
-            } else {
-                let err = ReadError::Other("Unknown tag {}.".into());
-                return Err(CborError::Decode(err).into())
-            }
+        Ok(Ipld::Array(vec))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Ipld, A::Error> {
+        let mut object = HashMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((key, ipld)) = map.next_entry()? {
+            object.insert(key, ipld);
         }
-    };
-    Ok(ipld)
+        Ok(Ipld::Object(object))
+    }
 }
 
 impl Encodable for Ipld {
@@ -102,8 +159,9 @@ impl Encodable for Ipld {
                 e.emit_nil()
             },
             Ipld::Link(ref root) => {
-                let bytes = cbor::CborBytes(root.to_bytes());
-                cbor::CborTagEncode::new(42, &bytes).encode(e)
+                let mut bytes = vec![CID_MULTIBASE];
+                bytes.extend(root.to_bytes());
+                cbor::CborTagEncode::new(CID_TAG, &cbor::CborBytes(bytes)).encode(e)
             }
         }
     }
@@ -118,16 +176,46 @@ mod tests {
     fn test_encode_decode() {
         let data = Ipld::Array(vec![Ipld::U64(1), Ipld::U64(2), Ipld::U64(3)]);
         let bytes = encode(&data).unwrap();
-        let data2 = decode(bytes).unwrap();
+        let data2 = decode(&bytes).unwrap();
         assert_eq!(data, data2);
     }
 
     #[test]
     fn test_cid_encode_decode() {
         let cid = Block::from("hello").cid().to_owned();
-        let data = Ipld::Link(cid.into());
+        let data = Ipld::Link(cid.clone().into());
         let bytes = encode(&data).unwrap();
-        let data2 = decode(bytes).unwrap();
+        assert_eq!(bytes[..5], [0xd8, 0x2a, 0x58, cid.to_bytes().len() as u8 + 1, CID_MULTIBASE]);
+        assert_eq!(bytes[5..], cid.to_bytes()[..]);
+        let data2 = decode(&bytes).unwrap();
         assert_eq!(data, data2);
     }
+
+    #[test]
+    fn test_decode_nested_links() {
+        let cid = Block::from("hello").cid().to_owned();
+        let mut map = HashMap::new();
+        map.insert("link", Ipld::from(cid.clone()));
+        map.insert("links", Ipld::Array(vec![cid.clone().into(), Ipld::Null]));
+        map.insert("bytes", Ipld::Bytes(cid.to_bytes()));
+        map.insert("neg", Ipld::I64(-3));
+        let data = Ipld::from(map);
+        let bytes = encode(&data).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn test_decode_invalid_tags() {
+        // tag 42 around an integer.
+        assert!(decode(b"\xd8\x2a\x01").is_err());
+        // tag 42 around bytes that are not a cid.
+        assert!(decode(b"\xd8\x2a\x42\x00\x01").is_err());
+        // tag 42 around a cid without the multibase prefix.
+        let cid = Block::from("hello").cid().to_bytes();
+        let mut bytes = vec![0xd8, 0x2a, 0x58, cid.len() as u8];
+        bytes.extend(&cid);
+        assert!(decode(&bytes).is_err());
+        // unknown tag.
+        assert!(decode(b"\xd8\x2b\x41\x01").is_err());
+    }
 }
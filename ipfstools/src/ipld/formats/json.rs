@@ -0,0 +1,186 @@
+use crate::block::Cid;
+use crate::error::Error;
+use crate::ipld::Ipld;
+use crate::path::{IpfsPath, PathRoot};
+use cid::Prefix;
+use multibase::Base;
+use serde_json::{Map, Number, Value};
+
+pub(crate) const PREFIX: Prefix = Prefix {
+    version: cid::Version::V1,
+    codec: cid::Codec::DagJSON,
+    mh_type: multihash::Hash::SHA2256,
+    mh_len: 32,
+};
+
+/// Encodes `data` as dag-json.
+///
+/// Links become `{"/": "<cid>"}` and byte strings `{"/": {"bytes": "<base64>"}}`.
+pub(crate) fn encode(data: &Ipld) -> Result<Vec<u8>, Error> {
+    Ok(serde_json::to_vec(&ipld_to_json(data)?)?)
+}
+
+/// Decodes dag-json `bytes`, the inverse of `encode`.
+pub(crate) fn decode(bytes: &[u8]) -> Result<Ipld, Error> {
+    json_to_ipld(serde_json::from_slice(bytes)?)
+}
+
+fn ipld_to_json(data: &Ipld) -> Result<Value, Error> {
+    let value = match data {
+        Ipld::U64(u) => Value::Number((*u).into()),
+        Ipld::I64(i) => Value::Number((*i).into()),
+        Ipld::F64(f) => match Number::from_f64(*f) {
+            Some(n) => Value::Number(n),
+            None => bail!("{} can not be represented in dag-json", f),
+        },
+        Ipld::Bool(b) => Value::Bool(*b),
+        Ipld::Null => Value::Null,
+        Ipld::String(string) => Value::String(string.to_owned()),
+        Ipld::Bytes(bytes) => {
+            // Strip the multibase prefix, dag-json uses plain unpadded base64.
+            let encoded = multibase::encode(Base::Base64, bytes)[1..].to_string();
+            let mut inner = Map::new();
+            inner.insert("bytes".into(), Value::String(encoded));
+            reserved(Value::Object(inner))
+        }
+        Ipld::Array(vec) => {
+            let vec = vec.iter().map(ipld_to_json).collect::<Result<_, _>>()?;
+            Value::Array(vec)
+        }
+        Ipld::Object(map) => {
+            let map = map.iter()
+                .map(|(k, v)| Ok((k.to_owned(), ipld_to_json(v)?)))
+                .collect::<Result<_, Error>>()?;
+            Value::Object(map)
+        }
+        Ipld::Link(root) => {
+            let link = match root {
+                PathRoot::Ipld(cid) => cid.to_string(),
+                root => root.to_string(),
+            };
+            reserved(Value::String(link))
+        }
+    };
+    Ok(value)
+}
+
+fn json_to_ipld(value: Value) -> Result<Ipld, Error> {
+    let ipld = match value {
+        Value::Null => Ipld::Null,
+        Value::Bool(b) => Ipld::Bool(b),
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                Ipld::U64(u)
+            } else if let Some(i) = n.as_i64() {
+                Ipld::I64(i)
+            } else {
+                Ipld::F64(n.as_f64().expect("a number is u64, i64 or f64; qed"))
+            }
+        }
+        Value::String(string) => Ipld::String(string),
+        Value::Array(vec) => {
+            let vec = vec.into_iter().map(json_to_ipld).collect::<Result<_, _>>()?;
+            Ipld::Array(vec)
+        }
+        Value::Object(mut map) => {
+            if map.len() == 1 {
+                if let Some(value) = map.remove("/") {
+                    return reserved_to_ipld(value);
+                }
+            }
+            let map = map.into_iter()
+                .map(|(k, v)| Ok((k, json_to_ipld(v)?)))
+                .collect::<Result<_, Error>>()?;
+            Ipld::Object(map)
+        }
+    };
+    Ok(ipld)
+}
+
+/// Decodes the `value` of a `{"/": value}` object, a link or a byte string.
+fn reserved_to_ipld(value: Value) -> Result<Ipld, Error> {
+    let ipld = match value {
+        Value::String(link) => {
+            if link.starts_with('/') {
+                Ipld::Link(IpfsPath::from_str(&link)?.root().to_owned())
+            } else {
+                Ipld::Link(Cid::from(link.as_str())?.into())
+            }
+        }
+        Value::Object(mut map) => {
+            let encoded = match (map.remove("bytes"), map.is_empty()) {
+                (Some(Value::String(encoded)), true) => encoded,
+                _ => bail!("expected {{\"bytes\": <base64>}} under the reserved key"),
+            };
+            let (_, bytes) = multibase::decode(format!("m{}", encoded))?;
+            Ipld::Bytes(bytes)
+        }
+        _ => bail!("expected a link or bytes under the reserved key"),
+    };
+    Ok(ipld)
+}
+
+/// Wraps `value` under the reserved `/` key.
+fn reserved(value: Value) -> Value {
+    let mut map = Map::new();
+    map.insert("/".into(), value);
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_encode() {
+        let data = Ipld::Array(vec![Ipld::U64(1), Ipld::String("a".into()), Ipld::Null]);
+        let bytes = encode(&data).unwrap();
+        assert_eq!(bytes, br#"[1,"a",null]"#.to_vec());
+    }
+
+    #[test]
+    fn test_encode_link_and_bytes() {
+        let cid = Cid::from("QmSy5pnHk1EnvE5dmJSyFKG5unXLGjPpBuJJCBQkBTvBaW").unwrap();
+        let data = Ipld::Array(vec![Ipld::Link(cid.into()), Ipld::Bytes(vec![1, 2, 3])]);
+        let bytes = encode(&data).unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            r#"[{"/":"QmSy5pnHk1EnvE5dmJSyFKG5unXLGjPpBuJJCBQkBTvBaW"},{"/":{"bytes":"AQID"}}]"#
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        let data = decode(br#"{"a":[1,-2,1.5,"b",true,null]}"#).unwrap();
+        let mut map = HashMap::new();
+        map.insert("a", Ipld::Array(vec![
+            Ipld::U64(1),
+            Ipld::I64(-2),
+            Ipld::F64(1.5),
+            Ipld::String("b".into()),
+            Ipld::Bool(true),
+            Ipld::Null,
+        ]));
+        assert_eq!(data, Ipld::from(map));
+    }
+
+    #[test]
+    fn test_encode_decode_link_and_bytes() {
+        let cid = Cid::from("QmSy5pnHk1EnvE5dmJSyFKG5unXLGjPpBuJJCBQkBTvBaW").unwrap();
+        let mut map = HashMap::new();
+        map.insert("link", Ipld::Link(cid.into()));
+        map.insert("bytes", Ipld::Bytes(vec![1, 2, 3, 4]));
+        let data = Ipld::from(map);
+        let bytes = encode(&data).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(decode(br#"{"/":"not a cid"}"#).is_err());
+        assert!(decode(br#"{"/":{"bytes":"AQID","extra":1}}"#).is_err());
+        assert!(decode(br#"{"/":1}"#).is_err());
+        assert!(decode(br#"[1,"#).is_err());
+    }
+}
@@ -1,2 +1,38 @@
 pub mod cbor;
+pub mod json;
 pub mod pb;
+
+use crate::error::Error;
+use crate::ipld::Ipld;
+
+/// Encodings a resolved dag node can be returned in, independently of how it is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputCodec {
+    DagJson,
+    DagCbor,
+}
+
+impl OutputCodec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputCodec::DagJson => "dag-json",
+            OutputCodec::DagCbor => "dag-cbor",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<OutputCodec> {
+        match s {
+            "dag-json" => Some(OutputCodec::DagJson),
+            "dag-cbor" => Some(OutputCodec::DagCbor),
+            _ => None,
+        }
+    }
+
+    /// Encodes `data` with this codec.
+    pub fn encode(&self, data: &Ipld) -> Result<Vec<u8>, Error> {
+        match self {
+            OutputCodec::DagJson => json::encode(data),
+            OutputCodec::DagCbor => cbor::encode(data),
+        }
+    }
+}
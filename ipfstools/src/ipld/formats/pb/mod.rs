@@ -28,6 +28,25 @@ pub(crate) fn encode(data: Ipld) -> Result<Vec<u8>, Error> {
     Ok(pb_node.into_bytes())
 }
 
+/// The target of the link called `name` in the dag-pb node `ipld`.
+pub(crate) fn named_link(ipld: &Ipld, name: &str) -> Option<Ipld> {
+    let links = match ipld {
+        Ipld::Object(map) => map.get("Links")?,
+        _ => return None,
+    };
+    let links = match links {
+        Ipld::Array(links) => links,
+        _ => return None,
+    };
+    links.iter().find_map(|link| match link {
+        Ipld::Object(link) => match link.get("Name") {
+            Some(Ipld::String(link_name)) if link_name == name => link.get("Hash").cloned(),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
 pub(crate) struct PbLink {
     pub cid: PathRoot,
     pub name: String,
@@ -40,7 +59,7 @@ pub(crate) struct PbNode {
 }
 
 impl PbNode {
-    fn from_bytes(bytes: &Vec<u8>) -> Result<Self, Error> {
+    pub(crate) fn from_bytes(bytes: &Vec<u8>) -> Result<Self, Error> {
         let proto: dag_pb::PBNode = protobuf::parse_from_bytes(bytes)?;
         let data = proto.get_Data().to_vec();
         let mut links = Vec::new();
@@ -60,7 +79,7 @@ impl PbNode {
         })
     }
 
-    fn into_bytes(self) -> Vec<u8> {
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
         let mut proto = dag_pb::PBNode::new();
         proto.set_Data(self.data);
         for link in self.links {
@@ -155,4 +174,33 @@ mod tests {
         let data2 = decode(&bytes).unwrap();
         assert_eq!(data, data2);
     }
+
+    #[test]
+    fn test_decode_go_ipfs_empty_dir() {
+        // `ipfs object new unixfs-dir`
+        let bytes = vec![0x0a, 0x02, 0x08, 0x01];
+        let cid = cid::Cid::new_from_prefix(&PREFIX, &bytes);
+        assert_eq!(cid.to_string(), "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn");
+
+        let links: Vec<Ipld> = vec![];
+        let mut pb_node = HashMap::<&str, Ipld>::new();
+        pb_node.insert("Data", vec![0x08u8, 0x01].into());
+        pb_node.insert("Links", links.into());
+        assert_eq!(decode(&bytes).unwrap(), pb_node.into());
+    }
+
+    #[test]
+    fn test_named_link() {
+        let cid = Cid::from("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+        let node: Ipld = PbNode {
+            links: vec![PbLink {
+                cid: cid.clone().into(),
+                name: "dir".into(),
+                size: 4,
+            }],
+            data: vec![8, 1],
+        }.into();
+        assert_eq!(named_link(&node, "dir"), Some(Ipld::from(cid)));
+        assert_eq!(named_link(&node, "file"), None);
+    }
 }
@@ -46,6 +46,12 @@ impl Ipld {
                     formats::pb::encode(self.to_owned())?,
                 )
             }
+            Codec::DagJSON => {
+                (
+                    formats::json::PREFIX,
+                    formats::json::encode(&self)?,
+                )
+            }
             codec => return Err(IpldError::UnsupportedCodec(codec).into()),
         };
         let cid = cid::Cid::new_from_prefix(&prefix, &bytes);
@@ -60,14 +66,23 @@ impl Ipld {
         self.to_block(Codec::DagProtobuf)
     }
 
+    pub fn to_dag_json(&self) -> Result<Block, Error> {
+        self.to_block(Codec::DagJSON)
+    }
+
     pub fn from(block: &Block) -> Result<Self, Error> {
         let data = match block.cid().prefix().codec {
             Codec::DagCBOR => {
-                formats::cbor::decode(block.data().to_owned())?
+                formats::cbor::decode(block.data())?
             }
             Codec::DagProtobuf => {
                 formats::pb::decode(block.data())?
             }
+            Codec::DagJSON => {
+                formats::json::decode(block.data())?
+            }
+            // the leaves of unixfs files added with raw leaves.
+            Codec::Raw => Ipld::Bytes(block.data().to_owned()),
             codec => return Err(IpldError::UnsupportedCodec(codec).into()),
         };
         Ok(data)
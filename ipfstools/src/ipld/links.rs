@@ -0,0 +1,267 @@
+//! Links of encoded blocks, found without decoding the blocks into `Ipld`.
+//!
+//! Gc, pinning and car export only need the links of the blocks they walk. Building every node
+//! just to collect its links was what made them slow, so dag-pb and dag-cbor blocks are scanned
+//! instead, stepping over the encoded values and keeping the cids they pass.
+use crate::block::{Block, Cid};
+use crate::error::Error;
+use crate::ipld::{dag, formats, IpldError};
+use crate::ipld::formats::cbor::CID_MULTIBASE;
+use cid::Codec;
+
+/// Nesting depth of dag-cbor values beyond which a block is rejected, as by the decoder.
+const MAX_DEPTH: usize = 128;
+/// Tag of the byte strings holding cids in dag-cbor.
+const CID_TAG: u64 = 42;
+/// Stop code of dag-cbor values of indefinite length.
+const BREAK: u8 = 0xff;
+
+/// Cids linked from the block `bytes` encoded with `codec`, in the order they are encoded in.
+///
+/// Raw blocks have no links. Dag-json blocks are decoded, links to ipns or dns paths are
+/// ignored like in decoded nodes.
+pub fn extract_links(codec: Codec, bytes: &[u8]) -> Result<Vec<Cid>, Error> {
+    let mut links = Vec::new();
+    match codec {
+        Codec::Raw => {}
+        Codec::DagProtobuf => pb_links(bytes, &mut links)?,
+        Codec::DagCBOR => {
+            let mut bytes = bytes;
+            cbor_links(&mut bytes, 0, &mut links)?;
+            if !bytes.is_empty() {
+                bail!("trailing bytes after dag-cbor value");
+            }
+        }
+        Codec::DagJSON => links = dag::links(&formats::json::decode(bytes)?),
+        codec => return Err(IpldError::UnsupportedCodec(codec).into()),
+    }
+    Ok(links)
+}
+
+/// Cids of the blocks linked from `block`.
+pub(crate) fn block_links(block: &Block) -> Result<Vec<Cid>, Error> {
+    extract_links(block.cid().prefix().codec, block.data())
+}
+
+/// Collects the `Hash` of every `Links` entry of a dag-pb node.
+fn pb_links(mut bytes: &[u8], links: &mut Vec<Cid>) -> Result<(), Error> {
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        match (key >> 3, key & 7) {
+            // PBNode.Links
+            (2, 2) => {
+                let mut link = read_len_delimited(&mut bytes)?;
+                while !link.is_empty() {
+                    let key = read_varint(&mut link)?;
+                    match (key >> 3, key & 7) {
+                        // PBLink.Hash
+                        (1, 2) => links.push(Cid::from(read_len_delimited(&mut link)?)?),
+                        (_, wire_type) => skip_pb_field(&mut link, wire_type)?,
+                    }
+                }
+            }
+            (_, wire_type) => skip_pb_field(&mut bytes, wire_type)?,
+        }
+    }
+    Ok(())
+}
+
+fn skip_pb_field(bytes: &mut &[u8], wire_type: u64) -> Result<(), Error> {
+    match wire_type {
+        0 => { read_varint(bytes)?; }
+        1 => { take(bytes, 8)?; }
+        2 => { read_len_delimited(bytes)?; }
+        5 => { take(bytes, 4)?; }
+        _ => bail!("unsupported protobuf wire type {}", wire_type),
+    }
+    Ok(())
+}
+
+/// Steps over the dag-cbor value at the start of `bytes`, collecting the cids in it.
+fn cbor_links(bytes: &mut &[u8], depth: usize, links: &mut Vec<Cid>) -> Result<(), Error> {
+    if depth > MAX_DEPTH {
+        bail!("dag-cbor value is nested deeper than {}", MAX_DEPTH);
+    }
+    let byte = take(bytes, 1)?[0];
+    let (major, info) = (byte >> 5, byte & 0x1f);
+    if info == 31 {
+        return cbor_indefinite(bytes, major, depth, links);
+    }
+    let arg = cbor_arg(bytes, info)?;
+    match major {
+        // integers, floats and simple values are all in `arg`.
+        0 | 1 | 7 => {}
+        2 | 3 => { take(bytes, arg)?; }
+        4 => {
+            for _ in 0..arg {
+                cbor_links(bytes, depth + 1, links)?;
+            }
+        }
+        5 => {
+            for _ in 0..arg {
+                cbor_links(bytes, depth + 1, links)?;
+                cbor_links(bytes, depth + 1, links)?;
+            }
+        }
+        6 if arg == CID_TAG => {
+            let byte = take(bytes, 1)?[0];
+            if byte >> 5 != 2 || byte & 0x1f == 31 {
+                bail!("dag-cbor cid is not a byte string");
+            }
+            let len = cbor_arg(bytes, byte & 0x1f)?;
+            match take(bytes, len)?.split_first() {
+                Some((&CID_MULTIBASE, cid)) => links.push(Cid::from(cid)?),
+                _ => bail!("dag-cbor cid has no multibase prefix"),
+            }
+        }
+        _ => bail!("unknown dag-cbor tag {}", arg),
+    }
+    Ok(())
+}
+
+/// Steps over a dag-cbor value of indefinite length, after its initial byte.
+fn cbor_indefinite(bytes: &mut &[u8], major: u8, depth: usize, links: &mut Vec<Cid>) -> Result<(), Error> {
+    loop {
+        if bytes.first() == Some(&BREAK) {
+            *bytes = &bytes[1..];
+            return Ok(());
+        }
+        match major {
+            // strings are split into chunks of definite length of the same type.
+            2 | 3 => {
+                let byte = take(bytes, 1)?[0];
+                if byte >> 5 != major || byte & 0x1f == 31 {
+                    bail!("invalid chunk of a dag-cbor string");
+                }
+                let len = cbor_arg(bytes, byte & 0x1f)?;
+                take(bytes, len)?;
+            }
+            4 => cbor_links(bytes, depth + 1, links)?,
+            5 => {
+                cbor_links(bytes, depth + 1, links)?;
+                cbor_links(bytes, depth + 1, links)?;
+            }
+            _ => bail!("dag-cbor major type {} has no indefinite length", major),
+        }
+    }
+}
+
+/// The argument of a dag-cbor initial byte with additional information `info`.
+fn cbor_arg(bytes: &mut &[u8], info: u8) -> Result<u64, Error> {
+    let len = match info {
+        0..=23 => return Ok(u64::from(info)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => bail!("invalid dag-cbor additional information {}", info),
+    };
+    Ok(take(bytes, len)?.iter().fold(0, |arg, byte| arg << 8 | u64::from(*byte)))
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Ok(value);
+        }
+    }
+    bail!("invalid varint in dag-pb node")
+}
+
+fn read_len_delimited<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    let len = read_varint(bytes)?;
+    take(bytes, len)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: u64) -> Result<&'a [u8], Error> {
+    if len > bytes.len() as u64 {
+        bail!("block is truncated");
+    }
+    let (head, tail) = bytes.split_at(len as usize);
+    *bytes = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipld::formats::pb::{PbLink, PbNode};
+    use crate::ipld::Ipld;
+    use std::collections::HashMap;
+
+    fn cid(data: &str) -> Cid {
+        Block::from(data).cid().to_owned()
+    }
+
+    #[test]
+    fn test_extract_cbor_links() {
+        let mut map = HashMap::new();
+        map.insert("link", cid("a").into());
+        map.insert("links", Ipld::Array(vec![cid("b").into(), Ipld::Null, Ipld::F64(1.5)]));
+        map.insert("bytes", Ipld::Bytes(cid("c").to_bytes()));
+        map.insert("neg", Ipld::I64(-300));
+        map.insert("text", Ipld::String("x".repeat(300)));
+        let ipld: Ipld = map.into();
+        let block = ipld.to_dag_cbor().unwrap();
+
+        let mut links = extract_links(Codec::DagCBOR, block.data()).unwrap();
+        let mut expected = dag::links(&Ipld::from(&block).unwrap());
+        links.sort_by_key(|cid| cid.to_string());
+        expected.sort_by_key(|cid| cid.to_string());
+        assert_eq!(links, expected);
+        assert_eq!(links.len(), 2);
+    }
+
+    #[test]
+    fn test_extract_cbor_indefinite_links() {
+        let mut link = vec![CID_MULTIBASE];
+        link.extend(cid("a").to_bytes());
+        // [_ h'..', 42(h'00<cid>'), {_ "k": 42(h'00<cid>')}]
+        let mut bytes = vec![0x9f, 0x5f, 0x41, 0x01, 0xff, 0xd8, 0x2a, 0x58, link.len() as u8];
+        bytes.extend(&link);
+        bytes.extend(&[0xbf, 0x61, b'k', 0xd8, 0x2a, 0x58, link.len() as u8]);
+        bytes.extend(&link);
+        bytes.extend(&[0xff, 0xff]);
+
+        assert_eq!(extract_links(Codec::DagCBOR, &bytes).unwrap(), vec![cid("a"), cid("a")]);
+    }
+
+    #[test]
+    fn test_extract_invalid_cbor() {
+        // truncated array.
+        assert!(extract_links(Codec::DagCBOR, b"\x82\x01").is_err());
+        // unknown tag.
+        assert!(extract_links(Codec::DagCBOR, b"\xd8\x2b\x41\x01").is_err());
+        // tag 42 around bytes that are not a cid.
+        assert!(extract_links(Codec::DagCBOR, b"\xd8\x2a\x42\x00\x01").is_err());
+        // tag 42 around a cid without the multibase prefix.
+        let link = cid("a").to_bytes();
+        let mut bytes = vec![0xd8, 0x2a, 0x58, link.len() as u8];
+        bytes.extend(&link);
+        assert!(extract_links(Codec::DagCBOR, &bytes).is_err());
+        // trailing bytes.
+        assert!(extract_links(Codec::DagCBOR, b"\x01\x01").is_err());
+        // nested too deeply.
+        assert!(extract_links(Codec::DagCBOR, &[0x81; MAX_DEPTH + 2]).is_err());
+    }
+
+    #[test]
+    fn test_extract_pb_links() {
+        let links = vec![cid("a"), cid("b")];
+        let node = PbNode {
+            links: links.iter().map(|cid| PbLink { cid: cid.clone().into(), name: "x".into(), size: 1 }).collect(),
+            data: vec![8, 1],
+        };
+
+        assert_eq!(extract_links(Codec::DagProtobuf, &node.into_bytes()).unwrap(), links);
+        assert!(extract_links(Codec::DagProtobuf, &[0x12, 0x05, 0x0a]).is_err());
+    }
+
+    #[test]
+    fn test_extract_raw_links() {
+        assert_eq!(extract_links(Codec::Raw, b"\xd8\x2a").unwrap(), vec![]);
+    }
+}
@@ -2,7 +2,10 @@ pub mod dag;
 pub mod error;
 pub mod formats;
 pub mod ipld;
+pub mod links;
 
 pub use self::dag::IpldDag;
 pub use self::error::IpldError;
+pub use self::formats::OutputCodec;
 pub use self::ipld::Ipld;
+pub use self::links::extract_links;
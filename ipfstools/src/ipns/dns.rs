@@ -1,58 +1,144 @@
 use crate::error::Error;
-use crate::path::IpfsPath;
+use crate::path::{IpfsPath, PathRoot, SubPath};
 use domain::core::bits::{Dname, Question};
 use domain::core::iana::Rtype;
 use domain::core::rdata::Txt;
 use domain::resolv::{Resolver, StubResolver};
 use domain::resolv::stub::resolver::Query;
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
-use std::task::{Poll, Waker};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 use std::str::FromStr;
-use tokio::prelude::{Async, Future as FutureOld, future::SelectOk, future::select_ok};
+use std::time::{Duration, Instant};
+use futures::compat::{Compat01As03, Future01CompatExt};
+use futures01::future::{select_ok, SelectOk};
+
+/// Number of chained dnslink names followed by default, as in go-ipfs.
+pub const DEFAULT_MAX_DEPTH: usize = 32;
 
 #[derive(Debug, Fail)]
 #[fail(display = "no dnslink entry")]
 pub struct DnsLinkError;
 
+/// Resolves dnslink names, following names that link to other names and caching every answer
+/// for the ttl of its TXT record.
+#[derive(Clone, Debug)]
+pub struct DnsResolver {
+    max_depth: usize,
+    cache: Arc<Mutex<HashMap<String, (IpfsPath, Instant)>>>,
+}
+
+impl Default for DnsResolver {
+    fn default() -> Self {
+        DnsResolver::new(DEFAULT_MAX_DEPTH)
+    }
+}
+
+impl DnsResolver {
+    /// A resolver following at most `max_depth` dnslink names per resolution.
+    pub fn new(max_depth: usize) -> Self {
+        DnsResolver {
+            max_depth,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Resolves the dnslink of `domain` to a path that is not a dnslink.
+    ///
+    /// Sub paths of the names along the way are appended in order, so if `a.com` links to
+    /// `/ipns/b.com/x` and `b.com` links to `/ipfs/Qm..`, `a.com` resolves to `/ipfs/Qm../x`.
+    pub fn resolve(&self, domain: &str) -> impl Future<Output=Result<IpfsPath, Error>> {
+        let resolver = self.clone();
+        let mut domain = domain.to_owned();
+        async move {
+            let mut rest: Vec<SubPath> = Vec::new();
+            for _ in 0..resolver.max_depth {
+                let path = match resolver.cached(&domain) {
+                    Some(path) => path,
+                    None => {
+                        let (path, ttl) = lookup(&domain)?.await?;
+                        resolver.insert(&domain, path.clone(), ttl);
+                        path
+                    }
+                };
+                match path.root() {
+                    PathRoot::Dns(next) => {
+                        rest = path.iter().cloned().chain(rest).collect();
+                        domain = next.to_owned();
+                    }
+                    _ => {
+                        let mut path = path;
+                        for sub_path in rest {
+                            path.push(sub_path);
+                        }
+                        return Ok(path);
+                    }
+                }
+            }
+            bail!("dnslink {} is chained deeper than {} names", domain, resolver.max_depth)
+        }
+    }
+
+    fn cached(&self, domain: &str) -> Option<IpfsPath> {
+        match self.cache.lock().unwrap().get(domain) {
+            Some((path, expires)) if Instant::now() < *expires => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, domain: &str, path: IpfsPath, ttl: Duration) {
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (_, expires)| now < *expires);
+        cache.insert(domain.to_owned(), (path, now + ttl));
+    }
+}
+
+/// Looks up the dnslink of a single name, without following it.
+///
+/// The resolver of `domain` still returns futures 0.1 futures, which are polled through
+/// `Compat01As03`.
 pub struct DnsLinkFuture {
-    query: SelectOk<Query>,
+    query: Compat01As03<SelectOk<Query>>,
 }
 
 impl Future for DnsLinkFuture {
-    type Output = Result<IpfsPath, Error>;
+    /// The linked path and the ttl of its record.
+    type Output = Result<(IpfsPath, Duration), Error>;
 
-    fn poll(self: Pin<&mut Self>, _waker: &Waker) -> Poll<Self::Output> {
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         let _self = self.get_mut();
         loop {
-            let poll = _self.query.poll();
-            if poll.is_err() {
-                return Poll::Ready(Err(DnsLinkError.into()));
-            }
-            match poll.unwrap() {
-                Async::Ready((answer, rest)) => {
+            let poll = Pin::new(&mut _self.query).poll(cx);
+            match poll {
+                Poll::Ready(Err(_)) => return Poll::Ready(Err(DnsLinkError.into())),
+                Poll::Ready(Ok((answer, rest))) => {
                     for record in answer.answer()?.limit_to::<Txt>() {
                         let txt = record?;
                         let bytes = txt.data().text();
                         let string = String::from_utf8_lossy(&bytes).to_string();
                         if string.starts_with("dnslink=") {
                             let path = IpfsPath::from_str(&string[8..])?;
-                            return Poll::Ready(Ok(path));
+                            let ttl = Duration::from_secs(u64::from(record.ttl()));
+                            return Poll::Ready(Ok((path, ttl)));
                         }
                     }
                     if rest.len() > 0 {
-                        _self.query = select_ok(rest);
+                        _self.query = select_ok(rest).compat();
                     } else {
                         return Poll::Ready(Err(DnsLinkError.into()))
                     }
                 }
-                Async::NotReady => return Poll::Pending,
+                Poll::Pending => return Poll::Pending,
             }
         }
     }
 }
 
-pub fn resolve(domain: &str) -> Result<DnsLinkFuture, Error> {
+/// Queries the TXT records of both `domain` and `_dnslink.domain`.
+pub fn lookup(domain: &str) -> Result<DnsLinkFuture, Error> {
     let mut dnslink = "_dnslink.".to_string();
     dnslink.push_str(domain);
     let qname = Dname::from_str(&dnslink[9..])?;
@@ -64,7 +150,7 @@ pub fn resolve(domain: &str) -> Result<DnsLinkFuture, Error> {
     let query2 = StubResolver::new().query(question);
 
     Ok(DnsLinkFuture {
-        query: select_ok(vec![query1, query2]),
+        query: select_ok(vec![query1, query2]).compat(),
     })
 }
 
@@ -72,18 +158,47 @@ pub fn resolve(domain: &str) -> Result<DnsLinkFuture, Error> {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_resolve1() {
-        tokio::run_async(async {
-            let res = await!(resolve("ipfs.io").unwrap()).unwrap().to_string();
-            assert_eq!(res, "/ipns/website.ipfs.io");
-        })
+    const CID: &str = "QmYfHCcUQBjyvrLfQ8Cnt2YAEiLDNRqMXAeHndM6fDW8yB";
+
+    #[tokio::test]
+    async fn test_resolve1() {
+        let (res, _) = lookup("ipfs.io").unwrap().await.unwrap();
+        assert_eq!(res.to_string(), "/ipns/website.ipfs.io");
     }
 
-    fn test_resolve2() {
-        tokio::run_async(async {
-            let res = await!(resolve("website.ipfs.io").unwrap()).unwrap().to_string();
-            assert_eq!(res, "/ipfs/QmYfHCcUQBjyvrLfQ8Cnt2YAEiLDNRqMXAeHndM6fDW8yB");
-        })
+    async fn test_resolve2() {
+        let (res, _) = lookup("website.ipfs.io").unwrap().await.unwrap();
+        assert_eq!(res.to_string(), format!("/ipfs/{}", CID));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_chained_from_cache() {
+        let resolver = DnsResolver::default();
+        let ttl = Duration::from_secs(60);
+        resolver.insert("a.example", IpfsPath::from_str("/ipns/b.example/x").unwrap(), ttl);
+        resolver.insert("b.example", IpfsPath::from_str(&format!("/ipfs/{}/y", CID)).unwrap(), ttl);
+
+        let res = resolver.resolve("a.example").await.unwrap();
+        assert_eq!(res.to_string(), format!("/ipfs/{}/y/x", CID));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_max_depth() {
+        let resolver = DnsResolver::new(4);
+        let ttl = Duration::from_secs(60);
+        resolver.insert("a.example", IpfsPath::from_str("/ipns/b.example").unwrap(), ttl);
+        resolver.insert("b.example", IpfsPath::from_str("/ipns/a.example").unwrap(), ttl);
+
+        assert!(resolver.resolve("a.example").await.is_err());
+    }
+
+    #[test]
+    fn test_cache_expires() {
+        let resolver = DnsResolver::default();
+        let path = IpfsPath::from_str(&format!("/ipfs/{}", CID)).unwrap();
+        resolver.insert("fresh.example", path.clone(), Duration::from_secs(60));
+        resolver.insert("stale.example", path.clone(), Duration::from_secs(0));
+        assert_eq!(resolver.cached("fresh.example"), Some(path));
+        assert_eq!(resolver.cached("stale.example"), None);
     }
 }
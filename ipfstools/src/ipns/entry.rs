@@ -1,12 +1,16 @@
 use crate::error::Error;
 use crate::ipns::ipns_pb as proto;
+use crate::ipns::key::{self, IpnsKey};
 use crate::path::IpfsPath;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use libp2p::PeerId;
 use libp2p::core::PublicKey;
-use libp2p::secio::SecioKeyPair;
 use protobuf::{self, ProtobufError, Message as ProtobufMessage};
 use std::time::{Duration, SystemTime};
 
+/// How long a published record is valid, as in go-ipfs.
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct IpnsEntry {
     value: String,
@@ -17,10 +21,10 @@ pub struct IpnsEntry {
 }
 
 impl IpnsEntry {
-    pub fn new(value: String, seq: u64, ttl: Duration, key: &SecioKeyPair) -> Self {
+    pub fn new(value: String, seq: u64, ttl: Duration, key: &IpnsKey) -> Self {
         let validity = SystemTime::now() + ttl;
-        let public_key = key.to_public_key();
-        let signature = IpnsEntry::sign(&validity, &value, &key);
+        let public_key = key.public_key();
+        let signature = key.sign(&IpnsEntry::signing_bytes(&validity, &value));
         IpnsEntry {
             value,
             seq,
@@ -34,30 +38,24 @@ impl IpnsEntry {
         self.seq
     }
 
-    pub fn from_path(path: &IpfsPath, seq: u64, key: &SecioKeyPair) -> Self {
-        let value = path.to_string();
-        // TODO what is a reasonable default?
-        let ttl = Duration::new(1, 0);
-        IpnsEntry::new(value, seq, ttl, key)
+    pub fn from_path(path: &IpfsPath, seq: u64, key: &IpnsKey) -> Self {
+        IpnsEntry::new(path.to_string(), seq, DEFAULT_LIFETIME, key)
     }
 
-    fn sign(_validity: &SystemTime, _value: &String, _key: &SecioKeyPair) -> Vec<u8> {
-        // TODO
-        Vec::new()
+    /// The signed part of the record: value, validity and validity type, as in go-ipfs.
+    fn signing_bytes(validity: &SystemTime, value: &str) -> Vec<u8> {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.extend_from_slice(&validity_bytes(validity));
+        bytes.extend_from_slice(b"EOL");
+        bytes
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut proto = proto::IpnsEntry::new();
         proto.set_value(self.value.as_bytes().to_vec());
         proto.set_sequence(self.seq);
-        let nanos = self.validity
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        let mut validity = vec![];
-        validity.write_u64::<BigEndian>(nanos as u64).unwrap();
         proto.set_validityType(proto::IpnsEntry_ValidityType::EOL);
-        proto.set_validity(validity);
+        proto.set_validity(validity_bytes(&self.validity));
         proto.set_signature(self.signature.clone());
         proto.set_pubKey(self.public_key.clone().into_protobuf_encoding());
         proto
@@ -81,9 +79,21 @@ impl IpnsEntry {
         Ok(ipns)
     }
 
+    /// Whether the record is signed by its public key and not expired.
     pub fn is_valid(&self) -> bool {
-        // TODO
-        true
+        let signed = IpnsEntry::signing_bytes(&self.validity, &self.value);
+        key::verify(&self.public_key, &signed, &self.signature) && SystemTime::now() < self.validity
+    }
+
+    /// Checks that the record is valid and published by `peer_id`.
+    pub fn verify(&self, peer_id: &PeerId) -> Result<(), Error> {
+        if &self.public_key.clone().into_peer_id() != peer_id {
+            bail!("ipns record for {} is signed by another key", peer_id.to_base58());
+        }
+        if !self.is_valid() {
+            bail!("ipns record for {} has an invalid signature or expired", peer_id.to_base58());
+        }
+        Ok(())
     }
 
     pub fn resolve(&self) -> Result<IpfsPath, Error> {
@@ -91,6 +101,16 @@ impl IpnsEntry {
     }
 }
 
+fn validity_bytes(validity: &SystemTime) -> Vec<u8> {
+    let nanos = validity
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let mut bytes = vec![];
+    bytes.write_u64::<BigEndian>(nanos as u64).unwrap();
+    bytes
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,16 +119,30 @@ mod tests {
     fn test_valid() {
         let value = "/ipfs/".into();
         let duration = Duration::new(1, 0);
-        let key = SecioKeyPair::ed25519_generated().unwrap();
+        let key = IpnsKey::generate();
         let ipns = IpnsEntry::new(value, 0, duration, &key);
         assert!(ipns.is_valid());
     }
 
+    #[test]
+    fn test_invalid() {
+        let key = IpnsKey::generate();
+        let mut ipns = IpnsEntry::new("/ipfs/".into(), 0, Duration::new(1, 0), &key);
+        assert!(ipns.verify(&key.peer_id()).is_ok());
+        assert!(ipns.verify(&IpnsKey::generate().peer_id()).is_err());
+
+        ipns.value = "/ipfs/other".into();
+        assert!(!ipns.is_valid());
+
+        let expired = IpnsEntry::new("/ipfs/".into(), 0, Duration::new(0, 0), &key);
+        assert!(!expired.is_valid());
+    }
+
     #[test]
     fn test_to_from_bytes() {
         let value = "/ipfs/".into();
         let duration = Duration::new(1, 0);
-        let key = SecioKeyPair::ed25519_generated().unwrap();
+        let key = IpnsKey::generate();
         let ipns = IpnsEntry::new(value, 0, duration, &key);
         let bytes = ipns.to_bytes();
         let ipns2 = IpnsEntry::from_bytes(&bytes).unwrap();
@@ -117,7 +151,7 @@ mod tests {
 
     #[test]
     fn test_from_path() {
-        let key = SecioKeyPair::ed25519_generated().unwrap();
+        let key = IpnsKey::generate();
         let path = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8").unwrap();
         let ipns = IpnsEntry::from_path(&path, 0, &key);
         assert_eq!(path, ipns.resolve().unwrap());
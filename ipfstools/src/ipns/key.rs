@@ -0,0 +1,83 @@
+use crate::error::Error;
+use ed25519_dalek::{Keypair, PublicKey as Ed25519PublicKey, SecretKey, Signature};
+use libp2p::PeerId;
+use libp2p::core::PublicKey;
+use std::sync::Arc;
+
+/// The ed25519 key ipns records are signed with.
+///
+/// Built from the same raw key as the node's secio key, so names published with it resolve
+/// under the node's peer id.
+#[derive(Clone, Debug)]
+pub struct IpnsKey {
+    keypair: Arc<Keypair>,
+}
+
+impl IpnsKey {
+    pub fn from_raw_key(raw_key: &[u8; 32]) -> Result<Self, Error> {
+        let secret = match SecretKey::from_bytes(raw_key) {
+            Ok(secret) => secret,
+            Err(_) => bail!("invalid ed25519 key"),
+        };
+        let public = Ed25519PublicKey::from(&secret);
+        Ok(IpnsKey {
+            keypair: Arc::new(Keypair { secret, public }),
+        })
+    }
+
+    pub fn generate() -> Self {
+        IpnsKey::from_raw_key(&rand::random()).expect("every 32 bytes are a valid ed25519 key")
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::Ed25519(self.keypair.public.to_bytes().to_vec())
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key().into_peer_id()
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.keypair.sign(message).to_bytes().to_vec()
+    }
+}
+
+/// Whether `signature` is the signature of `message` by `public_key`.
+///
+/// Only ed25519 keys are supported.
+pub fn verify(public_key: &PublicKey, message: &[u8], signature: &[u8]) -> bool {
+    let public_key = match public_key {
+        PublicKey::Ed25519(bytes) => match Ed25519PublicKey::from_bytes(bytes) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+    match Signature::from_bytes(signature) {
+        Ok(signature) => public_key.verify(message, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::secio::SecioKeyPair;
+
+    #[test]
+    fn test_peer_id_matches_secio() {
+        let raw_key = [7u8; 32];
+        let key = IpnsKey::from_raw_key(&raw_key).unwrap();
+        let secio = SecioKeyPair::ed25519_raw_key(&raw_key).unwrap();
+        assert_eq!(key.peer_id(), secio.to_peer_id());
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let key = IpnsKey::generate();
+        let signature = key.sign(b"message");
+        assert!(verify(&key.public_key(), b"message", &signature));
+        assert!(!verify(&key.public_key(), b"massage", &signature));
+        assert!(!verify(&IpnsKey::generate().public_key(), b"message", &signature));
+    }
+}
@@ -1,54 +1,215 @@
 #![allow(dead_code)]
+use crate::context::Context;
 use crate::error::Error;
 use crate::path::{IpfsPath, PathRoot};
-use crate::repo::{Repo, RepoTypes};
+use crate::repo::{Column, DataStore, Repo, RepoTypes};
+use libp2p::PeerId;
 use std::future::Future;
 
 mod dns;
 mod entry;
 mod ipns_pb;
+mod key;
 
+use self::entry::IpnsEntry;
+pub use self::dns::DnsResolver;
+pub use self::key::IpnsKey;
+
+/// Ipns names, with the signed records published by the node stored in `Column::Ipns`.
 pub struct Ipns<Types: RepoTypes> {
-    repo: Repo<Types>,
+    data_store: Types::TDataStore,
+    /// The node's ipns key, names published with it resolve under the node's peer id.
+    key: IpnsKey,
+    dns: DnsResolver,
 }
 
 impl<Types: RepoTypes> Ipns<Types> {
-    pub fn new(repo: Repo<Types>) -> Self {
+    pub fn new(data_store: Types::TDataStore, key: IpnsKey) -> Self {
         Ipns {
-            repo
+            data_store,
+            key,
+            dns: DnsResolver::default(),
+        }
+    }
+
+    /// Names stored in `repo`, published with `key`.
+    pub fn from_repo(repo: &Repo<Types>, key: IpnsKey) -> Self {
+        Ipns::new(repo.data_store().clone(), key)
+    }
+
+    /// Resolves dnslink names with `dns` instead of a resolver of its own.
+    pub fn with_dns_resolver(mut self, dns: DnsResolver) -> Self {
+        self.dns = dns;
+        self
+    }
+
+    /// Returns the ipns name derived from the node's ipns key.
+    pub fn self_name(&self) -> IpfsPath {
+        IpfsPath::new(PathRoot::Ipns(self.key.peer_id()))
+    }
+
+    /// Publishes `path` under the node's own name, see `self_name`.
+    ///
+    /// The record is signed with the node's key and replaces the previous one with the next
+    /// sequence number.
+    pub fn publish_self(&self, path: &IpfsPath) ->
+    impl Future<Output=Result<IpfsPath, Error>>
+    {
+        let put = put_record(self.data_store.clone(), self.key.clone(), path.to_owned());
+        let name = self.self_name();
+        async move {
+            put.await?;
+            Ok(name)
         }
     }
 
     /// Resolves a ipns path to an ipld path.
-    pub fn resolve(&self, path: &IpfsPath) ->
+    ///
+    /// Names resolving to other names are followed, keeping the sub path of each. Dnslink names
+    /// are only resolved if `ctx` wants the network. Ipns records are checked to be signed by the
+    /// name's key and not expired. A name without a record resolves to itself.
+    pub fn resolve(&self, path: &IpfsPath, ctx: Context) ->
     impl Future<Output=Result<IpfsPath, Error>>
     {
-        let path = path.to_owned();
+        let mut path = path.to_owned();
+        let data_store = self.data_store.clone();
+        let dns = self.dns.clone();
         async move {
-            match path.root() {
-                PathRoot::Ipld(_) => Ok(path),
-                PathRoot::Dns(domain) => {
-                    Ok(await!(dns::resolve(domain)?)?)
-                },
-                _ => Ok(path),
+            for _ in 0..dns::DEFAULT_MAX_DEPTH {
+                ctx.check()?;
+                let mut resolved = match path.root() {
+                    PathRoot::Ipld(_) => return Ok(path),
+                    PathRoot::Dns(domain) => {
+                        if !ctx.wants_network() {
+                            bail!("can't resolve dnslink {} offline", domain);
+                        }
+                        dns.resolve(domain).await?
+                    },
+                    PathRoot::Ipns(peer_id) => {
+                        match get_record(&data_store, peer_id).await? {
+                            Some(entry) => {
+                                entry.verify(peer_id)?;
+                                entry.resolve()?
+                            }
+                            None => return Ok(path),
+                        }
+                    },
+                };
+                for sub_path in path.iter() {
+                    resolved.push(sub_path.to_owned());
+                }
+                path = resolved;
             }
+            bail!("{} is chained deeper than {} names", path.to_string(), dns::DEFAULT_MAX_DEPTH)
         }
     }
 
-    /// Publishes an ipld path.
+    /// Publishes `path` under the node's own name, resolving ipns and dnslink paths first.
     pub fn publish(&self, path: &IpfsPath) ->
     impl Future<Output=Result<IpfsPath, Error>>
     {
+        let resolve = self.resolve(path, Context::default());
         let path = path.to_owned();
+        let name = self.self_name();
+        let key = self.key.clone();
+        let data_store = self.data_store.clone();
         async move {
-            match path.root() {
-                PathRoot::Ipld(_) => Ok(path),
-                PathRoot::Dns(domain) => {
-                    Ok(await!(dns::resolve(domain)?)?)
-                },
-                _ => Ok(path),
+            let path = match path.root() {
+                PathRoot::Ipld(_) => path,
+                _ => resolve.await?,
+            };
+            put_record(data_store, key, path).await?;
+            Ok(name)
+        }
+    }
+}
 
-            }
+/// Signs a record for `path` with `key` and stores it, replacing the previous record of the key
+/// with the next sequence number.
+fn put_record<TDataStore: DataStore>(data_store: TDataStore, key: IpnsKey, path: IpfsPath) ->
+impl Future<Output=Result<(), Error>>
+{
+    async move {
+        let peer_id = key.peer_id();
+        let previous = get_record(&data_store, &peer_id).await?;
+        let seq = previous.map(|entry| entry.seq() + 1).unwrap_or(0);
+        let entry = IpnsEntry::from_path(&path, seq, &key);
+        data_store.put(Column::Ipns, peer_id.as_bytes(), &entry.to_bytes()).await
+    }
+}
+
+/// The stored record of `peer_id`, if any.
+fn get_record<TDataStore: DataStore>(data_store: &TDataStore, peer_id: &PeerId) ->
+impl Future<Output=Result<Option<IpnsEntry>, Error>>
+{
+    let get = data_store.get(Column::Ipns, peer_id.as_bytes());
+    async move {
+        match get.await? {
+            Some(bytes) => Ok(Some(IpnsEntry::from_bytes(&bytes)?)),
+            None => Ok(None),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::tests::{create_mock_repo, Types};
+
+    fn create_ipns() -> Ipns<Types> {
+        Ipns::new(create_mock_repo().data_store().clone(), IpnsKey::generate())
+    }
+
+    #[tokio::test]
+    async fn test_publish_self() {
+        let ipns = create_ipns();
+        let path = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8").unwrap();
+
+        let name = ipns.publish_self(&path).await.unwrap();
+        assert_eq!(name, ipns.self_name());
+        assert_eq!(name.root().peer_id(), Some(&ipns.key.peer_id()));
+        assert_eq!(ipns.resolve(&name, Context::default()).await.unwrap(), path);
+    }
+
+    #[tokio::test]
+    async fn test_publish_increments_seq() {
+        let ipns = create_ipns();
+        let first = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8").unwrap();
+        let second = IpfsPath::from_str("/ipfs/QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+
+        let name = ipns.publish(&first).await.unwrap();
+        // publishing a name publishes what it resolves to.
+        assert_eq!(ipns.publish(&name).await.unwrap(), name);
+        ipns.publish(&second).await.unwrap();
+
+        let record = get_record(&ipns.data_store, &ipns.key.peer_id()).await.unwrap().unwrap();
+        assert_eq!(record.seq(), 2);
+        assert_eq!(ipns.resolve(&name, Context::default()).await.unwrap(), second);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_chained_sub_path() {
+        let ipns = create_ipns();
+        let other = Ipns::<Types>::new(ipns.data_store.clone(), IpnsKey::generate());
+        let path = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8/a").unwrap();
+
+        let name = other.publish_self(&path).await.unwrap();
+        let name = ipns.publish_self(&name.sub_path("b").unwrap()).await.unwrap();
+        let resolved = ipns.resolve(&name.sub_path("c").unwrap(), Context::default()).await.unwrap();
+        assert_eq!(resolved, path.sub_path("b/c").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_rejects_foreign_record() {
+        let ipns = create_ipns();
+        let other = Ipns::<Types>::new(ipns.data_store.clone(), IpnsKey::generate());
+        let path = IpfsPath::from_str("/ipfs/QmUJPTFZnR2CPGAzmfdYPghgrFtYFB6pf1BqMvqfiPDam8").unwrap();
+
+        let name = ipns.publish_self(&path).await.unwrap();
+        // a record signed by another key stored under our name.
+        let forged = IpnsEntry::from_path(&path, 1, &other.key).to_bytes();
+        let peer_id = ipns.key.peer_id();
+        ipns.data_store.put(Column::Ipns, peer_id.as_bytes(), &forged).await.unwrap();
+        assert!(ipns.resolve(&name, Context::default()).await.is_err());
+    }
+}
@@ -1,24 +1,29 @@
 //! IPFS node implementation
 //#![deny(missing_docs)]
 #![deny(warnings)]
-#![feature(async_await, await_macro, futures_api)]
 #![feature(drain_filter)]
 #![feature(try_trait)]
 
 #[macro_use] extern crate failure;
+#[macro_use] extern crate lazy_static;
 #[macro_use] extern crate log;
+#[macro_use] extern crate prometheus;
+use futures::compat::{Compat01As03, Stream01CompatExt};
 use futures::prelude::*;
 pub use libp2p::PeerId;
+use near_primitives::crypto::keystore::Keystore;
 use std::marker::PhantomData;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::mpsc::{channel, Sender, Receiver};
-use std::task::{Poll, Waker};
-use tokio::prelude::{Async, Stream as StreamOld};
+use std::task::{Context as TaskContext, Poll};
 
 pub mod bitswap;
 pub mod block;
+pub mod car;
+pub mod cid_profile;
 mod config;
+pub mod context;
 pub mod error;
 mod future;
 pub mod ipld;
@@ -29,22 +34,30 @@ pub mod repo;
 pub mod unixfs;
 
 pub use self::block::{Block, Cid};
+pub use self::cid_profile::CidProfile;
 use self::config::ConfigFile;
+pub use self::context::Context;
 pub use self::error::Error;
 use self::ipld::IpldDag;
-pub use self::ipld::Ipld;
+pub use self::ipld::{Ipld, OutputCodec};
 use self::ipns::Ipns;
 pub use self::p2p::SwarmTypes;
 use self::p2p::{create_swarm, SwarmOptions, TSwarm};
 pub use self::path::IpfsPath;
-pub use self::repo::RepoTypes;
-use self::repo::{create_repo, RepoOptions, Repo, RepoEvent};
+use self::path::PathRoot;
+pub use self::repo::{BlockCount, GcReport, GetBlockOptions, PinProgress, PutTimings, RepoStats, RepoTypes};
+use self::repo::{create_repo, Journal, RepoOptions, Repo, RepoEvent};
+pub use self::unixfs::AddOptions;
 use self::unixfs::File;
 
 static IPFS_LOG: &str = "info";
 static IPFS_PATH: &str = ".ipfstools";
+static IPFS_CID_VERSION: &str = "0";
+static IPFS_CID_BASE: &str = "base58btc";
 static XDG_APP_NAME: &str = "ipfstools";
 static CONFIG_FILE: &str = "config.json";
+/// Directory of the keystore in the ipfs repo, holding the node's identity.
+static KEYSTORE_DIR: &str = "keystore";
 
 /// All types can be changed at compile time by implementing
 /// `IpfsTypes`.
@@ -58,7 +71,8 @@ impl<T: SwarmTypes + RepoTypes> IpfsTypes for T {}
 #[derive(Clone)]
 pub struct Types;
 impl RepoTypes for Types {
-    type TBlockStore = repo::fs::FsBlockStore;
+    type TBlockStore = repo::cache::CachedBlockStore<repo::fs::FsBlockStore>;
+    type TDataStore = repo::fs::FsDataStore;
 }
 
 /// Testing IPFS types
@@ -66,6 +80,7 @@ impl RepoTypes for Types {
 pub struct TestTypes;
 impl RepoTypes for TestTypes {
     type TBlockStore = repo::mem::MemBlockStore;
+    type TDataStore = repo::mem::MemDataStore;
 }
 
 /// Ipfs options
@@ -78,13 +93,15 @@ pub struct IpfsOptions<Types: IpfsTypes> {
     pub ipfs_path: PathBuf,
     /// The ipfs config.
     pub config: ConfigFile,
+    /// How CIDs are shown to users.
+    pub cid_profile: CidProfile,
 }
 
 impl Default for IpfsOptions<Types> {
     /// Create `IpfsOptions` from environment.
     fn default() -> Self {
         let ipfs_log = std::env::var("IPFS_LOG").unwrap_or(IPFS_LOG.into());
-        let ipfs_path = std::env::var("IPFS_PATH").unwrap_or_else(|_| {
+        let ipfs_path: PathBuf = std::env::var("IPFS_PATH").unwrap_or_else(|_| {
             let mut ipfs_path = std::env::var("HOME").unwrap_or("".into());
             ipfs_path.push_str("/");
             ipfs_path.push_str(IPFS_PATH);
@@ -92,13 +109,15 @@ impl Default for IpfsOptions<Types> {
         }).into();
         let xdg_dirs = xdg::BaseDirectories::with_prefix(XDG_APP_NAME).unwrap();
         let path = xdg_dirs.place_config_file(CONFIG_FILE).unwrap();
-        let config = ConfigFile::new(path);
+        let mut config = ConfigFile::new(path);
+        config.load_identity(&Keystore::new(&ipfs_path.join(KEYSTORE_DIR))).unwrap();
 
         IpfsOptions {
             _marker: PhantomData,
             ipfs_log,
             ipfs_path,
-            config
+            config,
+            cid_profile: cid_profile_from_env(),
         }
     }
 }
@@ -115,10 +134,18 @@ impl Default for IpfsOptions<TestTypes> {
             ipfs_log,
             ipfs_path,
             config,
+            cid_profile: CidProfile::default(),
         }
     }
 }
 
+/// `CidProfile` of `IPFS_CID_VERSION` and `IPFS_CID_BASE`, CIDv0 and base58btc if unset.
+fn cid_profile_from_env() -> CidProfile {
+    let version = std::env::var("IPFS_CID_VERSION").unwrap_or(IPFS_CID_VERSION.into());
+    let base = std::env::var("IPFS_CID_BASE").unwrap_or(IPFS_CID_BASE.into());
+    CidProfile::from_names(&version, &base).unwrap()
+}
+
 /// Ipfs struct creates a new IPFS node and is the main entry point
 /// for interacting with IPFS.
 pub struct Ipfs<Types: IpfsTypes> {
@@ -137,12 +164,13 @@ enum IpfsEvent {
 impl<Types: IpfsTypes> Ipfs<Types> {
     /// Creates a new ipfs node.
     pub fn new(options: IpfsOptions<Types>) -> Self {
+        cid_profile::set(options.cid_profile);
         let repo_options = RepoOptions::<Types>::from(&options);
         let (repo, repo_events) = create_repo(repo_options);
         let swarm_options = SwarmOptions::<Types>::from(&options);
         let swarm = create_swarm(swarm_options, repo.clone());
         let dag = IpldDag::new(repo.clone());
-        let ipns = Ipns::new(repo.clone());
+        let ipns = Ipns::from_repo(&repo, options.config.ipns_key());
 
         Ipfs {
             repo,
@@ -169,9 +197,16 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         self.repo.put_block(block)
     }
 
+    /// Puts a batch of blocks into the ipfs repo, hashing and writing them in a pipeline.
+    pub fn put_blocks(&self, blocks: Vec<Block>) ->
+    impl Future<Output=Result<(Vec<Cid>, PutTimings), Error>>
+    {
+        self.repo.put_blocks(blocks)
+    }
+
     /// Retrives a block from the ipfs repo.
-    pub fn get_block(&self, cid: &Cid) -> impl Future<Output=Result<Block, Error>> {
-        self.repo.get_block(cid)
+    pub fn get_block(&self, cid: &Cid, ctx: Context) -> impl Future<Output=Result<Block, Error>> {
+        self.repo.get_block(cid, ctx)
     }
 
     /// Remove block from the ipfs repo.
@@ -179,36 +214,109 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         self.repo.remove_block(cid)
     }
 
+    /// Removes every unpinned block from the ipfs repo.
+    pub fn gc(&self) -> impl Future<Output=Result<GcReport, Error>> {
+        self.repo.gc()
+    }
+
+    /// Fetches the dag below `root` and pins it recursively, resuming an interrupted add, see
+    /// `Repo::pin_add`.
+    pub fn pin_add<F>(&self, root: &Cid, ctx: Context, on_progress: F) ->
+    impl Future<Output=Result<PinProgress, Error>>
+    where
+        F: FnMut(&PinProgress),
+    {
+        self.repo.pin_add(root, ctx, on_progress)
+    }
+
+    /// Returns the progress of adding the recursive pin `root`, if it is being added.
+    pub fn pin_progress(&self, root: &Cid) -> impl Future<Output=Result<Option<PinProgress>, Error>> {
+        self.repo.pins().progress(root)
+    }
+
+    /// Counts the blocks of the ipfs repo and their total size.
+    pub fn repo_stat(&self) -> impl Future<Output=Result<BlockCount, Error>> {
+        self.repo.stat()
+    }
+
+    /// Counts the blocks of the ipfs repo by codec and multihash type.
+    pub fn repo_stats(&self) -> impl Future<Output=Result<RepoStats, Error>> {
+        self.repo.stats()
+    }
+
     /// Puts an ipld dag node into the ipfs repo.
     pub fn put_dag(&self, ipld: Ipld) -> impl Future<Output=Result<IpfsPath, Error>> {
         self.dag.put(ipld, cid::Codec::DagCBOR)
     }
 
     /// Gets an ipld dag node from the ipfs repo.
-    pub fn get_dag(&self, path: IpfsPath) -> impl Future<Output=Result<Ipld, Error>> {
-        self.dag.get(path)
+    pub fn get_dag(&self, path: IpfsPath, ctx: Context) -> impl Future<Output=Result<Ipld, Error>> {
+        self.dag.get(path, ctx)
+    }
+
+    /// Gets an ipld dag node from the ipfs repo, encoded with `output_codec`.
+    pub fn get_dag_encoded(&self, path: IpfsPath, output_codec: OutputCodec, ctx: Context) ->
+    impl Future<Output=Result<Vec<u8>, Error>>
+    {
+        self.dag.get_encoded(path, output_codec, ctx)
+    }
+
+    /// Walks the ipld dag below `path` breadth-first, see `IpldDag::traverse`.
+    pub fn traverse_dag<F>(&self, path: IpfsPath, max_depth: Option<usize>, ctx: Context, visitor: F) ->
+    impl Future<Output=Result<(), Error>>
+    where
+        F: FnMut(&Cid, &Ipld, usize),
+    {
+        self.dag.traverse(path, max_depth, ctx, visitor)
+    }
+
+    /// Writes the dags below `roots` as a CAR archive to `writer`, see `car::export`.
+    pub fn export_car<W: std::io::Write>(&self, roots: Vec<Cid>, max_depth: Option<usize>, writer: W, ctx: Context) ->
+    impl Future<Output=Result<W, Error>>
+    {
+        car::export(self.repo.clone(), roots, max_depth, writer, ctx)
     }
 
-    /// Adds a file into the ipfs repo.
+    /// Imports the blocks of the CAR archive read from `reader`, returning its roots.
+    pub fn import_car<R: std::io::Read>(&self, reader: R) -> impl Future<Output=Result<Vec<Cid>, Error>> {
+        car::import(self.repo.clone(), reader)
+    }
+
+    /// Adds a file into the ipfs repo as a single block and pins it recursively.
+    ///
+    /// Use `add_file` to add a file without pinning it.
     pub fn add(&self, path: PathBuf) -> impl Future<Output=Result<IpfsPath, Error>> {
-        let dag = self.dag.clone();
+        let repo = self.repo.clone();
         async move {
-            let file = await!(File::new(path))?;
-            let path = await!(file.put_unixfs_v1(&dag))?;
-            Ok(path)
+            let file = File::new(path).await?;
+            let block = file.to_unixfs_v1_block()?;
+            let cid = block.cid().to_owned();
+            repo.put_blocks_pinned(vec![block], &cid).await?;
+            Ok(IpfsPath::new(PathRoot::Ipld(cid)))
         }
     }
 
+    /// Adds a file into the ipfs repo, split into chunks by `options.chunker` and linked from a
+    /// balanced unixfs dag.
+    pub fn add_file(&self, path: PathBuf, options: AddOptions) -> impl Future<Output=Result<Cid, Error>> {
+        unixfs::add_file(self.repo.clone(), path, options)
+    }
+
+    /// Streams the contents of the unixfs file `cid` from the ipfs repo.
+    pub fn cat(&self, cid: Cid, ctx: Context) -> impl Stream<Item=Result<Vec<u8>, Error>> {
+        unixfs::cat(self.repo.clone(), cid, ctx)
+    }
+
     /// Gets a file from the ipfs repo.
-    pub fn get(&self, path: IpfsPath) -> impl Future<Output=Result<File, Error>> {
-        File::get_unixfs_v1(&self.dag, path)
+    pub fn get(&self, path: IpfsPath, ctx: Context) -> impl Future<Output=Result<File, Error>> {
+        File::get_unixfs_v1(&self.dag, path, ctx)
     }
 
     /// Resolves a ipns path to an ipld path.
-    pub fn resolve_ipns(&self, path: &IpfsPath) ->
+    pub fn resolve_ipns(&self, path: &IpfsPath, ctx: Context) ->
     impl Future<Output=Result<IpfsPath, Error>>
     {
-        self.ipns.resolve(path)
+        self.ipns.resolve(path, ctx)
     }
 
     /// Publishes an ipld path.
@@ -218,6 +326,18 @@ impl<Types: IpfsTypes> Ipfs<Types> {
         self.ipns.publish(path)
     }
 
+    /// Returns the ipns name derived from the node's peer id.
+    pub fn ipns_self_name(&self) -> IpfsPath {
+        self.ipns.self_name()
+    }
+
+    /// Publishes an ipld path under the node's own ipns name.
+    pub fn publish_ipns_self(&self, path: &IpfsPath) ->
+    impl Future<Output=Result<IpfsPath, Error>>
+    {
+        self.ipns.publish_self(path)
+    }
+
     /// Start daemon.
     pub fn start_daemon(&mut self) -> Option<IpfsFuture<Types>> {
         self.repo_events.take().map(|repo_events|{
@@ -227,7 +347,8 @@ impl<Types: IpfsTypes> Ipfs<Types> {
             IpfsFuture {
                 repo_events,
                 exit_events: receiver,
-                swarm: Box::new(self.swarm.take().unwrap()),
+                swarm: Box::new(self.swarm.take().unwrap().compat()),
+                journal: self.repo.journal().clone(),
             }
         })
     }
@@ -241,15 +362,17 @@ impl<Types: IpfsTypes> Ipfs<Types> {
 }
 
 pub struct IpfsFuture<Types: SwarmTypes> {
-    swarm: Box<TSwarm<Types>>,
+    /// libp2p still implements futures 0.1, its swarm is polled through `Compat01As03`.
+    swarm: Box<Compat01As03<TSwarm<Types>>>,
     repo_events: Receiver<RepoEvent>,
     exit_events: Receiver<IpfsEvent>,
+    journal: Journal<Types>,
 }
 
 impl<Types: SwarmTypes> Future for IpfsFuture<Types> {
     type Output = ();
 
-    fn poll(self: Pin<&mut Self>, _waker: &Waker) -> Poll<Self::Output> {
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Self::Output> {
         let _self = self.get_mut();
         loop {
             if let Ok(IpfsEvent::Exit) = _self.exit_events.try_recv() {
@@ -259,14 +382,19 @@ impl<Types: SwarmTypes> Future for IpfsFuture<Types> {
             loop {
                 if let Ok(event) = _self.repo_events.try_recv() {
                     match event {
-                        RepoEvent::WantBlock(cid) => {
-                            _self.swarm.want_block(cid);
+                        RepoEvent::WantBlock(cid, providers) => {
+                            _self.swarm.get_mut().want_block(cid, providers);
                         }
                         RepoEvent::ProvideBlock(cid) => {
-                            _self.swarm.provide_block(cid);
+                            _self.swarm.get_mut().provide_block(cid.clone());
+                            let settle = _self.journal.settle_provide(&cid);
+                            tokio::spawn(async move {
+                                // the block is provided again after a restart if this fails.
+                                let _ = settle.await;
+                            });
                         }
                         RepoEvent::UnprovideBlock(cid) => {
-                            _self.swarm.stop_providing_block(&cid);
+                            _self.swarm.get_mut().stop_providing_block(&cid);
                         }
                     }
                 } else {
@@ -274,13 +402,13 @@ impl<Types: SwarmTypes> Future for IpfsFuture<Types> {
                 }
             }
 
-            let poll = _self.swarm.poll().expect("Error while polling swarm");
-            match poll {
-                Async::Ready(Some(_)) => {},
-                Async::Ready(None) => {
+            match _self.swarm.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(_))) => {},
+                Poll::Ready(Some(Err(err))) => panic!("Error while polling swarm: {:?}", err),
+                Poll::Ready(None) => {
                     return Poll::Ready(());
                 },
-                Async::NotReady => {
+                Poll::Pending => {
                     return Poll::Pending;
                 }
             }
@@ -292,39 +420,35 @@ impl<Types: SwarmTypes> Future for IpfsFuture<Types> {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_put_and_get_block() {
+    #[tokio::test]
+    async fn test_put_and_get_block() {
         let options = IpfsOptions::<TestTypes>::default();
         let mut ipfs = Ipfs::new(options);
         let block = Block::from("hello block\n");
 
-        tokio::run_async(async move {
-            let fut = ipfs.start_daemon().unwrap();
-            tokio::spawn_async(fut);
+        let fut = ipfs.start_daemon().unwrap();
+        tokio::spawn(fut);
 
-            let cid = await!(ipfs.put_block(block.clone())).unwrap();
-            let new_block = await!(ipfs.get_block(&cid)).unwrap();
-            assert_eq!(block, new_block);
+        let cid = ipfs.put_block(block.clone()).await.unwrap();
+        let new_block = ipfs.get_block(&cid, Context::default()).await.unwrap();
+        assert_eq!(block, new_block);
 
-            ipfs.exit_daemon();
-        });
+        ipfs.exit_daemon();
     }
 
-    #[test]
-    fn test_put_and_get_dag() {
+    #[tokio::test]
+    async fn test_put_and_get_dag() {
         let options = IpfsOptions::<TestTypes>::default();
         let mut ipfs = Ipfs::new(options);
 
-        tokio::run_async(async move {
-            let fut = ipfs.start_daemon().unwrap();
-            tokio::spawn_async(fut);
+        let fut = ipfs.start_daemon().unwrap();
+        tokio::spawn(fut);
 
-            let data: Ipld = vec![-1, -2, -3].into();
-            let cid = await!(ipfs.put_dag(data.clone())).unwrap();
-            let new_data = await!(ipfs.get_dag(cid.into())).unwrap();
-            assert_eq!(data, new_data);
+        let data: Ipld = vec![-1, -2, -3].into();
+        let cid = ipfs.put_dag(data.clone()).await.unwrap();
+        let new_data = ipfs.get_dag(cid.into(), Context::default()).await.unwrap();
+        assert_eq!(data, new_data);
 
-            ipfs.exit_daemon();
-        });
+        ipfs.exit_daemon();
     }
 }
@@ -1,5 +1,6 @@
 use crate::bitswap::{Bitswap, Strategy};
 use crate::block::Cid;
+use crate::cid_profile;
 use crate::p2p::{SwarmOptions, SwarmTypes};
 use crate::repo::Repo;
 use libp2p::{NetworkBehaviour, PeerId};
@@ -12,7 +13,7 @@ use libp2p::ping::{Ping, PingEvent};
 use libp2p::floodsub::{Floodsub, FloodsubEvent};
 //use parity_multihash::Multihash;
 use std::sync::Arc;
-use tokio::prelude::*;
+use tokio_io::{AsyncRead, AsyncWrite};
 
 /// Behaviour type.
 #[derive(NetworkBehaviour)]
@@ -157,21 +158,26 @@ impl<TSubstream: AsyncRead + AsyncWrite, TSwarmTypes: SwarmTypes> Behaviour<TSub
         }
     }
 
-    pub fn want_block(&mut self, cid: Cid) {
-        info!("Want block {}", cid.to_string());
+    /// Wants `cid` from the connected peers and from `providers`, which are connected to first.
+    pub fn want_block(&mut self, cid: Cid, providers: Vec<PeerId>) {
+        info!("Want block {}", cid_profile::display(&cid));
         //let hash = Multihash::from_bytes(cid.to_bytes()).unwrap();
         //self.kademlia.get_providers(hash);
+        for peer_id in providers {
+            // newly connected peers are sent the want list.
+            self.bitswap.connect(peer_id);
+        }
         self.bitswap.want_block(cid, 1);
     }
 
     pub fn provide_block(&mut self, cid: Cid) {
-        info!("Providing block {}", cid.to_string());
+        info!("Providing block {}", cid_profile::display(&cid));
         //let hash = Multihash::from_bytes(cid.to_bytes()).unwrap();
         //self.kademlia.add_providing(PeerId::from_multihash(hash).unwrap());
     }
 
     pub fn stop_providing_block(&mut self, cid: &Cid) {
-        info!("Finished providing block {}", cid.to_string());
+        info!("Finished providing block {}", cid_profile::display(cid));
         //let hash = Multihash::from_bytes(cid.to_bytes()).unwrap();
         //self.kademlia.remove_providing(&hash);
     }
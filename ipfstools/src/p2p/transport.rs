@@ -10,7 +10,7 @@ use libp2p::tcp::TcpConfig;
 use libp2p::yamux::Config as YamuxConfig;
 use std::io::{Error, ErrorKind};
 use std::time::Duration;
-use tokio::prelude::*;
+use futures01::prelude::*;
 
 /// Transport type.
 pub(crate) type TTransport = Boxed<(PeerId, StreamMuxerBox), Error>;
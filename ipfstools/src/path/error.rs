@@ -1,9 +1,16 @@
 use crate::ipld::Ipld;
+use filesys_errors::{CoreError, ErrorCode};
 use crate::path::SubPath;
 
 #[derive(Debug)]
 pub enum IpfsPathError {
     InvalidPath(String),
+    /// `path` could not be parsed, `offset` is the byte offset of the failure in `path`.
+    ParseError {
+        path: String,
+        offset: usize,
+        reason: &'static str,
+    },
     ResolveError {
         ipld: Ipld,
         path: SubPath,
@@ -15,6 +22,7 @@ impl std::error::Error for IpfsPathError {
     fn description(&self) -> &str {
         match *self {
             IpfsPathError::InvalidPath(_) => "invalid path",
+            IpfsPathError::ParseError { .. } => "error parsing path",
             IpfsPathError::ResolveError { .. } => "error resolving path",
             IpfsPathError::ExpectedIpldPath => "expected ipld path",
         }
@@ -27,6 +35,9 @@ impl std::fmt::Display for IpfsPathError {
             IpfsPathError::InvalidPath(ref path) => {
                 write!(f, "Invalid path {:?}", path)
             }
+            IpfsPathError::ParseError { ref path, offset, reason } => {
+                write!(f, "Invalid path {:?} at byte {}: {}", path, offset, reason)
+            }
             IpfsPathError::ResolveError { ref path, .. } => {
                 write!(f, "Can't resolve {}", path.to_string())
             }
@@ -36,3 +47,14 @@ impl std::fmt::Display for IpfsPathError {
         }
     }
 }
+
+impl CoreError for IpfsPathError {
+    fn code(&self) -> ErrorCode {
+        match *self {
+            IpfsPathError::InvalidPath(_) => ErrorCode::InvalidInput,
+            IpfsPathError::ParseError { .. } => ErrorCode::InvalidInput,
+            IpfsPathError::ResolveError { .. } => ErrorCode::NotFound,
+            IpfsPathError::ExpectedIpldPath => ErrorCode::InvalidInput,
+        }
+    }
+}
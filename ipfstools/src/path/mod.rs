@@ -3,6 +3,7 @@ use crate::error::Error;
 use libp2p::PeerId;
 use std::convert::{TryFrom, TryInto};
 use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
 
 pub mod error;
 pub use self::error::IpfsPathError;
@@ -21,25 +22,51 @@ impl IpfsPath {
         }
     }
 
+    /// Parses a path of the form `/ipfs/<cid>/<segments>` or `/ipns/<key>/<segments>`.
+    ///
+    /// Segments are percent-decoded and normalized to unicode NFC. Segments that are empty,
+    /// contain a NUL byte or an encoded `/`, or traverse the path with `.` or `..` are rejected
+    /// with the byte offset of the failure.
     pub fn from_str(string: &str) -> Result<Self, Error> {
-        let mut subpath = string.split("/");
-        let empty = subpath.next();
-        let root_type = subpath.next();
-        let key = subpath.next();
-
-        let root = match (empty, root_type, key) {
-            (Some(""), Some("ipfs"), Some(key)) => PathRoot::Ipld(Cid::from(key)?),
-            (Some(""), Some("ipld"), Some(key)) => PathRoot::Ipld(Cid::from(key)?),
-            (Some(""), Some("ipns"), Some(key)) => {
-                match PeerId::from_str(key).ok() {
-                    Some(peer_id) => PathRoot::Ipns(peer_id),
-                    None => PathRoot::Dns(key.to_string())
-                }
-            },
-            _ => return Err(IpfsPathError::InvalidPath(string.to_owned()).into()),
+        let parse_error = |offset, reason| IpfsPathError::ParseError {
+            path: string.to_owned(),
+            offset,
+            reason,
+        };
+        if !string.starts_with('/') {
+            return Err(parse_error(0, "expected a leading /").into());
+        }
+        let (root_type, rest) = split_first(&string[1..]);
+        let ipns = match root_type {
+            "ipfs" | "ipld" => false,
+            "ipns" => true,
+            _ => return Err(parse_error(1, "expected ipfs, ipld or ipns").into()),
+        };
+        let key_offset = root_type.len() + 2;
+        let (key, rest) = match rest {
+            Some(rest) => split_first(rest),
+            None => return Err(parse_error(string.len(), "expected a root key").into()),
+        };
+        if key.is_empty() {
+            return Err(parse_error(key_offset, "empty root key").into());
+        }
+        if let Some(nul) = key.find('\0') {
+            return Err(parse_error(key_offset + nul, "NUL byte").into());
+        }
+
+        let root = if ipns {
+            match PeerId::from_str(key).ok() {
+                Some(peer_id) => PathRoot::Ipns(peer_id),
+                None => PathRoot::Dns(key.to_string())
+            }
+        } else {
+            let cid = Cid::from(key).map_err(|_| parse_error(key_offset, "invalid cid"))?;
+            PathRoot::Ipld(cid)
         };
         let mut path = IpfsPath::new(root);
-        path.push_str(&subpath.collect::<Vec<&str>>().join("/"))?;
+        if let Some(rest) = rest {
+            path.push_segments(string, string.len() - rest.len(), rest)?;
+        }
         Ok(path)
     }
 
@@ -55,21 +82,23 @@ impl IpfsPath {
         self.path.push(sub_path.into());
     }
 
+    /// Appends the `/` separated segments of `string`, see `IpfsPath::from_str`.
     pub fn push_str(&mut self, string: &str) -> Result<(), Error> {
-        if string.is_empty() {
+        self.push_segments(string, 0, string)?;
+        Ok(())
+    }
+
+    /// Appends the segments of `segments`, which starts at byte `offset` of `path`.
+    fn push_segments(&mut self, path: &str, mut offset: usize, segments: &str) -> Result<(), IpfsPathError> {
+        if segments.is_empty() {
             return Ok(());
         }
-        for sub_path in string.split("/") {
-            if sub_path == "" {
-                return Err(IpfsPathError::InvalidPath(string.to_owned()).into());
-            }
-            let index = sub_path.parse::<usize>();
-            if index.is_ok() {
-                self.push(index.unwrap());
-            } else {
-                self.push(sub_path);
-            }
+        let mut parsed = Vec::new();
+        for segment in segments.split('/') {
+            parsed.push(SubPath::parse(path, offset, segment)?);
+            offset += segment.len() + 1;
         }
+        self.path.extend(parsed);
         Ok(())
     }
 
@@ -134,6 +163,14 @@ impl TryInto<PeerId> for IpfsPath {
     }
 }
 
+/// Splits `string` at its first `/`.
+fn split_first(string: &str) -> (&str, Option<&str>) {
+    match string.find('/') {
+        Some(i) => (&string[..i], Some(&string[i + 1..])),
+        None => (string, None),
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum PathRoot {
     Ipld(Cid),
@@ -249,6 +286,63 @@ impl From<usize> for SubPath {
 }
 
 impl SubPath {
+    /// Parses the raw `segment` found at byte `offset` of `path`.
+    fn parse(path: &str, offset: usize, segment: &str) -> Result<Self, IpfsPathError> {
+        let parse_error = |at, reason| IpfsPathError::ParseError {
+            path: path.to_owned(),
+            offset: offset + at,
+            reason,
+        };
+        if segment.is_empty() {
+            return Err(parse_error(0, "empty segment"));
+        }
+
+        let raw = segment.as_bytes();
+        let mut bytes = Vec::with_capacity(raw.len());
+        // offset in `segment` of every decoded byte.
+        let mut origins = Vec::with_capacity(raw.len());
+        let mut i = 0;
+        while i < raw.len() {
+            let start = i;
+            let byte = if raw[i] == b'%' {
+                match (raw.get(i + 1).and_then(hex_digit), raw.get(i + 2).and_then(hex_digit)) {
+                    (Some(high), Some(low)) => {
+                        i += 3;
+                        high << 4 | low
+                    }
+                    _ => return Err(parse_error(start, "invalid percent-encoding")),
+                }
+            } else {
+                i += 1;
+                raw[start]
+            };
+            match byte {
+                0 => return Err(parse_error(start, "NUL byte")),
+                b'/' => return Err(parse_error(start, "encoded path separator")),
+                _ => {}
+            }
+            bytes.push(byte);
+            origins.push(start);
+        }
+
+        let key = match String::from_utf8(bytes) {
+            Ok(key) => key,
+            Err(e) => {
+                let at = origins[e.utf8_error().valid_up_to()];
+                return Err(parse_error(at, "invalid utf-8"));
+            }
+        };
+        let key: String = key.nfc().collect();
+        if key == "." || key == ".." {
+            return Err(parse_error(0, "path traversal"));
+        }
+
+        match key.parse::<usize>() {
+            Ok(index) => Ok(SubPath::Index(index)),
+            Err(_) => Ok(SubPath::Key(key)),
+        }
+    }
+
     pub fn is_key(&self) -> bool {
         match *self {
             SubPath::Key(_) => true,
@@ -279,12 +373,16 @@ impl SubPath {
 
     pub fn to_string(&self) -> String {
         match self {
-            SubPath::Key(ref key) => key.to_owned(),
+            SubPath::Key(ref key) => key.replace('%', "%25"),
             SubPath::Index(index) => index.to_string(),
         }
     }
 }
 
+fn hex_digit(byte: &u8) -> Option<u8> {
+    (*byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -332,6 +430,66 @@ mod tests {
         assert!(IpfsPath::from_str("/QmRN").is_err());
     }
 
+    fn error_offset(string: &str) -> usize {
+        let err = IpfsPath::from_str(string).unwrap_err();
+        match err.downcast_ref::<IpfsPathError>() {
+            Some(IpfsPathError::ParseError { offset, .. }) => *offset,
+            _ => panic!("expected a parse error, got {}", err),
+        }
+    }
+
+    #[test]
+    fn test_from_str_error_offsets() {
+        let root = "/ipfs/QmRN6wdp1S2A5EtjW9A3M1vKSBuQQGcgvuhoMUoEz4iiT5";
+        assert_eq!(error_offset("ipfs/QmRN"), 0);
+        assert_eq!(error_offset("/ipfz/QmRN"), 1);
+        assert_eq!(error_offset("/ipfs"), 5);
+        assert_eq!(error_offset("/ipfs//key"), 6);
+        assert_eq!(error_offset("/ipfs/QmRN"), 6);
+        assert_eq!(error_offset(&format!("{}/key//3", root)), root.len() + 5);
+        assert_eq!(error_offset(&format!("{}/key/a%2", root)), root.len() + 6);
+        assert_eq!(error_offset(&format!("{}/key/a%zz", root)), root.len() + 6);
+        assert_eq!(error_offset(&format!("{}/key/%ff", root)), root.len() + 5);
+    }
+
+    #[test]
+    fn test_percent_decoding() {
+        let path = Block::from("hello").path("a%20b/%33/%c3%A9").unwrap();
+        let segments: Vec<_> = path.iter().cloned().collect();
+        assert_eq!(segments, vec!["a b".into(), 3.into(), "\u{e9}".into()]);
+    }
+
+    #[test]
+    fn test_unicode_normalization() {
+        let composed = Block::from("hello").path("caf\u{e9}").unwrap();
+        let decomposed = Block::from("hello").path("cafe\u{301}").unwrap();
+        let encoded = Block::from("hello").path("cafe%CC%81").unwrap();
+        assert_eq!(composed, decomposed);
+        assert_eq!(composed, encoded);
+    }
+
+    #[test]
+    fn test_rejects_nul_and_traversal() {
+        let root = "/ipfs/QmRN6wdp1S2A5EtjW9A3M1vKSBuQQGcgvuhoMUoEz4iiT5";
+        assert_eq!(error_offset(&format!("{}/a\0b", root)), root.len() + 2);
+        assert_eq!(error_offset(&format!("{}/a%00b", root)), root.len() + 2);
+        assert_eq!(error_offset(&format!("{}/key/..", root)), root.len() + 5);
+        assert_eq!(error_offset(&format!("{}/key/%2e%2E", root)), root.len() + 5);
+        assert_eq!(error_offset(&format!("{}/.", root)), root.len() + 1);
+        assert_eq!(error_offset(&format!("{}/a%2Fb", root)), root.len() + 2);
+        assert_eq!(error_offset("/ipns/exa\0mple.com"), 9);
+        assert!(IpfsPath::from_str(&format!("{}/...", root)).is_ok());
+    }
+
+    #[test]
+    fn test_to_string_round_trip() {
+        let mut path = Block::from("hello").path("").unwrap();
+        path.push("100%");
+        path.push("caf\u{e9}");
+        let res = IpfsPath::from_str(&path.to_string()).unwrap();
+        assert_eq!(path, res);
+    }
+
     #[test]
     fn test_to_string() {
         let path = Block::from("hello").path("key/3").unwrap();
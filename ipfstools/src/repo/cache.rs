@@ -0,0 +1,220 @@
+//! In-memory LRU cache of blocks
+use crate::block::{Cid, Block};
+use crate::error::Error;
+use crate::repo::{metrics, BlockCount, BlockStore};
+use cached::{Cached, SizedCache};
+use futures::future::BoxFuture;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Blocks kept in memory by a `CachedBlockStore` created with `BlockStore::new`.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// Keeps the most recently used blocks of another block store in memory.
+///
+/// Writes go through to the wrapped store before the cache is updated, and removed blocks are
+/// evicted, so the cache never holds a block the wrapped store does not. Absent blocks are not
+/// cached.
+#[derive(Clone)]
+pub struct CachedBlockStore<T: BlockStore> {
+    store: T,
+    capacity: usize,
+    cache: Arc<Mutex<Cache>>,
+}
+
+struct Cache {
+    blocks: SizedCache<Cid, Block>,
+    /// Counts the finished removals. A read or write that saw a removal finish while it was
+    /// running doesn't cache its block, which may be the removed one.
+    removals: u64,
+}
+
+impl Cache {
+    /// Caches `block` unless a removal finished since `removals` was read.
+    fn set(&mut self, block: Block, removals: u64) {
+        if self.removals == removals {
+            self.blocks.cache_set(block.cid().to_owned(), block);
+        }
+    }
+}
+
+impl<T: BlockStore + std::fmt::Debug> std::fmt::Debug for CachedBlockStore<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CachedBlockStore")
+            .field("store", &self.store)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl<T: BlockStore> CachedBlockStore<T> {
+    /// Caches up to `capacity` blocks of `store`.
+    pub fn with_capacity(store: T, capacity: usize) -> Self {
+        CachedBlockStore {
+            store,
+            capacity,
+            cache: Arc::new(Mutex::new(Cache {
+                blocks: SizedCache::with_size(capacity),
+                removals: 0,
+            })),
+        }
+    }
+
+    /// The wrapped store. Blocks removed through it are not evicted from the cache.
+    pub fn inner(&self) -> &T {
+        &self.store
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn cached(&self, cid: &Cid) -> Option<Block> {
+        let block = self.cache.lock().unwrap().blocks.cache_get(cid).cloned();
+        let result = if block.is_some() { "hit" } else { "miss" };
+        metrics::BLOCK_CACHE_LOOKUPS.with_label_values(&[result]).inc();
+        block
+    }
+}
+
+impl<T: BlockStore> BlockStore for CachedBlockStore<T> {
+    fn new(path: PathBuf) -> Self {
+        CachedBlockStore::with_capacity(T::new(path), DEFAULT_CAPACITY)
+    }
+
+    fn init(&self) -> BoxFuture<'static, Result<(), Error>> {
+        self.store.init()
+    }
+
+    fn open(&self) -> BoxFuture<'static, Result<(), Error>> {
+        self.store.open()
+    }
+
+    fn contains(&self, cid: &Cid) -> BoxFuture<'static, Result<bool, Error>> {
+        if self.cached(cid).is_some() {
+            return Box::pin(futures::future::ok(true));
+        }
+        self.store.contains(cid)
+    }
+
+    fn get(&self, cid: &Cid) -> BoxFuture<'static, Result<Option<Block>, Error>> {
+        if let Some(block) = self.cached(cid) {
+            return Box::pin(futures::future::ok(Some(block)));
+        }
+        let removals = self.cache.lock().unwrap().removals;
+        let get = self.store.get(cid);
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let block = get.await?;
+            if let Some(block) = &block {
+                cache.lock().unwrap().set(block.clone(), removals);
+            }
+            Ok(block)
+        })
+    }
+
+    fn put(&self, block: Block) -> BoxFuture<'static, Result<Cid, Error>> {
+        let removals = self.cache.lock().unwrap().removals;
+        let put = self.store.put(block.clone());
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let cid = put.await?;
+            cache.lock().unwrap().set(block, removals);
+            Ok(cid)
+        })
+    }
+
+    fn remove(&self, cid: &Cid) -> BoxFuture<'static, Result<(), Error>> {
+        self.cache.lock().unwrap().blocks.cache_remove(cid);
+        let remove = self.store.remove(cid);
+        let cache = self.cache.clone();
+        let cid = cid.to_owned();
+        Box::pin(async move {
+            let removed = remove.await;
+            let mut cache = cache.lock().unwrap();
+            // A `get` or `put` running concurrently may have cached the block again already,
+            // the ones still running see the removal and don't.
+            cache.blocks.cache_remove(&cid);
+            cache.removals += 1;
+            removed
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'static, Result<Vec<Cid>, Error>> {
+        self.store.list()
+    }
+
+    fn stat(&self) -> BoxFuture<'static, Result<BlockCount, Error>> {
+        self.store.stat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::mem::MemBlockStore;
+    use std::env::temp_dir;
+
+    #[tokio::test]
+    async fn test_cached_blockstore() {
+        let store = CachedBlockStore::<MemBlockStore>::new(temp_dir());
+        let block = Block::from("1");
+        let cid = block.cid();
+
+        assert_eq!(store.init().await.unwrap(), ());
+        assert_eq!(store.get(cid).await.unwrap(), None);
+        assert_eq!(store.put(block.clone()).await.unwrap(), cid.to_owned());
+
+        // Served from the cache once gone from the wrapped store.
+        store.inner().remove(cid).await.unwrap();
+        assert_eq!(store.contains(cid).await.unwrap(), true);
+        assert_eq!(store.get(cid).await.unwrap(), Some(block.clone()));
+
+        store.put(block.clone()).await.unwrap();
+        store.remove(cid).await.unwrap();
+        assert_eq!(store.inner().contains(cid).await.unwrap(), false);
+        assert_eq!(store.contains(cid).await.unwrap(), false);
+        assert_eq!(store.get(cid).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_cache_eviction() {
+        let store = CachedBlockStore::with_capacity(MemBlockStore::new(temp_dir()), 1);
+        let block1 = Block::from("1");
+        let block2 = Block::from("2");
+
+        store.put(block1.clone()).await.unwrap();
+        store.put(block2.clone()).await.unwrap();
+        store.inner().remove(block1.cid()).await.unwrap();
+        store.inner().remove(block2.cid()).await.unwrap();
+
+        // Only the most recently used block is left in the cache.
+        assert_eq!(store.get(block1.cid()).await.unwrap(), None);
+        assert_eq!(store.get(block2.cid()).await.unwrap(), Some(block2.clone()));
+    }
+
+    #[tokio::test]
+    async fn test_remove_during_get() {
+        let store = CachedBlockStore::<MemBlockStore>::new(temp_dir());
+        let block = Block::from("1");
+        let cid = block.cid();
+        store.inner().put(block.clone()).await.unwrap();
+
+        // The get reads the block before the removal, and finishes after it.
+        let get = store.get(cid);
+        store.remove(cid).await.unwrap();
+        assert_eq!(get.await.unwrap(), Some(block.clone()));
+        assert_eq!(store.contains(cid).await.unwrap(), false);
+        assert_eq!(store.get(cid).await.unwrap(), None);
+
+        let put = store.put(block.clone());
+        store.remove(cid).await.unwrap();
+        assert_eq!(put.await.unwrap(), cid.to_owned());
+        assert_eq!(store.contains(cid).await.unwrap(), false);
+
+        // Later reads are cached again.
+        store.put(block.clone()).await.unwrap();
+        store.inner().remove(cid).await.unwrap();
+        assert_eq!(store.get(cid).await.unwrap(), Some(block));
+    }
+}
@@ -1,165 +1,248 @@
-use crate::block::Cid;
-
-pub use errors::Error;
-pub use types::*;
-pub use storage::DB;
+//! Persistent rocksdb backed repo
+use crate::block::{Cid, Block};
+use crate::error::Error;
+use crate::repo::{BlockCount, BlockStore};
+use futures::future::BoxFuture;
+use rocksdb::{ColumnFamily, IteratorMode, Options, DB};
 use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// Column families of a `RocksBlockStore`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RocksConfig {
+    /// The column family holding the blocks, keyed by cid.
+    pub blocks: String,
+    /// Further column families to create, for other stores sharing the database.
+    pub column_families: Vec<String>,
+}
 
-pub trait BlockStore: Clone + Send + Sync + Unpin + 'static {
-    fn new(path: PathBuf) -> Self;
-    fn init(&self) ->
-    FutureObj<'static, Result<(), Error>>;
-    fn open(&self) ->
-    FutureObj<'static, Result<(), Error>>;
-    fn contains(&self, cid: &Cid) ->
-    FutureObj<'static, Result<bool, Error>>;
-    fn get(&self, cid: &Cid) ->
-    FutureObj<'static, Result<Option<Block>, Error>>;
-    fn put(&self, block: Block) ->
-    FutureObj<'static, Result<Cid, Error>>;
-    fn remove(&self, cid: &Cid) ->
-    FutureObj<'static, Result<(), Error>>;
+impl Default for RocksConfig {
+    fn default() -> Self {
+        RocksConfig {
+            blocks: "blocks".into(),
+            column_families: Vec::new(),
+        }
+    }
 }
 
-/// An object capable of storing and retrieving objects implementing `StoreItem`.
-///
-/// A `Store` is fundamentally backed by a key-value database, however it provides support for
-/// columns. A simple column implementation might involve prefixing a key with some bytes unique to
-/// each column.
-pub trait DataStore : Sync + Send + Sized +Clone + Unpin + 'static{
-
-    /// Store an item in `Self`.
-    fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), DBError>;
-
-    /// Retrieve an item from `Self`.
-    fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
-
-    /// Returns `true` if the given key represents an item in `Self`.
-    fn exists(&self, col: Column, key: &[u8]) -> Result<bool, Error> ;
-
-    /// Remove an item from `Self`.
-    fn delete(&self, col: Column, key: &[u8]) -> Result<(), Error> ;
-
-    /// Given the root of an existing block in the store (`start_block_root`), return a parent
-    /// block with the specified `slot`.
-    ///
-    /// Returns `None` if no parent block exists at that slot, or if `slot` is greater than the
-    /// slot of `start_block_root`.
-    fn get_block_at_preceeding_slot(
-        &self,
-        start_block_root: Cid,
-        slot: Slot,
-    ) -> Result<Option<(Cid, BeaconBlock)>, Error> {
-        block_at_slot::get_block_at_preceeding_slot(self, slot, start_block_root)
+impl RocksConfig {
+    fn names(&self) -> Vec<&str> {
+        let mut names = vec![self.blocks.as_str()];
+        for name in &self.column_families {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
     }
 }
 
-/// A on-disk database which implements the DataStore trait.
+/// Stores blocks in a column family of a rocksdb database.
 ///
-/// This implementation uses RocksDB with default options.
-pub struct DBStore {
+/// Rocksdb calls block the calling thread, so they run on the blocking threads of the tokio
+/// runtime polling the returned futures.
+#[derive(Clone)]
+pub struct RocksBlockStore {
     path: PathBuf,
-    db:Arc<Mutex<Option<DB>>>,
+    config: RocksConfig,
+    db: Arc<RwLock<Option<DB>>>,
 }
 
-impl DBStore {
+impl std::fmt::Debug for RocksBlockStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RocksBlockStore")
+            .field("path", &self.path)
+            .field("config", &self.config)
+            .finish()
+    }
+}
 
-    fn open(path:&PathBuf) -> Self {
-        // Rocks options.
-        let mut options = Options::default();
-        options.create_if_missing(true);
-
-        // Ensure the path exists.
-        fs::create_dir_all(&path).unwrap_or_else(|_| panic!("Unable to create {:?}", &path));
-        let db_path = path.join("database");
-        let columns = columns.unwrap_or(&COLUMNS);
-
-        if db_path.exists() {
-            Self {
-                path:db_path,
-                db: DB::open_cf(&options, db_path, &COLUMNS)
-                    .expect("Unable to open local database"),
-            }
-        } else {
-            let mut db = Self {
-                path:db_path,
-                db: DB::open(&options, db_path).expect("Unable to open local database"),
-            };
-            for cf in columns {
-                db.create_col(cf).unwrap();
-            }
-            db
+impl RocksBlockStore {
+    pub fn with_config(path: PathBuf, config: RocksConfig) -> Self {
+        RocksBlockStore {
+            path,
+            config,
+            db: Arc::new(RwLock::new(None)),
         }
     }
 
-    /// Create a RocksDB column family. Corresponds to the
-/// `create_cf()` function on the RocksDB API.
-    #[allow(dead_code)]
-    fn create_col(&mut self, col: &str) -> Result<(), DBError> {
-        match self.db.create_cf(col, &Options::default()) {
-            Err(e) => Err(e.into()),
-            Ok(_) => Ok(()),
+    /// Opens the database, creating it if `create` is set. Missing column families are created
+    /// either way, so column families can be added to an existing database.
+    fn open_db(&self, create: bool) -> Result<(), Error> {
+        if create {
+            std::fs::create_dir_all(&self.path)?;
         }
+        let mut options = Options::default();
+        options.create_if_missing(create);
+        options.create_missing_column_families(true);
+        let db = DB::open_cf(&options, &self.path, &self.config.names())?;
+        *self.db.write().unwrap() = Some(db);
+        Ok(())
+    }
+
+    /// Runs `f` with the database and the blocks column family.
+    fn with_blocks<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&DB, ColumnFamily) -> Result<T, Error>,
+    {
+        let db = self.db.read().unwrap();
+        let db = match db.as_ref() {
+            Some(db) => db,
+            None => bail!("block store at {:?} is not open", self.path),
+        };
+        let blocks = match db.cf_handle(&self.config.blocks) {
+            Some(blocks) => blocks,
+            None => bail!("missing column family {}", self.config.blocks),
+        };
+        f(db, blocks)
+    }
+
+    /// Runs `f` on a blocking thread, not to hold up a worker of the runtime.
+    fn blocking<T, F>(&self, f: F) -> BoxFuture<'static, Result<T, Error>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&RocksBlockStore) -> Result<T, Error> + Send + 'static,
+    {
+        let store = self.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || f(&store)).await?
+        })
     }
 }
 
-impl DataStore for DBStore {
+impl BlockStore for RocksBlockStore {
+    fn new(path: PathBuf) -> Self {
+        RocksBlockStore::with_config(path, RocksConfig::default())
+    }
 
-    /// Corresponds to the `get_cf()` method on the RocksDB API.
-    /// Will attempt to get the `ColumnFamily` and return an Err
-    /// if it fails.
-    fn get(&self, col: Column, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    fn init(&self) -> BoxFuture<'static, Result<(), Error>> {
+        self.blocking(|store| store.open_db(true))
+    }
 
-        let db = self.db.lock().unwrap();
-        let db = self.db.as_ref().unwrap();
-        let get = self.db.get_cf(cf, &key)?.map(|value| value.to_vec());
-        Ok(get)
+    fn open(&self) -> BoxFuture<'static, Result<(), Error>> {
+        self.blocking(|store| store.open_db(false))
     }
 
-    /// Set some value for some key on some column.
-    ///
-    /// Corresponds to the `cf_handle()` method on the RocksDB API.
-    /// Will attempt to get the `ColumnFamily` and return an Err
-    /// if it fails.
-    fn put(&self, col: Column, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+    fn contains(&self, cid: &Cid) -> BoxFuture<'static, Result<bool, Error>> {
+        let key = cid.to_bytes();
+        self.blocking(move |store| store.with_blocks(|db, blocks| {
+            Ok(db.get_cf(blocks, &key)?.is_some())
+        }))
+    }
 
-        match self.db.cf_handle(col) {
-            None => Err(DecodeError::BytesInvalid(
-                "Unknown column".to_string(),
-            )),
-            Some(handle) => self.db.put_cf(handle, key, val).map_err(|e| e.into()),
-        }
+    fn get(&self, cid: &Cid) -> BoxFuture<'static, Result<Option<Block>, Error>> {
+        let cid = cid.to_owned();
+        self.blocking(move |store| store.with_blocks(|db, blocks| {
+            let data = db.get_cf(blocks, &cid.to_bytes())?;
+            Ok(data.map(|data| Block::new(data.to_vec(), cid)))
+        }))
     }
 
-    /// Returns `true` if the given key represents an item in `Self`.
-    fn exists(&self, col: Column, key: &[u8]) -> Result<bool, Error>{
-        /*
-         * I'm not sure if this is the correct way to read if some
-         * block exists. Naively I would expect this to unncessarily
-         * copy some data, but I could be wrong.
-         */
-        match self.db.cf_handle(col) {
-            None => Err(DecodeError::BytesInvalid(
-                "Unknown column".to_string(),
-            )),
-            Some(handle) => Ok(self.db.get_cf(handle, key)?.is_some()),
-        }
+    fn put(&self, block: Block) -> BoxFuture<'static, Result<Cid, Error>> {
+        self.blocking(move |store| store.with_blocks(|db, blocks| {
+            db.put_cf(blocks, &block.cid().to_bytes(), block.data())?;
+            Ok(block.cid().to_owned())
+        }))
     }
 
-    /// Delete the value for some key on some column.
-    ///
-    /// Corresponds to the `delete_cf()` method on the RocksDB API.
-    /// Will attempt to get the `ColumnFamily` and return an Err
-    /// if it fails.
-    fn delete(&self, col: Column, key: &[u8]) -> Result<(), Error> {
-        match self.db.cf_handle(col) {
-            None => Err(DecodeError::BytesInvalid(
-                "Unknown column".to_string(),
-            )),
-            Some(handle) => {
-                self.db.delete_cf(handle, key)?;
-                Ok(())
+    fn remove(&self, cid: &Cid) -> BoxFuture<'static, Result<(), Error>> {
+        let key = cid.to_bytes();
+        self.blocking(move |store| store.with_blocks(|db, blocks| {
+            db.delete_cf(blocks, &key)?;
+            Ok(())
+        }))
+    }
+
+    fn list(&self) -> BoxFuture<'static, Result<Vec<Cid>, Error>> {
+        self.blocking(|store| store.with_blocks(|db, blocks| {
+            let mut cids = Vec::new();
+            for (key, _) in db.iterator_cf(blocks, IteratorMode::Start)? {
+                cids.push(Cid::from(&key[..])?);
             }
-        }
+            Ok(cids)
+        }))
     }
-}
\ No newline at end of file
+
+    fn stat(&self) -> BoxFuture<'static, Result<BlockCount, Error>> {
+        self.blocking(|store| store.with_blocks(|db, blocks| {
+            let mut count = BlockCount::default();
+            for (_, data) in db.iterator_cf(blocks, IteratorMode::Start)? {
+                count.add(data.len());
+            }
+            Ok(count)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    #[tokio::test]
+    async fn test_rocks_blockstore() {
+        let mut tmp = temp_dir();
+        tmp.push("rocksblockstore1");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+        let store = RocksBlockStore::new(tmp.clone());
+
+        let block = Block::from("1");
+        let cid = block.cid();
+
+        assert!(store.contains(cid).await.is_err());
+        assert_eq!(store.init().await.unwrap(), ());
+
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), None);
+        let remove = store.remove(cid);
+        assert_eq!(remove.await.unwrap(), ());
+
+        let put = store.put(block.clone());
+        assert_eq!(put.await.unwrap(), cid.to_owned());
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), true);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), Some(block.clone()));
+        assert_eq!(store.list().await.unwrap(), vec![cid.to_owned()]);
+
+        let remove = store.remove(cid);
+        assert_eq!(remove.await.unwrap(), ());
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), None);
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+
+    #[tokio::test]
+    async fn test_rocks_blockstore_open() {
+        let mut tmp = temp_dir();
+        tmp.push("rocksblockstore2");
+        std::fs::remove_dir_all(tmp.clone()).ok();
+
+        let path = tmp.clone();
+        let block = Block::from("1");
+        let config = RocksConfig {
+            blocks: "ipfs-blocks".into(),
+            column_families: vec!["ipns".into()],
+        };
+
+        let block_store = RocksBlockStore::new(path.clone());
+        assert!(block_store.open().await.is_err());
+
+        let block_store = RocksBlockStore::with_config(path.clone(), config.clone());
+        block_store.init().await.unwrap();
+        block_store.put(block.clone()).await.unwrap();
+        drop(block_store);
+
+        let block_store = RocksBlockStore::with_config(path, config);
+        block_store.open().await.unwrap();
+        assert!(block_store.contains(block.cid()).await.unwrap());
+        assert_eq!(block_store.get(block.cid()).await.unwrap().unwrap(), block);
+
+        std::fs::remove_dir_all(tmp).ok();
+    }
+}
@@ -0,0 +1,41 @@
+use crate::block::Cid;
+use crate::cid_profile;
+use filesys_errors::{CoreError, ErrorCode};
+
+#[derive(Debug)]
+pub enum RepoError {
+    /// No peer sent the block within the attempts of a `GetBlockOptions`. `providers` is the
+    /// number of hinted providers that were asked on top of the connected peers.
+    BlockNotFound {
+        cid: Cid,
+        attempts: u32,
+        providers: usize,
+    },
+}
+
+impl std::error::Error for RepoError {
+    fn description(&self) -> &str {
+        match *self {
+            RepoError::BlockNotFound { .. } => "block not found",
+        }
+    }
+}
+
+impl std::fmt::Display for RepoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            RepoError::BlockNotFound { ref cid, attempts, providers } => {
+                write!(f, "Block {} not found after {} attempts, {} providers hinted",
+                       cid_profile::display(cid), attempts, providers)
+            }
+        }
+    }
+}
+
+impl CoreError for RepoError {
+    fn code(&self) -> ErrorCode {
+        match *self {
+            RepoError::BlockNotFound { .. } => ErrorCode::NotFound,
+        }
+    }
+}
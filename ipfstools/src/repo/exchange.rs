@@ -0,0 +1,100 @@
+//! Hands blocks arriving from the network to the requests waiting for them.
+//!
+//! Every `Repo::get_block` that has to go to the network registers a want here. A network layer
+//! asks for the blocks on the `wantlist` and passes whatever it receives to `inject_block`, which
+//! completes the pending requests for it without them having to find it in the block store.
+use crate::block::{Block, Cid};
+use futures::channel::oneshot;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The blocks wanted by pending requests of a repo.
+#[derive(Clone, Debug, Default)]
+pub struct BlockExchange {
+    wants: Arc<Mutex<HashMap<Cid, Vec<oneshot::Sender<Block>>>>>,
+}
+
+impl BlockExchange {
+    pub fn new() -> Self {
+        BlockExchange::default()
+    }
+
+    /// Adds `cid` to the wantlist until the returned receiver gets the block or is dropped.
+    pub fn want(&self, cid: Cid) -> oneshot::Receiver<Block> {
+        let (sender, receiver) = oneshot::channel();
+        self.wants.lock().unwrap().entry(cid).or_default().push(sender);
+        receiver
+    }
+
+    /// Whether a pending request waits for `cid`.
+    pub fn is_wanted(&self, cid: &Cid) -> bool {
+        let mut wants = self.wants.lock().unwrap();
+        prune(&mut wants);
+        wants.contains_key(cid)
+    }
+
+    /// The cids waited for by pending requests.
+    pub fn wantlist(&self) -> Vec<Cid> {
+        let mut wants = self.wants.lock().unwrap();
+        prune(&mut wants);
+        wants.keys().cloned().collect()
+    }
+
+    /// Completes the requests waiting for `block` and removes it from the wantlist.
+    ///
+    /// The block is not stored, which is up to the caller. Returns whether the block was wanted.
+    pub fn inject_block(&self, block: Block) -> bool {
+        let senders = match self.wants.lock().unwrap().remove(block.cid()) {
+            Some(senders) => senders,
+            None => return false,
+        };
+        let mut delivered = false;
+        for sender in senders {
+            // the request may have given up in the meantime.
+            delivered |= sender.send(block.clone()).is_ok();
+        }
+        delivered
+    }
+}
+
+/// Drops the wants of requests that gave up.
+fn prune(wants: &mut HashMap<Cid, Vec<oneshot::Sender<Block>>>) {
+    wants.retain(|_, senders| {
+        senders.retain(|sender| !sender.is_canceled());
+        !senders.is_empty()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_wanted_block() {
+        let exchange = BlockExchange::new();
+        let block = Block::from("wanted");
+        let mut first = exchange.want(block.cid().to_owned());
+        let mut second = exchange.want(block.cid().to_owned());
+        assert_eq!(exchange.wantlist(), vec![block.cid().to_owned()]);
+
+        assert!(!exchange.inject_block(Block::from("unwanted")));
+        assert!(exchange.inject_block(block.clone()));
+        assert_eq!(first.try_recv().unwrap(), Some(block.clone()));
+        assert_eq!(second.try_recv().unwrap(), Some(block.clone()));
+        assert!(exchange.wantlist().is_empty());
+        assert!(!exchange.inject_block(block));
+    }
+
+    #[test]
+    fn test_dropped_want() {
+        let exchange = BlockExchange::new();
+        let block = Block::from("abandoned");
+        let receiver = exchange.want(block.cid().to_owned());
+        assert!(exchange.is_wanted(block.cid()));
+
+        drop(receiver);
+        assert!(!exchange.is_wanted(block.cid()));
+        assert!(exchange.wantlist().is_empty());
+        assert!(!exchange.inject_block(block));
+    }
+}
@@ -1,14 +1,14 @@
 //! Persistent fs backed repo
 use crate::block::{Cid, Block};
 use crate::error::Error;
-use crate::repo::BlockStore;
-use futures::compat::*;
-use futures::future::FutureObj;
+use crate::repo::{metrics, BlockCount, BlockStore, Column, DataStore};
+use futures::future::BoxFuture;
+use rustc_serialize::hex::{FromHex, ToHex};
 use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::path::PathBuf;
+use std::io::ErrorKind;
 use std::sync::{Arc, Mutex};
-use tokio::prelude::{Future as OldFuture, Stream as OldStream};
 use tokio::fs;
 
 #[derive(Clone, Debug)]
@@ -25,82 +25,104 @@ impl BlockStore for FsBlockStore {
         }
     }
 
-    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
+    fn init(&self) -> BoxFuture<'static, Result<(), Error>> {
         let path = self.path.clone();
-        FutureObj::new(Box::new(async move {
-            await!(fs::create_dir_all(path).compat())?;
+        Box::pin(async move {
+            fs::create_dir_all(path).await?;
             Ok(())
-        }))
+        })
     }
 
-    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
+    fn open(&self) -> BoxFuture<'static, Result<(), Error>> {
         let path = self.path.clone();
         let cids = self.cids.clone();
-        FutureObj::new(Box::new(async move {
-            await!(fs::read_dir(path).flatten_stream().for_each(|dir| {
+        Box::pin(async move {
+            let mut entries = fs::read_dir(path).await?;
+            while let Some(dir) = entries.next_entry().await? {
                 let path = dir.path();
                 if path.extension() == Some(OsStr::new("data")) {
                     let cid_str = path.file_stem().unwrap();
                     let cid = Cid::from(cid_str.to_str().unwrap()).unwrap();
                     cids.lock().unwrap().insert(cid);
                 }
-                Ok(())
-            }).compat())?;
+            }
             Ok(())
-        }))
+        })
     }
 
-    fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+    fn contains(&self, cid: &Cid) -> BoxFuture<'static, Result<bool, Error>> {
         let contains = self.cids.lock().unwrap().contains(cid);
-        FutureObj::new(Box::new(async move {
+        Box::pin(async move {
             Ok(contains)
-        }))
+        })
     }
 
-    fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+    fn get(&self, cid: &Cid) -> BoxFuture<'static, Result<Option<Block>, Error>> {
         let path = block_path(self.path.clone(), cid);
         let cid = cid.to_owned();
-        FutureObj::new(Box::new(async move {
-            let file = match await!(fs::File::open(path).compat()) {
-                Ok(file) => file,
-                Err(err) => {
-                    if err.kind() == std::io::ErrorKind::NotFound {
-                        return Ok(None);
-                    } else {
-                        return Err(err.into());
-                    }
-                }
+        Box::pin(async move {
+            let data = match fs::read(path).await {
+                Ok(data) => data,
+                Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err.into()),
             };
-            let (_, data) = await!(tokio::io::read_to_end(file, Vec::new()).compat())?;
             let block = Block::new(data, cid);
             Ok(Some(block))
-        }))
+        })
     }
 
-    fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+    fn put(&self, block: Block) -> BoxFuture<'static, Result<Cid, Error>> {
         let path = block_path(self.path.clone(), &block.cid());
         let cids = self.cids.clone();
-        FutureObj::new(Box::new(async move {
-            let file = await!(fs::File::create(path).compat())?;
-            let data = block.data();
-            await!(tokio::io::write_all(file, &*data).compat())?;
+        let contains = self.cids.lock().unwrap().contains(block.cid());
+        Box::pin(async move {
+            // blocks are content addressed, a stored block has the same data.
+            if contains {
+                return Ok(block.cid().to_owned());
+            }
+            fs::write(path, block.data()).await?;
             cids.lock().unwrap().insert(block.cid().to_owned());
             Ok(block.cid().to_owned())
-        }))
+        })
     }
 
-    fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+    fn remove(&self, cid: &Cid) -> BoxFuture<'static, Result<(), Error>> {
         let path = block_path(self.path.clone(), cid);
         let cid = cid.to_owned();
         let cids = self.cids.clone();
         let contains = self.contains(&cid);
-        FutureObj::new(Box::new(async move {
-            if await!(contains)? {
-                await!(fs::remove_file(path).compat())?;
+        Box::pin(async move {
+            if contains.await? {
+                fs::remove_file(path).await?;
                 cids.lock().unwrap().remove(&cid);
             }
             Ok(())
-        }))
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'static, Result<Vec<Cid>, Error>> {
+        let cids = self.cids.lock().unwrap().iter().cloned().collect();
+        Box::pin(async move {
+            Ok(cids)
+        })
+    }
+
+    fn stat(&self) -> BoxFuture<'static, Result<BlockCount, Error>> {
+        let paths: Vec<_> = self.cids.lock().unwrap().iter()
+            .map(|cid| block_path(self.path.clone(), cid))
+            .collect();
+        Box::pin(async move {
+            let mut count = BlockCount::default();
+            for path in paths {
+                match fs::metadata(path).await {
+                    Ok(metadata) => count.add(metadata.len() as usize),
+                    // removed since.
+                    Err(ref err) if err.kind() == ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Ok(count)
+        })
     }
 }
 
@@ -111,112 +133,217 @@ fn block_path(mut base: PathBuf, cid: &Cid) -> PathBuf {
     base
 }
 
+/// Stores each value in a file named after the hex encoded key, in a directory per column.
+#[derive(Clone, Debug)]
+pub struct FsDataStore {
+    path: PathBuf,
+}
+
+impl DataStore for FsDataStore {
+    fn new(path: PathBuf) -> Self {
+        FsDataStore {
+            path,
+        }
+    }
+
+    fn init(&self) -> BoxFuture<'static, Result<(), Error>> {
+        let paths: Vec<_> = [Column::Ipns, Column::Pin, Column::Journal, Column::PinProgress].iter()
+            .map(|col| column_path(self.path.clone(), *col))
+            .collect();
+        Box::pin(async move {
+            for path in paths {
+                fs::create_dir_all(path).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn open(&self) -> BoxFuture<'static, Result<(), Error>> {
+        let path = self.path.clone();
+        // repos initialized before the journal and pin progress existed have no directories
+        // for them.
+        let journal = column_path(self.path.clone(), Column::Journal);
+        let pin_progress = column_path(self.path.clone(), Column::PinProgress);
+        Box::pin(async move {
+            if !path.is_dir() {
+                bail!("datastore {:?} does not exist", path);
+            }
+            fs::create_dir_all(journal).await?;
+            fs::create_dir_all(pin_progress).await?;
+            Ok(())
+        })
+    }
+
+    fn contains(&self, col: Column, key: &[u8]) -> BoxFuture<'static, Result<bool, Error>> {
+        let path = value_path(self.path.clone(), col, key);
+        Box::pin(async move {
+            let _timer = metrics::time_datastore("contains");
+            Ok(path.is_file())
+        })
+    }
+
+    fn get(&self, col: Column, key: &[u8]) -> BoxFuture<'static, Result<Option<Vec<u8>>, Error>> {
+        let path = value_path(self.path.clone(), col, key);
+        Box::pin(async move {
+            let _timer = metrics::time_datastore("get");
+            match fs::read(path).await {
+                Ok(data) => Ok(Some(data)),
+                Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+
+    fn put(&self, col: Column, key: &[u8], value: &[u8]) -> BoxFuture<'static, Result<(), Error>> {
+        let path = value_path(self.path.clone(), col, key);
+        let value = value.to_vec();
+        Box::pin(async move {
+            let _timer = metrics::time_datastore("put");
+            fs::write(path, value).await?;
+            Ok(())
+        })
+    }
+
+    fn remove(&self, col: Column, key: &[u8]) -> BoxFuture<'static, Result<(), Error>> {
+        let path = value_path(self.path.clone(), col, key);
+        Box::pin(async move {
+            let _timer = metrics::time_datastore("remove");
+            match fs::remove_file(path).await {
+                Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+                res => Ok(res?),
+            }
+        })
+    }
+
+    fn keys(&self, col: Column) -> BoxFuture<'static, Result<Vec<Vec<u8>>, Error>> {
+        let path = column_path(self.path.clone(), col);
+        Box::pin(async move {
+            let _timer = metrics::time_datastore("keys");
+            let mut entries = fs::read_dir(path).await?;
+            let mut keys = Vec::new();
+            while let Some(dir) = entries.next_entry().await? {
+                if let Some(key) = dir.file_name().to_str().and_then(|name| name.from_hex().ok()) {
+                    keys.push(key);
+                }
+            }
+            Ok(keys)
+        })
+    }
+}
+
+fn column_path(mut base: PathBuf, col: Column) -> PathBuf {
+    base.push(col.as_str());
+    base
+}
+
+fn value_path(base: PathBuf, col: Column, key: &[u8]) -> PathBuf {
+    let mut path = column_path(base, col);
+    path.push(key.to_hex());
+    path
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::env::temp_dir;
 
-    #[test]
-    fn test_fs_blockstore() {
+    #[tokio::test]
+    async fn test_fs_blockstore() {
         let mut tmp = temp_dir();
         tmp.push("blockstore1");
         std::fs::remove_dir_all(tmp.clone()).ok();
         let store = FsBlockStore::new(tmp.clone());
 
-        tokio::run_async(async move {
-            let block = Block::from("1");
-            let cid = block.cid();
-
-            assert_eq!(await!(store.init()).unwrap(), ());
-            assert_eq!(await!(store.open()).unwrap(), ());
-
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), None);
-            let remove = store.remove(cid);
-            assert_eq!(await!(remove).unwrap(), ());
-
-            let put = store.put(block.clone());
-            assert_eq!(await!(put).unwrap(), cid.to_owned());
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), true);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), Some(block.clone()));
-
-            let remove = store.remove(cid);
-            assert_eq!(await!(remove).unwrap(), ());
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), None);
-        });
+        let block = Block::from("1");
+        let cid = block.cid();
+
+        assert_eq!(store.init().await.unwrap(), ());
+        assert_eq!(store.open().await.unwrap(), ());
+
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), None);
+        let remove = store.remove(cid);
+        assert_eq!(remove.await.unwrap(), ());
+
+        let put = store.put(block.clone());
+        assert_eq!(put.await.unwrap(), cid.to_owned());
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), true);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), Some(block.clone()));
+
+        let remove = store.remove(cid);
+        assert_eq!(remove.await.unwrap(), ());
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), None);
 
         std::fs::remove_dir_all(tmp).ok();
     }
 
-    #[test]
-    fn test_fs_blockstore_open() {
+    #[tokio::test]
+    async fn test_fs_blockstore_open() {
         let mut tmp = temp_dir();
         tmp.push("blockstore2");
         std::fs::remove_dir_all(tmp.clone()).ok();
 
         let blockstore_path = tmp.clone();
-        tokio::run_async(async move {
-            let block = Block::from("1");
+        let block = Block::from("1");
 
-            let block_store = FsBlockStore::new(blockstore_path.clone());
-            await!(block_store.init()).unwrap();
-            await!(block_store.open()).unwrap();
+        let block_store = FsBlockStore::new(blockstore_path.clone());
+        block_store.init().await.unwrap();
+        block_store.open().await.unwrap();
 
-            assert!(!await!(block_store.contains(block.cid())).unwrap());
-            await!(block_store.put(block.clone())).unwrap();
+        assert!(!block_store.contains(block.cid()).await.unwrap());
+        block_store.put(block.clone()).await.unwrap();
 
-            let block_store = FsBlockStore::new(blockstore_path);
-            await!(block_store.open()).unwrap();
-            assert!(await!(block_store.contains(block.cid())).unwrap());
-            assert_eq!(await!(block_store.get(block.cid())).unwrap().unwrap(), block);
-        });
+        let block_store = FsBlockStore::new(blockstore_path);
+        block_store.open().await.unwrap();
+        assert!(block_store.contains(block.cid()).await.unwrap());
+        assert_eq!(block_store.get(block.cid()).await.unwrap().unwrap(), block);
+        block_store.put(block.clone()).await.unwrap();
+        assert_eq!(block_store.stat().await.unwrap(), BlockCount { blocks: 1, bytes: 1 });
 
         std::fs::remove_dir_all(tmp).ok();
     }
 
-    #[test]
-    fn test_rocks_datastore() {
+    #[tokio::test]
+    async fn test_fs_datastore() {
         let mut tmp = temp_dir();
         tmp.push("datastore1");
         std::fs::remove_dir_all(tmp.clone()).ok();
-        let store = RocksDataStore::new(tmp.clone());
-
-        tokio::run_async(async move {
-            let col = Column::Ipns;
-            let key = [1, 2, 3, 4];
-            let value = [5, 6, 7, 8];
-
-            assert_eq!(await!(store.init()).unwrap(), ());
-            assert_eq!(await!(store.open()).unwrap(), ());
-
-            let contains = store.contains(col, &key);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(col, &key);
-            assert_eq!(await!(get).unwrap(), None);
-            let remove = store.remove(col, &key);
-            assert_eq!(await!(remove).unwrap(), ());
-
-            let put = store.put(col, &key, &value);
-            assert_eq!(await!(put).unwrap(), ());
-            let contains = store.contains(col, &key);
-            assert_eq!(await!(contains).unwrap(), true);
-            let get = store.get(col, &key);
-            assert_eq!(await!(get).unwrap(), Some(value.to_vec()));
-
-            let remove = store.remove(col, &key);
-            assert_eq!(await!(remove).unwrap(), ());
-            let contains = store.contains(col, &key);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(col, &key);
-            assert_eq!(await!(get).unwrap(), None);
-        });
+        let store = FsDataStore::new(tmp.clone());
+
+        let col = Column::Ipns;
+        let key = [1, 2, 3, 4];
+        let value = [5, 6, 7, 8];
+
+        assert_eq!(store.init().await.unwrap(), ());
+        assert_eq!(store.open().await.unwrap(), ());
+
+        let contains = store.contains(col, &key);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(col, &key);
+        assert_eq!(get.await.unwrap(), None);
+        let remove = store.remove(col, &key);
+        assert_eq!(remove.await.unwrap(), ());
+
+        let put = store.put(col, &key, &value);
+        assert_eq!(put.await.unwrap(), ());
+        let contains = store.contains(col, &key);
+        assert_eq!(contains.await.unwrap(), true);
+        let get = store.get(col, &key);
+        assert_eq!(get.await.unwrap(), Some(value.to_vec()));
+
+        let remove = store.remove(col, &key);
+        assert_eq!(remove.await.unwrap(), ());
+        let contains = store.contains(col, &key);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(col, &key);
+        assert_eq!(get.await.unwrap(), None);
 
         std::fs::remove_dir_all(tmp).ok();
     }
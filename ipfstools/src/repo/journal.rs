@@ -0,0 +1,233 @@
+//! Journal of the wants and provides not carried out yet.
+//!
+//! Repo events only live in the channel to the swarm, so a node that stops drops every block it
+//! was waiting for and every block it had yet to announce. The journal records them in
+//! `Column::Journal` until they are settled, and the repo sends them again when it is opened.
+//!
+//! A want is settled once the block is stored or the last request waiting for it gives up,
+//! a provide once the swarm announced the block or it was removed.
+use crate::block::Cid;
+use crate::error::Error;
+use crate::repo::{Column, DataStore, RepoEvent, RepoTypes};
+use core::future::Future;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Kind of a journaled event, the first byte of its key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Intent {
+    Want,
+    Provide,
+}
+
+impl Intent {
+    fn to_byte(self) -> u8 {
+        match self {
+            Intent::Want => 0,
+            Intent::Provide => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, Error> {
+        match byte {
+            0 => Ok(Intent::Want),
+            1 => Ok(Intent::Provide),
+            _ => bail!("invalid journal intent {}", byte),
+        }
+    }
+
+    fn key(self, cid: &Cid) -> Vec<u8> {
+        let mut key = vec![self.to_byte()];
+        key.extend(cid.to_bytes());
+        key
+    }
+}
+
+/// The outstanding wants and provides of a repo, stored in `Column::Journal`.
+#[derive(Clone, Debug)]
+pub struct Journal<TRepoTypes: RepoTypes> {
+    data_store: TRepoTypes::TDataStore,
+    /// Number of requests waiting for each journaled want, none for replayed wants.
+    wants: Arc<Mutex<HashMap<Cid, usize>>>,
+}
+
+impl<TRepoTypes: RepoTypes> Journal<TRepoTypes> {
+    pub fn new(data_store: TRepoTypes::TDataStore) -> Self {
+        Journal {
+            data_store,
+            wants: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records that a request waits for the block `cid`, hinting `providers` to have it.
+    pub fn want(&self, cid: &Cid, providers: &[PeerId]) -> impl Future<Output=Result<(), Error>> {
+        *self.wants.lock().unwrap().entry(cid.to_owned()).or_insert(0) += 1;
+        let mut value = Vec::new();
+        for peer in providers {
+            let bytes = peer.as_bytes();
+            value.push(bytes.len() as u8);
+            value.extend(bytes);
+        }
+        self.data_store.put(Column::Journal, &Intent::Want.key(cid), &value)
+    }
+
+    /// Records that a request stopped waiting for the block `cid`, settling the want if it was
+    /// the last one.
+    pub fn release_want(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let settled = {
+            let mut wants = self.wants.lock().unwrap();
+            match wants.get_mut(cid) {
+                Some(waiting) if *waiting > 1 => {
+                    *waiting -= 1;
+                    false
+                }
+                Some(_) => wants.remove(cid).is_some(),
+                None => false,
+            }
+        };
+        self.remove_if(settled, Intent::Want, cid)
+    }
+
+    /// Settles the want of the block `cid` because it was stored.
+    pub fn settle_want(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let settled = self.wants.lock().unwrap().remove(cid).is_some();
+        self.remove_if(settled, Intent::Want, cid)
+    }
+
+    /// Records that the block `cid` is to be provided.
+    pub fn provide(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        self.data_store.put(Column::Journal, &Intent::Provide.key(cid), &[])
+    }
+
+    /// Settles the provide of the block `cid`.
+    pub fn settle_provide(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        self.data_store.remove(Column::Journal, &Intent::Provide.key(cid))
+    }
+
+    /// The events of the wants and provides not settled yet, in no particular order.
+    ///
+    /// The wants are kept until their blocks are stored, no request waits for them anymore.
+    pub fn pending(&self) -> impl Future<Output=Result<Vec<RepoEvent>, Error>> {
+        let data_store = self.data_store.clone();
+        let wants = self.wants.clone();
+        async move {
+            let mut events = Vec::new();
+            for key in data_store.keys(Column::Journal).await? {
+                if key.is_empty() {
+                    bail!("empty journal key");
+                }
+                let cid = Cid::from(&key[1..])?;
+                match Intent::from_byte(key[0])? {
+                    Intent::Want => {
+                        let value = match data_store.get(Column::Journal, &key).await? {
+                            Some(value) => value,
+                            None => continue,
+                        };
+                        wants.lock().unwrap().entry(cid.clone()).or_insert(0);
+                        events.push(RepoEvent::WantBlock(cid, decode_peers(&value)?));
+                    }
+                    Intent::Provide => events.push(RepoEvent::ProvideBlock(cid)),
+                }
+            }
+            Ok(events)
+        }
+    }
+
+    fn remove_if(&self, settled: bool, intent: Intent, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let remove = if settled {
+            Some(self.data_store.remove(Column::Journal, &intent.key(cid)))
+        } else {
+            None
+        };
+        async move {
+            if let Some(remove) = remove {
+                remove.await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn decode_peers(mut bytes: &[u8]) -> Result<Vec<PeerId>, Error> {
+    let mut peers = Vec::new();
+    while !bytes.is_empty() {
+        let len = bytes[0] as usize + 1;
+        if len > bytes.len() {
+            bail!("truncated peer id in journal");
+        }
+        match PeerId::from_bytes(bytes[1..len].to_vec()) {
+            Ok(peer) => peers.push(peer),
+            Err(_) => bail!("invalid peer id in journal"),
+        }
+        bytes = &bytes[len..];
+    }
+    Ok(peers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::repo::tests::create_mock_repo;
+
+    fn sorted(events: Vec<RepoEvent>) -> Vec<String> {
+        let mut events: Vec<String> = events.iter().map(|event| format!("{:?}", event)).collect();
+        events.sort();
+        events
+    }
+
+    #[tokio::test]
+    async fn test_pending() {
+        let repo = create_mock_repo();
+        let journal = repo.journal().clone();
+        let wanted = Block::from("wanted").cid().to_owned();
+        let provided = Block::from("provided").cid().to_owned();
+        let peers = vec![PeerId::random(), PeerId::random()];
+
+        journal.want(&wanted, &peers).await.unwrap();
+        journal.provide(&provided).await.unwrap();
+
+        let events = journal.pending().await.unwrap();
+        assert_eq!(sorted(events), sorted(vec![
+            RepoEvent::WantBlock(wanted.clone(), peers),
+            RepoEvent::ProvideBlock(provided.clone()),
+        ]));
+
+        journal.settle_provide(&provided).await.unwrap();
+        journal.release_want(&wanted).await.unwrap();
+        assert!(journal.pending().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_release_want() {
+        let repo = create_mock_repo();
+        let journal = repo.journal().clone();
+        let cid = Block::from("wanted").cid().to_owned();
+
+        journal.want(&cid, &[]).await.unwrap();
+        journal.want(&cid, &[]).await.unwrap();
+
+        // the second request still waits.
+        journal.release_want(&cid).await.unwrap();
+        assert_eq!(journal.pending().await.unwrap().len(), 1);
+
+        journal.release_want(&cid).await.unwrap();
+        assert!(journal.pending().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_settle_replayed_want() {
+        let repo = create_mock_repo();
+        let block = Block::from("wanted");
+        let cid = block.cid().to_owned();
+
+        repo.journal().want(&cid, &[]).await.unwrap();
+        // a restarted node has a fresh journal with no requests waiting.
+        let journal = Journal::<crate::repo::tests::Types>::new(repo.data_store().clone());
+        assert_eq!(journal.pending().await.unwrap().len(), 1);
+
+        journal.settle_want(&cid).await.unwrap();
+        assert!(journal.pending().await.unwrap().is_empty());
+    }
+}
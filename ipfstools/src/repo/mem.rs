@@ -1,8 +1,8 @@
 //! Volatile memory backed repo
 use crate::block::{Cid, Block};
 use crate::error::Error;
-use crate::repo::{BlockStore};
-use futures::future::FutureObj;
+use crate::repo::{BlockCount, BlockStore, Column, DataStore};
+use futures::future::BoxFuture;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
@@ -19,36 +19,102 @@ impl BlockStore for MemBlockStore {
         }
     }
 
-    fn init(&self) -> FutureObj<'static, Result<(), Error>> {
-        FutureObj::new(Box::new(futures::future::ok(())))
+    fn init(&self) -> BoxFuture<'static, Result<(), Error>> {
+        Box::pin(futures::future::ok(()))
     }
 
-    fn open(&self) -> FutureObj<'static, Result<(), Error>> {
-        FutureObj::new(Box::new(futures::future::ok(())))
+    fn open(&self) -> BoxFuture<'static, Result<(), Error>> {
+        Box::pin(futures::future::ok(()))
     }
 
-    fn contains(&self, cid: &Cid) -> FutureObj<'static, Result<bool, Error>> {
+    fn contains(&self, cid: &Cid) -> BoxFuture<'static, Result<bool, Error>> {
         let contains = self.blocks.lock().unwrap().contains_key(cid);
-        FutureObj::new(Box::new(futures::future::ok(contains)))
+        Box::pin(futures::future::ok(contains))
     }
 
-    fn get(&self, cid: &Cid) -> FutureObj<'static, Result<Option<Block>, Error>> {
+    fn get(&self, cid: &Cid) -> BoxFuture<'static, Result<Option<Block>, Error>> {
         let block = self.blocks.lock().unwrap()
             .get(cid)
             .map(|block| block.to_owned());
-        FutureObj::new(Box::new(futures::future::ok(block)))
+        Box::pin(futures::future::ok(block))
     }
 
-    fn put(&self, block: Block) -> FutureObj<'static, Result<Cid, Error>> {
+    fn put(&self, block: Block) -> BoxFuture<'static, Result<Cid, Error>> {
         let cid = block.cid().to_owned();
         self.blocks.lock().unwrap()
             .insert(cid.clone(), block);
-        FutureObj::new(Box::new(futures::future::ok(cid)))
+        Box::pin(futures::future::ok(cid))
     }
 
-    fn remove(&self, cid: &Cid) -> FutureObj<'static, Result<(), Error>> {
+    fn remove(&self, cid: &Cid) -> BoxFuture<'static, Result<(), Error>> {
         self.blocks.lock().unwrap().remove(cid);
-        FutureObj::new(Box::new(futures::future::ok(())))
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn list(&self) -> BoxFuture<'static, Result<Vec<Cid>, Error>> {
+        let cids = self.blocks.lock().unwrap().keys().cloned().collect();
+        Box::pin(futures::future::ok(cids))
+    }
+
+    fn stat(&self) -> BoxFuture<'static, Result<BlockCount, Error>> {
+        let mut count = BlockCount::default();
+        for block in self.blocks.lock().unwrap().values() {
+            count.add(block.size());
+        }
+        Box::pin(futures::future::ok(count))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MemDataStore {
+    data: Arc<Mutex<HashMap<(Column, Vec<u8>), Vec<u8>>>>,
+}
+
+impl DataStore for MemDataStore {
+    fn new(_path: PathBuf) -> Self {
+        MemDataStore {
+            data: Arc::new(Mutex::new(HashMap::new()))
+        }
+    }
+
+    fn init(&self) -> BoxFuture<'static, Result<(), Error>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn open(&self) -> BoxFuture<'static, Result<(), Error>> {
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn contains(&self, col: Column, key: &[u8]) -> BoxFuture<'static, Result<bool, Error>> {
+        let contains = self.data.lock().unwrap().contains_key(&(col, key.to_vec()));
+        Box::pin(futures::future::ok(contains))
+    }
+
+    fn get(&self, col: Column, key: &[u8]) -> BoxFuture<'static, Result<Option<Vec<u8>>, Error>> {
+        let value = self.data.lock().unwrap()
+            .get(&(col, key.to_vec()))
+            .map(|value| value.to_owned());
+        Box::pin(futures::future::ok(value))
+    }
+
+    fn put(&self, col: Column, key: &[u8], value: &[u8]) -> BoxFuture<'static, Result<(), Error>> {
+        self.data.lock().unwrap()
+            .insert((col, key.to_vec()), value.to_vec());
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn remove(&self, col: Column, key: &[u8]) -> BoxFuture<'static, Result<(), Error>> {
+        self.data.lock().unwrap().remove(&(col, key.to_vec()));
+        Box::pin(futures::future::ok(()))
+    }
+
+    fn keys(&self, col: Column) -> BoxFuture<'static, Result<Vec<Vec<u8>>, Error>> {
+        let keys = self.data.lock().unwrap()
+            .keys()
+            .filter(|(key_col, _)| *key_col == col)
+            .map(|(_, key)| key.to_owned())
+            .collect();
+        Box::pin(futures::future::ok(keys))
     }
 }
 
@@ -57,72 +123,74 @@ mod tests {
     use super::*;
     use std::env::temp_dir;
 
-    #[test]
-    fn test_mem_blockstore() {
+    #[tokio::test]
+    async fn test_mem_blockstore() {
         let tmp = temp_dir();
         let store = MemBlockStore::new(tmp);
-        tokio::run_async(async move {
-            let block = Block::from("1");
-            let cid = block.cid();
-
-            assert_eq!(await!(store.init()).unwrap(), ());
-            assert_eq!(await!(store.open()).unwrap(), ());
-
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), None);
-            let remove = store.remove(cid);
-            assert_eq!(await!(remove).unwrap(), ());
-
-            let put = store.put(block.clone());
-            assert_eq!(await!(put).unwrap(), cid.to_owned());
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), true);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), Some(block.clone()));
-
-            let remove = store.remove(cid);
-            assert_eq!(await!(remove).unwrap(), ());
-            let contains = store.contains(cid);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(cid);
-            assert_eq!(await!(get).unwrap(), None);
-        });
-    }
-
-    #[test]
-    fn test_mem_datastore() {
+        let block = Block::from("1");
+        let cid = block.cid();
+
+        assert_eq!(store.init().await.unwrap(), ());
+        assert_eq!(store.open().await.unwrap(), ());
+
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), None);
+        let remove = store.remove(cid);
+        assert_eq!(remove.await.unwrap(), ());
+
+        let put = store.put(block.clone());
+        assert_eq!(put.await.unwrap(), cid.to_owned());
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), true);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), Some(block.clone()));
+        let put = store.put(block.clone());
+        assert_eq!(put.await.unwrap(), cid.to_owned());
+        let stat = store.stat();
+        assert_eq!(stat.await.unwrap(), BlockCount { blocks: 1, bytes: 1 });
+
+        let remove = store.remove(cid);
+        assert_eq!(remove.await.unwrap(), ());
+        let contains = store.contains(cid);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(cid);
+        assert_eq!(get.await.unwrap(), None);
+        let stat = store.stat();
+        assert_eq!(stat.await.unwrap(), BlockCount::default());
+    }
+
+    #[tokio::test]
+    async fn test_mem_datastore() {
         let tmp = temp_dir();
         let store = MemDataStore::new(tmp);
-        tokio::run_async(async move {
-            let col = Column::Ipns;
-            let key = [1, 2, 3, 4];
-            let value = [5, 6, 7, 8];
-
-            assert_eq!(await!(store.init()).unwrap(), ());
-            assert_eq!(await!(store.open()).unwrap(), ());
-
-            let contains = store.contains(col, &key);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(col, &key);
-            assert_eq!(await!(get).unwrap(), None);
-            let remove = store.remove(col, &key);
-            assert_eq!(await!(remove).unwrap(), ());
-
-            let put = store.put(col, &key, &value);
-            assert_eq!(await!(put).unwrap(), ());
-            let contains = store.contains(col, &key);
-            assert_eq!(await!(contains).unwrap(), true);
-            let get = store.get(col, &key);
-            assert_eq!(await!(get).unwrap(), Some(value.to_vec()));
-
-            let remove = store.remove(col, &key);
-            assert_eq!(await!(remove).unwrap(), ());
-            let contains = store.contains(col, &key);
-            assert_eq!(await!(contains).unwrap(), false);
-            let get = store.get(col, &key);
-            assert_eq!(await!(get).unwrap(), None);
-        });
+        let col = Column::Ipns;
+        let key = [1, 2, 3, 4];
+        let value = [5, 6, 7, 8];
+
+        assert_eq!(store.init().await.unwrap(), ());
+        assert_eq!(store.open().await.unwrap(), ());
+
+        let contains = store.contains(col, &key);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(col, &key);
+        assert_eq!(get.await.unwrap(), None);
+        let remove = store.remove(col, &key);
+        assert_eq!(remove.await.unwrap(), ());
+
+        let put = store.put(col, &key, &value);
+        assert_eq!(put.await.unwrap(), ());
+        let contains = store.contains(col, &key);
+        assert_eq!(contains.await.unwrap(), true);
+        let get = store.get(col, &key);
+        assert_eq!(get.await.unwrap(), Some(value.to_vec()));
+
+        let remove = store.remove(col, &key);
+        assert_eq!(remove.await.unwrap(), ());
+        let contains = store.contains(col, &key);
+        assert_eq!(contains.await.unwrap(), false);
+        let get = store.get(col, &key);
+        assert_eq!(get.await.unwrap(), None);
     }
 }
@@ -0,0 +1,38 @@
+//! Prometheus metrics of the repo, registered with the default registry and served by the API.
+use prometheus::{HistogramTimer, HistogramVec, IntCounter, IntCounterVec};
+
+lazy_static! {
+    /// Blocks put into the block store.
+    pub static ref BLOCK_PUTS: IntCounter = register_int_counter!(
+        "repo_block_puts_total",
+        "Blocks put into the block store"
+    ).expect("metric is registered once; qed");
+    /// Blocks put that were stored already, and not written again.
+    pub static ref BLOCK_PUTS_DUPLICATE: IntCounter = register_int_counter!(
+        "repo_block_puts_duplicate_total",
+        "Blocks put that were stored already"
+    ).expect("metric is registered once; qed");
+    /// Block requests, by where the block came from: `local`, `network` or `failed`.
+    pub static ref BLOCK_GETS: IntCounterVec = register_int_counter_vec!(
+        "repo_block_gets_total",
+        "Block requests, by where the block came from",
+        &["source"]
+    ).expect("metric is registered once; qed");
+    /// Lookups in the block cache, by result: `hit` or `miss`.
+    pub static ref BLOCK_CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "repo_block_cache_lookups_total",
+        "Lookups in the block cache, by result",
+        &["result"]
+    ).expect("metric is registered once; qed");
+    /// Time taken by the operations of the data store, by operation.
+    static ref DATASTORE_SECONDS: HistogramVec = register_histogram_vec!(
+        "repo_datastore_seconds",
+        "Time taken by the operations of the data store",
+        &["op"]
+    ).expect("metric is registered once; qed");
+}
+
+/// Times the data store operation `op` until the returned timer is dropped.
+pub fn time_datastore(op: &str) -> HistogramTimer {
+    DATASTORE_SECONDS.with_label_values(&[op]).start_timer()
+}
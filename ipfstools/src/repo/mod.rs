@@ -1,19 +1,50 @@
 //! IPFS repo
 use crate::block::{Cid, Block};
+use crate::cid_profile;
+use crate::context::{Context, ContextError};
 use crate::error::Error;
 use crate::future::BlockFuture;
+use crate::ipld::formats;
+use crate::ipld::links::block_links;
 use crate::IpfsOptions;
 use core::future::Future;
-use futures::future::FutureObj;
+use libp2p::PeerId;
+use futures::channel::mpsc::unbounded;
+use futures::future::BoxFuture;
+use futures::stream::StreamExt;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashSet};
 use std::marker::PhantomData;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::sync::mpsc::{channel, Sender, Receiver};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
 
 pub mod mem;
 pub mod fs;
+pub mod ds;
+pub mod cache;
+pub mod error;
+pub mod exchange;
+pub mod journal;
+mod metrics;
+pub mod pin;
+pub mod watch;
+
+pub use self::cache::CachedBlockStore;
+pub use self::error::RepoError;
+pub use self::exchange::BlockExchange;
+pub use self::journal::Journal;
+pub use self::pin::{PinMode, PinProgress, PinStore};
+pub use self::watch::{RepoChange, RepoWatchers};
+
+/// Blocks fetched by `Repo::pin_add` between saving its progress.
+const PIN_PROGRESS_INTERVAL: u64 = 64;
 
 pub trait RepoTypes: Clone + Send + Sync + 'static {
     type TBlockStore: BlockStore;
+    type TDataStore: DataStore;
 }
 
 #[derive(Clone, Debug)]
@@ -38,22 +69,62 @@ pub fn create_repo<TRepoTypes: RepoTypes>(options: RepoOptions<TRepoTypes>) -> (
 pub trait BlockStore: Clone + Send + Sync + Unpin + 'static {
     fn new(path: PathBuf) -> Self;
     fn init(&self) ->
-        FutureObj<'static, Result<(), Error>>;
+        BoxFuture<'static, Result<(), Error>>;
     fn open(&self) ->
-        FutureObj<'static, Result<(), Error>>;
+        BoxFuture<'static, Result<(), Error>>;
     fn contains(&self, cid: &Cid) ->
-        FutureObj<'static, Result<bool, Error>>;
+        BoxFuture<'static, Result<bool, Error>>;
     fn get(&self, cid: &Cid) ->
-        FutureObj<'static, Result<Option<Block>, Error>>;
+        BoxFuture<'static, Result<Option<Block>, Error>>;
+    /// Stores `block`. Putting a block that is stored already leaves the store unchanged.
     fn put(&self, block: Block) ->
-        FutureObj<'static, Result<Cid, Error>>;
+        BoxFuture<'static, Result<Cid, Error>>;
     fn remove(&self, cid: &Cid) ->
-        FutureObj<'static, Result<(), Error>>;
+        BoxFuture<'static, Result<(), Error>>;
+    /// Returns the cids of all stored blocks, in no particular order.
+    fn list(&self) ->
+        BoxFuture<'static, Result<Vec<Cid>, Error>>;
+    /// Returns the number and total size of the stored blocks.
+    fn stat(&self) ->
+        BoxFuture<'static, Result<BlockCount, Error>>;
 }
 
-#[derive(Clone, Copy, Debug)]
+pub trait DataStore: Clone + Send + Sync + Unpin + 'static {
+    fn new(path: PathBuf) -> Self;
+    fn init(&self) ->
+        BoxFuture<'static, Result<(), Error>>;
+    fn open(&self) ->
+        BoxFuture<'static, Result<(), Error>>;
+    fn contains(&self, col: Column, key: &[u8]) ->
+        BoxFuture<'static, Result<bool, Error>>;
+    fn get(&self, col: Column, key: &[u8]) ->
+        BoxFuture<'static, Result<Option<Vec<u8>>, Error>>;
+    fn put(&self, col: Column, key: &[u8], value: &[u8]) ->
+        BoxFuture<'static, Result<(), Error>>;
+    fn remove(&self, col: Column, key: &[u8]) ->
+        BoxFuture<'static, Result<(), Error>>;
+    /// Returns all keys of `col`, in no particular order.
+    fn keys(&self, col: Column) ->
+        BoxFuture<'static, Result<Vec<Vec<u8>>, Error>>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Column {
-    Ipns
+    Ipns,
+    Pin,
+    Journal,
+    PinProgress,
+}
+
+impl Column {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Column::Ipns => "ipns",
+            Column::Pin => "pin",
+            Column::Journal => "journal",
+            Column::PinProgress => "pin_progress",
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -66,12 +137,95 @@ pub enum DBColumn {
 #[derive(Clone, Debug)]
 pub struct Repo<TRepoTypes: RepoTypes> {
     block_store: TRepoTypes::TBlockStore,
+    data_store: TRepoTypes::TDataStore,
+    pins: PinStore<TRepoTypes>,
+    journal: Journal<TRepoTypes>,
+    exchange: BlockExchange,
     events: Sender<RepoEvent>,
+    watchers: RepoWatchers,
+    /// Blocks and pins are added under the read side, blocks are removed under the write side.
+    gc_lock: Arc<RwLock<()>>,
+}
+
+/// Time spent in each stage of `Repo::put_blocks`.
+///
+/// `hash` and `validate` are summed over all worker threads, so they can exceed `total`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PutTimings {
+    pub hash: Duration,
+    pub validate: Duration,
+    pub write: Duration,
+    pub total: Duration,
+}
+
+/// Outcome of `Repo::gc`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GcReport {
+    /// The removed blocks.
+    pub freed: Vec<Cid>,
+    /// Total size of the removed blocks.
+    pub bytes: u64,
+}
+
+/// Number and total size of blocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BlockCount {
+    pub blocks: u64,
+    pub bytes: u64,
+}
+
+impl BlockCount {
+    fn add(&mut self, size: usize) {
+        self.blocks += 1;
+        self.bytes += size as u64;
+    }
+}
+
+/// Outcome of `Repo::stats`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepoStats {
+    /// All stored blocks.
+    pub total: BlockCount,
+    /// The blocks by codec name, like `raw` or `dag-pb`.
+    pub codecs: BTreeMap<String, BlockCount>,
+    /// The blocks by multihash name, like `sha2-256`.
+    pub hashes: BTreeMap<String, BlockCount>,
+}
+
+impl RepoStats {
+    fn add(&mut self, cid: &Cid, size: usize) {
+        let prefix = cid.prefix();
+        self.total.add(size);
+        self.codecs.entry(codec_name(prefix.codec)).or_default().add(size);
+        self.hashes.entry(hash_name(prefix.mh_type)).or_default().add(size);
+    }
+}
+
+/// How long `Repo::get_block` waits for a missing block and how often it asks for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GetBlockOptions {
+    /// How long each attempt waits for the block.
+    pub timeout: Duration,
+    /// Attempts after the first one, each sending the want again.
+    pub retries: u32,
+    /// Peers likely to have the block, connected to and asked on top of the connected peers.
+    pub providers_hint: Vec<PeerId>,
+}
+
+impl Default for GetBlockOptions {
+    fn default() -> Self {
+        GetBlockOptions {
+            timeout: Duration::from_secs(30),
+            retries: 2,
+            providers_hint: Vec::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum RepoEvent {
-    WantBlock(Cid),
+    /// A block is wanted, with the peers hinted to provide it.
+    WantBlock(Cid, Vec<PeerId>),
     ProvideBlock(Cid),
     UnprovideBlock(Cid),
 }
@@ -80,69 +234,510 @@ impl<TRepoTypes: RepoTypes> Repo<TRepoTypes> {
     pub fn new(options: RepoOptions<TRepoTypes>) -> (Self, Receiver<RepoEvent>) {
         let mut blockstore_path = options.path.clone();
         blockstore_path.push("blockstore");
+        let mut datastore_path = options.path.clone();
+        datastore_path.push("datastore");
         let block_store = TRepoTypes::TBlockStore::new(blockstore_path);
+        let data_store = TRepoTypes::TDataStore::new(datastore_path);
+        let gc_lock = Arc::new(RwLock::new(()));
+        let pins = PinStore::new(data_store.clone(), block_store.clone(), gc_lock.clone());
+        let journal = Journal::new(data_store.clone());
         let (sender, receiver) = channel::<RepoEvent>();
 
         (Repo {
             block_store,
+            data_store,
+            pins,
+            journal,
+            exchange: BlockExchange::new(),
             events: sender,
+            watchers: RepoWatchers::new(),
+            gc_lock,
         }, receiver)
     }
 
-    pub fn init(&self) -> FutureObj<'static, Result<(), Error>> {
-        self.block_store.init()
+    pub fn init(&self) -> impl Future<Output=Result<(), Error>> {
+        let block_store = self.block_store.init();
+        let data_store = self.data_store.init();
+        async move {
+            block_store.await?;
+            data_store.await
+        }
     }
 
-    pub fn open(&self) -> FutureObj<'static, Result<(), Error>> {
-        self.block_store.open()
+    /// Opens the stores and sends the wants and provides left in the journal again.
+    pub fn open(&self) -> impl Future<Output=Result<(), Error>> {
+        let block_store = self.block_store.open();
+        let data_store = self.data_store.open();
+        let repo = self.clone();
+        async move {
+            block_store.await?;
+            data_store.await?;
+            repo.replay_journal().await?;
+            Ok(())
+        }
     }
 
-    /// Puts a block into the block store.
+    /// Sends the events of the wants and provides not settled yet, returning their number.
+    pub fn replay_journal(&self) -> impl Future<Output=Result<usize, Error>> {
+        let events = self.events.clone();
+        let pending = self.journal.pending();
+        async move {
+            let pending = pending.await?;
+            let count = pending.len();
+            for event in pending {
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = events.send(event);
+            }
+            Ok(count)
+        }
+    }
+
+    pub(crate) fn data_store(&self) -> &TRepoTypes::TDataStore {
+        &self.data_store
+    }
+
+    /// The pins protecting blocks from removal.
+    pub fn pins(&self) -> &PinStore<TRepoTypes> {
+        &self.pins
+    }
+
+    /// The wants and provides not carried out yet, see `Journal`.
+    pub fn journal(&self) -> &Journal<TRepoTypes> {
+        &self.journal
+    }
+
+    /// The blocks pending requests wait for, see `BlockExchange`.
+    pub fn exchange(&self) -> &BlockExchange {
+        &self.exchange
+    }
+
+    /// Returns a receiver of the blocks imported, the dags pinned and the collections finished
+    /// from now on.
+    pub fn subscribe(&self) -> Receiver<RepoChange> {
+        self.watchers.subscribe()
+    }
+
+    /// Puts a block into the block store and hands it to the requests waiting for it.
+    ///
+    /// Blocks that are stored already are not written again. The block is journaled to be
+    /// provided until the swarm announced it.
     pub fn put_block(&self, block: Block) ->
     impl Future<Output=Result<Cid, Error>>
     {
         let events = self.events.clone();
         let block_store = self.block_store.clone();
+        let exchange = self.exchange.clone();
+        let journal = self.journal.clone();
+        let pins = self.pins.clone();
+        let watchers = self.watchers.clone();
+        let gc_lock = self.gc_lock.clone();
         async move {
-            let cid = await!(block_store.put(block))?;
+            let _gc = gc_lock.read().await;
+            let wanted = if exchange.is_wanted(block.cid()) { Some(block.clone()) } else { None };
+            let cid = put_new_block(&block_store, block).await?;
+            pins.invalidate();
+            if let Some(block) = wanted {
+                exchange.inject_block(block);
+            }
+            journal.settle_want(&cid).await?;
+            journal.provide(&cid).await?;
             // sending only fails if no one is listening anymore
             // and that is okay with us.
             let _ = events.send(RepoEvent::ProvideBlock(cid.clone()));
+            watchers.notify(RepoChange::BlockImported(cid.clone()));
             Ok(cid)
         }
     }
 
+    /// Puts a batch of blocks into the block store.
+    ///
+    /// Blocks are hashed and validated on the rayon pool while already verified blocks are
+    /// written, so hashing no longer bounds the import rate. Blocks that are stored already are
+    /// not written again, so importing the same blocks twice only hashes them. Returns the cids in input order and
+    /// the time spent in each stage. Fails on the first block whose data does not match its cid
+    /// or that is not valid for its codec; blocks written before that are kept.
+    pub fn put_blocks(&self, blocks: Vec<Block>) ->
+    impl Future<Output=Result<(Vec<Cid>, PutTimings), Error>>
+    {
+        self.write_blocks(blocks, None)
+    }
+
+    /// Puts a batch of blocks like `put_blocks` and pins `root` recursively.
+    ///
+    /// The blocks and the pin are written under the same read side of the gc lock, so a
+    /// collection never sees the blocks without the pin. The pin is only written once every
+    /// block was.
+    pub fn put_blocks_pinned(&self, blocks: Vec<Block>, root: &Cid) ->
+    impl Future<Output=Result<(Vec<Cid>, PutTimings), Error>>
+    {
+        self.write_blocks(blocks, Some(root.to_owned()))
+    }
+
+    fn write_blocks(&self, blocks: Vec<Block>, pin: Option<Cid>) ->
+    impl Future<Output=Result<(Vec<Cid>, PutTimings), Error>>
+    {
+        let events = self.events.clone();
+        let block_store = self.block_store.clone();
+        let exchange = self.exchange.clone();
+        let journal = self.journal.clone();
+        let pins = self.pins.clone();
+        let watchers = self.watchers.clone();
+        let gc_lock = self.gc_lock.clone();
+        async move {
+            let _gc = gc_lock.read().await;
+            let start = Instant::now();
+            let mut timings = PutTimings::default();
+            let mut cids = vec![None; blocks.len()];
+
+            let (tx, mut rx) = unbounded();
+            // waits on the rayon pool, so it must not hold up a worker of the runtime.
+            let prepare = tokio::task::spawn_blocking(move || {
+                blocks.into_par_iter().enumerate().for_each_with(tx, |tx, (i, block)| {
+                    // sending only fails if the write stage gave up already.
+                    let _ = tx.unbounded_send((i, prepare_block(block)));
+                });
+            });
+
+            while let Some((i, prepared)) = rx.next().await {
+                let (block, hash, validate) = prepared?;
+                timings.hash += hash;
+                timings.validate += validate;
+
+                let write = Instant::now();
+                let wanted = if exchange.is_wanted(block.cid()) { Some(block.clone()) } else { None };
+                let cid = put_new_block(&block_store, block).await?;
+                pins.invalidate();
+                timings.write += write.elapsed();
+                if let Some(block) = wanted {
+                    exchange.inject_block(block);
+                }
+                journal.settle_want(&cid).await?;
+                journal.provide(&cid).await?;
+
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = events.send(RepoEvent::ProvideBlock(cid.clone()));
+                watchers.notify(RepoChange::BlockImported(cid.clone()));
+                cids[i] = Some(cid);
+            }
+
+            // the blocks stop arriving early only if preparing one of them panicked.
+            prepare.await.map_err(|err| format_err!("failed to prepare blocks: {}", err))?;
+            let cids = cids.into_iter().collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format_err!("not every block was prepared"))?;
+            if let Some(root) = pin {
+                pins.pin_recursive_locked(&root).await?;
+                watchers.notify(RepoChange::Pinned(root));
+            }
+            timings.total = start.elapsed();
+            Ok((cids, timings))
+        }
+    }
+
+    /// Retrives a block from the block store, see `get_block_with_options`.
+    pub fn get_block(&self, cid: &Cid, ctx: Context) ->
+    impl Future<Output=Result<Block, Error>>
+    {
+        self.get_block_with_options(cid, ctx, GetBlockOptions::default())
+    }
+
     /// Retrives a block from the block store.
-    pub fn get_block(&self, cid: &Cid) ->
+    ///
+    /// Missing blocks are requested from the network unless `ctx` does not want it, in which case
+    /// they fail with `ContextError::NotAvailableOffline`. Each attempt waits `options.timeout`
+    /// for the block to be stored or injected into the `exchange`, and the want is sent again on
+    /// every retry. Once all attempts are used up
+    /// the request fails with `RepoError::BlockNotFound`. Waiting for the block is abandoned
+    /// earlier if the deadline of `ctx` is exceeded.
+    ///
+    /// The want is kept in the journal while the request waits, so a restarted node asks for
+    /// the block again.
+    pub fn get_block_with_options(&self, cid: &Cid, ctx: Context, options: GetBlockOptions) ->
     impl Future<Output=Result<Block, Error>>
     {
         let cid = cid.to_owned();
         let events = self.events.clone();
         let block_store = self.block_store.clone();
+        let exchange = self.exchange.clone();
+        let journal = self.journal.clone();
         async move {
-            if !await!(block_store.contains(&cid))? {
+            ctx.check()?;
+            if block_store.contains(&cid).await? {
+                metrics::BLOCK_GETS.with_label_values(&["local"]).inc();
+                return BlockFuture::new(block_store, cid, ctx).await;
+            }
+            if !ctx.wants_network() {
+                return Err(ContextError::NotAvailableOffline(cid).into());
+            }
+
+            journal.want(&cid, &options.providers_hint).await?;
+            let attempts = options.retries + 1;
+            let mut result = Err(RepoError::BlockNotFound {
+                cid: cid.clone(),
+                attempts,
+                providers: options.providers_hint.len(),
+            }.into());
+            for _ in 0..attempts {
+                let wanted = exchange.want(cid.clone());
                 // sending only fails if no one is listening anymore
                 // and that is okay with us.
-                let _ = events.send(RepoEvent::WantBlock(cid.clone()));
+                let _ = events.send(RepoEvent::WantBlock(cid.clone(), options.providers_hint.clone()));
+
+                let mut deadline = Instant::now() + options.timeout;
+                if let Some(ctx_deadline) = ctx.get_deadline() {
+                    deadline = deadline.min(ctx_deadline);
+                }
+                let attempt = ctx.deadline(deadline);
+                match BlockFuture::new(block_store.clone(), cid.clone(), attempt).or_wanted(wanted).await {
+                    Ok(block) => {
+                        result = Ok(block);
+                        break;
+                    }
+                    // the attempt ran out of time, but the request did not.
+                    Err(ref err) if is_deadline_exceeded(err) && !ctx.is_expired() => {}
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
             }
-            await!(BlockFuture::new(block_store, cid))
+            journal.release_want(&cid).await?;
+            let source = if result.is_ok() { "network" } else { "failed" };
+            metrics::BLOCK_GETS.with_label_values(&[source]).inc();
+            result
         }
     }
 
     /// Remove block from the block store.
+    ///
+    /// Fails if the block is pinned. The pinned blocks are only walked again once a pin or a
+    /// block was added, so removing many blocks in a row does not walk the pins for each.
     pub fn remove_block(&self, cid: &Cid)
         -> impl Future<Output=Result<(), Error>>
     {
-        // sending only fails if no one is listening anymore
-        // and that is okay with us.
-        let _ = self.events.send(RepoEvent::UnprovideBlock(cid.to_owned()));
-        self.block_store.remove(cid)
+        let cid = cid.to_owned();
+        let events = self.events.clone();
+        let block_store = self.block_store.clone();
+        let journal = self.journal.clone();
+        let is_pinned = self.pins.is_pinned(&cid);
+        let gc_lock = self.gc_lock.clone();
+        async move {
+            let _gc = gc_lock.write().await;
+            if is_pinned.await? {
+                bail!("block {} is pinned", cid_profile::display(&cid));
+            }
+            journal.settle_provide(&cid).await?;
+            // sending only fails if no one is listening anymore
+            // and that is okay with us.
+            let _ = events.send(RepoEvent::UnprovideBlock(cid.clone()));
+            block_store.remove(&cid).await
+        }
+    }
+
+    /// Removes every block that is not pinned.
+    ///
+    /// The collection holds the write side of the gc lock, so no block or pin is added between
+    /// reading the pins and removing the blocks. Blocks put but not pinned are removed, files
+    /// added are pinned along with their blocks unless asked not to, and the blocks fetched by
+    /// `pin_add` are kept by its progress.
+    pub fn gc(&self) -> impl Future<Output=Result<GcReport, Error>> {
+        let events = self.events.clone();
+        let block_store = self.block_store.clone();
+        let journal = self.journal.clone();
+        let pinned = self.pins.pinned();
+        let watchers = self.watchers.clone();
+        let gc_lock = self.gc_lock.clone();
+        async move {
+            let _gc = gc_lock.write().await;
+            let pinned = pinned.await?;
+            let mut report = GcReport::default();
+            for cid in block_store.list().await? {
+                if pinned.contains(&cid) {
+                    continue;
+                }
+                let size = match block_store.get(&cid).await? {
+                    Some(block) => block.size(),
+                    None => continue,
+                };
+                block_store.remove(&cid).await?;
+                journal.settle_provide(&cid).await?;
+                // sending only fails if no one is listening anymore
+                // and that is okay with us.
+                let _ = events.send(RepoEvent::UnprovideBlock(cid.clone()));
+                report.bytes += size as u64;
+                report.freed.push(cid);
+            }
+            watchers.notify(RepoChange::GcFinished { freed: report.freed.len(), bytes: report.bytes });
+            Ok(report)
+        }
+    }
+
+    /// Fetches the dag below `root` and pins it recursively, calling `on_progress` after each
+    /// block.
+    ///
+    /// Blocks are fetched one at a time, so a huge dag is never wanted all at once. The links not
+    /// followed yet are saved every `PIN_PROGRESS_INTERVAL` blocks; an add that was interrupted,
+    /// by an error, an expired `ctx` or a restart, resumes from them when called again. Blocks
+    /// linked more than once in the dag may be walked again after resuming, but are not fetched
+    /// again. Returns the final progress.
+    pub fn pin_add<F: FnMut(&PinProgress)>(&self, root: &Cid, ctx: Context, mut on_progress: F) ->
+    impl Future<Output=Result<PinProgress, Error>>
+    {
+        let root = root.to_owned();
+        let repo = self.clone();
+        async move {
+            let pins = &repo.pins;
+            let (mut fetched, mut frontier) = match pins.resume_point(&root).await? {
+                Some(resume_point) => resume_point,
+                None => {
+                    pins.save_progress(&root, 0, &[root.clone()]).await?;
+                    (0, vec![root.clone()])
+                }
+            };
+
+            let mut walked = HashSet::new();
+            let mut unsaved = 0;
+            while let Some(cid) = frontier.pop() {
+                if !walked.insert(cid.clone()) {
+                    continue;
+                }
+                let block = match repo.get_block(&cid, ctx).await {
+                    Ok(block) => block,
+                    Err(err) => {
+                        frontier.push(cid);
+                        pins.save_progress(&root, fetched, &frontier).await?;
+                        return Err(err);
+                    }
+                };
+                // blocks handed over by the exchange are not stored by it.
+                if !repo.block_store.contains(&cid).await? {
+                    repo.put_block(block.clone()).await?;
+                }
+                frontier.extend(block_links(&block)?);
+                fetched += 1;
+                on_progress(&PinProgress::new(fetched, &frontier));
+
+                unsaved += 1;
+                if unsaved == PIN_PROGRESS_INTERVAL {
+                    pins.save_progress(&root, fetched, &frontier).await?;
+                    unsaved = 0;
+                }
+            }
+
+            pins.complete(&root).await?;
+            repo.watchers.notify(RepoChange::Pinned(root));
+            Ok(PinProgress::new(fetched, &[]))
+        }
+    }
+
+    /// Calls `f` with the cid and size of every stored block, in no particular order.
+    ///
+    /// Blocks removed while the scan runs may be skipped.
+    pub fn scan<F: FnMut(&Cid, usize)>(&self, mut f: F) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let block_store = self.block_store.clone();
+        async move {
+            for cid in block_store.list().await? {
+                if let Some(block) = block_store.get(&cid).await? {
+                    f(&cid, block.size());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Counts the stored blocks and their total size.
+    ///
+    /// Unlike `stats`, this does not read every block unless the block store has to.
+    pub fn stat(&self) -> impl Future<Output=Result<BlockCount, Error>> {
+        self.block_store.stat()
+    }
+
+    /// Counts the stored blocks by codec and by multihash type, see `scan`.
+    pub fn stats(&self) -> impl Future<Output=Result<RepoStats, Error>> {
+        let repo = self.clone();
+        async move {
+            let mut stats = RepoStats::default();
+            repo.scan(|cid, size| stats.add(cid, size)).await?;
+            Ok(stats)
+        }
+    }
+}
+
+/// Writes `block` unless it is stored already.
+async fn put_new_block<T: BlockStore>(block_store: &T, block: Block) -> Result<Cid, Error> {
+    if block_store.contains(block.cid()).await? {
+        metrics::BLOCK_PUTS_DUPLICATE.inc();
+        return Ok(block.cid().to_owned());
+    }
+    let cid = block_store.put(block).await?;
+    metrics::BLOCK_PUTS.inc();
+    Ok(cid)
+}
+
+/// Hash and validation stages of `Repo::put_blocks`.
+fn prepare_block(block: Block) -> Result<(Block, Duration, Duration), Error> {
+    let start = Instant::now();
+    let cid = cid::Cid::new_from_prefix(&block.cid().prefix(), block.data());
+    if &cid != block.cid() {
+        bail!("block data does not match cid {}", cid_profile::display(block.cid()));
+    }
+    let hash = start.elapsed();
+
+    let start = Instant::now();
+    match block.cid().prefix().codec {
+        cid::Codec::DagCBOR => { formats::cbor::decode(block.data())?; }
+        cid::Codec::DagJSON => { formats::json::decode(block.data())?; }
+        _ => {}
+    }
+    let validate = start.elapsed();
+
+    Ok((block, hash, validate))
+}
+
+/// Name of `codec` in the multicodec table.
+fn codec_name(codec: cid::Codec) -> String {
+    let name = match codec {
+        cid::Codec::Raw => "raw",
+        cid::Codec::DagProtobuf => "dag-pb",
+        cid::Codec::DagCBOR => "dag-cbor",
+        cid::Codec::DagJSON => "dag-json",
+        cid::Codec::EthereumBlock => "eth-block",
+        cid::Codec::EthereumBlockList => "eth-block-list",
+        cid::Codec::EthereumTx => "eth-tx",
+        cid::Codec::EthereumStateTrie => "eth-state-trie",
+        codec => return format!("{:?}", codec),
+    };
+    name.to_string()
+}
+
+/// Name of `hash` in the multihash table.
+fn hash_name(hash: multihash::Hash) -> String {
+    let name = match hash {
+        multihash::Hash::SHA1 => "sha1",
+        multihash::Hash::SHA2256 => "sha2-256",
+        multihash::Hash::SHA2512 => "sha2-512",
+        multihash::Hash::SHA3256 => "sha3-256",
+        multihash::Hash::SHA3512 => "sha3-512",
+        multihash::Hash::Keccak256 => "keccak-256",
+        hash => return format!("{:?}", hash),
+    };
+    name.to_string()
+}
+
+fn is_deadline_exceeded(err: &Error) -> bool {
+    match err.downcast_ref::<ContextError>() {
+        Some(ContextError::DeadlineExceeded) => true,
+        _ => false,
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use std::thread;
     use std::env::temp_dir;
 
     #[derive(Clone)]
@@ -150,6 +745,7 @@ pub(crate) mod tests {
 
     impl RepoTypes for Types {
         type TBlockStore = mem::MemBlockStore;
+        type TDataStore = mem::MemDataStore;
     }
 
     pub fn create_mock_repo() -> Repo<Types> {
@@ -163,17 +759,220 @@ pub(crate) mod tests {
         r
     }
 
-    #[test]
-    fn test_repo() {
+    #[tokio::test]
+    async fn test_put_blocks() {
+        let repo = create_mock_repo();
+        let blocks: Vec<Block> = (0..16).map(|i| Block::from(format!("block {}", i).as_str())).collect();
+        let expected: Vec<Cid> = blocks.iter().map(|block| block.cid().to_owned()).collect();
+
+        let (cids, _) = repo.put_blocks(blocks).await.unwrap();
+        assert_eq!(cids, expected);
+        for cid in &cids {
+            assert!(repo.get_block(cid, Context::default()).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_put_duplicate_blocks() {
+        let repo = create_mock_repo();
+        let block = Block::from("block");
+        let size = block.size() as u64;
+
+        repo.put_block(block.clone()).await.unwrap();
+        assert_eq!(repo.stat().await.unwrap(), BlockCount { blocks: 1, bytes: size });
+        repo.put_block(block.clone()).await.unwrap();
+        let (cids, _) = repo.put_blocks(vec![block.clone(), block.clone()]).await.unwrap();
+        assert_eq!(cids, vec![block.cid().to_owned(); 2]);
+        assert_eq!(repo.stat().await.unwrap(), BlockCount { blocks: 1, bytes: size });
+    }
+
+    #[tokio::test]
+    async fn test_put_blocks_cid_mismatch() {
+        let repo = create_mock_repo();
+        let cid = Block::from("a").cid().to_owned();
+        let block = Block::new(b"b".to_vec(), cid);
+
+        assert!(repo.put_blocks(vec![block]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_block_context() {
+        let repo = create_mock_repo();
+        let cid = Block::from("missing block").cid().to_owned();
+
+        let offline = Context::default().want_network(false);
+        assert!(repo.get_block(&cid, offline).await.is_err());
+        let expired = Context::with_timeout(Duration::from_millis(10));
+        assert!(repo.get_block(&cid, expired).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_block_retries() {
         let mut tmp = temp_dir();
         tmp.push("ipfstools-repo");
         let options: RepoOptions<Types> = RepoOptions {
             _marker: PhantomData,
             path: tmp,
         };
-        let (repo, _) = Repo::new(options);
-        tokio::run_async(async move {
-            await!(repo.init()).unwrap();
+        let (repo, events) = Repo::new(options);
+        let cid = Block::from("missing block").cid().to_owned();
+        let provider = PeerId::random();
+        let options = GetBlockOptions {
+            timeout: Duration::from_millis(10),
+            retries: 2,
+            providers_hint: vec![provider.clone()],
+        };
+
+        let get = repo.get_block_with_options(&cid, Context::default(), options);
+        let err = get.await.unwrap_err();
+        match err.downcast_ref::<RepoError>() {
+            Some(RepoError::BlockNotFound { attempts, providers, .. }) => {
+                assert_eq!(*attempts, 3);
+                assert_eq!(*providers, 1);
+            }
+            _ => panic!("expected block not found, got {}", err),
+        }
+
+        let wants: Vec<_> = events.try_iter().collect();
+        assert_eq!(wants.len(), 3);
+        for want in wants {
+            match want {
+                RepoEvent::WantBlock(want, providers) => {
+                    assert_eq!(want, cid);
+                    assert_eq!(providers, vec![provider.clone()]);
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_journal() {
+        let mut tmp = temp_dir();
+        tmp.push("ipfstools-repo");
+        let options: RepoOptions<Types> = RepoOptions {
+            _marker: PhantomData,
+            path: tmp,
+        };
+        let (repo, events) = Repo::new(options);
+        let block = Block::from("journaled block");
+        let cid = block.cid().to_owned();
+
+        repo.journal().want(&cid, &[]).await.unwrap();
+        assert_eq!(repo.replay_journal().await.unwrap(), 1);
+        match events.try_recv() {
+            Ok(RepoEvent::WantBlock(want, _)) => assert_eq!(want, cid),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        // storing the block settles the want and journals the provide.
+        repo.put_block(block).await.unwrap();
+        let _ = events.try_iter().count();
+        assert_eq!(repo.replay_journal().await.unwrap(), 1);
+        match events.try_recv() {
+            Ok(RepoEvent::ProvideBlock(provide)) => assert_eq!(provide, cid),
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        repo.journal().settle_provide(&cid).await.unwrap();
+        assert_eq!(repo.replay_journal().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_block_from_exchange() {
+        let repo = create_mock_repo();
+        let block = Block::from("exchanged block");
+        let cid = block.cid().to_owned();
+        let exchange = repo.exchange().clone();
+
+        let expected = block.clone();
+        thread::spawn(move || {
+            // wait for the request to put the block on the wantlist.
+            while !exchange.inject_block(block.clone()) {
+                thread::sleep(Duration::from_millis(1));
+            }
         });
+
+        let received = repo.get_block(&cid, Context::default()).await.unwrap();
+        assert_eq!(received, expected);
+        assert!(repo.exchange().wantlist().is_empty());
+        // injected blocks are not stored.
+        assert!(!repo.block_store.contains(&cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_gc() {
+        let repo = create_mock_repo();
+        let pinned = Block::from("pinned");
+        let unpinned = Block::from("unpinned");
+
+        let pinned = repo.put_block(pinned).await.unwrap();
+        let unpinned_size = unpinned.size() as u64;
+        let unpinned = repo.put_block(unpinned).await.unwrap();
+        repo.pins().pin_direct(&pinned).await.unwrap();
+
+        let report = repo.gc().await.unwrap();
+        assert_eq!(report.freed, vec![unpinned.clone()]);
+        assert_eq!(report.bytes, unpinned_size);
+        assert!(repo.block_store.contains(&pinned).await.unwrap());
+        assert!(!repo.block_store.contains(&unpinned).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let repo = create_mock_repo();
+        let changes = repo.subscribe();
+        let pinned = Block::from("pinned");
+        let unpinned = Block::from("unpinned");
+        let unpinned_size = unpinned.size() as u64;
+
+        let pinned = repo.put_block(pinned).await.unwrap();
+        let (unpinned, _) = repo.put_blocks(vec![unpinned]).await.unwrap();
+        repo.pin_add(&pinned, Context::default(), |_| {}).await.unwrap();
+        repo.gc().await.unwrap();
+
+        let changes: Vec<_> = changes.try_iter().collect();
+        assert_eq!(changes, vec![
+            RepoChange::BlockImported(pinned.clone()),
+            RepoChange::BlockImported(unpinned[0].clone()),
+            RepoChange::Pinned(pinned),
+            RepoChange::GcFinished { freed: 1, bytes: unpinned_size },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let repo = create_mock_repo();
+        let pb = Block::from("dag-pb block");
+        let prefix = cid::Prefix {
+            version: cid::Version::V1,
+            codec: cid::Codec::Raw,
+            mh_type: multihash::Hash::SHA2256,
+            mh_len: 32,
+        };
+        let data = b"raw block".to_vec();
+        let raw = Block::new(data.clone(), cid::Cid::new_from_prefix(&prefix, &data));
+        let (pb_size, raw_size) = (pb.size() as u64, raw.size() as u64);
+
+        repo.put_block(pb).await.unwrap();
+        repo.put_block(raw).await.unwrap();
+
+        let stats = repo.stats().await.unwrap();
+        assert_eq!(stats.total, BlockCount { blocks: 2, bytes: pb_size + raw_size });
+        assert_eq!(stats.codecs["dag-pb"], BlockCount { blocks: 1, bytes: pb_size });
+        assert_eq!(stats.codecs["raw"], BlockCount { blocks: 1, bytes: raw_size });
+        assert_eq!(stats.hashes["sha2-256"], stats.total);
+    }
+
+    #[tokio::test]
+    async fn test_repo() {
+        let mut tmp = temp_dir();
+        tmp.push("ipfstools-repo");
+        let options: RepoOptions<Types> = RepoOptions {
+            _marker: PhantomData,
+            path: tmp,
+        };
+        let (repo, _) = Repo::new(options);
+        repo.init().await.unwrap();
     }
 }
@@ -0,0 +1,463 @@
+//! Pins keep blocks from being removed from the repo.
+//!
+//! A direct pin protects a single block, a recursive pin also protects every block below it.
+//! Blocks below a recursive pin are pinned indirectly: they are not recorded, but found by walking
+//! the dags of the recursive pins through the blocks present in the repo.
+//!
+//! Adding a recursive pin with `Repo::pin_add` fetches the whole dag first. Its progress, the
+//! links not followed yet and the number of blocks fetched, is kept in `Column::PinProgress` so
+//! an interrupted add resumes where it stopped. The fetched blocks of a pin in progress are
+//! pinned indirectly, so they are not collected before the add completes.
+//!
+//! Walking the dags is costly, so the set of pinned blocks is kept until a pin or a block is
+//! added. Pins are added under the read side of the gc lock of the repo, see `Repo::gc`.
+use crate::block::Cid;
+use crate::error::Error;
+use crate::ipld::links::block_links;
+use crate::repo::{BlockStore, Column, DataStore, RepoTypes};
+use core::future::Future;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+
+/// How a block is pinned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinMode {
+    Direct,
+    Recursive,
+    /// The block is below a recursive pin.
+    Indirect,
+}
+
+impl PinMode {
+    fn to_byte(self) -> u8 {
+        match self {
+            PinMode::Direct => 0,
+            PinMode::Recursive => 1,
+            PinMode::Indirect => unreachable!("indirect pins are not stored"),
+        }
+    }
+
+    fn decode(value: &[u8]) -> Result<Self, Error> {
+        match value.first() {
+            Some(0) => Ok(PinMode::Direct),
+            Some(1) => Ok(PinMode::Recursive),
+            Some(byte) => bail!("invalid pin mode {}", byte),
+            None => bail!("empty pin mode"),
+        }
+    }
+}
+
+/// Progress of adding a recursive pin, see `Repo::pin_add`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PinProgress {
+    /// Blocks of the dag fetched or found in the repo.
+    pub fetched: u64,
+    /// The fetched blocks and the links not followed yet. Grows while the dag is walked, links
+    /// below the unfetched blocks are not known yet.
+    pub estimated_total: u64,
+}
+
+impl PinProgress {
+    pub(crate) fn new(fetched: u64, frontier: &[Cid]) -> Self {
+        PinProgress {
+            fetched,
+            estimated_total: fetched + frontier.len() as u64,
+        }
+    }
+}
+
+/// The set of pinned blocks last walked by `PinStore::pinned`.
+#[derive(Debug, Default)]
+struct PinnedCache {
+    /// Bumped on every invalidation, so that a set walked meanwhile is not kept.
+    generation: u64,
+    pinned: Option<Arc<HashSet<Cid>>>,
+}
+
+/// The pins of a repo, stored in `Column::Pin`.
+#[derive(Clone, Debug)]
+pub struct PinStore<TRepoTypes: RepoTypes> {
+    data_store: TRepoTypes::TDataStore,
+    block_store: TRepoTypes::TBlockStore,
+    gc_lock: Arc<RwLock<()>>,
+    cache: Arc<Mutex<PinnedCache>>,
+}
+
+impl<TRepoTypes: RepoTypes> PinStore<TRepoTypes> {
+    pub fn new(
+        data_store: TRepoTypes::TDataStore,
+        block_store: TRepoTypes::TBlockStore,
+        gc_lock: Arc<RwLock<()>>,
+    ) -> Self {
+        PinStore {
+            data_store,
+            block_store,
+            gc_lock,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Drops the set of pinned blocks, to be called once a pin or a block was added.
+    pub(crate) fn invalidate(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.generation += 1;
+        cache.pinned = None;
+    }
+
+    /// Pins the block `cid` alone. Fails if it is pinned recursively already.
+    pub fn pin_direct(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let cid = cid.to_owned();
+        let pins = self.clone();
+        async move {
+            let _gc = pins.gc_lock.read().await;
+            let key = cid.to_bytes();
+            if let Some(mode) = pins.data_store.get(Column::Pin, &key).await? {
+                if PinMode::decode(&mode)? == PinMode::Recursive {
+                    bail!("{} is pinned recursively already", cid);
+                }
+            }
+            pins.data_store.put(Column::Pin, &key, &[PinMode::Direct.to_byte()]).await?;
+            pins.invalidate();
+            Ok(())
+        }
+    }
+
+    /// Pins the block `cid` and every block below it, replacing a direct pin of `cid`.
+    pub fn pin_recursive(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let key = cid.to_bytes();
+        let pins = self.clone();
+        async move {
+            let _gc = pins.gc_lock.read().await;
+            pins.write_recursive(&key).await
+        }
+    }
+
+    /// Pins `cid` recursively like `pin_recursive`, for callers holding the gc lock already.
+    pub(crate) fn pin_recursive_locked(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let key = cid.to_bytes();
+        let pins = self.clone();
+        async move {
+            pins.write_recursive(&key).await
+        }
+    }
+
+    async fn write_recursive(&self, key: &[u8]) -> Result<(), Error> {
+        self.data_store.put(Column::Pin, key, &[PinMode::Recursive.to_byte()]).await?;
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Removes the direct or recursive pin of `cid`.
+    ///
+    /// Fails if `cid` is not pinned, or only indirectly.
+    pub fn unpin(&self, cid: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let cid = cid.to_owned();
+        let pins = self.clone();
+        async move {
+            let key = cid.to_bytes();
+            if !pins.data_store.contains(Column::Pin, &key).await? {
+                bail!("{} is not pinned directly or recursively", cid);
+            }
+            pins.data_store.remove(Column::Pin, &key).await?;
+            pins.invalidate();
+            Ok(())
+        }
+    }
+
+    /// Returns how `cid` is pinned, if it is.
+    ///
+    /// Finding indirect pins walks the dags of all recursive pins, see `pinned`.
+    pub fn pin_mode(&self, cid: &Cid) -> impl Future<Output=Result<Option<PinMode>, Error>> {
+        let cid = cid.to_owned();
+        let data_store = self.data_store.clone();
+        let pinned = self.pinned();
+        async move {
+            if let Some(mode) = data_store.get(Column::Pin, &cid.to_bytes()).await? {
+                return Ok(Some(PinMode::decode(&mode)?));
+            }
+            if pinned.await?.contains(&cid) {
+                return Ok(Some(PinMode::Indirect));
+            }
+            Ok(None)
+        }
+    }
+
+    /// Returns every pinned cid, whether pinned directly, recursively or indirectly.
+    ///
+    /// Only blocks present in the repo are walked, links out of missing blocks are not followed.
+    /// The set is walked again only after a pin or a block was added, see `invalidate`.
+    pub fn pinned(&self) -> impl Future<Output=Result<Arc<HashSet<Cid>>, Error>> {
+        let data_store = self.data_store.clone();
+        let block_store = self.block_store.clone();
+        let cache = self.cache.clone();
+        async move {
+            let generation = {
+                let cache = cache.lock().unwrap();
+                if let Some(ref pinned) = cache.pinned {
+                    return Ok(pinned.clone());
+                }
+                cache.generation
+            };
+
+            let mut pinned = HashSet::new();
+            let mut queue = Vec::new();
+            for key in data_store.keys(Column::PinProgress).await? {
+                queue.push(Cid::from(&key[..])?);
+            }
+            for key in data_store.keys(Column::Pin).await? {
+                let cid = Cid::from(&key[..])?;
+                let mode = data_store.get(Column::Pin, &key).await?;
+                match mode {
+                    Some(ref mode) if PinMode::decode(mode)? == PinMode::Recursive => {
+                        queue.push(cid);
+                    }
+                    Some(_) => {
+                        pinned.insert(cid);
+                    }
+                    // removed since listing the keys.
+                    None => {}
+                }
+            }
+
+            let mut walked = HashSet::new();
+            while let Some(parent) = queue.pop() {
+                pinned.insert(parent.clone());
+                if !walked.insert(parent.clone()) {
+                    continue;
+                }
+                let block = match block_store.get(&parent).await? {
+                    Some(block) => block,
+                    None => continue,
+                };
+                queue.extend(block_links(&block)?);
+            }
+
+            let pinned = Arc::new(pinned);
+            let mut cache = cache.lock().unwrap();
+            if cache.generation == generation {
+                cache.pinned = Some(pinned.clone());
+            }
+            Ok(pinned)
+        }
+    }
+
+    /// Returns `true` if `cid` is pinned in any way.
+    pub fn is_pinned(&self, cid: &Cid) -> impl Future<Output=Result<bool, Error>> {
+        let pin_mode = self.pin_mode(cid);
+        async move {
+            Ok(pin_mode.await?.is_some())
+        }
+    }
+
+    /// Returns the progress of adding the recursive pin `root`, if it is being added.
+    pub fn progress(&self, root: &Cid) -> impl Future<Output=Result<Option<PinProgress>, Error>> {
+        let resume_point = self.resume_point(root);
+        async move {
+            Ok(resume_point.await?.map(|(fetched, frontier)| PinProgress::new(fetched, &frontier)))
+        }
+    }
+
+    /// Returns the roots of the recursive pins being added, in no particular order.
+    pub fn in_progress(&self) -> impl Future<Output=Result<Vec<Cid>, Error>> {
+        let keys = self.data_store.keys(Column::PinProgress);
+        async move {
+            let mut roots = Vec::new();
+            for key in keys.await? {
+                roots.push(Cid::from(&key[..])?);
+            }
+            Ok(roots)
+        }
+    }
+
+    /// Returns the number of blocks fetched and the links not followed yet of adding `root`.
+    pub(crate) fn resume_point(&self, root: &Cid) ->
+    impl Future<Output=Result<Option<(u64, Vec<Cid>)>, Error>>
+    {
+        let get = self.data_store.get(Column::PinProgress, &root.to_bytes());
+        async move {
+            match get.await? {
+                Some(value) => Ok(Some(decode_progress(&value)?)),
+                None => Ok(None),
+            }
+        }
+    }
+
+    /// Records the progress of adding `root`, replacing the previous one.
+    pub(crate) fn save_progress(&self, root: &Cid, fetched: u64, frontier: &[Cid]) ->
+    impl Future<Output=Result<(), Error>>
+    {
+        let mut value = fetched.to_be_bytes().to_vec();
+        for cid in frontier {
+            let bytes = cid.to_bytes();
+            value.extend((bytes.len() as u16).to_be_bytes().iter());
+            value.extend(bytes);
+        }
+        let key = root.to_bytes();
+        let pins = self.clone();
+        async move {
+            let _gc = pins.gc_lock.read().await;
+            pins.data_store.put(Column::PinProgress, &key, &value).await?;
+            pins.invalidate();
+            Ok(())
+        }
+    }
+
+    /// Pins `root` recursively once all of its dag was fetched, dropping the progress.
+    pub(crate) fn complete(&self, root: &Cid) -> impl Future<Output=Result<(), Error>> {
+        let key = root.to_bytes();
+        let pins = self.clone();
+        async move {
+            let _gc = pins.gc_lock.read().await;
+            pins.data_store.put(Column::Pin, &key, &[PinMode::Recursive.to_byte()]).await?;
+            pins.data_store.remove(Column::PinProgress, &key).await?;
+            pins.invalidate();
+            Ok(())
+        }
+    }
+}
+
+fn decode_progress(bytes: &[u8]) -> Result<(u64, Vec<Cid>), Error> {
+    if bytes.len() < 8 {
+        bail!("truncated pin progress");
+    }
+    let mut fetched = [0u8; 8];
+    fetched.copy_from_slice(&bytes[..8]);
+    let fetched = u64::from_be_bytes(fetched);
+    let mut frontier = Vec::new();
+    let mut bytes = &bytes[8..];
+    while !bytes.is_empty() {
+        if bytes.len() < 2 {
+            bail!("truncated cid in pin progress");
+        }
+        let len = u16::from_be_bytes([bytes[0], bytes[1]]) as usize + 2;
+        if len > bytes.len() {
+            bail!("truncated cid in pin progress");
+        }
+        frontier.push(Cid::from(&bytes[2..len])?);
+        bytes = &bytes[len..];
+    }
+    Ok((fetched, frontier))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::context::Context;
+    use crate::ipld::{Ipld, IpldDag};
+    use crate::repo::tests::create_mock_repo;
+    use cid::Codec;
+
+    #[tokio::test]
+    async fn test_pin_modes() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo.clone());
+        let leaf = dag.put(vec![1].into(), Codec::DagCBOR).await.unwrap();
+        let root = dag.put(vec![leaf.root().to_owned()].into(), Codec::DagCBOR).await.unwrap();
+        let leaf = leaf.root().cid().unwrap().to_owned();
+        let root = root.root().cid().unwrap().to_owned();
+        let pins = repo.pins();
+
+        assert_eq!(pins.pin_mode(&leaf).await.unwrap(), None);
+        pins.pin_direct(&leaf).await.unwrap();
+        assert_eq!(pins.pin_mode(&leaf).await.unwrap(), Some(PinMode::Direct));
+        pins.unpin(&leaf).await.unwrap();
+
+        pins.pin_recursive(&root).await.unwrap();
+        assert!(pins.pin_direct(&root).await.is_err());
+        assert_eq!(pins.pin_mode(&root).await.unwrap(), Some(PinMode::Recursive));
+        assert_eq!(pins.pin_mode(&leaf).await.unwrap(), Some(PinMode::Indirect));
+        assert!(pins.unpin(&leaf).await.is_err());
+
+        pins.unpin(&root).await.unwrap();
+        assert!(!pins.is_pinned(&leaf).await.unwrap());
+    }
+
+    #[test]
+    fn test_decode_pin_mode() {
+        assert_eq!(PinMode::decode(&[1]).unwrap(), PinMode::Recursive);
+        assert!(PinMode::decode(&[2]).is_err());
+        assert!(PinMode::decode(&[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_invalidated() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo.clone());
+        let leaf = dag.put(vec![1].into(), Codec::DagCBOR).await.unwrap();
+        let leaf = leaf.root().cid().unwrap().to_owned();
+        let pins = repo.pins();
+
+        let pinned = pins.pinned().await.unwrap();
+        assert!(pinned.is_empty());
+        assert!(Arc::ptr_eq(&pinned, &pins.pinned().await.unwrap()));
+
+        pins.pin_direct(&leaf).await.unwrap();
+        assert!(pins.pinned().await.unwrap().contains(&leaf));
+
+        // a block put below a recursive pin is pinned indirectly.
+        let other = create_mock_repo();
+        let missing = IpldDag::new(other.clone()).put(vec![2].into(), Codec::DagCBOR).await.unwrap();
+        let missing = missing.root().cid().unwrap().to_owned();
+        let root = dag.put(vec![Ipld::from(missing.clone())].into(), Codec::DagCBOR).await.unwrap();
+        pins.pin_recursive(root.root().cid().unwrap()).await.unwrap();
+        assert!(!pins.pinned().await.unwrap().contains(&missing));
+        let block = other.get_block(&missing, Context::default()).await.unwrap();
+        repo.put_block(block).await.unwrap();
+        assert!(pins.pinned().await.unwrap().contains(&missing));
+    }
+
+    #[tokio::test]
+    async fn test_remove_pinned_block() {
+        let repo = create_mock_repo();
+        let block = Block::from("pinned");
+        let cid = repo.put_block(block).await.unwrap();
+
+        repo.pins().pin_direct(&cid).await.unwrap();
+        assert!(repo.remove_block(&cid).await.is_err());
+        assert!(repo.get_block(&cid, Context::default()).await.is_ok());
+
+        repo.pins().unpin(&cid).await.unwrap();
+        repo.remove_block(&cid).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pin_add_resume() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo.clone());
+        // the missing leaf is only stored in another repo.
+        let other = create_mock_repo();
+        let missing = IpldDag::new(other.clone()).put(vec![2].into(), Codec::DagCBOR).await.unwrap();
+        let missing = missing.root().cid().unwrap().to_owned();
+        let leaf = dag.put(vec![1].into(), Codec::DagCBOR).await.unwrap();
+        // links are walked last to first.
+        let links: Vec<Ipld> = vec![missing.clone().into(), leaf.root().to_owned().into()];
+        let root = dag.put(links.into(), Codec::DagCBOR).await.unwrap();
+        let root = root.root().cid().unwrap().to_owned();
+        let pins = repo.pins();
+
+        let offline = Context::default().want_network(false);
+        let mut seen = Vec::new();
+        assert!(repo.pin_add(&root, offline, |progress| seen.push(*progress)).await.is_err());
+        assert_eq!(seen.last(), Some(&PinProgress { fetched: 2, estimated_total: 3 }));
+        assert_eq!(pins.progress(&root).await.unwrap(), Some(PinProgress { fetched: 2, estimated_total: 3 }));
+        assert_eq!(pins.in_progress().await.unwrap(), vec![root.clone()]);
+        assert_eq!(pins.pin_mode(&root).await.unwrap(), Some(PinMode::Indirect));
+
+        // the fetched blocks survive a collection.
+        let report = repo.gc().await.unwrap();
+        assert!(report.freed.is_empty());
+
+        let block = other.get_block(&missing, Context::default()).await.unwrap();
+        repo.put_block(block).await.unwrap();
+        let mut seen = Vec::new();
+        let done = repo.pin_add(&root, offline, |progress| seen.push(*progress)).await.unwrap();
+        // only the missing leaf is fetched after resuming.
+        assert_eq!(seen, vec![PinProgress { fetched: 3, estimated_total: 3 }]);
+        assert_eq!(done, PinProgress { fetched: 3, estimated_total: 3 });
+        assert_eq!(pins.progress(&root).await.unwrap(), None);
+        assert_eq!(pins.pin_mode(&root).await.unwrap(), Some(PinMode::Recursive));
+        assert_eq!(pins.pin_mode(&missing).await.unwrap(), Some(PinMode::Indirect));
+    }
+}
@@ -0,0 +1,62 @@
+//! Tells observers about the blocks and pins added to a repo and the blocks collected from it.
+//!
+//! `RepoEvent`s drive the swarm and are received by the node only. Anything else interested in
+//! the contents of the repo, like the event stream of the API, subscribes here instead.
+use crate::block::Cid;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+/// A change to the contents of a repo.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RepoChange {
+    /// A block was put into the block store.
+    BlockImported(Cid),
+    /// The dag below the root was fetched and pinned recursively.
+    Pinned(Cid),
+    /// A garbage collection finished, removing `freed` blocks of `bytes` in total.
+    GcFinished { freed: usize, bytes: u64 },
+}
+
+/// The subscribers to the changes of a repo.
+#[derive(Clone, Debug, Default)]
+pub struct RepoWatchers {
+    subscribers: Arc<Mutex<Vec<Sender<RepoChange>>>>,
+}
+
+impl RepoWatchers {
+    pub fn new() -> Self {
+        RepoWatchers::default()
+    }
+
+    /// Returns a receiver of all changes from now on.
+    pub fn subscribe(&self) -> Receiver<RepoChange> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends `change` to all subscribers, forgetting the ones that hung up.
+    pub fn notify(&self, change: RepoChange) {
+        self.subscribers.lock().unwrap()
+            .retain(|sender| sender.send(change.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+
+    #[test]
+    fn test_notify_subscribers() {
+        let watchers = RepoWatchers::new();
+        let cid = Block::from("imported").cid().to_owned();
+        let first = watchers.subscribe();
+        let second = watchers.subscribe();
+        drop(second);
+
+        watchers.notify(RepoChange::BlockImported(cid.clone()));
+        assert_eq!(first.try_recv(), Ok(RepoChange::BlockImported(cid)));
+        assert_eq!(watchers.subscribers.lock().unwrap().len(), 1);
+    }
+}
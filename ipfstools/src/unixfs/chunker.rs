@@ -0,0 +1,164 @@
+//! Strategies for splitting file contents into the leaves of a unixfs dag.
+
+/// Splits file contents into chunks.
+pub trait Chunker {
+    /// Splits `data` into consecutive non-empty chunks covering all of it.
+    fn chunks<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]>;
+}
+
+/// Chunks of `n` bytes each, except for a shorter last chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedSize(pub usize);
+
+impl Chunker for FixedSize {
+    fn chunks<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        assert!(self.0 > 0, "chunk size must not be zero");
+        data.chunks(self.0).collect()
+    }
+}
+
+/// Irreducible polynomial of degree 53 over GF(2) the fingerprints are taken modulo.
+const POLYNOMIAL: u64 = 0x3DA3358B4DC173;
+/// Number of bytes the rolling fingerprint is taken over.
+const WINDOW_SIZE: usize = 64;
+
+/// Content defined chunks, cut where the Rabin fingerprint of the last `WINDOW_SIZE` bytes has
+/// its low bits cleared.
+///
+/// An insertion or deletion only changes the chunks around it, so a file stored again with a
+/// few changes shares most of its blocks with the earlier version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rabin {
+    min_size: usize,
+    max_size: usize,
+    mask: u64,
+    out_table: Vec<u64>,
+    mod_table: Vec<u64>,
+}
+
+impl Rabin {
+    /// Chunks cut once every `avg_size` bytes on average after the first `min_size` bytes, and
+    /// never longer than `max_size`. `avg_size` is rounded up to a power of two.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        assert!(0 < min_size && min_size <= max_size, "invalid rabin chunk sizes");
+        let shift = degree(POLYNOMIAL);
+        let mut out_table = vec![0; 256];
+        let mut mod_table = vec![0; 256];
+        for byte in 0..256u64 {
+            let mut hash = append_byte(0, byte as u8);
+            for _ in 0..WINDOW_SIZE - 1 {
+                hash = append_byte(hash, 0);
+            }
+            out_table[byte as usize] = hash;
+            mod_table[byte as usize] = modulo(byte << shift) | byte << shift;
+        }
+        Rabin {
+            min_size,
+            max_size,
+            mask: avg_size.next_power_of_two() as u64 - 1,
+            out_table,
+            mod_table,
+        }
+    }
+
+    fn slide(&self, hash: u64, out: u8, input: u8) -> u64 {
+        let hash = hash ^ self.out_table[out as usize];
+        let index = hash >> (degree(POLYNOMIAL) - 8);
+        ((hash << 8) | u64::from(input)) ^ self.mod_table[index as usize]
+    }
+}
+
+impl Default for Rabin {
+    /// Chunks between 64 KiB and 1 MiB long, about 320 KiB on average.
+    fn default() -> Self {
+        Rabin::new(64 * 1024, 256 * 1024, 1024 * 1024)
+    }
+}
+
+impl Chunker for Rabin {
+    fn chunks<'a>(&self, data: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut hash = 0;
+        for (i, byte) in data.iter().enumerate() {
+            let out = if i >= WINDOW_SIZE { data[i - WINDOW_SIZE] } else { 0 };
+            hash = self.slide(hash, out, *byte);
+            let len = i + 1 - start;
+            if (len >= self.min_size && hash & self.mask == 0) || len >= self.max_size {
+                chunks.push(&data[start..=i]);
+                start = i + 1;
+            }
+        }
+        if start < data.len() {
+            chunks.push(&data[start..]);
+        }
+        chunks
+    }
+}
+
+fn degree(polynomial: u64) -> u32 {
+    63 - polynomial.leading_zeros()
+}
+
+/// `value` modulo `POLYNOMIAL`, with both read as polynomials over GF(2).
+fn modulo(mut value: u64) -> u64 {
+    let shift = degree(POLYNOMIAL);
+    while value != 0 && degree(value) >= shift {
+        value ^= POLYNOMIAL << (degree(value) - shift);
+    }
+    value
+}
+
+fn append_byte(hash: u64, byte: u8) -> u64 {
+    modulo((hash << 8) | u64::from(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn random_bytes(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        }).collect()
+    }
+
+    #[test]
+    fn test_fixed_size() {
+        let data = vec![1u8; 10];
+        let sizes: Vec<_> = FixedSize(4).chunks(&data).iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(sizes, vec![4, 4, 2]);
+        assert!(FixedSize(4).chunks(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_rabin_bounds() {
+        let rabin = Rabin::new(1024, 4096, 16384);
+        let data = random_bytes(1 << 20, 1);
+        let chunks = rabin.chunks(&data);
+        assert_eq!(chunks.iter().map(|chunk| chunk.len()).sum::<usize>(), data.len());
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= 1024 && chunk.len() <= 16384);
+        }
+        let average = data.len() / chunks.len();
+        assert!(average > 2048 && average < 8192, "average chunk size {}", average);
+    }
+
+    #[test]
+    fn test_rabin_insertion_keeps_chunks() {
+        let rabin = Rabin::new(1024, 4096, 16384);
+        let data = random_bytes(1 << 20, 2);
+        let mut changed = data[..5000].to_vec();
+        changed.extend_from_slice(b"inserted");
+        changed.extend_from_slice(&data[5000..]);
+
+        let before: HashSet<_> = rabin.chunks(&data).into_iter().collect();
+        let after = rabin.chunks(&changed);
+        let shared = after.iter().filter(|chunk| before.contains(*chunk)).count();
+        assert!(shared + 3 >= after.len(), "{} of {} chunks shared", shared, after.len());
+    }
+}
@@ -0,0 +1,165 @@
+//! The unixfs `Data` protobuf message carried in the data field of dag-pb nodes.
+//!
+//! ```protobuf
+//! message Data {
+//!     required DataType Type = 1;
+//!     optional bytes Data = 2;
+//!     optional uint64 filesize = 3;
+//!     repeated uint64 blocksizes = 4;
+//! }
+//! ```
+use crate::error::Error;
+
+/// `DataType` of a raw leaf node.
+pub const TYPE_RAW: u64 = 0;
+/// `DataType` of a file node.
+pub const TYPE_FILE: u64 = 2;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Data {
+    pub data_type: u64,
+    pub data: Vec<u8>,
+    pub filesize: Option<u64>,
+    pub blocksizes: Vec<u64>,
+}
+
+impl Data {
+    /// A file node holding `data` directly.
+    pub fn file_leaf(data: Vec<u8>) -> Self {
+        Data {
+            data_type: TYPE_FILE,
+            filesize: Some(data.len() as u64),
+            data,
+            blocksizes: Vec::new(),
+        }
+    }
+
+    /// A file node whose contents are split over children of `blocksizes` bytes each.
+    pub fn file_parent(blocksizes: Vec<u64>) -> Self {
+        Data {
+            data_type: TYPE_FILE,
+            data: Vec::new(),
+            filesize: Some(blocksizes.iter().sum()),
+            blocksizes,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() + 16 + self.blocksizes.len() * 4);
+        write_varint(&mut bytes, 1 << 3);
+        write_varint(&mut bytes, self.data_type);
+        if !self.data.is_empty() {
+            write_varint(&mut bytes, 2 << 3 | 2);
+            write_varint(&mut bytes, self.data.len() as u64);
+            bytes.extend_from_slice(&self.data);
+        }
+        if let Some(filesize) = self.filesize {
+            write_varint(&mut bytes, 3 << 3);
+            write_varint(&mut bytes, filesize);
+        }
+        for blocksize in &self.blocksizes {
+            write_varint(&mut bytes, 4 << 3);
+            write_varint(&mut bytes, *blocksize);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, Error> {
+        let mut data = Data::default();
+        let mut has_type = false;
+        while !bytes.is_empty() {
+            let key = read_varint(&mut bytes)?;
+            match (key >> 3, key & 7) {
+                (1, 0) => {
+                    data.data_type = read_varint(&mut bytes)?;
+                    has_type = true;
+                }
+                (2, 2) => data.data = read_len_delimited(&mut bytes)?.to_vec(),
+                (3, 0) => data.filesize = Some(read_varint(&mut bytes)?),
+                (4, 0) => data.blocksizes.push(read_varint(&mut bytes)?),
+                (4, 2) => {
+                    let mut packed = read_len_delimited(&mut bytes)?;
+                    while !packed.is_empty() {
+                        data.blocksizes.push(read_varint(&mut packed)?);
+                    }
+                }
+                (_, 0) => { read_varint(&mut bytes)?; }
+                (_, 1) => { take(&mut bytes, 8)?; }
+                (_, 2) => { read_len_delimited(&mut bytes)?; }
+                (_, 5) => { take(&mut bytes, 4)?; }
+                (field, wire_type) => {
+                    bail!("unixfs field {} has unsupported wire type {}", field, wire_type)
+                }
+            }
+        }
+        if !has_type {
+            bail!("unixfs data is missing its type");
+        }
+        Ok(data)
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Ok(value);
+        }
+    }
+    bail!("invalid varint in unixfs data")
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if bytes.len() < len {
+        bail!("unixfs data is truncated");
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Ok(head)
+}
+
+fn read_len_delimited<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8], Error> {
+    let len = read_varint(bytes)?;
+    if len > bytes.len() as u64 {
+        bail!("unixfs data is truncated");
+    }
+    take(bytes, len as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_file_leaf() {
+        let data = Data::file_leaf(b"Here is some data\n".to_vec());
+        let bytes = data.to_bytes();
+        assert_eq!(bytes, b"\x08\x02\x12\x12Here is some data\n\x18\x12".to_vec());
+        assert_eq!(Data::from_bytes(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn test_encode_file_parent() {
+        let data = Data::file_parent(vec![262144, 10]);
+        let bytes = data.to_bytes();
+        assert_eq!(bytes, vec![0x08, 0x02, 0x18, 0x8a, 0x80, 0x10, 0x20, 0x80, 0x80, 0x10, 0x20, 0x0a]);
+        assert_eq!(Data::from_bytes(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(Data::from_bytes(&[]).is_err());
+        assert!(Data::from_bytes(&[0x08, 0x02, 0x12, 0x05, 0x00]).is_err());
+        assert!(Data::from_bytes(&[0x08, 0xff]).is_err());
+    }
+}
@@ -1,12 +1,26 @@
+use crate::block::{Block, Cid};
+use crate::context::Context;
 use crate::error::Error;
-use crate::ipld::{Ipld, IpldDag, formats::pb::PbNode};
+use crate::ipld::{Ipld, IpldDag, formats::pb::{self, PbLink, PbNode}};
 use crate::path::IpfsPath;
-use crate::repo::RepoTypes;
+use crate::repo::{Repo, RepoTypes};
 use core::future::Future;
-use futures::compat::*;
+use futures::stream::{self, Stream};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::path::PathBuf;
+use std::sync::Arc;
+
+pub mod chunker;
+mod data;
+
+use self::chunker::{Chunker, FixedSize};
+use self::data::Data;
+
+/// Size of the leaf chunks files are split into by default.
+pub const CHUNK_SIZE: usize = 256 * 1024;
+/// Maximum number of links of a file node, as in go-ipfs.
+pub const MAX_LINKS: usize = 174;
 
 pub struct File {
     data: Vec<u8>,
@@ -15,19 +29,23 @@ pub struct File {
 impl File {
     pub fn new(path: PathBuf) -> impl Future<Output=Result<Self, Error>> {
         async move {
-            let file = await!(tokio::fs::File::open(path).compat())?;
-            let (_, data) = await!(tokio::io::read_to_end(file, Vec::new()).compat())?;
+            let data = tokio::fs::read(path).await?;
             Ok(File {
                 data
             })
         }
     }
 
-    pub fn get_unixfs_v1<T: RepoTypes>(dag: &IpldDag<T>, path: IpfsPath) ->
+    /// Returns the contents of the file.
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    pub fn get_unixfs_v1<T: RepoTypes>(dag: &IpldDag<T>, path: IpfsPath, ctx: Context) ->
     impl Future<Output=Result<Self, Error>> {
-        let future = dag.get(path);
+        let future = dag.get(path, ctx);
         async move {
-            let ipld = await!(future)?;
+            let ipld = future.await?;
             let pb_node: PbNode = match ipld.try_into() {
                 Ok(pb_node) => pb_node,
                 Err(_) => bail!("invalid dag_pb node"),
@@ -41,12 +59,20 @@ impl File {
     pub fn put_unixfs_v1<T: RepoTypes>(&self, dag: &IpldDag<T>) ->
     impl Future<Output=Result<IpfsPath, Error>>
     {
+        dag.put(self.unixfs_v1(), cid::Codec::DagProtobuf)
+    }
+
+    /// Encodes the file as a single dag_pb node, as `put_unixfs_v1` stores it.
+    pub fn to_unixfs_v1_block(&self) -> Result<Block, Error> {
+        self.unixfs_v1().to_block(cid::Codec::DagProtobuf)
+    }
+
+    fn unixfs_v1(&self) -> Ipld {
         let links: Vec<Ipld> = vec![];
         let mut pb_node = HashMap::<&str, Ipld>::new();
         pb_node.insert("Data", self.data.clone().into());
         pb_node.insert("Links", links.into());
-        let ipld = pb_node.into();
-        dag.put(ipld, cid::Codec::DagProtobuf)
+        pb_node.into()
     }
 }
 
@@ -72,22 +98,289 @@ impl Into<String> for File {
     }
 }
 
+/// How files are added to the repo.
+#[derive(Clone)]
+pub struct AddOptions {
+    /// Splits the file contents into leaves.
+    pub chunker: Arc<dyn Chunker + Send + Sync>,
+    /// Pins the root recursively, so a gc keeps the file. On by default.
+    pub pin: bool,
+}
+
+impl AddOptions {
+    pub fn with_chunker<C: Chunker + Send + Sync + 'static>(chunker: C) -> Self {
+        AddOptions {
+            chunker: Arc::new(chunker),
+            pin: true,
+        }
+    }
+}
+
+impl Default for AddOptions {
+    fn default() -> Self {
+        AddOptions::with_chunker(FixedSize(CHUNK_SIZE))
+    }
+}
+
+/// Adds the file at `path` to `repo` and returns the cid of its root node.
+pub fn add_file<T: RepoTypes>(repo: Repo<T>, path: PathBuf, options: AddOptions) ->
+impl Future<Output=Result<Cid, Error>>
+{
+    async move {
+        let file = File::new(path).await?;
+        add_bytes(repo, file.data, options).await
+    }
+}
+
+/// Adds `data` to `repo` as a unixfs file and returns the cid of its root node.
+pub fn add_bytes<T: RepoTypes>(repo: Repo<T>, data: Vec<u8>, options: AddOptions) ->
+impl Future<Output=Result<Cid, Error>>
+{
+    async move {
+        let (root, blocks) = build_file(&data, &*options.chunker);
+        if options.pin {
+            repo.put_blocks_pinned(blocks, &root).await?;
+        } else {
+            repo.put_blocks(blocks).await?;
+        }
+        Ok(root)
+    }
+}
+
+/// Splits `data` with `chunker` and builds a balanced dag of unixfs file nodes with at most
+/// `MAX_LINKS` children each. Returns the root and the blocks of the dag, leaves first.
+pub fn build_file(data: &[u8], chunker: &dyn Chunker) -> (Cid, Vec<Block>) {
+    let mut blocks = Vec::new();
+    let mut level: Vec<(Cid, u64, u64)> = if data.is_empty() {
+        vec![file_node(&mut blocks, Data::file_leaf(Vec::new()), Vec::new())]
+    } else {
+        chunker.chunks(data).into_iter()
+            .map(|chunk| file_node(&mut blocks, Data::file_leaf(chunk.to_vec()), Vec::new()))
+            .collect()
+    };
+    while level.len() > 1 {
+        level = level.chunks(MAX_LINKS).map(|children| {
+            let blocksizes = children.iter().map(|(_, filesize, _)| *filesize).collect();
+            let links = children.iter().map(|(cid, _, tsize)| PbLink {
+                cid: cid.clone().into(),
+                name: String::new(),
+                size: *tsize,
+            }).collect();
+            file_node(&mut blocks, Data::file_parent(blocksizes), links)
+        }).collect();
+    }
+    let (root, _, _) = level.pop().expect("there is at least one chunk");
+    (root, blocks)
+}
+
+/// Stores a dag-pb node with unixfs `data` and `links` in `blocks`. Returns its cid, the size
+/// of the file contents below it and the cumulative size of its blocks.
+fn file_node(blocks: &mut Vec<Block>, data: Data, links: Vec<PbLink>) -> (Cid, u64, u64) {
+    let filesize = data.filesize.unwrap_or(0);
+    let tsize = links.iter().map(|link| link.size).sum::<u64>();
+    let bytes = PbNode {
+        links,
+        data: data.to_bytes(),
+    }.into_bytes();
+    let cid = Cid::new_from_prefix(&pb::PREFIX, &bytes);
+    let tsize = tsize + bytes.len() as u64;
+    blocks.push(Block::new(bytes, cid.clone()));
+    (cid, filesize, tsize)
+}
+
+/// Resolves `path` and returns the contents of the unixfs file it ends in. If it ends in any
+/// other node, the data of the block holding that node is returned.
+pub fn read<T: RepoTypes>(repo: Repo<T>, path: IpfsPath, ctx: Context) ->
+impl Future<Output=Result<Vec<u8>, Error>>
+{
+    let resolve = IpldDag::new(repo.clone()).resolve(path, ctx);
+    async move {
+        let (cid, _) = resolve.await?;
+        let block = repo.get_block(&cid, ctx).await?;
+        if !is_file(&block) {
+            return Ok(block.data().to_owned());
+        }
+        let mut contents = Vec::new();
+        for chunk in cat(repo, cid, ctx).collect::<Vec<_>>().await {
+            contents.extend(chunk?);
+        }
+        Ok(contents)
+    }
+}
+
+/// Whether `block` is a raw leaf or a unixfs file node.
+fn is_file(block: &Block) -> bool {
+    match block.cid().prefix().codec {
+        cid::Codec::Raw => true,
+        cid::Codec::DagProtobuf => {
+            PbNode::from_bytes(block.data())
+                .and_then(|node| Data::from_bytes(&node.data))
+                .map(|data| data.data_type == data::TYPE_RAW || data.data_type == data::TYPE_FILE)
+                .unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
+/// Streams the contents of the unixfs file `cid` from `repo`, one chunk at a time.
+///
+/// The stream ends after the first error.
+pub fn cat<T: RepoTypes>(repo: Repo<T>, cid: Cid, ctx: Context) ->
+impl Stream<Item=Result<Vec<u8>, Error>>
+{
+    stream::unfold(Some(vec![cid]), move |stack| {
+        let repo = repo.clone();
+        async move {
+            match next_chunk(repo, stack?, ctx).await {
+                Ok(Some((chunk, stack))) => Some((Ok(chunk), Some(stack))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), None)),
+            }
+        }
+    })
+}
+
+/// Walks the file nodes on `stack` depth-first until a node carrying data is found.
+fn next_chunk<T: RepoTypes>(repo: Repo<T>, mut stack: Vec<Cid>, ctx: Context) ->
+impl Future<Output=Result<Option<(Vec<u8>, Vec<Cid>)>, Error>>
+{
+    async move {
+        while let Some(cid) = stack.pop() {
+            ctx.check()?;
+            let block = repo.get_block(&cid, ctx).await?;
+            let chunk = match cid.prefix().codec {
+                cid::Codec::Raw => block.data().to_vec(),
+                cid::Codec::DagProtobuf => {
+                    let node = PbNode::from_bytes(block.data())?;
+                    let data = Data::from_bytes(&node.data)?;
+                    if data.data_type != data::TYPE_RAW && data.data_type != data::TYPE_FILE {
+                        bail!("{} is not a unixfs file", cid);
+                    }
+                    for link in node.links.into_iter().rev() {
+                        match link.cid.cid() {
+                            Some(cid) => stack.push(cid.clone()),
+                            None => bail!("{} links to a non-ipld path", cid),
+                        }
+                    }
+                    data.data
+                }
+                codec => bail!("{} has codec {:?} which is not a unixfs file", cid, codec),
+            };
+            if !chunk.is_empty() {
+                return Ok(Some((chunk, stack)));
+            }
+        }
+        Ok(None)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::block::Cid;
     use crate::repo::tests::create_mock_repo;
+    use futures::prelude::*;
 
-    #[test]
-    fn test_file_cid() {
+    #[tokio::test]
+    async fn test_file_cid() {
         let repo = create_mock_repo();
         let dag = IpldDag::new(repo);
         let file = File::from("\u{8}\u{2}\u{12}\u{12}Here is some data\n\u{18}\u{12}");
         let cid = Cid::from("QmSy5pnHk1EnvE5dmJSyFKG5unXLGjPpBuJJCBQkBTvBaW").unwrap();
 
-        tokio::run_async(async move {
-            let path = await!(file.put_unixfs_v1(&dag)).unwrap();
-            assert_eq!(cid.to_string(), path.root().cid().unwrap().to_string());
-        });
+        let path = file.put_unixfs_v1(&dag).await.unwrap();
+        assert_eq!(cid.to_string(), path.root().cid().unwrap().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_add_bytes_single_chunk() {
+        let repo = create_mock_repo();
+        let cid = Cid::from("QmSy5pnHk1EnvE5dmJSyFKG5unXLGjPpBuJJCBQkBTvBaW").unwrap();
+
+        let root = add_bytes(repo, b"Here is some data\n".to_vec(), AddOptions::default()).await.unwrap();
+        assert_eq!(root, cid);
+    }
+
+    #[tokio::test]
+    async fn test_add_cat_chunked() {
+        let repo = create_mock_repo();
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+
+        let root = add_bytes(repo.clone(), data.clone(), AddOptions::default()).await.unwrap();
+        let block = repo.get_block(&root, Context::default()).await.unwrap();
+        let node = PbNode::from_bytes(block.data()).unwrap();
+        let sizes: Vec<_> = node.links.iter().map(|link| link.size).collect();
+        assert_eq!(sizes.len(), 3);
+        assert!(sizes[0] > CHUNK_SIZE as u64 && sizes[2] > 1000);
+        assert_eq!(Data::from_bytes(&node.data).unwrap(),
+                   Data::file_parent(vec![CHUNK_SIZE as u64, CHUNK_SIZE as u64, 1000]));
+
+        let chunks: Vec<_> = cat(repo, root, Context::default()).collect().await;
+        assert_eq!(chunks.len(), 3);
+        let contents: Vec<u8> = chunks.into_iter().flat_map(|chunk| chunk.unwrap()).collect();
+        assert_eq!(contents, data);
+    }
+
+    #[tokio::test]
+    async fn test_add_gc_cat() {
+        let repo = create_mock_repo();
+        let data: Vec<u8> = (0..CHUNK_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+
+        let root = add_bytes(repo.clone(), data.clone(), AddOptions::default()).await.unwrap();
+        let report = repo.gc().await.unwrap();
+        assert!(report.freed.is_empty());
+        let chunks: Vec<_> = cat(repo.clone(), root, Context::default()).collect().await;
+        let contents: Vec<u8> = chunks.into_iter().flat_map(|chunk| chunk.unwrap()).collect();
+        assert_eq!(contents, data);
+
+        let options = AddOptions { pin: false, ..AddOptions::default() };
+        let unpinned = add_bytes(repo.clone(), b"not pinned\n".to_vec(), options).await.unwrap();
+        let report = repo.gc().await.unwrap();
+        assert_eq!(report.freed, vec![unpinned]);
+    }
+
+    #[tokio::test]
+    async fn test_read() {
+        let repo = create_mock_repo();
+        let dag = IpldDag::new(repo.clone());
+        let data = vec![7u8; CHUNK_SIZE + 10];
+
+        let file = add_bytes(repo.clone(), data.clone(), AddOptions::default()).await.unwrap();
+        let path = IpfsPath::from(file.clone());
+        assert_eq!(read(repo.clone(), path, Context::default()).await.unwrap(), data);
+
+        let mut map = HashMap::new();
+        map.insert("file".to_string(), Ipld::from(file));
+        let root = dag.put(Ipld::Object(map), cid::Codec::DagCBOR).await.unwrap();
+        let contents = read(repo.clone(), root.sub_path("file").unwrap(), Context::default()).await;
+        assert_eq!(contents.unwrap(), data);
+
+        // a node that is no file is read as its block.
+        let block = repo.get_block(root.root().cid().unwrap(), Context::default()).await.unwrap();
+        assert_eq!(read(repo, root, Context::default()).await.unwrap(), block.data().to_owned());
+    }
+
+    #[test]
+    fn test_build_file_balanced() {
+        let data = vec![0u8; 3 * CHUNK_SIZE];
+        let (root, blocks) = build_file(&data, &FixedSize(CHUNK_SIZE));
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks.last().unwrap().cid(), &root);
+
+        let (empty, blocks) = build_file(&[], &FixedSize(CHUNK_SIZE));
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].cid(), &empty);
+    }
+
+    #[tokio::test]
+    async fn test_add_cat_rabin() {
+        let repo = create_mock_repo();
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let options = AddOptions::with_chunker(chunker::Rabin::new(16 * 1024, 64 * 1024, 256 * 1024));
+
+        let root = add_bytes(repo.clone(), data.clone(), options).await.unwrap();
+        let chunks: Vec<_> = cat(repo, root, Context::default()).collect().await;
+        assert!(chunks.len() > 1);
+        let contents: Vec<u8> = chunks.into_iter().flat_map(|chunk| chunk.unwrap()).collect();
+        assert_eq!(contents, data);
     }
 }
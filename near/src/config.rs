@@ -105,6 +105,23 @@ pub struct Config {
     pub rpc: RpcConfig,
     pub network: Network,
     pub consensus: Consensus,
+    /// Validators to watch in the node logs.
+    #[serde(default)]
+    pub monitored_validators: Vec<AccountId>,
+    /// Blocks below the finalized one that keep their state, every state is kept if unset.
+    #[serde(default)]
+    pub state_retention: Option<BlockIndex>,
+    /// Pruning keeps the states at multiples of this height, no pruned state can be
+    /// reconstructed if unset.
+    #[serde(default)]
+    pub state_snapshot_interval: Option<BlockIndex>,
+    /// Where to write the CBOR replay report of a block rejected for its state root, no report
+    /// is written if unset.
+    #[serde(default)]
+    pub replay_report_dir: Option<PathBuf>,
+    /// Most transactions of one account in a block, no limit if unset.
+    #[serde(default)]
+    pub max_transactions_per_sender: Option<u32>,
 }
 
 impl Default for Config {
@@ -116,6 +133,11 @@ impl Default for Config {
             rpc: RpcConfig::default(),
             network: Network::default(),
             consensus: Consensus::default(),
+            monitored_validators: vec![],
+            state_retention: None,
+            state_snapshot_interval: None,
+            replay_report_dir: None,
+            max_transactions_per_sender: None,
         }
     }
 }
@@ -168,6 +190,7 @@ impl NearConfig {
                 min_block_production_delay: Duration::from_millis(100),
                 max_block_production_delay: Duration::from_millis(2000),
                 block_expected_weight: 1000,
+                max_transactions_per_sender: config.max_transactions_per_sender,
                 skip_sync_wait: config.network.skip_sync_wait,
                 sync_check_period: Duration::from_secs(10),
                 sync_step_period: Duration::from_millis(10),
@@ -177,6 +200,11 @@ impl NearConfig {
                 fetch_info_period: Duration::from_millis(100),
                 log_summary_period: Duration::from_secs(10),
                 produce_empty_blocks: config.consensus.produce_empty_blocks,
+                monitored_validators: config.monitored_validators.clone(),
+                state_retention: config.state_retention,
+                state_snapshot_interval: config.state_snapshot_interval,
+                prune_period: Duration::from_secs(10),
+                replay_report_dir: config.replay_report_dir.clone(),
             },
             network_config: NetworkConfig {
                 public_key: network_key_pair.public_key,
@@ -127,6 +127,10 @@ impl RuntimeAdapter for NightshadeRuntime {
         (store_update, state_root)
     }
 
+    fn get_trie(&self) -> Arc<Trie> {
+        self.trie.clone()
+    }
+
     fn compute_block_weight(
         &self,
         prev_header: &BlockHeader,
@@ -284,6 +288,30 @@ impl RuntimeAdapter for NightshadeRuntime {
         ))
     }
 
+    fn replay_transactions(
+        &self,
+        shard_id: ShardId,
+        state_root: &MerkleHash,
+        block_index: BlockIndex,
+        prev_block_hash: &CryptoHash,
+        receipts: &Vec<Vec<ReceiptTransaction>>,
+        transactions: &Vec<SignedTransaction>,
+    ) -> Result<(WrappedTrieChanges, MerkleHash), Box<dyn std::error::Error>> {
+        let apply_state = ApplyState {
+            root: state_root.clone(),
+            shard_id,
+            block_index,
+            parent_block_hash: *prev_block_hash,
+        };
+        let state_update = TrieUpdate::new(self.trie.clone(), apply_state.root);
+        let apply_result =
+            self.runtime.apply(state_update, &apply_state, &receipts, &transactions)?;
+        Ok((
+            WrappedTrieChanges::new(self.trie.clone(), apply_result.trie_changes),
+            apply_result.root,
+        ))
+    }
+
     fn query(
         &self,
         state_root: MerkleHash,
@@ -1,75 +1,123 @@
 use constants::*;
+#[cfg(feature = "ct")]
+use ct;
+
+/// The length of an AES key, which sets the number of rounds.
+#[derive(Eq,PartialEq,Clone,Copy,Debug)]
+pub enum KeySize {
+    Aes128,
+    Aes192,
+    Aes256,
+}
+
+impl KeySize {
+    /// Length of the key in bytes.
+    pub fn key_len(&self) -> usize {
+        match *self {
+            KeySize::Aes128 => 16,
+            KeySize::Aes192 => 24,
+            KeySize::Aes256 => 32,
+        }
+    }
+
+    fn rounds(&self) -> usize {
+        match *self {
+            KeySize::Aes128 => 10,
+            KeySize::Aes192 => 12,
+            KeySize::Aes256 => 14,
+        }
+    }
+}
 
 #[derive(Copy,Clone)]
 pub struct Key {
-    data: [u8; 16]
+    data: [u8; 32], // only the first size.key_len() bytes are used
+    size: KeySize
 }
 
 impl Key {
+    /// A 128-bit key.
     pub fn new(data: &[u8; 16]) -> Self {
-        Key { data: data.clone() }
+        Key::from_slice(data).unwrap()
     }
 
-    fn expand(&self) -> [Self; 11] {
-        let mut keys: [[u8; 4]; 44] = [[0; 4]; 44]; // table of columns
-
-        load_initial_key(&mut keys, &self.data);
-
-        for i in 1..11 {
-            ksa_core(&mut keys, i, i*4);
-            expand_column(&mut keys, i*4+1);
-            expand_column(&mut keys, i*4+2);
-            expand_column(&mut keys, i*4+3);
-        }
-
-        return columns_to_keys(&keys);
+    /// A 192-bit key.
+    pub fn new_192(data: &[u8; 24]) -> Self {
+        Key::from_slice(data).unwrap()
+    }
 
-        fn load_initial_key(keys: &mut [[u8;4];44], data: &[u8;16]) {
-            let (a, b, c, d) = array_refs![data,4,4,4,4];
-            keys[0] = a.clone();
-            keys[1] = b.clone();
-            keys[2] = c.clone();
-            keys[3] = d.clone();
-        }
+    /// A 256-bit key.
+    pub fn new_256(data: &[u8; 32]) -> Self {
+        Key::from_slice(data).unwrap()
+    }
 
-        fn ksa_core(keys: &mut [[u8;4];44], i: usize, column: usize) {
-            keys[column][0] = SBOX[keys[column-1][1] as usize];
-            keys[column][1] = SBOX[keys[column-1][2] as usize];
-            keys[column][2] = SBOX[keys[column-1][3] as usize];
-            keys[column][3] = SBOX[keys[column-1][0] as usize];
+    /// A key of the size given by the length of `data`, `None` unless it is 16, 24 or 32 bytes.
+    pub fn from_slice(data: &[u8]) -> Option<Self> {
+        let size = match data.len() {
+            16 => KeySize::Aes128,
+            24 => KeySize::Aes192,
+            32 => KeySize::Aes256,
+            _ => return None,
+        };
+        let mut key = Key { data: [0; 32], size };
+        key.data[..data.len()].copy_from_slice(data);
+        Some(key)
+    }
 
-            keys[column][0] ^= RCON[i];
+    pub fn size(&self) -> KeySize {
+        self.size
+    }
 
-            keys[column][0] ^= keys[column-4][0];
-            keys[column][1] ^= keys[column-4][1];
-            keys[column][2] ^= keys[column-4][2];
-            keys[column][3] ^= keys[column-4][3];
-        }
+    /// The round keys, one more than the number of rounds.
+    fn expand(&self) -> Vec<RoundKey> {
+        self.expand_with(|byte| SBOX[byte as usize])
+    }
 
-        fn expand_column(keys: &mut [[u8;4];44], column: usize) {
-            keys[column][0] = keys[column-4][0] ^ keys[column-1][0];
-            keys[column][1] = keys[column-4][1] ^ keys[column-1][1];
-            keys[column][2] = keys[column-4][2] ^ keys[column-1][2];
-            keys[column][3] = keys[column-4][3] ^ keys[column-1][3];
+    /// Like `expand`, with `sub_byte` as the S-box.
+    pub(crate) fn expand_with<F: Fn(u8) -> u8>(&self, sub_byte: F) -> Vec<RoundKey> {
+        let nk = self.size.key_len() / 4; // key length in columns
+        let mut columns: Vec<[u8; 4]> = self.data[..self.size.key_len()].chunks(4)
+            .map(|column| *array_ref![column, 0, 4])
+            .collect();
+
+        for i in nk..4 * (self.size.rounds() + 1) {
+            let mut column = columns[i-1];
+            if i % nk == 0 {
+                column = [ sub_byte(column[1]) ^ RCON[i / nk]
+                         , sub_byte(column[2])
+                         , sub_byte(column[3])
+                         , sub_byte(column[0])
+                         ];
+            } else if nk > 6 && i % nk == 4 {
+                for byte in column.iter_mut() {
+                    *byte = sub_byte(*byte);
+                }
+            }
+            for j in 0..4 {
+                column[j] ^= columns[i-nk][j];
+            }
+            columns.push(column);
         }
 
-        fn columns_to_keys(columns: &[[u8;4];44]) -> [Key; 11] {
-            let mut keys = [Key { data: [0;16] }; 11];
-            for i in 0..11 {
-                keys[i].data[0..4].copy_from_slice(&columns[i*4] as &[u8]);
-                keys[i].data[4..8].copy_from_slice(&columns[i*4 + 1] as &[u8]);
-                keys[i].data[8..12].copy_from_slice(&columns[i*4 + 2] as &[u8]);
-                keys[i].data[12..16].copy_from_slice(&columns[i*4 + 3] as &[u8]);
+        columns.chunks(4).map(|round| {
+            let mut key = RoundKey { data: [0; 16] };
+            for (j, column) in round.iter().enumerate() {
+                key.data[j*4..j*4 + 4].copy_from_slice(column);
             }
-            return keys;
-        }
+            key
+        }).collect()
     }
 }
 
+#[derive(Copy,Clone)]
+pub(crate) struct RoundKey {
+    pub(crate) data: [u8; 16]
+}
+
 
 #[derive(Eq,PartialEq,Clone,Copy,Debug)]
 pub struct Block {
-    data: [u8; 16]
+    pub(crate) data: [u8; 16]
 }
 
 impl Block {
@@ -81,7 +129,7 @@ impl Block {
         &self.data
     }
 
-    fn add_round_key(&mut self, key: &Key) {
+    pub(crate) fn add_round_key(&mut self, key: &RoundKey) {
         for i in 0..16 {
             self.data[i] ^= key.data[i];
         }
@@ -99,7 +147,7 @@ impl Block {
         }
     }
 
-    fn shift_rows(&mut self) {
+    pub(crate) fn shift_rows(&mut self) {
         let mut new_data = self.data.clone();
         for row in 1..4 {
             for col in 0..4 {
@@ -109,7 +157,7 @@ impl Block {
         self.data = new_data;
     }
 
-    fn inv_shift_rows(&mut self) {
+    pub(crate) fn inv_shift_rows(&mut self) {
         let mut new_data = self.data.clone();
         for row in 1..4 {
             for col in 0..4 {
@@ -159,13 +207,36 @@ impl Block {
 }
 
 
+/// Encrypts `block` with `key`.
+///
+/// Without the `ct` feature the S-box and MixColumns steps are table lookups indexed by the key and
+/// the data, which leak both through cache timings.
 pub fn encrypt(key: Key, block: Block) -> Block {
+    #[cfg(feature = "ct")]
+    return ct::encrypt(key, block);
+    #[cfg(not(feature = "ct"))]
+    return encrypt_tables(key, block);
+}
+
+
+/// Decrypts `block` with `key`, see `encrypt`.
+pub fn decrypt(key: Key, block: Block) -> Block {
+    #[cfg(feature = "ct")]
+    return ct::decrypt(key, block);
+    #[cfg(not(feature = "ct"))]
+    return decrypt_tables(key, block);
+}
+
+
+#[cfg_attr(feature = "ct", allow(dead_code))]
+fn encrypt_tables(key: Key, block: Block) -> Block {
     let mut state = block.clone();
-    let keys: [Key; 11] = key.expand();
+    let keys = key.expand();
+    let rounds = keys.len() - 1;
 
     state.add_round_key(&keys[0]);
 
-    for i in 1..10 {
+    for i in 1..rounds {
         state.sub_bytes();
         state.shift_rows();
         state.mix_columns();
@@ -174,22 +245,24 @@ pub fn encrypt(key: Key, block: Block) -> Block {
 
     state.sub_bytes();
     state.shift_rows();
-    state.add_round_key(&keys[10]);
+    state.add_round_key(&keys[rounds]);
 
     return state;
 }
 
 
-pub fn decrypt(key: Key, block: Block) -> Block {
+#[cfg_attr(feature = "ct", allow(dead_code))]
+fn decrypt_tables(key: Key, block: Block) -> Block {
     let mut state = block.clone();
-    let keys: [Key; 11] = key.expand();
+    let keys = key.expand();
+    let rounds = keys.len() - 1;
 
-    state.add_round_key(&keys[10]);
+    state.add_round_key(&keys[rounds]);
 
-    for i in 1..10 {
+    for i in 1..rounds {
         state.inv_shift_rows();
         state.inv_sub_bytes();
-        state.add_round_key(&keys[10-i]);
+        state.add_round_key(&keys[rounds-i]);
         state.inv_mix_columns();
     }
 
@@ -207,7 +280,7 @@ mod tests {
 
     #[test]
     fn encryption_decryption_test() {
-        let key = Key { data: [0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15] };
+        let key = Key::new(&[0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15]);
         let message = Block { data: [0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15] };
 
         let result = decrypt(key, encrypt(key, message));
@@ -217,7 +290,7 @@ mod tests {
 
     #[test]
     fn encryption_test() {
-        let key = Key { data: [0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15] };
+        let key = Key::new(&[0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15]);
         let message = Block { data: [0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15] };
         let expected = Block { data: [0x0a,0x94,0x0b,0xb5,0x41,0x6e,0xf0,0x45
                                      ,0xf1,0xc3,0x94,0x58,0xc6,0x53,0xea,0x5a
@@ -228,13 +301,46 @@ mod tests {
         assert_eq!(expected, encrypted);
     }
 
+    /// FIPS-197 appendix C, the same plaintext under a 128, 192 and 256-bit key.
+    #[test]
+    fn key_sizes_test() {
+        let message = Block { data: [ 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77
+                                    , 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff
+                                    ] };
+        let key_bytes: Vec<u8> = (0..32).collect();
+        let cases = [ (16, [ 0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30
+                           , 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5, 0x5a
+                           ])
+                    , (24, [ 0xdd, 0xa9, 0x7c, 0xa4, 0x86, 0x4c, 0xdf, 0xe0
+                           , 0x6e, 0xaf, 0x70, 0xa0, 0xec, 0x0d, 0x71, 0x91
+                           ])
+                    , (32, [ 0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf
+                           , 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49, 0x60, 0x89
+                           ])
+                    ];
+
+        for &(len, expected) in cases.iter() {
+            let key = Key::from_slice(&key_bytes[..len]).unwrap();
+            assert_eq!(len, key.size().key_len());
+            assert_eq!(key.size().rounds() + 1, key.expand().len());
+
+            let encrypted = encrypt(key, message);
+            assert_eq!(Block::new(&expected), encrypted);
+            assert_eq!(message, decrypt(key, encrypted));
+        }
+
+        assert_eq!(KeySize::Aes192, Key::new_192(array_ref![key_bytes, 0, 24]).size());
+        assert_eq!(KeySize::Aes256, Key::new_256(array_ref![key_bytes, 0, 32]).size());
+        assert!(Key::from_slice(&key_bytes[..20]).is_none());
+    }
+
     #[test]
     fn key_schedule_test() {
-        let key = Key { data: [ 0x2b, 0x7e, 0x15, 0x16
-                              , 0x28, 0xae, 0xd2, 0xa6
-                              , 0xab, 0xf7, 0x15, 0x88
-                              , 0x09, 0xcf, 0x4f, 0x3c
-                              ]};
+        let key = Key::new(&[ 0x2b, 0x7e, 0x15, 0x16
+                            , 0x28, 0xae, 0xd2, 0xa6
+                            , 0xab, 0xf7, 0x15, 0x88
+                            , 0x09, 0xcf, 0x4f, 0x3c
+                            ]);
         let expected = [ 0xa0, 0xfa, 0xfe, 0x17
                        , 0x88, 0x54, 0x2c, 0xb1
                        , 0x23, 0xa3, 0x39, 0x39
@@ -0,0 +1,243 @@
+//! Constant-time AES, enabled by the `ct` feature.
+//!
+//! Blocks are encrypted with the AES-NI instructions when the CPU has them, detected at runtime,
+//! and otherwise in software without any table lookup: the S-box is computed as an inversion in
+//! GF(2^8) followed by the affine map, and every multiplication is done without branching, so
+//! neither the memory accesses nor the branches depend on the key or the data.
+//!
+//! The key schedule always runs in software, through the same constant-time S-box.
+use aes::{Block, Key, RoundKey};
+
+/// Encrypts `block` with `key` in constant time.
+pub fn encrypt(key: Key, block: Block) -> Block {
+    let keys = key.expand_with(sub_byte);
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("aes") {
+            return unsafe { aesni::encrypt(&keys, block) };
+        }
+    }
+    soft::encrypt(&keys, block)
+}
+
+/// Decrypts `block` with `key` in constant time.
+pub fn decrypt(key: Key, block: Block) -> Block {
+    let keys = key.expand_with(sub_byte);
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("aes") {
+            return unsafe { aesni::decrypt(&keys, block) };
+        }
+    }
+    soft::decrypt(&keys, block)
+}
+
+/// Multiplies `a` by `b` in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1.
+fn gf_mul(a: u8, b: u8) -> u8 {
+    let mut a = a;
+    let mut product = 0;
+    for i in 0..8 {
+        product ^= a & 0u8.wrapping_sub((b >> i) & 1);
+        a = xtime(a);
+    }
+    product
+}
+
+/// Multiplies `a` by x in GF(2^8).
+fn xtime(a: u8) -> u8 {
+    (a << 1) ^ (0x1b & 0u8.wrapping_sub(a >> 7))
+}
+
+/// The inverse of `a` in GF(2^8), a^254, with 0 mapped to 0.
+fn gf_inv(a: u8) -> u8 {
+    let a2 = gf_mul(a, a);
+    let a3 = gf_mul(a2, a);
+    let a6 = gf_mul(a3, a3);
+    let a12 = gf_mul(a6, a6);
+    let a15 = gf_mul(a12, a3);
+    let a30 = gf_mul(a15, a15);
+    let a60 = gf_mul(a30, a30);
+    let a120 = gf_mul(a60, a60);
+    let a126 = gf_mul(a120, a6);
+    let a127 = gf_mul(a126, a);
+    gf_mul(a127, a127)
+}
+
+fn sub_byte(byte: u8) -> u8 {
+    let b = gf_inv(byte);
+    b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3) ^ b.rotate_left(4) ^ 0x63
+}
+
+fn inv_sub_byte(byte: u8) -> u8 {
+    gf_inv(byte.rotate_left(1) ^ byte.rotate_left(3) ^ byte.rotate_left(6) ^ 0x05)
+}
+
+mod soft {
+    use super::*;
+
+    pub fn encrypt(keys: &[RoundKey], block: Block) -> Block {
+        let rounds = keys.len() - 1;
+        let mut state = block;
+
+        state.add_round_key(&keys[0]);
+        for key in &keys[1..rounds] {
+            sub_bytes(&mut state);
+            state.shift_rows();
+            mix_columns(&mut state);
+            state.add_round_key(key);
+        }
+        sub_bytes(&mut state);
+        state.shift_rows();
+        state.add_round_key(&keys[rounds]);
+
+        state
+    }
+
+    pub fn decrypt(keys: &[RoundKey], block: Block) -> Block {
+        let rounds = keys.len() - 1;
+        let mut state = block;
+
+        state.add_round_key(&keys[rounds]);
+        for key in keys[1..rounds].iter().rev() {
+            state.inv_shift_rows();
+            inv_sub_bytes(&mut state);
+            state.add_round_key(key);
+            inv_mix_columns(&mut state);
+        }
+        state.inv_shift_rows();
+        inv_sub_bytes(&mut state);
+        state.add_round_key(&keys[0]);
+
+        state
+    }
+
+    fn sub_bytes(state: &mut Block) {
+        for byte in state.data.iter_mut() {
+            *byte = sub_byte(*byte);
+        }
+    }
+
+    fn inv_sub_bytes(state: &mut Block) {
+        for byte in state.data.iter_mut() {
+            *byte = inv_sub_byte(*byte);
+        }
+    }
+
+    fn mix_columns(state: &mut Block) {
+        for col in state.data.chunks_mut(4) {
+            let c = [col[0], col[1], col[2], col[3]];
+            let all = c[0] ^ c[1] ^ c[2] ^ c[3];
+            for i in 0..4 {
+                col[i] = c[i] ^ all ^ xtime(c[i] ^ c[(i + 1) % 4]);
+            }
+        }
+    }
+
+    fn inv_mix_columns(state: &mut Block) {
+        for col in state.data.chunks_mut(4) {
+            let c = [col[0], col[1], col[2], col[3]];
+            for i in 0..4 {
+                col[i] = gf_mul(c[i], 14) ^ gf_mul(c[(i + 1) % 4], 11)
+                       ^ gf_mul(c[(i + 2) % 4], 13) ^ gf_mul(c[(i + 3) % 4], 9);
+            }
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod aesni {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use aes::{Block, RoundKey};
+
+    /// Callers must check that the CPU supports AES-NI.
+    #[target_feature(enable = "aes")]
+    pub unsafe fn encrypt(keys: &[RoundKey], block: Block) -> Block {
+        let rounds = keys.len() - 1;
+        let mut state = _mm_xor_si128(load(&block.data), load(&keys[0].data));
+        for key in &keys[1..rounds] {
+            state = _mm_aesenc_si128(state, load(&key.data));
+        }
+        store(_mm_aesenclast_si128(state, load(&keys[rounds].data)))
+    }
+
+    /// Callers must check that the CPU supports AES-NI.
+    #[target_feature(enable = "aes")]
+    pub unsafe fn decrypt(keys: &[RoundKey], block: Block) -> Block {
+        let rounds = keys.len() - 1;
+        let mut state = _mm_xor_si128(load(&block.data), load(&keys[rounds].data));
+        // AESDEC applies InvMixColumns before the round key, which must be transformed to match.
+        for key in keys[1..rounds].iter().rev() {
+            state = _mm_aesdec_si128(state, _mm_aesimc_si128(load(&key.data)));
+        }
+        store(_mm_aesdeclast_si128(state, load(&keys[0].data)))
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn load(bytes: &[u8; 16]) -> __m128i {
+        _mm_loadu_si128(bytes.as_ptr() as *const __m128i)
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn store(state: __m128i) -> Block {
+        let mut bytes = [0; 16];
+        _mm_storeu_si128(bytes.as_mut_ptr() as *mut __m128i, state);
+        Block::new(&bytes)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use constants::{INV_SBOX, SBOX};
+
+    #[test]
+    fn sbox_test() {
+        for byte in 0..256 {
+            assert_eq!(SBOX[byte], sub_byte(byte as u8));
+            assert_eq!(INV_SBOX[byte], inv_sub_byte(byte as u8));
+        }
+    }
+
+    /// Every implementation against FIPS-197 appendix C, for every key size.
+    #[test]
+    fn implementations_test() {
+        let message = Block::new(&[ 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77
+                                  , 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff
+                                  ]);
+        let key_bytes: Vec<u8> = (0..32).collect();
+        let cases = [ (16, [ 0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30
+                           , 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5, 0x5a
+                           ])
+                    , (24, [ 0xdd, 0xa9, 0x7c, 0xa4, 0x86, 0x4c, 0xdf, 0xe0
+                           , 0x6e, 0xaf, 0x70, 0xa0, 0xec, 0x0d, 0x71, 0x91
+                           ])
+                    , (32, [ 0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf
+                           , 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49, 0x60, 0x89
+                           ])
+                    ];
+
+        for &(len, expected) in cases.iter() {
+            let key = Key::from_slice(&key_bytes[..len]).unwrap();
+            let keys = key.expand_with(sub_byte);
+            let expected = Block::new(&expected);
+
+            assert_eq!(expected, soft::encrypt(&keys, message));
+            assert_eq!(message, soft::decrypt(&keys, expected));
+            assert_eq!(expected, encrypt(key, message));
+            assert_eq!(message, decrypt(key, expected));
+
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            {
+                if is_x86_feature_detected!("aes") {
+                    assert_eq!(expected, unsafe { aesni::encrypt(&keys, message) });
+                    assert_eq!(message, unsafe { aesni::decrypt(&keys, expected) });
+                }
+            }
+        }
+    }
+}
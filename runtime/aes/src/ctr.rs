@@ -0,0 +1,165 @@
+//! Counter (CTR) mode, NIST SP 800-38A.
+//!
+//! Successive counter blocks are encrypted and the result is XORed with the data, so encryption
+//! and decryption are the same operation and no padding is needed: the output is exactly as long as
+//! the input, the unused end of the last keystream block is dropped.
+//!
+//! A counter block must never be encrypted twice under the same key, so a nonce must never be
+//! reused with the same key and the counter never wraps: `Ctr` panics rather than repeat its
+//! keystream.
+use aes::{encrypt, Block, Key};
+
+/// A CTR keystream, applied to the data with `apply_keystream`.
+pub struct Ctr {
+    key: Key,
+    counter: [u8; 16],
+    /// Number of trailing bytes of `counter` that are incremented, the rest is the nonce.
+    counter_len: usize,
+    /// Set once the counter wrapped, the next counter block would be a repeated one.
+    exhausted: bool,
+    keystream: [u8; 16],
+    /// Number of bytes of `keystream` already used.
+    used: usize,
+}
+
+impl Ctr {
+    /// Returns the keystream of the 96-bit `nonce`: the counter blocks are the nonce followed by a
+    /// 32-bit big-endian block counter starting at 0, which allows 2^32 blocks per nonce.
+    pub fn new(key: Key, nonce: &[u8; 12]) -> Ctr {
+        let mut counter = [0; 16];
+        counter[..12].copy_from_slice(nonce);
+        Ctr::with_counter(key, counter, 4)
+    }
+
+    /// Returns the keystream starting at the `initial` counter block, the whole block being
+    /// incremented as a 128-bit big-endian integer as in the examples of SP 800-38A.
+    pub fn from_counter_block(key: Key, initial: &[u8; 16]) -> Ctr {
+        Ctr::with_counter(key, *initial, 16)
+    }
+
+    pub(crate) fn with_counter(key: Key, counter: [u8; 16], counter_len: usize) -> Ctr {
+        Ctr {
+            key,
+            counter,
+            counter_len,
+            exhausted: false,
+            keystream: [0; 16],
+            used: 16,
+        }
+    }
+
+    /// XORs `data` with the next `data.len()` bytes of keystream, calls may be split anywhere.
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            if self.used == 16 {
+                self.next_block();
+            }
+            *byte ^= self.keystream[self.used];
+            self.used += 1;
+        }
+    }
+
+    fn next_block(&mut self) {
+        assert!(!self.exhausted, "CTR counter exhausted, the keystream would repeat");
+        self.keystream = *encrypt(self.key, Block::new(&self.counter)).as_bytes();
+        self.used = 0;
+
+        self.exhausted = true;
+        for byte in self.counter[16 - self.counter_len..].iter_mut().rev() {
+            *byte = byte.wrapping_add(1);
+            if *byte != 0 {
+                self.exhausted = false;
+                break;
+            }
+        }
+    }
+}
+
+/// Encrypts `plaintext` with the keystream of `nonce`, see `Ctr::new`.
+///
+/// CTR mode does not authenticate the data, prefer `gcm::encrypt_gcm` unless integrity is checked
+/// some other way.
+pub fn encrypt_ctr(key: Key, nonce: &[u8; 12], plaintext: &[u8]) -> Vec<u8> {
+    let mut data = plaintext.to_vec();
+    Ctr::new(key, nonce).apply_keystream(&mut data);
+    data
+}
+
+/// Decrypts `ciphertext` produced by `encrypt_ctr` with the same key and nonce.
+pub fn decrypt_ctr(key: Key, nonce: &[u8; 12], ciphertext: &[u8]) -> Vec<u8> {
+    encrypt_ctr(key, nonce, ciphertext)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [ 0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6
+                          , 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c
+                          ];
+
+    /// SP 800-38A F.5.1, CTR-AES128.Encrypt.
+    #[test]
+    fn nist_vector_test() {
+        let initial = [ 0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7
+                      , 0xf8, 0xf9, 0xfa, 0xfb, 0xfc, 0xfd, 0xfe, 0xff
+                      ];
+        let plaintext = [ 0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96
+                        , 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a
+                        , 0xae, 0x2d, 0x8a, 0x57, 0x1e, 0x03, 0xac, 0x9c
+                        , 0x9e, 0xb7, 0x6f, 0xac, 0x45, 0xaf, 0x8e, 0x51
+                        , 0x30, 0xc8, 0x1c, 0x46, 0xa3, 0x5c, 0xe4, 0x11
+                        , 0xe5, 0xfb, 0xc1, 0x19, 0x1a, 0x0a, 0x52, 0xef
+                        , 0xf6, 0x9f, 0x24, 0x45, 0xdf, 0x4f, 0x9b, 0x17
+                        , 0xad, 0x2b, 0x41, 0x7b, 0xe6, 0x6c, 0x37, 0x10
+                        ];
+        let expected = [ 0x87, 0x4d, 0x61, 0x91, 0xb6, 0x20, 0xe3, 0x26
+                       , 0x1b, 0xef, 0x68, 0x64, 0x99, 0x0d, 0xb6, 0xce
+                       , 0x98, 0x06, 0xf6, 0x6b, 0x79, 0x70, 0xfd, 0xff
+                       , 0x86, 0x17, 0x18, 0x7b, 0xb9, 0xff, 0xfd, 0xff
+                       , 0x5a, 0xe4, 0xdf, 0x3e, 0xdb, 0xd5, 0xd3, 0x5e
+                       , 0x5b, 0x4f, 0x09, 0x02, 0x0d, 0xb0, 0x3e, 0xab
+                       , 0x1e, 0x03, 0x1d, 0xda, 0x2f, 0xbe, 0x03, 0xd1
+                       , 0x79, 0x21, 0x70, 0xa0, 0xf3, 0x00, 0x9c, 0xee
+                       ];
+
+        let mut data = plaintext;
+        Ctr::from_counter_block(Key::new(&KEY), &initial).apply_keystream(&mut data);
+        assert_eq!(&expected[..], &data[..]);
+
+        // The keystream does not depend on how the data is split.
+        let mut data = plaintext;
+        let mut ctr = Ctr::from_counter_block(Key::new(&KEY), &initial);
+        for chunk in data.chunks_mut(7) {
+            ctr.apply_keystream(chunk);
+        }
+        assert_eq!(&expected[..], &data[..]);
+    }
+
+    #[test]
+    fn encryption_decryption_test() {
+        let key = Key::new(&KEY);
+        let nonce = [7; 12];
+        for len in 0..70 {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let ciphertext = encrypt_ctr(key, &nonce, &plaintext);
+            assert_eq!(plaintext.len(), ciphertext.len());
+            assert_eq!(plaintext, decrypt_ctr(key, &nonce, &ciphertext));
+            if len > 0 {
+                assert!(plaintext != ciphertext);
+                assert!(ciphertext != encrypt_ctr(key, &[8; 12], &plaintext));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn counter_overflow_test() {
+        let mut counter = [0xff; 16];
+        counter[11] = 0;
+        let mut ctr = Ctr::with_counter(Key::new(&KEY), counter, 4);
+        ctr.apply_keystream(&mut [0; 16]);
+        ctr.apply_keystream(&mut [0; 1]);
+    }
+}
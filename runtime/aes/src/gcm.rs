@@ -0,0 +1,222 @@
+//! Galois/Counter Mode (GCM), NIST SP 800-38D.
+//!
+//! The plaintext is encrypted in CTR mode and the additional data and ciphertext are authenticated
+//! by a 128-bit tag, appended to the ciphertext. Nonces are 96 bits, the length SP 800-38D
+//! recommends, and must never be reused with the same key: a repeated nonce leaks the XOR of the
+//! plaintexts and lets anyone forge tags.
+use std::error::Error;
+use std::fmt;
+
+use aes::{encrypt, Block, Key};
+use ctr::Ctr;
+
+/// Length of the nonce in bytes.
+pub const NONCE_LEN: usize = 12;
+/// Length of the authentication tag appended to the ciphertext in bytes.
+pub const TAG_LEN: usize = 16;
+
+/// The ciphertext, its additional data, key or nonce were not the ones it was encrypted with.
+#[derive(Eq,PartialEq,Clone,Copy,Debug)]
+pub struct AuthenticationError;
+
+impl fmt::Display for AuthenticationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GCM authentication failed")
+    }
+}
+
+impl Error for AuthenticationError {}
+
+/// Encrypts `plaintext` and authenticates it together with `aad`, which is not encrypted.
+///
+/// Returns the ciphertext followed by the `TAG_LEN` bytes tag.
+pub fn encrypt_gcm(key: Key, nonce: &[u8; NONCE_LEN], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut data = plaintext.to_vec();
+    counter_mode(key, nonce).apply_keystream(&mut data);
+    let tag = tag(key, nonce, &data, aad);
+    data.extend_from_slice(&tag);
+    data
+}
+
+/// Checks the tag of `ciphertext`, produced by `encrypt_gcm` with the same `aad`, and decrypts it.
+///
+/// Nothing is decrypted unless the tag is valid.
+pub fn decrypt_gcm(key: Key, nonce: &[u8; NONCE_LEN], ciphertext: &[u8], aad: &[u8])
+    -> Result<Vec<u8>, AuthenticationError> {
+    if ciphertext.len() < TAG_LEN {
+        return Err(AuthenticationError);
+    }
+    let (ciphertext, expected) = ciphertext.split_at(ciphertext.len() - TAG_LEN);
+
+    // Compares every byte, so the time taken does not tell how much of the tag was right.
+    let difference = tag(key, nonce, ciphertext, aad).iter().zip(expected)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    if difference != 0 {
+        return Err(AuthenticationError);
+    }
+
+    let mut data = ciphertext.to_vec();
+    counter_mode(key, nonce).apply_keystream(&mut data);
+    Ok(data)
+}
+
+/// The counter block J0 of SP 800-38D: the nonce followed by a 32-bit counter set to 1.
+fn initial_counter(nonce: &[u8; NONCE_LEN]) -> [u8; 16] {
+    let mut counter = [0; 16];
+    counter[..NONCE_LEN].copy_from_slice(nonce);
+    counter[15] = 1;
+    counter
+}
+
+/// The keystream encrypting the data, which starts at the counter block after J0.
+fn counter_mode(key: Key, nonce: &[u8; NONCE_LEN]) -> Ctr {
+    let mut counter = initial_counter(nonce);
+    counter[15] = 2;
+    Ctr::with_counter(key, counter, 4)
+}
+
+fn tag(key: Key, nonce: &[u8; NONCE_LEN], ciphertext: &[u8], aad: &[u8]) -> [u8; TAG_LEN] {
+    let h = u128::from_be_bytes(*encrypt(key, Block::new(&[0; 16])).as_bytes());
+    let mut ghash = 0;
+    for data in &[aad, ciphertext] {
+        for chunk in data.chunks(16) {
+            let mut block = [0; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            ghash = gf_mul(ghash ^ u128::from_be_bytes(block), h);
+        }
+    }
+    let lengths = ((aad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+    ghash = gf_mul(ghash ^ lengths, h);
+
+    let mask = u128::from_be_bytes(*encrypt(key, Block::new(&initial_counter(nonce))).as_bytes());
+    (ghash ^ mask).to_be_bytes()
+}
+
+/// Multiplies `x` by `y` in GF(2^128) with the bit order of GCM, without branching on either.
+fn gf_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xe1 << 120;
+    let mut z = 0;
+    let mut v = y;
+    for i in 0..128 {
+        z ^= v & 0u128.wrapping_sub((x >> (127 - i)) & 1);
+        v = (v >> 1) ^ (R & 0u128.wrapping_sub(v & 1));
+    }
+    z
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 16] = [ 0xfe, 0xff, 0xe9, 0x92, 0x86, 0x65, 0x73, 0x1c
+                          , 0x6d, 0x6a, 0x8f, 0x94, 0x67, 0x30, 0x83, 0x08
+                          ];
+    const NONCE: [u8; 12] = [ 0xca, 0xfe, 0xba, 0xbe, 0xfa, 0xce, 0xdb, 0xad
+                            , 0xde, 0xca, 0xf8, 0x88
+                            ];
+    const PLAINTEXT: [u8; 64] = [ 0xd9, 0x31, 0x32, 0x25, 0xf8, 0x84, 0x06, 0xe5
+                                , 0xa5, 0x59, 0x09, 0xc5, 0xaf, 0xf5, 0x26, 0x9a
+                                , 0x86, 0xa7, 0xa9, 0x53, 0x15, 0x34, 0xf7, 0xda
+                                , 0x2e, 0x4c, 0x30, 0x3d, 0x8a, 0x31, 0x8a, 0x72
+                                , 0x1c, 0x3c, 0x0c, 0x95, 0x95, 0x68, 0x09, 0x53
+                                , 0x2f, 0xcf, 0x0e, 0x24, 0x49, 0xa6, 0xb5, 0x25
+                                , 0xb1, 0x6a, 0xed, 0xf5, 0xaa, 0x0d, 0xe6, 0x57
+                                , 0xba, 0x63, 0x7b, 0x39, 0x1a, 0xaf, 0xd2, 0x55
+                                ];
+    const CIPHERTEXT: [u8; 64] = [ 0x42, 0x83, 0x1e, 0xc2, 0x21, 0x77, 0x74, 0x24
+                                 , 0x4b, 0x72, 0x21, 0xb7, 0x84, 0xd0, 0xd4, 0x9c
+                                 , 0xe3, 0xaa, 0x21, 0x2f, 0x2c, 0x02, 0xa4, 0xe0
+                                 , 0x35, 0xc1, 0x7e, 0x23, 0x29, 0xac, 0xa1, 0x2e
+                                 , 0x21, 0xd5, 0x14, 0xb2, 0x54, 0x66, 0x93, 0x1c
+                                 , 0x7d, 0x8f, 0x6a, 0x5a, 0xac, 0x84, 0xaa, 0x05
+                                 , 0x1b, 0xa3, 0x0b, 0x39, 0x6a, 0x0a, 0xac, 0x97
+                                 , 0x3d, 0x58, 0xe0, 0x91, 0x47, 0x3f, 0x59, 0x85
+                                 ];
+
+    fn check(key: Key, nonce: &[u8; 12], plaintext: &[u8], aad: &[u8], ciphertext: &[u8]
+             , tag: &[u8]) {
+        let mut expected = ciphertext.to_vec();
+        expected.extend_from_slice(tag);
+
+        let encrypted = encrypt_gcm(key, nonce, plaintext, aad);
+        assert_eq!(expected, encrypted);
+        assert_eq!(Ok(plaintext.to_vec()), decrypt_gcm(key, nonce, &encrypted, aad));
+    }
+
+    /// Test cases 1 to 4 of the GCM specification, AES-128 with 96-bit nonces.
+    #[test]
+    fn nist_vectors_test() {
+        let zero = Key::new(&[0; 16]);
+        check(zero, &[0; 12], &[], &[], &[]
+              , &[ 0x58, 0xe2, 0xfc, 0xce, 0xfa, 0x7e, 0x30, 0x61
+                 , 0x36, 0x7f, 0x1d, 0x57, 0xa4, 0xe7, 0x45, 0x5a
+                 ]);
+        check(zero, &[0; 12], &[0; 16], &[]
+              , &[ 0x03, 0x88, 0xda, 0xce, 0x60, 0xb6, 0xa3, 0x92
+                 , 0xf3, 0x28, 0xc2, 0xb9, 0x71, 0xb2, 0xfe, 0x78
+                 ]
+              , &[ 0xab, 0x6e, 0x47, 0xd4, 0x2c, 0xec, 0x13, 0xbd
+                 , 0xf5, 0x3a, 0x67, 0xb2, 0x12, 0x57, 0xbd, 0xdf
+                 ]);
+        check(Key::new(&KEY), &NONCE, &PLAINTEXT, &[], &CIPHERTEXT
+              , &[ 0x4d, 0x5c, 0x2a, 0xf3, 0x27, 0xcd, 0x64, 0xa6
+                 , 0x2c, 0xf3, 0x5a, 0xbd, 0x2b, 0xa6, 0xfa, 0xb4
+                 ]);
+        let aad = [ 0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef
+                  , 0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef
+                  , 0xab, 0xad, 0xda, 0xd2
+                  ];
+        check(Key::new(&KEY), &NONCE, &PLAINTEXT[..60], &aad, &CIPHERTEXT[..60]
+              , &[ 0x5b, 0xc9, 0x4f, 0xbc, 0x32, 0x21, 0xa5, 0xdb
+                 , 0x94, 0xfa, 0xe9, 0x5a, 0xe7, 0x12, 0x1a, 0x47
+                 ]);
+    }
+
+    /// Test cases 13 and 14 of the GCM specification, AES-256.
+    #[test]
+    fn nist_vectors_256_test() {
+        let zero = Key::new_256(&[0; 32]);
+        check(zero, &[0; 12], &[], &[], &[]
+              , &[ 0x53, 0x0f, 0x8a, 0xfb, 0xc7, 0x45, 0x36, 0xb9
+                 , 0xa9, 0x63, 0xb4, 0xf1, 0xc4, 0xcb, 0x73, 0x8b
+                 ]);
+        check(zero, &[0; 12], &[0; 16], &[]
+              , &[ 0xce, 0xa7, 0x40, 0x3d, 0x4d, 0x60, 0x6b, 0x6e
+                 , 0x07, 0x4e, 0xc5, 0xd3, 0xba, 0xf3, 0x9d, 0x18
+                 ]
+              , &[ 0xd0, 0xd1, 0xc8, 0xa7, 0x99, 0x99, 0x6b, 0xf0
+                 , 0x26, 0x5b, 0x98, 0xb5, 0xd4, 0x8a, 0xb9, 0x19
+                 ]);
+    }
+
+    #[test]
+    fn encryption_decryption_test() {
+        let key = Key::new(&KEY);
+        let aad = b"header";
+        for len in 0..70 {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encrypted = encrypt_gcm(key, &NONCE, &plaintext, aad);
+            assert_eq!(len + TAG_LEN, encrypted.len());
+            assert_eq!(Ok(plaintext), decrypt_gcm(key, &NONCE, &encrypted, aad));
+        }
+    }
+
+    #[test]
+    fn authentication_test() {
+        let key = Key::new(&KEY);
+        let encrypted = encrypt_gcm(key, &NONCE, &PLAINTEXT, b"header");
+
+        for i in 0..encrypted.len() {
+            let mut tampered = encrypted.clone();
+            tampered[i] ^= 1;
+            assert_eq!(Err(AuthenticationError), decrypt_gcm(key, &NONCE, &tampered, b"header"));
+        }
+        assert_eq!(Err(AuthenticationError), decrypt_gcm(key, &NONCE, &encrypted, b"other"));
+        assert_eq!(Err(AuthenticationError), decrypt_gcm(key, &[0; 12], &encrypted, b"header"));
+        assert_eq!(Err(AuthenticationError), decrypt_gcm(Key::new(&[0; 16]), &NONCE, &encrypted
+                                                         , b"header"));
+        assert_eq!(Err(AuthenticationError), decrypt_gcm(key, &NONCE, &encrypted[..TAG_LEN - 1]
+                                                         , b"header"));
+    }
+}
@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate arrayref;
+
+pub mod aes;
+pub mod ctr;
+#[cfg(feature = "ct")]
+pub mod ct;
+pub mod gcm;
+mod constants;
+
+pub use aes::{decrypt, encrypt, Block, Key, KeySize};
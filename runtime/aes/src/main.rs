@@ -1,8 +1,4 @@
-#[macro_use]
-extern crate arrayref;
-
-pub mod aes;
-mod constants;
+extern crate aes;
 
 fn main() {
     let message_text = "Hello, world! <3";
@@ -0,0 +1,201 @@
+//! Canonical CBOR encoding.
+//!
+//! Content addressed data is hashed in its encoded form, so two nodes must encode the same value
+//! to the same bytes. The canonical form follows [RFC 7049 section 3.9]: integers, lengths and
+//! tags use their shortest encoding, map keys are sorted by the length of their encoding and then
+//! bytewise, and all lengths are definite. Floats are rejected since the same number has several
+//! encodings.
+//!
+//! ```rust
+//! use std::collections::BTreeMap;
+//!
+//! let mut map = BTreeMap::new();
+//! map.insert("bb", 1);
+//! map.insert("a", 2);
+//! let bytes = serde_cbor::to_vec_canonical(&map).unwrap();
+//! assert_eq!(bytes, b"\xa2\x61a\x02\x62bb\x01");
+//! ```
+//!
+//! [RFC 7049 section 3.9]: https://tools.ietf.org/html/rfc7049#section-3.9
+
+use serde::Serialize;
+
+use crate::error::{Error, ErrorCode, Result};
+
+/// Nesting depth of arrays, maps and tags at which input is rejected.
+const RECURSION_LIMIT: usize = 128;
+
+/// Serializes `value` as canonical CBOR.
+///
+/// Fails if `value` contains a float, or a sequence or map whose length is not known up front.
+pub fn to_vec_canonical<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    canonicalize_slice(&crate::to_vec(value)?)
+}
+
+/// Re-encodes the CBOR item in `slice` in canonical form.
+///
+/// Fails on floats, indefinite lengths, duplicate map keys and trailing data. Comparing the
+/// result with `slice` tells whether `slice` was canonical already.
+pub fn canonicalize_slice(slice: &[u8]) -> Result<Vec<u8>> {
+    let mut canonicalizer = Canonicalizer {
+        input: slice,
+        pos: 0,
+    };
+    let mut out = Vec::with_capacity(slice.len());
+    canonicalizer.item(&mut out, 0)?;
+    if canonicalizer.pos != slice.len() {
+        return Err(Error::syntax(
+            ErrorCode::TrailingData,
+            canonicalizer.pos as u64,
+        ));
+    }
+    Ok(out)
+}
+
+/// Writes the initial byte and argument of an item in their shortest encoding.
+fn write_header(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    if value < 24 {
+        out.push(major | value as u8);
+    } else if value <= u64::from(u8::MAX) {
+        out.extend_from_slice(&[major | 24, value as u8]);
+    } else if value <= u64::from(u16::MAX) {
+        out.push(major | 25);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u64::from(u32::MAX) {
+        out.push(major | 26);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+struct Canonicalizer<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Canonicalizer<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.input.len() - self.pos < len {
+            return Err(Error::syntax(
+                ErrorCode::EofWhileParsingValue,
+                self.input.len() as u64,
+            ));
+        }
+        let bytes = &self.input[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads the initial byte of an item and its argument.
+    fn header(&mut self) -> Result<(u8, u8, u64)> {
+        let start = self.pos as u64;
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let value = match info {
+            0..=23 => u64::from(info),
+            24 => u64::from(self.take(1)?[0]),
+            25 => self
+                .take(2)?
+                .iter()
+                .fold(0, |acc, &b| acc << 8 | u64::from(b)),
+            26 => self
+                .take(4)?
+                .iter()
+                .fold(0, |acc, &b| acc << 8 | u64::from(b)),
+            27 => self
+                .take(8)?
+                .iter()
+                .fold(0, |acc, &b| acc << 8 | u64::from(b)),
+            31 => return Err(Error::syntax(ErrorCode::IndefiniteLength, start)),
+            _ => return Err(Error::syntax(ErrorCode::UnassignedCode, start)),
+        };
+        if major == 7 && info >= 25 {
+            return Err(Error::syntax(ErrorCode::Float, start));
+        }
+        Ok((major, info, value))
+    }
+
+    fn len(&self, value: u64) -> Result<usize> {
+        if value > (self.input.len() - self.pos) as u64 {
+            return Err(Error::syntax(ErrorCode::LengthOutOfRange, self.pos as u64));
+        }
+        Ok(value as usize)
+    }
+
+    fn item(&mut self, out: &mut Vec<u8>, depth: usize) -> Result<()> {
+        if depth > RECURSION_LIMIT {
+            return Err(Error::syntax(
+                ErrorCode::RecursionLimitExceeded,
+                self.pos as u64,
+            ));
+        }
+        let (major, info, value) = self.header()?;
+        match major {
+            // integers
+            0 | 1 => write_header(out, major, value),
+            // byte and text strings
+            2 | 3 => {
+                let len = self.len(value)?;
+                let start = self.pos as u64;
+                let bytes = self.take(len)?;
+                if major == 3 && std::str::from_utf8(bytes).is_err() {
+                    return Err(Error::syntax(ErrorCode::InvalidUtf8, start));
+                }
+                write_header(out, major, value);
+                out.extend_from_slice(bytes);
+            }
+            4 => {
+                // every element takes at least one byte.
+                let len = self.len(value)?;
+                write_header(out, major, value);
+                for _ in 0..len {
+                    self.item(out, depth + 1)?;
+                }
+            }
+            5 => {
+                let len = self.len(value)?;
+                let mut entries = Vec::with_capacity(len);
+                for _ in 0..len {
+                    let offset = self.pos as u64;
+                    let mut key = Vec::new();
+                    self.item(&mut key, depth + 1)?;
+                    let mut value = Vec::new();
+                    self.item(&mut value, depth + 1)?;
+                    entries.push((key, value, offset));
+                }
+                entries
+                    .sort_by(|(a, _, _), (b, _, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+                if let Some(pair) = entries.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                    return Err(Error::syntax(
+                        ErrorCode::DuplicateKey,
+                        pair[0].2.max(pair[1].2),
+                    ));
+                }
+                write_header(out, major, value);
+                for (key, value, _) in entries {
+                    out.extend_from_slice(&key);
+                    out.extend_from_slice(&value);
+                }
+            }
+            6 => {
+                write_header(out, major, value);
+                self.item(out, depth + 1)?;
+            }
+            // simple values, floats are rejected by `header`.
+            _ => {
+                if info == 24 {
+                    out.extend_from_slice(&[7 << 5 | 24, value as u8]);
+                } else {
+                    out.push(7 << 5 | info);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -8,6 +8,10 @@ use core::str;
 use half::f16;
 use serde::de;
 #[cfg(feature = "std")]
+use std::borrow::Cow;
+#[cfg(feature = "std")]
+use std::collections::BTreeSet;
+#[cfg(feature = "std")]
 use std::io;
 
 use crate::error::{Error, ErrorCode, Result};
@@ -124,10 +128,121 @@ where
     Ok(value)
 }
 
+/// How a map key that already appeared in the same map is handled.
+///
+/// Only integer keys and byte and text string keys of definite length are compared.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Fail with an error. Keys that cannot be compared, such as floats, tagged values or strings
+    /// of indefinite length, are rejected as well.
+    Error,
+    /// Keep the first entry and skip the later ones. Keys that cannot be compared are passed on.
+    FirstWins,
+    /// Pass every entry on and let the type being deserialized decide.
+    ///
+    /// Only maps keep the last entry: structs deriving `Deserialize` fail on a duplicate field
+    /// whatever the policy, unless `FirstWins` skips it before they see it.
+    #[default]
+    LastWins,
+}
+
+/// Nesting depth of arrays, maps and tags allowed by default.
+const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// Options affecting deserialization.
+///
+/// The limits protect against untrusted input: `max_depth` against stack overflows on deeply
+/// nested values, `max_collection_len` and `max_bytes` against running for long or allocating a
+/// lot on huge declared lengths. By default only the depth is limited.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use serde_cbor::de::{DeserializerOptions, DuplicateKeys};
+///
+/// // {"a": 1, "a": 2}
+/// let v = b"\xa2\x61a\x01\x61a\x02";
+/// let options = DeserializerOptions {
+///     duplicate_keys: DuplicateKeys::FirstWins,
+///     ..Default::default()
+/// };
+/// let value: BTreeMap<String, u8> = options.from_slice(v).unwrap();
+/// assert_eq!(value["a"], 1);
+///
+/// // [[[1]]]
+/// let v = b"\x81\x81\x81\x01";
+/// let options = DeserializerOptions {
+///     max_depth: 2,
+///     ..Default::default()
+/// };
+/// assert!(options.from_slice::<Vec<Vec<Vec<u8>>>>(v).is_err());
+/// ```
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+pub struct DeserializerOptions {
+    /// Handling of duplicate map keys.
+    ///
+    /// Unless this is `LastWins`, compared keys are kept until the end of their map, which copies
+    /// them when they cannot be borrowed from the input.
+    pub duplicate_keys: DuplicateKeys,
+    /// How deeply arrays, maps and tags may be nested. Defaults to 128.
+    pub max_depth: usize,
+    /// How many elements an array, or entries a map, may have, whether its length is declared
+    /// upfront or not. Unlimited by default.
+    pub max_collection_len: usize,
+    /// How many bytes may be read from the input, counting declared lengths of byte and text
+    /// strings before they are read. Unlimited by default.
+    pub max_bytes: usize,
+}
+
+#[cfg(feature = "std")]
+impl Default for DeserializerOptions {
+    fn default() -> Self {
+        DeserializerOptions {
+            duplicate_keys: DuplicateKeys::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_collection_len: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl DeserializerOptions {
+    /// Decodes a value from CBOR data in a slice.
+    pub fn from_slice<'a, T>(&self, slice: &'a [u8]) -> Result<T>
+    where
+        T: de::Deserialize<'a>,
+    {
+        let mut deserializer = Deserializer::new_with_options(SliceRead::new(slice), self);
+        let value = de::Deserialize::deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        Ok(value)
+    }
+
+    /// Decodes a value from CBOR data in a reader.
+    pub fn from_reader<T, R>(&self, reader: R) -> Result<T>
+    where
+        T: de::DeserializeOwned,
+        R: io::Read,
+    {
+        let mut deserializer = Deserializer::new_with_options(IoRead::new(reader), self);
+        let value = de::Deserialize::deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        Ok(value)
+    }
+}
+
 /// A Serde `Deserialize`r of CBOR data.
 pub struct Deserializer<R> {
     read: R,
-    remaining_depth: u8,
+    remaining_depth: usize,
+    max_collection_len: usize,
+    max_bytes: u64,
+    #[cfg(feature = "std")]
+    duplicate_keys: DuplicateKeys,
 }
 
 #[cfg(feature = "std")]
@@ -181,7 +296,24 @@ where
     pub fn new(read: R) -> Self {
         Deserializer {
             read,
-            remaining_depth: 128,
+            remaining_depth: DEFAULT_MAX_DEPTH,
+            max_collection_len: usize::MAX,
+            max_bytes: u64::MAX,
+            #[cfg(feature = "std")]
+            duplicate_keys: DuplicateKeys::default(),
+        }
+    }
+
+    /// Constructs a `Deserializer` from one of the possible serde_cbor input sources, honoring
+    /// `options`.
+    #[cfg(feature = "std")]
+    pub fn new_with_options(read: R, options: &DeserializerOptions) -> Self {
+        Deserializer {
+            read,
+            remaining_depth: options.max_depth,
+            max_collection_len: options.max_collection_len,
+            max_bytes: options.max_bytes as u64,
+            duplicate_keys: options.duplicate_keys,
         }
     }
 
@@ -207,7 +339,9 @@ where
     }
 
     fn next(&mut self) -> Result<Option<u8>> {
-        self.read.next()
+        let byte = self.read.next()?;
+        self.check_bytes(0)?;
+        Ok(byte)
     }
 
     fn peek(&mut self) -> Result<Option<u8>> {
@@ -223,6 +357,22 @@ where
         Error::syntax(reason, offset)
     }
 
+    /// Fails if reading `len` more bytes would go past `max_bytes`.
+    fn check_bytes(&self, len: usize) -> Result<()> {
+        if self.read.offset().saturating_add(len as u64) > self.max_bytes {
+            return Err(self.error(ErrorCode::SizeLimitExceeded));
+        }
+        Ok(())
+    }
+
+    /// Fails if `len` elements or entries are more than `max_collection_len`.
+    fn check_collection_len(&self, len: usize) -> Result<()> {
+        if len > self.max_collection_len {
+            return Err(self.error(ErrorCode::CollectionLimitExceeded));
+        }
+        Ok(())
+    }
+
     fn parse_u8(&mut self) -> Result<u8> {
         match self.next()? {
             Some(byte) => Ok(byte),
@@ -252,6 +402,7 @@ where
     where
         V: de::Visitor<'de>,
     {
+        self.check_bytes(len)?;
         match self.read.read(len)? {
             EitherLifetime::Long(buf) => visitor.visit_borrowed_bytes(buf),
             EitherLifetime::Short(buf) => visitor.visit_bytes(buf),
@@ -272,7 +423,7 @@ where
                 0x5a => self.parse_u32()? as usize,
                 0x5b => {
                     let len = self.parse_u64()?;
-                    if len > usize::max_value() as u64 {
+                    if len > usize::MAX as u64 {
                         return Err(self.error(ErrorCode::LengthOutOfRange));
                     }
                     len as usize
@@ -281,6 +432,7 @@ where
                 _ => return Err(self.error(ErrorCode::UnexpectedCode)),
             };
 
+            self.check_bytes(len)?;
             self.read.read_to_buffer(len)?;
         }
 
@@ -306,6 +458,7 @@ where
         V: de::Visitor<'de>,
     {
         if let Some(offset) = self.read.offset().checked_add(len as u64) {
+            self.check_bytes(len)?;
             match self.read.read(len)? {
                 EitherLifetime::Long(buf) => {
                     let s = Self::convert_str(buf, offset)?;
@@ -339,7 +492,7 @@ where
                 0x7a => self.parse_u32()? as usize,
                 0x7b => {
                     let len = self.parse_u64()?;
-                    if len > usize::max_value() as u64 {
+                    if len > usize::MAX as u64 {
                         return Err(self.error(ErrorCode::LengthOutOfRange));
                     }
                     len as usize
@@ -348,6 +501,7 @@ where
                 _ => return Err(self.error(ErrorCode::UnexpectedCode)),
             };
 
+            self.check_bytes(len)?;
             self.read.read_to_buffer(len)?;
         }
 
@@ -364,14 +518,133 @@ where
         }
     }
 
+    /// Parses the tag at the current position, if there is one.
+    fn parse_tag(&mut self) -> Result<Option<u64>> {
+        let tag = match self.peek()? {
+            Some(byte @ 0xc0..=0xd7) => {
+                self.consume();
+                u64::from(byte) - 0xc0
+            }
+            Some(0xd8) => {
+                self.consume();
+                u64::from(self.parse_u8()?)
+            }
+            Some(0xd9) => {
+                self.consume();
+                u64::from(self.parse_u16()?)
+            }
+            Some(0xda) => {
+                self.consume();
+                u64::from(self.parse_u32()?)
+            }
+            Some(0xdb) => {
+                self.consume();
+                self.parse_u64()?
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(tag))
+    }
+
+    /// Keys to track for a new map.
+    #[cfg(feature = "std")]
+    fn seen_keys(&self) -> SeenKeys<'de> {
+        match self.duplicate_keys {
+            DuplicateKeys::LastWins => None,
+            _ => Some(BTreeSet::new()),
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn seen_keys(&self) -> SeenKeys<'de> {
+        PhantomData
+    }
+
+    /// Parses the key at the current position if it is one that can be compared.
+    #[cfg(feature = "std")]
+    fn parse_map_key(&mut self) -> Result<Option<MapKey<'de>>> {
+        let byte = match self.peek()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        let major = byte >> 5;
+        let info = byte & 0x1f;
+        if major > 3 || info > 27 {
+            return Ok(None);
+        }
+        self.consume();
+
+        let arg = match info {
+            24 => u64::from(self.parse_u8()?),
+            25 => u64::from(self.parse_u16()?),
+            26 => u64::from(self.parse_u32()?),
+            27 => self.parse_u64()?,
+            _ => u64::from(info),
+        };
+        match major {
+            0 => Ok(Some(MapKey::Unsigned(arg))),
+            1 => Ok(Some(MapKey::Negative(arg))),
+            _ => {
+                if arg > usize::MAX as u64 {
+                    return Err(self.error(ErrorCode::LengthOutOfRange));
+                }
+                self.check_bytes(arg as usize)?;
+                let offset = self.read.offset().saturating_add(arg);
+                let key = match (major, self.read.read(arg as usize)?) {
+                    (2, EitherLifetime::Long(buf)) => MapKey::Bytes(Cow::Borrowed(buf)),
+                    (2, EitherLifetime::Short(buf)) => MapKey::Bytes(Cow::Owned(buf.to_vec())),
+                    (_, EitherLifetime::Long(buf)) => {
+                        MapKey::Text(Cow::Borrowed(Self::convert_str(buf, offset)?))
+                    }
+                    (_, EitherLifetime::Short(buf)) => {
+                        MapKey::Text(Cow::Owned(Self::convert_str(buf, offset)?.to_owned()))
+                    }
+                };
+                Ok(Some(key))
+            }
+        }
+    }
+
+    /// Reads ahead the next map key if duplicates are checked.
+    #[cfg(feature = "std")]
+    fn next_map_key(&mut self, seen: &mut SeenKeys<'de>) -> Result<NextKey<'de>> {
+        let seen = match seen {
+            Some(seen) => seen,
+            None => return Ok(NextKey::Read),
+        };
+        let key = match self.parse_map_key()? {
+            Some(key) => key,
+            None if self.duplicate_keys == DuplicateKeys::Error && self.peek()?.is_some() => {
+                return Err(self.error(ErrorCode::UncomparableKey));
+            }
+            None => return Ok(NextKey::Read),
+        };
+        if !seen.contains(&key) {
+            seen.insert(key.clone());
+            return Ok(NextKey::Parsed(key));
+        }
+        match self.duplicate_keys {
+            DuplicateKeys::FirstWins => {
+                let _: de::IgnoredAny = de::Deserialize::deserialize(&mut *self)?;
+                Ok(NextKey::Skipped)
+            }
+            _ => Err(self.error(ErrorCode::DuplicateKey)),
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn next_map_key(&mut self, _seen: &mut SeenKeys<'de>) -> Result<NextKey<'de>> {
+        Ok(NextKey::Read)
+    }
+
     fn recursion_checked<F, T>(&mut self, f: F) -> Result<T>
     where
         F: FnOnce(&mut Deserializer<R>) -> Result<T>,
     {
-        self.remaining_depth -= 1;
         if self.remaining_depth == 0 {
             return Err(self.error(ErrorCode::RecursionLimitExceeded));
         }
+        self.remaining_depth -= 1;
         let r = f(self);
         self.remaining_depth += 1;
         r
@@ -381,6 +654,7 @@ where
     where
         V: de::Visitor<'de>,
     {
+        self.check_collection_len(len)?;
         self.recursion_checked(|de| {
             let value = visitor.visit_seq(SeqAccess { de, len: &mut len })?;
 
@@ -397,7 +671,7 @@ where
         V: de::Visitor<'de>,
     {
         self.recursion_checked(|de| {
-            let value = visitor.visit_seq(IndefiniteSeqAccess { de })?;
+            let value = visitor.visit_seq(IndefiniteSeqAccess { de, len: 0 })?;
             match de.next()? {
                 Some(0xff) => Ok(value),
                 Some(_) => Err(de.error(ErrorCode::TrailingData)),
@@ -410,8 +684,14 @@ where
     where
         V: de::Visitor<'de>,
     {
+        self.check_collection_len(len)?;
         self.recursion_checked(|de| {
-            let value = visitor.visit_map(MapAccess { de, len: &mut len })?;
+            let seen = de.seen_keys();
+            let value = visitor.visit_map(MapAccess {
+                de,
+                len: &mut len,
+                seen,
+            })?;
 
             if len != 0 {
                 Err(de.error(ErrorCode::TrailingData))
@@ -426,7 +706,8 @@ where
         V: de::Visitor<'de>,
     {
         self.recursion_checked(|de| {
-            let value = visitor.visit_map(IndefiniteMapAccess { de })?;
+            let seen = de.seen_keys();
+            let value = visitor.visit_map(IndefiniteMapAccess { de, len: 0, seen })?;
             match de.next()? {
                 Some(0xff) => Ok(value),
                 Some(_) => Err(de.error(ErrorCode::TrailingData)),
@@ -439,6 +720,7 @@ where
     where
         V: de::Visitor<'de>,
     {
+        self.check_collection_len(len)?;
         self.recursion_checked(|de| {
             let value = visitor.visit_enum(VariantAccess {
                 seq: SeqAccess { de, len: &mut len },
@@ -459,7 +741,11 @@ where
         self.recursion_checked(|de| {
             let mut len = 1;
             let value = visitor.visit_enum(VariantAccessMap {
-                map: MapAccess { de, len: &mut len },
+                map: MapAccess {
+                    de,
+                    len: &mut len,
+                    seen: SeenKeys::default(),
+                },
             })?;
 
             if len != 0 {
@@ -476,7 +762,7 @@ where
     {
         self.recursion_checked(|de| {
             let value = visitor.visit_enum(VariantAccess {
-                seq: IndefiniteSeqAccess { de },
+                seq: IndefiniteSeqAccess { de, len: 0 },
             })?;
             match de.next()? {
                 Some(0xff) => Ok(value),
@@ -547,7 +833,7 @@ where
             }
             0x3b => {
                 let value = self.parse_u64()?;
-                if value > i64::max_value() as u64 {
+                if value > i64::MAX as u64 {
                     return visitor.visit_i128(-1 - value as i128);
                 }
                 visitor.visit_i64(-1 - value as i64)
@@ -570,7 +856,7 @@ where
             }
             0x5b => {
                 let len = self.parse_u64()?;
-                if len > usize::max_value() as u64 {
+                if len > usize::MAX as u64 {
                     return Err(self.error(ErrorCode::LengthOutOfRange));
                 }
                 self.parse_bytes(len as usize, visitor)
@@ -594,7 +880,7 @@ where
             }
             0x7b => {
                 let len = self.parse_u64()?;
-                if len > usize::max_value() as u64 {
+                if len > usize::MAX as u64 {
                     return Err(self.error(ErrorCode::LengthOutOfRange));
                 }
                 self.parse_str(len as usize, visitor)
@@ -618,7 +904,7 @@ where
             }
             0x9b => {
                 let len = self.parse_u64()?;
-                if len > usize::max_value() as u64 {
+                if len > usize::MAX as u64 {
                     return Err(self.error(ErrorCode::LengthOutOfRange));
                 }
                 self.parse_array(len as usize, visitor)
@@ -642,7 +928,7 @@ where
             }
             0xbb => {
                 let len = self.parse_u64()?;
-                if len > usize::max_value() as u64 {
+                if len > usize::MAX as u64 {
                     return Err(self.error(ErrorCode::LengthOutOfRange));
                 }
                 self.parse_map(len as usize, visitor)
@@ -726,10 +1012,25 @@ where
     }
 
     #[inline]
-    fn deserialize_newtype_struct<V>(self, _name: &str, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, name: &str, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'de>,
     {
+        #[cfg(feature = "std")]
+        {
+            if name == crate::tags::CBOR_NEWTYPE_NAME {
+                if let Some(tag) = self.parse_tag()? {
+                    return self.recursion_checked(|de| {
+                        visitor.visit_seq(TagAccess {
+                            de,
+                            tag: Some(tag),
+                            value_done: false,
+                        })
+                    });
+                }
+            }
+        }
+        let _ = name;
         visitor.visit_newtype_struct(self)
     }
 
@@ -765,7 +1066,7 @@ where
                     }
                     0x9b => {
                         let len = self.parse_u64()?;
-                        if len > usize::max_value() as u64 {
+                        if len > usize::MAX as u64 {
                             return Err(self.error(ErrorCode::LengthOutOfRange));
                         }
                         self.parse_enum(len as usize, visitor)
@@ -850,8 +1151,47 @@ where
     }
 }
 
+/// Yields the tag, then the tagged value.
+#[cfg(feature = "std")]
+struct TagAccess<'a, R> {
+    de: &'a mut Deserializer<R>,
+    tag: Option<u64>,
+    value_done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<'de, 'a, R> de::SeqAccess<'de> for TagAccess<'a, R>
+where
+    R: Read<'de>,
+{
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        use serde::de::IntoDeserializer;
+
+        if let Some(tag) = self.tag.take() {
+            let tag: de::value::U64Deserializer<Error> = tag.into_deserializer();
+            return seed.deserialize(tag).map(Some);
+        }
+        if self.value_done {
+            return Ok(None);
+        }
+        self.value_done = true;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.tag.iter().count() + if self.value_done { 0 } else { 1 })
+    }
+}
+
 struct IndefiniteSeqAccess<'a, R> {
     de: &'a mut Deserializer<R>,
+    /// Elements read so far.
+    len: usize,
 }
 
 impl<'de, 'a, R> de::SeqAccess<'de> for IndefiniteSeqAccess<'a, R>
@@ -869,6 +1209,8 @@ where
             Some(_) => {}
             None => return Err(self.de.error(ErrorCode::EofWhileParsingArray)),
         }
+        self.len += 1;
+        self.de.check_collection_len(self.len)?;
 
         let value = seed.deserialize(&mut *self.de)?;
         Ok(Some(value))
@@ -884,12 +1226,90 @@ where
     }
 }
 
-struct MapAccess<'a, R> {
+/// Keys met so far in a map, `None` unless duplicate keys are checked.
+#[cfg(feature = "std")]
+type SeenKeys<'de> = Option<BTreeSet<MapKey<'de>>>;
+
+#[cfg(not(feature = "std"))]
+type SeenKeys<'de> = PhantomData<&'de ()>;
+
+/// A map key read ahead to be compared with the following ones, borrowed from the input if the
+/// reader allows it.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum MapKey<'de> {
+    Unsigned(u64),
+    /// The negative integer `-1 - n`.
+    Negative(u64),
+    Bytes(Cow<'de, [u8]>),
+    Text(Cow<'de, str>),
+}
+
+#[cfg(feature = "std")]
+impl<'de> de::Deserializer<'de> for MapKey<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        match self {
+            MapKey::Unsigned(n) => visitor.visit_u64(n),
+            MapKey::Negative(n) if n > i64::MAX as u64 => {
+                visitor.visit_i128(-1 - i128::from(n))
+            }
+            MapKey::Negative(n) => visitor.visit_i64(-1 - n as i64),
+            MapKey::Bytes(Cow::Borrowed(buf)) => visitor.visit_borrowed_bytes(buf),
+            MapKey::Bytes(Cow::Owned(buf)) => visitor.visit_byte_buf(buf),
+            MapKey::Text(Cow::Borrowed(s)) => visitor.visit_borrowed_str(s),
+            MapKey::Text(Cow::Owned(s)) => visitor.visit_string(s),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &str, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        enum identifier ignored_any
+    }
+}
+
+/// Outcome of reading ahead a map key.
+enum NextKey<'de> {
+    /// The key is not checked, it is deserialized from the input.
+    Read,
+    /// The key was parsed already.
+    #[cfg(feature = "std")]
+    Parsed(MapKey<'de>),
+    /// The key was a duplicate, its entry was skipped.
+    #[cfg(feature = "std")]
+    Skipped,
+    /// Never built, only uses the lifetime of the keys without `std`.
+    #[cfg(not(feature = "std"))]
+    #[allow(dead_code)]
+    Never(PhantomData<&'de ()>),
+}
+
+struct MapAccess<'de, 'a, R> {
     de: &'a mut Deserializer<R>,
     len: &'a mut usize,
+    seen: SeenKeys<'de>,
 }
 
-impl<'de, 'a, R> de::MapAccess<'de> for MapAccess<'a, R>
+impl<'de, 'a, R> de::MapAccess<'de> for MapAccess<'de, 'a, R>
 where
     R: Read<'de>,
 {
@@ -899,13 +1319,22 @@ where
     where
         K: de::DeserializeSeed<'de>,
     {
-        if *self.len == 0 {
-            return Ok(None);
+        loop {
+            if *self.len == 0 {
+                return Ok(None);
+            }
+            *self.len -= 1;
+
+            match self.de.next_map_key(&mut self.seen)? {
+                NextKey::Read => return seed.deserialize(&mut *self.de).map(Some),
+                #[cfg(feature = "std")]
+                NextKey::Parsed(key) => return seed.deserialize(key).map(Some),
+                #[cfg(feature = "std")]
+                NextKey::Skipped => {}
+                #[cfg(not(feature = "std"))]
+                NextKey::Never(_) => unreachable!(),
+            }
         }
-        *self.len -= 1;
-
-        let value = seed.deserialize(&mut *self.de)?;
-        Ok(Some(value))
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
@@ -920,7 +1349,7 @@ where
     }
 }
 
-impl<'de, 'a, R> MakeError for MapAccess<'a, R>
+impl<'de, 'a, R> MakeError for MapAccess<'de, 'a, R>
 where
     R: Read<'de>,
 {
@@ -929,11 +1358,14 @@ where
     }
 }
 
-struct IndefiniteMapAccess<'a, R> {
+struct IndefiniteMapAccess<'de, 'a, R> {
     de: &'a mut Deserializer<R>,
+    /// Entries read so far, skipped duplicates included.
+    len: usize,
+    seen: SeenKeys<'de>,
 }
 
-impl<'de, 'a, R> de::MapAccess<'de> for IndefiniteMapAccess<'a, R>
+impl<'de, 'a, R> de::MapAccess<'de> for IndefiniteMapAccess<'de, 'a, R>
 where
     R: Read<'de>,
 {
@@ -943,14 +1375,25 @@ where
     where
         K: de::DeserializeSeed<'de>,
     {
-        match self.de.peek()? {
-            Some(0xff) => return Ok(None),
-            Some(_) => {}
-            None => return Err(self.de.error(ErrorCode::EofWhileParsingMap)),
+        loop {
+            match self.de.peek()? {
+                Some(0xff) => return Ok(None),
+                Some(_) => {}
+                None => return Err(self.de.error(ErrorCode::EofWhileParsingMap)),
+            }
+            self.len += 1;
+            self.de.check_collection_len(self.len)?;
+
+            match self.de.next_map_key(&mut self.seen)? {
+                NextKey::Read => return seed.deserialize(&mut *self.de).map(Some),
+                #[cfg(feature = "std")]
+                NextKey::Parsed(key) => return seed.deserialize(key).map(Some),
+                #[cfg(feature = "std")]
+                NextKey::Skipped => {}
+                #[cfg(not(feature = "std"))]
+                NextKey::Never(_) => unreachable!(),
+            }
         }
-
-        let value = seed.deserialize(&mut *self.de)?;
-        Ok(Some(value))
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
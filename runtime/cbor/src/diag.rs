@@ -0,0 +1,303 @@
+//! CBOR diagnostic notation.
+//!
+//! `to_diag` renders CBOR as annotated hex, like the example in the crate documentation: each
+//! item on a line of its own, indented by its nesting, with the bytes encoding it followed by a
+//! comment describing it in [RFC 8949 diagnostic notation]. Any input is accepted, malformed input
+//! is rendered up to the first error, which is reported on the last line with the bytes left.
+//!
+//! ```rust
+//! // [1, "a"]
+//! let diag = serde_cbor::to_diag(b"\x82\x01\x61a");
+//! assert_eq!(
+//!     diag,
+//!     "82                                      # array(2)\n\
+//!     \x20  01                                   # unsigned(1)\n\
+//!     \x20  61                                   # text(1)\n\
+//!     \x20     61                                # \"a\"\n"
+//! );
+//! ```
+//!
+//! [RFC 8949 diagnostic notation]: https://www.rfc-editor.org/rfc/rfc8949.html#section-8
+
+use core::fmt::Write;
+use half::f16;
+
+use crate::error::{Error, ErrorCode, Result};
+use crate::value::Value;
+
+/// Nesting depth of arrays, maps and tags at which rendering stops.
+const RECURSION_LIMIT: usize = 128;
+
+/// Column the comments start at, unless the bytes of the line are longer.
+const COMMENT_COLUMN: usize = 40;
+
+/// Indentation of each nesting level.
+const INDENT: &str = "   ";
+
+/// At most this many of the bytes left after an error are shown.
+const MAX_ERROR_BYTES: usize = 64;
+
+/// Renders the CBOR items in `slice` as annotated hex, see the module documentation.
+pub fn to_diag(slice: &[u8]) -> String {
+    let mut printer = Printer {
+        input: slice,
+        pos: 0,
+        out: String::new(),
+    };
+    while printer.pos < slice.len() {
+        if let Err(e) = printer.item(0) {
+            let rest = &slice[(e.offset() as usize).min(slice.len())..];
+            let mut bytes = hex(&rest[..rest.len().min(MAX_ERROR_BYTES)]);
+            if rest.len() > MAX_ERROR_BYTES {
+                bytes.push_str("..");
+            }
+            printer.line(0, &bytes, &format!("error: {}", e));
+            break;
+        }
+    }
+    printer.out
+}
+
+/// Renders `value` encoded as CBOR, see `to_diag`.
+pub fn value_to_diag(value: &Value) -> Result<String> {
+    Ok(to_diag(&crate::to_vec(value)?))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(s, "{:02x}", byte).expect("writing to a String never fails");
+    }
+    s
+}
+
+/// Quotes `s` as a text string of diagnostic notation, escaping like JSON.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                write!(quoted, "\\u{:04x}", c as u32).expect("writing to a String never fails")
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn float(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "Infinity".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Infinity".to_string()
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+/// The argument of an item, `None` for indefinite lengths.
+type Argument = Option<u64>;
+
+struct Printer<'a> {
+    input: &'a [u8],
+    pos: usize,
+    out: String,
+}
+
+impl<'a> Printer<'a> {
+    /// Writes a line holding `bytes` and `comment`, indented by `depth`.
+    fn line(&mut self, depth: usize, bytes: &str, comment: &str) {
+        let start = self.out.len();
+        for _ in 0..depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(bytes);
+        let width = self.out.len() - start;
+        for _ in width..COMMENT_COLUMN.max(width + 1) {
+            self.out.push(' ');
+        }
+        self.out.push_str("# ");
+        self.out.push_str(comment);
+        self.out.push('\n');
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.input.len() - self.pos < len {
+            return Err(Error::syntax(
+                ErrorCode::EofWhileParsingValue,
+                self.input.len() as u64,
+            ));
+        }
+        let bytes = &self.input[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn peek(&self) -> Result<u8> {
+        match self.input.get(self.pos) {
+            Some(&byte) => Ok(byte),
+            None => Err(Error::syntax(
+                ErrorCode::EofWhileParsingValue,
+                self.pos as u64,
+            )),
+        }
+    }
+
+    /// Reads the initial byte of an item and its argument, returning them with the hex of their
+    /// encoding.
+    fn header(&mut self) -> Result<(u8, u8, Argument, String)> {
+        let start = self.pos;
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let len = match info {
+            0..=23 => 0,
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            31 if major >= 2 && major != 6 => {
+                return Ok((major, info, None, hex(&[initial])));
+            }
+            _ => return Err(Error::syntax(ErrorCode::UnassignedCode, start as u64)),
+        };
+        let value = if len == 0 {
+            u64::from(info)
+        } else {
+            self.take(len)?
+                .iter()
+                .fold(0, |acc, &b| acc << 8 | u64::from(b))
+        };
+        let mut bytes = hex(&[initial]);
+        if len > 0 {
+            bytes.push(' ');
+            bytes.push_str(&hex(&self.input[start + 1..self.pos]));
+        }
+        Ok((major, info, Some(value), bytes))
+    }
+
+    fn len(&self, value: u64) -> Result<usize> {
+        if value > (self.input.len() - self.pos) as u64 {
+            return Err(Error::syntax(ErrorCode::LengthOutOfRange, self.pos as u64));
+        }
+        Ok(value as usize)
+    }
+
+    /// Renders the break ending an indefinite length item, returns `false` if there is none.
+    fn parse_break(&mut self, depth: usize) -> Result<bool> {
+        if self.peek()? != 0xff {
+            return Ok(false);
+        }
+        self.pos += 1;
+        self.line(depth, "ff", "break");
+        Ok(true)
+    }
+
+    fn item(&mut self, depth: usize) -> Result<()> {
+        if depth > RECURSION_LIMIT {
+            return Err(Error::syntax(
+                ErrorCode::RecursionLimitExceeded,
+                self.pos as u64,
+            ));
+        }
+        let start = self.pos;
+        let (major, info, argument, bytes) = self.header()?;
+        let value = match argument {
+            Some(value) => value,
+            None => return self.indefinite(major, start, &bytes, depth),
+        };
+        match major {
+            0 => self.line(depth, &bytes, &format!("unsigned({})", value)),
+            1 => {
+                let n = -1 - i128::from(value);
+                self.line(depth, &bytes, &format!("negative({})", n));
+            }
+            2 | 3 => {
+                let kind = if major == 2 { "bytes" } else { "text" };
+                self.line(depth, &bytes, &format!("{}({})", kind, value));
+                let len = self.len(value)?;
+                let content = self.take(len)?;
+                if content.is_empty() {
+                    return Ok(());
+                }
+                let comment = match (major, core::str::from_utf8(content)) {
+                    (3, Ok(s)) => quote(s),
+                    (3, Err(_)) => format!("h'{}' (invalid UTF-8)", hex(content)),
+                    _ => format!("h'{}'", hex(content)),
+                };
+                self.line(depth + 1, &hex(content), &comment);
+            }
+            4 => {
+                self.line(depth, &bytes, &format!("array({})", value));
+                // every element takes at least one byte, so a huge length ends at the end of the
+                // input.
+                for _ in 0..value {
+                    self.item(depth + 1)?;
+                }
+            }
+            5 => {
+                self.line(depth, &bytes, &format!("map({})", value));
+                for _ in 0..value {
+                    self.item(depth + 1)?;
+                    self.item(depth + 1)?;
+                }
+            }
+            6 => {
+                self.line(depth, &bytes, &format!("tag({})", value));
+                self.item(depth + 1)?;
+            }
+            _ => {
+                let comment = match info {
+                    20 => "false".to_string(),
+                    21 => "true".to_string(),
+                    22 => "null".to_string(),
+                    23 => "undefined".to_string(),
+                    25 => format!("float({})", float(f64::from(f16::from_bits(value as u16)))),
+                    26 => format!("float({})", float(f64::from(f32::from_bits(value as u32)))),
+                    27 => format!("float({})", float(f64::from_bits(value))),
+                    _ => format!("simple({})", value),
+                };
+                self.line(depth, &bytes, &comment);
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders an item of indefinite length, whose initial byte was read already.
+    fn indefinite(&mut self, major: u8, start: usize, bytes: &str, depth: usize) -> Result<()> {
+        let kind = match major {
+            2 => "bytes",
+            3 => "text",
+            4 => "array",
+            5 => "map",
+            _ => return Err(Error::syntax(ErrorCode::UnexpectedCode, start as u64)),
+        };
+        self.line(depth, bytes, &format!("{}(*)", kind));
+        while !self.parse_break(depth + 1)? {
+            match major {
+                // chunks are definite length strings of the same type.
+                2 | 3 => {
+                    let initial = self.peek()?;
+                    if initial >> 5 != major || initial & 0x1f == 31 {
+                        return Err(Error::syntax(ErrorCode::UnexpectedCode, self.pos as u64));
+                    }
+                    self.item(depth + 1)?;
+                }
+                4 => self.item(depth + 1)?,
+                _ => {
+                    self.item(depth + 1)?;
+                    self.item(depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
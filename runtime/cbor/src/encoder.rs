@@ -0,0 +1,180 @@
+//! Push-style encoding of CBOR documents too large to hold in memory.
+//!
+//! `to_writer` needs the whole Rust value up front. An `Encoder` instead opens indefinite-length
+//! arrays and maps, takes their elements one at a time and closes them with a break byte, so
+//! only the element being encoded has to be in memory.
+//!
+//! ```rust
+//! use serde_cbor::Encoder;
+//!
+//! let mut encoder = Encoder::new(Vec::new());
+//! encoder.begin_array().unwrap();
+//! encoder.extend(1u8..4).unwrap();
+//! encoder.end().unwrap();
+//! assert_eq!(encoder.finish().unwrap(), b"\x9f\x01\x02\x03\xff");
+//! ```
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::ser::{Serializer, SerializerOptions, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Container {
+    Array,
+    /// A map, `key_pending` is set between a key and its value.
+    Map { key_pending: bool },
+}
+
+/// Encodes a CBOR document incrementally.
+pub struct Encoder<W> {
+    ser: Serializer<W>,
+    open: Vec<Container>,
+}
+
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    /// Creates an encoder writing to `writer`.
+    pub fn new(writer: W) -> Self {
+        Encoder {
+            ser: Serializer::new(writer),
+            open: Vec::new(),
+        }
+    }
+
+    /// Creates an encoder writing to `writer`, serializing elements with `options`.
+    ///
+    /// `options.self_describe` is honored by writing the self-describe tag right away.
+    pub fn with_options(writer: W, options: &SerializerOptions) -> Result<Self> {
+        let mut ser = Serializer::new_with_options(writer, options);
+        if options.self_describe {
+            ser.self_describe()?;
+        }
+        Ok(Encoder {
+            ser,
+            open: Vec::new(),
+        })
+    }
+
+    /// Number of arrays and maps opened and not yet ended.
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Begins an indefinite-length array, elements are added with `push` or `extend`.
+    pub fn begin_array(&mut self) -> Result<()> {
+        self.next_item();
+        self.ser.write_raw(&[4 << 5 | 31])?;
+        self.open.push(Container::Array);
+        Ok(())
+    }
+
+    /// Begins an indefinite-length map, entries are added with `push_entry` or `extend_entries`.
+    ///
+    /// Keys and values may also be pushed one by one with `push`, which lets a value be a
+    /// nested array or map.
+    pub fn begin_map(&mut self) -> Result<()> {
+        self.next_item();
+        self.ser.write_raw(&[5 << 5 | 31])?;
+        self.open.push(Container::Map { key_pending: false });
+        Ok(())
+    }
+
+    /// Ends the innermost array or map with a break byte.
+    pub fn end(&mut self) -> Result<()> {
+        match self.open.last() {
+            None => Err(Error::message("no array or map to end")),
+            Some(Container::Map { key_pending: true }) => {
+                Err(Error::message("map key without a value"))
+            }
+            Some(_) => {
+                self.ser.write_raw(&[0xff])?;
+                self.open.pop();
+                Ok(())
+            }
+        }
+    }
+
+    /// Encodes one element of the innermost array, or one key or value of the innermost map.
+    pub fn push<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.next_item();
+        value.serialize(&mut self.ser)
+    }
+
+    /// Encodes one entry of the innermost map.
+    pub fn push_entry<K, V>(&mut self, key: &K, value: &V) -> Result<()>
+    where
+        K: ?Sized + Serialize,
+        V: ?Sized + Serialize,
+    {
+        match self.open.last() {
+            Some(Container::Map { key_pending: false }) => {
+                key.serialize(&mut self.ser)?;
+                value.serialize(&mut self.ser)
+            }
+            _ => Err(Error::message("map entry outside of a map")),
+        }
+    }
+
+    /// Encodes every item of `iter` with `push`.
+    pub fn extend<I>(&mut self, iter: I) -> Result<()>
+    where
+        I: IntoIterator,
+        I::Item: Serialize,
+    {
+        for item in iter {
+            self.push(&item)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes every `(key, value)` pair of `iter` with `push_entry`.
+    pub fn extend_entries<I, K, V>(&mut self, iter: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Serialize,
+        V: Serialize,
+    {
+        for (key, value) in iter {
+            self.push_entry(&key, &value)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that every array and map was ended and returns the writer.
+    pub fn finish(self) -> Result<W> {
+        if !self.open.is_empty() {
+            return Err(Error::message("unterminated array or map"));
+        }
+        Ok(self.ser.into_inner())
+    }
+
+    /// Accounts for an item about to be written in the innermost container.
+    fn next_item(&mut self) {
+        if let Some(Container::Map { key_pending }) = self.open.last_mut() {
+            *key_pending = !*key_pending;
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<W> Encoder<W>
+where
+    W: Write,
+{
+    /// Encodes every item of `stream` with `push`, resolving to the encoder once the stream ends.
+    pub fn extend_stream<S>(self, stream: S) -> impl futures::Future<Item = Self, Error = Error>
+    where
+        S: futures::Stream<Error = Error>,
+        S::Item: Serialize,
+    {
+        use futures::Stream;
+
+        stream.fold(self, |mut encoder, item| encoder.push(&item).map(|()| encoder))
+    }
+}
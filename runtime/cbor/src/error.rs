@@ -4,6 +4,8 @@ use core::result;
 use serde::de;
 use serde::ser;
 #[cfg(feature = "std")]
+use filesys_errors::{CoreError, ErrorCode as CoreErrorCode};
+#[cfg(feature = "std")]
 use std::error;
 #[cfg(feature = "std")]
 use std::io;
@@ -143,7 +145,14 @@ impl Error {
             | ErrorCode::TrailingData
             | ErrorCode::ArrayTooShort
             | ErrorCode::ArrayTooLong
-            | ErrorCode::RecursionLimitExceeded => Category::Syntax,
+            | ErrorCode::RecursionLimitExceeded
+            | ErrorCode::CollectionLimitExceeded
+            | ErrorCode::SizeLimitExceeded => Category::Syntax,
+            #[cfg(feature = "std")]
+            ErrorCode::DuplicateKey
+            | ErrorCode::UncomparableKey
+            | ErrorCode::IndefiniteLength
+            | ErrorCode::Float => Category::Data,
         }
     }
 
@@ -200,6 +209,16 @@ impl error::Error for Error {
     }
 }
 
+#[cfg(feature = "std")]
+impl CoreError for Error {
+    fn code(&self) -> CoreErrorCode {
+        match self.classify() {
+            Category::Io => CoreErrorCode::Io,
+            Category::Syntax | Category::Data | Category::Eof => CoreErrorCode::Decode,
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.0.offset == 0 {
@@ -281,6 +300,16 @@ pub(crate) enum ErrorCode {
     ArrayTooShort,
     ArrayTooLong,
     RecursionLimitExceeded,
+    CollectionLimitExceeded,
+    SizeLimitExceeded,
+    #[cfg(feature = "std")]
+    DuplicateKey,
+    #[cfg(feature = "std")]
+    UncomparableKey,
+    #[cfg(feature = "std")]
+    IndefiniteLength,
+    #[cfg(feature = "std")]
+    Float,
 }
 
 impl fmt::Display for ErrorCode {
@@ -306,6 +335,16 @@ impl fmt::Display for ErrorCode {
             ErrorCode::ArrayTooShort => f.write_str("array too short"),
             ErrorCode::ArrayTooLong => f.write_str("array too long"),
             ErrorCode::RecursionLimitExceeded => f.write_str("recursion limit exceeded"),
+            ErrorCode::CollectionLimitExceeded => f.write_str("collection length limit exceeded"),
+            ErrorCode::SizeLimitExceeded => f.write_str("input size limit exceeded"),
+            #[cfg(feature = "std")]
+            ErrorCode::DuplicateKey => f.write_str("duplicate map key"),
+            #[cfg(feature = "std")]
+            ErrorCode::UncomparableKey => f.write_str("map key cannot be checked for duplicates"),
+            #[cfg(feature = "std")]
+            ErrorCode::IndefiniteLength => f.write_str("indefinite length in canonical CBOR"),
+            #[cfg(feature = "std")]
+            ErrorCode::Float => f.write_str("float in canonical CBOR"),
         }
     }
 }
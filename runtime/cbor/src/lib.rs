@@ -24,7 +24,8 @@
 //! }
 //! ```
 //!
-//! The CBOR encoded object with comments in hexadecimal notation looks like
+//! The CBOR encoded object with comments in hexadecimal notation, as rendered by `to_diag`, looks
+//! like
 //!
 //! ```cbor
 //! a5                                      # map(5)
@@ -169,9 +170,9 @@
 //! While Serde CBOR strives to support all features of Serde and CBOR
 //! there are a few limitations.
 //!
-//! * [Tags] are ignored during deserialization and can't be emitted during
-//!     serialization. This is because Serde has no concept of tagged
-//!     values. See:&nbsp;[#3]
+//! * [Tags] are ignored during deserialization unless the value is read as a
+//!     `tags::Tagged`, which is also how they are emitted during serialization.
+//!     This is because Serde has no concept of tagged values. See:&nbsp;[#3]
 //! * Unknown [simple values] cause an `UnassignedCode` error.
 //!     The simple values *False* and *True* are recognized and parsed as bool.
 //!     *Null* and *Undefined* are both deserialized as *unit*.
@@ -194,10 +195,18 @@
 #[cfg(all(not(feature = "std"), test))]
 extern crate std;
 
+#[cfg(feature = "std")]
+pub mod canonical;
 pub mod de;
+#[cfg(feature = "std")]
+pub mod diag;
+#[cfg(feature = "std")]
+pub mod encoder;
 pub mod error;
 mod read;
 pub mod ser;
+#[cfg(feature = "std")]
+pub mod tags;
 mod write;
 
 #[cfg(feature = "std")]
@@ -207,12 +216,24 @@ pub mod value;
 pub use crate::de::{from_mut_slice, from_slice_with_scratch, Deserializer, StreamDeserializer};
 #[doc(inline)]
 #[cfg(feature = "std")]
-pub use crate::de::{from_reader, from_slice};
+pub use crate::de::{from_reader, from_slice, DeserializerOptions, DuplicateKeys};
+
+#[doc(inline)]
+#[cfg(feature = "std")]
+pub use crate::canonical::to_vec_canonical;
+
+#[doc(inline)]
+#[cfg(feature = "std")]
+pub use crate::diag::{to_diag, value_to_diag};
+
+#[doc(inline)]
+#[cfg(feature = "std")]
+pub use crate::encoder::Encoder;
 
 #[doc(inline)]
 #[cfg(feature = "std")]
 pub use crate::ser::{to_vec, to_vec_with_options, to_writer};
-pub use crate::ser::{Serializer, SerializerOptions};
+pub use crate::ser::{to_slice, Serializer, SerializerOptions};
 #[doc(inline)]
 #[cfg(feature = "std")]
 pub use crate::value::{from_value, to_value, ObjectKey, Value};
@@ -1,6 +1,8 @@
 #[cfg(feature = "std")]
 use core::cmp;
 use core::mem;
+#[cfg(feature = "std")]
+use core::ops::Range;
 
 #[cfg(feature = "std")]
 use std::io::{self, Read as StdRead};
@@ -282,10 +284,16 @@ where
 }
 
 /// A CBOR input source that reads from a slice of bytes.
+///
+/// Byte and text strings are borrowed from the slice. Only indefinite length strings of more than
+/// one chunk are copied, to join their chunks.
 #[cfg(feature = "std")]
 pub struct SliceRead<'a> {
     slice: &'a [u8],
     scratch: Vec<u8>,
+    /// The range of the slice holding the buffer, while it is a single chunk still. The scratch
+    /// buffer is used once a second chunk is read.
+    chunk: Option<Range<usize>>,
     index: usize,
 }
 
@@ -296,6 +304,7 @@ impl<'a> SliceRead<'a> {
         SliceRead {
             slice,
             scratch: vec![],
+            chunk: None,
             index: 0,
         }
     }
@@ -346,12 +355,19 @@ impl<'a> Read<'a> for SliceRead<'a> {
 
     fn clear_buffer(&mut self) {
         self.scratch.clear();
+        self.chunk = None;
     }
 
     fn read_to_buffer(&mut self, n: usize) -> Result<()> {
         let end = self.end(n)?;
-        let slice = &self.slice[self.index..end];
-        self.scratch.extend_from_slice(slice);
+        if self.chunk.is_none() && self.scratch.is_empty() {
+            self.chunk = Some(self.index..end);
+        } else {
+            if let Some(chunk) = self.chunk.take() {
+                self.scratch.extend_from_slice(&self.slice[chunk]);
+            }
+            self.scratch.extend_from_slice(&self.slice[self.index..end]);
+        }
         self.index = end;
 
         Ok(())
@@ -366,7 +382,10 @@ impl<'a> Read<'a> for SliceRead<'a> {
     }
 
     fn take_buffer<'b>(&'b mut self) -> EitherLifetime<'b, 'a> {
-        EitherLifetime::Short(&self.scratch)
+        match self.chunk.take() {
+            Some(chunk) => EitherLifetime::Long(&self.slice[chunk]),
+            None => EitherLifetime::Short(&self.scratch),
+        }
     }
 
     #[inline]
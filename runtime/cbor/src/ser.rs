@@ -113,6 +113,20 @@ where
     Ok(vec)
 }
 
+/// Serializes a value into a mutable byte slice and returns the number of bytes written.
+///
+/// Fails with an error for which `Error::is_scratch_too_small` is true if the value does not fit
+/// in `slice`. Nothing is allocated, so a caller serializing many small values can reuse one
+/// buffer for all of them.
+pub fn to_slice<T>(value: &T, slice: &mut [u8]) -> Result<usize>
+where
+    T: ser::Serialize,
+{
+    let mut ser = Serializer::new(SliceWrite::new(slice));
+    value.serialize(&mut ser)?;
+    Ok(ser.into_inner().bytes_written())
+}
+
 /// Serializes a value to a vector.
 #[cfg(feature = "std")]
 pub fn to_vec_with_options<T>(value: &T, options: &SerializerOptions) -> Result<Vec<u8>>
@@ -267,12 +281,38 @@ where
         self.writer.write_all(&buf).map_err(|e| e.into())
     }
 
+    /// Serializes `value` preceded by the CBOR tag `tag`.
+    ///
+    /// See also `tags::Tagged`, which does the same through the serde data model.
+    #[inline]
+    pub fn serialize_tagged<T>(&mut self, tag: u64, value: &T) -> Result<()>
+    where
+        T: ?Sized + ser::Serialize,
+    {
+        self.write_u64(6, tag)?;
+        value.serialize(self)
+    }
+
+    /// Writes `buf` as is, used by `Encoder` for headers and break bytes.
+    #[inline]
+    pub(crate) fn write_raw(&mut self, buf: &[u8]) -> Result<()> {
+        self.writer.write_all(buf).map_err(|e| e.into())
+    }
+
     /// Unwrap the `Writer` from the `Serializer`.
     #[inline]
     pub fn into_inner(self) -> W {
         self.writer
     }
 
+    /// Replaces the `Writer` of the `Serializer` with `writer` and returns the old one.
+    ///
+    /// The options of the serializer are kept, so one serializer can be reused for many values.
+    #[inline]
+    pub fn reset(&mut self, writer: W) -> W {
+        core::mem::replace(&mut self.writer, writer)
+    }
+
     #[inline]
     fn write_u8(&mut self, major: u8, value: u8) -> Result<()> {
         if value <= 0x17 {
@@ -286,7 +326,7 @@ where
 
     #[inline]
     fn write_u16(&mut self, major: u8, value: u16) -> Result<()> {
-        if value <= u16::from(u8::max_value()) {
+        if value <= u16::from(u8::MAX) {
             self.write_u8(major, value as u8)
         } else {
             let mut buf = [major << 5 | 25, 0, 0];
@@ -297,7 +337,7 @@ where
 
     #[inline]
     fn write_u32(&mut self, major: u8, value: u32) -> Result<()> {
-        if value <= u32::from(u16::max_value()) {
+        if value <= u32::from(u16::MAX) {
             self.write_u16(major, value as u16)
         } else {
             let mut buf = [major << 5 | 26, 0, 0, 0, 0];
@@ -308,7 +348,7 @@ where
 
     #[inline]
     fn write_u64(&mut self, major: u8, value: u64) -> Result<()> {
-        if value <= u64::from(u32::max_value()) {
+        if value <= u64::from(u32::MAX) {
             self.write_u32(major, value as u32)
         } else {
             let mut buf = [major << 5 | 27, 0, 0, 0, 0, 0, 0, 0, 0];
@@ -403,12 +443,12 @@ where
     #[inline]
     fn serialize_i128(self, value: i128) -> Result<()> {
         if value < 0 {
-            if -(value + 1) > u64::max_value() as i128 {
+            if -(value + 1) > u64::MAX as i128 {
                 return Err(Error::message("The number can't be stored in CBOR"));
             }
             self.write_u64(1, -(value + 1) as u64)
         } else {
-            if value > u64::max_value() as i128 {
+            if value > u64::MAX as i128 {
                 return Err(Error::message("The number can't be stored in CBOR"));
             }
             self.write_u64(0, value as u64)
@@ -437,7 +477,7 @@ where
 
     #[inline]
     fn serialize_u128(self, value: u128) -> Result<()> {
-        if value > u64::max_value() as u128 {
+        if value > u64::MAX as u128 {
             return Err(Error::message("The number can't be stored in CBOR"));
         }
         self.write_u64(0, value as u64)
@@ -537,10 +577,19 @@ where
     }
 
     #[inline]
-    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<()>
     where
         T: ?Sized + ser::Serialize,
     {
+        #[cfg(feature = "std")]
+        {
+            if name == crate::tags::CBOR_NEWTYPE_NAME {
+                if let Some(tag) = crate::tags::take_tag() {
+                    return self.serialize_tagged(tag, value);
+                }
+            }
+        }
+        let _ = name;
         value.serialize(self)
     }
 
@@ -0,0 +1,103 @@
+//! Support for CBOR tags.
+//!
+//! Serde has no concept of tagged values, so tags are passed through a newtype struct with a
+//! reserved name that only the CBOR serializer and deserializer understand. Other formats see a
+//! plain newtype struct and ignore the tag.
+
+use core::cell::Cell;
+use core::fmt;
+use core::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+/// Name of the newtype struct a tagged value is passed through.
+pub(crate) const CBOR_NEWTYPE_NAME: &str = "\0cbor_tag";
+
+/// Tag the CBOR IPLD codec uses for links.
+pub const CID_TAG: u64 = 42;
+
+/// A value with an optional CBOR tag.
+///
+/// Serializing a `Tagged` emits the tag before the value. Deserializing accepts both tagged and
+/// untagged values, `tag` is `None` for the latter.
+///
+/// ```rust
+/// use serde_bytes::ByteBuf;
+/// use serde_cbor::tags::{Tagged, CID_TAG};
+///
+/// let link = Tagged::new(Some(CID_TAG), ByteBuf::from(vec![0, 1, 2]));
+/// let bytes = serde_cbor::to_vec(&link).unwrap();
+/// assert_eq!(bytes, b"\xd8\x2a\x43\x00\x01\x02");
+/// let decoded: Tagged<ByteBuf> = serde_cbor::from_slice(&bytes).unwrap();
+/// assert_eq!(decoded, link);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tagged<T> {
+    /// The tag, if any.
+    pub tag: Option<u64>,
+    /// The tagged value.
+    pub value: T,
+}
+
+impl<T> Tagged<T> {
+    /// Creates a new tagged value.
+    pub fn new(tag: Option<u64>, value: T) -> Self {
+        Tagged { tag, value }
+    }
+}
+
+thread_local!(static CBOR_TAG: Cell<Option<u64>> = Cell::new(None));
+
+/// Takes the tag set by the `Tagged` being serialized, if any.
+pub(crate) fn take_tag() -> Option<u64> {
+    CBOR_TAG.with(|tag| tag.replace(None))
+}
+
+impl<T: Serialize> Serialize for Tagged<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.tag {
+            None => self.value.serialize(serializer),
+            Some(tag) => {
+                CBOR_TAG.with(|cell| cell.set(Some(tag)));
+                let result = serializer.serialize_newtype_struct(CBOR_NEWTYPE_NAME, &self.value);
+                // a serializer other than CBOR never takes the tag.
+                take_tag();
+                result
+            }
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Tagged<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TaggedVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for TaggedVisitor<T> {
+            type Value = Tagged<T>;
+
+            fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+                fmt.write_str("a tagged value")
+            }
+
+            fn visit_newtype_struct<D: Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                T::deserialize(deserializer).map(|value| Tagged::new(None, value))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let tag = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let value = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                Ok(Tagged::new(Some(tag), value))
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(CBOR_NEWTYPE_NAME, TaggedVisitor(PhantomData))
+    }
+}
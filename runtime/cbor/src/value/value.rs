@@ -7,6 +7,8 @@ use std::fmt;
 use serde::de;
 use serde::ser;
 
+use crate::error::{Error, ErrorCode};
+
 /// An enum over all possible CBOR types.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value {
@@ -210,6 +212,28 @@ impl Value {
             None
         }
     }
+
+    /// Returns the canonical form of this value, in which non-negative integers are `U64`.
+    ///
+    /// Fails if the value contains a float. The order of map keys is fixed by `ObjectKey`, use
+    /// `to_vec_canonical` to encode the result with the canonical key order.
+    pub fn canonicalize(&self) -> Result<Value, Error> {
+        Ok(match *self {
+            Value::I64(n) if n >= 0 => Value::U64(n as u64),
+            Value::Array(ref v) => Value::Array(
+                v.iter()
+                    .map(Value::canonicalize)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(ref v) => Value::Object(
+                v.iter()
+                    .map(|(key, value)| Ok((key.clone(), value.canonicalize()?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            Value::F64(_) => return Err(Error::syntax(ErrorCode::Float, 0)),
+            ref v => v.clone(),
+        })
+    }
 }
 
 impl<'de> de::Deserialize<'de> for Value {
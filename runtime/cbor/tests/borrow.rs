@@ -0,0 +1,96 @@
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(feature = "std")]
+mod std_tests {
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
+
+    use serde_cbor::de::{DeserializerOptions, DuplicateKeys};
+    use serde_cbor::{from_slice, to_vec};
+
+    /// Whether `part` lies within `whole`.
+    fn borrowed_from(part: &[u8], whole: &[u8]) -> bool {
+        let range = whole.as_ptr_range();
+        range.start <= part.as_ptr() && part.as_ptr_range().end <= range.end
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Block<'a> {
+        #[serde(borrow)]
+        name: Cow<'a, str>,
+        #[serde(serialize_with = "serde_bytes::serialize")]
+        payload: &'a [u8],
+        #[serde(borrow)]
+        extra: Bytes<'a>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Bytes<'a>(#[serde(borrow, serialize_with = "serde_bytes::serialize")] Cow<'a, [u8]>);
+
+    #[test]
+    fn test_block_payload() {
+        let payload = vec![0xab; 2 << 20];
+        let block = Block {
+            name: Cow::Borrowed("block"),
+            payload: &payload,
+            extra: Bytes(Cow::Borrowed(b"extra")),
+        };
+        let bytes = to_vec(&block).unwrap();
+
+        let decoded: Block = from_slice(&bytes).unwrap();
+        assert_eq!(decoded, block);
+        assert!(borrowed_from(decoded.payload, &bytes));
+        match (&decoded.name, &decoded.extra.0) {
+            (Cow::Borrowed(name), Cow::Borrowed(extra)) => {
+                assert!(borrowed_from(name.as_bytes(), &bytes));
+                assert!(borrowed_from(extra, &bytes));
+            }
+            _ => panic!("not borrowed: {:?}", decoded),
+        }
+    }
+
+    #[test]
+    fn test_indefinite_strings() {
+        // (_ "ab")
+        let bytes = b"\x7f\x62ab\xff";
+        let s: &str = from_slice(bytes).unwrap();
+        assert_eq!(s, "ab");
+        assert!(borrowed_from(s.as_bytes(), bytes));
+
+        // (_ h'01', h'0203'), more than one chunk cannot be borrowed.
+        let bytes = b"\x5f\x41\x01\x42\x02\x03\xff";
+        let buf: Bytes = from_slice(bytes).unwrap();
+        assert_eq!(buf.0, Cow::Owned::<[u8]>(vec![1, 2, 3]));
+        assert!(from_slice::<&[u8]>(bytes).is_err());
+
+        // the chunk of the previous string is not kept.
+        // [(_ "a", "b"), (_ "c")]
+        let bytes = b"\x82\x7f\x61a\x61b\xff\x7f\x61c\xff";
+        let value: (String, &str) = from_slice(bytes).unwrap();
+        assert_eq!(value, ("ab".to_string(), "c"));
+    }
+
+    #[test]
+    fn test_map_keys() {
+        // {"a": 1, "b": 2, "a": 3}
+        let bytes = b"\xa3\x61a\x01\x61b\x02\x61a\x03";
+        let options = DeserializerOptions {
+            duplicate_keys: DuplicateKeys::FirstWins,
+            ..Default::default()
+        };
+        let map: BTreeMap<&str, u8> = options.from_slice(bytes).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], 1);
+        assert!(map.keys().all(|key| borrowed_from(key.as_bytes(), bytes)));
+
+        let options = DeserializerOptions {
+            duplicate_keys: DuplicateKeys::Error,
+            ..Default::default()
+        };
+        assert!(options.from_slice::<BTreeMap<&str, u8>>(bytes).is_err());
+        // {"a": 1, "b": 2}
+        let map: BTreeMap<&str, u8> = options.from_slice(b"\xa2\x61a\x01\x61b\x02").unwrap();
+        assert_eq!(map["b"], 2);
+    }
+}
@@ -1,6 +1,10 @@
 #[cfg(feature = "std")]
 mod std_tests {
-    use serde_cbor::ObjectKey;
+    use serde_bytes::ByteBuf;
+    use serde_cbor::canonical::canonicalize_slice;
+    use serde_cbor::tags::{Tagged, CID_TAG};
+    use serde_cbor::{to_vec_canonical, ObjectKey, Value};
+    use std::collections::BTreeMap;
 
     #[test]
     fn integer_canonical_sort_order() {
@@ -89,4 +93,66 @@ mod std_tests {
 
         assert_eq!(expected, sorted);
     }
+
+    #[test]
+    fn to_vec_canonical_sorts_keys_by_length() {
+        let mut map = BTreeMap::new();
+        map.insert(ObjectKey::Integer(1000), Value::U64(1));
+        map.insert(ObjectKey::String("a".into()), Value::U64(2));
+        map.insert(ObjectKey::Integer(-1), Value::U64(3));
+
+        let bytes = to_vec_canonical(&Value::Object(map)).unwrap();
+
+        assert_eq!(bytes, b"\xa3\x20\x03\x61a\x02\x19\x03\xe8\x01");
+    }
+
+    #[test]
+    fn to_vec_canonical_rejects_floats() {
+        assert!(to_vec_canonical(&1.5f64).unwrap_err().is_data());
+        assert!(to_vec_canonical(&vec![Value::F64(0.0)]).is_err());
+    }
+
+    #[test]
+    fn to_vec_canonical_keeps_tags() {
+        let link = Tagged::new(Some(CID_TAG), ByteBuf::from(vec![0, 1]));
+
+        let bytes = to_vec_canonical(&link).unwrap();
+
+        assert_eq!(bytes, serde_cbor::to_vec(&link).unwrap());
+    }
+
+    #[test]
+    fn canonicalize_slice_rejects_indefinite_lengths() {
+        let err = canonicalize_slice(b"\x9f\x01\xff").unwrap_err();
+        assert!(err.is_data());
+        assert_eq!(err.offset(), 0);
+        assert!(canonicalize_slice(b"\xbf\x61a\x01\xff").is_err());
+    }
+
+    #[test]
+    fn canonicalize_slice_shortens_encodings() {
+        // 1 encoded in two bytes, a map with keys out of order.
+        assert_eq!(canonicalize_slice(b"\x18\x01").unwrap(), b"\x01");
+        assert_eq!(
+            canonicalize_slice(b"\xa2\x62bb\x01\x61a\x02").unwrap(),
+            b"\xa2\x61a\x02\x62bb\x01"
+        );
+    }
+
+    #[test]
+    fn canonicalize_slice_rejects_duplicate_keys() {
+        assert!(canonicalize_slice(b"\xa2\x61a\x01\x61a\x02").is_err());
+        assert!(canonicalize_slice(b"\x01\x02").is_err());
+    }
+
+    #[test]
+    fn value_canonicalize() {
+        let value = Value::Array(vec![Value::I64(1), Value::I64(-1)]);
+
+        assert_eq!(
+            value.canonicalize().unwrap(),
+            Value::Array(vec![Value::U64(1), Value::I64(-1)])
+        );
+        assert!(Value::Array(vec![Value::F64(1.0)]).canonicalize().is_err());
+    }
 }
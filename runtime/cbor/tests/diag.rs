@@ -0,0 +1,181 @@
+#[cfg(feature = "std")]
+mod std_tests {
+    use serde_cbor::{to_diag, value_to_diag, Value};
+
+    // The example of the crate documentation.
+    const PERSON: &str = "\
+a5                                      # map(5)
+   69                                   # text(9)
+      46697273744e616d65                # \"FirstName\"
+   64                                   # text(4)
+      4a6f686e                          # \"John\"
+   68                                   # text(8)
+      4c6173744e616d65                  # \"LastName\"
+   63                                   # text(3)
+      446f65                            # \"Doe\"
+   63                                   # text(3)
+      416765                            # \"Age\"
+   18 2b                                # unsigned(43)
+   67                                   # text(7)
+      41646472657373                    # \"Address\"
+   a3                                   # map(3)
+      66                                # text(6)
+         537472656574                   # \"Street\"
+      71                                # text(17)
+         446f776e696e6720537472656574203130 # \"Downing Street 10\"
+      64                                # text(4)
+         43697479                       # \"City\"
+      66                                # text(6)
+         4c6f6e646f6e                   # \"London\"
+      67                                # text(7)
+         436f756e747279                 # \"Country\"
+      6d                                # text(13)
+         4772656174204272697461696e     # \"Great Britain\"
+   6c                                   # text(12)
+      50686f6e654e756d62657273          # \"PhoneNumbers\"
+   82                                   # array(2)
+      6b                                # text(11)
+         2b34342031323334353637         # \"+44 1234567\"
+      6b                                # text(11)
+         2b34342032333435363738         # \"+44 2345678\"
+";
+
+    /// Decodes the hex before the comments of annotated hex.
+    fn from_diag(diag: &str) -> Vec<u8> {
+        let hex: String = diag
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap().split_whitespace())
+            .collect();
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// The comments of annotated hex.
+    fn comments(diag: &str) -> Vec<&str> {
+        diag.lines()
+            .map(|line| line.splitn(2, "# ").nth(1).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_crate_example() {
+        let bytes = from_diag(PERSON);
+        assert_eq!(bytes.len(), 141);
+        assert_eq!(to_diag(&bytes), PERSON);
+    }
+
+    #[test]
+    fn test_scalars() {
+        let diag = to_diag(b"\x17\x1b\xff\xff\xff\xff\xff\xff\xff\xff\x20\x3b\xff\xff\xff\xff\xff\xff\xff\xff\xf4\xf5\xf6\xf7\xf0\xf8\x20");
+        assert_eq!(
+            comments(&diag),
+            vec![
+                "unsigned(23)",
+                "unsigned(18446744073709551615)",
+                "negative(-1)",
+                "negative(-18446744073709551616)",
+                "false",
+                "true",
+                "null",
+                "undefined",
+                "simple(16)",
+                "simple(32)",
+            ]
+        );
+
+        let diag = to_diag(
+            b"\xf9\x3e\x00\xfa\x7f\x80\x00\x00\xfb\x3f\xf1\x99\x99\x99\x99\x99\x9a\xf9\x7e\x00",
+        );
+        assert_eq!(
+            comments(&diag),
+            vec!["float(1.5)", "float(Infinity)", "float(1.1)", "float(NaN)"]
+        );
+        assert!(diag.starts_with("f9 3e00 "));
+    }
+
+    #[test]
+    fn test_strings() {
+        // h'0102', "a\"\n", h'', ""
+        let diag = to_diag(b"\x42\x01\x02\x63a\"\n\x40\x60");
+        assert_eq!(
+            comments(&diag),
+            vec![
+                "bytes(2)",
+                "h'0102'",
+                "text(3)",
+                "\"a\\\"\\n\"",
+                "bytes(0)",
+                "text(0)"
+            ]
+        );
+        assert_eq!(
+            comments(&to_diag(b"\x61\xff")),
+            vec!["text(1)", "h'ff' (invalid UTF-8)"]
+        );
+    }
+
+    #[test]
+    fn test_indefinite_and_tags() {
+        // 42(h'00'), [_ 1, {_ "a": (_ "b", "c")}]
+        let bytes = b"\xd8\x2a\x41\x00\x9f\x01\xbf\x61a\x7f\x61b\x61c\xff\xff\xff";
+        assert_eq!(
+            to_diag(bytes),
+            "\
+d8 2a                                   # tag(42)
+   41                                   # bytes(1)
+      00                                # h'00'
+9f                                      # array(*)
+   01                                   # unsigned(1)
+   bf                                   # map(*)
+      61                                # text(1)
+         61                             # \"a\"
+      7f                                # text(*)
+         61                             # text(1)
+            62                          # \"b\"
+         61                             # text(1)
+            63                          # \"c\"
+         ff                             # break
+      ff                                # break
+   ff                                   # break
+"
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        // [1, 2, then the input ends.
+        let diag = to_diag(b"\x83\x01\x02");
+        assert_eq!(diag.lines().count(), 4);
+        assert!(diag
+            .lines()
+            .last()
+            .unwrap()
+            .ends_with("# error: EOF while parsing a value at offset 3"));
+
+        // a text string of 5 bytes holding 2.
+        let diag = to_diag(b"\x65ab");
+        assert!(diag.lines().last().unwrap().starts_with("6162 "));
+
+        // a break outside of an indefinite length item.
+        let diag = to_diag(b"\x01\xff\x02");
+        assert_eq!(diag.lines().count(), 2);
+        assert!(diag.lines().last().unwrap().starts_with("ff02 "));
+
+        // a chunk of another type in an indefinite byte string.
+        assert!(to_diag(b"\x5f\x61a\xff").contains("error: unexpected code"));
+
+        let deep = vec![0x81; 1000];
+        assert!(to_diag(&deep).contains("error: recursion limit exceeded"));
+    }
+
+    #[test]
+    fn test_value() {
+        let value = Value::Array(vec![Value::U64(1), Value::String("a".to_string())]);
+        assert_eq!(
+            value_to_diag(&value).unwrap(),
+            to_diag(&serde_cbor::to_vec(&value).unwrap())
+        );
+    }
+}
@@ -0,0 +1,103 @@
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(feature = "std")]
+mod std_tests {
+    use std::collections::BTreeMap;
+
+    use serde::de::IgnoredAny;
+    use serde_cbor::error::Category;
+    use serde_cbor::{from_slice, DeserializerOptions, DuplicateKeys};
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    fn options(duplicate_keys: DuplicateKeys) -> DeserializerOptions {
+        DeserializerOptions {
+            duplicate_keys,
+            ..Default::default()
+        }
+    }
+
+    // {"a": 1, "b": 2, "a": 3}
+    const DUPLICATE_TEXT: &[u8] = b"\xa3\x61a\x01\x61b\x02\x61a\x03";
+
+    #[test]
+    fn test_last_wins_by_default() {
+        let map: BTreeMap<String, u8> = from_slice(DUPLICATE_TEXT).unwrap();
+        assert_eq!(map["a"], 3);
+        assert_eq!(map["b"], 2);
+    }
+
+    #[test]
+    fn test_error() {
+        let err = options(DuplicateKeys::Error)
+            .from_slice::<BTreeMap<String, u8>>(DUPLICATE_TEXT)
+            .unwrap_err();
+        assert_eq!(err.classify(), Category::Data);
+        assert_eq!(err.offset(), 9);
+    }
+
+    #[test]
+    fn test_first_wins() {
+        let map: BTreeMap<String, u8> = options(DuplicateKeys::FirstWins)
+            .from_slice(DUPLICATE_TEXT)
+            .unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["a"], 1);
+        assert_eq!(map["b"], 2);
+    }
+
+    #[test]
+    fn test_first_wins_struct() {
+        // {"x": 1, "y": 2, "x": [3, 4]}
+        let bytes = b"\xa3\x61x\x01\x61y\x02\x61x\x82\x03\x04";
+        assert!(from_slice::<Point>(bytes).is_err());
+        let point: Point = options(DuplicateKeys::FirstWins).from_slice(bytes).unwrap();
+        assert_eq!(point, Point { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn test_indefinite_map() {
+        // {_ -1: "a", 0: "b", -1: "c"}
+        let bytes = b"\xbf\x20\x61a\x00\x61b\x20\x61c\xff";
+        let map: BTreeMap<i64, String> = options(DuplicateKeys::FirstWins)
+            .from_slice(bytes)
+            .unwrap();
+        assert_eq!(map[&-1], "a");
+        assert_eq!(map[&0], "b");
+        assert!(options(DuplicateKeys::Error)
+            .from_slice::<BTreeMap<i64, String>>(bytes)
+            .is_err());
+    }
+
+    #[test]
+    fn test_uncomparable_keys() {
+        // {(_ "a"): 1, 1.5: 2, 0("a"): 3}
+        let keys: [&[u8]; 3] = [b"\x7f\x61a\xff", b"\xf9\x3e\x00", b"\xc0\x61a"];
+        for key in keys.iter() {
+            let bytes = [b"\xa1", *key, b"\x01"].concat();
+            let err = options(DuplicateKeys::Error)
+                .from_slice::<IgnoredAny>(&bytes)
+                .unwrap_err();
+            assert_eq!(err.classify(), Category::Data);
+            assert_eq!(err.offset(), 1);
+            assert!(options(DuplicateKeys::FirstWins)
+                .from_slice::<IgnoredAny>(&bytes)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_nested_maps_are_separate() {
+        // {"a": {"a": 1}}
+        let bytes = b"\xa1\x61a\xa1\x61a\x01";
+        let map: BTreeMap<String, BTreeMap<String, u8>> = options(DuplicateKeys::Error)
+            .from_slice(bytes)
+            .unwrap();
+        assert_eq!(map["a"]["a"], 1);
+    }
+}
@@ -0,0 +1,46 @@
+#[cfg(feature = "std")]
+mod std_tests {
+    use std::collections::BTreeMap;
+
+    use serde_cbor::{from_slice, Encoder, Value};
+
+    #[test]
+    fn test_nested() {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.begin_map().unwrap();
+        encoder.push_entry("a", &1u8).unwrap();
+        encoder.push("b").unwrap();
+        encoder.begin_array().unwrap();
+        encoder.extend(vec!["x", "y"]).unwrap();
+        encoder.end().unwrap();
+        encoder.extend_entries(vec![("c", true)]).unwrap();
+        encoder.end().unwrap();
+        assert_eq!(encoder.depth(), 0);
+
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(bytes, b"\xbfaa\x01ab\x9faxay\xffac\xf5\xff".to_vec());
+
+        let value: BTreeMap<String, Value> = from_slice(&bytes).unwrap();
+        assert_eq!(value["a"], Value::U64(1));
+        assert_eq!(
+            value["b"],
+            Value::Array(vec![Value::String("x".into()), Value::String("y".into())])
+        );
+    }
+
+    #[test]
+    fn test_misuse() {
+        let mut encoder = Encoder::new(Vec::new());
+        assert!(encoder.end().is_err());
+        assert!(encoder.push_entry("a", &1u8).is_err());
+
+        encoder.begin_map().unwrap();
+        encoder.push("key").unwrap();
+        assert!(encoder.end().is_err());
+        encoder.push(&1u8).unwrap();
+        encoder.end().unwrap();
+
+        encoder.begin_array().unwrap();
+        assert!(encoder.finish().is_err());
+    }
+}
@@ -0,0 +1,107 @@
+#[cfg(feature = "std")]
+mod std_tests {
+    use std::collections::BTreeMap;
+
+    use serde_bytes::ByteBuf;
+    use serde_cbor::error::Category;
+    use serde_cbor::{from_slice, DeserializerOptions, Value};
+
+    fn nested_arrays(depth: usize) -> Vec<u8> {
+        let mut bytes = vec![0x81; depth];
+        bytes.push(0x01);
+        bytes
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let options = DeserializerOptions {
+            max_depth: 2,
+            ..Default::default()
+        };
+        let value: Vec<Vec<u8>> = options.from_slice(&nested_arrays(2)).unwrap();
+        assert_eq!(value, vec![vec![1]]);
+        let err = options
+            .from_slice::<Vec<Vec<Vec<u8>>>>(&nested_arrays(3))
+            .unwrap_err();
+        assert_eq!(err.classify(), Category::Syntax);
+
+        assert!(from_slice::<Value>(&nested_arrays(200)).is_err());
+        let options = DeserializerOptions {
+            max_depth: 256,
+            ..Default::default()
+        };
+        assert!(options.from_slice::<Value>(&nested_arrays(200)).is_ok());
+    }
+
+    #[test]
+    fn test_max_collection_len() {
+        let options = DeserializerOptions {
+            max_collection_len: 3,
+            ..Default::default()
+        };
+        // [1, 2, 3]
+        let value: Vec<u8> = options.from_slice(b"\x83\x01\x02\x03").unwrap();
+        assert_eq!(value, vec![1, 2, 3]);
+        // [1, 2, 3, 4]
+        let err = options
+            .from_slice::<Vec<u8>>(b"\x84\x01\x02\x03\x04")
+            .unwrap_err();
+        assert_eq!(err.classify(), Category::Syntax);
+        assert_eq!(err.offset(), 1);
+        // [_ 1, 2, 3, 4]
+        assert!(options
+            .from_slice::<Vec<u8>>(b"\x9f\x01\x02\x03\x04\xff")
+            .is_err());
+        // an array of 2^64 - 1 elements, only the first one is present.
+        assert!(options
+            .from_slice::<Vec<u8>>(b"\x9b\xff\xff\xff\xff\xff\xff\xff\xff\x01")
+            .is_err());
+        // {_ 1: 1, 2: 2, 3: 3, 4: 4}
+        assert!(options
+            .from_slice::<BTreeMap<u8, u8>>(b"\xbf\x01\x01\x02\x02\x03\x03\x04\x04\xff")
+            .is_err());
+        // {1: 1, 2: 2, 3: 3, 4: 4}
+        assert!(options
+            .from_slice::<BTreeMap<u8, u8>>(b"\xa4\x01\x01\x02\x02\x03\x03\x04\x04")
+            .is_err());
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let options = DeserializerOptions {
+            max_bytes: 4,
+            ..Default::default()
+        };
+        // [1, 2, 3]
+        let value: Vec<u8> = options.from_slice(b"\x83\x01\x02\x03").unwrap();
+        assert_eq!(value, vec![1, 2, 3]);
+        // [1, 2, 3, 4]
+        let err = options
+            .from_slice::<Vec<u8>>(b"\x84\x01\x02\x03\x04")
+            .unwrap_err();
+        assert_eq!(err.classify(), Category::Syntax);
+        // 1.5 as a double, the header fits but not the value.
+        assert!(options
+            .from_slice::<f64>(b"\xfb\x3f\xf8\x00\x00\x00\x00\x00\x00")
+            .is_err());
+
+        // a byte string of 2^32 - 1 bytes, only the first one is present.
+        let huge = b"\x5a\xff\xff\xff\xff\x00";
+        let options = DeserializerOptions {
+            max_bytes: 1024,
+            ..Default::default()
+        };
+        let err = options.from_slice::<ByteBuf>(huge).unwrap_err();
+        assert_eq!(err.classify(), Category::Syntax);
+        let err = options.from_reader::<ByteBuf, _>(&huge[..]).unwrap_err();
+        assert_eq!(err.classify(), Category::Syntax);
+        // "abc" in chunks.
+        let chunked = b"\x7f\x61a\x62bc\xff";
+        assert_eq!(options.from_slice::<String>(chunked).unwrap(), "abc");
+        let options = DeserializerOptions {
+            max_bytes: 5,
+            ..Default::default()
+        };
+        assert!(options.from_reader::<String, _>(&chunked[..]).is_err());
+    }
+}
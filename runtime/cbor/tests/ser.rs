@@ -30,6 +30,29 @@ fn test_integer() {
     serialize_and_compare(::core::u64::MAX, b"\x1b\xff\xff\xff\xff\xff\xff\xff\xff");
 }
 
+#[test]
+fn test_to_slice() {
+    let mut slice = [0u8; 8];
+    let written = serde_cbor::to_slice(&[1, 2, 3], &mut slice).unwrap();
+    assert_eq!(&slice[..written], b"\x83\x01\x02\x03");
+
+    let err = serde_cbor::to_slice(&"too long for the slice", &mut slice).unwrap_err();
+    assert!(err.is_scratch_too_small());
+}
+
+#[test]
+fn test_reset() {
+    let mut first = [0u8; 8];
+    let mut second = [0u8; 8];
+    let mut serializer = Serializer::packed(SliceWrite::new(&mut first));
+    1.serialize(&mut serializer).unwrap();
+    let writer = serializer.reset(SliceWrite::new(&mut second));
+    assert_eq!(writer.bytes_written(), 1);
+    "ab".serialize(&mut serializer).unwrap();
+    assert_eq!(serializer.into_inner().bytes_written(), 3);
+    assert_eq!(&second[..3], b"bab");
+}
+
 fn serialize_and_compare<T: Serialize>(value: T, expected: &[u8]) {
     let mut slice = [0u8; 64];
     let writer = SliceWrite::new(&mut slice);
@@ -0,0 +1,63 @@
+#[macro_use]
+extern crate serde_derive;
+
+#[cfg(feature = "std")]
+mod std_tests {
+    use serde_bytes::ByteBuf;
+    use serde_cbor::ser::Serializer;
+    use serde_cbor::tags::{Tagged, CID_TAG};
+    use serde_cbor::{from_slice, to_vec, Value};
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Node {
+        name: String,
+        link: Tagged<ByteBuf>,
+    }
+
+    #[test]
+    fn test_tagged_round_trip() {
+        let node = Node {
+            name: "a".to_string(),
+            link: Tagged::new(Some(CID_TAG), ByteBuf::from(vec![0, 1, 2])),
+        };
+        let bytes = to_vec(&node).unwrap();
+        assert_eq!(
+            bytes,
+            b"\xa2\x64link\xd8\x2a\x43\x00\x01\x02\x64name\x61a".to_vec()
+        );
+        let decoded: Node = from_slice(&bytes).unwrap();
+        assert_eq!(decoded, node);
+    }
+
+    #[test]
+    fn test_untagged() {
+        let value = Tagged::new(None, 7u8);
+        let bytes = to_vec(&value).unwrap();
+        assert_eq!(bytes, b"\x07".to_vec());
+        assert_eq!(from_slice::<Tagged<u8>>(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_nested_tags() {
+        let value = Tagged::new(Some(1), Tagged::new(Some(CID_TAG), 7u8));
+        let bytes = to_vec(&value).unwrap();
+        assert_eq!(bytes, b"\xc1\xd8\x2a\x07".to_vec());
+        assert_eq!(from_slice::<Tagged<Tagged<u8>>>(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_tags_ignored_by_other_types() {
+        let bytes = to_vec(&Tagged::new(Some(CID_TAG), 7u8)).unwrap();
+        assert_eq!(from_slice::<u8>(&bytes).unwrap(), 7);
+        assert_eq!(from_slice::<Value>(&bytes).unwrap(), Value::U64(7));
+    }
+
+    #[test]
+    fn test_serialize_tagged() {
+        let mut serializer = Serializer::new(Vec::new());
+        serializer.serialize_tagged(0x1_0000, &"x").unwrap();
+        assert_eq!(
+            serializer.into_inner(),
+            b"\xda\x00\x01\x00\x00\x61x".to_vec()
+        );
+    }
+}
@@ -0,0 +1,635 @@
+//! API keys of a node or a shared gateway.
+//!
+//! With keys configured every request must carry an `Authorization: Bearer <token>` header. Each
+//! key has a scope, telling which routes it may use, and a quota of requests per minute and of
+//! bytes added per day, where the bytes of the files uploaded to `/api/v0/add` count as added as
+//! they are received. Keys and their usage are kept in the `api_keys` column of a `KeyStore`,
+//! which `RepoKeyStore` persists in the repo. Requests without a known key are answered with a
+//! 401, requests of a key whose scope does not cover the route with a 403, and requests over a
+//! quota with a 429 telling when it resets.
+//!
+//! Unmetered keys can also be loaded from the repo's `api` file, one `<scope> <token>` pair per
+//! line.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use repo::{DataStore, Error as StoreError};
+use crate::route::{get_param, Out, ADD_PATH};
+
+/// Column of the `KeyStore` holding the keys, keyed by token.
+pub const API_KEYS_COLUMN: &str = "api_keys";
+
+/// Name of the file in the repo unmetered keys are loaded from.
+pub const API_FILE: &str = "api";
+
+const SECS_PER_MINUTE: u64 = 60;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Size of an encoded `KeyRecord`.
+const RECORD_LEN: usize = 49;
+
+/// Size of a `KeyRecord` encoded before keys had a scope, read as `Scope::Write`.
+const UNSCOPED_RECORD_LEN: usize = 48;
+
+/// Column based storage of the keys.
+pub trait KeyStore: Send + Sync {
+	fn get(&self, column: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError>;
+	fn put(&self, column: &str, key: &[u8], value: Vec<u8>) -> Result<(), StoreError>;
+	fn delete(&self, column: &str, key: &[u8]) -> Result<(), StoreError>;
+	/// All keys of `column`, in order.
+	fn keys(&self, column: &str) -> Result<Vec<Vec<u8>>, StoreError>;
+}
+
+/// A `KeyStore` losing the keys and their usage on restart.
+#[derive(Debug, Default)]
+pub struct MemoryKeyStore {
+	columns: Mutex<HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>>,
+}
+
+impl KeyStore for MemoryKeyStore {
+	fn get(&self, column: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+		let columns = self.columns.lock().expect("lock is never poisoned; qed");
+		Ok(columns.get(column).and_then(|column| column.get(key).cloned()))
+	}
+
+	fn put(&self, column: &str, key: &[u8], value: Vec<u8>) -> Result<(), StoreError> {
+		let mut columns = self.columns.lock().expect("lock is never poisoned; qed");
+		columns.entry(column.into()).or_insert_with(BTreeMap::new).insert(key.to_vec(), value);
+		Ok(())
+	}
+
+	fn delete(&self, column: &str, key: &[u8]) -> Result<(), StoreError> {
+		let mut columns = self.columns.lock().expect("lock is never poisoned; qed");
+		if let Some(column) = columns.get_mut(column) {
+			column.remove(key);
+		}
+		Ok(())
+	}
+
+	fn keys(&self, column: &str) -> Result<Vec<Vec<u8>>, StoreError> {
+		let columns = self.columns.lock().expect("lock is never poisoned; qed");
+		Ok(columns.get(column).map_or_else(Vec::new, |column| column.keys().cloned().collect()))
+	}
+}
+
+/// A `KeyStore` keeping the keys and their usage in a datastore of the repo, so quotas survive a
+/// restart.
+#[derive(Debug)]
+pub struct RepoKeyStore<S> {
+	store: S,
+}
+
+impl<S: DataStore> RepoKeyStore<S> {
+	/// Fails if `store` was not opened with `API_KEYS_COLUMN` registered, see
+	/// `repo::ColumnRegistry::register`.
+	pub fn new(store: S) -> Result<Self, StoreError> {
+		store.columns().id(API_KEYS_COLUMN)?;
+		Ok(RepoKeyStore { store })
+	}
+}
+
+impl<S: DataStore> KeyStore for RepoKeyStore<S> {
+	fn get(&self, column: &str, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+		self.store.get_bytes(self.store.columns().id(column)?, key)
+	}
+
+	fn put(&self, column: &str, key: &[u8], value: Vec<u8>) -> Result<(), StoreError> {
+		self.store.put_bytes(self.store.columns().id(column)?, key, &value)
+	}
+
+	fn delete(&self, column: &str, key: &[u8]) -> Result<(), StoreError> {
+		self.store.key_delete(self.store.columns().id(column)?, key)
+	}
+
+	fn keys(&self, column: &str) -> Result<Vec<Vec<u8>>, StoreError> {
+		let column = self.store.columns().id(column)?;
+		Ok(self.store.iter_column(column)?.map(|(key, _)| key).collect())
+	}
+}
+
+/// Routes an API key may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+	/// Read content and the state of the node.
+	Read,
+	/// Also add content and post to the routes that are not admin ones.
+	Write,
+	/// Every route, like the admin token.
+	Admin,
+}
+
+impl Scope {
+	/// Scope needed to `post` to `path`.
+	pub fn of_route(post: bool, path: &str) -> Scope {
+		if path.starts_with("/admin/") {
+			Scope::Admin
+		} else if post || path == ADD_PATH {
+			Scope::Write
+		} else {
+			Scope::Read
+		}
+	}
+
+	fn parse(scope: &str) -> Option<Scope> {
+		match scope {
+			"read" => Some(Scope::Read),
+			"write" => Some(Scope::Write),
+			"admin" => Some(Scope::Admin),
+			_ => None,
+		}
+	}
+
+	fn as_str(&self) -> &'static str {
+		match *self {
+			Scope::Read => "read",
+			Scope::Write => "write",
+			Scope::Admin => "admin",
+		}
+	}
+}
+
+/// Limits of an API key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+	pub requests_per_min: u32,
+	pub bytes_added_per_day: u64,
+}
+
+impl Quota {
+	/// No limit, for the keys of the `api` file.
+	pub const UNLIMITED: Quota = Quota { requests_per_min: ::std::u32::MAX, bytes_added_per_day: ::std::u64::MAX };
+}
+
+/// Usage of an API key in the current minute and day, counted since the unix epoch.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+	pub minute: u64,
+	pub requests: u32,
+	pub day: u64,
+	pub bytes_added: u64,
+}
+
+/// An API key as stored in `API_KEYS_COLUMN`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyRecord {
+	pub scope: Scope,
+	pub quota: Quota,
+	pub usage: Usage,
+}
+
+impl KeyRecord {
+	fn encode(&self) -> Vec<u8> {
+		let mut bytes = Vec::with_capacity(RECORD_LEN);
+		bytes.extend_from_slice(&u64::from(self.quota.requests_per_min).to_be_bytes());
+		bytes.extend_from_slice(&self.quota.bytes_added_per_day.to_be_bytes());
+		bytes.extend_from_slice(&self.usage.minute.to_be_bytes());
+		bytes.extend_from_slice(&u64::from(self.usage.requests).to_be_bytes());
+		bytes.extend_from_slice(&self.usage.day.to_be_bytes());
+		bytes.extend_from_slice(&self.usage.bytes_added.to_be_bytes());
+		bytes.push(self.scope as u8);
+		bytes
+	}
+
+	fn decode(bytes: &[u8]) -> Option<Self> {
+		let scope = match (bytes.len(), bytes.get(UNSCOPED_RECORD_LEN)) {
+			(UNSCOPED_RECORD_LEN, None) => Scope::Write,
+			(RECORD_LEN, Some(&0)) => Scope::Read,
+			(RECORD_LEN, Some(&1)) => Scope::Write,
+			(RECORD_LEN, Some(&2)) => Scope::Admin,
+			_ => return None,
+		};
+		let field = |i: usize| bytes[i * 8..(i + 1) * 8].iter().fold(0u64, |acc, &b| acc << 8 | u64::from(b));
+		Some(KeyRecord {
+			scope,
+			quota: Quota {
+				requests_per_min: field(0) as u32,
+				bytes_added_per_day: field(1),
+			},
+			usage: Usage {
+				minute: field(2),
+				requests: field(3) as u32,
+				day: field(4),
+				bytes_added: field(5),
+			},
+		})
+	}
+
+	fn to_json(&self, token: &str) -> String {
+		format!(
+			"{{\"token\":\"{}\",\"scope\":\"{}\",\"requests_per_min\":{},\"bytes_added_per_day\":{},\"requests\":{},\"bytes_added\":{}}}",
+			token, self.scope.as_str(), self.quota.requests_per_min, self.quota.bytes_added_per_day, self.usage.requests, self.usage.bytes_added,
+		)
+	}
+}
+
+/// Why a request was not let through.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Denied {
+	/// No token or an unknown one.
+	Unauthorized,
+	/// The scope of the key does not cover the route.
+	Forbidden,
+	/// A quota of the key is used up until `retry_after` from now.
+	QuotaExceeded { retry_after: Duration },
+	/// The key could not be read or its usage could not be saved.
+	Store,
+}
+
+impl From<Denied> for Out {
+	fn from(denied: Denied) -> Out {
+		match denied {
+			Denied::Unauthorized => Out::Unauthorized("Missing or unknown API key"),
+			Denied::Forbidden => Out::Forbidden("API key scope does not cover the route"),
+			Denied::QuotaExceeded { retry_after } => Out::TooManyRequests { retry_after },
+			Denied::Store => Out::Internal("Accounting the API key failed"),
+		}
+	}
+}
+
+/// The API keys of a gateway and the admin token managing them.
+#[derive(Clone)]
+pub struct ApiKeys {
+	store: Arc<dyn KeyStore>,
+	admin_token: String,
+	/// Serializes the read-modify-write of usage records.
+	lock: Arc<Mutex<()>>,
+}
+
+impl ApiKeys {
+	pub fn new(store: Arc<dyn KeyStore>, admin_token: String) -> Self {
+		ApiKeys {
+			store,
+			admin_token,
+			lock: Arc::new(Mutex::new(())),
+		}
+	}
+
+	/// Creates the key `token`, or sets the scope and quota of an existing one keeping its usage.
+	pub fn upsert(&self, token: &str, scope: Scope, quota: Quota) -> Result<KeyRecord, StoreError> {
+		let _lock = self.lock.lock().expect("lock is never poisoned; qed");
+		let usage = self.get(token)?.map_or_else(Usage::default, |record| record.usage);
+		let record = KeyRecord { scope, quota, usage };
+		self.store.put(API_KEYS_COLUMN, token.as_bytes(), record.encode())?;
+		Ok(record)
+	}
+
+	/// Removes the key `token`, returning whether it existed.
+	pub fn revoke(&self, token: &str) -> Result<bool, StoreError> {
+		let _lock = self.lock.lock().expect("lock is never poisoned; qed");
+		let exists = self.get(token)?.is_some();
+		self.store.delete(API_KEYS_COLUMN, token.as_bytes())?;
+		Ok(exists)
+	}
+
+	/// Creates or updates the unmetered keys of the `api` file in `repo`.
+	pub fn load_api_file(&self, repo: &Path) -> io::Result<()> {
+		self.load_tokens(&fs::read_to_string(repo.join(API_FILE))?)
+	}
+
+	/// Creates or updates unmetered keys from `<scope> <token>` lines, skipping empty lines and `#`
+	/// comments. Nothing is saved if a line is invalid.
+	pub fn load_tokens(&self, contents: &str) -> io::Result<()> {
+		let mut tokens = Vec::new();
+		for (i, line) in contents.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let mut words = line.split_whitespace();
+			match (words.next().and_then(Scope::parse), words.next(), words.next()) {
+				(Some(scope), Some(token), None) if is_valid_token(token) => tokens.push((scope, token)),
+				_ => return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!("line {}: expected `<read|write|admin> <token>`", i + 1),
+				)),
+			}
+		}
+		for (scope, token) in tokens {
+			self.upsert(token, scope, Quota::UNLIMITED)
+				.map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+		}
+		Ok(())
+	}
+
+	/// All keys with their scope, quota and usage.
+	pub fn list(&self) -> Result<Vec<(String, KeyRecord)>, StoreError> {
+		let mut keys = Vec::new();
+		for token in self.store.keys(API_KEYS_COLUMN)? {
+			let token = match String::from_utf8(token) {
+				Ok(token) => token,
+				Err(_) => continue,
+			};
+			if let Some(record) = self.get(&token)? {
+				keys.push((token, record));
+			}
+		}
+		Ok(keys)
+	}
+
+	/// Counts a request of `token` against its quota, if its scope covers `needed`.
+	pub fn charge(&self, token: Option<&str>, needed: Scope) -> Result<(), Denied> {
+		self.charge_at(token, needed, 1, 0, now())
+	}
+
+	/// Counts `bytes_added` bytes of a request of `token` already charged, as they are received.
+	pub fn charge_bytes(&self, token: Option<&str>, bytes_added: u64) -> Result<(), Denied> {
+		self.charge_at(token, Scope::Write, 0, bytes_added, now())
+	}
+
+	fn charge_at(&self, token: Option<&str>, needed: Scope, requests: u32, bytes_added: u64, now: u64) -> Result<(), Denied> {
+		let token = token.ok_or(Denied::Unauthorized)?;
+		let _lock = self.lock.lock().expect("lock is never poisoned; qed");
+		let mut record = self.get(token).map_err(|_| Denied::Store)?.ok_or(Denied::Unauthorized)?;
+		if record.scope < needed {
+			return Err(Denied::Forbidden);
+		}
+
+		let (minute, day) = (now / SECS_PER_MINUTE, now / SECS_PER_DAY);
+		if record.usage.minute != minute {
+			record.usage.minute = minute;
+			record.usage.requests = 0;
+		}
+		if record.usage.day != day {
+			record.usage.day = day;
+			record.usage.bytes_added = 0;
+		}
+
+		if requests > 0 && record.usage.requests >= record.quota.requests_per_min {
+			let retry_after = (minute + 1) * SECS_PER_MINUTE - now;
+			return Err(Denied::QuotaExceeded { retry_after: Duration::from_secs(retry_after) });
+		}
+		if bytes_added > 0 && record.usage.bytes_added.saturating_add(bytes_added) > record.quota.bytes_added_per_day {
+			let retry_after = (day + 1) * SECS_PER_DAY - now;
+			return Err(Denied::QuotaExceeded { retry_after: Duration::from_secs(retry_after) });
+		}
+
+		record.usage.requests += requests;
+		record.usage.bytes_added = record.usage.bytes_added.saturating_add(bytes_added);
+		self.store.put(API_KEYS_COLUMN, token.as_bytes(), record.encode()).map_err(|_| Denied::Store)
+	}
+
+	/// Whether `token` is the admin token, compared in constant time, or a key of the admin scope.
+	pub fn is_admin(&self, token: Option<&str>) -> bool {
+		let token = match token {
+			Some(token) => token,
+			None => return false,
+		};
+		let admin = self.admin_token.as_bytes();
+		let is_admin_token = token.len() == admin.len()
+			&& token.bytes().zip(admin).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0;
+		is_admin_token || self.get(token).ok().and_then(|record| record).map_or(false, |record| record.scope == Scope::Admin)
+	}
+
+	fn get(&self, token: &str) -> Result<Option<KeyRecord>, StoreError> {
+		Ok(self.store.get(API_KEYS_COLUMN, token.as_bytes())?.and_then(|bytes| KeyRecord::decode(&bytes)))
+	}
+
+	/// Route `/admin/keys` requests, authorized by the admin token.
+	///
+	/// - `GET /admin/keys` lists the keys.
+	/// - `POST /admin/keys?token=..&requests_per_min=..&bytes_added_per_day=..&scope=..` creates
+	///   or updates a key, of the `write` scope unless `scope` is given.
+	/// - `POST /admin/keys/revoke?token=..` removes a key.
+	pub(crate) fn route_admin(&self, token: Option<&str>, post: bool, path: &str, query: Option<&str>) -> Out {
+		if !self.is_admin(token) {
+			return Out::Unauthorized("Admin token required");
+		}
+		let param = |name| query.and_then(|q| get_param(q, name));
+
+		match (post, path) {
+			(false, "/admin/keys") => match self.list() {
+				Ok(keys) => {
+					let keys: Vec<_> = keys.iter().map(|(token, record)| record.to_json(token)).collect();
+					Out::Json(format!("[{}]", keys.join(",")))
+				},
+				Err(_) => Out::Internal("Reading the API keys failed"),
+			},
+			(true, "/admin/keys") => {
+				let token = match param("token") {
+					Some(token) if is_valid_token(token) => token,
+					_ => return Out::Bad("Token must be 1 to 128 characters of [A-Za-z0-9._-]"),
+				};
+				let scope = match param("scope") {
+					Some(scope) => match Scope::parse(scope) {
+						Some(scope) => scope,
+						None => return Out::Bad("Scope must be read, write or admin"),
+					},
+					None => Scope::Write,
+				};
+				let requests_per_min = param("requests_per_min").and_then(|n| n.parse().ok());
+				let bytes_added_per_day = param("bytes_added_per_day").and_then(|n| n.parse().ok());
+				match (requests_per_min, bytes_added_per_day) {
+					(Some(requests_per_min), Some(bytes_added_per_day)) => {
+						match self.upsert(token, scope, Quota { requests_per_min, bytes_added_per_day }) {
+							Ok(record) => Out::Json(record.to_json(token)),
+							Err(_) => Out::Internal("Saving the API key failed"),
+						}
+					},
+					_ => Out::Bad("Invalid requests_per_min or bytes_added_per_day"),
+				}
+			},
+			(true, "/admin/keys/revoke") => {
+				match param("token").map(|token| self.revoke(token)) {
+					Some(Ok(true)) => Out::Json("{\"revoked\":true}".into()),
+					Some(Err(_)) => Out::Internal("Revoking the API key failed"),
+					_ => Out::NotFound("API key not found"),
+				}
+			},
+			_ => Out::NotFound("Route not found"),
+		}
+	}
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Tokens are used in JSON bodies unescaped.
+fn is_valid_token(token: &str) -> bool {
+	!token.is_empty() && token.len() <= 128
+		&& token.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'_' || b == b'-')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn keys() -> ApiKeys {
+		ApiKeys::new(Arc::new(MemoryKeyStore::default()), "admin".into())
+	}
+
+	#[test]
+	fn test_requests_per_min() {
+		let keys = keys();
+		keys.upsert("alice", Scope::Write, Quota { requests_per_min: 2, bytes_added_per_day: 0 }).unwrap();
+
+		assert_eq!(keys.charge_at(Some("alice"), Scope::Write, 1, 0, 120), Ok(()));
+		assert_eq!(keys.charge_at(Some("alice"), Scope::Write, 1, 0, 130), Ok(()));
+		assert_eq!(
+			keys.charge_at(Some("alice"), Scope::Write, 1, 0, 135),
+			Err(Denied::QuotaExceeded { retry_after: Duration::from_secs(45) })
+		);
+		// the next minute starts over.
+		assert_eq!(keys.charge_at(Some("alice"), Scope::Write, 1, 0, 180), Ok(()));
+	}
+
+	#[test]
+	fn test_bytes_added_per_day() {
+		let keys = keys();
+		keys.upsert("bob", Scope::Write, Quota { requests_per_min: 100, bytes_added_per_day: 1000 }).unwrap();
+
+		assert_eq!(keys.charge_at(Some("bob"), Scope::Write, 1, 600, 10), Ok(()));
+		assert_eq!(
+			keys.charge_at(Some("bob"), Scope::Write, 1, 600, 20),
+			Err(Denied::QuotaExceeded { retry_after: Duration::from_secs(SECS_PER_DAY - 20) })
+		);
+		// reads still go through.
+		assert_eq!(keys.charge_at(Some("bob"), Scope::Write, 1, 0, 30), Ok(()));
+		assert_eq!(keys.charge_at(Some("bob"), Scope::Write, 1, 600, SECS_PER_DAY), Ok(()));
+	}
+
+	#[test]
+	fn test_bytes_received() {
+		let keys = keys();
+		keys.upsert("grace", Scope::Write, Quota { requests_per_min: 1, bytes_added_per_day: 1000 }).unwrap();
+
+		assert_eq!(keys.charge_at(Some("grace"), Scope::Write, 1, 0, 10), Ok(()));
+		// the bytes of the body are counted as they arrive, not as requests.
+		assert_eq!(keys.charge_at(Some("grace"), Scope::Write, 0, 600, 11), Ok(()));
+		assert_eq!(keys.charge_at(Some("grace"), Scope::Write, 0, 400, 12), Ok(()));
+		assert_eq!(
+			keys.charge_at(Some("grace"), Scope::Write, 0, 1, 13),
+			Err(Denied::QuotaExceeded { retry_after: Duration::from_secs(SECS_PER_DAY - 13) })
+		);
+		let record = keys.get("grace").unwrap().unwrap();
+		assert_eq!((record.usage.requests, record.usage.bytes_added), (1, 1000));
+	}
+
+	#[test]
+	fn test_repo_key_store() {
+		use repo::{ColumnRegistry, MemoryStore};
+
+		assert!(RepoKeyStore::new(MemoryStore::open()).is_err());
+
+		let columns = ColumnRegistry::with_custom(&[API_KEYS_COLUMN]).unwrap();
+		let store = Arc::new(RepoKeyStore::new(MemoryStore::open().with_columns(columns)).unwrap());
+		let keys = ApiKeys::new(store.clone(), "admin".into());
+		keys.upsert("heidi", Scope::Write, Quota { requests_per_min: 10, bytes_added_per_day: 100 }).unwrap();
+		assert_eq!(keys.charge_at(Some("heidi"), Scope::Write, 1, 40, 10), Ok(()));
+
+		// the usage is read back from the store.
+		let reopened = ApiKeys::new(store, "admin".into());
+		let list = reopened.list().unwrap();
+		assert_eq!(list.len(), 1);
+		assert_eq!((list[0].1.usage.requests, list[0].1.usage.bytes_added), (1, 40));
+		assert!(reopened.revoke("heidi").unwrap());
+		assert_eq!(reopened.list().unwrap(), vec![]);
+	}
+
+	#[test]
+	fn test_unknown_keys() {
+		let keys = keys();
+		keys.upsert("carol", Scope::Write, Quota { requests_per_min: 1, bytes_added_per_day: 0 }).unwrap();
+
+		assert_eq!(keys.charge_at(None, Scope::Read, 1, 0, 0), Err(Denied::Unauthorized));
+		assert_eq!(keys.charge_at(Some("dave"), Scope::Write, 1, 0, 0), Err(Denied::Unauthorized));
+		assert!(keys.revoke("carol").unwrap());
+		assert!(!keys.revoke("carol").unwrap());
+		assert_eq!(keys.charge_at(Some("carol"), Scope::Write, 1, 0, 0), Err(Denied::Unauthorized));
+	}
+
+	#[test]
+	fn test_usage_survives_quota_update() {
+		let keys = keys();
+		keys.upsert("erin", Scope::Write, Quota { requests_per_min: 1, bytes_added_per_day: 0 }).unwrap();
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		keys.charge_at(Some("erin"), Scope::Write, 1, 0, now).unwrap();
+
+		let record = keys.upsert("erin", Scope::Write, Quota { requests_per_min: 5, bytes_added_per_day: 10 }).unwrap();
+		assert_eq!(record.usage.requests, 1);
+		assert_eq!(record.usage.day, now / SECS_PER_DAY);
+		assert_eq!(keys.list().unwrap(), vec![("erin".to_string(), record)]);
+	}
+
+	#[test]
+	fn test_scopes() {
+		let keys = keys();
+		keys.upsert("reader", Scope::Read, Quota::UNLIMITED).unwrap();
+		keys.upsert("root", Scope::Admin, Quota::UNLIMITED).unwrap();
+
+		assert_eq!(keys.charge_at(Some("reader"), Scope::Read, 1, 0, 0), Ok(()));
+		assert_eq!(keys.charge_at(Some("reader"), Scope::Write, 1, 0, 0), Err(Denied::Forbidden));
+		assert_eq!(keys.charge_at(Some("root"), Scope::Write, 1, 0, 0), Ok(()));
+		assert!(!keys.is_admin(Some("reader")));
+		assert!(keys.is_admin(Some("root")));
+		assert!(keys.is_admin(Some("admin")));
+		assert!(!keys.is_admin(None));
+	}
+
+	#[test]
+	fn test_scope_of_route() {
+		assert_eq!(Scope::of_route(false, "/api/v0/block/get"), Scope::Read);
+		assert_eq!(Scope::of_route(false, "/ipfs/QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA"), Scope::Read);
+		assert_eq!(Scope::of_route(true, ADD_PATH), Scope::Write);
+		assert_eq!(Scope::of_route(false, "/admin/repo/stats"), Scope::Admin);
+		assert_eq!(Scope::of_route(true, "/admin/denylist"), Scope::Admin);
+	}
+
+	#[test]
+	fn test_load_tokens() {
+		let keys = keys();
+		keys.load_tokens("# node tokens\nread reader\n\nadmin  root\n").unwrap();
+
+		let list = keys.list().unwrap();
+		assert_eq!(list.len(), 2);
+		assert_eq!((list[0].0.as_str(), list[0].1.scope, list[0].1.quota), ("reader", Scope::Read, Quota::UNLIMITED));
+		assert_eq!((list[1].0.as_str(), list[1].1.scope), ("root", Scope::Admin));
+		assert!(keys.load_tokens("owner token").is_err());
+		assert!(keys.load_tokens("read").is_err());
+		assert!(keys.load_tokens("read a b").is_err());
+		assert!(keys.load_tokens("read \"").is_err());
+	}
+
+	#[test]
+	fn test_unscoped_record() {
+		let record = KeyRecord {
+			scope: Scope::Admin,
+			quota: Quota { requests_per_min: 1, bytes_added_per_day: 2 },
+			usage: Usage::default(),
+		};
+		let bytes = record.encode();
+		assert_eq!(KeyRecord::decode(&bytes), Some(record));
+		// records saved before keys had a scope could add content.
+		assert_eq!(KeyRecord::decode(&bytes[..UNSCOPED_RECORD_LEN]), Some(KeyRecord { scope: Scope::Write, ..record }));
+		assert_eq!(KeyRecord::decode(&[bytes[..UNSCOPED_RECORD_LEN].to_vec(), vec![3]].concat()), None);
+	}
+
+	#[test]
+	fn test_route_admin() {
+		let keys = keys();
+
+		assert_eq!(keys.route_admin(None, false, "/admin/keys", None), Out::Unauthorized("Admin token required"));
+		assert_eq!(keys.route_admin(Some("admi"), false, "/admin/keys", None), Out::Unauthorized("Admin token required"));
+
+		let create = Some("token=frank&requests_per_min=10&bytes_added_per_day=100");
+		assert_eq!(
+			keys.route_admin(Some("admin"), true, "/admin/keys", create),
+			Out::Json(r#"{"token":"frank","scope":"write","requests_per_min":10,"bytes_added_per_day":100,"requests":0,"bytes_added":0}"#.into())
+		);
+		assert_eq!(
+			keys.route_admin(Some("admin"), false, "/admin/keys", None),
+			Out::Json(r#"[{"token":"frank","scope":"write","requests_per_min":10,"bytes_added_per_day":100,"requests":0,"bytes_added":0}]"#.into())
+		);
+		let invalid = Some("token=\"&requests_per_min=10&bytes_added_per_day=100");
+		assert!(match keys.route_admin(Some("admin"), true, "/admin/keys", invalid) { Out::Bad(_) => true, _ => false });
+
+		let revoke = Some("token=frank");
+		assert_eq!(keys.route_admin(Some("admin"), true, "/admin/keys/revoke", revoke), Out::Json(r#"{"revoked":true}"#.into()));
+		assert_eq!(keys.route_admin(Some("admin"), true, "/admin/keys/revoke", revoke), Out::NotFound("API key not found"));
+	}
+}
@@ -0,0 +1,167 @@
+//! CBOR bodies for machine-to-machine callers.
+//!
+//! POST routes take their parameters from the query string. A caller may send them as a CBOR map
+//! in a `Content-Type: application/cbor` body instead, and is answered in kind: JSON bodies are
+//! sent as CBOR to requests with a CBOR body or `Accept: application/cbor`. Files are still
+//! added with a multipart body.
+
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::TryStreamExt;
+use http::hyper::{header::{self, HeaderValue}, Body, Method, Request, Uri};
+use serde_cbor::{self, ObjectKey, Value};
+
+use crate::route::{Out, ADD_PATH};
+
+/// Content type of CBOR bodies.
+pub const CBOR: &str = "application/cbor";
+
+/// Largest CBOR body read, it only carries parameters.
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+/// Whether the media type of a `Content-Type` or `Accept` entry is CBOR.
+fn is_cbor(value: &str) -> bool {
+	value.split(';').next().map_or(false, |media_type| media_type.trim().eq_ignore_ascii_case(CBOR))
+}
+
+/// Whether `req` carries its parameters in a CBOR body.
+pub fn has_params_body(req: &Request<Body>) -> bool {
+	*req.method() == Method::POST && req.uri().path() != ADD_PATH
+		&& req.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).map_or(false, is_cbor)
+}
+
+/// Whether `req` is answered in CBOR.
+pub fn accepts_cbor(req: &Request<Body>) -> bool {
+	req.headers().get_all(header::ACCEPT).iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(is_cbor)
+}
+
+/// Read the CBOR body of `req` into its query string, see `params`. The request is left without
+/// a body and asks to be answered in CBOR.
+pub fn read_params(req: Request<Body>) -> BoxFuture<'static, Result<Request<Body>, Out>> {
+	let (mut parts, body) = req.into_parts();
+
+	body
+		.map_err(|_| Out::Bad("Failed to read the request body"))
+		.try_fold(Vec::new(), |mut body, chunk| {
+			if body.len() + chunk.len() > MAX_BODY_LEN {
+				return future::err(Out::Bad("CBOR body too large"));
+			}
+			body.extend_from_slice(&chunk);
+			future::ok(body)
+		})
+		.map(move |body| {
+			let params = params(&body?)?;
+			let query = match parts.uri.query() {
+				Some(query) if !query.is_empty() => format!("{}&{}", query, params),
+				_ => params,
+			};
+			parts.uri = format!("{}?{}", parts.uri.path(), query).parse::<Uri>()
+				.map_err(|_| Out::Bad("Invalid CBOR parameters"))?;
+			parts.headers.remove(header::CONTENT_TYPE);
+			parts.headers.insert(header::ACCEPT, HeaderValue::from_static(CBOR));
+			Ok(Request::from_parts(parts, Body::empty()))
+		})
+		.boxed()
+}
+
+/// The entries of a CBOR map as a query string. Keys are text, values are text, integers or
+/// booleans.
+pub fn params(body: &[u8]) -> Result<String, Out> {
+	let map = match serde_cbor::from_slice(body) {
+		Ok(Value::Object(map)) => map,
+		_ => return Err(Out::Bad("Expected a CBOR map of parameters")),
+	};
+
+	let mut params = Vec::with_capacity(map.len());
+	for (key, value) in map {
+		let key = match key {
+			ObjectKey::String(ref key) if is_query_safe(key) && !key.contains('=') => key.clone(),
+			_ => return Err(Out::Bad("Expected text parameter names")),
+		};
+		let value = match value {
+			Value::String(value) => value,
+			Value::U64(value) => value.to_string(),
+			Value::I64(value) => value.to_string(),
+			Value::Bool(value) => value.to_string(),
+			_ => return Err(Out::Bad("Expected text, integer or boolean parameter values")),
+		};
+		if !is_query_safe(&value) {
+			return Err(Out::Bad("Invalid CBOR parameters"));
+		}
+		params.push(format!("{}={}", key, value));
+	}
+	Ok(params.join("&"))
+}
+
+/// Parameters are not percent-decoded when routed, so they may not hold what ends or splits a
+/// query.
+fn is_query_safe(text: &str) -> bool {
+	text.bytes().all(|b| b > b' ' && b < 0x7f && b != b'&' && b != b'#')
+}
+
+/// `out` for a caller answered in CBOR: JSON bodies are sent as CBOR, other bodies as they are.
+pub fn encode(out: Out) -> Out {
+	match out {
+		Out::Json(json) => {
+			let cbor = serde_json::from_str::<serde_json::Value>(&json).ok()
+				.and_then(|value| serde_cbor::to_vec(&value).ok());
+			match cbor {
+				Some(bytes) => Out::Cbor(bytes),
+				None => Out::Json(json),
+			}
+		},
+		out => out,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use std::collections::BTreeMap;
+
+	fn cbor_map(entries: Vec<(&str, Value)>) -> Vec<u8> {
+		let map: BTreeMap<ObjectKey, Value> = entries.into_iter()
+			.map(|(key, value)| (ObjectKey::String(key.into()), value))
+			.collect();
+		serde_cbor::to_vec(&Value::Object(map)).unwrap()
+	}
+
+	#[test]
+	fn test_params() {
+		let body = cbor_map(vec![
+			("token", Value::String("abc".into())),
+			("requests_per_min", Value::U64(60)),
+		]);
+		// keys are in canonical CBOR order, shorter first.
+		assert_eq!(params(&body), Ok("token=abc&requests_per_min=60".into()));
+
+		assert!(params(&cbor_map(vec![("rule", Value::String("a&b".into()))])).is_err());
+		assert!(params(&cbor_map(vec![("a=b", Value::Bool(true))])).is_err());
+		assert!(params(&cbor_map(vec![("list", Value::Array(vec![]))])).is_err());
+		assert!(params(&serde_cbor::to_vec(&Value::U64(1)).unwrap()).is_err());
+	}
+
+	#[test]
+	fn test_read_params() {
+		let body = cbor_map(vec![("code", Value::String("dmca".into()))]);
+		let req = Request::post("/admin/denylist?rule=x")
+			.header(header::CONTENT_TYPE, "application/cbor")
+			.body(Body::from(body))
+			.unwrap();
+		assert!(has_params_body(&req));
+
+		let req = block_on(read_params(req)).unwrap();
+		assert_eq!(req.uri().query(), Some("rule=x&code=dmca"));
+		assert!(!has_params_body(&req));
+		assert!(accepts_cbor(&req));
+	}
+
+	#[test]
+	fn test_encode() {
+		assert_eq!(encode(Out::Json(r#"{"added":true}"#.into())), Out::Cbor(b"\xa1\x65added\xf5".to_vec()));
+		assert_eq!(encode(Out::Bad("bad")), Out::Bad("bad"));
+	}
+}
@@ -0,0 +1,112 @@
+//! Content types of served files, by name or by their first bytes.
+
+use std::str;
+
+/// Content type of anything not recognized.
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Number of bytes looked at by `sniff`, as in the WHATWG MIME sniffing standard.
+const SNIFF_LEN: usize = 512;
+
+/// Content type of a file named `name`, from its extension.
+pub fn by_extension(name: &str) -> Option<&'static str> {
+	let name = name.rsplit('/').next().unwrap_or(name);
+	let dot = name.rfind('.')?;
+	let content_type = match &*name[dot + 1..].to_ascii_lowercase() {
+		"html" | "htm" => "text/html; charset=utf-8",
+		"js" | "mjs" => "application/javascript; charset=utf-8",
+		"css" => "text/css; charset=utf-8",
+		"json" => "application/json",
+		"txt" => "text/plain; charset=utf-8",
+		"md" => "text/markdown; charset=utf-8",
+		"xml" => "application/xml",
+		"svg" => "image/svg+xml",
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"webp" => "image/webp",
+		"ico" => "image/x-icon",
+		"woff2" => "font/woff2",
+		"pdf" => "application/pdf",
+		"wasm" => "application/wasm",
+		"mp4" => "video/mp4",
+		_ => return None,
+	};
+	Some(content_type)
+}
+
+/// Content type of `bytes` from their signature, text if they are UTF-8 without control
+/// characters and `OCTET_STREAM` otherwise.
+pub fn sniff(bytes: &[u8]) -> &'static str {
+	let head = &bytes[..bytes.len().min(SNIFF_LEN)];
+
+	if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+		return "image/png";
+	}
+	if head.starts_with(b"\xff\xd8\xff") {
+		return "image/jpeg";
+	}
+	if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+		return "image/gif";
+	}
+	if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+		return "image/webp";
+	}
+	if head.starts_with(b"%PDF-") {
+		return "application/pdf";
+	}
+	if head.starts_with(b"\0asm") {
+		return "application/wasm";
+	}
+
+	let text = match str::from_utf8(head) {
+		Ok(text) => text,
+		// the head may end within a character.
+		Err(err) if err.error_len().is_none() => str::from_utf8(&head[..err.valid_up_to()]).expect("valid up to here; qed"),
+		Err(_) => return OCTET_STREAM,
+	};
+	if text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+		return OCTET_STREAM;
+	}
+	let start = text.trim_start().chars().take(14).collect::<String>().to_ascii_lowercase();
+	if start.starts_with("<!doctype html") || start.starts_with("<html") {
+		"text/html; charset=utf-8"
+	} else {
+		"text/plain; charset=utf-8"
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_by_extension() {
+		assert_eq!(by_extension("index.html"), Some("text/html; charset=utf-8"));
+		assert_eq!(by_extension("app.js"), Some("application/javascript; charset=utf-8"));
+		assert_eq!(by_extension("/ipfs/Qm../photo.JPG"), Some("image/jpeg"));
+		assert_eq!(by_extension("/ipfs/Qm.x/README"), None);
+		assert_eq!(by_extension("archive.unknown"), None);
+	}
+
+	#[test]
+	fn test_sniff() {
+		assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+		assert_eq!(sniff(b"GIF89a..."), "image/gif");
+		assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+		assert_eq!(sniff(b"\n  <!DOCTYPE html><title>hi</title>"), "text/html; charset=utf-8");
+		assert_eq!(sniff(b"<HTML>"), "text/html; charset=utf-8");
+		assert_eq!(sniff("hello \u{e9}\n".as_bytes()), "text/plain; charset=utf-8");
+		assert_eq!(sniff(b""), "text/plain; charset=utf-8");
+		assert_eq!(sniff(b"\0\x01\x02"), OCTET_STREAM);
+		assert_eq!(sniff(b"\xff\xfe"), OCTET_STREAM);
+	}
+
+	#[test]
+	fn test_sniff_cut_character() {
+		let mut text = vec![b'a'; SNIFF_LEN - 1];
+		text.extend("\u{e9}".as_bytes());
+
+		assert_eq!(sniff(&text), "text/plain; charset=utf-8");
+	}
+}
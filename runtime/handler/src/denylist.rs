@@ -0,0 +1,303 @@
+//! Content the node refuses to serve.
+//!
+//! Public gateway operators must stop serving content they are told to. A rule blocks a cid, a
+//! name, or a path below either, and carries a code explaining why, like `dmca` or `malware`.
+//! Blocked requests of the gateway and of `/api/v0/block/get` are answered with a 410 Gone and the
+//! code. Cids are compared by multihash, so blocking a cid blocks it in every version and codec,
+//! and a gateway path is blocked if any block it resolves through is.
+//!
+//! Rules are loaded from the repo's `denylist` file or a list fetched by the node, one
+//! `<code> <rule>` pair per line, and managed at runtime on `/admin/denylist`:
+//!
+//! - `GET /admin/denylist` lists the rules.
+//! - `POST /admin/denylist?rule=..&code=..` adds a rule or changes its code.
+//! - `POST /admin/denylist/remove?rule=..` removes a rule.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use cid::ToCid;
+
+use crate::gateway::{IPFS_PREFIX, IPNS_PREFIX};
+use crate::route::{get_param, Out};
+
+/// Name of the file in the repo the rules are kept in.
+pub const DENYLIST_FILE: &str = "denylist";
+
+/// Path of the admin routes managing the rules.
+pub const DENYLIST_PATH: &str = "/admin/denylist";
+
+/// What a rule blocks below.
+#[derive(Debug, Clone, PartialEq)]
+enum Root {
+	/// The multihash of a cid.
+	Hash(Vec<u8>),
+	/// An ipns name or dnslink domain.
+	Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+	/// The rule as given.
+	text: String,
+	root: Root,
+	/// Segments of the blocked path below the root, the whole root is blocked if empty.
+	path: Vec<String>,
+	code: String,
+}
+
+impl Rule {
+	/// Parse a cid, `/ipfs/<cid>[/path]` or `/ipns/<name>[/path]`.
+	fn parse(text: &str, code: &str) -> Option<Rule> {
+		if !is_valid_code(code) {
+			return None;
+		}
+		let (root, path) = parse_path(text)?;
+		Some(Rule { text: text.to_owned(), root, path, code: code.to_owned() })
+	}
+
+	fn blocks(&self, root: &Root, path: &[String]) -> bool {
+		self.root == *root && path.starts_with(&self.path)
+	}
+
+	fn to_json(&self) -> String {
+		format!("{{\"rule\":\"{}\",\"code\":\"{}\"}}", escape(&self.text), self.code)
+	}
+}
+
+/// The rules of a node, shared by the handlers of a server.
+#[derive(Debug, Clone, Default)]
+pub struct Denylist {
+	rules: Arc<RwLock<Vec<Rule>>>,
+}
+
+impl Denylist {
+	/// Load the rules from the `denylist` file in `repo`, none if there is no such file.
+	pub fn from_repo(repo: &Path) -> io::Result<Self> {
+		let denylist = Denylist::default();
+		match fs::read_to_string(repo.join(DENYLIST_FILE)) {
+			Ok(contents) => denylist.load(&contents)?,
+			Err(ref err) if err.kind() == io::ErrorKind::NotFound => {},
+			Err(err) => return Err(err),
+		}
+		Ok(denylist)
+	}
+
+	/// Add the `<code> <rule>` lines of `contents`, skipping empty lines and `#` comments. No
+	/// rule is added if a line is invalid.
+	pub fn load(&self, contents: &str) -> io::Result<()> {
+		let mut rules = Vec::new();
+		for (i, line) in contents.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let mut words = line.split_whitespace();
+			let rule = match (words.next(), words.next(), words.next()) {
+				(Some(code), Some(rule), None) => Rule::parse(rule, code),
+				_ => None,
+			};
+			rules.push(rule.ok_or_else(|| io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("line {}: expected `<code> <cid or path>`", i + 1),
+			))?);
+		}
+		for rule in rules {
+			self.insert(rule);
+		}
+		Ok(())
+	}
+
+	/// Block `rule` with `code`, returning `false` if either is invalid.
+	pub fn add(&self, rule: &str, code: &str) -> bool {
+		match Rule::parse(rule, code) {
+			Some(rule) => { self.insert(rule); true },
+			None => false,
+		}
+	}
+
+	/// Unblock `rule`, returning whether it was blocked.
+	pub fn remove(&self, rule: &str) -> bool {
+		let (root, path) = match parse_path(rule) {
+			Some(parsed) => parsed,
+			None => return false,
+		};
+		let mut rules = self.rules.write().expect("lock is never poisoned; qed");
+		let len = rules.len();
+		rules.retain(|rule| rule.root != root || rule.path != path);
+		rules.len() != len
+	}
+
+	/// Code of the rule blocking the gateway path `path`, see `gateway::is_gateway_path`.
+	pub fn blocked_path(&self, path: &str) -> Option<String> {
+		let (root, segments) = parse_path(path)?;
+		self.blocked(&root, &segments)
+	}
+
+	/// Code of the rule blocking the gateway path `path` or any of the blocks `cids` it resolves
+	/// through, so a blocked block can't be reached as the child of another root.
+	pub fn blocked_resolved<S: AsRef<str>>(&self, path: &str, cids: &[S]) -> Option<String> {
+		self.blocked_path(path).or_else(|| cids.iter().find_map(|cid| self.blocked_cid(cid.as_ref())))
+	}
+
+	/// Code of the rule blocking the block `cid`.
+	pub fn blocked_cid(&self, cid: &str) -> Option<String> {
+		let hash = cid.to_cid().ok()?.hash;
+		self.blocked(&Root::Hash(hash), &[])
+	}
+
+	fn blocked(&self, root: &Root, path: &[String]) -> Option<String> {
+		let rules = self.rules.read().expect("lock is never poisoned; qed");
+		rules.iter().find(|rule| rule.blocks(root, path)).map(|rule| rule.code.clone())
+	}
+
+	fn insert(&self, rule: Rule) {
+		let mut rules = self.rules.write().expect("lock is never poisoned; qed");
+		match rules.iter_mut().find(|known| known.root == rule.root && known.path == rule.path) {
+			Some(known) => *known = rule,
+			None => rules.push(rule),
+		}
+	}
+
+	/// Route `/admin/denylist` requests, authorized before getting here.
+	pub(crate) fn route_admin(&self, post: bool, path: &str, query: Option<&str>) -> Out {
+		let param = |name| query.and_then(|q| get_param(q, name));
+
+		match (post, path) {
+			(false, DENYLIST_PATH) => {
+				let rules = self.rules.read().expect("lock is never poisoned; qed");
+				let rules: Vec<_> = rules.iter().map(Rule::to_json).collect();
+				Out::Json(format!("[{}]", rules.join(",")))
+			},
+			(true, DENYLIST_PATH) => {
+				match (param("rule"), param("code")) {
+					(Some(rule), Some(code)) if self.add(rule, code) => Out::Json("{\"added\":true}".into()),
+					_ => Out::Bad("Expected a cid or gateway path as rule and a code of [a-z0-9_-]"),
+				}
+			},
+			(true, "/admin/denylist/remove") => {
+				match param("rule") {
+					Some(rule) if self.remove(rule) => Out::Json("{\"removed\":true}".into()),
+					_ => Out::NotFound("Rule not found"),
+				}
+			},
+			_ => Out::NotFound("Route not found"),
+		}
+	}
+}
+
+/// Whether `path` is one of the admin routes of the denylist.
+pub fn is_denylist_path(path: &str) -> bool {
+	path == DENYLIST_PATH || path.starts_with("/admin/denylist/")
+}
+
+/// Split a cid or gateway path into its root and the segments below it.
+fn parse_path(path: &str) -> Option<(Root, Vec<String>)> {
+	let (is_name, rest) = if path.starts_with(IPFS_PREFIX) {
+		(false, &path[IPFS_PREFIX.len()..])
+	} else if path.starts_with(IPNS_PREFIX) {
+		(true, &path[IPNS_PREFIX.len()..])
+	} else if path.contains('/') {
+		return None;
+	} else {
+		(false, path)
+	};
+
+	let mut segments = rest.split('/').filter(|segment| !segment.is_empty());
+	let root = segments.next()?;
+	let root = if is_name {
+		Root::Name(root.to_ascii_lowercase())
+	} else {
+		Root::Hash(root.to_cid().ok()?.hash)
+	};
+	Some((root, segments.map(ToOwned::to_owned).collect()))
+}
+
+/// Codes are used in JSON bodies unescaped.
+fn is_valid_code(code: &str) -> bool {
+	!code.is_empty() && code.len() <= 64
+		&& code.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_' || b == b'-')
+}
+
+fn escape(text: &str) -> String {
+	text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CID_V0: &str = "QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA";
+
+	fn cid_v1() -> String {
+		let cid = CID_V0.to_cid().unwrap();
+		::cid::Cid::new(::cid::Codec::Raw, ::cid::Version::V1, &cid.hash).to_string()
+	}
+
+	#[test]
+	fn test_blocked_cid() {
+		let denylist = Denylist::default();
+		assert!(denylist.add(CID_V0, "dmca"));
+
+		assert_eq!(denylist.blocked_cid(CID_V0), Some("dmca".into()));
+		assert_eq!(denylist.blocked_cid(&cid_v1()), Some("dmca".into()));
+		assert_eq!(denylist.blocked_path(&format!("/ipfs/{}/a/b.txt", cid_v1())), Some("dmca".into()));
+		assert_eq!(denylist.blocked_cid("z43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM"), None);
+	}
+
+	#[test]
+	fn test_blocked_path() {
+		let denylist = Denylist::default();
+		denylist.load(&format!("# rules\nmalware /ipfs/{}/bad\nlegal /ipns/Example.com/private/\n", CID_V0)).unwrap();
+
+		assert_eq!(denylist.blocked_path(&format!("/ipfs/{}/bad/file", CID_V0)), Some("malware".into()));
+		assert_eq!(denylist.blocked_path(&format!("/ipfs/{}/badge", CID_V0)), None);
+		assert_eq!(denylist.blocked_path(&format!("/ipfs/{}", CID_V0)), None);
+		assert_eq!(denylist.blocked_path("/ipns/example.com/private/a"), Some("legal".into()));
+		assert_eq!(denylist.blocked_path("/ipns/example.com/public"), None);
+		assert_eq!(denylist.blocked_cid(CID_V0), None);
+	}
+
+	#[test]
+	fn test_blocked_resolved() {
+		let denylist = Denylist::default();
+		assert!(denylist.add(CID_V0, "dmca"));
+
+		let other = "z43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM";
+		let path = format!("/ipfs/{}/sub", other);
+		assert_eq!(denylist.blocked_path(&path), None);
+		assert_eq!(denylist.blocked_resolved(&path, &[other, CID_V0]), Some("dmca".into()));
+		assert_eq!(denylist.blocked_resolved(&path, &[other]), None);
+		assert_eq!(denylist.blocked_resolved(&format!("/ipfs/{}/a", CID_V0), &[other]), Some("dmca".into()));
+	}
+
+	#[test]
+	fn test_load_invalid() {
+		let denylist = Denylist::default();
+
+		assert!(denylist.load(&format!("dmca {}\nDMCA {}\n", CID_V0, CID_V0)).is_err());
+		assert!(denylist.load("dmca /ipfs/not-a-cid").is_err());
+		assert!(denylist.load("dmca").is_err());
+		// nothing of an invalid list is added.
+		assert_eq!(denylist.blocked_cid(CID_V0), None);
+	}
+
+	#[test]
+	fn test_route_admin() {
+		let denylist = Denylist::default();
+		let rule = format!("rule=/ipfs/{}&code=dmca", CID_V0);
+
+		assert_eq!(denylist.route_admin(true, DENYLIST_PATH, Some(&rule)), Out::Json(r#"{"added":true}"#.into()));
+		assert_eq!(
+			denylist.route_admin(false, DENYLIST_PATH, None),
+			Out::Json(format!(r#"[{{"rule":"/ipfs/{}","code":"dmca"}}]"#, CID_V0))
+		);
+		assert!(match denylist.route_admin(true, DENYLIST_PATH, Some("rule=x&code=dmca")) { Out::Bad(_) => true, _ => false });
+
+		let remove = format!("rule={}", CID_V0);
+		assert_eq!(denylist.route_admin(true, "/admin/denylist/remove", Some(&remove)), Out::Json(r#"{"removed":true}"#.into()));
+		assert_eq!(denylist.route_admin(true, "/admin/denylist/remove", Some(&remove)), Out::NotFound("Rule not found"));
+	}
+}
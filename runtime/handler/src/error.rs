@@ -1,5 +1,5 @@
-use {multihash, cid, http};
-use route::Out;
+use filesys_errors::{CoreError, ErrorCode};
+use crate::route::Out;
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
@@ -9,9 +9,11 @@ pub enum ServerError {
 	/// Wrapped `std::io::Error`
 	IoError(::std::io::Error),
 	/// Other `hyper` error
-	Other(http::hyper::error::Error),
+	Other(http::hyper::Error),
 	/// Invalid --ipfs-api-interface
-	InvalidInterface
+	InvalidInterface,
+	/// Certificate or private key that could not be loaded
+	Tls(String),
 }
 
 /// Handle IO errors (ports taken when starting the server).
@@ -21,8 +23,8 @@ impl From<::std::io::Error> for ServerError {
 	}
 }
 
-impl From<http::hyper::error::Error> for ServerError {
-	fn from(err: http::hyper::error::Error) -> ServerError {
+impl From<http::hyper::Error> for ServerError {
+	fn from(err: http::hyper::Error) -> ServerError {
 		ServerError::Other(err)
 	}
 }
@@ -33,6 +35,7 @@ impl From<ServerError> for String {
 			ServerError::IoError(err) => err.to_string(),
 			ServerError::Other(err) => err.to_string(),
 			ServerError::InvalidInterface => "Invalid --ipfs-api-interface parameter".into(),
+			ServerError::Tls(reason) => format!("Invalid TLS configuration: {}", reason),
 		}
 	}
 }
@@ -43,13 +46,24 @@ impl ::std::fmt::Display for ServerError {
         	ServerError::IoError(err) => write!(f, "Io Error: {}", err),
         	ServerError::Other(err) => write!(f, "Other error: {}", err),
         	ServerError::InvalidInterface => write!(f, "Invalid interface"),
+        	ServerError::Tls(reason) => write!(f, "Invalid TLS configuration: {}", reason),
         }
     }
 }
 
 impl ::std::error::Error for ServerError {}
 
-#[derive(Debug, PartialEq)]
+impl CoreError for ServerError {
+	fn code(&self) -> ErrorCode {
+		match self {
+			ServerError::IoError(_) => ErrorCode::Io,
+			ServerError::Other(_) => ErrorCode::Internal,
+			ServerError::InvalidInterface | ServerError::Tls(_) => ErrorCode::InvalidInput,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
 	CidParsingFailed,
 	UnsupportedHash,
@@ -60,20 +74,62 @@ pub enum Error {
 	ContractNotFound,
 }
 
+impl Error {
+	/// Reason given in the body of the response.
+	fn reason(&self) -> &'static str {
+		use self::Error::*;
+
+		match *self {
+			UnsupportedHash => "Hash must be Keccak-256",
+			UnsupportedCid => "CID codec not supported",
+			CidParsingFailed => "CID parsing failed",
+			BlockNotFound => "Block not found",
+			TransactionNotFound => "Transaction not found",
+			StateRootNotFound => "State root not found",
+			ContractNotFound => "Contract not found",
+		}
+	}
+}
+
+impl ::std::fmt::Display for Error {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		f.write_str(self.reason())
+	}
+}
+
+impl ::std::error::Error for Error {}
+
+impl CoreError for Error {
+	fn code(&self) -> ErrorCode {
+		use self::Error::*;
+
+		match self {
+			CidParsingFailed => ErrorCode::InvalidInput,
+			UnsupportedHash | UnsupportedCid => ErrorCode::Unsupported,
+			BlockNotFound | TransactionNotFound | StateRootNotFound | ContractNotFound => ErrorCode::NotFound,
+		}
+	}
+}
+
+/// The JSON body of an error answer: its code, a message for humans and whether retrying the
+/// request later may succeed.
+pub(crate) fn error_body(code: ErrorCode, message: &str) -> String {
+	serde_json::json!({
+		"code": code.as_str(),
+		"message": message,
+		"retriable": code.is_retriable(),
+	}).to_string()
+}
+
 /// Convert Error into Out, handy when switching from Rust's Result-based
-/// error handling to Hyper's request handling.
+/// error handling to Hyper's request handling. The status is the one of the error's code.
 impl From<Error> for Out {
 	fn from(err: Error) -> Out {
-		use self::Error::*;
-
-		match err {
-			UnsupportedHash => Out::Bad("Hash must be Keccak-256"),
-			UnsupportedCid => Out::Bad("CID codec not supported"),
-			CidParsingFailed => Out::Bad("CID parsing failed"),
-			BlockNotFound => Out::NotFound("Block not found"),
-			TransactionNotFound => Out::NotFound("Transaction not found"),
-			StateRootNotFound => Out::NotFound("State root not found"),
-			ContractNotFound => Out::NotFound("Contract not found"),
+		let reason = err.reason();
+		match err.code() {
+			ErrorCode::NotFound => Out::NotFound(reason),
+			code if code.http_status() == 400 => Out::Bad(reason),
+			_ => Out::Internal(reason),
 		}
 	}
 }
@@ -0,0 +1,235 @@
+//! Chain event bus and its Server-Sent Events encoding.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::mpsc;
+#[cfg(feature = "in-process")]
+use ipfstools::repo::RepoChange;
+
+/// Interval between two heartbeats sent to every subscriber.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Topics a client can subscribe to on `/eth/v1/events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventTopic {
+	Head,
+	Block,
+	Attestation,
+	FinalizedCheckpoint,
+	/// A dag was pinned
+	Pin,
+	/// A garbage collection of the repo finished
+	Gc,
+}
+
+impl EventTopic {
+	pub fn as_str(&self) -> &'static str {
+		match *self {
+			EventTopic::Head => "head",
+			EventTopic::Block => "block",
+			EventTopic::Attestation => "attestation",
+			EventTopic::FinalizedCheckpoint => "finalized_checkpoint",
+			EventTopic::Pin => "pin",
+			EventTopic::Gc => "gc",
+		}
+	}
+
+	pub fn from_str(s: &str) -> Option<EventTopic> {
+		match s {
+			"head" => Some(EventTopic::Head),
+			"block" => Some(EventTopic::Block),
+			"attestation" => Some(EventTopic::Attestation),
+			"finalized_checkpoint" => Some(EventTopic::FinalizedCheckpoint),
+			"pin" => Some(EventTopic::Pin),
+			"gc" => Some(EventTopic::Gc),
+			_ => None,
+		}
+	}
+
+	/// Parse a comma separated list of topics, as found in the `topics` query parameter.
+	pub fn parse_list(list: &str) -> Option<Vec<EventTopic>> {
+		let topics = list.split(',')
+			.map(EventTopic::from_str)
+			.collect::<Option<Vec<_>>>()?;
+
+		if topics.is_empty() { None } else { Some(topics) }
+	}
+}
+
+/// A single chain event, carrying a JSON encoded payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+	pub topic: EventTopic,
+	pub data: String,
+}
+
+impl Event {
+	pub fn new(topic: EventTopic, data: String) -> Self {
+		Event { topic, data }
+	}
+
+	/// A block imported into the repo.
+	pub fn block(cid: &str) -> Self {
+		Event::new(EventTopic::Block, format!("{{\"cid\":\"{}\"}}", cid))
+	}
+
+	/// The dag below `cid` pinned.
+	pub fn pin(cid: &str) -> Self {
+		Event::new(EventTopic::Pin, format!("{{\"cid\":\"{}\"}}", cid))
+	}
+
+	/// A garbage collection removing `freed` blocks of `bytes` in total.
+	pub fn gc(freed: usize, bytes: u64) -> Self {
+		Event::new(EventTopic::Gc, format!("{{\"freed\":{},\"bytes\":{}}}", freed, bytes))
+	}
+
+	/// Encode as a Server-Sent Events message.
+	pub fn to_sse(&self) -> String {
+		format!("event: {}\ndata: {}\n\n", self.topic.as_str(), self.data)
+	}
+}
+
+struct Subscriber {
+	topics: Vec<EventTopic>,
+	sender: mpsc::UnboundedSender<String>,
+}
+
+/// Fan-out of chain events to the handler's event stream subscribers.
+///
+/// The chain publishes events as it imports blocks; every subscriber receives the SSE encoding
+/// of the events matching its topics, plus a periodic heartbeat comment. Subscribers whose
+/// stream was dropped are removed on the next send.
+#[derive(Clone, Default)]
+pub struct EventBus {
+	subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventBus {
+	pub fn new() -> Self {
+		EventBus::default()
+	}
+
+	/// Spawn a thread sending heartbeats to all subscribers for as long as the bus is alive.
+	pub fn start_heartbeat(&self) {
+		let bus = Arc::downgrade(&self.subscribers);
+
+		thread::spawn(move || loop {
+			thread::sleep(HEARTBEAT_INTERVAL);
+			match bus.upgrade() {
+				Some(subscribers) => EventBus { subscribers }.heartbeat(),
+				None => break,
+			}
+		});
+	}
+
+	/// Register a subscriber for `topics`, returning the stream of SSE messages to send.
+	pub fn subscribe(&self, topics: Vec<EventTopic>) -> mpsc::UnboundedReceiver<String> {
+		let (sender, receiver) = mpsc::unbounded();
+
+		self.subscribers.lock().expect("lock is never poisoned; qed")
+			.push(Subscriber { topics, sender });
+
+		receiver
+	}
+
+	/// Send `event` to every subscriber of its topic.
+	pub fn publish(&self, event: Event) {
+		let message = event.to_sse();
+
+		self.subscribers.lock().expect("lock is never poisoned; qed")
+			.retain(|sub| {
+				!sub.topics.contains(&event.topic)
+					|| sub.sender.unbounded_send(message.clone()).is_ok()
+			});
+	}
+
+	/// Send a heartbeat comment to every subscriber.
+	pub fn heartbeat(&self) {
+		self.subscribers.lock().expect("lock is never poisoned; qed")
+			.retain(|sub| sub.sender.unbounded_send(":\n\n".into()).is_ok());
+	}
+
+	/// Number of live subscribers.
+	pub fn subscriber_count(&self) -> usize {
+		self.subscribers.lock().expect("lock is never poisoned; qed").len()
+	}
+
+	/// Spawn a thread publishing the changes of an in-process repo until the repo is dropped, see
+	/// `Repo::subscribe`.
+	#[cfg(feature = "in-process")]
+	pub fn publish_repo_changes(&self, changes: ::std::sync::mpsc::Receiver<RepoChange>) {
+		let bus = self.clone();
+
+		thread::spawn(move || for change in changes {
+			bus.publish(change.into());
+		});
+	}
+}
+
+#[cfg(feature = "in-process")]
+impl From<RepoChange> for Event {
+	fn from(change: RepoChange) -> Event {
+		match change {
+			RepoChange::BlockImported(cid) => Event::block(&cid.to_string()),
+			RepoChange::Pinned(cid) => Event::pin(&cid.to_string()),
+			RepoChange::GcFinished { freed, bytes } => Event::gc(freed, bytes),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on_stream;
+
+	#[test]
+	fn test_parse_topics() {
+		assert_eq!(
+			EventTopic::parse_list("head,block,attestation,finalized_checkpoint"),
+			Some(vec![EventTopic::Head, EventTopic::Block, EventTopic::Attestation, EventTopic::FinalizedCheckpoint])
+		);
+		assert_eq!(EventTopic::parse_list("pin,gc"), Some(vec![EventTopic::Pin, EventTopic::Gc]));
+		assert_eq!(EventTopic::parse_list("head,foo"), None);
+		assert_eq!(EventTopic::parse_list(""), None);
+	}
+
+	#[test]
+	fn test_publish_filters_topics() {
+		let bus = EventBus::new();
+		let head = bus.subscribe(vec![EventTopic::Head]);
+
+		bus.publish(Event::new(EventTopic::Block, "{}".into()));
+		bus.publish(Event::new(EventTopic::Head, "{\"slot\":\"1\"}".into()));
+		bus.heartbeat();
+
+		let mut head = block_on_stream(head);
+		assert_eq!(head.next(), Some("event: head\ndata: {\"slot\":\"1\"}\n\n".into()));
+		assert_eq!(head.next(), Some(":\n\n".into()));
+	}
+
+	#[test]
+	fn test_drop_closed_subscribers() {
+		let bus = EventBus::new();
+		drop(bus.subscribe(vec![EventTopic::Head]));
+
+		bus.heartbeat();
+
+		assert_eq!(bus.subscriber_count(), 0);
+	}
+
+	#[test]
+	#[cfg(feature = "in-process")]
+	fn test_publish_repo_changes() {
+		let bus = EventBus::new();
+		let gc = bus.subscribe(vec![EventTopic::Gc]);
+		let (changes, receiver) = ::std::sync::mpsc::channel();
+
+		bus.publish_repo_changes(receiver);
+		changes.send(RepoChange::GcFinished { freed: 2, bytes: 64 }).unwrap();
+
+		let mut gc = block_on_stream(gc);
+		assert_eq!(gc.next(), Some("event: gc\ndata: {\"freed\":2,\"bytes\":64}\n\n".into()));
+	}
+}
@@ -0,0 +1,46 @@
+//! Gateway paths, `/ipfs/<cid>/sub/path` and `/ipns/<name>/sub/path`.
+//!
+//! A path is resolved to the file it ends in and served with the content type of its extension,
+//! or sniffed from its first bytes if it has none. Files are served from the API's origin, so
+//! they are sandboxed and cannot read what the web UI stores.
+
+use crate::content_type;
+
+/// Path prefix of immutable paths.
+pub const IPFS_PREFIX: &str = "/ipfs/";
+/// Path prefix of names, resolved through ipns records or dnslink.
+pub const IPNS_PREFIX: &str = "/ipns/";
+
+/// Served files run in a sandbox of their own origin and may not be framed by other sites.
+pub const CONTENT_SECURITY_POLICY: &str = "sandbox allow-scripts allow-forms allow-popups; frame-ancestors 'self'";
+
+/// Whether `path` is a gateway path naming a cid or a name.
+pub fn is_gateway_path(path: &str) -> bool {
+	[IPFS_PREFIX, IPNS_PREFIX].iter().any(|prefix| path.starts_with(prefix) && path.len() > prefix.len())
+}
+
+/// Content type of the file at `path` holding `bytes`.
+pub fn content_type(path: &str, bytes: &[u8]) -> &'static str {
+	content_type::by_extension(path).unwrap_or_else(|| content_type::sniff(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_is_gateway_path() {
+		assert!(is_gateway_path("/ipfs/QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA"));
+		assert!(is_gateway_path("/ipns/example.com/index.html"));
+		assert!(!is_gateway_path("/ipfs/"));
+		assert!(!is_gateway_path("/ipfsx/Qm"));
+		assert!(!is_gateway_path("/api/v0/block/get"));
+	}
+
+	#[test]
+	fn test_content_type() {
+		assert_eq!(content_type("/ipfs/Qm../style.css", b"body {}"), "text/css; charset=utf-8");
+		assert_eq!(content_type("/ipfs/Qm..", b"<!doctype html>"), "text/html; charset=utf-8");
+		assert_eq!(content_type("/ipns/example.com/blob", b"\0\x01"), content_type::OCTET_STREAM);
+	}
+}
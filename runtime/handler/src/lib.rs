@@ -1,33 +1,86 @@
 extern crate multihash;
 extern crate cid;
 extern crate unicase;
+extern crate filesys_errors;
 
 extern crate rlp;
+extern crate serde_cbor;
 extern crate parity_bytes as bytes;
 extern crate ethereum_types;
-extern crate jsonrpc_core as core;
 extern crate jsonrpc_http_server as http;
+extern crate tokio;
+extern crate tokio_rustls;
+extern crate sha1;
+extern crate serde_json;
+extern crate futures;
+extern crate base64;
+extern crate repo;
+extern crate tempfile;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate prometheus;
+#[cfg(feature = "embedded-webui")]
+#[macro_use]
+extern crate include_dir;
+#[cfg(feature = "in-process")]
+extern crate ipfstools;
 
+pub mod auth;
+pub mod cbor;
+pub mod content_type;
+pub mod denylist;
 pub mod error;
+pub mod events;
+pub mod gateway;
+pub mod metrics;
+pub mod multipart;
+pub mod pubsub;
+pub mod range;
 mod route;
+pub mod shutdown;
+pub mod spec;
+pub mod timeout;
+pub mod tls;
+pub mod webui;
+pub mod websocket;
 
+use std::convert::Infallible;
+use std::io;
+use std::pin::Pin;
 use std::thread;
 use std::sync::{mpsc, Arc};
 use std::net::{SocketAddr, IpAddr};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
-use core::futures::future::{self, FutureResult};
-use core::futures::{self, Future};
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::stream::{self, StreamExt};
 use filesys_api::FileSysClient;
 use http::hyper::{self, server, Method, StatusCode, Body,
 	header::{self, HeaderValue},
+	server::accept::{self, Accept},
+	service::make_service_fn,
 };
 
-use error::ServerError;
-use route::Out;
+use crate::auth::{ApiKeys, Scope};
+use crate::denylist::Denylist;
+use crate::error::{error_body, ServerError};
+use filesys_errors::ErrorCode;
+use crate::events::EventBus;
+use crate::pubsub::{PubsubRouter, PUBSUB_SUB_PATH};
+use crate::route::{get_param, Out, ADD_PATH, MAX_ADD_LEN, REPO_STATS_PATH};
+use repo::RepoStat;
+use crate::shutdown::{InFlight, InFlightGuard, ShutdownReport};
+use crate::timeout::{timeout_body, Stage, Timeouts};
+use crate::tls::TlsConfig;
+use tokio::runtime::Runtime;
 
 pub use http::{AccessControlAllowOrigin, Host, DomainsValidation};
 
 /// Request/response handler
+#[derive(Clone)]
 pub struct Handler {
 	/// Allowed CORS domains
 	cors_domains: Option<Vec<AccessControlAllowOrigin>>,
@@ -35,6 +88,25 @@ pub struct Handler {
 	allowed_hosts: Option<Vec<Host>>,
 	/// Reference to the Blockchain Client
 	client: Arc<FileSysClient>,
+	/// Chain events served on `/eth/v1/events`
+	events: EventBus,
+	/// Execution timeout of each route
+	timeouts: Timeouts,
+	/// Stage of the request being routed
+	stage: Stage,
+	/// API keys requests are authorized by scope and accounted with, every request is let through
+	/// if `None`
+	keys: Option<ApiKeys>,
+	/// Requests in flight, drained on shutdown
+	in_flight: InFlight,
+	/// Content refused to be served
+	denylist: Denylist,
+	/// Topics streamed on `/api/v0/pubsub/sub`
+	pubsub: PubsubRouter,
+	/// Measures the datastores of the repo for `/api/v0/repo/stat`, the route is not available if `None`
+	repo_stat: Option<RepoStatFn>,
+	/// Longest body of an `/api/v0/add` request
+	max_add_len: u64,
 }
 
 impl Handler {
@@ -42,73 +114,361 @@ impl Handler {
 		&*self.client
 	}
 
-	pub fn new(cors: DomainsValidation<AccessControlAllowOrigin>, hosts: DomainsValidation<Host>, client: Arc<Client>) -> Self {
+	pub fn new(cors: DomainsValidation<AccessControlAllowOrigin>, hosts: DomainsValidation<Host>, client: Arc<Client>, events: EventBus, timeouts: Timeouts, keys: Option<ApiKeys>) -> Self {
 		Handler {
 			cors_domains: cors.into(),
 			allowed_hosts: hosts.into(),
 			client: client,
+			events: events,
+			timeouts: timeouts,
+			stage: Stage::new(),
+			keys: keys,
+			in_flight: InFlight::default(),
+			denylist: Denylist::default(),
+			pubsub: PubsubRouter::default(),
+			repo_stat: None,
+			max_add_len: MAX_ADD_LEN,
 		}
 	}
-	pub fn on_request(&self, req: hyper::Request<Body>) -> (Option<HeaderValue>, Out) {
+
+	pub fn on_request(&self, req: hyper::Request<Body>) -> (Option<HeaderValue>, RouteFuture) {
 		match *req.method() {
 			Method::GET | Method::POST => {},
-			_ => return (None, Out::Bad("Invalid Request")),
+			_ => return (None, future::ready(Out::Bad("Invalid Request")).boxed()),
 		}
 
 		if !http::is_host_allowed(&req, &self.allowed_hosts) {
-			return (None, Out::Bad("Disallowed Host header"));
+			return (None, future::ready(Out::Bad("Disallowed Host header")).boxed());
 		}
 
 		let cors_header = http::cors_allow_origin(&req, &self.cors_domains);
 		if cors_header == http::AllowCors::Invalid {
-			return (None, Out::Bad("Disallowed Origin header"));
+			return (None, future::ready(Out::Bad("Disallowed Origin header")).boxed());
+		}
+
+		let path = req.uri().path().to_owned();
+		let query = req.uri().query().map(ToOwned::to_owned);
+
+		// the UI's assets are public, its API requests carry the key.
+		let public = cfg!(feature = "embedded-webui") && webui::is_webui_path(&path);
+
+		if let Some(keys) = self.keys.as_ref().filter(|_| !public) {
+			let token = bearer_token(&req);
+			let post = *req.method() == Method::POST;
+			if path == "/admin/keys" || path.starts_with("/admin/keys/") {
+				let out = keys.route_admin(token, post, &path, query.as_ref().map(|q| &**q));
+				return (cors_header.into(), future::ready(out).boxed());
+			}
+			if path == REPO_STATS_PATH {
+				if !keys.is_admin(token) {
+					return (cors_header.into(), future::ready(Out::Unauthorized("Admin token required")).boxed());
+				}
+				return (cors_header.into(), self.route_with_timeout(path, query));
+			}
+			if denylist::is_denylist_path(&path) {
+				if !keys.is_admin(token) {
+					return (cors_header.into(), future::ready(Out::Unauthorized("Admin token required")).boxed());
+				}
+			} else if let Err(denied) = keys.charge(token, Scope::of_route(post, &path)) {
+				// the bytes of an upload are charged as they are received, see `route_add`.
+				return (cors_header.into(), future::ready(denied.into()).boxed());
+			}
+		}
+
+		// the rules can only be managed by an admin, so not without API keys.
+		if denylist::is_denylist_path(&path) && self.keys.is_some() {
+			let out = self.denylist.route_admin(*req.method() == Method::POST, &path, query.as_ref().map(|q| &**q));
+			return (cors_header.into(), future::ready(out).boxed());
+		}
+
+		if path == PUBSUB_SUB_PATH {
+			let out = self.route_pubsub_sub(req, query.as_ref().map(|q| &**q));
+			return (cors_header.into(), future::ready(out).boxed());
+		}
+
+		if path == ADD_PATH && *req.method() == Method::POST {
+			return (cors_header.into(), self.route_add(req, query.as_ref().map(|q| &**q)));
+		}
+
+		// only GET requests can ask for a range.
+		let range = match *req.method() {
+			Method::GET => req.headers().get(header::RANGE).and_then(|value| value.to_str().ok()).map(ToOwned::to_owned),
+			_ => None,
+		};
+		let out = self.route_with_timeout(path, query)
+			.map(move |out| range::apply(range.as_ref().map(|r| &**r), out));
+
+		return (cors_header.into(), out.boxed());
+	}
+
+	/// Route the request, resolving to `Out::Timeout` if it takes longer than the route's timeout.
+	/// A timed out route is dropped, which stops it waiting on the node.
+	fn route_with_timeout(&self, path: String, query: Option<String>) -> RouteFuture {
+		let timeout = self.timeouts.for_route(&path);
+		let handler = Handler { stage: Stage::new(), ..self.clone() };
+		let stage = handler.stage.clone();
+
+		let route = handler.route(&path, query.as_ref().map(|q| &**q));
+		async move {
+			match tokio::time::timeout(timeout, route).await {
+				Ok(out) => out,
+				Err(_) => Out::Timeout { stage: stage.current(), timeout },
+			}
+		}.boxed()
+	}
+
+	/// Stream the `multipart/form-data` body of an `/api/v0/add` request to `add`, failing it once
+	/// it exceeds `max_add_len` bytes. With API keys, the bytes received count against the key's
+	/// daily quota. The files are pinned unless the query asks for `pin=false`.
+	fn route_add(&self, req: hyper::Request<Body>, query: Option<&str>) -> RouteFuture {
+		let boundary = req.headers().get(header::CONTENT_TYPE)
+			.and_then(|value| value.to_str().ok())
+			.and_then(multipart::boundary)
+			.map(ToOwned::to_owned);
+		let boundary = match boundary {
+			Some(boundary) => boundary,
+			None => return future::ready(Out::Bad("Expected a multipart/form-data body")).boxed(),
+		};
+
+		let token = bearer_token(&req).map(ToOwned::to_owned);
+		let keys = self.keys.clone();
+		let max_add_len = self.max_add_len;
+		let mut received = 0;
+		let body = req.into_body()
+			.map(move |chunk| {
+				let chunk = chunk.map_err(|_| Out::Bad("Failed to read the request body"))?;
+				received += chunk.len() as u64;
+				if received > max_add_len {
+					return Err(Out::Bad("Upload too large"));
+				}
+				if let Some(keys) = keys.as_ref() {
+					keys.charge_bytes(token.as_ref().map(|t| &**t), chunk.len() as u64)?;
+				}
+				Ok(chunk)
+			});
+
+		let pin = query.and_then(|q| get_param(q, "pin")) != Some("false");
+		let handler = Handler { stage: Stage::new(), ..self.clone() };
+		handler.add(&boundary, pin, body)
+	}
+
+	/// Upgrade a `/api/v0/pubsub/sub?arg=<topic>` request to a WebSocket streaming the messages
+	/// of the topic.
+	fn route_pubsub_sub(&self, req: hyper::Request<Body>, query: Option<&str>) -> Out {
+		let topic = match query.and_then(|q| get_param(q, "arg")) {
+			Some(topic) if !topic.is_empty() => topic.to_owned(),
+			_ => return Out::Bad("Expected a topic as arg"),
+		};
+		if !websocket::is_upgrade(&req) {
+			return Out::Bad("Expected a WebSocket upgrade");
 		}
+		let accept = match req.headers().get(header::SEC_WEBSOCKET_KEY).and_then(|key| key.to_str().ok()) {
+			Some(key) => websocket::accept_key(key),
+			None => return Out::Bad("Missing Sec-WebSocket-Key header"),
+		};
+
+		let messages = self.pubsub.subscribe(&topic);
+		// the connection is handed over once the response is sent.
+		tokio::spawn(async move {
+			if let Ok(upgraded) = hyper::upgrade::on(req).await {
+				websocket::serve(upgraded, messages).await;
+			}
+		});
 
-		let path = req.uri().path();
-		let query = req.uri().query();
-		return (cors_header.into(), self.route(path, query));
+		Out::SwitchingProtocols { accept }
 	}
 }
 
-impl hyper::service::Service for Handler {
-	type ReqBody = Body;
-	type ResBody = Body;
+/// The token of an `Authorization: Bearer <token>` header.
+fn bearer_token(req: &hyper::Request<Body>) -> Option<&str> {
+	let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+	if value.len() > 7 && value[..7].eq_ignore_ascii_case("bearer ") {
+		Some(value[7..].trim())
+	} else {
+		None
+	}
+}
+
+/// Outcome of routing a request.
+pub type RouteFuture = BoxFuture<'static, Out>;
+
+/// Measures the datastores of the repo, see `repo::Repo::stat`.
+pub type RepoStatFn = Arc<dyn Fn() -> Result<RepoStat, repo::Error> + Send + Sync>;
+
+impl hyper::service::Service<hyper::Request<Body>> for Handler {
+	type Response = hyper::Response<Body>;
 	type Error = hyper::Error;
-	type Future = FutureResult<hyper::Response<Body>, Self::Error>;
+	type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, request: hyper::Request<Body>) -> Self::Future {
+		let route = metrics::route_label(request.uri().path());
 
-	fn call(&mut self, request: hyper::Request<Self::ReqBody>) -> Self::Future {
+		// the parameters of a CBOR body are moved to the query before routing.
+		if cbor::has_params_body(&request) {
+			let mut handler = self.clone();
+			return async move {
+				match cbor::read_params(request).await {
+					Ok(request) => handler.call(request).await,
+					Err(out) => {
+						let in_flight = handler.in_flight.enter();
+						let res = respond(out, &handler.events, in_flight)
+							.expect("Response builder: Parsing 'content-type' header name will not fail; qed");
+						metrics::observe(route, res.status());
+						Ok(res)
+					},
+				}
+			}.boxed();
+		}
+
+		let in_flight = self.in_flight.enter();
+		let answer_cbor = cbor::accepts_cbor(&request);
 		let (cors_header, out) = self.on_request(request);
+		let events = self.events.clone();
 
-		let mut res = match out {
-			Out::OctetStream(bytes) => {
-				hyper::Response::builder()
-					.status(StatusCode::OK)
-					.header("content-type", HeaderValue::from_static("application/octet-stream"))
-					.body(bytes.into())
-			},
-			Out::NotFound(reason) => {
-				hyper::Response::builder()
-					.status(StatusCode::NOT_FOUND)
-					.header("content-type", HeaderValue::from_static("text/plain; charset=utf-8"))
-					.body(reason.into())
-			},
-			Out::Bad(reason) => {
-				hyper::Response::builder()
-					.status(StatusCode::BAD_REQUEST)
-					.header("content-type", HeaderValue::from_static("text/plain; charset=utf-8"))
-					.body(reason.into())
+		out.map(move |out| {
+			let mut res = respond(if answer_cbor { cbor::encode(out) } else { out }, &events, in_flight)
+				.expect("Response builder: Parsing 'content-type' header name will not fail; qed");
+
+			if let Some(cors_header) = cors_header {
+				res.headers_mut().append(header::ACCESS_CONTROL_ALLOW_ORIGIN, cors_header);
+				res.headers_mut().append(header::VARY, HeaderValue::from_static("origin"));
 			}
-		}.expect("Response builder: Parsing 'content-type' header name will not fail; qed");
 
-		if let Some(cors_header) = cors_header {
-			res.headers_mut().append(header::ACCESS_CONTROL_ALLOW_ORIGIN, cors_header);
-			res.headers_mut().append(header::VARY, HeaderValue::from_static("origin"));
-		}
+			metrics::observe(route, res.status());
+			Ok(res)
+		}).boxed()
+	}
+}
 
-		future::ok(res)
+/// Build the response for `out`, the request stays in flight until it is built or, for event
+/// streams, until the stream ends.
+fn respond(out: Out, events: &EventBus, in_flight: InFlightGuard) -> hyper::http::Result<hyper::Response<Body>> {
+	match out {
+		Out::OctetStream(bytes) => {
+			hyper::Response::builder()
+				.status(StatusCode::OK)
+				.header("content-type", HeaderValue::from_static("application/octet-stream"))
+				.header("accept-ranges", HeaderValue::from_static("bytes"))
+				.body(bytes.into())
+		},
+		Out::Content { content_type, bytes } => {
+			hyper::Response::builder()
+				.status(StatusCode::OK)
+				.header("content-type", content_type)
+				.header("accept-ranges", HeaderValue::from_static("bytes"))
+				.header("content-security-policy", gateway::CONTENT_SECURITY_POLICY)
+				.header("x-content-type-options", HeaderValue::from_static("nosniff"))
+				.body(bytes.into())
+		},
+		Out::PartialContent { content_type, bytes, first, total } => {
+			let last = first + bytes.len() as u64 - 1;
+			// a range of a gateway file is as untrusted as the whole file.
+			hyper::Response::builder()
+				.status(StatusCode::PARTIAL_CONTENT)
+				.header("content-type", content_type)
+				.header("accept-ranges", HeaderValue::from_static("bytes"))
+				.header("content-range", format!("bytes {}-{}/{}", first, last, total).as_str())
+				.header("content-security-policy", gateway::CONTENT_SECURITY_POLICY)
+				.header("x-content-type-options", HeaderValue::from_static("nosniff"))
+				.body(bytes.into())
+		},
+		Out::RangeNotSatisfiable { total } => {
+			hyper::Response::builder()
+				.status(StatusCode::RANGE_NOT_SATISFIABLE)
+				.header("content-type", HeaderValue::from_static("text/plain; charset=utf-8"))
+				.header("content-range", format!("bytes */{}", total).as_str())
+				.body("Range not satisfiable".into())
+		},
+		Out::NotFound(reason) => error_response(ErrorCode::NotFound, reason),
+		Out::Bad(reason) => error_response(ErrorCode::InvalidInput, reason),
+		Out::Events(topics) => {
+			let stream = in_flight.hold(events.subscribe(topics)).map(Ok::<_, io::Error>);
+
+			hyper::Response::builder()
+				.status(StatusCode::OK)
+				.header("content-type", HeaderValue::from_static("text/event-stream"))
+				.header("cache-control", HeaderValue::from_static("no-cache"))
+				.body(Body::wrap_stream(stream))
+		},
+		Out::Timeout { stage, timeout } => {
+			hyper::Response::builder()
+				.status(StatusCode::GATEWAY_TIMEOUT)
+				.header("content-type", HeaderValue::from_static("application/json"))
+				.body(timeout_body(stage, timeout).into())
+		},
+		Out::Internal(reason) => error_response(ErrorCode::Internal, reason),
+		Out::Asset { content_type, cache_control, body } => {
+			hyper::Response::builder()
+				.status(StatusCode::OK)
+				.header("content-type", content_type)
+				.header("cache-control", cache_control)
+				.header("content-security-policy", webui::CONTENT_SECURITY_POLICY)
+				.header("x-content-type-options", HeaderValue::from_static("nosniff"))
+				.body(body.into())
+		},
+		Out::Unauthorized(reason) => {
+			hyper::Response::builder()
+				.status(StatusCode::UNAUTHORIZED)
+				.header("content-type", HeaderValue::from_static("text/plain; charset=utf-8"))
+				.header("www-authenticate", HeaderValue::from_static("Bearer"))
+				.body(reason.into())
+		},
+		Out::Forbidden(reason) => {
+			hyper::Response::builder()
+				.status(StatusCode::FORBIDDEN)
+				.header("content-type", HeaderValue::from_static("text/plain; charset=utf-8"))
+				.body(reason.into())
+		},
+		Out::SwitchingProtocols { accept } => {
+			hyper::Response::builder()
+				.status(StatusCode::SWITCHING_PROTOCOLS)
+				.header(header::UPGRADE, HeaderValue::from_static("websocket"))
+				.header(header::CONNECTION, HeaderValue::from_static("upgrade"))
+				.header(header::SEC_WEBSOCKET_ACCEPT, accept.as_str())
+				.body(Body::empty())
+		},
+		Out::Gone { code } => {
+			hyper::Response::builder()
+				.status(StatusCode::GONE)
+				.header("content-type", HeaderValue::from_static("application/json"))
+				.body(format!("{{\"error\":\"Content blocked\",\"code\":\"{}\"}}", code).into())
+		},
+		Out::TooManyRequests { retry_after } => {
+			// round up so a client retrying on time finds the quota reset.
+			let secs = retry_after.as_secs() + if retry_after.subsec_nanos() > 0 { 1 } else { 0 };
+			hyper::Response::builder()
+				.status(StatusCode::TOO_MANY_REQUESTS)
+				.header("content-type", HeaderValue::from_static("text/plain; charset=utf-8"))
+				.header("retry-after", secs.to_string().as_str())
+				.body("API key quota exceeded".into())
+		},
+		Out::Json(body) => {
+			hyper::Response::builder()
+				.status(StatusCode::OK)
+				.header("content-type", HeaderValue::from_static("application/json"))
+				.body(body.into())
+		},
+		Out::Cbor(bytes) => {
+			hyper::Response::builder()
+				.status(StatusCode::OK)
+				.header("content-type", HeaderValue::from_static(cbor::CBOR))
+				.body(bytes.into())
+		},
 	}
 }
 
+/// Answer with the status of `code` and the JSON body of `error_body`.
+fn error_response(code: ErrorCode, message: &str) -> hyper::http::Result<hyper::Response<Body>> {
+	hyper::Response::builder()
+		.status(code.http_status())
+		.header("content-type", HeaderValue::from_static("application/json"))
+		.body(error_body(code, message).into())
+}
+
 /// Add current interface (default: "127.0.0.1:5001") to list of allowed hosts
 fn include_current_interface(mut hosts: Vec<Host>, interface: String, port: u16) -> Vec<Host> {
 	hosts.push(match port {
@@ -121,57 +481,191 @@ fn include_current_interface(mut hosts: Vec<Host>, interface: String, port: u16)
 
 #[derive(Debug)]
 pub struct Listening {
-	close: Option<futures::sync::oneshot::Sender<()>>,
-	thread: Option<thread::JoinHandle<()>>,
+	/// Sends the time open connections are given to finish
+	close: Option<oneshot::Sender<Duration>>,
+	/// Resolves to the number of requests aborted at the deadline
+	thread: Option<thread::JoinHandle<usize>>,
+	in_flight: InFlight,
 }
 
+impl Listening {
+	/// Stop accepting connections and wait up to `timeout` for the requests in flight to finish.
+	/// Connections still open at the deadline are closed.
+	pub fn shutdown(mut self, timeout: Duration) -> ShutdownReport {
+		self.stop(timeout)
+	}
+
+	fn stop(&mut self, timeout: Duration) -> ShutdownReport {
+		let in_flight = self.in_flight.count();
+		if let Some(close) = self.close.take() {
+			// the server is gone already if the receiver is.
+			let _ = close.send(timeout);
+		}
+		let aborted = match self.thread.take() {
+			Some(thread) => thread.join().unwrap_or(in_flight),
+			None => 0,
+		};
+
+		ShutdownReport { in_flight, aborted }
+	}
+}
+
+/// Dropping the server closes every connection right away.
 impl Drop for Listening {
 	fn drop(&mut self) {
-		self.close.take().unwrap().send(()).unwrap();
-		let _ = self.thread.take().unwrap().join();
+		self.stop(Duration::from_secs(0));
+	}
+}
+
+/// Options of the API server, see `start_server`.
+#[derive(Clone)]
+pub struct ServerConfig {
+	/// Port to listen on
+	pub port: u16,
+	/// Address to listen on, also allowed as `Host` header
+	pub interface: String,
+	/// Allowed CORS domains
+	pub cors: DomainsValidation<AccessControlAllowOrigin>,
+	/// Hostnames allowed in the `Host` request header
+	pub hosts: DomainsValidation<Host>,
+	/// Reference to the Blockchain Client
+	pub client: Arc<FileSysClient>,
+	/// Chain events served on `/eth/v1/events`
+	pub events: EventBus,
+	/// Topics streamed on `/api/v0/pubsub/sub`
+	pub pubsub: PubsubRouter,
+	/// Execution timeout of each route
+	pub timeouts: Timeouts,
+	/// API keys requests are authorized by scope and accounted with, every request is let through
+	/// if `None`
+	pub keys: Option<ApiKeys>,
+	/// Content refused to be served
+	pub denylist: Denylist,
+	/// Measures the datastores of the repo for `/api/v0/repo/stat`, the route is not available if `None`
+	pub repo_stat: Option<RepoStatFn>,
+	/// Certificate and key to serve HTTPS with, HTTP is served if `None`
+	pub tls: Option<TlsConfig>,
+	/// Longest body of an `/api/v0/add` request
+	pub max_add_len: u64,
+}
+
+impl ServerConfig {
+	/// Serve `client` on `interface:port` over HTTP, with the defaults of every other option: no
+	/// CORS domains, any `Host` header and no API keys.
+	pub fn new(port: u16, interface: String, client: Arc<FileSysClient>) -> Self {
+		ServerConfig {
+			port: port,
+			interface: interface,
+			cors: DomainsValidation::Disabled,
+			hosts: DomainsValidation::Disabled,
+			client: client,
+			events: EventBus::new(),
+			pubsub: PubsubRouter::new(),
+			timeouts: Timeouts::default(),
+			keys: None,
+			denylist: Denylist::default(),
+			repo_stat: None,
+			tls: None,
+			max_add_len: MAX_ADD_LEN,
+		}
 	}
 }
 
-pub fn start_server(
-	port: u16,
-	interface: String,
-	cors: DomainsValidation<AccessControlAllowOrigin>,
-	hosts: DomainsValidation<Host>,
-	client: Arc<FileSysClient>
-) -> Result<Listening, ServerError> {
+/// Start the API server on `config.interface:config.port`, serving HTTPS only if `config.tls` is
+/// given.
+pub fn start_server(config: ServerConfig) -> Result<Listening, ServerError> {
+	let ServerConfig { port, interface, cors, hosts, client, events, pubsub, timeouts, keys, denylist, repo_stat, tls, max_add_len } = config;
 
 	let ip: IpAddr = interface.parse().map_err(|_| ServerError::InvalidInterface)?;
 	let addr = SocketAddr::new(ip, port);
 	let hosts: Option<Vec<_>> = hosts.into();
 	let hosts: DomainsValidation<_> = hosts.map(move |hosts| include_current_interface(hosts, interface, port)).into();
 
-	let (close, shutdown_signal) = futures::sync::oneshot::channel::<()>();
+	let acceptor = match tls {
+		Some(tls) => Some(tls.acceptor()?),
+		None => None,
+	};
+
+	events.start_heartbeat();
+
+	let in_flight = InFlight::default();
+	let (close, shutdown_signal) = oneshot::channel::<Duration>();
 	let (tx, rx) = mpsc::sync_channel::<Result<(), ServerError>>(1);
+	let server_in_flight = in_flight.clone();
 	let thread = thread::spawn(move || {
 		let send = |res| tx.send(res).expect("rx end is never dropped; qed");
 
-		let server_bldr = match server::Server::try_bind(&addr) {
-			Ok(s) => s,
+		let runtime = match Runtime::new() {
+			Ok(runtime) => runtime,
+			Err(err) => {
+				send(Err(ServerError::from(err)));
+				return 0;
+			}
+		};
+		// the listener is registered with the runtime it is bound in.
+		let incoming = match runtime.block_on(async { server::conn::AddrIncoming::bind(&addr) }) {
+			Ok(incoming) => incoming,
 			Err(err) => {
 				send(Err(ServerError::from(err)));
-				return;
+				return 0;
 			}
 		};
 
-		let new_service = move || {
-			Ok::<_, ServerError>(
-				Handler::new(cors.clone(), hosts.clone(), client.clone())
-			)
+		let in_flight = server_in_flight.clone();
+		let new_handler = move || {
+			Handler {
+				in_flight: in_flight.clone(),
+				denylist: denylist.clone(),
+				pubsub: pubsub.clone(),
+				repo_stat: repo_stat.clone(),
+				max_add_len: max_add_len,
+				..Handler::new(cors.clone(), hosts.clone(), client.clone(), events.clone(), timeouts.clone(), keys.clone())
+			}
 		};
 
-		let server = server_bldr
-	        .serve(new_service)
-	        .map_err(|_| ())
-	        .select(shutdown_signal.map_err(|_| ()))
-	        .then(|_| Ok(()));
+		// on shutdown no connections are accepted anymore, the open ones are closed once they
+		// are idle or at the deadline.
+		let shutdown_signal = shutdown_signal.shared();
+		let graceful = shutdown_signal.clone().map(|_| ());
+		let server: BoxFuture<'static, ()> = match acceptor {
+			Some(acceptor) => {
+				let mut incoming = incoming;
+				// a failed or stalled handshake only drops its own connection.
+				let incoming = stream::poll_fn(move |cx| Pin::new(&mut incoming).poll_accept(cx))
+					.map(move |stream| {
+						let acceptor = acceptor.clone();
+						async move {
+							let handshake = acceptor.accept(stream.ok()?);
+							tokio::time::timeout(tls::HANDSHAKE_TIMEOUT, handshake).await.ok()?.ok()
+						}
+					})
+					.buffer_unordered(tls::MAX_PENDING_HANDSHAKES)
+					.filter_map(|stream| future::ready(stream.map(Ok::<_, io::Error>)));
+				server::Server::builder(accept::from_stream(incoming))
+					.serve(make_service_fn(move |_| future::ok::<_, Infallible>(new_handler())))
+					.with_graceful_shutdown(graceful)
+					.map(|_| ())
+					.boxed()
+			},
+			None => server::Server::builder(incoming)
+				.serve(make_service_fn(move |_| future::ok::<_, Infallible>(new_handler())))
+				.with_graceful_shutdown(graceful)
+				.map(|_| ())
+				.boxed(),
+		};
+		let deadline = async move {
+			if let Ok(timeout) = shutdown_signal.await {
+				tokio::time::sleep(timeout).await;
+			}
+		};
 
-	    hyper::rt::run(server);
 		send(Ok(()));
+		runtime.block_on(future::select(server, deadline.boxed()));
+
+		let aborted = server_in_flight.count();
+		// drops the connections still open.
+		runtime.shutdown_background();
+		aborted
 	});
 
 	// Wait for server to start successfuly.
@@ -180,5 +674,44 @@ pub fn start_server(
 	Ok(Listening {
 		close: close.into(),
 		thread: thread.into(),
+		in_flight,
 	})
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+
+	fn error_answer(out: Out) -> (StatusCode, String, serde_json::Value) {
+		let res = respond(out, &EventBus::new(), InFlight::default().enter()).unwrap();
+		let status = res.status();
+		let content_type = res.headers()["content-type"].to_str().unwrap().to_owned();
+		let body = block_on(hyper::body::to_bytes(res.into_body())).unwrap();
+		(status, content_type, serde_json::from_slice(&body).unwrap())
+	}
+
+	#[test]
+	fn respond_not_found() {
+		let (status, content_type, body) = error_answer(Out::NotFound("Block not found"));
+		assert_eq!(status, StatusCode::NOT_FOUND);
+		assert_eq!(content_type, "application/json");
+		assert_eq!(body, serde_json::json!({"code": "not_found", "message": "Block not found", "retriable": false}));
+	}
+
+	#[test]
+	fn respond_bad() {
+		let (status, content_type, body) = error_answer(Out::Bad("CID parsing \"failed\""));
+		assert_eq!(status, StatusCode::BAD_REQUEST);
+		assert_eq!(content_type, "application/json");
+		assert_eq!(body, serde_json::json!({"code": "invalid_input", "message": "CID parsing \"failed\"", "retriable": false}));
+	}
+
+	#[test]
+	fn respond_internal() {
+		let (status, content_type, body) = error_answer(Out::Internal("Adding the file failed"));
+		assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+		assert_eq!(content_type, "application/json");
+		assert_eq!(body, serde_json::json!({"code": "internal", "message": "Adding the file failed", "retriable": false}));
+	}
+}
@@ -0,0 +1,89 @@
+//! Prometheus metrics of the node, served on `/metrics`.
+//!
+//! Metrics are registered with the default registry of the `prometheus` crate, so the repo and
+//! the chain register theirs without knowing of the server, and all of them are served together.
+//! The handler counts the requests it answers by route and status. Requests are counted by the
+//! route they matched rather than their path, so arbitrary paths do not grow the label set.
+
+use prometheus::{self, Encoder, IntCounterVec, TextEncoder};
+
+use crate::denylist::{self, DENYLIST_PATH};
+use http::hyper::StatusCode;
+use crate::pubsub::PUBSUB_SUB_PATH;
+use crate::spec;
+use crate::webui::{self, WEBUI_PATH};
+
+/// Path the metrics are served on.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Route label of requests matching no route.
+const OTHER_ROUTE: &str = "other";
+
+lazy_static! {
+	static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+		"http_requests_total",
+		"Requests answered by the API, by route and status",
+		&["route", "status"]
+	).expect("metric is registered once; qed");
+}
+
+/// Count a request to `route` answered with `status`.
+pub fn observe(route: &str, status: StatusCode) {
+	HTTP_REQUESTS.with_label_values(&[route, status.as_str()]).inc();
+}
+
+/// The route `path` is counted under, like `/ipfs/{path}` for gateway paths.
+pub fn route_label(path: &str) -> &'static str {
+	let documented = spec::ROUTES.iter().find(|route| match route.path.find('{') {
+		Some(param) => path.starts_with(&route.path[..param]),
+		None => path == route.path,
+	});
+	if let Some(route) = documented {
+		return route.path;
+	}
+
+	if webui::is_webui_path(path) {
+		WEBUI_PATH
+	} else if denylist::is_denylist_path(path) {
+		DENYLIST_PATH
+	} else if path == PUBSUB_SUB_PATH {
+		PUBSUB_SUB_PATH
+	} else {
+		OTHER_ROUTE
+	}
+}
+
+/// Every registered metric in the text exposition format.
+pub fn render() -> Vec<u8> {
+	let mut buffer = Vec::new();
+	TextEncoder::new()
+		.encode(&prometheus::gather(), &mut buffer)
+		.expect("writing to a Vec does not fail; qed");
+	buffer
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_route_label() {
+		assert_eq!(route_label("/api/v0/block/get"), "/api/v0/block/get");
+		assert_eq!(route_label("/ipfs/QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA/a.txt"), "/ipfs/{path}");
+		assert_eq!(route_label("/admin/denylist/remove"), DENYLIST_PATH);
+		assert_eq!(route_label(METRICS_PATH), METRICS_PATH);
+		assert_eq!(route_label("/api/v0/unknown"), OTHER_ROUTE);
+	}
+
+	#[test]
+	fn test_render() {
+		observe("/api/v0/block/get", StatusCode::NOT_FOUND);
+
+		let text = String::from_utf8(render()).unwrap();
+		assert!(text.contains("# TYPE http_requests_total counter"));
+		assert!(text.contains(r#"http_requests_total{route="/api/v0/block/get",status="404"}"#));
+	}
+}
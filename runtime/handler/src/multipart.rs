@@ -0,0 +1,306 @@
+//! `multipart/form-data` request bodies, as sent by `ipfs add` and the API clients.
+//!
+//! `parts` parses a whole body at once, its parts borrow from it. `Parser` parses a body as it is
+//! received, so an upload never has to be held in memory.
+
+use std::mem;
+use std::str;
+
+/// Longest headers of a part `Parser` accepts.
+const MAX_HEADERS_LEN: usize = 8 * 1024;
+
+/// A part of a `multipart/form-data` body.
+#[derive(Debug, PartialEq)]
+pub struct Part<'a> {
+	/// `name` of the part's `Content-Disposition` header.
+	pub name: Option<&'a str>,
+	/// `filename` of the part's `Content-Disposition` header.
+	pub filename: Option<&'a str>,
+	pub data: &'a [u8],
+}
+
+/// The boundary of a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<&str> {
+	let mut params = content_type.split(';');
+	if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+		return None;
+	}
+	params
+		.filter_map(|param| param_value(param, "boundary"))
+		.next()
+		.filter(|boundary| !boundary.is_empty())
+}
+
+/// The parts of `body`, `None` if it is not a complete multipart body.
+pub fn parts<'a>(body: &'a [u8], boundary: &str) -> Option<Vec<Part<'a>>> {
+	let delimiter = format!("--{}", boundary);
+	// a part's data ends at the line break before the next delimiter.
+	let close = format!("\r\n{}", delimiter);
+
+	let start = find(body, delimiter.as_bytes())? + delimiter.len();
+	let mut rest = &body[start..];
+	let mut parts = Vec::new();
+	loop {
+		if rest.starts_with(b"--") {
+			return Some(parts);
+		}
+		if !rest.starts_with(b"\r\n") {
+			return None;
+		}
+		rest = &rest[2..];
+
+		let (headers, data) = if rest.starts_with(b"\r\n") {
+			("", &rest[2..])
+		} else {
+			let end = find(rest, b"\r\n\r\n")?;
+			(str::from_utf8(&rest[..end]).ok()?, &rest[end + 4..])
+		};
+		let end = find(data, close.as_bytes())?;
+		let (name, filename) = content_disposition(headers);
+		parts.push(Part { name, filename, data: &data[..end] });
+		rest = &data[end + close.len()..];
+	}
+}
+
+/// What `Parser` found in the bytes fed to it.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+	/// A part starts, with the `name` and `filename` of its `Content-Disposition` header. The
+	/// previous part, if any, is complete.
+	Part { name: Option<String>, filename: Option<String> },
+	/// More data of the current part.
+	Data(Vec<u8>),
+	/// The closing delimiter, the body and its last part are complete.
+	End,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+	Preamble,
+	Delimiter,
+	Headers,
+	Data,
+	Epilogue,
+}
+
+/// Parses a body fed in chunks of any size, keeping at most a delimiter or the headers of a part
+/// between chunks.
+#[derive(Debug)]
+pub struct Parser {
+	delimiter: Vec<u8>,
+	/// A part's data ends at the line break before the next delimiter.
+	close: Vec<u8>,
+	state: State,
+	buf: Vec<u8>,
+}
+
+impl Parser {
+	pub fn new(boundary: &str) -> Self {
+		let delimiter = format!("--{}", boundary).into_bytes();
+		let mut close = b"\r\n".to_vec();
+		close.extend_from_slice(&delimiter);
+		Parser { delimiter, close, state: State::Preamble, buf: Vec::new() }
+	}
+
+	/// Parse the next `bytes` of the body, `None` if it is not a multipart body.
+	pub fn feed(&mut self, bytes: &[u8]) -> Option<Vec<Event>> {
+		self.buf.extend_from_slice(bytes);
+		let mut events = Vec::new();
+		loop {
+			match self.state {
+				State::Preamble => match find(&self.buf, &self.delimiter) {
+					Some(start) => {
+						self.buf.drain(..start + self.delimiter.len());
+						self.state = State::Delimiter;
+					},
+					None => {
+						let keep = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+						self.buf.drain(..keep);
+						return Some(events);
+					},
+				},
+				State::Delimiter => {
+					if self.buf.len() < 2 {
+						return Some(events);
+					}
+					if self.buf.starts_with(b"--") {
+						events.push(Event::End);
+						self.state = State::Epilogue;
+					} else if self.buf.starts_with(b"\r\n") {
+						self.buf.drain(..2);
+						self.state = State::Headers;
+					} else {
+						return None;
+					}
+				},
+				State::Headers => {
+					let end = if self.buf.starts_with(b"\r\n") {
+						0
+					} else {
+						match find(&self.buf, b"\r\n\r\n") {
+							Some(end) => end + 2,
+							None if self.buf.len() > MAX_HEADERS_LEN => return None,
+							None => return Some(events),
+						}
+					};
+					{
+						let headers = str::from_utf8(&self.buf[..end]).ok()?;
+						let (name, filename) = content_disposition(headers);
+						events.push(Event::Part { name: name.map(Into::into), filename: filename.map(Into::into) });
+					}
+					self.buf.drain(..end + 2);
+					self.state = State::Data;
+				},
+				State::Data => match find(&self.buf, &self.close) {
+					Some(end) => {
+						if end > 0 {
+							events.push(Event::Data(self.buf[..end].to_vec()));
+						}
+						self.buf.drain(..end + self.close.len());
+						self.state = State::Delimiter;
+					},
+					None => {
+						// the end of the buffer may be the start of the next delimiter.
+						let keep = self.close.len() - 1;
+						if self.buf.len() > keep {
+							let rest = self.buf.split_off(self.buf.len() - keep);
+							events.push(Event::Data(mem::replace(&mut self.buf, rest)));
+						}
+						return Some(events);
+					},
+				},
+				State::Epilogue => {
+					self.buf.clear();
+					return Some(events);
+				},
+			}
+		}
+	}
+
+	/// Whether the closing delimiter was read.
+	pub fn is_complete(&self) -> bool {
+		self.state == State::Epilogue
+	}
+}
+
+/// `name` and `filename` of the `Content-Disposition` header in `headers`.
+fn content_disposition(headers: &str) -> (Option<&str>, Option<&str>) {
+	for header in headers.split("\r\n") {
+		let colon = match header.find(':') {
+			Some(colon) => colon,
+			None => continue,
+		};
+		if !header[..colon].trim().eq_ignore_ascii_case("content-disposition") {
+			continue;
+		}
+		let params = || header[colon + 1..].split(';').skip(1);
+		let name = params().filter_map(|param| param_value(param, "name")).next();
+		let filename = params().filter_map(|param| param_value(param, "filename")).next();
+		return (name, filename);
+	}
+	(None, None)
+}
+
+/// The value of `param` if it is named `name`, without quotes.
+fn param_value<'a>(param: &'a str, name: &str) -> Option<&'a str> {
+	let eq = param.find('=')?;
+	if param[..eq].trim().eq_ignore_ascii_case(name) {
+		Some(param[eq + 1..].trim().trim_matches('"'))
+	} else {
+		None
+	}
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_boundary() {
+		assert_eq!(boundary("multipart/form-data; boundary=abc"), Some("abc"));
+		assert_eq!(boundary("Multipart/Form-Data;charset=utf-8; Boundary=\"a b\""), Some("a b"));
+		assert_eq!(boundary("multipart/form-data"), None);
+		assert_eq!(boundary("multipart/form-data; boundary="), None);
+		assert_eq!(boundary("application/json; boundary=abc"), None);
+	}
+
+	#[test]
+	fn test_parts() {
+		let body = b"preamble\r\n--abc\r\n\
+			Content-Disposition: form-data; name=\"file\"; filename=\"hello.txt\"\r\n\
+			Content-Type: application/octet-stream\r\n\
+			\r\n\
+			hello\r\n--ab\r\n\
+			--abc\r\n\
+			\r\n\
+			no headers\r\n\
+			--abc--\r\n";
+
+		assert_eq!(parts(body, "abc"), Some(vec![
+			Part { name: Some("file"), filename: Some("hello.txt"), data: b"hello\r\n--ab" },
+			Part { name: None, filename: None, data: b"no headers" },
+		]));
+	}
+
+	#[test]
+	fn test_parser() {
+		let body: &[u8] = b"preamble\r\n--abc\r\n\
+			Content-Disposition: form-data; name=\"file\"; filename=\"hello.txt\"\r\n\
+			\r\n\
+			hello\r\n--ab\r\n\
+			--abc\r\n\
+			\r\n\
+			no headers\r\n\
+			--abc--\r\n";
+
+		// however the body is cut, the parts are the same.
+		for chunk_len in 1..body.len() + 1 {
+			let mut parser = Parser::new("abc");
+			let mut events = Vec::new();
+			for chunk in body.chunks(chunk_len) {
+				events.extend(parser.feed(chunk).unwrap());
+			}
+			assert!(parser.is_complete());
+
+			let mut parts: Vec<(Option<String>, Vec<u8>)> = Vec::new();
+			for event in events {
+				match event {
+					Event::Part { filename, .. } => parts.push((filename, Vec::new())),
+					Event::Data(data) => parts.last_mut().unwrap().1.extend(data),
+					Event::End => {},
+				}
+			}
+			assert_eq!(parts, vec![
+				(Some("hello.txt".to_string()), b"hello\r\n--ab".to_vec()),
+				(None, b"no headers".to_vec()),
+			]);
+		}
+	}
+
+	#[test]
+	fn test_parser_invalid() {
+		let mut parser = Parser::new("abc");
+		assert_eq!(parser.feed(b"--abc\r\n\r\ncut off"), Some(vec![
+			Event::Part { name: None, filename: None },
+			Event::Data(b"c".to_vec()),
+		]));
+		assert!(!parser.is_complete());
+
+		assert_eq!(Parser::new("abc").feed(b"--abcdef"), None);
+		assert_eq!(Parser::new("abc").feed(&[b'x'; MAX_HEADERS_LEN + 1][..]).map(|events| events.len()), Some(0));
+		let mut headers = b"--abc\r\n".to_vec();
+		headers.extend_from_slice(&[b'x'; MAX_HEADERS_LEN + 1]);
+		assert_eq!(Parser::new("abc").feed(&headers), None);
+	}
+
+	#[test]
+	fn test_incomplete_parts() {
+		assert_eq!(parts(b"--abc\r\n\r\ncut off", "abc"), None);
+		assert_eq!(parts(b"no delimiter", "abc"), None);
+		assert_eq!(parts(b"--abc--", "abc"), Some(vec![]));
+	}
+}
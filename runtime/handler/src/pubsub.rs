@@ -0,0 +1,123 @@
+//! In-process pubsub, wiring local publishers to the subscribers of `/api/v0/pubsub/sub`.
+//!
+//! Messages are sent to the subscribers of their topic as JSON shaped like a
+//! `PubsubSubResponse`, with `from`, `data` and `seqno` base64 encoded. Subscribers whose stream
+//! was dropped are removed on the next publish to their topic.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+
+use crate::spec;
+
+/// Route subscribing to a topic over a WebSocket.
+pub const PUBSUB_SUB_PATH: &str = "/api/v0/pubsub/sub";
+
+/// A message published to a topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+	pub from: Vec<u8>,
+	pub data: Vec<u8>,
+	pub seqno: u64,
+	pub topic: String,
+}
+
+impl Message {
+	/// Encode like a `PubsubSubResponse`.
+	pub fn to_json(&self) -> String {
+		format!(
+			"{{\"from\":\"{}\",\"data\":\"{}\",\"seqno\":\"{}\",\"topicIDs\":[{}]}}",
+			base64::encode(&self.from), base64::encode(&self.data), base64::encode(&self.seqno.to_be_bytes()),
+			spec::string(&self.topic),
+		)
+	}
+}
+
+/// Topics and their subscribers, shared by the handlers of a server and local publishers.
+#[derive(Clone, Default)]
+pub struct PubsubRouter {
+	topics: Arc<Mutex<HashMap<String, Vec<mpsc::UnboundedSender<String>>>>>,
+	seqno: Arc<AtomicUsize>,
+}
+
+impl PubsubRouter {
+	pub fn new() -> Self {
+		PubsubRouter::default()
+	}
+
+	/// Subscribe to `topic`, returning the stream of JSON encoded messages.
+	pub fn subscribe(&self, topic: &str) -> mpsc::UnboundedReceiver<String> {
+		let (sender, receiver) = mpsc::unbounded();
+
+		self.topics.lock().expect("lock is never poisoned; qed")
+			.entry(topic.to_owned())
+			.or_insert_with(Vec::new)
+			.push(sender);
+
+		receiver
+	}
+
+	/// Send `data` of the peer `from` to every subscriber of `topic`, returning how many got it.
+	pub fn publish(&self, topic: &str, from: &[u8], data: &[u8]) -> usize {
+		let message = Message {
+			from: from.to_vec(),
+			data: data.to_vec(),
+			seqno: self.seqno.fetch_add(1, Ordering::SeqCst) as u64,
+			topic: topic.to_owned(),
+		}.to_json();
+
+		let mut topics = self.topics.lock().expect("lock is never poisoned; qed");
+		let count = match topics.get_mut(topic) {
+			Some(subscribers) => {
+				subscribers.retain(|sender| sender.unbounded_send(message.clone()).is_ok());
+				subscribers.len()
+			},
+			None => return 0,
+		};
+		if count == 0 {
+			topics.remove(topic);
+		}
+		count
+	}
+
+	/// Topics with subscribers, in no particular order.
+	pub fn topics(&self) -> Vec<String> {
+		self.topics.lock().expect("lock is never poisoned; qed").keys().cloned().collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on_stream;
+
+	#[test]
+	fn test_message_json() {
+		let message = Message { from: b"peer".to_vec(), data: b"hi".to_vec(), seqno: 1, topic: "news".into() };
+
+		assert_eq!(
+			message.to_json(),
+			r#"{"from":"cGVlcg==","data":"aGk=","seqno":"AAAAAAAAAAE=","topicIDs":["news"]}"#
+		);
+	}
+
+	#[test]
+	fn test_publish() {
+		let router = PubsubRouter::new();
+		let news = router.subscribe("news");
+		let other = router.subscribe("other");
+
+		assert_eq!(router.publish("news", b"peer", b"first"), 1);
+		assert_eq!(router.publish("nobody", b"peer", b"lost"), 0);
+		drop(other);
+		assert_eq!(router.publish("other", b"peer", b"dropped"), 0);
+		assert_eq!(router.topics(), vec!["news".to_string()]);
+
+		drop(router);
+		let messages: Vec<String> = block_on_stream(news).collect();
+		assert_eq!(messages.len(), 1);
+		assert!(messages[0].contains(&format!("\"data\":\"{}\"", base64::encode(b"first"))));
+	}
+}
@@ -0,0 +1,135 @@
+//! `Range` requests of octet streams and gateway files.
+//!
+//! Only single byte ranges are served. A header that does not parse or asks for several ranges is
+//! ignored and the whole stream is sent, which RFC 7233 allows.
+
+use crate::content_type;
+use crate::route::Out;
+
+/// The byte range of a `Range: bytes=..` header.
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+	/// `bytes=first-`
+	From(u64),
+	/// `bytes=first-last`, `last` included.
+	FromTo(u64, u64),
+	/// `bytes=-len`, the last `len` bytes.
+	Suffix(u64),
+}
+
+impl ByteRange {
+	pub fn parse(header: &str) -> Option<Self> {
+		let header = header.trim();
+		if header.len() < 6 || !header[..6].eq_ignore_ascii_case("bytes=") {
+			return None;
+		}
+		let spec = header[6..].trim();
+		if spec.contains(',') {
+			return None;
+		}
+		let dash = spec.find('-')?;
+		let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+		match (first.is_empty(), last.is_empty()) {
+			(true, false) => last.parse().ok().map(ByteRange::Suffix),
+			(false, true) => first.parse().ok().map(ByteRange::From),
+			(false, false) => {
+				let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+				if first > last {
+					return None;
+				}
+				Some(ByteRange::FromTo(first, last))
+			},
+			(true, true) => None,
+		}
+	}
+
+	/// First and last byte of the range within `len` bytes, `None` if none of them are in it.
+	pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+		let (first, last) = match *self {
+			ByteRange::From(first) => (first, len.checked_sub(1)?),
+			ByteRange::FromTo(first, last) => (first, last.min(len.checked_sub(1)?)),
+			ByteRange::Suffix(0) => return None,
+			ByteRange::Suffix(suffix) => (len.saturating_sub(suffix), len.checked_sub(1)?),
+		};
+		if first > last {
+			return None;
+		}
+		Some((first, last))
+	}
+}
+
+/// Answer an octet stream or a gateway file with the bytes selected by the `range` header, other
+/// outputs are left as they are.
+pub(crate) fn apply(range: Option<&str>, out: Out) -> Out {
+	let range = match range.and_then(ByteRange::parse) {
+		Some(range) => range,
+		None => return out,
+	};
+	let (content_type, bytes) = match out {
+		Out::OctetStream(bytes) => (content_type::OCTET_STREAM, bytes),
+		Out::Content { content_type, bytes } => (content_type, bytes),
+		out => return out,
+	};
+	let total = bytes.len() as u64;
+	match range.resolve(total) {
+		Some((first, last)) => Out::PartialContent {
+			content_type,
+			bytes: bytes[first as usize..last as usize + 1].to_vec(),
+			first,
+			total,
+		},
+		None => Out::RangeNotSatisfiable { total },
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse() {
+		assert_eq!(ByteRange::parse("bytes=0-"), Some(ByteRange::From(0)));
+		assert_eq!(ByteRange::parse("bytes=10-19"), Some(ByteRange::FromTo(10, 19)));
+		assert_eq!(ByteRange::parse("Bytes= -5"), Some(ByteRange::Suffix(5)));
+		assert_eq!(ByteRange::parse("bytes=5-4"), None);
+		assert_eq!(ByteRange::parse("bytes=-"), None);
+		assert_eq!(ByteRange::parse("bytes=0-1,5-6"), None);
+		assert_eq!(ByteRange::parse("items=0-1"), None);
+		assert_eq!(ByteRange::parse("bytes=a-b"), None);
+	}
+
+	#[test]
+	fn test_resolve() {
+		assert_eq!(ByteRange::From(2).resolve(10), Some((2, 9)));
+		assert_eq!(ByteRange::From(10).resolve(10), None);
+		assert_eq!(ByteRange::FromTo(2, 100).resolve(10), Some((2, 9)));
+		assert_eq!(ByteRange::Suffix(3).resolve(10), Some((7, 9)));
+		assert_eq!(ByteRange::Suffix(30).resolve(10), Some((0, 9)));
+		assert_eq!(ByteRange::Suffix(0).resolve(10), None);
+		assert_eq!(ByteRange::From(0).resolve(0), None);
+	}
+
+	#[test]
+	fn test_apply() {
+		let out = || Out::OctetStream(b"0123456789".to_vec());
+
+		assert_eq!(apply(None, out()), out());
+		assert_eq!(apply(Some("bytes=0-1,5-6"), out()), out());
+		assert_eq!(
+			apply(Some("bytes=2-4"), out()),
+			Out::PartialContent { content_type: content_type::OCTET_STREAM, bytes: b"234".to_vec(), first: 2, total: 10 }
+		);
+		assert_eq!(apply(Some("bytes=20-"), out()), Out::RangeNotSatisfiable { total: 10 });
+		assert_eq!(apply(Some("bytes=2-4"), Out::Bad("bad")), Out::Bad("bad"));
+	}
+
+	#[test]
+	fn test_apply_content() {
+		let content = Out::Content { content_type: "text/plain; charset=utf-8", bytes: b"hello".to_vec() };
+
+		assert_eq!(
+			apply(Some("bytes=-2"), content),
+			Out::PartialContent { content_type: "text/plain; charset=utf-8", bytes: b"lo".to_vec(), first: 3, total: 5 }
+		);
+	}
+}
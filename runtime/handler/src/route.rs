@@ -1,39 +1,130 @@
-use {rlp, multihash, Handler};
-use error::{Error, Result};
+use crate::{Handler, RouteFuture};
+use futures::compat::Future01CompatExt;
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::{Stream, StreamExt};
+use crate::error::{Error, Result};
 use cid::{ToCid, Codec};
+use crate::events::EventTopic;
+use crate::gateway;
+use crate::metrics::{self, METRICS_PATH};
+use crate::spec::{self, SPEC_PATH};
+use std::time::Duration;
 
 use multihash::Hash;
 use ethereum_types::H256;
 use bytes::Bytes;
 use ethcore::client::{BlockId, TransactionId};
+use filesys_api::response::{AddResponse, RepoBlockCount, RepoBlockStatsResponse};
+use repo::{Datastore, RepoStat};
+use crate::multipart::{self, Event};
+#[cfg(feature = "embedded-webui")]
+use crate::webui;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write as IoWrite};
 
 type Reason = &'static str;
 
+/// Route adding the files of a `multipart/form-data` POST body.
+pub const ADD_PATH: &str = "/api/v0/add";
+
+/// Longest body of an `ADD_PATH` request unless the server is configured with another limit.
+pub const MAX_ADD_LEN: u64 = 1 << 30;
+
+/// Admin route counting the repo's blocks by codec and multihash type.
+pub const REPO_STATS_PATH: &str = "/admin/repo/stats";
+
+/// Route reporting the key count and disk usage of each datastore of the repo.
+pub const REPO_STAT_PATH: &str = "/api/v0/repo/stat";
+
 /// Keeps the state of the response to send out
 #[derive(Debug, PartialEq)]
 pub enum Out {
 	OctetStream(Bytes),
 	NotFound(Reason),
 	Bad(Reason),
+	/// Subscribe to chain events and stream them as Server-Sent Events
+	Events(Vec<EventTopic>),
+	/// The request did not complete within `timeout`, it was last in `stage`
+	Timeout { stage: &'static str, timeout: Duration },
+	/// The request lacks a valid API key
+	Unauthorized(Reason),
+	/// The token of the request does not cover the route
+	Forbidden(Reason),
+	/// The content is blocked by a denylist rule, for the reason `code`
+	Gone { code: String },
+	/// The connection is upgraded to a WebSocket, answering the client's key with `accept`
+	SwitchingProtocols { accept: String },
+	/// The quota of the API key is used up for `retry_after`
+	TooManyRequests { retry_after: Duration },
+	/// JSON body
+	Json(String),
+	/// CBOR body, a JSON body answered to a CBOR caller
+	Cbor(Bytes),
+	/// The request failed on our side
+	Internal(Reason),
+	/// `bytes` of an octet stream or file of `total` bytes, starting at byte `first`
+	PartialContent { content_type: &'static str, bytes: Bytes, first: u64, total: u64 },
+	/// The requested range is not within the octet stream of `total` bytes
+	RangeNotSatisfiable { total: u64 },
+	/// A file of the web UI
+	Asset { content_type: &'static str, cache_control: &'static str, body: &'static [u8] },
+	/// A file served on a gateway path
+	Content { content_type: &'static str, bytes: Bytes },
 }
 
 impl Handler {
-	/// Route path + query string to a specialized method
-	pub fn route(&self, path: &str, query: Option<&str>) -> Out {
-		match path {
+	/// Route path + query string to a specialized method. Routes waiting on the node resolve once
+	/// it answers, dropping the future stops waiting.
+	pub fn route(&self, path: &str, query: Option<&str>) -> RouteFuture {
+		let out = match path {
 			"/api/v0/block/get" => {
 				let arg = query.and_then(|q| get_param(q, "arg")).unwrap_or("");
 
-				self.route_cid(arg).unwrap_or_else(Into::into)
+				if let Some(code) = self.denylist.blocked_cid(arg) {
+					Out::Gone { code }
+				} else {
+					self.route_cid(arg).unwrap_or_else(Into::into)
+				}
+			},
+
+			"/eth/v1/events" => {
+				let topics = query.and_then(|q| get_param(q, "topics")).unwrap_or("");
+
+				EventTopic::parse_list(topics).map_or(Out::Bad("Invalid event topics"), Out::Events)
+			},
+
+			// POST requests are streamed to `add` before getting here.
+			ADD_PATH => Out::Bad("Files must be added with a multipart/form-data POST body"),
+
+			SPEC_PATH => Out::Json(spec::openapi_json(self.keys.is_some())),
+
+			METRICS_PATH => Out::Content { content_type: metrics::CONTENT_TYPE, bytes: metrics::render() },
+
+			REPO_STAT_PATH => self.repo_stat(),
+
+			path if gateway::is_gateway_path(path) => match self.denylist.blocked_path(path) {
+				Some(code) => Out::Gone { code },
+				None => return self.gateway(path),
 			},
 
+			#[cfg(feature = "embedded-webui")]
+			path if webui::is_webui_path(path) => webui::serve(path),
+
+			// admin routes only exist with API keys, the admin token is checked before routing.
+			REPO_STATS_PATH if self.keys.is_some() => return self.repo_stats(),
+
 			_ => Out::NotFound("Route not found")
-		}
+		};
+
+		future::ready(out).boxed()
 	}
 
 	/// Attempt to read Content ID from `arg` query parameter, get a hash and
 	/// route further by the CID's codec.
 	fn route_cid(&self, cid: &str) -> Result<Out> {
+		self.stage.enter("parse_cid");
+
 		let cid = cid.to_cid()?;
 
 		let mh = multihash::decode(&cid.hash)?;
@@ -54,6 +145,8 @@ impl Handler {
 
 	/// Get block header by hash as raw binary.
 	fn block(&self, hash: H256) -> Result<Out> {
+		self.stage.enter("block");
+
 		let block_id = BlockId::Hash(hash);
 		let block = self.client().block_header(block_id).ok_or(Error::BlockNotFound)?;
 
@@ -62,6 +155,8 @@ impl Handler {
 
 	/// Get list of block ommers by hash as raw binary.
 	fn block_list(&self, hash: H256) -> Result<Out> {
+		self.stage.enter("block_list");
+
 		let uncles = self.client().find_uncles(&hash).ok_or(Error::BlockNotFound)?;
 
 		Ok(Out::OctetStream(rlp::encode_list(&uncles)))
@@ -69,6 +164,8 @@ impl Handler {
 
 	/// Get transaction by hash and return as raw binary.
 	fn transaction(&self, hash: H256) -> Result<Out> {
+		self.stage.enter("transaction");
+
 		let tx_id = TransactionId::Hash(hash);
 		let tx = self.client().transaction(tx_id).ok_or(Error::TransactionNotFound)?;
 
@@ -77,21 +174,216 @@ impl Handler {
 
 	/// Get state trie node by hash and return as raw binary.
 	fn state_trie(&self, hash: H256) -> Result<Out> {
+		self.stage.enter("state_trie");
+
 		let data = self.client().state_data(&hash).ok_or(Error::StateRootNotFound)?;
 
 		Ok(Out::OctetStream(data))
 	}
 
+	/// Add every part of a multipart body as a UnixFS file.
+	///
+	/// The body is parsed as it is received and each part is written to a temporary file, which is
+	/// added once the part is complete, so an upload is never held in memory. Adding each file
+	/// counts against the route's timeout, receiving the body does not. A body failing with `Out`
+	/// is answered with it, the files added before are kept.
+	///
+	/// Answers like go-ipfs with a JSON object per file, one per line. The files are pinned
+	/// recursively if `pin` is set.
+	pub(crate) fn add<S, C>(&self, boundary: &str, pin: bool, body: S) -> RouteFuture
+		where S: Stream<Item = ::std::result::Result<C, Out>> + Send + 'static, C: AsRef<[u8]> + Send
+	{
+		self.stage.enter("parse_multipart");
+
+		let handler = self.clone();
+		let mut parser = multipart::Parser::new(boundary);
+		let mut part = None;
+		let added = async move {
+			let mut body = Box::pin(body);
+			let mut added = Added::default();
+			while let Some(chunk) = body.next().await {
+				let events = parser.feed(chunk?.as_ref()).ok_or(Out::Bad("Invalid multipart body"))?;
+				for spooled in spool(&mut part, events).map_err(|_| Out::Internal("Receiving the file failed"))? {
+					added = handler.add_spooled(added, spooled, pin).await?;
+				}
+			}
+			match added {
+				Added { complete: false, .. } => Err(Out::Bad("Invalid multipart body")),
+				Added { files: 0, .. } => Err(Out::Bad("No file in multipart body")),
+				Added { json, .. } => Ok(Out::Json(json)),
+			}
+		};
+
+		added.map(|out| out.unwrap_or_else(|out| out)).boxed()
+	}
+
+	/// Add the file of a complete part, see `add`.
+	fn add_spooled(&self, mut added: Added, spooled: Spooled, pin: bool) -> BoxFuture<'static, ::std::result::Result<Added, Out>> {
+		let (filename, file) = match spooled {
+			Spooled::Part { filename, file } => (filename, file),
+			Spooled::End => {
+				added.complete = true;
+				return future::ok(added).boxed();
+			},
+		};
+
+		self.stage.enter("add");
+
+		let timeout = self.timeouts.for_route(ADD_PATH);
+		let adding = self.client.add_with_pin(file, pin).compat();
+		async move {
+			match tokio::time::timeout(timeout, adding).await {
+				Ok(Ok(response)) => {
+					added.json.push_str(&added_json(filename.as_ref().map(|name| &**name), &response));
+					added.json.push('\n');
+					added.files += 1;
+					Ok(added)
+				},
+				Ok(Err(_)) => Err(Out::Internal("Adding the file failed")),
+				Err(_) => Err(Out::Timeout { stage: "add", timeout }),
+			}
+		}.boxed()
+	}
+
+	/// Resolve an `/ipfs/` or `/ipns/` path and serve the file it ends in.
+	fn gateway(&self, path: &str) -> RouteFuture {
+		self.stage.enter("gateway");
+
+		let path = path.to_owned();
+		let handler = self.clone();
+		async move {
+			// a rule on a block blocks every path through it, not only the paths rooted at it.
+			let cids = match handler.client.resolve_path(&path).compat().await {
+				Ok(cids) => cids,
+				Err(_) => return Out::NotFound("Path not found"),
+			};
+			if let Some(code) = handler.denylist.blocked_resolved(&path, &cids) {
+				return Out::Gone { code };
+			}
+			match handler.client.get_path(&path).compat().await {
+				Ok(bytes) => Out::Content { content_type: gateway::content_type(&path, &bytes), bytes: bytes.to_vec() },
+				Err(_) => Out::NotFound("Path not found"),
+			}
+		}.boxed()
+	}
+
+	/// Count the repo's blocks by codec and multihash type.
+	fn repo_stats(&self) -> RouteFuture {
+		self.stage.enter("repo_stats");
+
+		self.client.repo_block_stats().compat().map(|res| match res {
+			Ok(stats) => Out::Json(repo_stats_json(&stats)),
+			Err(_) => Out::NotFound("Repo statistics not available"),
+		}).boxed()
+	}
+
+	/// Count the keys and measure the disk usage of each datastore of the repo.
+	fn repo_stat(&self) -> Out {
+		self.stage.enter("repo_stat");
+
+		match self.repo_stat.as_ref().map(|stat| stat()) {
+			Some(Ok(stat)) => Out::Json(repo_stat_json(&stat)),
+			Some(Err(_)) => Out::Internal("Measuring the repo failed"),
+			None => Out::NotFound("Repo statistics not available"),
+		}
+	}
+
 	/// Get state trie node by hash and return as raw binary.
 	fn contract_code(&self, hash: H256) -> Result<Out> {
+		self.stage.enter("contract_code");
+
 		let data = self.client().state_data(&hash).ok_or(Error::ContractNotFound)?;
 
 		Ok(Out::OctetStream(data))
 	}
 }
 
+/// The files added so far by `add`.
+#[derive(Debug, Default)]
+struct Added {
+	json: String,
+	files: usize,
+	/// Whether the closing delimiter of the body was read.
+	complete: bool,
+}
+
+/// A complete part of a multipart body, or its end.
+enum Spooled {
+	Part { filename: Option<String>, file: File },
+	End,
+}
+
+/// Write the data of `events` to the temporary file of the current `part`, returning the parts
+/// they complete.
+fn spool(part: &mut Option<(Option<String>, File)>, events: Vec<Event>) -> io::Result<Vec<Spooled>> {
+	fn complete(part: &mut Option<(Option<String>, File)>, spooled: &mut Vec<Spooled>) -> io::Result<()> {
+		if let Some((filename, mut file)) = part.take() {
+			file.seek(SeekFrom::Start(0))?;
+			spooled.push(Spooled::Part { filename, file });
+		}
+		Ok(())
+	}
+
+	let mut spooled = Vec::new();
+	for event in events {
+		match event {
+			Event::Part { filename, .. } => {
+				complete(part, &mut spooled)?;
+				*part = Some((filename, tempfile::tempfile()?));
+			},
+			Event::Data(data) => if let Some((_, ref mut file)) = *part {
+				file.write_all(&data)?;
+			},
+			Event::End => {
+				complete(part, &mut spooled)?;
+				spooled.push(Spooled::End);
+			},
+		}
+	}
+	Ok(spooled)
+}
+
+/// `{"Name":..,"Hash":..,"Size":..}`, named after the uploaded file if it has a name.
+fn added_json(filename: Option<&str>, added: &AddResponse) -> String {
+	let name = filename.filter(|name| !name.is_empty()).unwrap_or(added.name.as_str());
+	format!("{{\"Name\":{},\"Hash\":{},\"Size\":{}}}", spec::string(name), spec::string(&added.hash), spec::string(&added.size))
+}
+
+/// `{"blocks":..,"bytes":..,"codecs":{name:{"blocks":..,"bytes":..}},"hashes":{..}}`
+fn repo_stats_json(stats: &RepoBlockStatsResponse) -> String {
+	fn counts(json: &mut String, counts: &[RepoBlockCount]) {
+		json.push('{');
+		for (i, count) in counts.iter().enumerate() {
+			if i > 0 {
+				json.push(',');
+			}
+			write!(json, "{}:{{\"blocks\":{},\"bytes\":{}}}", spec::string(&count.name), count.blocks, count.bytes).unwrap();
+		}
+		json.push('}');
+	}
+
+	let mut json = format!("{{\"blocks\":{},\"bytes\":{},\"codecs\":", stats.blocks, stats.bytes);
+	counts(&mut json, &stats.codecs);
+	json.push_str(",\"hashes\":");
+	counts(&mut json, &stats.hashes);
+	json.push('}');
+	json
+}
+
+/// `{"wallet":{"keys":..,"bytes":..},"chain":{..},"deals":{..},"blockstore":{..},"total":{..}}`
+fn repo_stat_json(stat: &RepoStat) -> String {
+	let mut json = String::from("{");
+	for datastore in Datastore::all() {
+		let datastore_stat = stat.get(*datastore);
+		write!(json, "{}:{{\"keys\":{},\"bytes\":{}}},", spec::string(datastore.as_str()), datastore_stat.keys, datastore_stat.bytes).unwrap();
+	}
+	let total = stat.total();
+	write!(json, "\"total\":{{\"keys\":{},\"bytes\":{}}}}}", total.keys, total.bytes).unwrap();
+	json
+}
+
 /// Get a query parameter's value by name.
-fn get_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+pub(crate) fn get_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
 	query.split('&')
 		.find(|part| part.starts_with(name) && part[name.len()..].starts_with("="))
 		.map(|part| &part[name.len() + 1..])
@@ -101,10 +393,17 @@ fn get_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
 mod tests {
 	use std::sync::Arc;
 	use super::*;
+	use futures::executor::block_on;
+	use futures::stream;
 	use ethcore::client::TestBlockChainClient;
+	use crate::events::EventBus;
+	use crate::timeout::Timeouts;
+	use crate::auth::{ApiKeys, MemoryKeyStore};
+	use repo::DatastoreStat;
+	use crate::RepoStatFn;
 
 	fn get_mocked_handler() -> IpfsHandler {
-		IpfsHandler::new(None.into(), None.into(), Arc::new(TestBlockChainClient::new()))
+		IpfsHandler::new(None.into(), None.into(), Arc::new(TestBlockChainClient::new()), EventBus::new(), Timeouts::default(), None)
 	}
 
 	#[test]
@@ -196,7 +495,7 @@ mod tests {
 	fn route_block() {
 		let handler = get_mocked_handler();
 
-		let out = handler.route("/api/v0/block/get", Some("arg=z43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM"));
+		let out = block_on(handler.route("/api/v0/block/get", Some("arg=z43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM")));
 
 		assert_eq!(out, Out::NotFound("Block not found"));
 	}
@@ -205,7 +504,7 @@ mod tests {
 	fn route_block_missing_query() {
 		let handler = get_mocked_handler();
 
-		let out = handler.route("/api/v0/block/get", None);
+		let out = block_on(handler.route("/api/v0/block/get", None));
 
 		assert_eq!(out, Out::Bad("CID parsing failed"));
 	}
@@ -214,16 +513,167 @@ mod tests {
 	fn route_block_invalid_query() {
 		let handler = get_mocked_handler();
 
-		let out = handler.route("/api/v0/block/get", Some("arg=foobarz43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM"));
+		let out = block_on(handler.route("/api/v0/block/get", Some("arg=foobarz43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM")));
 
 		assert_eq!(out, Out::Bad("CID parsing failed"));
 	}
 
+	#[test]
+	fn route_events() {
+		let handler = get_mocked_handler();
+
+		let out = block_on(handler.route("/eth/v1/events", Some("topics=head,finalized_checkpoint")));
+
+		assert_eq!(out, Out::Events(vec![EventTopic::Head, EventTopic::FinalizedCheckpoint]));
+	}
+
+	#[test]
+	fn route_events_invalid_topic() {
+		let handler = get_mocked_handler();
+
+		assert_eq!(block_on(handler.route("/eth/v1/events", Some("topics=head,foo"))), Out::Bad("Invalid event topics"));
+		assert_eq!(block_on(handler.route("/eth/v1/events", None)), Out::Bad("Invalid event topics"));
+	}
+
+	#[test]
+	fn route_spec() {
+		let handler = get_mocked_handler();
+
+		assert_eq!(block_on(handler.route("/api/spec.json", None)), Out::Json(spec::openapi_json(false)));
+	}
+
+	#[test]
+	fn route_every_documented_route() {
+		let handler = get_mocked_handler();
+		let keys = ApiKeys::new(Arc::new(MemoryKeyStore::default()), "admin".into());
+		let admin = IpfsHandler { keys: Some(keys.clone()), ..get_mocked_handler() };
+
+		for route in spec::ROUTES {
+			let out = if route.path.starts_with("/admin/keys") {
+				keys.route_admin(Some("admin"), route.method == "post", route.path, None)
+			} else if route.admin {
+				block_on(admin.route(route.path, None))
+			} else {
+				block_on(handler.route(route.path, None))
+			};
+			assert!(out != Out::NotFound("Route not found"), "{} is not routed", route.path);
+		}
+	}
+
+	#[test]
+	fn route_gateway_path_not_found() {
+		let handler = get_mocked_handler();
+
+		assert_eq!(block_on(handler.route("/ipfs/QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA/a.txt", None)), Out::NotFound("Path not found"));
+		assert_eq!(block_on(handler.route("/ipfs/", None)), Out::NotFound("Route not found"));
+	}
+
+	#[test]
+	fn route_blocked_content() {
+		let handler = get_mocked_handler();
+		handler.denylist.add("QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA", "dmca");
+		handler.denylist.add("z43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM", "legal");
+
+		assert_eq!(block_on(handler.route("/ipfs/QmXdNSQx7nbdRvkjGCEQgVjVtVwsHvV8NmV2a8xzQVwuFA/a.txt", None)), Out::Gone { code: "dmca".into() });
+		assert_eq!(
+			block_on(handler.route("/api/v0/block/get", Some("arg=z43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM"))),
+			Out::Gone { code: "legal".into() }
+		);
+	}
+
+	#[test]
+	fn route_repo_stats_without_keys() {
+		let handler = get_mocked_handler();
+
+		assert_eq!(block_on(handler.route(REPO_STATS_PATH, None)), Out::NotFound("Route not found"));
+	}
+
+	#[test]
+	fn test_repo_stats_json() {
+		let count = |name: &str, blocks, bytes| RepoBlockCount { name: name.into(), blocks, bytes };
+		let stats = RepoBlockStatsResponse {
+			blocks: 3,
+			bytes: 300,
+			codecs: vec![count("dag-pb", 2, 200), count("raw", 1, 100)],
+			hashes: vec![count("sha2-256", 3, 300)],
+		};
+
+		assert_eq!(
+			repo_stats_json(&stats),
+			r#"{"blocks":3,"bytes":300,"codecs":{"dag-pb":{"blocks":2,"bytes":200},"raw":{"blocks":1,"bytes":100}},"hashes":{"sha2-256":{"blocks":3,"bytes":300}}}"#
+		);
+	}
+
+	#[test]
+	fn route_repo_stat() {
+		let handler = get_mocked_handler();
+		assert_eq!(block_on(handler.route(REPO_STAT_PATH, None)), Out::NotFound("Repo statistics not available"));
+
+		let stat: RepoStatFn = Arc::new(|| Ok(RepoStat::default()));
+		let handler = IpfsHandler { repo_stat: Some(stat), ..get_mocked_handler() };
+		assert_eq!(block_on(handler.route(REPO_STAT_PATH, None)), Out::Json(repo_stat_json(&RepoStat::default())));
+
+		let stat: RepoStatFn = Arc::new(|| Err(repo::Error::IoError { message: "denied".into() }));
+		let handler = IpfsHandler { repo_stat: Some(stat), ..get_mocked_handler() };
+		assert_eq!(block_on(handler.route(REPO_STAT_PATH, None)), Out::Internal("Measuring the repo failed"));
+	}
+
+	#[test]
+	fn test_repo_stat_json() {
+		let stat = RepoStat {
+			wallet: DatastoreStat { keys: 2, bytes: 100 },
+			chain: DatastoreStat { keys: 10, bytes: 4000 },
+			..RepoStat::default()
+		};
+
+		assert_eq!(
+			repo_stat_json(&stat),
+			r#"{"wallet":{"keys":2,"bytes":100},"chain":{"keys":10,"bytes":4000},"deals":{"keys":0,"bytes":0},"blockstore":{"keys":0,"bytes":0},"total":{"keys":12,"bytes":4100}}"#
+		);
+	}
+
+	#[test]
+	fn route_add_get() {
+		let handler = get_mocked_handler();
+
+		assert_eq!(block_on(handler.route(ADD_PATH, None)), Out::Bad("Files must be added with a multipart/form-data POST body"));
+	}
+
+	#[test]
+	fn add_invalid_body() {
+		let handler = get_mocked_handler();
+		let add = |chunks: Vec<&'static [u8]>| block_on(handler.add("abc", true, stream::iter(chunks.into_iter().map(Ok))));
+
+		assert_eq!(add(vec![b"--abc\r\n\r\ncut off"]), Out::Bad("Invalid multipart body"));
+		assert_eq!(add(vec![b"--abc\r\n\r\n", b"cut off"]), Out::Bad("Invalid multipart body"));
+		assert_eq!(add(vec![b"--ab", b"c--"]), Out::Bad("No file in multipart body"));
+		assert_eq!(add(vec![b"--abcdef"]), Out::Bad("Invalid multipart body"));
+		assert_eq!(
+			block_on(handler.add("abc", true, stream::once(future::err::<&[u8], _>(Out::Bad("Upload too large"))))),
+			Out::Bad("Upload too large")
+		);
+	}
+
+	#[test]
+	fn test_error_out() {
+		assert_eq!(Out::from(Error::BlockNotFound), Out::NotFound("Block not found"));
+		assert_eq!(Out::from(Error::UnsupportedHash), Out::Bad("Hash must be Keccak-256"));
+		assert_eq!(Out::from(Error::CidParsingFailed), Out::Bad("CID parsing failed"));
+	}
+
+	#[test]
+	fn test_added_json() {
+		let added = AddResponse { name: "QmHash".into(), hash: "QmHash".into(), size: "12".into() };
+
+		assert_eq!(added_json(Some("a.txt"), &added), r#"{"Name":"a.txt","Hash":"QmHash","Size":"12"}"#);
+		assert_eq!(added_json(None, &added), r#"{"Name":"QmHash","Hash":"QmHash","Size":"12"}"#);
+	}
+
 	#[test]
 	fn route_invalid_route() {
 		let handler = get_mocked_handler();
 
-		let out = handler.route("/foo/bar/baz", Some("arg=z43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM"));
+		let out = block_on(handler.route("/foo/bar/baz", Some("arg=z43AaGF5tmkT9SEX6urrhwpEW5ZSaACY73Vw357ZXTsur2fR8BM")));
 
 		assert_eq!(out, Out::NotFound("Route not found"));
 	}
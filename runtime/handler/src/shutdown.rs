@@ -0,0 +1,99 @@
+//! Graceful shutdown of the server.
+//!
+//! Requests are counted while they are in flight, so that a shutdown can drain them and tell how
+//! many it had to cut off. An event stream is in flight until its client goes away.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+use futures::{Stream, StreamExt};
+
+/// Outcome of `Listening::shutdown`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShutdownReport {
+	/// Requests in flight when the server stopped accepting connections.
+	pub in_flight: usize,
+	/// Requests still in flight at the deadline, their connections were closed.
+	pub aborted: usize,
+}
+
+/// Number of requests in flight, shared by the handlers of a server.
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+	/// Count a request as in flight until the returned guard is dropped.
+	pub fn enter(&self) -> InFlightGuard {
+		self.0.fetch_add(1, Ordering::SeqCst);
+		InFlightGuard(self.0.clone())
+	}
+
+	pub fn count(&self) -> usize {
+		self.0.load(Ordering::SeqCst)
+	}
+}
+
+/// A request in flight.
+#[derive(Debug)]
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlightGuard {
+	/// Keep the request in flight until `stream` is dropped.
+	pub fn hold<S: Stream>(self, stream: S) -> Held<S> {
+		Held { stream, _guard: self }
+	}
+}
+
+impl Drop for InFlightGuard {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// A stream keeping its request in flight.
+#[derive(Debug)]
+pub struct Held<S> {
+	stream: S,
+	_guard: InFlightGuard,
+}
+
+impl<S: Stream + Unpin> Stream for Held<S> {
+	type Item = S::Item;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		self.stream.poll_next_unpin(cx)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+	use futures::stream;
+
+	#[test]
+	fn test_in_flight() {
+		let in_flight = InFlight::default();
+
+		let first = in_flight.enter();
+		let second = in_flight.clone().enter();
+		assert_eq!(in_flight.count(), 2);
+
+		drop(first);
+		assert_eq!(in_flight.count(), 1);
+		drop(second);
+		assert_eq!(in_flight.count(), 0);
+	}
+
+	#[test]
+	fn test_held_stream() {
+		let in_flight = InFlight::default();
+		let held = in_flight.enter().hold(stream::iter(vec![1, 2]));
+
+		assert_eq!(in_flight.count(), 1);
+		assert_eq!(block_on(held.collect::<Vec<_>>()), vec![1, 2]);
+		assert_eq!(in_flight.count(), 0);
+	}
+}
@@ -0,0 +1,370 @@
+//! OpenAPI 3 description of the routes, served on `/api/spec.json`.
+//!
+//! The routes are matched by hand in `route.rs` and `auth.rs`, so they are described here once
+//! more. `ROUTES` has to be kept in sync with them, which the tests check for every path.
+
+use std::fmt::Write;
+
+use crate::metrics::METRICS_PATH;
+use crate::route::{ADD_PATH, REPO_STATS_PATH, REPO_STAT_PATH};
+
+/// Path the document is served on.
+pub const SPEC_PATH: &str = "/api/spec.json";
+
+/// A query parameter of a route, or a path parameter if the path holds `{name}`. All parameters
+/// are strings.
+pub struct Param {
+	pub name: &'static str,
+	pub required: bool,
+	pub description: &'static str,
+}
+
+/// A possible response of a route.
+pub struct Response {
+	pub status: u16,
+	pub content_type: &'static str,
+	pub description: &'static str,
+	/// JSON schema of the body.
+	pub schema: &'static str,
+}
+
+/// A route served by the handler.
+pub struct Route {
+	pub method: &'static str,
+	pub path: &'static str,
+	pub summary: &'static str,
+	pub params: &'static [Param],
+	/// Content type of the request body, which holds a file in its `file` field.
+	pub body: Option<&'static str>,
+	pub responses: &'static [Response],
+	/// Only served when API keys are configured, and only to the admin.
+	pub admin: bool,
+}
+
+const TEXT_SCHEMA: &str = r#"{"type":"string"}"#;
+const BINARY_SCHEMA: &str = r#"{"type":"string","format":"binary"}"#;
+const ERROR_SCHEMA: &str = r#"{"type":"object","properties":{"code":{"type":"string"},"message":{"type":"string"},"retriable":{"type":"boolean"}}}"#;
+const TIMEOUT_SCHEMA: &str = r#"{"type":"object","properties":{"error":{"type":"string"},"stage":{"type":"string"},"timeout_ms":{"type":"integer"}}}"#;
+const KEY_SCHEMA: &str = r#"{"type":"object","properties":{"token":{"type":"string"},"scope":{"type":"string","enum":["read","write","admin"]},"requests_per_min":{"type":"integer"},"bytes_added_per_day":{"type":"integer"},"requests":{"type":"integer"},"bytes_added":{"type":"integer"}}}"#;
+const KEYS_SCHEMA: &str = r#"{"type":"array","items":{"type":"object","properties":{"token":{"type":"string"},"scope":{"type":"string","enum":["read","write","admin"]},"requests_per_min":{"type":"integer"},"bytes_added_per_day":{"type":"integer"},"requests":{"type":"integer"},"bytes_added":{"type":"integer"}}}}"#;
+const REVOKED_SCHEMA: &str = r#"{"type":"object","properties":{"revoked":{"type":"boolean"}}}"#;
+const REPO_STATS_SCHEMA: &str = r#"{"type":"object","properties":{"blocks":{"type":"integer"},"bytes":{"type":"integer"},"codecs":{"type":"object","additionalProperties":{"type":"object","properties":{"blocks":{"type":"integer"},"bytes":{"type":"integer"}}}},"hashes":{"type":"object","additionalProperties":{"type":"object","properties":{"blocks":{"type":"integer"},"bytes":{"type":"integer"}}}}}}"#;
+const REPO_STAT_SCHEMA: &str = r#"{"type":"object","additionalProperties":{"type":"object","properties":{"keys":{"type":"integer"},"bytes":{"type":"integer"}}}}"#;
+const SPEC_SCHEMA: &str = r#"{"type":"object"}"#;
+
+const ADDED_SCHEMA: &str = r#"{"type":"object","properties":{"Name":{"type":"string"},"Hash":{"type":"string"},"Size":{"type":"string"}}}"#;
+
+const BAD: Response = Response { status: 400, content_type: "application/json", description: "Invalid request", schema: ERROR_SCHEMA };
+const NOT_FOUND: Response = Response { status: 404, content_type: "application/json", description: "Not found", schema: ERROR_SCHEMA };
+const PARTIAL_CONTENT: Response = Response { status: 206, content_type: "application/octet-stream", description: "The bytes asked for with a `Range: bytes=..` header", schema: BINARY_SCHEMA };
+const GATEWAY_PARTIAL_CONTENT: Response = Response { status: 206, content_type: "*/*", description: "The bytes asked for with a `Range: bytes=..` header", schema: BINARY_SCHEMA };
+const RANGE_NOT_SATISFIABLE: Response = Response { status: 416, content_type: "text/plain", description: "The range is not within the body", schema: TEXT_SCHEMA };
+const TIMEOUT: Response = Response { status: 504, content_type: "application/json", description: "The request timed out", schema: TIMEOUT_SCHEMA };
+const UNAUTHORIZED: Response = Response { status: 401, content_type: "text/plain", description: "Missing or unknown token", schema: TEXT_SCHEMA };
+const TOO_MANY_REQUESTS: Response = Response { status: 429, content_type: "text/plain", description: "The quota of the API key is used up, see `retry-after`", schema: TEXT_SCHEMA };
+
+const GATEWAY_PATH: Param = Param { name: "path", required: true, description: "CID or name followed by a sub path" };
+const TOKEN: Param = Param { name: "token", required: true, description: "API key, 1 to 128 characters of [A-Za-z0-9._-]" };
+
+/// Every route, in the order they are documented.
+pub const ROUTES: &[Route] = &[
+	Route {
+		method: "get",
+		path: "/api/v0/block/get",
+		summary: "Get a raw block",
+		params: &[Param { name: "arg", required: true, description: "CID of the block" }],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "application/octet-stream", description: "The block", schema: BINARY_SCHEMA },
+			PARTIAL_CONTENT,
+			BAD,
+			NOT_FOUND,
+			RANGE_NOT_SATISFIABLE,
+			TIMEOUT,
+		],
+		admin: false,
+	},
+	Route {
+		method: "post",
+		path: ADD_PATH,
+		summary: "Add files as UnixFS, every part of the body is a file",
+		params: &[Param { name: "pin", required: false, description: "Pin the added files recursively, true unless `false`" }],
+		body: Some("multipart/form-data"),
+		responses: &[
+			Response { status: 200, content_type: "application/json", description: "A JSON object per added file, one per line", schema: ADDED_SCHEMA },
+			BAD,
+			TIMEOUT,
+			Response { status: 500, content_type: "application/json", description: "Adding a file failed", schema: ERROR_SCHEMA },
+		],
+		admin: false,
+	},
+	Route {
+		method: "get",
+		path: "/eth/v1/events",
+		summary: "Subscribe to chain events as Server-Sent Events",
+		params: &[Param { name: "topics", required: true, description: "Comma separated event topics" }],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "text/event-stream", description: "Stream of events", schema: TEXT_SCHEMA },
+			BAD,
+		],
+		admin: false,
+	},
+	Route {
+		method: "get",
+		path: "/ipfs/{path}",
+		summary: "Get the file a CID and sub path resolve to, typed by its extension or first bytes",
+		params: &[GATEWAY_PATH],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "*/*", description: "The file, or the block if it is no file", schema: BINARY_SCHEMA },
+			GATEWAY_PARTIAL_CONTENT,
+			NOT_FOUND,
+			RANGE_NOT_SATISFIABLE,
+			TIMEOUT,
+		],
+		admin: false,
+	},
+	Route {
+		method: "get",
+		path: "/ipns/{path}",
+		summary: "Get the file an IPNS or dnslink name and sub path resolve to",
+		params: &[GATEWAY_PATH],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "*/*", description: "The file, or the block if it is no file", schema: BINARY_SCHEMA },
+			GATEWAY_PARTIAL_CONTENT,
+			NOT_FOUND,
+			RANGE_NOT_SATISFIABLE,
+			TIMEOUT,
+		],
+		admin: false,
+	},
+	Route {
+		method: "get",
+		path: SPEC_PATH,
+		summary: "This document",
+		params: &[],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "application/json", description: "OpenAPI 3 document", schema: SPEC_SCHEMA },
+		],
+		admin: false,
+	},
+	Route {
+		method: "get",
+		path: METRICS_PATH,
+		summary: "Metrics of the node in the Prometheus text format",
+		params: &[],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "text/plain", description: "Prometheus metrics", schema: TEXT_SCHEMA },
+		],
+		admin: false,
+	},
+	Route {
+		method: "get",
+		path: REPO_STAT_PATH,
+		summary: "Key count and disk usage of the wallet, chain, deals and block datastores, and their total",
+		params: &[],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "application/json", description: "Keys and bytes by datastore", schema: REPO_STAT_SCHEMA },
+			NOT_FOUND,
+			Response { status: 500, content_type: "application/json", description: "Measuring the repo failed", schema: ERROR_SCHEMA },
+		],
+		admin: false,
+	},
+	Route {
+		method: "get",
+		path: "/admin/keys",
+		summary: "List API keys with their quotas and usage",
+		params: &[],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "application/json", description: "The API keys", schema: KEYS_SCHEMA },
+			UNAUTHORIZED,
+		],
+		admin: true,
+	},
+	Route {
+		method: "post",
+		path: "/admin/keys",
+		summary: "Create an API key or change its scope and quota",
+		params: &[
+			TOKEN,
+			Param { name: "requests_per_min", required: true, description: "Requests allowed per minute" },
+			Param { name: "bytes_added_per_day", required: true, description: "Bytes uploaded to `/api/v0/add` allowed per day" },
+			Param { name: "scope", required: false, description: "Routes the key may use: `read`, `write` (the default) or `admin`" },
+		],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "application/json", description: "The API key", schema: KEY_SCHEMA },
+			BAD,
+			UNAUTHORIZED,
+		],
+		admin: true,
+	},
+	Route {
+		method: "post",
+		path: "/admin/keys/revoke",
+		summary: "Revoke an API key",
+		params: &[TOKEN],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "application/json", description: "The key was revoked", schema: REVOKED_SCHEMA },
+			NOT_FOUND,
+			UNAUTHORIZED,
+		],
+		admin: true,
+	},
+	Route {
+		method: "get",
+		path: REPO_STATS_PATH,
+		summary: "Count the stored blocks by codec and multihash type",
+		params: &[],
+		body: None,
+		responses: &[
+			Response { status: 200, content_type: "application/json", description: "Block counts and sizes", schema: REPO_STATS_SCHEMA },
+			NOT_FOUND,
+			UNAUTHORIZED,
+		],
+		admin: true,
+	},
+];
+
+/// The OpenAPI 3 document of `ROUTES`.
+///
+/// With `keys` the admin routes are included and every route requires a bearer token.
+pub fn openapi_json(keys: bool) -> String {
+	let routes: Vec<&Route> = ROUTES.iter().filter(|route| keys || !route.admin).collect();
+
+	let mut paths = Vec::new();
+	for route in &routes {
+		if !paths.contains(&route.path) {
+			paths.push(route.path);
+		}
+	}
+
+	let mut json = String::new();
+	write!(json, "{{\"openapi\":\"3.0.0\",\"info\":{{\"title\":\"IPFS API\",\"version\":{}}}", string(env!("CARGO_PKG_VERSION"))).unwrap();
+	if keys {
+		json.push_str(r#","components":{"securitySchemes":{"bearer":{"type":"http","scheme":"bearer"}}},"security":[{"bearer":[]}]"#);
+	}
+	json.push_str(",\"paths\":{");
+	for (i, path) in paths.iter().enumerate() {
+		if i > 0 {
+			json.push(',');
+		}
+		write!(json, "{}:{{", string(path)).unwrap();
+		let operations = routes.iter().filter(|route| route.path == *path);
+		for (j, route) in operations.enumerate() {
+			if j > 0 {
+				json.push(',');
+			}
+			operation(&mut json, route, keys);
+		}
+		json.push('}');
+	}
+	json.push_str("}}");
+	json
+}
+
+fn operation(json: &mut String, route: &Route, keys: bool) {
+	write!(json, "{}:{{\"summary\":{},\"parameters\":[", string(route.method), string(route.summary)).unwrap();
+	for (i, param) in route.params.iter().enumerate() {
+		if i > 0 {
+			json.push(',');
+		}
+		let location = if route.path.contains(&format!("{{{}}}", param.name)) { "path" } else { "query" };
+		write!(
+			json,
+			"{{\"name\":{},\"in\":{},\"required\":{},\"description\":{},\"schema\":{{\"type\":\"string\"}}}}",
+			string(param.name), string(location), param.required, string(param.description),
+		).unwrap();
+	}
+	json.push(']');
+	if let Some(content_type) = route.body {
+		write!(
+			json,
+			",\"requestBody\":{{\"required\":true,\"content\":{{{}:{{\"schema\":{{\"type\":\"object\",\"properties\":{{\"file\":{}}}}}}}}}}}",
+			string(content_type), BINARY_SCHEMA,
+		).unwrap();
+	}
+	json.push_str(",\"responses\":{");
+	let mut responses: Vec<&Response> = route.responses.iter().collect();
+	if keys && !route.admin {
+		responses.push(&UNAUTHORIZED);
+		responses.push(&TOO_MANY_REQUESTS);
+	}
+	responses.sort_by_key(|response| response.status);
+	for (i, response) in responses.iter().enumerate() {
+		if i > 0 {
+			json.push(',');
+		}
+		write!(
+			json,
+			"\"{}\":{{\"description\":{},\"content\":{{{}:{{\"schema\":{}}}}}}}",
+			response.status, string(response.description), string(response.content_type), response.schema,
+		).unwrap();
+	}
+	json.push_str("}}");
+}
+
+/// `value` as a JSON string.
+pub(crate) fn string(value: &str) -> String {
+	let mut json = String::with_capacity(value.len() + 2);
+	json.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => json.push_str("\\\""),
+			'\\' => json.push_str("\\\\"),
+			c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+			c => json.push(c),
+		}
+	}
+	json.push('"');
+	json
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_openapi_json() {
+		let json = openapi_json(false);
+
+		assert!(json.starts_with(r#"{"openapi":"3.0.0","info":{"title":"IPFS API","version":""#));
+		assert!(json.contains(r#""/api/v0/block/get":{"get":{"summary":"Get a raw block","parameters":[{"name":"arg","in":"query","required":true,"description":"CID of the block","schema":{"type":"string"}}]"#));
+		assert!(json.contains(r#""/eth/v1/events":{"get""#));
+		assert!(json.contains(r#""/api/v0/add":{"post":{"summary":"Add files as UnixFS, every part of the body is a file","parameters":[{"name":"pin","in":"query","required":false,"description":"Pin the added files recursively, true unless `false`","schema":{"type":"string"}}],"requestBody":{"required":true,"content":{"multipart/form-data":{"schema":{"type":"object","properties":{"file":{"type":"string","format":"binary"}}}}}},"responses""#));
+		assert!(json.contains(r#""/ipfs/{path}":{"get":{"summary":"Get the file a CID and sub path resolve to, typed by its extension or first bytes","parameters":[{"name":"path","in":"path","required":true"#));
+		assert!(json.contains(r#""/ipns/{path}":{"get""#));
+		assert!(json.contains(r#""/api/spec.json":{"get""#));
+		assert!(json.contains(r#""/metrics":{"get""#));
+		assert!(json.contains(r#""/api/v0/repo/stat":{"get""#));
+		assert!(!json.contains("/admin/keys"));
+		assert!(!json.contains("/admin/repo/stats"));
+		assert!(!json.contains("\"401\""));
+		assert_eq!(json.matches('{').count(), json.matches('}').count());
+	}
+
+	#[test]
+	fn test_openapi_json_with_keys() {
+		let json = openapi_json(true);
+
+		assert!(json.contains(r#""security":[{"bearer":[]}]"#));
+		assert!(json.contains(r#""/admin/keys":{"get":{"summary":"List API keys with their quotas and usage","parameters":[],"responses":{"200""#));
+		assert!(json.contains(r#"},"post":{"summary":"Create an API key or change its scope and quota""#));
+		assert!(json.contains(r#""/admin/keys/revoke":{"post""#));
+		assert!(json.contains(r#""/admin/repo/stats":{"get""#));
+		assert!(json.contains(r#""429":{"description":"The quota of the API key is used up, see `retry-after`""#));
+		assert_eq!(json.matches('{').count(), json.matches('}').count());
+	}
+
+	#[test]
+	fn test_string() {
+		assert_eq!(string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+	}
+}
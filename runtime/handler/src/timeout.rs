@@ -0,0 +1,114 @@
+//! Execution timeouts of the routes.
+//!
+//! Resolving a request may wait on data that never arrives. Every request runs with a deadline
+//! instead, and records the stage it is in so that a timed out request can be answered with a
+//! 504 saying where it got stuck.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Timeout of routes without one of their own.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Stage of a request before it reaches a specialized method.
+const ROUTE_STAGE: &str = "route";
+
+/// Execution timeout of every route, keyed by path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeouts {
+	default: Duration,
+	routes: HashMap<String, Duration>,
+}
+
+impl Default for Timeouts {
+	fn default() -> Self {
+		Timeouts::new(DEFAULT_TIMEOUT)
+	}
+}
+
+impl Timeouts {
+	/// Timeouts of `default` for every route.
+	pub fn new(default: Duration) -> Self {
+		Timeouts {
+			default,
+			routes: HashMap::new(),
+		}
+	}
+
+	/// Use `timeout` for requests to `path`.
+	pub fn with_route(mut self, path: &str, timeout: Duration) -> Self {
+		self.routes.insert(path.into(), timeout);
+		self
+	}
+
+	/// Timeout of requests to `path`.
+	pub fn for_route(&self, path: &str) -> Duration {
+		self.routes.get(path).cloned().unwrap_or(self.default)
+	}
+}
+
+/// The stage a request is in, shared between the request and its deadline.
+#[derive(Debug, Clone)]
+pub struct Stage(Arc<Mutex<&'static str>>);
+
+impl Default for Stage {
+	fn default() -> Self {
+		Stage(Arc::new(Mutex::new(ROUTE_STAGE)))
+	}
+}
+
+impl Stage {
+	pub fn new() -> Self {
+		Stage::default()
+	}
+
+	/// Record that the request moved on to `stage`.
+	pub fn enter(&self, stage: &'static str) {
+		*self.0.lock().expect("lock is never poisoned; qed") = stage;
+	}
+
+	/// The stage the request is in.
+	pub fn current(&self) -> &'static str {
+		*self.0.lock().expect("lock is never poisoned; qed")
+	}
+}
+
+/// JSON body of a 504 response.
+pub(crate) fn timeout_body(stage: &str, timeout: Duration) -> String {
+	let millis = timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis());
+	format!("{{\"error\":\"timeout\",\"stage\":\"{}\",\"timeout_ms\":{}}}", stage, millis)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_route_timeouts() {
+		let timeouts = Timeouts::new(Duration::from_secs(5))
+			.with_route("/api/v0/block/get", Duration::from_secs(1));
+
+		assert_eq!(timeouts.for_route("/api/v0/block/get"), Duration::from_secs(1));
+		assert_eq!(timeouts.for_route("/eth/v1/events"), Duration::from_secs(5));
+		assert_eq!(Timeouts::default().for_route("/foo"), DEFAULT_TIMEOUT);
+	}
+
+	#[test]
+	fn test_stage() {
+		let stage = Stage::new();
+		let shared = stage.clone();
+
+		assert_eq!(stage.current(), "route");
+		shared.enter("transaction");
+		assert_eq!(stage.current(), "transaction");
+	}
+
+	#[test]
+	fn test_timeout_body() {
+		assert_eq!(
+			timeout_body("block", Duration::from_millis(1500)),
+			r#"{"error":"timeout","stage":"block","timeout_ms":1500}"#
+		);
+	}
+}
@@ -0,0 +1,104 @@
+//! TLS for the server.
+//!
+//! With a `TlsConfig` the API is served over HTTPS only, so it can be exposed beyond localhost
+//! without a reverse proxy in front of it. Certificates and keys are read from PEM files when the
+//! server starts; client certificates are not asked for, requests are authorized by API key.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio_rustls::rustls::{self, internal::pemfile, NoClientAuth};
+use tokio_rustls::TlsAcceptor;
+
+use crate::error::ServerError;
+
+/// Time a client is given to complete the handshake before its connection is dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshakes run at once, further connections wait to be accepted.
+pub const MAX_PENDING_HANDSHAKES: usize = 64;
+
+/// Certificate and private key the server is bound with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+	/// PEM file with the certificate chain, the server's certificate first
+	pub cert_path: PathBuf,
+	/// PEM file with the PKCS#8 or RSA private key of the certificate
+	pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+	pub fn new<C: Into<PathBuf>, K: Into<PathBuf>>(cert_path: C, key_path: K) -> Self {
+		TlsConfig {
+			cert_path: cert_path.into(),
+			key_path: key_path.into(),
+		}
+	}
+
+	/// Load the certificate chain and the key into an acceptor of TLS connections.
+	pub fn acceptor(&self) -> Result<TlsAcceptor, ServerError> {
+		let certs = pemfile::certs(&mut open(&self.cert_path)?)
+			.map_err(|_| invalid(&self.cert_path, "unreadable certificate"))?;
+		if certs.is_empty() {
+			return Err(invalid(&self.cert_path, "no certificate"));
+		}
+
+		let mut keys = pemfile::pkcs8_private_keys(&mut open(&self.key_path)?)
+			.map_err(|_| invalid(&self.key_path, "unreadable private key"))?;
+		if keys.is_empty() {
+			keys = pemfile::rsa_private_keys(&mut open(&self.key_path)?)
+				.map_err(|_| invalid(&self.key_path, "unreadable private key"))?;
+		}
+		let key = keys.into_iter().next().ok_or_else(|| invalid(&self.key_path, "no private key"))?;
+
+		let mut config = rustls::ServerConfig::new(NoClientAuth::new());
+		config.set_single_cert(certs, key).map_err(|err| ServerError::Tls(err.to_string()))?;
+		Ok(TlsAcceptor::from(Arc::new(config)))
+	}
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, ServerError> {
+	File::open(path)
+		.map(BufReader::new)
+		.map_err(|err| ServerError::Tls(format!("{}: {}", path.display(), err)))
+}
+
+fn invalid(path: &Path, reason: &str) -> ServerError {
+	ServerError::Tls(format!("{}: {}", path.display(), reason))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::io::Write;
+
+	fn temp_file(name: &str, contents: &str) -> PathBuf {
+		let path = env::temp_dir().join(name);
+		File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+		path
+	}
+
+	#[test]
+	fn test_missing_files() {
+		let config = TlsConfig::new("/nonexistent/cert.pem", "/nonexistent/key.pem");
+
+		match config.acceptor() {
+			Err(ServerError::Tls(reason)) => assert!(reason.starts_with("/nonexistent/cert.pem")),
+			other => panic!("unexpected {:?}", other.map(|_| ())),
+		}
+	}
+
+	#[test]
+	fn test_no_certificate() {
+		let cert = temp_file("filesys-tls-test-cert.pem", "not a certificate\n");
+		let key = temp_file("filesys-tls-test-key.pem", "not a key\n");
+
+		match TlsConfig::new(cert, key).acceptor() {
+			Err(ServerError::Tls(reason)) => assert!(reason.ends_with("no certificate")),
+			other => panic!("unexpected {:?}", other.map(|_| ())),
+		}
+	}
+}
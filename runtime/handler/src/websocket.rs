@@ -0,0 +1,176 @@
+//! WebSocket connections, as much of RFC 6455 as streaming messages to a client takes.
+//!
+//! The server only sends text frames. Frames of the client are read to answer pings and to
+//! notice when it closes the connection, their data is dropped.
+
+use std::io;
+
+use futures::{future, pin_mut, stream, Stream, StreamExt, TryStreamExt};
+use http::hyper::{header, upgrade::Upgraded, Body, Request};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+/// Appended to the key of the client to derive the accept header.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest frame of the client read, clients have nothing to send but control frames.
+const MAX_CLIENT_FRAME_LEN: u64 = 64 * 1024;
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Whether `req` asks to switch to the WebSocket protocol.
+pub fn is_upgrade(req: &Request<Body>) -> bool {
+	let has_token = |name, token: &str| req.headers().get_all(name).iter()
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.any(|value| value.trim().eq_ignore_ascii_case(token));
+
+	has_token(header::CONNECTION, "upgrade") && has_token(header::UPGRADE, "websocket")
+		&& req.headers().get(header::SEC_WEBSOCKET_VERSION).map_or(false, |version| version == "13")
+}
+
+/// The `Sec-WebSocket-Accept` answering the `Sec-WebSocket-Key` of the client.
+pub fn accept_key(key: &str) -> String {
+	let digest = sha1::Sha1::from(format!("{}{}", key.trim(), GUID)).digest();
+	base64::encode(&digest.bytes())
+}
+
+/// An unmasked frame of the server, carrying all of `payload`.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+	let mut frame = Vec::with_capacity(payload.len() + 10);
+	frame.push(0x80 | opcode);
+	match payload.len() {
+		len if len < 126 => frame.push(len as u8),
+		len if len <= 0xffff => {
+			frame.push(126);
+			frame.extend_from_slice(&(len as u16).to_be_bytes());
+		},
+		len => {
+			frame.push(127);
+			frame.extend_from_slice(&(len as u64).to_be_bytes());
+		},
+	}
+	frame.extend_from_slice(payload);
+	frame
+}
+
+pub fn text_frame(text: &str) -> Vec<u8> {
+	frame(OPCODE_TEXT, text.as_bytes())
+}
+
+/// A frame of the client, unmasked.
+#[derive(Debug, PartialEq)]
+struct ClientFrame {
+	opcode: u8,
+	payload: Vec<u8>,
+}
+
+/// Length of the payload of a frame starting with `head`, and how many bytes encode it if it
+/// does not fit `head`. Frames of clients must be masked.
+fn payload_len(head: [u8; 2]) -> io::Result<(u64, usize)> {
+	if head[1] & 0x80 == 0 {
+		return Err(invalid("unmasked client frame"));
+	}
+	Ok(match head[1] & 0x7f {
+		126 => (0, 2),
+		127 => (0, 8),
+		len => (u64::from(len), 0),
+	})
+}
+
+fn invalid(reason: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, reason.to_owned())
+}
+
+/// Read the next frame of the client.
+async fn read_frame<R>(reader: &mut R) -> io::Result<ClientFrame>
+	where R: AsyncRead + Unpin
+{
+	let mut head = [0u8; 2];
+	reader.read_exact(&mut head).await?;
+	let opcode = head[0] & 0x0f;
+
+	let (len, extended) = payload_len(head)?;
+	let mut bytes = vec![0u8; extended];
+	reader.read_exact(&mut bytes).await?;
+	let len = bytes.iter().fold(len, |len, byte| len << 8 | u64::from(*byte));
+	if len > MAX_CLIENT_FRAME_LEN {
+		return Err(invalid("client frame too large"));
+	}
+
+	let mut bytes = vec![0u8; 4 + len as usize];
+	reader.read_exact(&mut bytes).await?;
+	let (mask, payload) = bytes.split_at(4);
+	let payload = payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]).collect();
+	Ok(ClientFrame { opcode, payload })
+}
+
+/// Send `messages` to the client of `upgraded` as text frames, until the client closes the
+/// connection or goes away.
+pub async fn serve<S>(upgraded: Upgraded, messages: S)
+	where S: Stream<Item = String>
+{
+	let (reader, mut writer) = tokio::io::split(upgraded);
+
+	// frames answering the client, the last one closes the connection.
+	let replies = stream::unfold(Some(reader), |reader| async move {
+		let mut reader = reader?;
+		Some(match read_frame(&mut reader).await {
+			Ok(frame) => match frame.opcode {
+				OPCODE_PING => (Some(Ok((self::frame(OPCODE_PONG, &frame.payload), false))), Some(reader)),
+				OPCODE_CLOSE => (Some(Ok((self::frame(OPCODE_CLOSE, &[]), true))), None),
+				_ => (None, Some(reader)),
+			},
+			Err(err) => (Some(Err(err)), None),
+		})
+	}).filter_map(future::ready);
+
+	let messages = messages.map(|message| Ok((text_frame(&message), false)));
+
+	let frames = stream::select(messages, replies);
+	pin_mut!(frames);
+	while let Ok(Some((frame, last))) = frames.try_next().await {
+		// a failed write stops sending, the connection is dropped after the close frame.
+		if writer.write_all(&frame).await.is_err() || last {
+			break;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+
+	#[test]
+	fn test_accept_key() {
+		// the example of RFC 6455, section 1.3.
+		assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+	}
+
+	#[test]
+	fn test_text_frame() {
+		assert_eq!(text_frame("Hello"), b"\x81\x05Hello".to_vec());
+
+		let long = "x".repeat(300);
+		let frame = text_frame(&long);
+		assert_eq!(&frame[..4], &[0x81, 126, 0x01, 0x2c]);
+		assert_eq!(frame.len(), 304);
+
+		let huge = "x".repeat(70000);
+		assert_eq!(&text_frame(&huge)[..10], &[0x81, 127, 0, 0, 0, 0, 0, 0x01, 0x11, 0x70]);
+	}
+
+	#[test]
+	fn test_read_frame() {
+		// a masked "Hello" from the client, RFC 6455 section 5.7.
+		let bytes: &[u8] = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+		let frame = block_on(read_frame(&mut io::Cursor::new(bytes))).unwrap();
+		assert_eq!(frame, ClientFrame { opcode: OPCODE_TEXT, payload: b"Hello".to_vec() });
+
+		let unmasked: &[u8] = b"\x81\x05Hello";
+		assert!(block_on(read_frame(&mut io::Cursor::new(unmasked))).is_err());
+	}
+}
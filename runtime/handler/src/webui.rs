@@ -0,0 +1,95 @@
+//! Web UI served on `/webui` with the `embedded-webui` feature.
+//!
+//! The UI is a single page app built into the binary from `webui/`. It shows the status, peers,
+//! pins and chain head of the node through the API, so a node can be looked after without other
+//! tools. Paths below `/webui` that are no asset are answered with `index.html` for the app to
+//! route itself.
+
+#[cfg(feature = "embedded-webui")]
+use crate::content_type;
+#[cfg(feature = "embedded-webui")]
+use include_dir::Dir;
+#[cfg(feature = "embedded-webui")]
+use crate::route::Out;
+
+/// Path the UI is served on.
+pub const WEBUI_PATH: &str = "/webui";
+
+/// Only the UI's own assets and API may be loaded, and it may not be framed.
+pub const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; script-src 'self'; style-src 'self'; img-src 'self' data:; connect-src 'self'; base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
+
+/// `index.html` is checked on every load so a new build is picked up at once.
+#[cfg(feature = "embedded-webui")]
+const INDEX_CACHE_CONTROL: &str = "no-cache";
+/// Asset names carry no hash, so they are only cached for a while.
+#[cfg(feature = "embedded-webui")]
+const ASSET_CACHE_CONTROL: &str = "public, max-age=3600";
+
+#[cfg(feature = "embedded-webui")]
+static ASSETS: Dir = include_dir!("webui");
+
+/// Whether `path` belongs to the UI.
+pub fn is_webui_path(path: &str) -> bool {
+	path == WEBUI_PATH || path.starts_with("/webui/")
+}
+
+/// Serve the asset at `path`, which belongs to the UI.
+#[cfg(feature = "embedded-webui")]
+pub(crate) fn serve(path: &str) -> Out {
+	let name = path[WEBUI_PATH.len()..].trim_start_matches('/');
+	let name = if name.is_empty() { "index.html" } else { name };
+
+	match ASSETS.get_file(name) {
+		Some(file) => asset(name, file.contents()),
+		// a path with an extension asks for an asset, others are routes of the app.
+		None if !name.rsplit('/').next().unwrap_or(name).contains('.') => {
+			let index = ASSETS.get_file("index.html").expect("index.html is part of the UI; qed");
+			asset("index.html", index.contents())
+		},
+		None => Out::NotFound("Asset not found"),
+	}
+}
+
+#[cfg(feature = "embedded-webui")]
+fn asset(name: &str, body: &'static [u8]) -> Out {
+	Out::Asset {
+		content_type: content_type::by_extension(name).unwrap_or(content_type::OCTET_STREAM),
+		cache_control: if name == "index.html" { INDEX_CACHE_CONTROL } else { ASSET_CACHE_CONTROL },
+		body,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_is_webui_path() {
+		assert!(is_webui_path("/webui"));
+		assert!(is_webui_path("/webui/app.js"));
+		assert!(!is_webui_path("/webuix"));
+		assert!(!is_webui_path("/api/v0/block/get"));
+	}
+
+	#[cfg(feature = "embedded-webui")]
+	#[test]
+	fn test_serve() {
+		match serve("/webui") {
+			Out::Asset { content_type, cache_control, body } => {
+				assert_eq!(content_type, "text/html; charset=utf-8");
+				assert_eq!(cache_control, INDEX_CACHE_CONTROL);
+				assert!(body.starts_with(b"<!DOCTYPE html>"));
+			},
+			out => panic!("unexpected {:?}", out),
+		}
+		match serve("/webui/app.js") {
+			Out::Asset { content_type, cache_control, .. } => {
+				assert_eq!(content_type, "application/javascript; charset=utf-8");
+				assert_eq!(cache_control, ASSET_CACHE_CONTROL);
+			},
+			out => panic!("unexpected {:?}", out),
+		}
+		assert_eq!(serve("/webui/peers"), serve("/webui/"));
+		assert_eq!(serve("/webui/missing.js"), Out::NotFound("Asset not found"));
+	}
+}
@@ -0,0 +1,132 @@
+'use strict';
+
+// The API key is kept in local storage and sent as a bearer token, every request needs it when
+// the node has API keys configured.
+const TOKEN_KEY = 'webui.token';
+
+function headers() {
+	const token = localStorage.getItem(TOKEN_KEY);
+	return token ? { Authorization: 'Bearer ' + token } : {};
+}
+
+function api(path) {
+	return fetch(path, { headers: headers() }).then((res) => {
+		if (!res.ok) {
+			throw new Error(res.status + ' ' + res.statusText);
+		}
+		return res.json();
+	});
+}
+
+function setText(id, text) {
+	document.getElementById(id).textContent = text;
+}
+
+function setList(id, items) {
+	const list = document.getElementById(id);
+	list.textContent = '';
+	for (const item of items) {
+		const li = document.createElement('li');
+		li.textContent = item;
+		list.appendChild(li);
+	}
+	if (items.length === 0) {
+		unavailable(list, 'none');
+	}
+}
+
+function unavailable(element, reason) {
+	const li = document.createElement(element.tagName === 'UL' ? 'li' : 'span');
+	li.className = 'unavailable';
+	li.textContent = reason;
+	element.textContent = '';
+	element.appendChild(li);
+}
+
+function loadStatus() {
+	api('/api/spec.json').then((spec) => {
+		setText('version', spec.info.version);
+		setText('routes', Object.keys(spec.paths).join(', '));
+	}).catch((err) => {
+		unavailable(document.getElementById('version'), err.message);
+		unavailable(document.getElementById('routes'), err.message);
+	});
+}
+
+function loadPeers() {
+	api('/api/v0/swarm/peers').then((res) => {
+		setList('peer-list', (res.Peers || []).map((peer) => peer.Peer + ' ' + peer.Addr));
+	}).catch((err) => unavailable(document.getElementById('peer-list'), err.message));
+}
+
+function loadPins() {
+	api('/api/v0/pin/ls').then((res) => {
+		setList('pin-list', Object.keys(res.Keys || {}).map((cid) => cid + ' ' + res.Keys[cid].Type));
+	}).catch((err) => unavailable(document.getElementById('pin-list'), err.message));
+}
+
+// `EventSource` can't send the API key, so the event stream is read with `fetch`.
+function followHead() {
+	fetch('/eth/v1/events?topics=head', { headers: headers() }).then((res) => {
+		if (!res.ok) {
+			throw new Error(res.status + ' ' + res.statusText);
+		}
+		const reader = res.body.getReader();
+		const decoder = new TextDecoder();
+		let buffer = '';
+		const read = () => reader.read().then(({ done, value }) => {
+			if (done) {
+				throw new Error('event stream closed');
+			}
+			buffer += decoder.decode(value, { stream: true });
+			let end;
+			while ((end = buffer.indexOf('\n\n')) >= 0) {
+				onEvent(buffer.slice(0, end));
+				buffer = buffer.slice(end + 2);
+			}
+			return read();
+		});
+		return read();
+	}).catch((err) => {
+		unavailable(document.getElementById('head-slot'), err.message);
+		setTimeout(followHead, 5000);
+	});
+}
+
+function onEvent(event) {
+	let name = '';
+	let data = '';
+	for (const line of event.split('\n')) {
+		if (line.startsWith('event:')) {
+			name = line.slice(6).trim();
+		} else if (line.startsWith('data:')) {
+			data += line.slice(5).trim();
+		}
+	}
+	if (name !== 'head' || !data) {
+		return;
+	}
+	const head = JSON.parse(data);
+	setText('head-slot', head.slot || '');
+	setText('head-block', head.block || '');
+	setText('head-state', head.state || '');
+}
+
+function load() {
+	loadStatus();
+	loadPeers();
+	loadPins();
+}
+
+document.getElementById('token-form').addEventListener('submit', (event) => {
+	event.preventDefault();
+	localStorage.setItem(TOKEN_KEY, document.getElementById('token').value);
+	load();
+});
+
+load();
+followHead();
+setInterval(() => {
+	loadPeers();
+	loadPins();
+}, 10000);
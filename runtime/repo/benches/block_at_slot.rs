@@ -0,0 +1,92 @@
+#[macro_use]
+extern crate bencher;
+
+use bencher::Bencher;
+use repo::test_utils::{import_chain, test_root, TestBlock};
+use repo::{BlockAtSlot, DataStore, DiskStore, Slot};
+use tempfile::{tempdir, TempDir};
+
+/// Blocks in the benchmarked chains.
+const CHAIN_LEN: u64 = 10_000;
+
+/// Opens a store holding a canonical chain at `slots`.
+fn chain_store(slots: &[Slot]) -> (TempDir, DiskStore) {
+    let dir = tempdir().unwrap();
+    let store = DiskStore::open(dir.path()).unwrap();
+    import_chain(&store, slots, 0);
+    (dir, store)
+}
+
+/// A deep chain skipping every tenth slot, looking up a skipped slot near the head.
+fn skipped_slot(bench: &mut Bencher) {
+    let slots: Vec<Slot> = (0..CHAIN_LEN * 10 / 9)
+        .filter(|slot| slot % 10 != 9)
+        .collect();
+    let (_dir, store) = chain_store(&slots);
+    let slot = slots[slots.len() - 2] + 1;
+
+    bench.iter(|| {
+        match store
+            .get_canonical_block_at_slot::<TestBlock>(slot)
+            .unwrap()
+        {
+            Some(BlockAtSlot::Ancestor(..)) => {}
+            other => panic!("expected an ancestor, got {:?}", other),
+        }
+    });
+}
+
+/// A deep chain whose head is a million slots after its parent, looking up the slot before it.
+fn long_gap(bench: &mut Bencher) {
+    let mut slots: Vec<Slot> = (0..CHAIN_LEN - 1).collect();
+    slots.push(CHAIN_LEN + 1_000_000);
+    let (_dir, store) = chain_store(&slots);
+    let slot = CHAIN_LEN + 999_999;
+
+    bench.iter(|| {
+        match store
+            .get_canonical_block_at_slot::<TestBlock>(slot)
+            .unwrap()
+        {
+            Some(BlockAtSlot::Ancestor(..)) => {}
+            other => panic!("expected an ancestor, got {:?}", other),
+        }
+    });
+}
+
+/// A deep chain, looking up its first slot from the head.
+fn preceeding_slot(bench: &mut Bencher) {
+    let slots: Vec<Slot> = (0..CHAIN_LEN).collect();
+    let (_dir, store) = chain_store(&slots);
+    let head = test_root(CHAIN_LEN - 1);
+
+    bench.iter(|| {
+        store
+            .get_block_at_preceeding_slot::<TestBlock>(head.clone(), 0)
+            .unwrap()
+            .unwrap()
+    });
+}
+
+/// Importing the next block of a deep chain.
+fn import_block(bench: &mut Bencher) {
+    let slots: Vec<Slot> = (0..CHAIN_LEN).collect();
+    let (_dir, store) = chain_store(&slots);
+    let parent = test_root(CHAIN_LEN - 1);
+    let root = test_root(CHAIN_LEN);
+
+    bench.iter(|| {
+        store
+            .import_canonical_block(root.clone(), TestBlock::new(CHAIN_LEN, &parent))
+            .unwrap()
+    });
+}
+
+benchmark_group!(
+    benches,
+    skipped_slot,
+    long_gap,
+    preceeding_slot,
+    import_block
+);
+benchmark_main!(benches);
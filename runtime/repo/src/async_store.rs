@@ -0,0 +1,245 @@
+//! Asynchronous access to a `DataStore`.
+//!
+//! `DataStore` methods block the calling thread until leveldb is done, which stalls an executor
+//! or a block processing thread for the length of a disk read. An `AsyncStore` runs them on its
+//! own pool of I/O threads instead and returns futures, so the calling thread keeps going while
+//! blocks and states are read and written.
+//!
+//! Operations run in no particular order: a write is only visible to operations started after its
+//! future completed.
+use super::*;
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, FutureExt};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// I/O threads of an `AsyncStore` created with `AsyncStore::new`.
+pub const DEFAULT_THREADS: usize = 4;
+
+/// The result of an operation run by an `AsyncStore`.
+pub type StoreFuture<T> = BoxFuture<'static, Result<T, Error>>;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs the operations of a `DataStore` on a pool of I/O threads.
+///
+/// Clones share the store and the pool. The threads exit once the last clone is dropped and the
+/// operations already started are done.
+pub struct AsyncStore<S: DataStore> {
+    store: Arc<S>,
+    jobs: Arc<Mutex<Sender<Job>>>,
+}
+
+impl<S: DataStore> Clone for AsyncStore<S> {
+    fn clone(&self) -> Self {
+        AsyncStore {
+            store: self.store.clone(),
+            jobs: self.jobs.clone(),
+        }
+    }
+}
+
+impl<S: DataStore + 'static> AsyncStore<S> {
+    /// Runs the operations of `store` on `DEFAULT_THREADS` threads.
+    pub fn new(store: S) -> Result<Self, Error> {
+        AsyncStore::with_threads(store, DEFAULT_THREADS)
+    }
+
+    /// Runs the operations of `store` on `threads` threads, at least one.
+    pub fn with_threads(store: S, threads: usize) -> Result<Self, Error> {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads.max(1) {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("repo-io-{}", i))
+                .spawn(move || run_jobs(&receiver))
+                .map_err(|e| Error::IoError {
+                    message: format!("failed to spawn I/O thread: {}", e),
+                })?;
+        }
+        Ok(AsyncStore {
+            store: Arc::new(store),
+            jobs: Arc::new(Mutex::new(sender)),
+        })
+    }
+
+    /// The wrapped store, whose methods block the calling thread.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Runs `operation` on an I/O thread.
+    pub fn run<T, F>(&self, operation: F) -> StoreFuture<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&S) -> Result<T, Error> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let store = self.store.clone();
+        let job: Job = Box::new(move || {
+            // The caller may have dropped the future already.
+            let _ = sender.send(operation(&store));
+        });
+        // The threads only exit once every sender is gone.
+        let _ = self.jobs.lock().unwrap().send(job);
+        receiver
+            .map(|result| {
+                result.unwrap_or_else(|_| {
+                    Err(Error::DBError {
+                        message: "store operation panicked".into(),
+                    })
+                })
+            })
+            .boxed()
+    }
+
+    /// See `DataStore::get`.
+    pub fn get<I: StoreItem + Send + 'static>(&self, key: Cid) -> StoreFuture<Option<I>> {
+        self.run(move |store| I::db_get(store, &key))
+    }
+
+    /// See `DataStore::put`.
+    pub fn put<I: StoreItem + Send + 'static>(&self, key: Cid, item: I) -> StoreFuture<()> {
+        self.run(move |store| item.db_put(store, &key))
+    }
+
+    /// See `DataStore::exists`.
+    pub fn exists<I: StoreItem>(&self, key: Cid) -> StoreFuture<bool> {
+        self.run(move |store| I::db_exists(store, &key))
+    }
+
+    /// See `DataStore::delete`.
+    pub fn delete<I: StoreItem>(&self, key: Cid) -> StoreFuture<()> {
+        self.run(move |store| I::db_delete(store, &key))
+    }
+
+    /// See `DataStore::get_block_at_preceeding_slot`.
+    pub fn get_block_at_preceeding_slot<B: SlotBlock + Send + 'static>(
+        &self,
+        start_block_root: Cid,
+        slot: Slot,
+    ) -> StoreFuture<Option<(Cid, B)>> {
+        self.run(move |store| store.get_block_at_preceeding_slot(start_block_root, slot))
+    }
+
+    /// See `DataStore::get_canonical_block_at_slot`.
+    pub fn get_canonical_block_at_slot<B: SlotBlock + Send + 'static>(
+        &self,
+        slot: Slot,
+    ) -> StoreFuture<Option<BlockAtSlot<B>>> {
+        self.run(move |store| store.get_canonical_block_at_slot(slot))
+    }
+
+    /// See `DataStore::import_canonical_block`.
+    pub fn import_canonical_block<B: SlotBlock + Send + 'static>(
+        &self,
+        root: Cid,
+        block: B,
+    ) -> StoreFuture<()> {
+        self.run(move |store| store.import_canonical_block(root, block))
+    }
+
+    /// See `DataStore::set_canonical_head`.
+    pub fn set_canonical_head<B: SlotBlock>(&self, root: Cid) -> StoreFuture<()> {
+        self.run(move |store| store.set_canonical_head::<B>(root))
+    }
+
+    /// See `DataStore::get_bytes`.
+    pub fn get_bytes(&self, column: ColumnId, key: Vec<u8>) -> StoreFuture<Option<Vec<u8>>> {
+        self.run(move |store| store.get_bytes(column, &key))
+    }
+
+    /// See `DataStore::put_bytes`.
+    pub fn put_bytes(&self, column: ColumnId, key: Vec<u8>, value: Vec<u8>) -> StoreFuture<()> {
+        self.run(move |store| store.put_bytes(column, &key, &value))
+    }
+
+    /// See `DataStore::key_exists`.
+    pub fn key_exists(&self, column: ColumnId, key: Vec<u8>) -> StoreFuture<bool> {
+        self.run(move |store| store.key_exists(column, &key))
+    }
+
+    /// See `DataStore::key_delete`.
+    pub fn key_delete(&self, column: ColumnId, key: Vec<u8>) -> StoreFuture<()> {
+        self.run(move |store| store.key_delete(column, &key))
+    }
+
+    /// See `DataStore::do_atomically`.
+    pub fn do_atomically(&self, ops: Vec<StoreOp>) -> StoreFuture<()> {
+        self.run(move |store| store.do_atomically(ops))
+    }
+}
+
+/// Runs jobs until every sender is dropped.
+fn run_jobs(jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        // A panicking job drops its sender, which fails its future. Keep the thread.
+        let _ = panic::catch_unwind(AssertUnwindSafe(job));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::future::join_all;
+
+    #[test]
+    fn bytes_roundtrip() {
+        let store = AsyncStore::with_threads(MemoryStore::open(), 2).unwrap();
+        let blk: ColumnId = DBColumn::BeaconBlock.into();
+
+        block_on(store.put_bytes(blk, b"key".to_vec(), b"1".to_vec())).unwrap();
+        assert_eq!(
+            block_on(store.get_bytes(blk, b"key".to_vec())),
+            Ok(Some(b"1".to_vec()))
+        );
+        assert_eq!(block_on(store.key_exists(blk, b"key".to_vec())), Ok(true));
+        assert_eq!(
+            store.store().get_bytes(blk, b"key"),
+            Ok(Some(b"1".to_vec()))
+        );
+
+        block_on(store.key_delete(blk, b"key".to_vec())).unwrap();
+        assert_eq!(block_on(store.key_exists(blk, b"key".to_vec())), Ok(false));
+    }
+
+    #[test]
+    fn concurrent_operations() {
+        let store = AsyncStore::new(MemoryStore::open()).unwrap();
+        let ste: ColumnId = DBColumn::BeaconState.into();
+
+        let puts = (0..64u32).map(|i| {
+            let ops = vec![StoreOp::Put {
+                column: ste,
+                key: i.to_be_bytes().to_vec(),
+                value: i.to_le_bytes().to_vec(),
+            }];
+            store.clone().do_atomically(ops)
+        });
+        assert!(block_on(join_all(puts)).into_iter().all(|put| put.is_ok()));
+
+        let gets = (0..64u32).map(|i| store.get_bytes(ste, i.to_be_bytes().to_vec()));
+        let values = block_on(join_all(gets));
+        assert!((0..64u32).all(|i| values[i as usize] == Ok(Some(i.to_le_bytes().to_vec()))));
+    }
+
+    #[test]
+    fn errors_and_panics() {
+        let store = AsyncStore::with_threads(MemoryStore::open(), 1).unwrap();
+        let other = ColumnId::custom("oth", StoreCodec::Raw, Compression::None);
+
+        assert!(block_on(store.get_bytes(other, b"key".to_vec())).is_err());
+        let panicked: Result<(), Error> = block_on(store.run(|_| panic!("operation failed")));
+        assert!(panicked.is_err());
+        // the thread survived the panic.
+        assert_eq!(block_on(store.run(|_| Ok(1))), Ok(1));
+    }
+}
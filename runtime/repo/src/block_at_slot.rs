@@ -0,0 +1,335 @@
+//! Locating blocks by slot.
+//!
+//! Canonical blocks are indexed by slot in `DBColumn::SlotIndex`, so finding the block at a slot
+//! is a single lookup instead of a walk over every ancestor. The index only describes the
+//! canonical chain: lookups starting from a block on another fork, or at slots the index does not
+//! cover yet, fall back to walking parent roots.
+//!
+//! The index is written by `DataStore::import_canonical_block` and
+//! `DataStore::set_canonical_head`, in the same batch as the block or head they record. Changing
+//! the head re-indexes the slots of the new chain down to where it joins the old one, and clears
+//! the slots the old chain filled but the new one skips.
+use super::*;
+use crate::query::{Order, Query};
+use std::ops::Bound;
+
+pub type Slot = u64;
+
+/// A block that knows its slot and its parent.
+pub trait SlotBlock: StoreItem {
+    fn slot(&self) -> Slot;
+
+    fn parent_root(&self) -> Cid;
+}
+
+/// A canonical block found by `get_canonical_block_at_slot`.
+#[derive(Debug, PartialEq)]
+pub enum BlockAtSlot<B> {
+    /// The block at the requested slot.
+    Exact(Cid, B),
+    /// The requested slot was skipped, this is the closest canonical block before it.
+    Ancestor(Cid, B),
+}
+
+/// Index keys are big endian so that slots sort in key order.
+fn slot_key(slot: Slot) -> [u8; 8] {
+    slot.to_be_bytes()
+}
+
+/// Returns the operation recording `root` as the canonical block at `slot`.
+pub fn index_op(slot: Slot, root: &Cid) -> StoreOp {
+    StoreOp::Put {
+        column: DBColumn::SlotIndex.into(),
+        key: slot_key(slot).to_vec(),
+        value: root.to_bytes(),
+    }
+}
+
+/// Returns the operation clearing the index at `slot`, for slots left empty by a re-org.
+pub fn unindex_op(slot: Slot) -> StoreOp {
+    StoreOp::Delete {
+        column: DBColumn::SlotIndex.into(),
+        key: slot_key(slot).to_vec(),
+    }
+}
+
+fn decode_slot(key: &[u8]) -> Result<Slot, Error> {
+    let mut slot = [0; 8];
+    if key.len() != slot.len() {
+        return Err(Error::DecodeError {
+            message: format!("slot index key of {} bytes", key.len()),
+        });
+    }
+    slot.copy_from_slice(key);
+    Ok(Slot::from_be_bytes(slot))
+}
+
+fn decode_root(bytes: &[u8]) -> Result<Cid, Error> {
+    Cid::from(bytes).map_err(|e| Error::DecodeError {
+        message: format!("{:?}", e),
+    })
+}
+
+fn indexed_root<T: DataStore>(store: &T, slot: Slot) -> Result<Option<Cid>, Error> {
+    match store.get_bytes(DBColumn::SlotIndex.into(), &slot_key(slot))? {
+        Some(bytes) => decode_root(&bytes).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Returns the operations clearing the index strictly between `start` and `end`.
+fn unindex_between<T: DataStore>(
+    store: &T,
+    start: Bound<Slot>,
+    end: Bound<Slot>,
+) -> Result<Vec<StoreOp>, Error> {
+    let key = |bound: Bound<Slot>| match bound {
+        Bound::Included(slot) => Bound::Included(slot_key(slot).to_vec()),
+        Bound::Excluded(slot) => Bound::Excluded(slot_key(slot).to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let query = Query::new(DBColumn::SlotIndex.into())
+        .range((key(start), key(end)))
+        .keys_only();
+    store
+        .query(&query)?
+        .map(|entry| decode_slot(&entry.key).map(unindex_op))
+        .collect()
+}
+
+/// Returns the operations making `root`, the block `head`, the head of the indexed chain.
+///
+/// Walks parent roots from `head` until it reaches a block the index already holds, or one that
+/// is not stored, so importing a child of the head only costs a lookup.
+fn head_ops<T: DataStore, B: SlotBlock>(
+    store: &T,
+    mut root: Cid,
+    head: B,
+) -> Result<Vec<StoreOp>, Error> {
+    // Slots after the new head belong to the old chain.
+    let mut ops = unindex_between(store, Bound::Excluded(head.slot()), Bound::Unbounded)?;
+
+    let mut block = head;
+    while indexed_root(store, block.slot())?.as_ref() != Some(&root) {
+        ops.push(index_op(block.slot(), &root));
+        let parent_root = block.parent_root();
+        let parent: B = match store.get(&parent_root)? {
+            Some(parent) => parent,
+            None => break,
+        };
+        // Slots the new chain skips between the parent and the block.
+        ops.extend(unindex_between(
+            store,
+            Bound::Excluded(parent.slot()),
+            Bound::Excluded(block.slot()),
+        )?);
+        root = parent_root;
+        block = parent;
+    }
+    Ok(ops)
+}
+
+/// See `DataStore::import_canonical_block`.
+pub fn import_canonical_block<T: DataStore, B: SlotBlock>(
+    store: &T,
+    root: Cid,
+    block: B,
+) -> Result<(), Error> {
+    let mut ops = vec![block.as_put_op(&root)];
+    ops.extend(head_ops(store, root, block)?);
+    store.do_atomically(ops)
+}
+
+/// See `DataStore::set_canonical_head`.
+pub fn set_canonical_head<T: DataStore, B: SlotBlock>(store: &T, root: Cid) -> Result<(), Error> {
+    let head: B = store.get(&root)?.ok_or_else(|| Error::DBError {
+        message: "canonical head is missing".to_string(),
+    })?;
+    let ops = head_ops(store, root, head)?;
+    store.do_atomically(ops)
+}
+
+/// See `DataStore::get_block_at_preceeding_slot`.
+pub fn get_block_at_preceeding_slot<T: DataStore, B: SlotBlock>(
+    store: &T,
+    slot: Slot,
+    start_block_root: Cid,
+) -> Result<Option<(Cid, B)>, Error> {
+    let start: B = match store.get(&start_block_root)? {
+        Some(block) => block,
+        None => return Ok(None),
+    };
+    if start.slot() < slot {
+        return Ok(None);
+    }
+
+    // Only trust the index if the start block is part of the chain it describes.
+    if indexed_root(store, start.slot())?.as_ref() == Some(&start_block_root) {
+        if let Some(root) = indexed_root(store, slot)? {
+            if let Some(block) = store.get(&root)? {
+                return Ok(Some((root, block)));
+            }
+        }
+    }
+
+    walk_to_slot(store, slot, start_block_root, start)
+}
+
+/// Follows parent roots from `root` down to `slot`.
+fn walk_to_slot<T: DataStore, B: SlotBlock>(
+    store: &T,
+    slot: Slot,
+    mut root: Cid,
+    mut block: B,
+) -> Result<Option<(Cid, B)>, Error> {
+    loop {
+        if block.slot() == slot {
+            return Ok(Some((root, block)));
+        }
+        if block.slot() < slot {
+            return Ok(None);
+        }
+        root = block.parent_root();
+        block = match store.get(&root)? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+    }
+}
+
+/// See `DataStore::get_canonical_block_at_slot`.
+///
+/// Reads the last index entry not after `slot` with a descending query, so skipped slots cost
+/// nothing on backends that seek backwards.
+pub fn get_canonical_block_at_slot<T: DataStore, B: SlotBlock>(
+    store: &T,
+    slot: Slot,
+) -> Result<Option<BlockAtSlot<B>>, Error> {
+    let query = Query::new(DBColumn::SlotIndex.into())
+        .range(..=slot_key(slot).to_vec())
+        .order(Order::Descending)
+        .limit(1);
+    let entry = match store.query(&query)?.next() {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    let found = decode_slot(&entry.key)?;
+    let root = decode_root(&entry.value.unwrap_or_default())?;
+    let block = store.get(&root)?.ok_or_else(|| Error::DBError {
+        message: format!("block indexed at slot {} is missing", found),
+    })?;
+    Ok(Some(if found == slot {
+        BlockAtSlot::Exact(root, block)
+    } else {
+        BlockAtSlot::Ancestor(root, block)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{create_memory_store, import_chain, test_root, TestBlock};
+
+    fn at_slot(store: &MemoryStore, slot: Slot) -> Option<BlockAtSlot<TestBlock>> {
+        store.get_canonical_block_at_slot(slot).unwrap()
+    }
+
+    #[test]
+    fn canonical_block_at_slot() {
+        let store = create_memory_store();
+        let roots = import_chain(&*store, &[0, 1, 4, 5], 0);
+
+        assert_eq!(
+            at_slot(&store, 1),
+            Some(BlockAtSlot::Exact(
+                roots[1].clone(),
+                TestBlock::new(1, &roots[0])
+            ))
+        );
+        assert_eq!(
+            at_slot(&store, 3),
+            Some(BlockAtSlot::Ancestor(
+                roots[1].clone(),
+                TestBlock::new(1, &roots[0])
+            ))
+        );
+        assert_eq!(
+            at_slot(&store, 9),
+            Some(BlockAtSlot::Ancestor(
+                roots[3].clone(),
+                TestBlock::new(5, &roots[2])
+            ))
+        );
+    }
+
+    #[test]
+    fn cold_index() {
+        let store = create_memory_store();
+        assert_eq!(at_slot(&store, 3), None);
+
+        // Blocks stored without being imported are found by walking parent roots.
+        let genesis = test_root(0);
+        let root = test_root(1);
+        store
+            .put(&genesis, &TestBlock::new(0, &test_root(99)))
+            .unwrap();
+        store.put(&root, &TestBlock::new(2, &genesis)).unwrap();
+        assert_eq!(at_slot(&store, 3), None);
+        assert_eq!(
+            store.get_block_at_preceeding_slot(root, 0).unwrap(),
+            Some((genesis.clone(), TestBlock::new(0, &test_root(99))))
+        );
+    }
+
+    #[test]
+    fn reorg() {
+        let store = create_memory_store();
+        let old = import_chain(&*store, &[0, 1, 2, 3, 5], 0);
+
+        // A fork off slot 1 that skips slot 2 and stops at slot 4.
+        let fork = test_root(100);
+        store.put(&fork, &TestBlock::new(3, &old[1])).unwrap();
+        let head = test_root(101);
+        store.put(&head, &TestBlock::new(4, &fork)).unwrap();
+        store.set_canonical_head::<TestBlock>(head.clone()).unwrap();
+
+        assert_eq!(
+            at_slot(&store, 2),
+            Some(BlockAtSlot::Ancestor(
+                old[1].clone(),
+                TestBlock::new(1, &old[0])
+            ))
+        );
+        assert_eq!(
+            at_slot(&store, 3),
+            Some(BlockAtSlot::Exact(fork.clone(), TestBlock::new(3, &old[1])))
+        );
+        assert_eq!(
+            at_slot(&store, 7),
+            Some(BlockAtSlot::Ancestor(
+                head.clone(),
+                TestBlock::new(4, &fork)
+            ))
+        );
+
+        // Reverting to the old head restores its index.
+        store
+            .set_canonical_head::<TestBlock>(old[4].clone())
+            .unwrap();
+        assert_eq!(
+            at_slot(&store, 4),
+            Some(BlockAtSlot::Ancestor(
+                old[3].clone(),
+                TestBlock::new(3, &old[2])
+            ))
+        );
+        assert_eq!(
+            at_slot(&store, 2),
+            Some(BlockAtSlot::Exact(
+                old[2].clone(),
+                TestBlock::new(2, &old[1])
+            ))
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Bloom filters over the keys of a column.
+//!
+//! Looking up a key that is not there is the common case of `DataStore::key_exists`, for instance
+//! while checking a want list during sync, and costs a disk read in leveldb. A `BloomFilter` holds
+//! every key of a column in a few bits per key and answers most of these lookups in memory: it has
+//! no false negatives, and a false positive only costs the disk read it was meant to save.
+//!
+//! Deleted keys stay in the filter until it is rebuilt, which only raises the false positive rate.
+//! A filter is sized for `BloomConfig::expected_keys`: past that, the rate rises too.
+use super::*;
+
+/// Bytes every serialized filter starts with.
+const MAGIC: &[u8] = b"fsbloom1";
+
+/// Sizing of a bloom filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BloomConfig {
+    /// Number of keys the filter is sized for.
+    pub expected_keys: u64,
+    /// Rate of false positives once the filter holds `expected_keys` keys, between 0 and 1.
+    pub false_positive_rate: f64,
+}
+
+impl Default for BloomConfig {
+    /// A million keys at 1%, which takes about 1.2MB.
+    fn default() -> Self {
+        BloomConfig {
+            expected_keys: 1_000_000,
+            false_positive_rate: 0.01,
+        }
+    }
+}
+
+/// A set of keys answering "maybe" or "no".
+#[derive(Clone, Debug, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// An empty filter sized for `config`.
+    pub fn new(config: BloomConfig) -> Self {
+        let keys = config.expected_keys.max(1) as f64;
+        let rate = if config.false_positive_rate > 0.0 {
+            config.false_positive_rate.min(0.5)
+        } else {
+            1e-9
+        };
+        let ln2 = std::f64::consts::LN_2;
+
+        let bits = (-keys * rate.ln() / (ln2 * ln2)).ceil() as u64;
+        let words = (bits / 64 + 1) as usize;
+        let hashes = ((words * 64) as f64 / keys * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0; words],
+            hashes,
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        for bit in self.bit_indexes(key) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Returns `false` if `key` was never inserted.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_indexes(key)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Returns `true` if `self` has the size `config` asks for.
+    pub fn is_sized_for(&self, config: BloomConfig) -> bool {
+        let empty = BloomFilter::new(config);
+        self.bits.len() == empty.bits.len() && self.hashes == empty.hashes
+    }
+
+    /// The bits probed for `key`, by double hashing.
+    fn bit_indexes(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let len = (self.bits.len() * 64) as u64;
+        let h1 = mix(fnv1a(key));
+        let h2 = mix(h1 ^ 0x9e37_79b9_7f4a_7c15) | 1;
+        (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 16 + self.bits.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        bytes.extend_from_slice(&(self.bits.len() as u64).to_le_bytes());
+        for word in &self.bits {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let checksum = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a filter serialized by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let invalid = |message: &str| Error::DecodeError {
+            message: format!("invalid bloom filter: {}", message),
+        };
+        let header = MAGIC.len() + 12;
+        if bytes.len() < header + 4 || !bytes.starts_with(MAGIC) {
+            return Err(invalid("bad header"));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32fast::hash(body).to_le_bytes() != checksum {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        let hashes = u32::from_le_bytes(le_bytes(&body[MAGIC.len()..]));
+        let words = u64::from_le_bytes(le_bytes(&body[MAGIC.len() + 4..]));
+        if hashes == 0 || words == 0 || (body.len() - header) as u64 != words * 8 {
+            return Err(invalid("bad size"));
+        }
+        let bits = body[header..]
+            .chunks(8)
+            .map(|word| u64::from_le_bytes(le_bytes(word)))
+            .collect();
+        Ok(BloomFilter { bits, hashes })
+    }
+}
+
+fn le_bytes<T: Default + AsMut<[u8]>>(bytes: &[u8]) -> T {
+    let mut array = T::default();
+    let len = array.as_mut().len();
+    array.as_mut().copy_from_slice(&bytes[..len]);
+    array
+}
+
+/// FNV-1a, which unlike the std hashers is stable across releases, as persisted filters need.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The splitmix64 finalizer, spreading the FNV hash of short keys over all bits.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BloomConfig {
+        BloomConfig {
+            expected_keys: 1000,
+            false_positive_rate: 0.01,
+        }
+    }
+
+    #[test]
+    fn no_false_negatives() {
+        let mut filter = BloomFilter::new(config());
+        for i in 0..1000u32 {
+            filter.insert(&i.to_be_bytes());
+        }
+
+        assert!((0..1000u32).all(|i| filter.may_contain(&i.to_be_bytes())));
+        let false_positives = (1000..11000u32)
+            .filter(|i| filter.may_contain(&i.to_be_bytes()))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn bytes_roundtrip() {
+        let mut filter = BloomFilter::new(config());
+        filter.insert(b"key");
+
+        let mut bytes = filter.to_bytes();
+        assert_eq!(BloomFilter::from_bytes(&bytes), Ok(filter.clone()));
+        assert!(filter.is_sized_for(config()));
+        assert!(!filter.is_sized_for(BloomConfig::default()));
+
+        bytes[MAGIC.len() + 12] ^= 1;
+        assert!(BloomFilter::from_bytes(&bytes).is_err());
+        assert!(BloomFilter::from_bytes(&bytes[..10]).is_err());
+    }
+}
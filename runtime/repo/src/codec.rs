@@ -0,0 +1,151 @@
+//! Serialization of column values.
+//!
+//! Every column records the `StoreCodec` its values are written with, see `ColumnId::codec`, so
+//! values can be told apart without knowing the item type that wrote them.
+//!
+//! Items stored with SSZ do not implement `StoreItem` by hand: implementing `SszStoreItem` for a
+//! type that is `ssz::Encode + ssz::Decode` gives it `StoreItem` through a blanket impl. Items
+//! stored with CBOR implement `StoreItem` with `cbor_encode` and `cbor_decode`.
+use super::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// How the values of a column are serialized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StoreCodec {
+    /// SimpleSerialize, used by the eth2 types.
+    Ssz,
+    /// CBOR, used by the serde types.
+    Cbor,
+    /// Bytes with a layout of their own, such as keys or indexes.
+    Raw,
+}
+
+impl StoreCodec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StoreCodec::Ssz => "ssz",
+            StoreCodec::Cbor => "cbor",
+            StoreCodec::Raw => "raw",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<StoreCodec> {
+        match s {
+            "ssz" => Some(StoreCodec::Ssz),
+            "cbor" => Some(StoreCodec::Cbor),
+            "raw" => Some(StoreCodec::Raw),
+            _ => None,
+        }
+    }
+}
+
+/// An item stored with SSZ in `db_column`, which gets `StoreItem` for free.
+pub trait SszStoreItem: ssz::Encode + ssz::Decode {
+    /// Identifies which column this item should be placed in.
+    fn db_column() -> ColumnId;
+}
+
+impl<T: SszStoreItem> StoreItem for T {
+    fn db_column() -> ColumnId {
+        <T as SszStoreItem>::db_column()
+    }
+
+    fn as_store_bytes(&self) -> Vec<u8> {
+        self.as_ssz_bytes()
+    }
+
+    fn from_store_bytes(bytes: &mut [u8]) -> Result<Self, Error> {
+        Self::from_ssz_bytes(bytes).map_err(|e| Error::DecodeError {
+            message: format!("{:?}", e),
+        })
+    }
+}
+
+/// Serializes `item` with CBOR, for `StoreItem::as_store_bytes`.
+pub fn cbor_encode<T: Serialize>(item: &T) -> Vec<u8> {
+    serde_cbor::to_vec(item).expect("serializing to a Vec never fails")
+}
+
+/// Deserializes an item serialized by `cbor_encode`, for `StoreItem::from_store_bytes`.
+pub fn cbor_decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    serde_cbor::from_slice(bytes).map_err(|e| Error::DecodeError {
+        message: format!("invalid CBOR: {}", e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+    use ssz_derive::{Decode, Encode};
+
+    #[derive(Debug, PartialEq, Encode, Decode)]
+    struct Checkpoint {
+        epoch: u64,
+        root: Vec<u8>,
+    }
+
+    impl SszStoreItem for Checkpoint {
+        fn db_column() -> ColumnId {
+            DBColumn::ForkChoice.into()
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Deal {
+        id: u64,
+        client: String,
+    }
+
+    #[test]
+    fn ssz_store_item() {
+        let checkpoint = Checkpoint {
+            epoch: 3,
+            root: vec![1, 2, 3],
+        };
+        let mut bytes = checkpoint.as_store_bytes();
+        assert_eq!(bytes, ssz::Encode::as_ssz_bytes(&checkpoint));
+        assert_eq!(Checkpoint::from_store_bytes(&mut bytes), Ok(checkpoint));
+        assert_eq!(
+            <Checkpoint as StoreItem>::db_column(),
+            ColumnId::from(DBColumn::ForkChoice)
+        );
+
+        match Checkpoint::from_store_bytes(&mut [1, 2]) {
+            Err(Error::DecodeError { .. }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cbor_roundtrip() {
+        let deal = Deal {
+            id: 7,
+            client: "alice".to_string(),
+        };
+        assert_eq!(cbor_decode::<Deal>(&cbor_encode(&deal)), Ok(deal));
+        assert!(cbor_decode::<Deal>(b"\xff").is_err());
+    }
+
+    #[test]
+    fn column_codecs() {
+        assert_eq!(DBColumn::BeaconBlock.codec(), StoreCodec::Ssz);
+        assert_eq!(DBColumn::Deals.codec(), StoreCodec::Cbor);
+        assert_eq!(DBColumn::SlotIndex.codec(), StoreCodec::Raw);
+
+        let mut registry = ColumnRegistry::new();
+        let raw = registry.register("ext").unwrap();
+        let ssz = registry
+            .register_with_codec("eth", StoreCodec::Ssz)
+            .unwrap();
+        assert_eq!(raw.codec(), StoreCodec::Raw);
+        assert_eq!(ssz.codec(), StoreCodec::Ssz);
+        assert_eq!(registry.id("dls").unwrap().codec(), StoreCodec::Cbor);
+
+        for codec in &[StoreCodec::Ssz, StoreCodec::Cbor, StoreCodec::Raw] {
+            assert_eq!(StoreCodec::from_str(codec.as_str()), Some(*codec));
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::{Compression, Error, StoreCodec};
+
+/// A unique column identifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DBColumn {
+    Wallet,
+    Keystore,
+    BeaconBlock,
+    BeaconState,
+    BeaconChain,
+    Deals,
+    /// Index entries of the deals, see the `deals` module.
+    DealIndex,
+    Ipns,
+    ForkChoice,
+    OpPool,
+    SlotIndex,
+    /// A column registered by a downstream crate through `ColumnRegistry::register`.
+    Custom(&'static str),
+}
+
+/// Every built-in column, in declaration order.
+const BUILTIN_COLUMNS: [DBColumn; 11] = [
+    DBColumn::Wallet,
+    DBColumn::Keystore,
+    DBColumn::BeaconBlock,
+    DBColumn::BeaconState,
+    DBColumn::BeaconChain,
+    DBColumn::Deals,
+    DBColumn::DealIndex,
+    DBColumn::Ipns,
+    DBColumn::ForkChoice,
+    DBColumn::OpPool,
+    DBColumn::SlotIndex,
+];
+
+impl DBColumn {
+    /// Returns a `&str` that can be used for keying a key-value data base.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DBColumn::Wallet => "wat",
+            DBColumn::Keystore => "kst",
+            DBColumn::BeaconBlock => "blk",
+            DBColumn::BeaconState => "ste",
+            DBColumn::BeaconChain => "bch",
+            DBColumn::Deals => "dls",
+            DBColumn::DealIndex => "dlx",
+            DBColumn::Ipns => "ipn",
+            DBColumn::ForkChoice => "frk",
+            DBColumn::OpPool => "opo",
+            DBColumn::SlotIndex => "sli",
+            DBColumn::Custom(name) => name,
+        }
+    }
+
+    /// Returns how the values of this built-in column are compressed, see the `compression`
+    /// module.
+    pub fn compression(&self) -> Compression {
+        match self {
+            DBColumn::BeaconBlock => Compression::Snappy,
+            DBColumn::BeaconState => Compression::Zstd { level: 3 },
+            _ => Compression::None,
+        }
+    }
+
+    /// Returns how the values of this built-in column are serialized, see the `codec` module.
+    ///
+    /// The codec of a custom column is the one it was registered with, see `ColumnId::codec`.
+    pub fn codec(&self) -> StoreCodec {
+        match self {
+            DBColumn::BeaconBlock
+            | DBColumn::BeaconState
+            | DBColumn::BeaconChain
+            | DBColumn::ForkChoice
+            | DBColumn::OpPool => StoreCodec::Ssz,
+            DBColumn::Deals => StoreCodec::Cbor,
+            DBColumn::Wallet
+            | DBColumn::Keystore
+            | DBColumn::DealIndex
+            | DBColumn::Ipns
+            | DBColumn::SlotIndex
+            | DBColumn::Custom(_) => StoreCodec::Raw,
+        }
+    }
+
+    /// Returns the built-in column keyed by `s`, if any.
+    ///
+    /// Custom columns are only known to a `ColumnRegistry`, see `ColumnRegistry::id`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<DBColumn> {
+        Self::all().iter().find(|column| column.as_str() == s).cloned()
+    }
+
+    /// Returns all built-in columns.
+    pub fn all() -> &'static [DBColumn] {
+        &BUILTIN_COLUMNS
+    }
+}
+
+/// A column a store is opened with: its key, how its values are serialized and how they are
+/// compressed.
+///
+/// `DataStore` methods take a `ColumnId` rather than a key, and fail with `Error::UnknownColumn`
+/// for columns that are not registered with the store, see `ColumnRegistry::check`. Built-in
+/// columns convert from `DBColumn`, custom ones are returned by `ColumnRegistry::register`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ColumnId {
+    column: DBColumn,
+    codec: StoreCodec,
+    compression: Compression,
+}
+
+impl ColumnId {
+    /// A custom column, usable once registered with `ColumnRegistry::register_column`.
+    pub const fn custom(name: &'static str, codec: StoreCodec, compression: Compression) -> Self {
+        ColumnId {
+            column: DBColumn::Custom(name),
+            codec,
+            compression,
+        }
+    }
+
+    /// Returns the key of this column in the key-value data base.
+    pub fn name(&self) -> &'static str {
+        self.column.as_str()
+    }
+
+    pub fn column(&self) -> DBColumn {
+        self.column
+    }
+
+    /// Returns how the values of this column are serialized, see the `codec` module.
+    pub fn codec(&self) -> StoreCodec {
+        self.codec
+    }
+
+    /// Returns how the values of this column are compressed, see the `compression` module.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns `true` if values of this column are stored compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.compression != Compression::None
+    }
+}
+
+impl From<DBColumn> for ColumnId {
+    fn from(column: DBColumn) -> Self {
+        ColumnId {
+            column,
+            codec: column.codec(),
+            compression: column.compression(),
+        }
+    }
+}
+
+/// The set of columns a store is opened with: the built-in ones plus any registered by downstream
+/// crates.
+///
+/// Registering a column whose key is already taken fails, so two columns can never silently share
+/// the same key space. So does registering a key that starts with the key of another column, or
+/// that another key starts with, as keys of one column would then read as keys of the other.
+#[derive(Clone, Debug, Default)]
+pub struct ColumnRegistry {
+    custom: Vec<ColumnId>,
+}
+
+impl ColumnRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a registry from a list of custom column keys, failing on the first collision.
+    pub fn with_custom(names: &[&'static str]) -> Result<Self, Error> {
+        let mut registry = Self::new();
+        for name in names {
+            registry.register(name)?;
+        }
+        Ok(registry)
+    }
+
+    /// Registers a custom column keyed by `name`, holding uncompressed `StoreCodec::Raw` values.
+    pub fn register(&mut self, name: &'static str) -> Result<ColumnId, Error> {
+        self.register_with_codec(name, StoreCodec::Raw)
+    }
+
+    /// Registers a custom column keyed by `name`, holding uncompressed values serialized with
+    /// `codec`.
+    pub fn register_with_codec(
+        &mut self,
+        name: &'static str,
+        codec: StoreCodec,
+    ) -> Result<ColumnId, Error> {
+        self.register_column(ColumnId::custom(name, codec, Compression::None))
+    }
+
+    /// Registers the custom `column`, for instance one holding compressed values.
+    pub fn register_column(&mut self, column: ColumnId) -> Result<ColumnId, Error> {
+        let name = column.name();
+        if self
+            .all()
+            .any(|other| other.name().starts_with(name) || name.starts_with(other.name()))
+        {
+            return Err(Error::ColumnCollision {
+                name: name.to_string(),
+            });
+        }
+
+        self.custom.push(column);
+        Ok(column)
+    }
+
+    /// Returns the built-in or custom column keyed by `name`.
+    pub fn id(&self, name: &str) -> Result<ColumnId, Error> {
+        self.all()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| Error::UnknownColumn {
+                name: name.to_string(),
+            })
+    }
+
+    /// Checks that `column` is a built-in column or was registered with this registry, with the
+    /// same codec and compression.
+    pub fn check(&self, column: ColumnId) -> Result<(), Error> {
+        match column.column() {
+            DBColumn::Custom(name) if !self.custom.contains(&column) => Err(Error::UnknownColumn {
+                name: name.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns all columns known to this registry, built-in ones first.
+    pub fn all(&self) -> impl Iterator<Item = ColumnId> + '_ {
+        DBColumn::all()
+            .iter()
+            .map(|column| ColumnId::from(*column))
+            .chain(self.custom.iter().cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_round_trip() {
+        for column in DBColumn::all() {
+            assert_eq!(DBColumn::from_str(column.as_str()), Some(*column));
+        }
+        assert_eq!(DBColumn::from_str("nope"), None);
+    }
+
+    #[test]
+    fn test_builtin_keys_unique() {
+        let all = DBColumn::all();
+        for (i, a) in all.iter().enumerate() {
+            for b in &all[i + 1..] {
+                assert_ne!(a.as_str(), b.as_str());
+            }
+        }
+    }
+
+    #[test]
+    fn test_register_custom() {
+        let mut registry = ColumnRegistry::new();
+        let column = registry.register("ext").unwrap();
+
+        assert_eq!(column.column(), DBColumn::Custom("ext"));
+        assert_eq!(column.codec(), StoreCodec::Raw);
+        assert_eq!(registry.id("ext"), Ok(column));
+        assert_eq!(DBColumn::from_str("ext"), None);
+        assert_eq!(registry.all().count(), DBColumn::all().len() + 1);
+    }
+
+    #[test]
+    fn test_unknown_column() {
+        let mut registry = ColumnRegistry::new();
+        registry.register("ext").unwrap();
+
+        assert_eq!(
+            registry.id("blk"),
+            Ok(ColumnId::from(DBColumn::BeaconBlock))
+        );
+        assert_eq!(
+            registry.id("blkk"),
+            Err(Error::UnknownColumn {
+                name: "blkk".to_string()
+            })
+        );
+
+        assert!(registry.check(DBColumn::Deals.into()).is_ok());
+        assert!(registry
+            .check(ColumnId::custom("ext", StoreCodec::Raw, Compression::None))
+            .is_ok());
+        assert!(registry
+            .check(ColumnId::custom("ext", StoreCodec::Ssz, Compression::None))
+            .is_err());
+        assert!(registry
+            .check(ColumnId::custom(
+                "ext",
+                StoreCodec::Raw,
+                Compression::Snappy
+            ))
+            .is_err());
+        assert!(registry
+            .check(ColumnId::custom(
+                "other",
+                StoreCodec::Raw,
+                Compression::None
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_register_collision() {
+        let mut registry = ColumnRegistry::new();
+        registry.register("ext").unwrap();
+
+        assert!(registry.register("ext").is_err());
+        assert!(registry.register(DBColumn::Wallet.as_str()).is_err());
+        assert!(ColumnRegistry::with_custom(&["a", "b", "a"]).is_err());
+
+        // Keys of one column must not read as keys of another.
+        assert!(registry.register("bl").is_err());
+        assert!(registry.register("blkx").is_err());
+        assert!(registry.register("ex").is_err());
+        assert!(registry.register("extra").is_err());
+        assert!(registry.register("").is_err());
+        registry.register("bx").unwrap();
+    }
+}
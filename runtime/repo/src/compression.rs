@@ -0,0 +1,232 @@
+//! Compression of column values.
+//!
+//! Blocks and states make up most of the repo and compress well, so each column records the
+//! `Compression` its values are written with, see `ColumnId::compression`: blocks are snappy
+//! compressed, and states, which are larger and compress better, use zstd. Custom columns choose
+//! their own when registered.
+//!
+//! A compressed value starts with a header byte naming the algorithm, followed by a snappy frame
+//! stream or a zstd frame, so a column can change algorithm without rewriting its values. Values
+//! written before the header byte was introduced are read too: a bare snappy frame stream starts
+//! with the snappy stream identifier, and values written before compression was introduced match
+//! neither format and are read as they are.
+use super::*;
+use std::io::{Read, Write};
+
+/// The chunk every snappy frame stream starts with.
+const STREAM_IDENTIFIER: &[u8] = b"\xff\x06\x00\x00sNaPpY";
+
+/// The magic number every zstd frame starts with.
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
+/// Header byte of snappy compressed values.
+const SNAPPY_HEADER: u8 = 1;
+
+/// Header byte of zstd compressed values.
+const ZSTD_HEADER: u8 = 2;
+
+/// How the values of a column are compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Compression {
+    None,
+    /// The snappy frame format: fast, and roughly halves the size of blocks.
+    Snappy,
+    /// zstd at `level`, from 1 to 22: slower than snappy as the level grows, and smaller.
+    Zstd {
+        level: i32,
+    },
+}
+
+/// The format of a stored value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Raw,
+    /// A snappy frame stream without header byte.
+    LegacySnappy,
+    Snappy,
+    Zstd,
+}
+
+fn format(bytes: &[u8]) -> Format {
+    match bytes.split_first() {
+        Some((&SNAPPY_HEADER, frame)) if frame.starts_with(STREAM_IDENTIFIER) => Format::Snappy,
+        Some((&ZSTD_HEADER, frame)) if frame.starts_with(ZSTD_MAGIC) => Format::Zstd,
+        _ if bytes.starts_with(STREAM_IDENTIFIER) => Format::LegacySnappy,
+        _ => Format::Raw,
+    }
+}
+
+/// Compresses `bytes` into a snappy frame stream.
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut writer = snap::Writer::new(Vec::with_capacity(bytes.len() / 2));
+    writer
+        .write_all(bytes)
+        .expect("writing to a Vec never fails");
+    writer
+        .into_inner()
+        .unwrap_or_else(|_| unreachable!("flushing to a Vec never fails"))
+}
+
+/// Decompresses a snappy frame stream.
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut decompressed = Vec::with_capacity(bytes.len() * 2);
+    snap::Reader::new(bytes)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| Error::DecodeError {
+            message: format!("invalid snappy frame: {}", e),
+        })?;
+    Ok(decompressed)
+}
+
+/// Compresses `bytes` with `compression`, behind the header byte of the algorithm.
+pub fn encode(compression: Compression, bytes: &[u8]) -> Vec<u8> {
+    match compression {
+        Compression::None => bytes.to_vec(),
+        Compression::Snappy => [&[SNAPPY_HEADER][..], &compress(bytes)].concat(),
+        Compression::Zstd { level } => {
+            let mut encoded = vec![ZSTD_HEADER];
+            zstd::stream::copy_encode(bytes, &mut encoded, level)
+                .expect("writing to a Vec never fails");
+            encoded
+        }
+    }
+}
+
+/// Decompresses a value written by `encode` with any algorithm, or by a previous version.
+pub fn decode(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    match format(bytes) {
+        Format::Raw => Ok(bytes.to_vec()),
+        Format::LegacySnappy => decompress(bytes),
+        Format::Snappy => decompress(&bytes[1..]),
+        Format::Zstd => zstd::stream::decode_all(&bytes[1..]).map_err(|e| Error::DecodeError {
+            message: format!("invalid zstd frame: {}", e),
+        }),
+    }
+}
+
+/// Returns `true` if `bytes` is a compressed value, with or without header byte.
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    format(bytes) != Format::Raw
+}
+
+/// Compresses `bytes` with snappy unless they are compressed already.
+///
+/// Suitable as `migration::Reencode::reencode`, with `is_compressed` as
+/// `migration::Reencode::is_reencoded`, to compress the values of a column written before it was
+/// compressed.
+pub fn recompress(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    if is_compressed(bytes) {
+        Ok(bytes.to_vec())
+    } else {
+        Ok(encode(Compression::Snappy, bytes))
+    }
+}
+
+/// Encodes `value` for storage in `column`.
+pub(crate) fn encode_value(column: ColumnId, value: Vec<u8>) -> Vec<u8> {
+    match column.compression() {
+        Compression::None => value,
+        compression => encode(compression, &value),
+    }
+}
+
+/// Decodes `value` read from `column`.
+///
+/// Values of columns that are not compressed are returned as they are, even if they look
+/// compressed: they may come from outside.
+pub(crate) fn decode_value(column: ColumnId, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if column.is_compressed() && is_compressed(&value) {
+        decode(&value)
+    } else {
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let value: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+
+        let compressed = compress(&value);
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < value.len() / 2);
+        assert_eq!(decompress(&compressed), Ok(value.clone()));
+
+        for compression in &[
+            Compression::None,
+            Compression::Snappy,
+            Compression::Zstd { level: 1 },
+            Compression::Zstd { level: 19 },
+        ] {
+            let encoded = encode(*compression, &value);
+            assert_eq!(is_compressed(&encoded), *compression != Compression::None);
+            assert_eq!(decode(&encoded), Ok(value.clone()));
+        }
+        assert!(encode(Compression::Zstd { level: 19 }, &value).len() < compressed.len());
+    }
+
+    #[test]
+    fn column_values() {
+        let value = b"block".to_vec();
+
+        let block = encode_value(DBColumn::BeaconBlock.into(), value.clone());
+        assert_eq!(block[0], SNAPPY_HEADER);
+        assert_eq!(
+            decode_value(DBColumn::BeaconBlock.into(), block),
+            Ok(value.clone())
+        );
+
+        let state = encode_value(DBColumn::BeaconState.into(), value.clone());
+        assert_eq!(state[0], ZSTD_HEADER);
+        assert_eq!(
+            decode_value(DBColumn::BeaconState.into(), state),
+            Ok(value.clone())
+        );
+
+        let deal = encode_value(DBColumn::Deals.into(), value.clone());
+        assert_eq!(deal, value);
+        assert_eq!(
+            decode_value(DBColumn::Deals.into(), deal),
+            Ok(value.clone())
+        );
+
+        // written before compression, and before the header byte.
+        assert_eq!(
+            decode_value(DBColumn::BeaconState.into(), value.clone()),
+            Ok(value.clone())
+        );
+        assert_eq!(
+            decode_value(DBColumn::BeaconState.into(), compress(&value)),
+            Ok(value.clone())
+        );
+
+        // a custom column registered as compressed.
+        let custom = ColumnId::custom("ext", StoreCodec::Raw, Compression::Zstd { level: 9 });
+        let compressed = encode_value(custom, value.clone());
+        assert!(is_compressed(&compressed));
+        assert_eq!(decode_value(custom, compressed), Ok(value));
+    }
+
+    #[test]
+    fn recompress_is_idempotent() {
+        let compressed = recompress(b"state").unwrap();
+
+        assert_eq!(recompress(&compressed), Ok(compressed.clone()));
+        assert_eq!(decode(&compressed), Ok(b"state".to_vec()));
+        assert_eq!(recompress(&compress(b"state")), Ok(compress(b"state")));
+    }
+
+    #[test]
+    fn decompress_invalid() {
+        let mut compressed = compress(b"state");
+        compressed.truncate(compressed.len() - 1);
+        assert!(decompress(&compressed).is_err());
+
+        let mut compressed = encode(Compression::Zstd { level: 3 }, b"state");
+        compressed.truncate(compressed.len() - 1);
+        assert!(decode(&compressed).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! Storage deals.
+//!
+//! A `DealStore` keeps `Deal` records in the deals column, CBOR encoded and keyed by their
+//! big-endian id, so deals are listed in the order they were proposed. A deal starts out
+//! `DealState::Proposed` and moves through the states allowed by `DealState::can_become`, every
+//! change being recorded with the time it happened.
+//!
+//! Deals are found by client, by provider and by state through index entries in the deal index
+//! column, written in the same batch as the deal itself so the two never disagree.
+use super::*;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Identifies a deal, ids are given out in increasing order.
+pub type DealId = u64;
+
+const CLIENT_INDEX: u8 = b'c';
+const PROVIDER_INDEX: u8 = b'p';
+const STATE_INDEX: u8 = b's';
+
+/// Where a deal is in its lifetime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DealState {
+    /// The client proposed the deal, the provider did not answer yet.
+    Proposed,
+    Accepted,
+    Rejected,
+    /// The provider received the data.
+    Staged,
+    /// The data is being sealed into a sector.
+    Sealing,
+    /// The data is stored and proven until the end of the deal.
+    Active,
+    Expired,
+    Failed,
+}
+
+/// Every state, in the order of their index key.
+const DEAL_STATES: [DealState; 8] = [
+    DealState::Proposed,
+    DealState::Accepted,
+    DealState::Rejected,
+    DealState::Staged,
+    DealState::Sealing,
+    DealState::Active,
+    DealState::Expired,
+    DealState::Failed,
+];
+
+impl DealState {
+    /// Returns all states.
+    pub fn all() -> &'static [DealState] {
+        &DEAL_STATES
+    }
+
+    /// Returns `true` if a deal in this state can move to `next`.
+    ///
+    /// Any deal that is not over can fail.
+    pub fn can_become(&self, next: DealState) -> bool {
+        match (self, next) {
+            (DealState::Proposed, DealState::Accepted)
+            | (DealState::Proposed, DealState::Rejected)
+            | (DealState::Accepted, DealState::Staged)
+            | (DealState::Staged, DealState::Sealing)
+            | (DealState::Sealing, DealState::Active)
+            | (DealState::Active, DealState::Expired) => true,
+            (state, DealState::Failed) => !state.is_final(),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if a deal in this state cannot change anymore.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            DealState::Rejected | DealState::Expired | DealState::Failed
+        )
+    }
+
+    fn index_byte(self) -> u8 {
+        DEAL_STATES
+            .iter()
+            .position(|state| *state == self)
+            .expect("every state is in DEAL_STATES") as u8
+    }
+}
+
+/// The terms of a deal, as proposed by the client.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DealProposal {
+    /// CID of the data stored, as text.
+    pub piece_cid: String,
+    pub piece_size: u64,
+    /// Address of the client paying for the deal.
+    pub client: String,
+    /// Address of the provider storing the data.
+    pub provider: String,
+    pub start_epoch: u64,
+    pub end_epoch: u64,
+    pub price_per_epoch: u64,
+    pub provider_collateral: u64,
+    pub client_collateral: u64,
+}
+
+/// A change of state of a deal.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DealUpdate {
+    pub state: DealState,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Why the state changed, for instance the reason a deal failed.
+    pub message: Option<String>,
+}
+
+/// A deal and its history.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Deal {
+    pub id: DealId,
+    pub proposal: DealProposal,
+    pub state: DealState,
+    /// Every state the deal was in, oldest first, starting with `DealState::Proposed`.
+    pub updates: Vec<DealUpdate>,
+}
+
+/// The deals of a node, kept in the deals column of `store`.
+pub struct DealStore<S> {
+    store: S,
+    /// The id of the next deal, held for the whole of a write so that deals are never updated
+    /// concurrently.
+    next_id: Mutex<DealId>,
+}
+
+impl<S: DataStore> DealStore<S> {
+    /// Opens the deals of `store`.
+    pub fn new(store: S) -> Result<Self, Error> {
+        let next_id = match store.iter_column(DBColumn::Deals.into())?.last() {
+            Some((key, _)) => deal_id(&key)? + 1,
+            None => 0,
+        };
+
+        Ok(DealStore {
+            store,
+            next_id: Mutex::new(next_id),
+        })
+    }
+
+    /// The wrapped store. Deals written through it are not indexed.
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    /// Records a new deal in the `Proposed` state.
+    pub fn propose(&self, proposal: DealProposal) -> Result<Deal, Error> {
+        let mut next_id = self.next_id.lock().expect("deal store lock poisoned");
+        let deal = Deal {
+            id: *next_id,
+            proposal,
+            state: DealState::Proposed,
+            updates: vec![DealUpdate {
+                state: DealState::Proposed,
+                timestamp: now(),
+                message: None,
+            }],
+        };
+
+        let mut ops = vec![put_deal_op(&deal)];
+        for key in index_keys(&deal) {
+            ops.push(StoreOp::Put {
+                column: DBColumn::DealIndex.into(),
+                key,
+                value: vec![],
+            });
+        }
+        self.store.do_atomically(ops)?;
+
+        *next_id += 1;
+        Ok(deal)
+    }
+
+    /// Returns the deal `id`, if any.
+    pub fn get(&self, id: DealId) -> Result<Option<Deal>, Error> {
+        let column = DBColumn::Deals.into();
+        match self.store.get_bytes(column, &id.to_be_bytes())? {
+            Some(bytes) => decode_deal(bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Moves the deal `id` to `state`, recording why with `message`.
+    ///
+    /// Fails with `Error::UnknownDeal` if there is no such deal, and with
+    /// `Error::InvalidDealTransition` if its current state cannot become `state`.
+    pub fn update(
+        &self,
+        id: DealId,
+        state: DealState,
+        message: Option<String>,
+    ) -> Result<Deal, Error> {
+        let _lock = self.next_id.lock().expect("deal store lock poisoned");
+        let mut deal = self.get(id)?.ok_or(Error::UnknownDeal { id })?;
+        if !deal.state.can_become(state) {
+            return Err(Error::InvalidDealTransition {
+                id,
+                from: deal.state,
+                to: state,
+            });
+        }
+
+        let old_state = state_key(deal.state, id);
+        deal.state = state;
+        deal.updates.push(DealUpdate {
+            state,
+            timestamp: now(),
+            message,
+        });
+        self.store.do_atomically(vec![
+            put_deal_op(&deal),
+            StoreOp::Delete {
+                column: DBColumn::DealIndex.into(),
+                key: old_state,
+            },
+            StoreOp::Put {
+                column: DBColumn::DealIndex.into(),
+                key: state_key(state, id),
+                value: vec![],
+            },
+        ])?;
+        Ok(deal)
+    }
+
+    /// Returns every deal, oldest first.
+    pub fn list(&self) -> Result<Vec<Deal>, Error> {
+        self.store
+            .iter_column(DBColumn::Deals.into())?
+            .map(|(_, value)| decode_deal(value))
+            .collect()
+    }
+
+    /// Returns the deals of `client`, oldest first.
+    pub fn by_client(&self, client: &str) -> Result<Vec<Deal>, Error> {
+        self.indexed(&address_prefix(CLIENT_INDEX, client))
+    }
+
+    /// Returns the deals stored by `provider`, oldest first.
+    pub fn by_provider(&self, provider: &str) -> Result<Vec<Deal>, Error> {
+        self.indexed(&address_prefix(PROVIDER_INDEX, provider))
+    }
+
+    /// Returns the deals currently in `state`, oldest first.
+    pub fn by_state(&self, state: DealState) -> Result<Vec<Deal>, Error> {
+        self.indexed(&[STATE_INDEX, state.index_byte()])
+    }
+
+    /// Returns the deals of the index entries starting with `prefix`.
+    fn indexed(&self, prefix: &[u8]) -> Result<Vec<Deal>, Error> {
+        let ids: Vec<DealId> = self
+            .store
+            .iter_prefix(DBColumn::DealIndex.into(), prefix)?
+            .map(|(key, _)| deal_id(&key[prefix.len()..]))
+            .collect::<Result<_, _>>()?;
+
+        let mut deals = Vec::with_capacity(ids.len());
+        for id in ids {
+            // the index is written with the deal, a missing deal was written around the store.
+            if let Some(deal) = self.get(id)? {
+                deals.push(deal);
+            }
+        }
+        Ok(deals)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+fn put_deal_op(deal: &Deal) -> StoreOp {
+    let column = DBColumn::Deals.into();
+    StoreOp::Put {
+        column,
+        key: deal.id.to_be_bytes().to_vec(),
+        value: compression::encode_value(column, codec::cbor_encode(deal)),
+    }
+}
+
+fn decode_deal(bytes: Vec<u8>) -> Result<Deal, Error> {
+    let bytes = compression::decode_value(DBColumn::Deals.into(), bytes)?;
+    codec::cbor_decode(&bytes)
+}
+
+/// Reads a deal id from the end of a key.
+fn deal_id(key: &[u8]) -> Result<DealId, Error> {
+    if key.len() != 8 {
+        return Err(Error::DecodeError {
+            message: format!("invalid deal key {:?}", key),
+        });
+    }
+    let mut id = [0; 8];
+    id.copy_from_slice(key);
+    Ok(DealId::from_be_bytes(id))
+}
+
+/// `index` followed by the length-prefixed `address`, so that no address is a prefix of another.
+fn address_prefix(index: u8, address: &str) -> Vec<u8> {
+    let mut prefix = vec![index];
+    prefix.extend_from_slice(&(address.len() as u32).to_be_bytes());
+    prefix.extend_from_slice(address.as_bytes());
+    prefix
+}
+
+fn state_key(state: DealState, id: DealId) -> Vec<u8> {
+    [&[STATE_INDEX, state.index_byte()][..], &id.to_be_bytes()].concat()
+}
+
+/// The index entries of `deal`.
+fn index_keys(deal: &Deal) -> Vec<Vec<u8>> {
+    let id = deal.id.to_be_bytes();
+    vec![
+        [
+            &address_prefix(CLIENT_INDEX, &deal.proposal.client)[..],
+            &id,
+        ]
+        .concat(),
+        [
+            &address_prefix(PROVIDER_INDEX, &deal.proposal.provider)[..],
+            &id,
+        ]
+        .concat(),
+        state_key(deal.state, deal.id),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proposal(client: &str, provider: &str) -> DealProposal {
+        DealProposal {
+            piece_cid: "bafk2bzacea".to_string(),
+            piece_size: 2048,
+            client: client.to_string(),
+            provider: provider.to_string(),
+            start_epoch: 100,
+            end_epoch: 200,
+            price_per_epoch: 3,
+            provider_collateral: 10,
+            client_collateral: 0,
+        }
+    }
+
+    fn ids(deals: Vec<Deal>) -> Vec<DealId> {
+        deals.into_iter().map(|deal| deal.id).collect()
+    }
+
+    #[test]
+    fn propose_and_update() {
+        let deals = DealStore::new(MemoryStore::open()).unwrap();
+        let deal = deals.propose(proposal("alice", "p1")).unwrap();
+        assert_eq!(deal.id, 0);
+        assert_eq!(deal.state, DealState::Proposed);
+        assert_eq!(deals.get(0), Ok(Some(deal.clone())));
+
+        deals.update(0, DealState::Accepted, None).unwrap();
+        let failed = deals
+            .update(0, DealState::Failed, Some("no data".to_string()))
+            .unwrap();
+        assert_eq!(deals.get(0), Ok(Some(failed.clone())));
+        let states: Vec<_> = failed.updates.iter().map(|update| update.state).collect();
+        assert_eq!(
+            states,
+            vec![DealState::Proposed, DealState::Accepted, DealState::Failed]
+        );
+        assert!(failed.updates[1].timestamp >= failed.updates[0].timestamp);
+        assert_eq!(failed.updates[2].message, Some("no data".to_string()));
+
+        assert_eq!(
+            deals.update(0, DealState::Active, None),
+            Err(Error::InvalidDealTransition {
+                id: 0,
+                from: DealState::Failed,
+                to: DealState::Active
+            })
+        );
+        assert_eq!(
+            deals.update(1, DealState::Accepted, None),
+            Err(Error::UnknownDeal { id: 1 })
+        );
+    }
+
+    #[test]
+    fn indexes() {
+        let deals = DealStore::new(MemoryStore::open()).unwrap();
+        deals.propose(proposal("alice", "p1")).unwrap();
+        deals.propose(proposal("bob", "p1")).unwrap();
+        deals.propose(proposal("alice", "p2")).unwrap();
+        // a client whose address starts with another's.
+        deals.propose(proposal("alice2", "p2")).unwrap();
+        deals.update(1, DealState::Accepted, None).unwrap();
+        deals.update(2, DealState::Rejected, None).unwrap();
+
+        assert_eq!(ids(deals.by_client("alice").unwrap()), vec![0, 2]);
+        assert_eq!(ids(deals.by_client("carol").unwrap()), Vec::<DealId>::new());
+        assert_eq!(ids(deals.by_provider("p1").unwrap()), vec![0, 1]);
+        assert_eq!(
+            ids(deals.by_state(DealState::Proposed).unwrap()),
+            vec![0, 3]
+        );
+        assert_eq!(ids(deals.by_state(DealState::Accepted).unwrap()), vec![1]);
+        assert_eq!(ids(deals.by_state(DealState::Rejected).unwrap()), vec![2]);
+        assert_eq!(ids(deals.list().unwrap()), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn ids_continue_after_reopening() {
+        let store = MemoryStore::open();
+        let deals = DealStore::new(store).unwrap();
+        deals.propose(proposal("alice", "p1")).unwrap();
+        deals.propose(proposal("alice", "p1")).unwrap();
+
+        let deals = DealStore::new(deals.store).unwrap();
+        assert_eq!(deals.propose(proposal("alice", "p1")).unwrap().id, 2);
+    }
+
+    #[test]
+    fn state_machine() {
+        assert!(DealState::Proposed.can_become(DealState::Accepted));
+        assert!(DealState::Sealing.can_become(DealState::Active));
+        assert!(DealState::Active.can_become(DealState::Failed));
+        assert!(!DealState::Proposed.can_become(DealState::Active));
+        assert!(!DealState::Accepted.can_become(DealState::Proposed));
+        for state in DealState::all() {
+            assert_eq!(
+                state.is_final(),
+                DealState::all().iter().all(|next| !state.can_become(*next))
+            );
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::deals::{DealId, DealState};
+use filesys_errors::{CoreError, ErrorCode};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The underlying key-value database failed.
+    DBError { message: String },
+    /// Bytes read from the database could not be decoded into the requested item.
+    DecodeError { message: String },
+    /// Two columns were registered with the same key, or one key starts with the other.
+    ColumnCollision { name: String },
+    /// A column was used, or looked up by key, without being registered with the store.
+    UnknownColumn { name: String },
+    /// A write was rejected because it would grow the repo past its `StorageMax`.
+    StorageFull { usage: u64, max: u64 },
+    /// Another process holds the lock at `path`.
+    RepoLocked { path: String },
+    /// Reading or writing a repo file failed.
+    IoError { message: String },
+    /// The repo was written by a newer binary.
+    UnsupportedVersion { found: u32, supported: u32 },
+    /// No migration upgrades the repo to `version`.
+    MissingMigration { version: u32 },
+    /// A snapshot archive is malformed or does not match its manifest.
+    InvalidSnapshot { message: String },
+    /// No deal has the id `id`.
+    UnknownDeal { id: DealId },
+    /// The deal `id` cannot go from state `from` to state `to`.
+    InvalidDealTransition {
+        id: DealId,
+        from: DealState,
+        to: DealState,
+    },
+    /// No address of the wallet is `address`.
+    UnknownAddress { address: String },
+    /// The keystore failed to read or decrypt a key.
+    KeystoreError { message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::DBError { message } => write!(f, "Database error: {}", message),
+            Error::DecodeError { message } => write!(f, "Decode error: {}", message),
+            Error::ColumnCollision { name } => write!(f, "Column key overlaps another column: {}", name),
+            Error::UnknownColumn { name } => write!(f, "Column not registered: {}", name),
+            Error::StorageFull { usage, max } => {
+                write!(f, "Storage full: {} bytes used out of {}", usage, max)
+            }
+            Error::RepoLocked { path } => write!(f, "Repo is locked by {}", path),
+            Error::IoError { message } => write!(f, "IO error: {}", message),
+            Error::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Repo version {} is newer than the supported version {}",
+                found, supported
+            ),
+            Error::MissingMigration { version } => {
+                write!(f, "No migration to repo version {}", version)
+            }
+            Error::InvalidSnapshot { message } => write!(f, "Invalid snapshot: {}", message),
+            Error::UnknownDeal { id } => write!(f, "Unknown deal {}", id),
+            Error::InvalidDealTransition { id, from, to } => {
+                write!(f, "Deal {} cannot go from {:?} to {:?}", id, from, to)
+            }
+            Error::UnknownAddress { address } => write!(f, "Unknown address {}", address),
+            Error::KeystoreError { message } => write!(f, "Keystore error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl CoreError for Error {
+    fn code(&self) -> ErrorCode {
+        match self {
+            Error::DBError { .. } => ErrorCode::Io,
+            Error::DecodeError { .. } => ErrorCode::Decode,
+            Error::ColumnCollision { .. } => ErrorCode::Conflict,
+            Error::UnknownColumn { .. } => ErrorCode::NotFound,
+            Error::StorageFull { .. } => ErrorCode::Unavailable,
+            Error::RepoLocked { .. } => ErrorCode::Unavailable,
+            Error::IoError { .. } => ErrorCode::Io,
+            Error::UnsupportedVersion { .. } => ErrorCode::Unsupported,
+            Error::MissingMigration { .. } => ErrorCode::Internal,
+            Error::InvalidSnapshot { .. } => ErrorCode::Decode,
+            Error::UnknownDeal { .. } => ErrorCode::NotFound,
+            Error::InvalidDealTransition { .. } => ErrorCode::Conflict,
+            Error::UnknownAddress { .. } => ErrorCode::NotFound,
+            Error::KeystoreError { .. } => ErrorCode::InvalidInput,
+        }
+    }
+}
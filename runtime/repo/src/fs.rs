@@ -1,11 +1,160 @@
-use crate::block::Cid;
-use StoreItem;
+use super::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+/// A repo kept in a directory of the local file system.
+///
+/// The repo lock is taken when the repo is opened and held until the last clone is dropped, so a
+/// second process opening the same directory fails with `Error::RepoLocked`.
 #[derive(Clone, Debug)]
 pub struct FsRepo {
     root: PathBuf,
-    cids: Arc<Mutex<HashSet<Cid>>>,
-    keystore:StoreItem,
-    chain:StoreItem,
-    wallet:StoreItem,
+    columns: ColumnRegistry,
+    lock: Arc<RepoLock>,
+}
+
+impl FsRepo {
+    /// Opens the repo at `root`, creating the directory if it does not exist, with the `custom`
+    /// columns of downstream crates registered next to the built-in ones.
+    pub fn open(root: &Path, custom: &[&'static str]) -> Result<Self, Error> {
+        Self::open_with(root, custom, false)
+    }
+
+    /// Like `open`, but breaks the lock of another holder, see `RepoLock::force_acquire`.
+    pub fn force_open(root: &Path, custom: &[&'static str]) -> Result<Self, Error> {
+        Self::open_with(root, custom, true)
+    }
+
+    fn open_with(root: &Path, custom: &[&'static str], force: bool) -> Result<Self, Error> {
+        fs::create_dir_all(root)?;
+        let lock = Self::open_lock(root, force)?;
+        let columns = Self::open_columns(custom)?;
+
+        Ok(FsRepo {
+            root: root.to_path_buf(),
+            columns,
+            lock: Arc::new(lock),
+        })
+    }
+
+    /// Returns the columns this repo was opened with.
+    pub fn columns(&self) -> &ColumnRegistry {
+        &self.columns
+    }
+
+    /// Returns the lock held on the repo directory.
+    pub fn lock(&self) -> &RepoLock {
+        &self.lock
+    }
+
+    /// Registers the `custom` columns of downstream crates against the built-in ones.
+    ///
+    /// Called when the repo is opened so that a column key collision is reported before anything
+    /// is written.
+    fn open_columns(custom: &[&'static str]) -> Result<ColumnRegistry, Error> {
+        ColumnRegistry::with_custom(custom)
+    }
+
+    /// Takes the repo lock, breaking a stale one if `force` is set.
+    ///
+    /// Called when the repo is opened, before the datastores, so that two nodes never share them.
+    fn open_lock(root: &Path, force: bool) -> Result<RepoLock, Error> {
+        if force {
+            RepoLock::force_acquire(root)
+        } else {
+            RepoLock::acquire(root)
+        }
+    }
+}
+
+impl Repo for FsRepo {
+    fn ChainDatastore() -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn Path(&self) -> Result<PathBuf, Error> {
+        Ok(self.root.clone())
+    }
+
+    /// Opens the datastore with the columns the repo was opened with.
+    fn open_datastore(&self, prefix: &str) -> Result<DiskStore, Error> {
+        Ok(DiskStore::open(&self.root.join(prefix))?.with_columns(self.columns.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn second_open_fails() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("repo");
+
+        let repo = FsRepo::open(&root, &[]).unwrap();
+        assert_eq!(repo.lock().path(), root.join(LOCK_FILE).as_path());
+        match FsRepo::open(&root, &[]) {
+            Err(Error::RepoLocked { .. }) => {}
+            other => panic!("expected the repo to be locked, got {:?}", other),
+        }
+
+        // Clones share the lock, which is released with the last of them.
+        let clone = repo.clone();
+        drop(repo);
+        assert!(FsRepo::open(&root, &[]).is_err());
+        drop(clone);
+        FsRepo::open(&root, &[]).unwrap();
+    }
+
+    #[test]
+    fn force_open() {
+        let dir = tempdir().unwrap();
+
+        let _stale = FsRepo::open(dir.path(), &[]).unwrap();
+        let repo = FsRepo::force_open(dir.path(), &["ext"]).unwrap();
+        assert_eq!(repo.Path().unwrap(), dir.path());
+
+        // Datastores are opened with the custom columns of the repo.
+        let ext = repo.columns().id("ext").unwrap();
+        let store = repo.open_datastore(CHAIN_DATASTORE_FILENAME_PREFIX).unwrap();
+        store.put_bytes(ext, b"key", b"value").unwrap();
+        assert_eq!(store.get_bytes(ext, b"key").unwrap(), Some(b"value".to_vec()));
+    }
+
+    #[test]
+    fn blocks_datastore_quota() {
+        let dir = tempdir().unwrap();
+        let repo = FsRepo::open(dir.path(), &[]).unwrap();
+        let quota = QuotaConfig {
+            storage_max: 8,
+            storage_gc_watermark: 50,
+        };
+
+        let store = repo.BlocksDatastore(quota).unwrap();
+        let blk = DBColumn::BeaconBlock.into();
+        store.put_bytes(blk, b"a", b"123").unwrap();
+        assert_eq!(
+            store.put_bytes(blk, b"b", b"123456"),
+            Err(Error::StorageFull { usage: 4, max: 8 })
+        );
+        drop(store);
+
+        // Usage is saved with the datastore and read back when it is reopened.
+        assert_eq!(repo.BlocksDatastore(quota).unwrap().usage(), 4);
+        let blocks_path = dir.path().join(BLOCKSTORE_DATASTORE_FILENAME_PREFIX);
+        assert!(blocks_path.join("quota.usage").exists());
+    }
+
+    #[test]
+    fn column_collision_releases_the_lock() {
+        let dir = tempdir().unwrap();
+
+        match FsRepo::open(dir.path(), &["ext", "blk1"]) {
+            Err(Error::ColumnCollision { .. }) => {}
+            other => panic!("expected a column collision, got {:?}", other),
+        }
+        FsRepo::open(dir.path(), &[]).unwrap();
+    }
 }
@@ -0,0 +1,244 @@
+//! Group commit of concurrent writes.
+//!
+//! During sync many threads write blocks at once, and syncing every write to disk on its own
+//! bounds the import rate by the number of fsyncs the disk can do. Writers instead join a group:
+//! the first one becomes its leader, waits up to `GroupCommitConfig::window` for others to join,
+//! then writes the whole group in one batch. Every writer returns once the batch holding its
+//! write is on disk, so a returned write is as durable as before.
+use super::*;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Trade-off between write latency and the number of disk syncs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupCommitConfig {
+    /// How long a group leader waits for more writes. Longer windows mean fewer, larger batches
+    /// and a higher latency for each write.
+    pub window: Duration,
+    /// A group is written as soon as it holds this many writes.
+    pub max_batch: usize,
+    /// Whether batches are synced to disk before writers return. Without it a crash may lose
+    /// writes that were reported as done.
+    pub sync: bool,
+}
+
+impl Default for GroupCommitConfig {
+    fn default() -> Self {
+        GroupCommitConfig {
+            window: Duration::from_millis(2),
+            max_batch: 1024,
+            sync: true,
+        }
+    }
+}
+
+struct GroupState<T> {
+    /// Writes of the open group.
+    pending: Vec<T>,
+    /// Id of the group new writes join.
+    open: u64,
+    /// Number of writers in the open group.
+    members: usize,
+    /// Whether a leader is gathering or writing a group.
+    leading: bool,
+    /// Every group up to this id is written.
+    written: u64,
+    /// Errors of failed groups, with the number of members that did not see it yet.
+    failed: HashMap<u64, (Error, usize)>,
+}
+
+/// Coalesces writes of type `T` submitted from many threads into batches.
+pub struct GroupCommit<T> {
+    config: GroupCommitConfig,
+    state: Mutex<GroupState<T>>,
+    changed: Condvar,
+}
+
+impl<T> GroupCommit<T> {
+    pub fn new(config: GroupCommitConfig) -> Self {
+        GroupCommit {
+            config,
+            state: Mutex::new(GroupState {
+                pending: Vec::new(),
+                open: 1,
+                members: 0,
+                leading: false,
+                written: 0,
+                failed: HashMap::new(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn config(&self) -> &GroupCommitConfig {
+        &self.config
+    }
+
+    /// Adds `item` to the open group and returns once the group is written.
+    ///
+    /// If this call ends up leading the group, `write` is called with all its items in submission
+    /// order. Otherwise `write` is dropped and the leader's result is returned.
+    pub fn submit<F>(&self, item: T, write: F) -> Result<(), Error>
+    where
+        F: FnOnce(Vec<T>) -> Result<(), Error>,
+    {
+        let mut state = self.state.lock().unwrap();
+        while state.pending.len() >= self.config.max_batch {
+            state = self.changed.wait(state).unwrap();
+        }
+        let group = state.open;
+        state.pending.push(item);
+        state.members += 1;
+        if state.pending.len() >= self.config.max_batch {
+            self.changed.notify_all();
+        }
+
+        // Wait for the group to be written, or to lead it if no one else does.
+        while state.leading || state.written >= group {
+            if state.written >= group {
+                return Self::group_result(&mut state, group);
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+        state.leading = true;
+
+        let deadline = Instant::now() + self.config.window;
+        while state.pending.len() < self.config.max_batch {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        let batch = mem::take(&mut state.pending);
+        let members = mem::take(&mut state.members);
+        state.open += 1;
+        // writers waiting for room can join the next group.
+        self.changed.notify_all();
+        drop(state);
+
+        let result = write(batch);
+
+        let mut state = self.state.lock().unwrap();
+        state.leading = false;
+        state.written = group;
+        if let Err(ref e) = result {
+            if members > 1 {
+                state.failed.insert(group, (e.clone(), members - 1));
+            }
+        }
+        self.changed.notify_all();
+        result
+    }
+
+    /// The outcome of the written `group` for one of its followers.
+    fn group_result(state: &mut GroupState<T>, group: u64) -> Result<(), Error> {
+        let (error, unseen) = match state.failed.get_mut(&group) {
+            Some(failure) => failure,
+            None => return Ok(()),
+        };
+        *unseen -= 1;
+        let error = error.clone();
+        if *unseen == 0 {
+            state.failed.remove(&group);
+        }
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn commit(window: Duration, max_batch: usize) -> Arc<GroupCommit<u32>> {
+        Arc::new(GroupCommit::new(GroupCommitConfig {
+            window,
+            max_batch,
+            sync: false,
+        }))
+    }
+
+    fn submit_all<F>(commit: &Arc<GroupCommit<u32>>, writers: u32, write: F) -> Vec<Result<(), Error>>
+    where
+        F: Fn(Vec<u32>) -> Result<(), Error> + Send + Sync + 'static,
+    {
+        let write = Arc::new(write);
+        let handles: Vec<_> = (0..writers)
+            .map(|i| {
+                let commit = commit.clone();
+                let write = write.clone();
+                thread::spawn(move || commit.submit(i, |batch| write(batch)))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    }
+
+    #[test]
+    fn coalesces_writes() {
+        let commit = commit(Duration::from_millis(200), 8);
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let written = batches.clone();
+
+        let results = submit_all(&commit, 8, move |batch| {
+            written.lock().unwrap().push(batch);
+            Ok(())
+        });
+
+        assert!(results.iter().all(|r| r.is_ok()));
+        let batches = batches.lock().unwrap();
+        let mut items: Vec<_> = batches.iter().flatten().cloned().collect();
+        items.sort();
+        assert_eq!(items, (0..8).collect::<Vec<_>>());
+        // writers spawned after the leader's window ends may form a second group.
+        assert!(batches.len() < 8);
+    }
+
+    #[test]
+    fn max_batch_splits_groups() {
+        let commit = commit(Duration::from_millis(50), 2);
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let written = batches.clone();
+
+        submit_all(&commit, 6, move |batch| {
+            written.lock().unwrap().push(batch.len());
+            Ok(())
+        });
+
+        let batches = batches.lock().unwrap();
+        assert!(batches.iter().all(|&len| len <= 2));
+        assert_eq!(batches.iter().sum::<usize>(), 6);
+    }
+
+    #[test]
+    fn failure_reaches_every_member() {
+        let commit = commit(Duration::from_millis(200), 4);
+
+        let results = submit_all(&commit, 4, |_| {
+            Err(Error::DBError {
+                message: "disk on fire".into(),
+            })
+        });
+
+        assert!(results.iter().all(|r| r.is_err()));
+        assert!(commit.state.lock().unwrap().failed.is_empty());
+    }
+
+    #[test]
+    fn single_writer() {
+        let commit = commit(Duration::from_millis(0), 16);
+        let mut batches = Vec::new();
+
+        commit.submit(1, |batch| {
+            batches.push(batch);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(batches, vec![vec![1]]);
+    }
+}
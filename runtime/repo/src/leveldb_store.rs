@@ -0,0 +1,583 @@
+use super::*;
+use crate::bloom::{BloomConfig, BloomFilter};
+use crate::group_commit::GroupCommit;
+use db_key::Key;
+use leveldb::database::batch::{Batch, Writebatch};
+use leveldb::database::kv::KV;
+use leveldb::database::Database;
+use leveldb::error::Error as LevelDBError;
+use leveldb::iterator::{Iterable, LevelDBIterator};
+use leveldb::options::{Options, ReadOptions, WriteOptions};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// A wrapped leveldb database.
+pub struct LevelDB {
+    db: Database<BytesKey>,
+    path: PathBuf,
+    group_commit: Option<GroupCommit<(BytesKey, Vec<u8>)>>,
+    columns: ColumnRegistry,
+    /// Bloom filters of the keys of some columns, by column key.
+    blooms: RwLock<HashMap<&'static str, BloomFilter>>,
+    /// The bloom filters saved by the last store, by column key, until `with_bloom_filter` takes
+    /// them.
+    saved_blooms: HashMap<String, Vec<u8>>,
+}
+
+impl LevelDB {
+    /// Open a database at `path`, creating a new database if one does not already exist.
+    ///
+    /// The database is opened with the built-in columns only, see `with_columns`. The saved bloom
+    /// filters are read and deleted, see `with_bloom_filter`.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut options = Options::new();
+
+        options.create_if_missing = true;
+
+        let db = Database::open(path, options)?;
+        let saved_blooms = take_saved_bloom_filters(path)?;
+
+        Ok(Self {
+            db,
+            path: path.to_path_buf(),
+            group_commit: None,
+            columns: ColumnRegistry::new(),
+            blooms: RwLock::new(HashMap::new()),
+            saved_blooms,
+        })
+    }
+
+    /// Opens the database with `columns` rather than the built-in columns only.
+    pub fn with_columns(mut self, columns: ColumnRegistry) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Keeps a bloom filter of the keys of `column`, so that `key_exists` answers for most absent
+    /// keys without reading the disk, see the `bloom` module. `column` must be registered already,
+    /// see `with_columns`.
+    ///
+    /// The filter is saved next to the database when the store is dropped and loaded back here.
+    /// `open` deletes every saved copy, so after a crash, or after the database was opened
+    /// without the filter, the filter is rebuilt from the keys of the column rather than trusted
+    /// while missing the last writes.
+    pub fn with_bloom_filter(
+        mut self,
+        column: ColumnId,
+        config: BloomConfig,
+    ) -> Result<Self, Error> {
+        self.columns.check(column)?;
+
+        let saved = self
+            .saved_blooms
+            .remove(column.name())
+            .and_then(|bytes| BloomFilter::from_bytes(&bytes).ok())
+            .filter(|filter| filter.is_sized_for(config));
+        let filter = match saved {
+            Some(filter) => filter,
+            None => {
+                let mut filter = BloomFilter::new(config);
+                for (key, _) in self.iter_column(column)? {
+                    filter.insert(&key);
+                }
+                filter
+            }
+        };
+
+        self.blooms
+            .write()
+            .expect("bloom filter lock poisoned")
+            .insert(column.name(), filter);
+        Ok(self)
+    }
+
+    fn bloom_path(&self, name: &str) -> PathBuf {
+        self.path.join(format!("{}.bloom", name))
+    }
+
+    /// Records `key` in the bloom filter of `col`, if any. Called before the key is written, so
+    /// that the filter never misses a key readers can see.
+    fn bloom_insert(&self, col: ColumnId, key: &[u8]) {
+        let mut blooms = self.blooms.write().expect("bloom filter lock poisoned");
+        if let Some(filter) = blooms.get_mut(col.name()) {
+            filter.insert(key);
+        }
+    }
+
+    /// Returns `false` if the bloom filter of `col` rules `key` out.
+    fn bloom_may_contain(&self, col: ColumnId, key: &[u8]) -> bool {
+        let blooms = self.blooms.read().expect("bloom filter lock poisoned");
+        match blooms.get(col.name()) {
+            Some(filter) => filter.may_contain(key),
+            None => true,
+        }
+    }
+
+    /// Writes the bloom filters next to the database, each to a temporary file renamed once
+    /// complete.
+    fn save_bloom_filters(&self) -> Result<(), Error> {
+        let blooms = self.blooms.read().expect("bloom filter lock poisoned");
+        for (name, filter) in blooms.iter() {
+            let path = self.bloom_path(name);
+            let tmp_path = path.with_extension("tmp");
+            fs::write(&tmp_path, filter.to_bytes())?;
+            fs::rename(&tmp_path, &path)?;
+        }
+        Ok(())
+    }
+
+    /// Like `open`, but concurrent `put_bytes` calls are written in shared batches, see the
+    /// `group_commit` module.
+    pub fn open_with_group_commit(path: &Path, config: GroupCommitConfig) -> Result<Self, Error> {
+        let mut store = Self::open(path)?;
+        store.group_commit = Some(GroupCommit::new(config));
+        Ok(store)
+    }
+
+    fn read_options(&self) -> ReadOptions<'_, BytesKey> {
+        ReadOptions::new()
+    }
+
+    fn write_options(&self) -> WriteOptions {
+        WriteOptions::new()
+    }
+
+    /// Walks the pairs of `col` backwards, from the last key not after `end`.
+    fn iter_back_from<'a>(&'a self, col: ColumnId, end: &[u8]) -> ColumnIter<'a> {
+        let end_key = Self::get_key_for_col(col, end);
+        let col_key = Self::get_key_for_col(col, &[]);
+        let col_len = col.name().len();
+
+        // Seeking past the last key of the database leaves the iterator invalid, so start from
+        // the last key when it is not after `end`.
+        let mut last = self.db.iter(self.read_options()).reverse().peekable();
+        let iter: Box<dyn Iterator<Item = (BytesKey, Vec<u8>)> + 'a> = match last.peek() {
+            Some((key, _)) if key.key > end_key.key => {
+                let iter = self.db.iter(self.read_options()).reverse();
+                iter.seek(&end_key);
+                Box::new(iter)
+            }
+            _ => Box::new(last),
+        };
+
+        Box::new(
+            iter.skip_while(move |(key, _)| key.key > end_key.key)
+                .take_while(move |(key, _)| key.key.starts_with(&col_key.key))
+                .map(move |(key, value)| (key.key[col_len..].to_vec(), value)),
+        )
+    }
+
+    fn get_key_for_col(col: ColumnId, key: &[u8]) -> BytesKey {
+        let mut col = col.name().as_bytes().to_vec();
+        col.append(&mut key.to_vec());
+        BytesKey { key: col }
+    }
+}
+
+/// Reads and deletes the bloom filters saved in `path`, by column key. They are only valid for
+/// the writes made before they were saved, so none may outlive the store opening the database.
+fn take_saved_bloom_filters(path: &Path) -> Result<HashMap<String, Vec<u8>>, Error> {
+    let mut saved = HashMap::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .map_or(true, |extension| extension != "bloom")
+        {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+            // An unreadable filter is rebuilt like a missing one.
+            if let Ok(bytes) = fs::read(&path) {
+                saved.insert(name.to_owned(), bytes);
+            }
+        }
+        fs::remove_file(&path)?;
+    }
+    Ok(saved)
+}
+
+/// Used for keying leveldb.
+pub struct BytesKey {
+    key: Vec<u8>,
+}
+
+impl Key for BytesKey {
+    fn from_u8(key: &[u8]) -> Self {
+        Self { key: key.to_vec() }
+    }
+
+    fn as_slice<T, F: Fn(&[u8]) -> T>(&self, f: F) -> T {
+        f(self.key.as_slice())
+    }
+}
+
+impl DataStore for LevelDB {
+    fn columns(&self) -> &ColumnRegistry {
+        &self.columns
+    }
+
+    /// Retrieve some bytes in `column` with `key`.
+    fn get_bytes(&self, col: ColumnId, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.columns.check(col)?;
+        let column_key = Self::get_key_for_col(col, key);
+
+        self.db
+            .get(self.read_options(), column_key)
+            .map_err(Into::into)
+    }
+
+    /// Store some `value` in `column`, indexed with `key`.
+    ///
+    /// With group commit enabled, returns once the batch holding the write is written.
+    fn put_bytes(&self, col: ColumnId, key: &[u8], val: &[u8]) -> Result<(), Error> {
+        self.columns.check(col)?;
+        self.bloom_insert(col, key);
+        let column_key = Self::get_key_for_col(col, key);
+
+        if let Some(group_commit) = &self.group_commit {
+            return group_commit.submit((column_key, val.to_vec()), |puts| {
+                let mut batch = Writebatch::new();
+                for (key, value) in puts {
+                    batch.put(key, &value);
+                }
+                let mut options = self.write_options();
+                options.sync = group_commit.config().sync;
+                self.db.write(options, &batch).map_err(Into::into)
+            });
+        }
+
+        self.db
+            .put(self.write_options(), column_key, val)
+            .map_err(Into::into)
+    }
+
+    /// Return `true` if `key` exists in `column`.
+    ///
+    /// Keys ruled out by the bloom filter of `column`, if any, are not looked up.
+    fn key_exists(&self, col: ColumnId, key: &[u8]) -> Result<bool, Error> {
+        self.columns.check(col)?;
+        if !self.bloom_may_contain(col, key) {
+            return Ok(false);
+        }
+        let column_key = Self::get_key_for_col(col, key);
+
+        self.db
+            .get(self.read_options(), column_key)
+            .map(|val| val.is_some())
+            .map_err(Into::into)
+    }
+
+    /// Removes `key` from `column`.
+    fn key_delete(&self, col: ColumnId, key: &[u8]) -> Result<(), Error> {
+        self.columns.check(col)?;
+        let column_key = Self::get_key_for_col(col, key);
+
+        self.db
+            .delete(self.write_options(), column_key)
+            .map_err(Into::into)
+    }
+
+    /// Applies all `ops` in a single leveldb write batch.
+    fn do_atomically(&self, ops: Vec<StoreOp>) -> Result<(), Error> {
+        let mut batch = Writebatch::new();
+
+        for op in ops {
+            match op {
+                StoreOp::Put { column, key, value } => {
+                    self.columns.check(column)?;
+                    self.bloom_insert(column, &key);
+                    batch.put(Self::get_key_for_col(column, &key), &value)
+                }
+                StoreOp::Delete { column, key } => {
+                    self.columns.check(column)?;
+                    batch.delete(Self::get_key_for_col(column, &key))
+                }
+            }
+        }
+
+        self.db
+            .write(self.write_options(), &batch)
+            .map_err(Into::into)
+    }
+
+    /// Seeks to the first key of `col` starting with `prefix` and stops at the first one that
+    /// does not.
+    fn iter_prefix<'a>(&'a self, col: ColumnId, prefix: &[u8]) -> Result<ColumnIter<'a>, Error> {
+        self.columns.check(col)?;
+        let start_key = Self::get_key_for_col(col, prefix);
+        let col_len = col.name().len();
+
+        let iter = self.db.iter(self.read_options());
+        iter.seek(&start_key);
+
+        Ok(Box::new(
+            iter.take_while(move |(key, _)| key.key.starts_with(&start_key.key))
+                .map(move |(key, value)| (key.key[col_len..].to_vec(), value)),
+        ))
+    }
+
+    /// Seeks to the start of the range and stops at its end. Descending queries seek to the end
+    /// of the range and walk backwards, or read the whole prefix if the range has no end.
+    fn query<'a>(&'a self, query: &Query) -> Result<QueryIter<'a>, Error> {
+        let col = query.column;
+        if query.order == Order::Descending {
+            return match query.end_key() {
+                Some(end) => {
+                    self.columns.check(col)?;
+                    Ok(query::execute_descending(
+                        self.iter_back_from(col, end),
+                        query,
+                    ))
+                }
+                None => Ok(query::execute(self.iter_prefix(col, &query.prefix)?, query)),
+            };
+        }
+
+        self.columns.check(col)?;
+        let start_key = Self::get_key_for_col(col, query.start_key());
+        let prefix_key = Self::get_key_for_col(col, &query.prefix);
+        let col_len = col.name().len();
+
+        let iter = self.db.iter(self.read_options());
+        iter.seek(&start_key);
+
+        let scan = iter
+            .take_while(move |(key, _)| key.key.starts_with(&prefix_key.key))
+            .map(move |(key, value)| (key.key[col_len..].to_vec(), value));
+        Ok(query::execute(Box::new(scan), query))
+    }
+}
+
+impl Drop for LevelDB {
+    fn drop(&mut self) {
+        // Failing to save only means the filters are rebuilt on the next open.
+        let _ = self.save_bloom_filters();
+    }
+}
+
+impl From<LevelDBError> for Error {
+    fn from(e: LevelDBError) -> Error {
+        Error::DBError {
+            message: format!("{:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn do_atomically() {
+        let dir = tempdir().unwrap();
+        let store = LevelDB::open(dir.path()).unwrap();
+        let blk = DBColumn::BeaconBlock.into();
+        let ste = DBColumn::BeaconState.into();
+
+        store.put_bytes(blk, b"stale", b"0").unwrap();
+        store
+            .do_atomically(vec![
+                StoreOp::Put {
+                    column: blk,
+                    key: b"block".to_vec(),
+                    value: b"1".to_vec(),
+                },
+                StoreOp::Put {
+                    column: ste,
+                    key: b"state".to_vec(),
+                    value: b"2".to_vec(),
+                },
+                StoreOp::Delete {
+                    column: blk,
+                    key: b"stale".to_vec(),
+                },
+            ])
+            .unwrap();
+
+        assert_eq!(store.get_bytes(blk, b"block").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get_bytes(ste, b"state").unwrap(), Some(b"2".to_vec()));
+        assert!(!store.key_exists(blk, b"stale").unwrap());
+    }
+
+    #[test]
+    fn unknown_column() {
+        let dir = tempdir().unwrap();
+        let mut columns = ColumnRegistry::new();
+        let ext = columns.register("ext").unwrap();
+        let store = LevelDB::open(dir.path()).unwrap().with_columns(columns);
+        let other = ColumnId::custom("oth", StoreCodec::Raw, Compression::None);
+
+        store.put_bytes(ext, b"key", b"1").unwrap();
+        assert_eq!(store.get_bytes(ext, b"key").unwrap(), Some(b"1".to_vec()));
+
+        assert!(store.get_bytes(other, b"key").is_err());
+        assert!(store.query(&Query::new(other)).is_err());
+        assert!(store
+            .do_atomically(vec![StoreOp::Delete {
+                column: other,
+                key: b"key".to_vec(),
+            }])
+            .is_err());
+    }
+
+    #[test]
+    fn bloom_filter() {
+        let dir = tempdir().unwrap();
+        let blk = DBColumn::BeaconBlock.into();
+        let config = BloomConfig {
+            expected_keys: 100,
+            false_positive_rate: 0.01,
+        };
+
+        let store = LevelDB::open(dir.path()).unwrap();
+        store.put_bytes(blk, b"before", b"0").unwrap();
+        let store = store.with_bloom_filter(blk, config).unwrap();
+        store.put_bytes(blk, b"after", b"1").unwrap();
+
+        assert!(store.key_exists(blk, b"before").unwrap());
+        assert!(store.key_exists(blk, b"after").unwrap());
+        assert!(!store.key_exists(blk, b"absent").unwrap());
+        assert!(!store.bloom_may_contain(blk, b"absent"));
+
+        // Saved on drop, loaded and deleted on open.
+        drop(store);
+        let path = dir.path().join("blk.bloom");
+        assert!(path.exists());
+        let store = LevelDB::open(dir.path())
+            .unwrap()
+            .with_bloom_filter(blk, config)
+            .unwrap();
+        assert!(!path.exists());
+        assert!(store.key_exists(blk, b"after").unwrap());
+
+        let custom = ColumnId::custom("ext", StoreCodec::Raw, Compression::None);
+        assert!(store.with_bloom_filter(custom, config).is_err());
+    }
+
+    #[test]
+    fn bloom_filter_not_stale() {
+        let dir = tempdir().unwrap();
+        let blk = DBColumn::BeaconBlock.into();
+        let config = BloomConfig {
+            expected_keys: 100,
+            false_positive_rate: 0.01,
+        };
+
+        let store = LevelDB::open(dir.path())
+            .unwrap()
+            .with_bloom_filter(blk, config)
+            .unwrap();
+        store.put_bytes(blk, b"filtered", b"0").unwrap();
+        drop(store);
+
+        // Opened without the filter, the saved one would miss the writes.
+        let path = dir.path().join("blk.bloom");
+        let store = LevelDB::open(dir.path()).unwrap();
+        assert!(!path.exists());
+        store.put_bytes(blk, b"unfiltered", b"1").unwrap();
+        drop(store);
+
+        let store = LevelDB::open(dir.path())
+            .unwrap()
+            .with_bloom_filter(blk, config)
+            .unwrap();
+        assert!(store.key_exists(blk, b"filtered").unwrap());
+        assert!(store.key_exists(blk, b"unfiltered").unwrap());
+    }
+
+    #[test]
+    fn group_commit() {
+        let dir = tempdir().unwrap();
+        let store = std::sync::Arc::new(
+            LevelDB::open_with_group_commit(dir.path(), GroupCommitConfig::default()).unwrap(),
+        );
+
+        let handles: Vec<_> = (0u8..16)
+            .map(|i| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    store
+                        .put_bytes(DBColumn::BeaconBlock.into(), &[i], &[i])
+                        .unwrap()
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for i in 0u8..16 {
+            assert_eq!(
+                store.get_bytes(DBColumn::BeaconBlock.into(), &[i]).unwrap(),
+                Some(vec![i])
+            );
+        }
+    }
+
+    #[test]
+    fn iter_prefix() {
+        let dir = tempdir().unwrap();
+        let store = LevelDB::open(dir.path()).unwrap();
+        let blk = DBColumn::BeaconBlock.into();
+        let ste = DBColumn::BeaconState.into();
+
+        store.put_bytes(blk, b"a1", b"1").unwrap();
+        store.put_bytes(blk, b"a2", b"2").unwrap();
+        store.put_bytes(blk, b"b1", b"3").unwrap();
+        store.put_bytes(ste, b"a3", b"4").unwrap();
+
+        let blocks: Vec<_> = store.iter_column(blk).unwrap().collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (b"a1".to_vec(), b"1".to_vec()),
+                (b"a2".to_vec(), b"2".to_vec()),
+                (b"b1".to_vec(), b"3".to_vec()),
+            ]
+        );
+
+        let keys: Vec<_> = store
+            .iter_prefix(blk, b"a")
+            .unwrap()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec![b"a1".to_vec(), b"a2".to_vec()]);
+        assert_eq!(store.iter_prefix(ste, b"b").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn descending_query() {
+        let dir = tempdir().unwrap();
+        let store = LevelDB::open(dir.path()).unwrap();
+        let blk = DBColumn::BeaconBlock.into();
+        let sli = DBColumn::SlotIndex.into();
+
+        for key in &[b"a1", b"a3", b"b1"] {
+            store.put_bytes(blk, &key[..], b"").unwrap();
+            store.put_bytes(sli, &key[..], b"").unwrap();
+        }
+
+        let keys = |column, end: &[u8]| -> Vec<Vec<u8>> {
+            let query = Query::new(column)
+                .range(..=end.to_vec())
+                .order(Order::Descending)
+                .limit(2)
+                .keys_only();
+            store
+                .query(&query)
+                .unwrap()
+                .map(|entry| entry.key)
+                .collect()
+        };
+        assert_eq!(keys(blk, b"a2"), vec![b"a1".to_vec()]);
+        assert_eq!(keys(blk, b"a3"), vec![b"a3".to_vec(), b"a1".to_vec()]);
+        assert_eq!(keys(blk, b"c"), vec![b"b1".to_vec(), b"a3".to_vec()]);
+        assert_eq!(keys(blk, b"a"), Vec::<Vec<u8>>::new());
+        // The slot index holds the last keys of this database.
+        assert_eq!(keys(sli, b"c"), vec![b"b1".to_vec(), b"a3".to_vec()]);
+    }
+}
@@ -2,69 +2,205 @@
 //!
 //! Provides the following stores:
 //!
-//! - `DataStore`: an on-disk store backed by leveldb. Used in production.
+//! - `DataStore`: an on-disk store backed by leveldb. Used in production. Concurrent writes can be
+//!   batched with `DiskStore::open_with_group_commit`, and lookups of absent keys skipped with
+//!   `DiskStore::with_bloom_filter`.
 //! - `MemoryStore`: an in-memory store backed by a hash-map. Used for testing.
+//! - `QuotaStore`: wraps another store to enforce disk usage quotas, see the `quota` module.
+//! - `AsyncStore`: wraps another store to run its operations on I/O threads and return futures,
+//!   see the `async_store` module.
+//!
+//! Values of the block and state columns are compressed, with snappy and zstd respectively, see
+//! the `compression` module.
+//! Each column records how its values are serialized, and SSZ types get `StoreItem` by
+//! implementing `SszStoreItem`, see the `codec` module.
+//!
+//! Storage deals are kept by a `DealStore`, indexed by client, provider and state, see the `deals`
+//! module.
+//!
+//! The addresses of the node, their nonces and cached balances are kept by a `WalletStore`, with
+//! their keys in the keystore, see the `wallet` module.
+//!
+//! The whole datastore can be backed up to and restored from a single archive, see the `snapshot`
+//! module.
+//!
+//! `FsRepo` is a repo in a local directory, which it locks for as long as it is open so that two
+//! nodes never share it.
+//!
+//! Writes to the wallet, chain and deals datastores are committed together by
+//! `Repo::transaction`, see the `transaction` module.
+//!
+//! The key counts and disk usage of the wallet, chain, deals and block datastores are reported by
+//! `Repo::stat`, see the `stat` module.
 //!
 //! Provides a simple API for storing/retrieving all types that sometimes needs type-hints. See
 //! tests for implementation examples.
 
-use crate::error::Error;
-use crate::block::Cid;
+pub mod async_store;
+pub mod block_at_slot;
+pub mod bloom;
+pub mod codec;
+mod column;
+pub mod compression;
+pub mod deals;
+mod error;
+mod fs;
+pub mod group_commit;
+mod leveldb_store;
+mod lock;
+mod memory_store;
+pub mod migration;
+pub mod query;
+pub mod quota;
+pub mod snapshot;
+pub mod stat;
+pub mod test_utils;
+pub mod transaction;
+pub mod wallet;
+
+pub use crate::async_store::AsyncStore;
+pub use crate::block_at_slot::{BlockAtSlot, Slot, SlotBlock};
+pub use crate::bloom::BloomConfig;
+pub use crate::codec::{SszStoreItem, StoreCodec};
+pub use crate::column::{ColumnId, ColumnRegistry, DBColumn};
+pub use crate::compression::Compression;
+pub use crate::deals::{Deal, DealId, DealProposal, DealState, DealStore, DealUpdate};
+pub use crate::error::Error;
+pub use crate::fs::FsRepo;
+pub use crate::group_commit::GroupCommitConfig;
+pub use crate::leveldb_store::LevelDB as DiskStore;
+pub use crate::lock::RepoLock;
+pub use crate::memory_store::MemoryStore;
+pub use crate::migration::{run_migrations, Migration, REPO_VERSION};
+pub use crate::query::{Order, Query, QueryEntry, QueryIter};
+pub use crate::quota::{QuotaConfig, QuotaEvent, QuotaStore};
+pub use crate::snapshot::{ColumnManifest, Manifest};
+pub use crate::stat::{Datastore, DatastoreStat, RepoStat};
+pub use crate::transaction::{Batch, Transaction, TransactionStores};
+pub use crate::wallet::{CachedBalance, WalletEntry, WalletStore};
+pub use cid::Cid;
+pub use near_primitives::crypto::keystore::{KeyType, Keystore, KeystoreError, KeystoreKey};
 
-const API_FILE: &str = "api";
-const CONFIG_FILE_NAME: &str = "config.json";
 const LOCK_FILE: &str = "repo.lock";
 const VERSION_FILENAME: &str = "version";
 const WALLET_DATASTORE_FILENAME_PREFIX: &str ="wallet";
+const KEYSTORE_DATASTORE_FILENAME_PREFIX: &str = "keystore";
 const CHAIN_DATASTORE_FILENAME_PREFIX: &str ="chain";
 const DEALS_DATASTROE_FILENAME_PREFIX: &str = "deals";
+const BLOCKSTORE_DATASTORE_FILENAME_PREFIX: &str = "blocks";
 const SNAPSHOT_DATASTORE_FILENAME_PREFIX: &str ="snapshots";
+const TRANSACTIONS_DIR: &str = "transactions";
 
 /// Repo is a representation of all persistent data in a FileSys node.
+///
+/// The method names follow the repo interface of go-filecoin.
+#[allow(non_snake_case)]
 pub trait Repo {
 
     /// WalletDatastore is a specific storage solution, only used to store sensitive wallet information.
-    fn WalletDatastore()-> Resut<(),Error>;
+    ///
+    /// The keys of the addresses stay in the keystore, opened with `passphrase`, see the `wallet`
+    /// module.
+    fn WalletDatastore(&self, passphrase: &str) -> Result<WalletStore<DiskStore>, Error> {
+        Ok(WalletStore::new(
+            self.open_datastore(WALLET_DATASTORE_FILENAME_PREFIX)?,
+            self.KeystoreDataStore(passphrase)?,
+        ))
+    }
 
     /// KeystoreDataStore is a specific storage solution, only used to store local keystore information.
-    fn KeystoreDataStore() -> Result<(),Error>;
+    ///
+    /// Keys are encrypted at rest with `passphrase`. The same keystore holds the IPNS and the
+    /// validator signing keys.
+    fn KeystoreDataStore(&self, passphrase: &str) -> Result<Keystore, Error> {
+        let path = self.Path()?.join(KEYSTORE_DATASTORE_FILENAME_PREFIX);
+        Ok(Keystore::with_passphrase(&path, passphrase))
+    }
 
     /// ChainDatastore is a specific storage solution, only used to store already validated chain data.
     fn ChainDatastore() -> Result<(),Error>;
 
-    /// DealsDatastore holds deals data.
-    fn DealsDatastore() -> Result<(),Error>;
+    /// BlocksDatastore holds the blocks of the node, whose disk usage is bounded by `quota`, see
+    /// the `quota` module.
+    fn BlocksDatastore(&self, quota: QuotaConfig) -> Result<QuotaStore<DiskStore>, Error> {
+        let store = self.open_datastore(BLOCKSTORE_DATASTORE_FILENAME_PREFIX)?;
+        let path = self.Path()?.join(BLOCKSTORE_DATASTORE_FILENAME_PREFIX);
+        QuotaStore::open(store, quota, &path)
+    }
+
+    /// DealsDatastore holds deals data, see the `deals` module.
+    fn DealsDatastore(&self) -> Result<DealStore<DiskStore>, Error> {
+        DealStore::new(self.open_datastore(DEALS_DATASTROE_FILENAME_PREFIX)?)
+    }
 
     /// Version returns the current repo version.
-    fn Version() -> Result<(),Error>;
+    fn Version(&self) -> Result<u32, Error> {
+        migration::read_version(&self.Path()?)
+    }
 
-    ///	Path returns the repo path.
-    fn Path() -> Result<(),Error>;
+    /// Path returns the repo path.
+    fn Path(&self) -> Result<std::path::PathBuf, Error>;
 
-}
+    /// Opens the datastore in the `prefix` directory of the repo, with the built-in columns only
+    /// unless the repo registers custom ones.
+    fn open_datastore(&self, prefix: &str) -> Result<DiskStore, Error> {
+        DiskStore::open(&self.Path()?.join(prefix))
+    }
 
-/// A unique column identifier.
-pub enum DBColumn {
-    Wallet,
-    Keystore,
-    BeaconBlock,
-    BeaconState,
-    BeaconChain,
-}
+    /// Writes every column `store` was opened with to the snapshot `path`, relative to the
+    /// snapshots directory of the repo.
+    fn export_snapshot<S: DataStore>(
+        &self,
+        store: &S,
+        path: &std::path::Path,
+    ) -> Result<Manifest, Error> {
+        let repo_path = self.Path()?;
+        let version = migration::read_version(&repo_path)?;
+        let path = snapshot::snapshot_path(&repo_path, path);
+        snapshot::export(store, version, &path)
+    }
 
-impl<'a> Into<&'a str> for DBColumn {
-    /// Returns a `&str` that can be used for keying a key-value data base.
-    fn into(self) -> &'a str {
-        match self {
-            DBColumn::Wallet => &"wat",
-            DBColumn::Keystore => &"kst",
-            DBColumn::BeaconBlock => &"blk",
-            DBColumn::BeaconState => &"ste",
-            DBColumn::BeaconChain => &"bch",
-        }
+    /// Restores `store` from the snapshot `path`, relative to the snapshots directory of the repo.
+    ///
+    /// The repo takes the version the snapshot was exported from, `migration::upgrade` brings it
+    /// up to date.
+    fn import_snapshot<S: DataStore>(
+        &self,
+        store: &S,
+        path: &std::path::Path,
+    ) -> Result<Manifest, Error> {
+        let repo_path = self.Path()?;
+        let path = snapshot::snapshot_path(&repo_path, path);
+        let manifest = snapshot::import(store, &path)?;
+        migration::write_version(&repo_path, manifest.repo_version)?;
+        Ok(manifest)
     }
-}
 
+    /// Counts the keys of each datastore in `store` and measures the disk space each takes.
+    fn stat<S: DataStore>(&self, store: &S) -> Result<RepoStat, Error> {
+        stat::stat(store, &self.Path()?)
+    }
+
+    /// Runs `f` and commits the writes it adds to the transaction to `stores` atomically, even
+    /// though each datastore is a database of its own, see the `transaction` module.
+    fn transaction<S, T, F>(&self, stores: &TransactionStores<S>, f: F) -> Result<T, Error>
+    where
+        S: DataStore,
+        F: FnOnce(&mut Transaction) -> Result<T, Error>,
+    {
+        transaction::run(&self.Path()?, stores, f)
+    }
+
+    /// Completes the transactions a crash interrupted, returning how many were. Must run when the
+    /// repo is opened, before anything is written to `stores`.
+    fn recover_transactions<S: DataStore>(
+        &self,
+        stores: &TransactionStores<S>,
+    ) -> Result<usize, Error> {
+        transaction::recover(&self.Path()?, stores)
+    }
+
+}
 
 /// An item that may be stored in a `Store`.
 ///
@@ -73,7 +209,7 @@ impl<'a> Into<&'a str> for DBColumn {
 pub trait StoreItem : Sized {
 
     /// Identifies which column this item should be placed in.
-    fn db_column() -> DBColumn;
+    fn db_column() -> ColumnId;
 
     /// Serialize `self` as bytes.
     fn as_store_bytes(&self) -> Vec<u8>;
@@ -82,42 +218,75 @@ pub trait StoreItem : Sized {
     fn from_store_bytes(bytes: &mut [u8]) -> Result<Self, Error>;
 
     /// Store `self`.
-    fn db_put(&self, store: &impl Store, key: &Cid) -> Result<(), Error> {
-        let column = Self::db_column().into();
-        let key = key.as_bytes();
+    fn db_put(&self, store: &impl DataStore, key: &Cid) -> Result<(), Error> {
+        let column = Self::db_column();
+        let key = key.to_bytes();
+
+        let value = compression::encode_value(column, self.as_store_bytes());
 
-        store
-            .put_bytes(column, key, &self.as_store_bytes())
-            .map_err(Into::into)
+        store.put_bytes(column, &key, &value)
     }
 
     /// Retrieve an instance of `Self`.
-    fn db_get(store: &impl Store, key: &Cid) -> Result<Option<Self>, Error> {
-        let column = Self::db_column().into();
-        let key = key.as_bytes();
-
-        match store.get_bytes(column, key)? {
-            Some(mut bytes) => Ok(Some(Self::from_store_bytes(&mut bytes[..])?)),
+    fn db_get(store: &impl DataStore, key: &Cid) -> Result<Option<Self>, Error> {
+        let column = Self::db_column();
+        let key = key.to_bytes();
+
+        match store.get_bytes(column, &key)? {
+            Some(bytes) => {
+                let mut bytes = compression::decode_value(column, bytes)?;
+                Ok(Some(Self::from_store_bytes(&mut bytes[..])?))
+            }
             None => Ok(None),
         }
     }
 
     /// Return `true` if an instance of `Self` exists in `Store`.
-    fn db_exists(store: &impl Store, key: &Cid) -> Result<bool, Error> {
-        let column = Self::db_column().into();
-        let key = key.as_bytes();
+    fn db_exists(store: &impl DataStore, key: &Cid) -> Result<bool, Error> {
+        let column = Self::db_column();
+        let key = key.to_bytes();
 
-        store.key_exists(column, key)
+        store.key_exists(column, &key)
     }
 
     /// Delete `self` from the `Store`.
-    fn db_delete(store: &impl Store, key: &Cid) -> Result<(), Error> {
-        let column = Self::db_column().into();
-        let key = key.as_bytes();
+    fn db_delete(store: &impl DataStore, key: &Cid) -> Result<(), Error> {
+        let column = Self::db_column();
+        let key = key.to_bytes();
+
+        store.key_delete(column, &key)
+    }
 
-        store.key_delete(column, key)
+    /// Returns an operation storing `self`, to be committed with `DataStore::do_atomically`.
+    fn as_put_op(&self, key: &Cid) -> StoreOp {
+        StoreOp::Put {
+            column: Self::db_column(),
+            key: key.to_bytes(),
+            value: compression::encode_value(Self::db_column(), self.as_store_bytes()),
+        }
     }
 
+    /// Returns an operation deleting an instance of `Self`, to be committed with
+    /// `DataStore::do_atomically`.
+    fn as_delete_op(key: &Cid) -> StoreOp {
+        StoreOp::Delete {
+            column: Self::db_column(),
+            key: key.to_bytes(),
+        }
+    }
+}
+
+/// A single write, to be applied together with others by `DataStore::do_atomically`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreOp {
+    /// Store `value` in `column`, indexed with `key`.
+    Put {
+        column: ColumnId,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Remove `key` from `column`.
+    Delete { column: ColumnId, key: Vec<u8> },
 }
 
 /// An object capable of storing and retrieving objects implementing `StoreItem`.
@@ -125,9 +294,15 @@ pub trait StoreItem : Sized {
 /// A `Store` is fundamentally backed by a key-value database, however it provides support for
 /// columns. A simple column implementation might involve prefixing a key with some bytes unique to
 /// each column.
+///
+/// A store is opened with a `ColumnRegistry`, and every method fails with `Error::UnknownColumn`
+/// when given a column that is not registered with it.
 pub trait DataStore : Sync + Send + Sized {
+    /// Returns the columns this store was opened with.
+    fn columns(&self) -> &ColumnRegistry;
+
     /// Store an item in `Self`.
-    fn put(&self, key: &Hash256, item: &impl StoreItem) -> Result<(), Error> {
+    fn put(&self, key: &Cid, item: &impl StoreItem) -> Result<(), Error> {
         item.db_put(self, key)
     }
 
@@ -151,27 +326,71 @@ pub trait DataStore : Sync + Send + Sized {
     ///
     /// Returns `None` if no parent block exists at that slot, or if `slot` is greater than the
     /// slot of `start_block_root`.
-    fn get_block_at_preceeding_slot(
+    fn get_block_at_preceeding_slot<B: SlotBlock>(
         &self,
         start_block_root: Cid,
         slot: Slot,
-    ) -> Result<Option<(Cid, BeaconBlock)>, Error> {
+    ) -> Result<Option<(Cid, B)>, Error> {
         block_at_slot::get_block_at_preceeding_slot(self, slot, start_block_root)
     }
 
+    /// Returns the canonical block at `slot`, or the closest canonical block before it if `slot`
+    /// was skipped.
+    fn get_canonical_block_at_slot<B: SlotBlock>(
+        &self,
+        slot: Slot,
+    ) -> Result<Option<BlockAtSlot<B>>, Error> {
+        block_at_slot::get_canonical_block_at_slot(self, slot)
+    }
+
+    /// Stores the block `root` and makes it the head of the canonical chain, indexing its slot in
+    /// the same batch, see the `block_at_slot` module.
+    fn import_canonical_block<B: SlotBlock>(&self, root: Cid, block: B) -> Result<(), Error> {
+        block_at_slot::import_canonical_block(self, root, block)
+    }
+
+    /// Makes the stored block `root` the head of the canonical chain, re-indexing the slots it
+    /// does not share with the previous head. Used to revert to another fork.
+    fn set_canonical_head<B: SlotBlock>(&self, root: Cid) -> Result<(), Error> {
+        block_at_slot::set_canonical_head::<_, B>(self, root)
+    }
+
     /// Retrieve some bytes in `column` with `key`.
-    fn get_bytes(&self, column: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+    fn get_bytes(&self, column: ColumnId, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
 
     /// Store some `value` in `column`, indexed with `key`.
-    fn put_bytes(&self, column: &str, key: &[u8], value: &[u8]) -> Result<(), Error>;
+    fn put_bytes(&self, column: ColumnId, key: &[u8], value: &[u8]) -> Result<(), Error>;
 
     /// Return `true` if `key` exists in `column`.
-    fn key_exists(&self, column: &str, key: &[u8]) -> Result<bool, Error>;
+    fn key_exists(&self, column: ColumnId, key: &[u8]) -> Result<bool, Error>;
 
     /// Removes `key` from `column`.
-    fn key_delete(&self, column: &str, key: &[u8]) -> Result<(), Error>;
+    fn key_delete(&self, column: ColumnId, key: &[u8]) -> Result<(), Error>;
+
+    /// Applies all `ops` in a single write batch: either all of them are persisted or none is.
+    fn do_atomically(&self, ops: Vec<StoreOp>) -> Result<(), Error>;
+
+    /// Iterate over all `(key, value)` pairs stored in `column`, in key order.
+    fn iter_column<'a>(&'a self, column: ColumnId) -> Result<ColumnIter<'a>, Error> {
+        self.iter_prefix(column, &[])
+    }
+
+    /// Iterate over the `(key, value)` pairs of `column` whose key starts with `prefix`, in key
+    /// order.
+    fn iter_prefix<'a>(&'a self, column: ColumnId, prefix: &[u8]) -> Result<ColumnIter<'a>, Error>;
+
+    /// Runs `query`, see the `query` module.
+    fn query<'a>(&'a self, query: &Query) -> Result<QueryIter<'a>, Error> {
+        Ok(query::execute(
+            self.iter_prefix(query.column, &query.prefix)?,
+            query,
+        ))
+    }
 }
 
+/// An iterator over `(key, value)` pairs of a column, keys are returned without the column prefix.
+pub type ColumnIter<'a> = Box<dyn Iterator<Item = (Vec<u8>, Vec<u8>)> + 'a>;
+
 #[cfg(test)]
 mod tests {
     #[test]
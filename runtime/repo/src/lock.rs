@@ -0,0 +1,111 @@
+//! Exclusive access to a repo directory.
+//!
+//! A node holds an advisory lock on `repo.lock` for as long as its repo is open, so that a second
+//! process opening the same directory fails instead of corrupting the datastore. The lock is tied
+//! to the open file and released by the OS when the process exits, even if it crashes.
+use super::*;
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// The lock on a repo directory, released when dropped.
+#[derive(Debug)]
+pub struct RepoLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// Locks the repo at `repo_path`, failing with `Error::RepoLocked` if another process holds it.
+    pub fn acquire(repo_path: &Path) -> Result<Self, Error> {
+        let path = repo_path.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // The pid of the current holder is kept until the lock is ours.
+            .truncate(false)
+            .open(&path)?;
+
+        if let Err(e) = file.try_lock_exclusive() {
+            return Err(if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+                Error::RepoLocked {
+                    path: path.display().to_string(),
+                }
+            } else {
+                e.into()
+            });
+        }
+
+        // Only informative, the lock itself is what keeps other processes out.
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+
+        Ok(RepoLock { file, path })
+    }
+
+    /// Locks the repo at `repo_path`, breaking the lock of any other holder.
+    ///
+    /// The lock file is replaced, so a process still holding the old one keeps running unaware
+    /// that it lost its lock. Only use this once sure that the holder is gone, e.g. a lock left
+    /// on a network file system by a dead host.
+    pub fn force_acquire(repo_path: &Path) -> Result<Self, Error> {
+        match fs::remove_file(repo_path.join(LOCK_FILE)) {
+            Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(Error::IoError {
+                    message: e.to_string(),
+                })
+            }
+            _ => {}
+        }
+        Self::acquire(repo_path)
+    }
+
+    /// Path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn exclusive() {
+        let dir = tempdir().unwrap();
+
+        let lock = RepoLock::acquire(dir.path()).unwrap();
+        assert_eq!(
+            fs::read_to_string(lock.path()).unwrap(),
+            std::process::id().to_string()
+        );
+        match RepoLock::acquire(dir.path()) {
+            Err(Error::RepoLocked { .. }) => {}
+            other => panic!("expected the repo to be locked, got {:?}", other),
+        }
+
+        drop(lock);
+        RepoLock::acquire(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn force() {
+        let dir = tempdir().unwrap();
+
+        let _stale = RepoLock::acquire(dir.path()).unwrap();
+        let lock = RepoLock::force_acquire(dir.path()).unwrap();
+
+        assert!(RepoLock::acquire(dir.path()).is_err());
+        drop(lock);
+        RepoLock::acquire(dir.path()).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use super::*;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+type DBHashMap = HashMap<Vec<u8>, Vec<u8>>;
+
+/// A thread-safe `HashMap` wrapper.
+pub struct MemoryStore {
+    db: RwLock<DBHashMap>,
+    columns: ColumnRegistry,
+}
+
+impl MemoryStore {
+    /// Create a new, empty database, with the built-in columns only.
+    pub fn open() -> Self {
+        Self {
+            db: RwLock::new(HashMap::new()),
+            columns: ColumnRegistry::new(),
+        }
+    }
+
+    /// Opens the database with `columns` rather than the built-in columns only.
+    pub fn with_columns(mut self, columns: ColumnRegistry) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    fn get_key_for_col(col: ColumnId, key: &[u8]) -> Vec<u8> {
+        let mut col = col.name().as_bytes().to_vec();
+        col.append(&mut key.to_vec());
+        col
+    }
+}
+
+impl DataStore for MemoryStore {
+    fn columns(&self) -> &ColumnRegistry {
+        &self.columns
+    }
+
+    /// Get the value of some key from the database. Returns `None` if the key does not exist.
+    fn get_bytes(&self, col: ColumnId, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.columns.check(col)?;
+        let column_key = Self::get_key_for_col(col, key);
+
+        Ok(self
+            .db
+            .read()
+            .expect("memory store lock poisoned")
+            .get(&column_key)
+            .cloned())
+    }
+
+    /// Puts a key in the database.
+    fn put_bytes(&self, col: ColumnId, key: &[u8], val: &[u8]) -> Result<(), Error> {
+        self.columns.check(col)?;
+        let column_key = Self::get_key_for_col(col, key);
+
+        self.db
+            .write()
+            .expect("memory store lock poisoned")
+            .insert(column_key, val.to_vec());
+
+        Ok(())
+    }
+
+    /// Return true if some key exists in some column.
+    fn key_exists(&self, col: ColumnId, key: &[u8]) -> Result<bool, Error> {
+        self.columns.check(col)?;
+        let column_key = Self::get_key_for_col(col, key);
+
+        Ok(self
+            .db
+            .read()
+            .expect("memory store lock poisoned")
+            .contains_key(&column_key))
+    }
+
+    /// Delete some key from the database.
+    fn key_delete(&self, col: ColumnId, key: &[u8]) -> Result<(), Error> {
+        self.columns.check(col)?;
+        let column_key = Self::get_key_for_col(col, key);
+
+        self.db
+            .write()
+            .expect("memory store lock poisoned")
+            .remove(&column_key);
+
+        Ok(())
+    }
+
+    /// Applies all `ops` while holding the write lock, so readers never see a partial batch.
+    fn do_atomically(&self, ops: Vec<StoreOp>) -> Result<(), Error> {
+        for op in &ops {
+            match op {
+                StoreOp::Put { column, .. } | StoreOp::Delete { column, .. } => {
+                    self.columns.check(*column)?
+                }
+            }
+        }
+
+        let mut db = self.db.write().expect("memory store lock poisoned");
+
+        for op in ops {
+            match op {
+                StoreOp::Put { column, key, value } => {
+                    db.insert(Self::get_key_for_col(column, &key), value);
+                }
+                StoreOp::Delete { column, key } => {
+                    db.remove(&Self::get_key_for_col(column, &key));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copies the matching pairs out of the map and sorts them, later writes are not seen by the
+    /// returned iterator.
+    fn iter_prefix<'a>(&'a self, col: ColumnId, prefix: &[u8]) -> Result<ColumnIter<'a>, Error> {
+        self.columns.check(col)?;
+        let start_key = Self::get_key_for_col(col, prefix);
+        let col_len = col.name().len();
+
+        let mut pairs: Vec<_> = self
+            .db
+            .read()
+            .expect("memory store lock poisoned")
+            .iter()
+            .filter(|(key, _)| key.starts_with(&start_key))
+            .map(|(key, value)| (key[col_len..].to_vec(), value.clone()))
+            .collect();
+        pairs.sort();
+
+        Ok(Box::new(pairs.into_iter()))
+    }
+
+    /// Only copies the values of matching pairs, and none for `keys_only` queries.
+    fn query<'a>(&'a self, query: &Query) -> Result<QueryIter<'a>, Error> {
+        self.columns.check(query.column)?;
+        let col = query.column.name();
+        let col_len = col.len();
+
+        let mut pairs: Vec<_> = self
+            .db
+            .read()
+            .expect("memory store lock poisoned")
+            .iter()
+            .filter(|(key, _)| key.starts_with(col.as_bytes()) && query.matches(&key[col_len..]))
+            .map(|(key, value)| {
+                let value = if query.keys_only { vec![] } else { value.clone() };
+                (key[col_len..].to_vec(), value)
+            })
+            .collect();
+        pairs.sort();
+
+        Ok(query::execute(Box::new(pairs.into_iter()), query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_get_delete() {
+        let store = MemoryStore::open();
+        let blk = DBColumn::BeaconBlock.into();
+        let ste = DBColumn::BeaconState.into();
+
+        store.put_bytes(blk, b"key", b"1").unwrap();
+        store.put_bytes(ste, b"key", b"2").unwrap();
+
+        assert_eq!(store.get_bytes(blk, b"key").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get_bytes(ste, b"key").unwrap(), Some(b"2".to_vec()));
+        assert!(store.key_exists(blk, b"key").unwrap());
+
+        store.key_delete(blk, b"key").unwrap();
+
+        assert!(!store.key_exists(blk, b"key").unwrap());
+        assert_eq!(store.get_bytes(blk, b"key").unwrap(), None);
+        assert!(store.key_exists(ste, b"key").unwrap());
+    }
+
+    #[test]
+    fn unknown_column() {
+        let mut columns = ColumnRegistry::new();
+        let ext = columns.register("ext").unwrap();
+        let other = ColumnId::custom("oth", StoreCodec::Raw, Compression::None);
+        let store = MemoryStore::open().with_columns(columns);
+
+        store.put_bytes(ext, b"key", b"1").unwrap();
+        assert_eq!(store.get_bytes(ext, b"key").unwrap(), Some(b"1".to_vec()));
+
+        let unknown = Err(Error::UnknownColumn {
+            name: "oth".to_string(),
+        });
+        assert_eq!(store.put_bytes(other, b"key", b"1"), unknown);
+        assert_eq!(store.key_delete(other, b"key"), unknown);
+        assert!(store.iter_column(other).is_err());
+        assert!(MemoryStore::open().get_bytes(ext, b"key").is_err());
+
+        // nothing of a batch is applied when one of its columns is unknown.
+        let ops = vec![
+            StoreOp::Put {
+                column: ext,
+                key: b"new".to_vec(),
+                value: b"2".to_vec(),
+            },
+            StoreOp::Delete {
+                column: other,
+                key: b"key".to_vec(),
+            },
+        ];
+        assert_eq!(store.do_atomically(ops), unknown);
+        assert!(!store.key_exists(ext, b"new").unwrap());
+    }
+
+    #[test]
+    fn iter_prefix() {
+        let store = MemoryStore::open();
+
+        store
+            .do_atomically(vec![
+                StoreOp::Put {
+                    column: DBColumn::BeaconBlock.into(),
+                    key: b"b1".to_vec(),
+                    value: b"3".to_vec(),
+                },
+                StoreOp::Put {
+                    column: DBColumn::BeaconBlock.into(),
+                    key: b"a2".to_vec(),
+                    value: b"2".to_vec(),
+                },
+                StoreOp::Put {
+                    column: DBColumn::BeaconBlock.into(),
+                    key: b"a1".to_vec(),
+                    value: b"1".to_vec(),
+                },
+                StoreOp::Put {
+                    column: DBColumn::BeaconState.into(),
+                    key: b"a3".to_vec(),
+                    value: b"4".to_vec(),
+                },
+            ])
+            .unwrap();
+
+        let blk = DBColumn::BeaconBlock.into();
+        let keys: Vec<_> = store
+            .iter_column(blk)
+            .unwrap()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, vec![b"a1".to_vec(), b"a2".to_vec(), b"b1".to_vec()]);
+
+        let pairs: Vec<_> = store.iter_prefix(blk, b"a").unwrap().collect();
+        assert_eq!(
+            pairs,
+            vec![(b"a1".to_vec(), b"1".to_vec()), (b"a2".to_vec(), b"2".to_vec())]
+        );
+    }
+}
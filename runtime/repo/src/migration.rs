@@ -0,0 +1,294 @@
+//! Repo versioning and on-disk layout upgrades.
+//!
+//! The repo version is kept as a decimal number in the `version` file at the root of the repo. A
+//! repo older than `REPO_VERSION` is brought up to date by `run_migrations`, one version at a
+//! time. The version file is rewritten after every step, so an interrupted upgrade resumes where
+//! it stopped.
+//!
+//! Migrations write their changes `MIGRATION_BATCH` pairs at a time, so a column never has to fit
+//! in memory, and an interrupted step leaves some batches applied and others not.
+use super::*;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+
+/// Version of the on-disk layout written by this binary.
+pub const REPO_VERSION: u32 = 1;
+
+/// Pairs a migration rewrites per write batch.
+pub const MIGRATION_BATCH: usize = 1024;
+
+/// Reads the version of the repo at `repo_path`.
+///
+/// Repos created before versioning have no version file and are at version 0.
+pub fn read_version(repo_path: &Path) -> Result<u32, Error> {
+    match fs::read_to_string(repo_path.join(VERSION_FILENAME)) {
+        Ok(contents) => contents.trim().parse().map_err(|e| Error::DecodeError {
+            message: format!("invalid repo version {:?}: {}", contents.trim(), e),
+        }),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Records `version` as the version of the repo at `repo_path`.
+///
+/// The version is written to a temporary file renamed once synced, so a crash never leaves the
+/// version file empty or truncated.
+pub fn write_version(repo_path: &Path, version: u32) -> Result<(), Error> {
+    let path = repo_path.join(VERSION_FILENAME);
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(format!("{}\n", version).as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
+/// An upgrade of the on-disk layout from `version() - 1` to `version()`.
+pub trait Migration<S: DataStore> {
+    /// The version the repo is at once the migration is applied.
+    fn version(&self) -> u32;
+
+    /// Applies the migration to `store`.
+    ///
+    /// A migration may be interrupted and run again, so it must cope with a partially migrated
+    /// store.
+    fn migrate(&self, store: &S) -> Result<(), Error>;
+}
+
+/// Moves every pair of column `from` to column `to`.
+pub struct RenameColumn {
+    pub version: u32,
+    pub from: ColumnId,
+    pub to: ColumnId,
+}
+
+impl<S: DataStore> Migration<S> for RenameColumn {
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Each pair is deleted in the batch that moves it, so a resumed run only moves the pairs
+    /// left in `from`.
+    fn migrate(&self, store: &S) -> Result<(), Error> {
+        rewrite_column(store, self.from, |key, value| {
+            Ok(vec![
+                StoreOp::Put {
+                    column: self.to,
+                    key: key.clone(),
+                    value,
+                },
+                StoreOp::Delete {
+                    column: self.from,
+                    key,
+                },
+            ])
+        })
+    }
+}
+
+/// Rewrites every value of `column` with `reencode`.
+///
+/// Values for which `is_reencoded` holds are left as they are, so a resumed run does not encode
+/// twice the values of the batches written before it was interrupted.
+pub struct Reencode {
+    pub version: u32,
+    pub column: ColumnId,
+    pub reencode: fn(&[u8]) -> Result<Vec<u8>, Error>,
+    /// Returns `true` for values `reencode` returned already.
+    pub is_reencoded: fn(&[u8]) -> bool,
+}
+
+impl<S: DataStore> Migration<S> for Reencode {
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn migrate(&self, store: &S) -> Result<(), Error> {
+        rewrite_column(store, self.column, |key, value| {
+            if (self.is_reencoded)(&value) {
+                return Ok(vec![]);
+            }
+            Ok(vec![StoreOp::Put {
+                column: self.column,
+                value: (self.reencode)(&value)?,
+                key,
+            }])
+        })
+    }
+}
+
+/// Applies the operations `rewrite` returns for each pair of `column`, `MIGRATION_BATCH` pairs
+/// per write batch.
+///
+/// `rewrite` may only write the pair it is given or pairs of other columns, as the column is
+/// still being read while the batches are written.
+fn rewrite_column<S, F>(store: &S, column: ColumnId, mut rewrite: F) -> Result<(), Error>
+where
+    S: DataStore,
+    F: FnMut(Vec<u8>, Vec<u8>) -> Result<Vec<StoreOp>, Error>,
+{
+    let mut ops = vec![];
+    let mut pairs = 0;
+    for (key, value) in store.iter_column(column)? {
+        ops.extend(rewrite(key, value)?);
+        pairs += 1;
+        if pairs == MIGRATION_BATCH {
+            store.do_atomically(mem::take(&mut ops))?;
+            pairs = 0;
+        }
+    }
+    store.do_atomically(ops)
+}
+
+/// Upgrades the repo at `repo_path`, whose datastore is `store`, from version `from` to `to`.
+///
+/// `migrations` must hold one migration for each version after `from` up to `to`, in any order.
+/// Downgrades are not supported.
+pub fn run_migrations<S: DataStore>(
+    repo_path: &Path,
+    store: &S,
+    migrations: &[&dyn Migration<S>],
+    from: u32,
+    to: u32,
+) -> Result<(), Error> {
+    if from > to {
+        return Err(Error::UnsupportedVersion {
+            found: from,
+            supported: to,
+        });
+    }
+
+    for version in from + 1..=to {
+        let migration = migrations
+            .iter()
+            .find(|migration| migration.version() == version)
+            .ok_or(Error::MissingMigration { version })?;
+        migration.migrate(store)?;
+        write_version(repo_path, version)?;
+    }
+    Ok(())
+}
+
+/// Brings the repo at `repo_path` up to `REPO_VERSION`, returning the version it was found at.
+pub fn upgrade<S: DataStore>(
+    repo_path: &Path,
+    store: &S,
+    migrations: &[&dyn Migration<S>],
+) -> Result<u32, Error> {
+    let version = read_version(repo_path)?;
+    run_migrations(repo_path, store, migrations, version, REPO_VERSION)?;
+    Ok(version)
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError {
+            message: e.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn tag(value: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok([b"v2:", value].concat())
+    }
+
+    fn is_tagged(value: &[u8]) -> bool {
+        value.starts_with(b"v2:")
+    }
+
+    #[test]
+    fn version_file() {
+        let dir = tempdir().unwrap();
+
+        assert_eq!(read_version(dir.path()), Ok(0));
+        write_version(dir.path(), 3).unwrap();
+        assert_eq!(read_version(dir.path()), Ok(3));
+        assert!(!dir.path().join("version.tmp").exists());
+
+        fs::write(dir.path().join(VERSION_FILENAME), "three").unwrap();
+        assert!(read_version(dir.path()).is_err());
+    }
+
+    #[test]
+    fn migrate() {
+        let dir = tempdir().unwrap();
+        let store = MemoryStore::open();
+        let ipn = DBColumn::Ipns.into();
+        let dls = DBColumn::Deals.into();
+        store.put_bytes(ipn, b"a", b"1").unwrap();
+
+        let rename = RenameColumn {
+            version: 1,
+            from: ipn,
+            to: dls,
+        };
+        let reencode = Reencode {
+            version: 2,
+            column: dls,
+            reencode: tag,
+            is_reencoded: is_tagged,
+        };
+        let migrations: [&dyn Migration<MemoryStore>; 2] = [&reencode, &rename];
+
+        run_migrations(dir.path(), &store, &migrations, 0, 2).unwrap();
+
+        assert_eq!(read_version(dir.path()), Ok(2));
+        assert!(!store.key_exists(ipn, b"a").unwrap());
+        assert_eq!(store.get_bytes(dls, b"a").unwrap(), Some(b"v2:1".to_vec()));
+    }
+
+    #[test]
+    fn migrate_resumed() {
+        let dir = tempdir().unwrap();
+        let store = MemoryStore::open();
+        let dls = DBColumn::Deals.into();
+        let pairs = 2 * MIGRATION_BATCH + 1;
+        for i in 0..pairs as u32 {
+            store.put_bytes(dls, &i.to_be_bytes(), b"1").unwrap();
+        }
+        // An interrupted run converted the first value already.
+        store.put_bytes(dls, &0u32.to_be_bytes(), b"v2:1").unwrap();
+
+        let reencode = Reencode {
+            version: 1,
+            column: dls,
+            reencode: tag,
+            is_reencoded: is_tagged,
+        };
+        run_migrations(dir.path(), &store, &[&reencode], 0, 1).unwrap();
+
+        let values: Vec<_> = store
+            .iter_column(dls)
+            .unwrap()
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(values, vec![b"v2:1".to_vec(); pairs]);
+    }
+
+    #[test]
+    fn migrate_errors() {
+        let dir = tempdir().unwrap();
+        let store = MemoryStore::open();
+
+        assert_eq!(
+            run_migrations(dir.path(), &store, &[], 2, 1),
+            Err(Error::UnsupportedVersion {
+                found: 2,
+                supported: 1
+            })
+        );
+        assert_eq!(
+            run_migrations(dir.path(), &store, &[], 0, 1),
+            Err(Error::MissingMigration { version: 1 })
+        );
+        assert_eq!(read_version(dir.path()), Ok(0));
+    }
+}
@@ -0,0 +1,275 @@
+//! Typed scans over a column.
+//!
+//! A `Query` describes which pairs of a column to read and how. `DataStore::query` runs it on top
+//! of `iter_prefix` with `execute`; backends able to do better, for instance by seeking straight
+//! to the start of the range, override it and leave the remaining clauses to `execute`, or to
+//! `execute_descending` when they walk the keys backwards from the end of the range.
+use super::*;
+use std::ops::{Bound, RangeBounds};
+
+/// Order in which a query returns keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+/// A scan over the pairs of a column.
+///
+/// Keys in `prefix` and `range` are given without the column prefix, like the keys returned.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    pub column: ColumnId,
+    /// Only keys starting with `prefix` are returned.
+    pub prefix: Vec<u8>,
+    /// Only keys within `range` are returned.
+    pub range: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    /// Maximum number of pairs returned.
+    pub limit: Option<usize>,
+    pub order: Order,
+    /// When set, values are not read and every `QueryEntry::value` is `None`.
+    pub keys_only: bool,
+}
+
+impl Query {
+    /// A query returning every pair of `column`, in ascending key order.
+    pub fn new(column: ColumnId) -> Self {
+        Query {
+            column,
+            prefix: vec![],
+            range: (Bound::Unbounded, Bound::Unbounded),
+            limit: None,
+            order: Order::Ascending,
+            keys_only: false,
+        }
+    }
+
+    pub fn prefix(mut self, prefix: &[u8]) -> Self {
+        self.prefix = prefix.to_vec();
+        self
+    }
+
+    pub fn range<R: RangeBounds<Vec<u8>>>(mut self, range: R) -> Self {
+        self.range = (cloned(range.start_bound()), cloned(range.end_bound()));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn keys_only(mut self) -> Self {
+        self.keys_only = true;
+        self
+    }
+
+    /// Returns `true` if `key` matches the prefix and range of the query.
+    pub fn matches(&self, key: &[u8]) -> bool {
+        key.starts_with(&self.prefix) && self.range.contains(&key.to_vec())
+    }
+
+    /// Returns `true` if `key` is past the end of the range, in ascending order.
+    pub fn is_after_range(&self, key: &[u8]) -> bool {
+        match &self.range.1 {
+            Bound::Included(end) => key > &end[..],
+            Bound::Excluded(end) => key >= &end[..],
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// The first key, in ascending order, a backend needs to seek to.
+    pub fn start_key(&self) -> &[u8] {
+        match &self.range.0 {
+            Bound::Included(start) | Bound::Excluded(start) if start[..] > self.prefix[..] => {
+                start
+            }
+            _ => &self.prefix,
+        }
+    }
+
+    /// The last key, in ascending order, a backend needs to seek to when walking the keys
+    /// backwards, or `None` if the range has no end.
+    pub fn end_key(&self) -> Option<&[u8]> {
+        match &self.range.1 {
+            Bound::Included(end) | Bound::Excluded(end) => Some(end),
+            Bound::Unbounded => None,
+        }
+    }
+}
+
+fn cloned(bound: Bound<&Vec<u8>>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.clone()),
+        Bound::Excluded(key) => Bound::Excluded(key.clone()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// A pair returned by a query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryEntry {
+    pub key: Vec<u8>,
+    /// `None` for `keys_only` queries.
+    pub value: Option<Vec<u8>>,
+}
+
+/// An iterator over the results of a query.
+pub type QueryIter<'a> = Box<dyn Iterator<Item = QueryEntry> + 'a>;
+
+/// Runs `query` over `iter`, the pairs of `query.column` in ascending key order.
+///
+/// `iter` may already be narrowed down to the prefix or range of the query, the clauses are
+/// applied again regardless.
+pub fn execute<'a>(iter: ColumnIter<'a>, query: &Query) -> QueryIter<'a> {
+    let filter = query.clone();
+    let matching = iter
+        .skip_while({
+            let start = query.start_key().to_vec();
+            move |(key, _)| key[..] < start[..]
+        })
+        .take_while({
+            let query = query.clone();
+            move |(key, _)| !query.is_after_range(key)
+        })
+        .filter(move |(key, _)| filter.matches(key));
+
+    let matching: ColumnIter<'a> = match query.order {
+        Order::Ascending => Box::new(matching),
+        Order::Descending => Box::new(matching.collect::<Vec<_>>().into_iter().rev()),
+    };
+    finish(matching, query)
+}
+
+/// Runs the descending `query` over `iter`, the pairs of `query.column` in descending key order.
+///
+/// `iter` may start after the end of the range and run past its start, the clauses are applied
+/// again regardless. Unlike `execute`, the pairs are not collected first.
+pub fn execute_descending<'a>(iter: ColumnIter<'a>, query: &Query) -> QueryIter<'a> {
+    let filter = query.clone();
+    let matching = iter
+        .skip_while({
+            let query = query.clone();
+            move |(key, _)| query.is_after_range(key)
+        })
+        .take_while({
+            let start = query.start_key().to_vec();
+            move |(key, _)| key[..] >= start[..]
+        })
+        .filter(move |(key, _)| filter.matches(key));
+    finish(Box::new(matching), query)
+}
+
+fn finish<'a>(matching: ColumnIter<'a>, query: &Query) -> QueryIter<'a> {
+    let matching = matching.take(query.limit.unwrap_or(usize::MAX));
+
+    let keys_only = query.keys_only;
+    Box::new(matching.map(move |(key, value)| QueryEntry {
+        key,
+        value: if keys_only { None } else { Some(value) },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> MemoryStore {
+        let store = MemoryStore::open();
+        for key in &[b"a1", b"a2", b"a3", b"b1", b"b2"] {
+            store
+                .put_bytes(DBColumn::Deals.into(), &key[..], &key[..])
+                .unwrap();
+        }
+        store.put_bytes(DBColumn::Ipns.into(), b"a0", b"x").unwrap();
+        store
+    }
+
+    fn keys(iter: Result<QueryIter, Error>) -> Vec<Vec<u8>> {
+        iter.unwrap().map(|entry| entry.key).collect()
+    }
+
+    #[test]
+    fn prefix_and_range() {
+        let store = store();
+        let query = Query::new(DBColumn::Deals.into()).prefix(b"a");
+        assert_eq!(
+            keys(store.query(&query)),
+            vec![b"a1".to_vec(), b"a2".to_vec(), b"a3".to_vec()]
+        );
+
+        let query = Query::new(DBColumn::Deals.into()).range(b"a2".to_vec()..b"b2".to_vec());
+        assert_eq!(
+            keys(store.query(&query)),
+            vec![b"a2".to_vec(), b"a3".to_vec(), b"b1".to_vec()]
+        );
+
+        let query = query.prefix(b"b");
+        assert_eq!(keys(store.query(&query)), vec![b"b1".to_vec()]);
+    }
+
+    #[test]
+    fn order_limit_keys_only() {
+        let store = store();
+        let query = Query::new(DBColumn::Deals.into())
+            .order(Order::Descending)
+            .limit(2)
+            .keys_only();
+
+        let entries: Vec<_> = store.query(&query).unwrap().collect();
+        assert_eq!(
+            entries,
+            vec![
+                QueryEntry {
+                    key: b"b2".to_vec(),
+                    value: None
+                },
+                QueryEntry {
+                    key: b"b1".to_vec(),
+                    value: None
+                },
+            ]
+        );
+
+        let query = Query::new(DBColumn::Deals.into()).limit(1);
+        assert_eq!(
+            store.query(&query).unwrap().collect::<Vec<_>>(),
+            vec![QueryEntry {
+                key: b"a1".to_vec(),
+                value: Some(b"a1".to_vec())
+            }]
+        );
+    }
+
+    #[test]
+    fn descending_from_end() {
+        let store = store();
+        let column = DBColumn::Deals.into();
+        let iter = || {
+            let mut pairs: Vec<_> = store.iter_column(column).unwrap().collect();
+            pairs.reverse();
+            Box::new(pairs.into_iter()) as ColumnIter
+        };
+
+        let query = Query::new(column)
+            .range(b"a2".to_vec()..b"b2".to_vec())
+            .order(Order::Descending);
+        assert_eq!(query.end_key(), Some(&b"b2"[..]));
+        assert_eq!(
+            keys(Ok(execute_descending(iter(), &query))),
+            vec![b"b1".to_vec(), b"a3".to_vec(), b"a2".to_vec()]
+        );
+
+        let query = query.prefix(b"a").limit(1);
+        assert_eq!(
+            keys(Ok(execute_descending(iter(), &query))),
+            vec![b"a3".to_vec()]
+        );
+        assert_eq!(Query::new(column).end_key(), None);
+    }
+}
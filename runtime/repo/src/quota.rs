@@ -0,0 +1,550 @@
+//! Disk usage quotas.
+//!
+//! Mirrors the `Datastore.StorageMax` and `Datastore.StorageGCWatermark` settings of go-ipfs. A
+//! `QuotaStore` wraps another store and keeps track of the bytes it holds:
+//!
+//! - once usage crosses the watermark, subscribers get `QuotaEvent::WatermarkReached` and the
+//!   garbage collector, if any, is woken up. It runs on its own thread, started with
+//!   `QuotaStore::spawn_gc`, so the write that crossed the watermark does not wait for it.
+//! - a write that would push usage past `storage_max` fails with `Error::StorageFull`, unless it
+//!   only touches essential columns. Deletes are always accepted as they free space.
+//!
+//! Measuring the usage takes a scan of the whole store. A store opened with `QuotaStore::open`
+//! saves its usage to a file when dropped, and reads it back instead of scanning on the next
+//! open. The file is deleted once read, so after a crash the usage is measured again rather than
+//! trusted stale.
+//!
+//! `Repo::BlocksDatastore` opens the block datastore of a repo with a quota.
+use super::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Name of the file `QuotaStore::open` keeps the usage in, as a decimal number.
+const USAGE_FILENAME: &str = "quota.usage";
+
+/// Usage limits of a repo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuotaConfig {
+    /// Hard limit, in bytes.
+    pub storage_max: u64,
+    /// Soft limit, as a percentage of `storage_max`.
+    pub storage_gc_watermark: u8,
+}
+
+impl Default for QuotaConfig {
+    /// Same defaults as go-ipfs: 10GB, with garbage collection starting at 90%.
+    fn default() -> Self {
+        QuotaConfig {
+            storage_max: 10_000_000_000,
+            storage_gc_watermark: 90,
+        }
+    }
+}
+
+impl QuotaConfig {
+    /// The soft limit, in bytes.
+    pub fn watermark(&self) -> u64 {
+        let percent = u64::from(self.storage_gc_watermark);
+        self.storage_max / 100 * percent + self.storage_max % 100 * percent / 100
+    }
+}
+
+/// Notifications sent to `QuotaStore::subscribe` receivers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QuotaEvent {
+    /// Usage went from below to above the watermark.
+    WatermarkReached { usage: u64, watermark: u64 },
+    /// A garbage collection triggered by the watermark finished.
+    GcFinished { usage: u64 },
+    /// A write was rejected because of the hard limit.
+    StorageFull { usage: u64, max: u64 },
+}
+
+/// Frees space in a store, called when usage crosses the watermark.
+pub type GarbageCollector<S> = Box<dyn Fn(&QuotaStore<S>) -> Result<(), Error> + Send + Sync>;
+
+/// Returns `true` if writes to `column` are accepted past the hard limit.
+///
+/// Losing these would leave the node unable to start or to follow the chain, while everything
+/// else can be fetched again from the network.
+pub fn is_essential(column: ColumnId) -> bool {
+    matches!(
+        column.column(),
+        DBColumn::Wallet | DBColumn::Keystore | DBColumn::BeaconChain | DBColumn::ForkChoice
+    )
+}
+
+/// A store enforcing a `QuotaConfig` on top of another store.
+///
+/// Usage is the sum of the key and value lengths of all stored pairs.
+pub struct QuotaStore<S> {
+    store: S,
+    config: QuotaConfig,
+    /// Held for the whole of a write, so that the accounting matches the store.
+    usage: Mutex<u64>,
+    gc: Option<GarbageCollector<S>>,
+    gc_running: AtomicBool,
+    /// Wakes up the thread started by `spawn_gc`, which exits once this is dropped.
+    gc_requests: Mutex<Option<Sender<()>>>,
+    subscribers: Mutex<Vec<Sender<QuotaEvent>>>,
+    /// Where the usage is saved when the store is dropped, if anywhere.
+    usage_path: Option<PathBuf>,
+}
+
+impl<S: DataStore> QuotaStore<S> {
+    /// Wraps `store`, measuring the current usage of all the columns it was opened with.
+    pub fn new(store: S, config: QuotaConfig) -> Result<Self, Error> {
+        let usage = Self::measure(&store)?;
+        Ok(Self::with_usage(store, config, usage, None))
+    }
+
+    /// Like `new`, but the usage is saved to a file in `dir` when the store is dropped, and read
+    /// back from there on the next open instead of measured. `dir` is usually the directory of
+    /// `store`.
+    pub fn open(store: S, config: QuotaConfig, dir: &Path) -> Result<Self, Error> {
+        let usage_path = dir.join(USAGE_FILENAME);
+        let usage = match take_saved_usage(&usage_path)? {
+            Some(usage) => usage,
+            None => Self::measure(&store)?,
+        };
+        Ok(Self::with_usage(store, config, usage, Some(usage_path)))
+    }
+
+    /// Sum of the pair sizes of all the columns `store` was opened with.
+    fn measure(store: &S) -> Result<u64, Error> {
+        let mut usage = 0;
+        for column in store.columns().all() {
+            usage += store
+                .iter_column(column)?
+                .map(|(key, value)| entry_size(&key, &value))
+                .sum::<u64>();
+        }
+        Ok(usage)
+    }
+
+    fn with_usage(store: S, config: QuotaConfig, usage: u64, usage_path: Option<PathBuf>) -> Self {
+        QuotaStore {
+            store,
+            config,
+            usage: Mutex::new(usage),
+            gc: None,
+            gc_running: AtomicBool::new(false),
+            gc_requests: Mutex::new(None),
+            subscribers: Mutex::new(Vec::new()),
+            usage_path,
+        }
+    }
+
+    /// Sets the garbage collector run when usage crosses the watermark, once `spawn_gc` started
+    /// its thread.
+    pub fn with_gc(mut self, gc: GarbageCollector<S>) -> Self {
+        self.gc = Some(gc);
+        self
+    }
+
+    /// Starts the thread running the garbage collector each time usage crosses the watermark.
+    ///
+    /// The thread only holds a weak reference to `store`, and exits once it is dropped.
+    pub fn spawn_gc(store: &Arc<Self>) -> Result<thread::JoinHandle<()>, Error>
+    where
+        S: 'static,
+    {
+        let (sender, receiver) = channel();
+        let weak = Arc::downgrade(store);
+        let handle = thread::Builder::new()
+            .name("repo-gc".to_string())
+            .spawn(move || {
+                for () in receiver {
+                    let store = match weak.upgrade() {
+                        Some(store) => store,
+                        None => return,
+                    };
+                    // Failures are reported by the `GcFinished` event, with the usage left.
+                    let _ = store.collect_garbage();
+                }
+            })
+            .map_err(|e| Error::IoError {
+                message: format!("failed to spawn gc thread: {}", e),
+            })?;
+        *store.gc_requests.lock().expect("quota lock poisoned") = Some(sender);
+        Ok(handle)
+    }
+
+    /// Bytes currently stored.
+    pub fn usage(&self) -> u64 {
+        *self.usage.lock().expect("quota lock poisoned")
+    }
+
+    pub fn config(&self) -> &QuotaConfig {
+        &self.config
+    }
+
+    /// The wrapped store. Writes made through it are not accounted for.
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns a receiver of all events from now on.
+    pub fn subscribe(&self) -> Receiver<QuotaEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers
+            .lock()
+            .expect("quota lock poisoned")
+            .push(sender);
+        receiver
+    }
+
+    /// Sends `event` to all subscribers, forgetting the ones that hung up.
+    fn emit(&self, event: QuotaEvent) {
+        self.subscribers
+            .lock()
+            .expect("quota lock poisoned")
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    /// Size of the pair currently stored at `key`, zero if none.
+    fn stored_size(&self, col: ColumnId, key: &[u8]) -> Result<u64, Error> {
+        Ok(self
+            .store
+            .get_bytes(col, key)?
+            .map_or(0, |value| entry_size(key, &value)))
+    }
+
+    /// Checks that growing usage to `new_usage` is allowed.
+    fn check_limit(&self, usage: u64, new_usage: u64, essential: bool) -> Result<(), Error> {
+        if essential || new_usage <= usage || new_usage <= self.config.storage_max {
+            return Ok(());
+        }
+
+        self.emit(QuotaEvent::StorageFull {
+            usage,
+            max: self.config.storage_max,
+        });
+        Err(Error::StorageFull {
+            usage,
+            max: self.config.storage_max,
+        })
+    }
+
+    /// Called with the usage before and after a write, once the write lock is released.
+    fn after_write(&self, before: u64, after: u64) {
+        let watermark = self.config.watermark();
+        if before >= watermark || after < watermark {
+            return;
+        }
+
+        self.emit(QuotaEvent::WatermarkReached {
+            usage: after,
+            watermark,
+        });
+        if let Some(requests) = &*self.gc_requests.lock().expect("quota lock poisoned") {
+            // The thread only exits once the store is dropped.
+            let _ = requests.send(());
+        }
+    }
+
+    /// Runs the garbage collector, unless it is already running.
+    pub fn collect_garbage(&self) -> Result<(), Error> {
+        let gc = match &self.gc {
+            Some(gc) => gc,
+            None => return Ok(()),
+        };
+        if self.gc_running.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let result = gc(self);
+        self.gc_running.store(false, Ordering::SeqCst);
+        self.emit(QuotaEvent::GcFinished {
+            usage: self.usage(),
+        });
+        result
+    }
+}
+
+impl<S> QuotaStore<S> {
+    /// Writes the usage to `usage_path`, through a temporary file renamed once complete.
+    fn save_usage(&self) -> Result<(), Error> {
+        let path = match &self.usage_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let usage = *self.usage.lock().expect("quota lock poisoned");
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, format!("{}\n", usage))?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+impl<S> Drop for QuotaStore<S> {
+    fn drop(&mut self) {
+        // Failing to save only means the usage is measured again on the next open.
+        let _ = self.save_usage();
+    }
+}
+
+/// Reads and deletes the usage saved at `path`, `None` if there is none.
+fn take_saved_usage(path: &Path) -> Result<Option<u64>, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    fs::remove_file(path)?;
+    // An unreadable usage is measured again like a missing one.
+    Ok(contents.trim().parse().ok())
+}
+
+fn entry_size(key: &[u8], value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
+
+impl<S: DataStore> DataStore for QuotaStore<S> {
+    fn columns(&self) -> &ColumnRegistry {
+        self.store.columns()
+    }
+
+    fn get_bytes(&self, col: ColumnId, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.store.get_bytes(col, key)
+    }
+
+    /// Fails with `Error::StorageFull` if `col` is not essential and the write would exceed
+    /// `storage_max`.
+    fn put_bytes(&self, col: ColumnId, key: &[u8], val: &[u8]) -> Result<(), Error> {
+        let (before, after) = {
+            let mut usage = self.usage.lock().expect("quota lock poisoned");
+            let before = *usage;
+            let after = before.saturating_sub(self.stored_size(col, key)?) + entry_size(key, val);
+            self.check_limit(before, after, is_essential(col))?;
+
+            self.store.put_bytes(col, key, val)?;
+            *usage = after;
+            (before, after)
+        };
+
+        self.after_write(before, after);
+        Ok(())
+    }
+
+    fn key_exists(&self, col: ColumnId, key: &[u8]) -> Result<bool, Error> {
+        self.store.key_exists(col, key)
+    }
+
+    fn key_delete(&self, col: ColumnId, key: &[u8]) -> Result<(), Error> {
+        let mut usage = self.usage.lock().expect("quota lock poisoned");
+        let after = usage.saturating_sub(self.stored_size(col, key)?);
+
+        self.store.key_delete(col, key)?;
+        *usage = after;
+        Ok(())
+    }
+
+    /// The batch is rejected as a whole if it grows usage past `storage_max` and any of its puts
+    /// targets a column that is not essential.
+    fn do_atomically(&self, ops: Vec<StoreOp>) -> Result<(), Error> {
+        let (before, after) = {
+            let mut usage = self.usage.lock().expect("quota lock poisoned");
+            let before = *usage;
+            // Sizes of the pairs the batch leaves behind, so that writing a key twice counts once.
+            let mut sizes: HashMap<(&str, &[u8]), (u64, u64)> = HashMap::new();
+            let mut essential = true;
+            for op in &ops {
+                let (column, key, new_size) = match op {
+                    StoreOp::Put { column, key, value } => {
+                        essential &= is_essential(*column);
+                        (*column, &key[..], entry_size(key, value))
+                    }
+                    StoreOp::Delete { column, key } => (*column, &key[..], 0),
+                };
+                match sizes.get_mut(&(column.name(), key)) {
+                    Some((_, size)) => *size = new_size,
+                    None => {
+                        let old_size = self.stored_size(column, key)?;
+                        sizes.insert((column.name(), key), (old_size, new_size));
+                    }
+                }
+            }
+            let after = sizes.values().fold(before, |usage, (old_size, new_size)| {
+                usage.saturating_sub(*old_size) + new_size
+            });
+            self.check_limit(before, after, essential)?;
+
+            self.store.do_atomically(ops)?;
+            *usage = after;
+            (before, after)
+        };
+
+        self.after_write(before, after);
+        Ok(())
+    }
+
+    fn iter_prefix<'a>(&'a self, col: ColumnId, prefix: &[u8]) -> Result<ColumnIter<'a>, Error> {
+        self.store.iter_prefix(col, prefix)
+    }
+
+    fn query<'a>(&'a self, query: &Query) -> Result<QueryIter<'a>, Error> {
+        self.store.query(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLK: DBColumn = DBColumn::BeaconBlock;
+
+    fn quota_store(storage_max: u64) -> QuotaStore<MemoryStore> {
+        let config = QuotaConfig {
+            storage_max,
+            storage_gc_watermark: 50,
+        };
+        QuotaStore::new(MemoryStore::open(), config).unwrap()
+    }
+
+    #[test]
+    fn usage_accounting() {
+        let store = MemoryStore::open();
+        store.put_bytes(BLK.into(), b"a", b"1234").unwrap();
+        let store = QuotaStore::new(store, QuotaConfig::default()).unwrap();
+        assert_eq!(store.usage(), 5);
+
+        store.put_bytes(BLK.into(), b"a", b"12").unwrap();
+        assert_eq!(store.usage(), 3);
+        store
+            .put_bytes(DBColumn::BeaconState.into(), b"b", b"1")
+            .unwrap();
+        assert_eq!(store.usage(), 5);
+        store.key_delete(BLK.into(), b"a").unwrap();
+        assert_eq!(store.usage(), 2);
+
+        store
+            .do_atomically(vec![
+                StoreOp::Put {
+                    column: BLK.into(),
+                    key: b"c".to_vec(),
+                    value: b"1".to_vec(),
+                },
+                StoreOp::Put {
+                    column: BLK.into(),
+                    key: b"c".to_vec(),
+                    value: b"123".to_vec(),
+                },
+                StoreOp::Delete {
+                    column: DBColumn::BeaconState.into(),
+                    key: b"b".to_vec(),
+                },
+            ])
+            .unwrap();
+        assert_eq!(store.usage(), 4);
+    }
+
+    #[test]
+    fn storage_full() {
+        let store = quota_store(10);
+        let events = store.subscribe();
+        store.put_bytes(BLK.into(), b"a", b"12345678").unwrap();
+
+        assert_eq!(
+            store.put_bytes(BLK.into(), b"b", b"12"),
+            Err(Error::StorageFull { usage: 9, max: 10 })
+        );
+        assert!(!store.key_exists(BLK.into(), b"b").unwrap());
+        // Shrinking writes and essential columns are still accepted.
+        store.put_bytes(BLK.into(), b"a", b"1").unwrap();
+        store
+            .put_bytes(DBColumn::Wallet.into(), b"key", b"123456789")
+            .unwrap();
+        assert_eq!(store.usage(), 14);
+
+        let events: Vec<_> = events.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                QuotaEvent::WatermarkReached {
+                    usage: 9,
+                    watermark: 5
+                },
+                QuotaEvent::StorageFull { usage: 9, max: 10 },
+                QuotaEvent::WatermarkReached {
+                    usage: 14,
+                    watermark: 5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn gc_at_watermark() {
+        let store = Arc::new(quota_store(100).with_gc(Box::new(|store| {
+            let keys: Vec<_> = store.iter_column(BLK.into())?.map(|(key, _)| key).collect();
+            for key in keys {
+                store.key_delete(BLK.into(), &key)?;
+            }
+            Ok(())
+        })));
+        let gc = QuotaStore::spawn_gc(&store).unwrap();
+        let events = store.subscribe();
+
+        store.put_bytes(BLK.into(), b"a", &[0; 29]).unwrap();
+        assert_eq!(store.usage(), 30);
+        store.put_bytes(BLK.into(), b"b", &[0; 29]).unwrap();
+
+        let timeout = std::time::Duration::from_secs(5);
+        assert_eq!(
+            events.recv_timeout(timeout),
+            Ok(QuotaEvent::WatermarkReached {
+                usage: 60,
+                watermark: 50
+            })
+        );
+        assert_eq!(
+            events.recv_timeout(timeout),
+            Ok(QuotaEvent::GcFinished { usage: 0 })
+        );
+        assert_eq!(store.usage(), 0);
+
+        // The thread exits with the store.
+        drop(store);
+        gc.join().unwrap();
+    }
+
+    #[test]
+    fn usage_never_underflows() {
+        let store = quota_store(100);
+        // Written behind the quota store's back, so not accounted for.
+        store.inner().put_bytes(BLK.into(), b"a", b"1234").unwrap();
+        store.put_bytes(BLK.into(), b"a", b"1").unwrap();
+        assert_eq!(store.usage(), 2);
+        store.inner().put_bytes(BLK.into(), b"b", b"1234").unwrap();
+        store.key_delete(BLK.into(), b"b").unwrap();
+        assert_eq!(store.usage(), 0);
+    }
+
+    #[test]
+    fn saved_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = QuotaConfig::default();
+        let store = QuotaStore::open(MemoryStore::open(), config, dir.path()).unwrap();
+        store.put_bytes(BLK.into(), b"a", b"1234").unwrap();
+        drop(store);
+
+        // Read back instead of measured, the new memory store being empty.
+        let store = QuotaStore::open(MemoryStore::open(), config, dir.path()).unwrap();
+        assert_eq!(store.usage(), 5);
+        assert!(!dir.path().join(USAGE_FILENAME).exists());
+
+        // Without a clean drop, the next open measures the store again.
+        std::mem::forget(store);
+        let store = MemoryStore::open();
+        store.put_bytes(BLK.into(), b"b", b"12").unwrap();
+        let store = QuotaStore::open(store, config, dir.path()).unwrap();
+        assert_eq!(store.usage(), 3);
+    }
+}
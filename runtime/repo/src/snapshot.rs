@@ -0,0 +1,341 @@
+//! Export and import of the whole datastore as a single archive.
+//!
+//! A snapshot is a snappy frame stream holding a manifest followed by every `(key, value)` pair of
+//! every column, columns in registry order and pairs in key order, so the same datastore always
+//! exports to the same bytes. The manifest records the snapshot format, the repo version the pairs
+//! were written with, and the number of pairs and a CRC32 checksum of each column.
+//!
+//! Values are archived as they are stored: compressed columns are not decompressed.
+//!
+//! Snapshots given by a relative path live in the `snapshots` directory of the repo, see
+//! `snapshot_path`.
+use super::*;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Bytes every snapshot starts with, once decompressed.
+const MAGIC: &[u8] = b"fssnap";
+
+/// Version of the archive layout written by this binary.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The number of pairs and the checksum of one archived column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnManifest {
+    pub column: String,
+    pub entries: u64,
+    /// CRC32 of the length-prefixed keys and values of the column, in archive order.
+    pub checksum: u32,
+}
+
+/// The header of a snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    /// Archive layout version, see `SNAPSHOT_VERSION`.
+    pub version: u32,
+    /// Version of the repo the snapshot was exported from.
+    pub repo_version: u32,
+    pub columns: Vec<ColumnManifest>,
+}
+
+/// Returns where the snapshot `path` of the repo at `repo_path` lives: `path` itself if absolute,
+/// otherwise in the `snapshots` directory of the repo.
+pub fn snapshot_path(repo_path: &Path, path: &Path) -> PathBuf {
+    repo_path
+        .join(SNAPSHOT_DATASTORE_FILENAME_PREFIX)
+        .join(path)
+}
+
+/// Writes every column `store` was opened with to a new snapshot at `path`.
+///
+/// The archive is written next to `path` and renamed once complete, so `path` never holds a
+/// partial snapshot.
+pub fn export<S: DataStore>(store: &S, repo_version: u32, path: &Path) -> Result<Manifest, Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // The checksums go first in the archive, so the pairs are read twice.
+    let ids: Vec<ColumnId> = store.columns().all().collect();
+    let mut columns = vec![];
+    for &id in &ids {
+        let mut summary = ColumnSummary::default();
+        for (key, value) in store.iter_column(id)? {
+            summary.add(&key, &value);
+        }
+        columns.push(summary.into_manifest(id));
+    }
+    let manifest = Manifest {
+        version: SNAPSHOT_VERSION,
+        repo_version,
+        columns,
+    };
+
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = snap::Writer::new(File::create(&tmp_path)?);
+    write_manifest(&mut writer, &manifest)?;
+    for (&id, column) in ids.iter().zip(&manifest.columns) {
+        let mut summary = ColumnSummary::default();
+        for (key, value) in store.iter_column(id)? {
+            summary.add(&key, &value);
+            write_bytes(&mut writer, &key)?;
+            write_bytes(&mut writer, &value)?;
+        }
+        if summary.entries != column.entries || summary.hasher.finalize() != column.checksum {
+            return Err(Error::InvalidSnapshot {
+                message: format!("column {} changed during export", column.column),
+            });
+        }
+    }
+    writer
+        .into_inner()
+        .map_err(|e| Error::IoError {
+            message: e.to_string(),
+        })?
+        .sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(manifest)
+}
+
+/// Replaces the content of the columns archived in the snapshot at `path` with the archived pairs.
+///
+/// The whole snapshot is read and checked against its manifest before anything is written, then
+/// applied in a single `DataStore::do_atomically` batch. Columns not in the snapshot are left
+/// untouched. Every archived column must be registered with `store`, or the import fails with
+/// `Error::UnknownColumn`, and the snapshot must not come from a repo newer than `REPO_VERSION`.
+pub fn import<S: DataStore>(store: &S, path: &Path) -> Result<Manifest, Error> {
+    let mut reader = snap::Reader::new(BufReader::new(File::open(path)?));
+    let manifest = read_manifest(&mut reader)?;
+    if manifest.repo_version > REPO_VERSION {
+        return Err(Error::UnsupportedVersion {
+            found: manifest.repo_version,
+            supported: REPO_VERSION,
+        });
+    }
+
+    let mut ops = vec![];
+    for archived in &manifest.columns {
+        let column = store.columns().id(&archived.column)?;
+        for (key, _) in store.iter_column(column)? {
+            ops.push(StoreOp::Delete { column, key });
+        }
+
+        let mut summary = ColumnSummary::default();
+        for _ in 0..archived.entries {
+            let key = read_bytes(&mut reader)?;
+            let value = read_bytes(&mut reader)?;
+            summary.add(&key, &value);
+            ops.push(StoreOp::Put { column, key, value });
+        }
+        if summary.hasher.finalize() != archived.checksum {
+            return Err(Error::InvalidSnapshot {
+                message: format!("checksum mismatch in column {}", archived.column),
+            });
+        }
+    }
+    if reader.read(&mut [0])? != 0 {
+        return Err(Error::InvalidSnapshot {
+            message: "trailing bytes after the last column".to_string(),
+        });
+    }
+
+    store.do_atomically(ops)?;
+    Ok(manifest)
+}
+
+/// Counts and checksums the pairs of a column.
+#[derive(Default)]
+struct ColumnSummary {
+    entries: u64,
+    hasher: crc32fast::Hasher,
+}
+
+impl ColumnSummary {
+    fn add(&mut self, key: &[u8], value: &[u8]) {
+        self.entries += 1;
+        for bytes in &[key, value] {
+            self.hasher.update(&(bytes.len() as u32).to_le_bytes());
+            self.hasher.update(bytes);
+        }
+    }
+
+    fn into_manifest(self, column: ColumnId) -> ColumnManifest {
+        ColumnManifest {
+            column: column.name().to_string(),
+            entries: self.entries,
+            checksum: self.hasher.finalize(),
+        }
+    }
+}
+
+fn write_manifest<W: Write>(writer: &mut W, manifest: &Manifest) -> Result<(), Error> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&manifest.version.to_le_bytes())?;
+    writer.write_all(&manifest.repo_version.to_le_bytes())?;
+    writer.write_all(&(manifest.columns.len() as u32).to_le_bytes())?;
+    for column in &manifest.columns {
+        write_bytes(writer, column.column.as_bytes())?;
+        writer.write_all(&column.entries.to_le_bytes())?;
+        writer.write_all(&column.checksum.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_manifest<R: Read>(reader: &mut R) -> Result<Manifest, Error> {
+    let mut magic = [0; MAGIC.len()];
+    read_exact(reader, &mut magic)?;
+    if magic != MAGIC {
+        return Err(Error::InvalidSnapshot {
+            message: "not a snapshot".to_string(),
+        });
+    }
+
+    let version = read_u32(reader)?;
+    if version > SNAPSHOT_VERSION {
+        return Err(Error::InvalidSnapshot {
+            message: format!(
+                "snapshot version {} is newer than the supported version {}",
+                version, SNAPSHOT_VERSION
+            ),
+        });
+    }
+    let repo_version = read_u32(reader)?;
+
+    let mut columns = vec![];
+    for _ in 0..read_u32(reader)? {
+        let column =
+            String::from_utf8(read_bytes(reader)?).map_err(|e| Error::InvalidSnapshot {
+                message: format!("invalid column name: {}", e),
+            })?;
+        let mut entries = [0; 8];
+        read_exact(reader, &mut entries)?;
+        columns.push(ColumnManifest {
+            column,
+            entries: u64::from_le_bytes(entries),
+            checksum: read_u32(reader)?,
+        });
+    }
+
+    Ok(Manifest {
+        version,
+        repo_version,
+        columns,
+    })
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), Error> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes).map_err(Into::into)
+}
+
+fn read_bytes<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![0; read_u32(reader)? as usize];
+    read_exact(reader, &mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, Error> {
+    let mut bytes = [0; 4];
+    read_exact(reader, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Like `Read::read_exact`, but a truncated or corrupted archive is an `InvalidSnapshot`.
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), Error> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => Error::InvalidSnapshot {
+            message: e.to_string(),
+        },
+        _ => e.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const BLK: DBColumn = DBColumn::BeaconBlock;
+    const CST: ColumnId = ColumnId::custom("cst", StoreCodec::Raw, Compression::None);
+
+    fn columns() -> ColumnRegistry {
+        ColumnRegistry::with_custom(&["cst"]).unwrap()
+    }
+
+    fn store() -> MemoryStore {
+        let store = MemoryStore::open().with_columns(columns());
+        store.put_bytes(BLK.into(), b"a", b"1").unwrap();
+        store.put_bytes(BLK.into(), b"b", b"2").unwrap();
+        store.put_bytes(DBColumn::Deals.into(), b"c", b"3").unwrap();
+        store.put_bytes(CST, b"d", b"4").unwrap();
+        store
+    }
+
+    #[test]
+    fn export_import() {
+        let dir = tempdir().unwrap();
+        let path = snapshot_path(dir.path(), Path::new("full"));
+
+        let manifest = export(&store(), 1, &path).unwrap();
+        assert_eq!(manifest.repo_version, 1);
+        assert_eq!(manifest.columns.len(), columns().all().count());
+        let blocks = manifest.columns.iter().find(|c| c.column == "blk").unwrap();
+        assert_eq!(blocks.entries, 2);
+
+        let copy = dir.path().join("copy");
+        export(&store(), 1, &copy).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), fs::read(&copy).unwrap());
+
+        let restored = MemoryStore::open().with_columns(columns());
+        restored.put_bytes(BLK.into(), b"z", b"stale").unwrap();
+        assert_eq!(import(&restored, &path), Ok(manifest));
+
+        assert!(!restored.key_exists(BLK.into(), b"z").unwrap());
+        for (column, key, value) in &[
+            (BLK.into(), b"a", b"1"),
+            (BLK.into(), b"b", b"2"),
+            (DBColumn::Deals.into(), b"c", b"3"),
+            (CST, b"d", b"4"),
+        ] {
+            assert_eq!(
+                restored.get_bytes(*column, *key).unwrap(),
+                Some(value.to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn import_errors() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("snapshot");
+        export(&store(), 1, &path).unwrap();
+
+        assert_eq!(
+            import(&MemoryStore::open(), &path),
+            Err(Error::UnknownColumn {
+                name: "cst".to_string()
+            })
+        );
+        let restored = MemoryStore::open().with_columns(columns());
+
+        let mut bytes = compression::decompress(&fs::read(&path).unwrap()).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        fs::write(&path, compression::compress(&bytes)).unwrap();
+        match import(&restored, &path) {
+            Err(Error::InvalidSnapshot { .. }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        bytes.truncate(last);
+        fs::write(&path, compression::compress(&bytes)).unwrap();
+        match import(&restored, &path) {
+            Err(Error::InvalidSnapshot { .. }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        assert_eq!(restored.iter_column(BLK.into()).unwrap().count(), 0);
+    }
+}
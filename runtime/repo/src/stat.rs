@@ -0,0 +1,212 @@
+//! Key counts and disk usage of the datastores of a repo.
+//!
+//! Keys are counted in the columns each datastore is made of, see `Datastore::columns`, while
+//! bytes are the size of the files in the directory of the datastore, so they include whatever
+//! overhead the database has on disk. The block store keeps one file per block and has no
+//! columns, its keys are its files.
+use super::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A datastore of the repo, living in its own directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Datastore {
+    Wallet,
+    Chain,
+    Deals,
+    Blockstore,
+}
+
+/// Every datastore, in the order they are reported.
+const DATASTORES: [Datastore; 4] = [
+    Datastore::Wallet,
+    Datastore::Chain,
+    Datastore::Deals,
+    Datastore::Blockstore,
+];
+
+impl Datastore {
+    /// Returns all datastores.
+    pub fn all() -> &'static [Datastore] {
+        &DATASTORES
+    }
+
+    /// Returns the name of the datastore.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Datastore::Wallet => "wallet",
+            Datastore::Chain => "chain",
+            Datastore::Deals => "deals",
+            Datastore::Blockstore => "blockstore",
+        }
+    }
+
+    /// Returns the name of the directory of the datastore in the repo.
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            Datastore::Wallet => WALLET_DATASTORE_FILENAME_PREFIX,
+            Datastore::Chain => CHAIN_DATASTORE_FILENAME_PREFIX,
+            Datastore::Deals => DEALS_DATASTROE_FILENAME_PREFIX,
+            Datastore::Blockstore => BLOCKSTORE_DATASTORE_FILENAME_PREFIX,
+        }
+    }
+
+    /// Returns the built-in columns holding the keys of the datastore.
+    pub fn columns(&self) -> &'static [DBColumn] {
+        match self {
+            Datastore::Wallet => &[DBColumn::Wallet],
+            Datastore::Chain => &[
+                DBColumn::BeaconBlock,
+                DBColumn::BeaconState,
+                DBColumn::BeaconChain,
+                DBColumn::ForkChoice,
+                DBColumn::OpPool,
+                DBColumn::SlotIndex,
+            ],
+            Datastore::Deals => &[DBColumn::Deals, DBColumn::DealIndex],
+            Datastore::Blockstore => &[],
+        }
+    }
+}
+
+/// Number of keys of a datastore and the bytes it takes on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DatastoreStat {
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+/// Outcome of `Repo::stat`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RepoStat {
+    pub wallet: DatastoreStat,
+    pub chain: DatastoreStat,
+    pub deals: DatastoreStat,
+    pub blockstore: DatastoreStat,
+}
+
+impl RepoStat {
+    /// Returns the stat of `datastore`.
+    pub fn get(&self, datastore: Datastore) -> &DatastoreStat {
+        match datastore {
+            Datastore::Wallet => &self.wallet,
+            Datastore::Chain => &self.chain,
+            Datastore::Deals => &self.deals,
+            Datastore::Blockstore => &self.blockstore,
+        }
+    }
+
+    fn get_mut(&mut self, datastore: Datastore) -> &mut DatastoreStat {
+        match datastore {
+            Datastore::Wallet => &mut self.wallet,
+            Datastore::Chain => &mut self.chain,
+            Datastore::Deals => &mut self.deals,
+            Datastore::Blockstore => &mut self.blockstore,
+        }
+    }
+
+    /// The keys and bytes of all datastores together.
+    pub fn total(&self) -> DatastoreStat {
+        Datastore::all()
+            .iter()
+            .fold(DatastoreStat::default(), |total, datastore| {
+                let stat = self.get(*datastore);
+                DatastoreStat {
+                    keys: total.keys + stat.keys,
+                    bytes: total.bytes + stat.bytes,
+                }
+            })
+    }
+}
+
+/// Counts the keys of every datastore in `store` and measures their directories in the repo at
+/// `repo_path`.
+pub fn stat<S: DataStore>(store: &S, repo_path: &Path) -> Result<RepoStat, Error> {
+    let mut stat = RepoStat::default();
+    for datastore in Datastore::all() {
+        let (files, bytes) = disk_usage(&repo_path.join(datastore.dir_name()))?;
+        let keys = if datastore.columns().is_empty() {
+            files
+        } else {
+            let mut keys = 0;
+            for column in datastore.columns() {
+                keys += store.iter_column((*column).into())?.count() as u64;
+            }
+            keys
+        };
+        *stat.get_mut(*datastore) = DatastoreStat { keys, bytes };
+    }
+    Ok(stat)
+}
+
+/// Returns the number of files under `path` and their total size, nothing if it does not exist.
+pub fn disk_usage(path: &Path) -> Result<(u64, u64), Error> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let (mut files, mut bytes) = (0, 0);
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let (dir_files, dir_bytes) = disk_usage(&entry.path())?;
+            files += dir_files;
+            bytes += dir_bytes;
+        } else {
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok((files, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn disk_usage_of_datastores() {
+        let dir = tempdir().unwrap();
+        let store = MemoryStore::open();
+        store
+            .put_bytes(DBColumn::Wallet.into(), b"addr", b"key")
+            .unwrap();
+        store
+            .put_bytes(DBColumn::BeaconBlock.into(), b"a", b"1")
+            .unwrap();
+        store
+            .put_bytes(DBColumn::BeaconState.into(), b"b", b"2")
+            .unwrap();
+        fs::create_dir_all(dir.path().join("chain/nested")).unwrap();
+        fs::write(dir.path().join("chain/000001.log"), [0; 10]).unwrap();
+        fs::write(dir.path().join("chain/nested/MANIFEST"), [0; 5]).unwrap();
+        fs::create_dir_all(dir.path().join("blocks")).unwrap();
+        fs::write(dir.path().join("blocks/a.data"), [0; 3]).unwrap();
+        fs::write(dir.path().join("blocks/b.data"), [0; 4]).unwrap();
+
+        let stat = stat(&store, dir.path()).unwrap();
+        assert_eq!(stat.wallet, DatastoreStat { keys: 1, bytes: 0 });
+        assert_eq!(stat.chain, DatastoreStat { keys: 2, bytes: 15 });
+        assert_eq!(stat.deals, DatastoreStat::default());
+        assert_eq!(stat.blockstore, DatastoreStat { keys: 2, bytes: 7 });
+        assert_eq!(stat.total(), DatastoreStat { keys: 5, bytes: 22 });
+    }
+
+    #[test]
+    fn datastores_do_not_share_columns() {
+        let all = Datastore::all();
+        for (i, a) in all.iter().enumerate() {
+            for b in &all[i + 1..] {
+                assert!(a
+                    .columns()
+                    .iter()
+                    .all(|column| !b.columns().contains(column)));
+            }
+        }
+    }
+}
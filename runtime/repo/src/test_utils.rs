@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use crate::{DBColumn, DataStore, Error, MemoryStore, Slot, SlotBlock, StoreItem};
+use cid::Cid;
+
+/// Creates an empty in-memory store, for tests that do not need a store on disk.
+pub fn create_memory_store() -> Arc<MemoryStore> {
+    Arc::new(MemoryStore::open())
+}
+
+/// A block that only holds its slot and parent, for tests and benchmarks of the `block_at_slot`
+/// module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestBlock {
+    pub slot: Slot,
+    pub parent_root: Cid,
+}
+
+impl TestBlock {
+    pub fn new(slot: Slot, parent_root: &Cid) -> Self {
+        TestBlock {
+            slot,
+            parent_root: parent_root.clone(),
+        }
+    }
+}
+
+impl StoreItem for TestBlock {
+    fn db_column() -> crate::ColumnId {
+        DBColumn::BeaconBlock.into()
+    }
+
+    fn as_store_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.slot.to_be_bytes().to_vec();
+        bytes.extend(self.parent_root.to_bytes());
+        bytes
+    }
+
+    fn from_store_bytes(bytes: &mut [u8]) -> Result<Self, Error> {
+        if bytes.len() < 8 {
+            return Err(Error::DecodeError {
+                message: "truncated test block".to_string(),
+            });
+        }
+        let mut slot = [0; 8];
+        slot.copy_from_slice(&bytes[..8]);
+        let parent_root = Cid::from(&bytes[8..]).map_err(|e| Error::DecodeError {
+            message: format!("{:?}", e),
+        })?;
+        Ok(TestBlock::new(Slot::from_be_bytes(slot), &parent_root))
+    }
+}
+
+impl SlotBlock for TestBlock {
+    fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    fn parent_root(&self) -> Cid {
+        self.parent_root.clone()
+    }
+}
+
+/// Returns a CIDv0 root that differs for every `n`.
+pub fn test_root(n: u64) -> Cid {
+    let mut bytes = vec![0x12, 0x20];
+    bytes.extend(&[0; 24]);
+    bytes.extend(&n.to_be_bytes());
+    Cid::from(bytes).expect("valid CIDv0")
+}
+
+/// Imports a chain of `TestBlock`s at `slots` as the canonical chain, returning their roots.
+///
+/// The roots are `test_root(seed)`, `test_root(seed + 1)` and so on. The parent of the first block
+/// is not stored.
+pub fn import_chain<T: DataStore>(store: &T, slots: &[Slot], seed: u64) -> Vec<Cid> {
+    let mut parent = test_root(u64::MAX);
+    let mut roots = Vec::with_capacity(slots.len());
+    for (i, slot) in slots.iter().enumerate() {
+        let root = test_root(seed + i as u64);
+        store
+            .import_canonical_block(root.clone(), TestBlock::new(*slot, &parent))
+            .expect("import test block");
+        parent = root.clone();
+        roots.push(root);
+    }
+    roots
+}
@@ -0,0 +1,496 @@
+//! Atomic writes across the wallet, chain and deals datastores.
+//!
+//! Each datastore is a database of its own, so no single write batch spans them. A transaction is
+//! instead committed in two phases:
+//!
+//! 1. the batches of all datastores are written to a journal in the `transactions` directory of
+//!    the repo, followed by a CRC32 checksum, and the journal is synced to disk.
+//! 2. each batch is applied with `DataStore::do_atomically`, then the journal is removed.
+//!
+//! A crash during the first phase leaves an incomplete journal and untouched datastores, a crash
+//! during the second one leaves a complete journal. `recover` discards the former and applies the
+//! latter again, which is safe as puts and deletes can be repeated. It must run when the repo is
+//! opened, before any other write, see `Repo::recover_transactions`.
+//!
+//! Transactions writing to a single datastore skip the journal, one batch is atomic already.
+//! Reads are not isolated: a transaction sees the datastores as they are, not its own writes.
+use super::*;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bytes every journal starts with.
+const MAGIC: &[u8] = b"fsjournal";
+
+/// Version of the journal layout written by this binary.
+const JOURNAL_VERSION: u32 = 1;
+
+const PUT: u8 = 0;
+const DELETE: u8 = 1;
+
+/// Tells journals of transactions started in the same nanosecond apart.
+static NEXT_JOURNAL: AtomicU64 = AtomicU64::new(0);
+
+/// The datastores a transaction writes to.
+pub struct TransactionStores<'a, S> {
+    pub wallet: &'a S,
+    pub chain: &'a S,
+    pub deals: &'a S,
+}
+
+impl<'a, S: DataStore> TransactionStores<'a, S> {
+    /// The datastores paired with their batch in `txn`, in journal order.
+    fn with_batches<'t>(&self, txn: &'t Transaction) -> [(&'a S, &'t Batch); 3] {
+        [
+            (self.wallet, &txn.wallet),
+            (self.chain, &txn.chain),
+            (self.deals, &txn.deals),
+        ]
+    }
+}
+
+/// The writes of a transaction, one batch per datastore.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transaction {
+    wallet: Batch,
+    chain: Batch,
+    deals: Batch,
+}
+
+impl Transaction {
+    /// The writes to the wallet datastore.
+    pub fn wallet(&mut self) -> &mut Batch {
+        &mut self.wallet
+    }
+
+    /// The writes to the chain datastore.
+    pub fn chain(&mut self) -> &mut Batch {
+        &mut self.chain
+    }
+
+    /// The writes to the deals datastore.
+    pub fn deals(&mut self) -> &mut Batch {
+        &mut self.deals
+    }
+
+    fn batches(&self) -> [&Batch; 3] {
+        [&self.wallet, &self.chain, &self.deals]
+    }
+}
+
+/// The writes of a transaction to one datastore.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Batch {
+    ops: Vec<StoreOp>,
+}
+
+impl Batch {
+    /// Stores `value` in `column`, indexed with `key`.
+    pub fn put_bytes(&mut self, column: ColumnId, key: &[u8], value: &[u8]) {
+        self.ops.push(StoreOp::Put {
+            column,
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+    }
+
+    /// Removes `key` from `column`.
+    pub fn key_delete(&mut self, column: ColumnId, key: &[u8]) {
+        self.ops.push(StoreOp::Delete {
+            column,
+            key: key.to_vec(),
+        });
+    }
+
+    /// Stores `item`, see `StoreItem::as_put_op`.
+    pub fn put<I: StoreItem>(&mut self, key: &Cid, item: &I) {
+        self.ops.push(item.as_put_op(key));
+    }
+
+    /// Removes an instance of `I`, see `StoreItem::as_delete_op`.
+    pub fn delete<I: StoreItem>(&mut self, key: &Cid) {
+        self.ops.push(I::as_delete_op(key));
+    }
+
+    pub fn push(&mut self, op: StoreOp) {
+        self.ops.push(op);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn ops(&self) -> &[StoreOp] {
+        &self.ops
+    }
+}
+
+/// Runs `f` and commits the writes it adds to the transaction, unless it fails.
+///
+/// Every column written to must be registered with its datastore, or nothing is written and the
+/// commit fails with `Error::UnknownColumn`. If applying a batch fails, the journal is kept and the
+/// transaction is completed by the next `recover`.
+pub fn run<S, T, F>(repo_path: &Path, stores: &TransactionStores<S>, f: F) -> Result<T, Error>
+where
+    S: DataStore,
+    F: FnOnce(&mut Transaction) -> Result<T, Error>,
+{
+    let mut txn = Transaction::default();
+    let outcome = f(&mut txn)?;
+    commit(repo_path, stores, txn)?;
+    Ok(outcome)
+}
+
+/// Commits `txn` to `stores`, see the module documentation.
+pub fn commit<S: DataStore>(
+    repo_path: &Path,
+    stores: &TransactionStores<S>,
+    txn: Transaction,
+) -> Result<(), Error> {
+    for (store, batch) in stores.with_batches(&txn).iter() {
+        for op in batch.ops() {
+            store.columns().check(op_column(op))?;
+        }
+    }
+
+    let written = txn
+        .batches()
+        .iter()
+        .filter(|batch| !batch.is_empty())
+        .count();
+    if written <= 1 {
+        return apply(stores, txn);
+    }
+
+    let path = write_journal(repo_path, &txn)?;
+    apply(stores, txn)?;
+    fs::remove_file(path).map_err(Into::into)
+}
+
+/// Completes the transactions interrupted after their journal was written, and discards those
+/// interrupted before. Returns the number of transactions completed.
+pub fn recover<S: DataStore>(
+    repo_path: &Path,
+    stores: &TransactionStores<S>,
+) -> Result<usize, Error> {
+    let mut paths = match fs::read_dir(repo_path.join(TRANSACTIONS_DIR)) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    // Journal names start with the time they were written at.
+    paths.sort();
+
+    let mut completed = 0;
+    for path in paths {
+        if let Some(txn) = read_journal(&fs::read(&path)?, stores)? {
+            apply(stores, txn)?;
+            completed += 1;
+        }
+        fs::remove_file(path)?;
+    }
+    Ok(completed)
+}
+
+fn apply<S: DataStore>(stores: &TransactionStores<S>, txn: Transaction) -> Result<(), Error> {
+    let batches = vec![
+        (stores.wallet, txn.wallet),
+        (stores.chain, txn.chain),
+        (stores.deals, txn.deals),
+    ];
+    for (store, batch) in batches {
+        if !batch.is_empty() {
+            store.do_atomically(batch.ops)?;
+        }
+    }
+    Ok(())
+}
+
+fn op_column(op: &StoreOp) -> ColumnId {
+    match op {
+        StoreOp::Put { column, .. } | StoreOp::Delete { column, .. } => *column,
+    }
+}
+
+/// Writes and syncs the journal of `txn`, returning its path.
+fn write_journal(repo_path: &Path, txn: &Transaction) -> Result<PathBuf, Error> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&JOURNAL_VERSION.to_le_bytes());
+    for batch in txn.batches().iter() {
+        bytes.extend_from_slice(&(batch.ops.len() as u32).to_le_bytes());
+        for op in batch.ops() {
+            match op {
+                StoreOp::Put { column, key, value } => {
+                    bytes.push(PUT);
+                    push_bytes(&mut bytes, column.name().as_bytes());
+                    push_bytes(&mut bytes, key);
+                    push_bytes(&mut bytes, value);
+                }
+                StoreOp::Delete { column, key } => {
+                    bytes.push(DELETE);
+                    push_bytes(&mut bytes, column.name().as_bytes());
+                    push_bytes(&mut bytes, key);
+                }
+            }
+        }
+    }
+    let checksum = crc32fast::hash(&bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+
+    let dir = repo_path.join(TRANSACTIONS_DIR);
+    fs::create_dir_all(&dir)?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .unwrap_or(0);
+    let path = dir.join(format!(
+        "{:032}-{:020}.journal",
+        nanos,
+        NEXT_JOURNAL.fetch_add(1, Ordering::SeqCst)
+    ));
+    let mut file = File::create(&path)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    Ok(path)
+}
+
+/// Decodes a journal, `None` if it is incomplete.
+fn read_journal<S: DataStore>(
+    bytes: &[u8],
+    stores: &TransactionStores<S>,
+) -> Result<Option<Transaction>, Error> {
+    if bytes.len() < MAGIC.len() + 8 || !bytes.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    let (bytes, checksum) = bytes.split_at(bytes.len() - 4);
+    if crc32fast::hash(bytes).to_le_bytes() != checksum {
+        return Ok(None);
+    }
+
+    let mut reader = JournalReader {
+        bytes: &bytes[MAGIC.len()..],
+    };
+    let version = reader.u32()?;
+    if version > JOURNAL_VERSION {
+        return Err(Error::UnsupportedVersion {
+            found: version,
+            supported: JOURNAL_VERSION,
+        });
+    }
+
+    let mut txn = Transaction::default();
+    for (store, batch) in [
+        (stores.wallet, &mut txn.wallet),
+        (stores.chain, &mut txn.chain),
+        (stores.deals, &mut txn.deals),
+    ]
+    .iter_mut()
+    {
+        for _ in 0..reader.u32()? {
+            let tag = reader.take(1)?[0];
+            let name = std::str::from_utf8(reader.bytes()?).map_err(|e| Error::DecodeError {
+                message: format!("invalid column name in journal: {}", e),
+            })?;
+            let column = store.columns().id(name)?;
+            let key = reader.bytes()?.to_vec();
+            batch.push(match tag {
+                PUT => StoreOp::Put {
+                    column,
+                    key,
+                    value: reader.bytes()?.to_vec(),
+                },
+                DELETE => StoreOp::Delete { column, key },
+                tag => {
+                    return Err(Error::DecodeError {
+                        message: format!("invalid journal operation {}", tag),
+                    })
+                }
+            });
+        }
+    }
+    Ok(Some(txn))
+}
+
+fn push_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Reads a journal whose checksum matched, so running out of bytes is a decoding error.
+struct JournalReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> JournalReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(Error::DecodeError {
+                message: "truncated journal".to_string(),
+            });
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const WAT: DBColumn = DBColumn::Wallet;
+    const BLK: DBColumn = DBColumn::BeaconBlock;
+    const DEAL: DBColumn = DBColumn::Deals;
+
+    struct Stores {
+        wallet: MemoryStore,
+        chain: MemoryStore,
+        deals: MemoryStore,
+    }
+
+    impl Stores {
+        fn new() -> Self {
+            Stores {
+                wallet: MemoryStore::open(),
+                chain: MemoryStore::open(),
+                deals: MemoryStore::open(),
+            }
+        }
+
+        fn get(&self) -> TransactionStores<'_, MemoryStore> {
+            TransactionStores {
+                wallet: &self.wallet,
+                chain: &self.chain,
+                deals: &self.deals,
+            }
+        }
+    }
+
+    fn journals(repo_path: &Path) -> usize {
+        fs::read_dir(repo_path.join(TRANSACTIONS_DIR))
+            .map(|entries| entries.count())
+            .unwrap_or(0)
+    }
+
+    fn transaction() -> Transaction {
+        let mut txn = Transaction::default();
+        txn.wallet().put_bytes(WAT.into(), b"nonce", b"1");
+        txn.chain().put_bytes(BLK.into(), b"head", b"b1");
+        txn.chain().key_delete(BLK.into(), b"old");
+        txn
+    }
+
+    #[test]
+    fn commit_across_datastores() {
+        let dir = tempdir().unwrap();
+        let stores = Stores::new();
+        stores.chain.put_bytes(BLK.into(), b"old", b"b0").unwrap();
+
+        let outcome = run(dir.path(), &stores.get(), |txn| {
+            *txn = transaction();
+            Ok(3)
+        });
+        assert_eq!(outcome, Ok(3));
+        assert_eq!(
+            stores.wallet.get_bytes(WAT.into(), b"nonce"),
+            Ok(Some(b"1".to_vec()))
+        );
+        assert_eq!(
+            stores.chain.get_bytes(BLK.into(), b"head"),
+            Ok(Some(b"b1".to_vec()))
+        );
+        assert_eq!(stores.chain.get_bytes(BLK.into(), b"old"), Ok(None));
+        assert_eq!(journals(dir.path()), 0);
+    }
+
+    #[test]
+    fn failed_transactions_write_nothing() {
+        let dir = tempdir().unwrap();
+        let stores = Stores::new();
+
+        let outcome: Result<(), Error> = run(dir.path(), &stores.get(), |txn| {
+            txn.wallet().put_bytes(WAT.into(), b"nonce", b"1");
+            Err(Error::DBError {
+                message: "aborted".to_string(),
+            })
+        });
+        assert!(outcome.is_err());
+
+        let unknown = ColumnId::custom("ext", StoreCodec::Raw, Compression::None);
+        let outcome = run(dir.path(), &stores.get(), |txn| {
+            txn.wallet().put_bytes(WAT.into(), b"nonce", b"1");
+            txn.deals().put_bytes(unknown, b"a", b"b");
+            Ok(())
+        });
+        assert_eq!(
+            outcome,
+            Err(Error::UnknownColumn {
+                name: "ext".to_string()
+            })
+        );
+        assert_eq!(stores.wallet.get_bytes(WAT.into(), b"nonce"), Ok(None));
+        assert_eq!(journals(dir.path()), 0);
+    }
+
+    #[test]
+    fn recover_complete_journals() {
+        let dir = tempdir().unwrap();
+        let stores = Stores::new();
+
+        // Crashed after the journal was written.
+        write_journal(dir.path(), &transaction()).unwrap();
+        let mut txn = Transaction::default();
+        txn.chain().put_bytes(BLK.into(), b"head", b"b2");
+        txn.deals().put_bytes(DEAL.into(), b"1", b"deal");
+        write_journal(dir.path(), &txn).unwrap();
+        assert_eq!(journals(dir.path()), 2);
+
+        assert_eq!(recover(dir.path(), &stores.get()), Ok(2));
+        assert_eq!(
+            stores.wallet.get_bytes(WAT.into(), b"nonce"),
+            Ok(Some(b"1".to_vec()))
+        );
+        assert_eq!(
+            stores.chain.get_bytes(BLK.into(), b"head"),
+            Ok(Some(b"b2".to_vec()))
+        );
+        assert_eq!(
+            stores.deals.get_bytes(DEAL.into(), b"1"),
+            Ok(Some(b"deal".to_vec()))
+        );
+        assert_eq!(journals(dir.path()), 0);
+        assert_eq!(recover(dir.path(), &stores.get()), Ok(0));
+    }
+
+    #[test]
+    fn discard_incomplete_journals() {
+        let dir = tempdir().unwrap();
+        let stores = Stores::new();
+
+        // Crashed while the journal was written.
+        let path = write_journal(dir.path(), &transaction()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 6]).unwrap();
+
+        assert_eq!(recover(dir.path(), &stores.get()), Ok(0));
+        assert_eq!(stores.wallet.get_bytes(WAT.into(), b"nonce"), Ok(None));
+        assert_eq!(stores.chain.get_bytes(BLK.into(), b"head"), Ok(None));
+        assert_eq!(journals(dir.path()), 0);
+    }
+}
@@ -0,0 +1,433 @@
+//! The wallet: the addresses of the node and what it knows about them.
+//!
+//! A `WalletStore` keeps, in the wallet column:
+//!
+//! - an entry per address, CBOR encoded: the name of the key controlling the address in the
+//!   keystore, its type and an optional label.
+//! - the default address, used when no address is given.
+//! - the nonce of the next message each address signs, as a big-endian `u64`.
+//! - the last balance seen for each address, as a big-endian `u128` followed by the big-endian
+//!   `u64` epoch it was seen at.
+//!
+//! Secret keys never leave the keystore, which encrypts them at rest when opened with a
+//! passphrase. An address is only added once its key was read from the keystore, so a wallet
+//! opened without the right passphrase can neither add addresses for encrypted keys nor hand out
+//! their keys, see `WalletStore::key`.
+use super::*;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+const ENTRY_PREFIX: u8 = b'a';
+const NONCE_PREFIX: u8 = b'n';
+const BALANCE_PREFIX: u8 = b'b';
+const DEFAULT_KEY: &[u8] = b"default";
+
+/// An address of the wallet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalletEntry {
+    pub address: String,
+    /// Name of the key controlling the address in the keystore.
+    pub key_name: String,
+    pub key_type: KeyType,
+    pub label: Option<String>,
+}
+
+/// The balance of an address as last seen on chain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CachedBalance {
+    pub balance: u128,
+    pub epoch: u64,
+}
+
+/// `WalletEntry` as stored, `KeyType` has no serialization of its own.
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    address: String,
+    key_name: String,
+    key_type: String,
+    label: Option<String>,
+}
+
+/// The wallet of a node, kept in the wallet column of `store` with the keys in `keystore`.
+pub struct WalletStore<S> {
+    store: S,
+    keystore: Keystore,
+    /// Held for the whole of a write, so that nonces are never handed out twice.
+    lock: Mutex<()>,
+}
+
+impl<S: DataStore> WalletStore<S> {
+    pub fn new(store: S, keystore: Keystore) -> Self {
+        WalletStore {
+            store,
+            keystore,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// The wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    pub fn keystore(&self) -> &Keystore {
+        &self.keystore
+    }
+
+    /// Adds `address`, controlled by the key `key_name` of the keystore, or changes the key and
+    /// label of an address already in the wallet.
+    ///
+    /// The key is read first, failing with `Error::KeystoreError` if it is missing or cannot be
+    /// decrypted. The first address added becomes the default one.
+    pub fn add(
+        &self,
+        address: &str,
+        key_name: &str,
+        label: Option<String>,
+    ) -> Result<WalletEntry, Error> {
+        let key = self.keystore.get(key_name)?;
+        let entry = WalletEntry {
+            address: address.to_string(),
+            key_name: key_name.to_string(),
+            key_type: key.key_type(),
+            label,
+        };
+
+        let _lock = self.lock.lock().expect("wallet lock poisoned");
+        let mut ops = vec![put_op(
+            entry_key(ENTRY_PREFIX, address),
+            encode_entry(&entry),
+        )];
+        if self.default_address()?.is_none() {
+            ops.push(put_op(DEFAULT_KEY.to_vec(), address.as_bytes().to_vec()));
+        }
+        self.store.do_atomically(ops)?;
+        Ok(entry)
+    }
+
+    /// Returns the entry of `address`, if it is in the wallet.
+    pub fn get(&self, address: &str) -> Result<Option<WalletEntry>, Error> {
+        match self.get_value(&entry_key(ENTRY_PREFIX, address))? {
+            Some(bytes) => decode_entry(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns every address of the wallet, sorted.
+    pub fn list(&self) -> Result<Vec<WalletEntry>, Error> {
+        self.store
+            .iter_prefix(DBColumn::Wallet.into(), &[ENTRY_PREFIX])?
+            .map(|(_, value)| decode_entry(&value))
+            .collect()
+    }
+
+    /// Sets the label of `address`.
+    pub fn set_label(&self, address: &str, label: Option<String>) -> Result<WalletEntry, Error> {
+        let _lock = self.lock.lock().expect("wallet lock poisoned");
+        let mut entry = self.known(address)?;
+        entry.label = label;
+        self.store.put_bytes(
+            DBColumn::Wallet.into(),
+            &entry_key(ENTRY_PREFIX, address),
+            &encode_entry(&entry),
+        )?;
+        Ok(entry)
+    }
+
+    /// Removes `address` with its nonce and cached balance. Its key stays in the keystore.
+    ///
+    /// The wallet has no default address anymore if `address` was the default one.
+    pub fn remove(&self, address: &str) -> Result<(), Error> {
+        let _lock = self.lock.lock().expect("wallet lock poisoned");
+        let mut ops: Vec<StoreOp> = [ENTRY_PREFIX, NONCE_PREFIX, BALANCE_PREFIX]
+            .iter()
+            .map(|prefix| StoreOp::Delete {
+                column: DBColumn::Wallet.into(),
+                key: entry_key(*prefix, address),
+            })
+            .collect();
+        if self.default_address()?.as_ref().map(|default| &default[..]) == Some(address) {
+            ops.push(StoreOp::Delete {
+                column: DBColumn::Wallet.into(),
+                key: DEFAULT_KEY.to_vec(),
+            });
+        }
+        self.store.do_atomically(ops)
+    }
+
+    /// Returns the address used when none is given, if any.
+    pub fn default_address(&self) -> Result<Option<String>, Error> {
+        match self.get_value(DEFAULT_KEY)? {
+            Some(bytes) => String::from_utf8(bytes)
+                .map(Some)
+                .map_err(|e| Error::DecodeError {
+                    message: format!("invalid default address: {}", e),
+                }),
+            None => Ok(None),
+        }
+    }
+
+    /// Makes `address` the default one, failing with `Error::UnknownAddress` if it is not in the
+    /// wallet.
+    pub fn set_default(&self, address: &str) -> Result<(), Error> {
+        let _lock = self.lock.lock().expect("wallet lock poisoned");
+        self.known(address)?;
+        self.store
+            .put_bytes(DBColumn::Wallet.into(), DEFAULT_KEY, address.as_bytes())
+    }
+
+    /// Returns the secret key of `address`, read and decrypted from the keystore.
+    pub fn key(&self, address: &str) -> Result<KeystoreKey, Error> {
+        let entry = self.known(address)?;
+        Ok(self.keystore.get(&entry.key_name)?)
+    }
+
+    /// Returns the nonce the next message signed by `address` will use.
+    pub fn nonce(&self, address: &str) -> Result<u64, Error> {
+        match self.get_value(&entry_key(NONCE_PREFIX, address))? {
+            Some(bytes) => decode_u64(&bytes),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns the nonce to sign the next message of `address` with, and moves past it.
+    ///
+    /// Fails with `Error::UnknownAddress` if `address` is not in the wallet.
+    pub fn next_nonce(&self, address: &str) -> Result<u64, Error> {
+        let _lock = self.lock.lock().expect("wallet lock poisoned");
+        self.known(address)?;
+        let nonce = self.nonce(address)?;
+        self.store.put_bytes(
+            DBColumn::Wallet.into(),
+            &entry_key(NONCE_PREFIX, address),
+            &(nonce + 1).to_be_bytes(),
+        )?;
+        Ok(nonce)
+    }
+
+    /// Sets the nonce of the next message of `address`, for instance to the one found on chain.
+    pub fn set_nonce(&self, address: &str, nonce: u64) -> Result<(), Error> {
+        let _lock = self.lock.lock().expect("wallet lock poisoned");
+        self.known(address)?;
+        self.store.put_bytes(
+            DBColumn::Wallet.into(),
+            &entry_key(NONCE_PREFIX, address),
+            &nonce.to_be_bytes(),
+        )
+    }
+
+    /// Returns the last balance cached for `address`, if any.
+    pub fn cached_balance(&self, address: &str) -> Result<Option<CachedBalance>, Error> {
+        let bytes = match self.get_value(&entry_key(BALANCE_PREFIX, address))? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        if bytes.len() != 24 {
+            return Err(Error::DecodeError {
+                message: format!("invalid cached balance {:?}", bytes),
+            });
+        }
+        let mut balance = [0; 16];
+        balance.copy_from_slice(&bytes[..16]);
+        Ok(Some(CachedBalance {
+            balance: u128::from_be_bytes(balance),
+            epoch: decode_u64(&bytes[16..])?,
+        }))
+    }
+
+    /// Caches the balance of `address` seen at `epoch`, unless a balance seen at a later epoch is
+    /// cached already.
+    pub fn cache_balance(&self, address: &str, balance: CachedBalance) -> Result<(), Error> {
+        let _lock = self.lock.lock().expect("wallet lock poisoned");
+        self.known(address)?;
+        if let Some(cached) = self.cached_balance(address)? {
+            if cached.epoch > balance.epoch {
+                return Ok(());
+            }
+        }
+        let value = [
+            &balance.balance.to_be_bytes()[..],
+            &balance.epoch.to_be_bytes(),
+        ]
+        .concat();
+        self.store.put_bytes(
+            DBColumn::Wallet.into(),
+            &entry_key(BALANCE_PREFIX, address),
+            &value,
+        )
+    }
+
+    /// Returns the entry of `address`, failing with `Error::UnknownAddress` if there is none.
+    fn known(&self, address: &str) -> Result<WalletEntry, Error> {
+        self.get(address)?.ok_or_else(|| Error::UnknownAddress {
+            address: address.to_string(),
+        })
+    }
+
+    fn get_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        self.store.get_bytes(DBColumn::Wallet.into(), key)
+    }
+}
+
+impl From<KeystoreError> for Error {
+    fn from(e: KeystoreError) -> Error {
+        Error::KeystoreError {
+            message: e.to_string(),
+        }
+    }
+}
+
+fn entry_key(prefix: u8, address: &str) -> Vec<u8> {
+    [&[prefix][..], address.as_bytes()].concat()
+}
+
+fn put_op(key: Vec<u8>, value: Vec<u8>) -> StoreOp {
+    StoreOp::Put {
+        column: DBColumn::Wallet.into(),
+        key,
+        value,
+    }
+}
+
+fn key_type_name(key_type: KeyType) -> &'static str {
+    match key_type {
+        KeyType::Ed25519 => "ed25519",
+        KeyType::Bls => "bls",
+    }
+}
+
+fn encode_entry(entry: &WalletEntry) -> Vec<u8> {
+    codec::cbor_encode(&StoredEntry {
+        address: entry.address.clone(),
+        key_name: entry.key_name.clone(),
+        key_type: key_type_name(entry.key_type).to_string(),
+        label: entry.label.clone(),
+    })
+}
+
+fn decode_entry(bytes: &[u8]) -> Result<WalletEntry, Error> {
+    let stored: StoredEntry = codec::cbor_decode(bytes)?;
+    let key_type = match &stored.key_type[..] {
+        "ed25519" => KeyType::Ed25519,
+        "bls" => KeyType::Bls,
+        other => {
+            return Err(Error::DecodeError {
+                message: format!("unknown key type {}", other),
+            })
+        }
+    };
+    Ok(WalletEntry {
+        address: stored.address,
+        key_name: stored.key_name,
+        key_type,
+        label: stored.label,
+    })
+}
+
+fn decode_u64(bytes: &[u8]) -> Result<u64, Error> {
+    if bytes.len() != 8 {
+        return Err(Error::DecodeError {
+            message: format!("invalid u64 {:?}", bytes),
+        });
+    }
+    let mut value = [0; 8];
+    value.copy_from_slice(bytes);
+    Ok(u64::from_be_bytes(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn wallet(dir: &tempfile::TempDir) -> WalletStore<MemoryStore> {
+        let keystore = Keystore::new(dir.path());
+        keystore.generate("k1", KeyType::Ed25519).unwrap();
+        keystore.generate("k2", KeyType::Bls).unwrap();
+        WalletStore::new(MemoryStore::open(), keystore)
+    }
+
+    #[test]
+    fn addresses_and_default() {
+        let dir = tempdir().unwrap();
+        let wallet = wallet(&dir);
+        assert_eq!(wallet.default_address(), Ok(None));
+
+        let first = wallet.add("f1abc", "k1", None).unwrap();
+        assert_eq!(first.key_type, KeyType::Ed25519);
+        let second = wallet.add("f3def", "k2", Some("cold".to_string())).unwrap();
+        assert_eq!(second.key_type, KeyType::Bls);
+        assert_eq!(wallet.default_address(), Ok(Some("f1abc".to_string())));
+        assert_eq!(wallet.list(), Ok(vec![first.clone(), second]));
+
+        let labelled = wallet.set_label("f1abc", Some("hot".to_string())).unwrap();
+        assert_eq!(wallet.get("f1abc"), Ok(Some(labelled)));
+        assert!(wallet.key("f3def").is_ok());
+
+        wallet.set_default("f3def").unwrap();
+        assert_eq!(wallet.default_address(), Ok(Some("f3def".to_string())));
+        assert_eq!(
+            wallet.set_default("f1nope"),
+            Err(Error::UnknownAddress {
+                address: "f1nope".to_string()
+            })
+        );
+
+        wallet.remove("f3def").unwrap();
+        assert_eq!(wallet.get("f3def"), Ok(None));
+        assert_eq!(wallet.default_address(), Ok(None));
+        assert!(wallet.key("f3def").is_err());
+    }
+
+    #[test]
+    fn keys_come_from_the_keystore() {
+        let dir = tempdir().unwrap();
+        let wallet = wallet(&dir);
+
+        match wallet.add("f1abc", "missing", None) {
+            Err(Error::KeystoreError { .. }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(wallet.get("f1abc"), Ok(None));
+    }
+
+    #[test]
+    fn nonces() {
+        let dir = tempdir().unwrap();
+        let wallet = wallet(&dir);
+        wallet.add("f1abc", "k1", None).unwrap();
+
+        assert_eq!(wallet.nonce("f1abc"), Ok(0));
+        assert_eq!(wallet.next_nonce("f1abc"), Ok(0));
+        assert_eq!(wallet.next_nonce("f1abc"), Ok(1));
+        assert_eq!(wallet.nonce("f1abc"), Ok(2));
+        wallet.set_nonce("f1abc", 10).unwrap();
+        assert_eq!(wallet.next_nonce("f1abc"), Ok(10));
+        assert!(wallet.next_nonce("f1nope").is_err());
+
+        wallet.remove("f1abc").unwrap();
+        wallet.add("f1abc", "k1", None).unwrap();
+        assert_eq!(wallet.nonce("f1abc"), Ok(0));
+    }
+
+    #[test]
+    fn balances() {
+        let dir = tempdir().unwrap();
+        let wallet = wallet(&dir);
+        wallet.add("f1abc", "k1", None).unwrap();
+        let balance = |balance, epoch| CachedBalance { balance, epoch };
+
+        assert_eq!(wallet.cached_balance("f1abc"), Ok(None));
+        wallet
+            .cache_balance("f1abc", balance(u128::from(u64::MAX) * 3, 7))
+            .unwrap();
+        // seen before the cached one.
+        wallet.cache_balance("f1abc", balance(1, 6)).unwrap();
+        assert_eq!(
+            wallet.cached_balance("f1abc"),
+            Ok(Some(balance(u128::from(u64::MAX) * 3, 7)))
+        );
+        wallet.cache_balance("f1abc", balance(2, 8)).unwrap();
+        assert_eq!(wallet.cached_balance("f1abc"), Ok(Some(balance(2, 8))));
+    }
+}